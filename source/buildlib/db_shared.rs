@@ -1,8 +1,53 @@
-use good_ormning::sqlite::schema::field::{
-    FieldType,
-    field_str,
+use good_ormning::sqlite::{
+    query::{
+        expr::{
+            BinOp,
+            Expr,
+        },
+        helpers::expr_and,
+    },
+    schema::field::{
+        Field,
+        FieldType,
+        field_i64,
+        field_str,
+    },
 };
 
 pub fn field_ident() -> FieldType {
     return field_str().custom("crate::interface::stored::identity::Identity").build();
 }
+
+fn param_str(name: &str) -> Expr {
+    return Expr::Param {
+        name: name.to_string(),
+        type_: field_str().build().type_,
+    };
+}
+
+/// Conditions for a page of a listing ordered by `field`: greater than the `after`
+/// param (`""` for the first page) and between the `prefix_start` and `prefix_end`
+/// params (see `prefix_range` in `utils::db_util`). The params are strings even if
+/// the field has a custom type.
+pub fn where_page(field: &Field) -> Expr {
+    let cmp = |op: BinOp, name: &str| Expr::BinOp {
+        left: Box::new(Expr::Field(field.clone())),
+        op: op,
+        right: Box::new(param_str(name)),
+    };
+    return expr_and(
+        vec![
+            cmp(BinOp::GreaterThan, "after"),
+            cmp(BinOp::GreaterThanEqualTo, "prefix_start"),
+            cmp(BinOp::LessThan, "prefix_end")
+        ],
+    );
+}
+
+/// The `limit` param, for paged listings.
+pub fn page_limit() -> Expr {
+    return Expr::Param {
+        name: "limit".to_string(),
+        type_: field_i64().build().type_,
+    };
+}
@@ -39,6 +39,7 @@ pub fn build(mut queries: Option<&mut Vec<Query>>) -> Version {
         queries.push(
             new_select(&secret).return_field(&secret_secret).build_query("secret_get", QueryResCount::MaybeOne),
         );
+        queries.push(new_delete(&secret).build_query("secret_delete", QueryResCount::None));
     }
 
     // Persistend neighbors
@@ -0,0 +1,90 @@
+use good_ormning::sqlite::{
+    Version,
+    Query,
+    schema::field::{
+        field_str,
+        field_i64,
+        field_utctime_ms,
+    },
+    query::{
+        expr::{
+            Expr,
+            BinOp,
+        },
+        helpers::set_field,
+    },
+    new_insert,
+    QueryResCount,
+    new_select,
+    new_delete,
+};
+
+pub fn build(mut queries: Option<&mut Vec<Query>>) -> Version {
+    let mut v_ = super::v0::build(queries.as_deref_mut());
+    let v = &mut v_;
+
+    // Traffic counts per peer and message type, rolled up periodically
+    let traffic = v.table("zKV7Z3USV", "traffic");
+    let traffic_start = traffic.field(v, "zRIHUBFNT", "start", field_utctime_ms().build());
+    let traffic_end = traffic.field(v, "zLUM0RJO9", "end", field_utctime_ms().build());
+    let traffic_peer = traffic.field(v, "zIEIR6H5N", "peer", field_str().build());
+    let traffic_message = traffic.field(v, "zLW1RB9GN", "message", field_str().build());
+    let traffic_sent_messages = traffic.field(v, "zMTETH7D5", "sent_messages", field_i64().build());
+    let traffic_sent_bytes = traffic.field(v, "z24SSJUK5", "sent_bytes", field_i64().build());
+    let traffic_received_messages = traffic.field(v, "zKEKB2ZXO", "received_messages", field_i64().build());
+    let traffic_received_bytes = traffic.field(v, "zCRJH3XDJ", "received_bytes", field_i64().build());
+    if let Some(queries) = &mut queries {
+        queries.push(
+            new_insert(
+                &traffic,
+                vec![
+                    set_field("start", &traffic_start),
+                    set_field("end", &traffic_end),
+                    set_field("peer", &traffic_peer),
+                    set_field("message", &traffic_message),
+                    set_field("sent_messages", &traffic_sent_messages),
+                    set_field("sent_bytes", &traffic_sent_bytes),
+                    set_field("received_messages", &traffic_received_messages),
+                    set_field("received_bytes", &traffic_received_bytes)
+                ],
+            ).build_query("traffic_insert", QueryResCount::None),
+        );
+        queries.push(
+            new_select(&traffic)
+                .return_fields(
+                    &[
+                        &traffic_start,
+                        &traffic_end,
+                        &traffic_peer,
+                        &traffic_message,
+                        &traffic_sent_messages,
+                        &traffic_sent_bytes,
+                        &traffic_received_messages,
+                        &traffic_received_bytes,
+                    ],
+                )
+                .where_(Expr::BinOp {
+                    left: Box::new(Expr::Field(traffic_end.clone())),
+                    op: BinOp::GreaterThan,
+                    right: Box::new(Expr::Param {
+                        name: "since".to_string(),
+                        type_: traffic_end.type_.type_.clone(),
+                    }),
+                })
+                .build_query("traffic_get_since", QueryResCount::Many),
+        );
+        queries.push(
+            new_delete(&traffic)
+                .where_(Expr::BinOp {
+                    left: Box::new(Expr::Field(traffic_end.clone())),
+                    op: BinOp::LessThan,
+                    right: Box::new(Expr::Param {
+                        name: "before".to_string(),
+                        type_: traffic_end.type_.type_.clone(),
+                    }),
+                })
+                .build_query("traffic_expire", QueryResCount::None),
+        );
+    }
+    return v_;
+}
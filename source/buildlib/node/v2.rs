@@ -0,0 +1,51 @@
+use good_ormning::sqlite::{
+    Version,
+    Query,
+    schema::field::{
+        field_str,
+        field_i32,
+    },
+    query::{
+        insert::InsertConflict,
+        expr::Expr,
+        helpers::set_field,
+    },
+    new_insert,
+    QueryResCount,
+    new_select,
+    new_delete,
+};
+
+pub fn build(mut queries: Option<&mut Vec<Query>>) -> Version {
+    let mut v_ = super::v1::build(queries.as_deref_mut());
+    let v = &mut v_;
+
+    // Node secret encrypted at rest, replaces the plaintext secret unless configured
+    // otherwise
+    let sealed_secret = v.table("zK2CVI8QA", "sealed_secret");
+    let sealed_secret_unique = sealed_secret.field(v, "zOTHXJ2MI", "unique", field_i32().build());
+    let sealed_secret_sealed =
+        sealed_secret.field(
+            v,
+            "zHLQZGQAE",
+            "sealed",
+            field_str().custom("crate::interface::stored::node_identity::SealedNodeSecret").build(),
+        );
+    if let Some(queries) = &mut queries {
+        queries.push(
+            new_insert(
+                &sealed_secret,
+                vec![(sealed_secret_unique.clone(), Expr::LitI32(0)), set_field("sealed", &sealed_secret_sealed)],
+            )
+                .on_conflict(InsertConflict::DoUpdate(vec![set_field("sealed", &sealed_secret_sealed)]))
+                .build_query("sealed_secret_ensure", QueryResCount::None),
+        );
+        queries.push(
+            new_select(&sealed_secret)
+                .return_field(&sealed_secret_sealed)
+                .build_query("sealed_secret_get", QueryResCount::MaybeOne),
+        );
+        queries.push(new_delete(&sealed_secret).build_query("sealed_secret_delete", QueryResCount::None));
+    }
+    return v_;
+}
@@ -1,12 +1,20 @@
 use std::path::Path;
 
 pub mod v0;
+pub mod v1;
+pub mod v2;
+pub mod v3;
 
 pub fn build(root: &Path) {
     let mut queries = vec![];
     good_ormning::sqlite::generate(
         &root.join("src/service/publisher/db.rs"),
-        vec![(0usize, v0::build(Some(&mut queries)))],
+        vec![
+            (0usize, v0::build(None)),
+            (1usize, v1::build(None)),
+            (2usize, v2::build(None)),
+            (3usize, v3::build(Some(&mut queries)))
+        ],
         queries,
     ).unwrap();
 }
@@ -5,7 +5,6 @@ use good_ormning::sqlite::{
         helpers::{
             eq_field,
             expr_and,
-            gt_field,
             set_field,
         },
         expr::Expr,
@@ -28,7 +27,11 @@ use good_ormning::sqlite::{
     new_insert,
     new_select,
 };
-use crate::buildlib::db_shared::field_ident;
+use crate::buildlib::db_shared::{
+    field_ident,
+    page_limit,
+    where_page,
+};
 
 pub fn build(mut queries: Option<&mut Vec<Query>>) -> Version {
     let mut v_ = Version::default();
@@ -94,17 +97,10 @@ pub fn build(mut queries: Option<&mut Vec<Query>>) -> Version {
         queries.push(
             new_select(&announce)
                 .return_fields(&[&announce_ident, &announce_value])
+                .where_(where_page(&announce_ident))
                 .order(Expr::Field(announce_ident.clone()), Order::Asc)
-                .limit(Expr::LitI32(50))
-                .build_query("announcements_list_start", QueryResCount::Many),
-        );
-        queries.push(
-            new_select(&announce)
-                .return_fields(&[&announce_ident, &announce_value])
-                .where_(gt_field("ident", &announce_ident))
-                .order(Expr::Field(announce_ident.clone()), Order::Asc)
-                .limit(Expr::LitI32(50))
-                .build_query("announcements_list_after", QueryResCount::Many),
+                .limit(page_limit())
+                .build_query("announcements_list", QueryResCount::Many),
         );
     }
 
@@ -177,28 +173,15 @@ pub fn build(mut queries: Option<&mut Vec<Query>>) -> Version {
             queries.push(
                 new_select(&publish)
                     .return_fields(&[&publish_key])
-                    .where_(eq_field("ident", &publish_ident))
-                    .order_from_iter(
-                        [
-                            (Expr::Field(publish_ident.clone()), Order::Asc),
-                            (Expr::Field(publish_key.clone()), Order::Asc),
-                        ].into_iter(),
-                    )
-                    .limit(Expr::LitI32(50))
-                    .build_query("values_keys_list_start", QueryResCount::Many),
-            );
-            queries.push(
-                new_select(&publish)
-                    .return_fields(&[&publish_key])
-                    .where_(expr_and(vec![eq_field("ident", &publish_ident), gt_field("after", &publish_key)]))
+                    .where_(expr_and(vec![eq_field("ident", &publish_ident), where_page(&publish_key)]))
                     .order_from_iter(
                         [
                             (Expr::Field(publish_ident.clone()), Order::Asc),
                             (Expr::Field(publish_key.clone()), Order::Asc),
                         ].into_iter(),
                     )
-                    .limit(Expr::LitI32(50))
-                    .build_query("values_keys_list_after", QueryResCount::Many),
+                    .limit(page_limit())
+                    .build_query("values_keys_list", QueryResCount::Many),
             );
             queries.push(
                 new_delete(&publish)
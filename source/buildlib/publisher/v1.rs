@@ -0,0 +1,72 @@
+use good_ormning::sqlite::{
+    Version,
+    Query,
+    schema::{
+        field::{
+            field_str,
+            field_utctime_ms,
+        },
+        constraint::{
+            PrimaryKeyDef,
+            ConstraintType,
+        },
+    },
+    query::{
+        helpers::{
+            eq_field,
+            expr_and,
+            set_field,
+        },
+        insert::InsertConflict,
+    },
+    new_insert,
+    QueryResCount,
+    new_select,
+    new_delete,
+};
+use crate::buildlib::db_shared::field_ident;
+
+pub fn build(mut queries: Option<&mut Vec<Query>>) -> Version {
+    let mut v_ = super::v0::build(queries.as_deref_mut());
+    let v = &mut v_;
+
+    // When each value was published, for resolvers merging responses from replicated
+    // publishers
+    let t = v.table("zNCIE4N6I", "publish_value_times");
+    let f_ident = t.field(v, "zND55Y7W1", "identity", field_ident());
+    let f_key = t.field(v, "zAYE4HYNR", "key", field_str().build());
+    let f_published = t.field(v, "zZU2VSFV8", "published", field_utctime_ms().build());
+    t.constraint(
+        v,
+        "zCFJZNEQ4",
+        "publish_value_times_pk",
+        ConstraintType::PrimaryKey(PrimaryKeyDef { fields: vec![f_ident.clone(), f_key.clone()] }),
+    );
+    if let Some(queries) = &mut queries {
+        queries.push(
+            new_insert(
+                &t,
+                vec![set_field("ident", &f_ident), set_field("key", &f_key), set_field("published", &f_published)],
+            )
+                .on_conflict(InsertConflict::DoUpdate(vec![set_field("published", &f_published)]))
+                .build_query("values_published_set", QueryResCount::None),
+        );
+        queries.push(
+            new_select(&t)
+                .return_field(&f_published)
+                .where_(expr_and(vec![eq_field("ident", &f_ident), eq_field("key", &f_key)]))
+                .build_query("values_published_get", QueryResCount::MaybeOne),
+        );
+        queries.push(
+            new_delete(&t)
+                .where_(expr_and(vec![eq_field("ident", &f_ident), eq_field("key", &f_key)]))
+                .build_query("values_published_delete", QueryResCount::None),
+        );
+        queries.push(
+            new_delete(&t)
+                .where_(eq_field("ident", &f_ident))
+                .build_query("values_published_delete_all", QueryResCount::None),
+        );
+    }
+    return v_;
+}
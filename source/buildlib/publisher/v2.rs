@@ -0,0 +1,86 @@
+use good_ormning::sqlite::{
+    Version,
+    Query,
+    schema::field::{
+        field_i64,
+        field_utctime_ms,
+    },
+    query::{
+        expr::{
+            Expr,
+            BinOp,
+        },
+        helpers::{
+            expr_and,
+            set_field,
+        },
+    },
+    new_insert,
+    QueryResCount,
+    new_select,
+    new_delete,
+};
+use crate::buildlib::db_shared::field_ident;
+
+pub fn build(mut queries: Option<&mut Vec<Query>>) -> Version {
+    let mut v_ = super::v1::build(queries.as_deref_mut());
+    let v = &mut v_;
+
+    // Resolve counts per identity, rolled up periodically, for usage reports
+    let t = v.table("zR4WQ8JMC", "usage");
+    let f_start = t.field(v, "zH1DT6XKA", "start", field_utctime_ms().build());
+    let f_end = t.field(v, "zP9UZ3BNE", "end", field_utctime_ms().build());
+    let f_ident = t.field(v, "zC7LS2VYO", "identity", field_ident());
+    let f_resolves = t.field(v, "zW5GF0QRI", "resolves", field_i64().build());
+    let f_bytes = t.field(v, "zE8NK4TDU", "bytes", field_i64().build());
+    t.index("zB6HM1XPW", "usage_end", &[&f_end]).build(v);
+    if let Some(queries) = &mut queries {
+        let time_param = |name: &str| Box::new(Expr::Param {
+            name: name.to_string(),
+            type_: f_end.type_.type_.clone(),
+        });
+        queries.push(
+            new_insert(
+                &t,
+                vec![
+                    set_field("start", &f_start),
+                    set_field("end", &f_end),
+                    set_field("ident", &f_ident),
+                    set_field("resolves", &f_resolves),
+                    set_field("bytes", &f_bytes)
+                ],
+            ).build_query("usage_insert", QueryResCount::None),
+        );
+        queries.push(
+            new_select(&t)
+                .return_fields(&[&f_start, &f_end, &f_ident, &f_resolves, &f_bytes])
+                .where_(
+                    expr_and(
+                        vec![
+                            Expr::BinOp {
+                                left: Box::new(Expr::Field(f_end.clone())),
+                                op: BinOp::GreaterThan,
+                                right: time_param("since"),
+                            },
+                            Expr::BinOp {
+                                left: Box::new(Expr::Field(f_start.clone())),
+                                op: BinOp::LessThan,
+                                right: time_param("until"),
+                            }
+                        ],
+                    ),
+                )
+                .build_query("usage_get_between", QueryResCount::Many),
+        );
+        queries.push(
+            new_delete(&t)
+                .where_(Expr::BinOp {
+                    left: Box::new(Expr::Field(f_end.clone())),
+                    op: BinOp::LessThan,
+                    right: time_param("before"),
+                })
+                .build_query("usage_expire", QueryResCount::None),
+        );
+    }
+    return v_;
+}
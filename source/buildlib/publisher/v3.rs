@@ -0,0 +1,145 @@
+use good_ormning::sqlite::{
+    Version,
+    Query,
+    schema::{
+        field::{
+            field_str,
+            field_utctime_ms,
+        },
+        constraint::{
+            PrimaryKeyDef,
+            ConstraintType,
+        },
+    },
+    query::{
+        expr::{
+            Expr,
+            BinOp,
+        },
+        helpers::{
+            eq_field,
+            expr_and,
+            set_field,
+        },
+        insert::InsertConflict,
+        select::Order,
+    },
+    new_insert,
+    QueryResCount,
+    new_select,
+    new_delete,
+};
+use crate::buildlib::db_shared::field_ident;
+
+pub fn build(mut queries: Option<&mut Vec<Query>>) -> Version {
+    let mut v_ = super::v2::build(queries.as_deref_mut());
+    let v = &mut v_;
+
+    // When each identity last announced or published values, for retention
+    {
+        let t = v.table("zK2RV7CXD", "identity_activity");
+        let f_ident = t.field(v, "zT8MJ3WQB", "identity", field_ident());
+        let f_active = t.field(v, "zF5YH0LNS", "last_active", field_utctime_ms().build());
+        t.constraint(
+            v,
+            "zQ1DX6RAE",
+            "identity_activity_pk",
+            ConstraintType::PrimaryKey(PrimaryKeyDef { fields: vec![f_ident.clone()] }),
+        );
+        if let Some(queries) = &mut queries {
+            queries.push(
+                new_insert(&t, vec![set_field("ident", &f_ident), set_field("last_active", &f_active)])
+                    .on_conflict(InsertConflict::DoUpdate(vec![set_field("last_active", &f_active)]))
+                    .build_query("activity_set", QueryResCount::None),
+            );
+            queries.push(
+                new_select(&t)
+                    .return_field(&f_active)
+                    .where_(eq_field("ident", &f_ident))
+                    .build_query("activity_get", QueryResCount::MaybeOne),
+            );
+            queries.push(
+                new_delete(&t).where_(eq_field("ident", &f_ident)).build_query("activity_delete", QueryResCount::None),
+            );
+        }
+    }
+
+    // Identities that went dormant (no activity within the retention period)
+    {
+        let t = v.table("zW4NB9GUO", "dormant");
+        let f_ident = t.field(v, "zH7PC2KTI", "identity", field_ident());
+        let f_since = t.field(v, "zL3ZS8EVM", "since", field_utctime_ms().build());
+        t.constraint(
+            v,
+            "zA6UF1OYJ",
+            "dormant_pk",
+            ConstraintType::PrimaryKey(PrimaryKeyDef { fields: vec![f_ident.clone()] }),
+        );
+        if let Some(queries) = &mut queries {
+            queries.push(
+                new_insert(&t, vec![set_field("ident", &f_ident), set_field("since", &f_since)])
+                    .on_conflict(InsertConflict::DoNothing)
+                    .build_query("dormant_set", QueryResCount::None),
+            );
+            queries.push(
+                new_select(&t)
+                    .return_field(&f_since)
+                    .where_(eq_field("ident", &f_ident))
+                    .build_query("dormant_get", QueryResCount::MaybeOne),
+            );
+            queries.push(
+                new_delete(&t).where_(eq_field("ident", &f_ident)).build_query("dormant_delete", QueryResCount::None),
+            );
+        }
+    }
+
+    // Retention events (identities going dormant, reactivating, purged)
+    {
+        let t = v.table("zE9QO5MHV", "audit");
+        let f_time = t.field(v, "zR2GW7JDC", "time", field_utctime_ms().build());
+        let f_ident = t.field(v, "zN6KA3XBF", "identity", field_ident());
+        let f_event =
+            t.field(
+                v,
+                "zY0TL4PSU",
+                "event",
+                field_str().custom("crate::interface::wire::api::admin::v1::AdminAuditEvent").build(),
+            );
+        t.index("zC8VE2IQN", "audit_time", &[&f_time]).build(v);
+        if let Some(queries) = &mut queries {
+            let time_param = |name: &str| Box::new(Expr::Param {
+                name: name.to_string(),
+                type_: f_time.type_.type_.clone(),
+            });
+            queries.push(
+                new_insert(
+                    &t,
+                    vec![set_field("time", &f_time), set_field("ident", &f_ident), set_field("event", &f_event)],
+                ).build_query("audit_insert", QueryResCount::None),
+            );
+            queries.push(
+                new_select(&t)
+                    .return_fields(&[&f_time, &f_ident, &f_event])
+                    .where_(
+                        expr_and(
+                            vec![
+                                Expr::BinOp {
+                                    left: Box::new(Expr::Field(f_time.clone())),
+                                    op: BinOp::GreaterThanEqualTo,
+                                    right: time_param("since"),
+                                },
+                                Expr::BinOp {
+                                    left: Box::new(Expr::Field(f_time.clone())),
+                                    op: BinOp::LessThan,
+                                    right: time_param("until"),
+                                }
+                            ],
+                        ),
+                    )
+                    .order(Expr::Field(f_time.clone()), Order::Asc)
+                    .build_query("audit_get_between", QueryResCount::Many),
+            );
+        }
+    }
+    return v_;
+}
@@ -6,7 +6,6 @@ use good_ormning::sqlite::{
         expr::Expr,
         helpers::{
             eq_field,
-            gt_field,
             set_field,
         },
         insert::InsertConflict,
@@ -23,7 +22,11 @@ use good_ormning::sqlite::{
     QueryResCount,
     Version,
 };
-use crate::buildlib::db_shared::field_ident;
+use crate::buildlib::db_shared::{
+    field_ident,
+    page_limit,
+    where_page,
+};
 
 pub fn build(mut queries: Option<&mut Vec<Query>>) -> Version {
     let mut v_ = Version::default();
@@ -60,17 +63,10 @@ pub fn build(mut queries: Option<&mut Vec<Query>>) -> Version {
         queries.push(
             new_select(&table)
                 .return_fields(&[&ident, &group])
+                .where_(where_page(&ident))
                 .order(Expr::Field(ident.clone()), Order::Asc)
-                .limit(Expr::LitI32(50))
-                .build_query("list_allowed_identities_start", QueryResCount::Many),
-        );
-        queries.push(
-            new_select(&table)
-                .return_fields(&[&ident, &group])
-                .where_(gt_field("ident", &ident))
-                .order(Expr::Field(ident.clone()), Order::Asc)
-                .limit(Expr::LitI32(50))
-                .build_query("list_allowed_identities_after", QueryResCount::Many),
+                .limit(page_limit())
+                .build_query("list_allowed_identities", QueryResCount::Many),
         );
     }
     return v_;
@@ -37,6 +37,7 @@ use spaghettinuum::{
     interface::{
         config::{
             identity::LocalIdentitySecret,
+            node::node_config::NodeSecretStorage,
             shared::StrSocketAddr,
         },
         stored::{
@@ -81,7 +82,22 @@ async fn main() {
                         address: addr,
                         ident: id,
                     }).into_iter().collect_vec(),
+                    &[],
+                    None,
                     &path,
+                    &NodeSecretStorage::Plaintext,
+                    None,
+                    None,
+                    false,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    false,
                 ).await?;
             nodes.push(node.clone());
             prev_node = Some((SerialAddr(addr), node.node_identity()));
@@ -141,11 +157,7 @@ async fn main() {
                 },
             }
         };
-        let found_addr = match found {
-            stored::announcement::Announcement::V1(a) => {
-                a.parse_unwrap().publishers.get(0).unwrap().addr.0
-            },
-        };
+        let found_addr = found.parse().unwrap().publishers.first().unwrap().addr.0;
         assert_eq!(found_addr, message_addr);
         tm.join(&Log::new_root(loga::INFO)).await?;
         return Ok(());
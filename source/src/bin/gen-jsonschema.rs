@@ -82,6 +82,34 @@ pub fn main() {
         out.join("record_ssh_hostkeys.schema.json"),
         &serde_json::to_string_pretty(&schema_for!(stored::record::ssh_record::SshHostKeys)).unwrap(),
     ).unwrap();
+    fs::write(
+        out.join("record_services.schema.json"),
+        serde_json::to_string_pretty(&schema_for!(stored::record::service_record::Services)).unwrap(),
+    ).unwrap();
+    fs::write(
+        out.join("record_succession.schema.json"),
+        serde_json::to_string_pretty(&schema_for!(stored::record::succession_record::Succession)).unwrap(),
+    ).unwrap();
+    fs::write(
+        out.join("record_addr_pref.schema.json"),
+        serde_json::to_string_pretty(&schema_for!(stored::record::addr_pref_record::AddrPref)).unwrap(),
+    ).unwrap();
+    fs::write(
+        out.join("record_alias.schema.json"),
+        serde_json::to_string_pretty(&schema_for!(stored::record::alias_record::Alias)).unwrap(),
+    ).unwrap();
+    fs::write(
+        out.join("record_handoff.schema.json"),
+        serde_json::to_string_pretty(&schema_for!(stored::record::handoff_record::Handoff)).unwrap(),
+    ).unwrap();
+    fs::write(
+        out.join("record_key_alias.schema.json"),
+        serde_json::to_string_pretty(&schema_for!(stored::record::key_alias_record::KeyAlias)).unwrap(),
+    ).unwrap();
+    fs::write(
+        out.join("record_encrypted.schema.json"),
+        serde_json::to_string_pretty(&schema_for!(stored::record::encrypted_record::EncryptedData)).unwrap(),
+    ).unwrap();
     fs::write(
         out.join("resolve.schema.json"),
         &serde_json::to_string_pretty(&schema_for!(wire::api::resolve::latest::ResolveKeyValues)).unwrap(),
@@ -13,10 +13,19 @@ use {
         interface::{
             config::{
                 auto::Config,
-                shared::GlobalAddrConfig,
+                content::{
+                    ContentConfig,
+                    ServeMode,
+                },
+                shared::{
+                    GlobalAddrConfig,
+                    IdentitySecretArg,
+                    StrSocketAddr,
+                },
                 DebugFlag,
                 ENV_CONFIG,
             },
+            wire::resolve::DNS_SUFFIX,
         },
         publishing::{
             system_publisher_url_pairs,
@@ -28,10 +37,14 @@ use {
             self,
             RequestCertOptions,
         },
-        service::content::start_serving_content,
+        service::content::{
+            start_serving_content,
+            ContentMetrics,
+        },
         ta_res,
         utils::{
             fs_util::cache_dir,
+            graceful::DEFAULT_SHUTDOWN_GRACE,
             identity_secret::get_identity_signer,
             publish_util::{
                 self,
@@ -44,6 +57,7 @@ use {
     },
     std::{
         collections::HashMap,
+        path::PathBuf,
         sync::Arc,
     },
     taskmanager::TaskManager,
@@ -57,14 +71,55 @@ use {
 struct Args {
     /// Config - json.  See the reference documentation and jsonschema for details.
     pub config: Option<AargvarkJson<Config>>,
+    /// Instead of a config, publish this host's IPs as this local identity (a file
+    /// created with `spagh identity new-local`)
+    pub identity: Option<PathBuf>,
+    /// With `identity`, get a TLS cert and serve static files from this directory
+    /// over HTTPS on port 443
+    pub content_dir: Option<PathBuf>,
     /// Enable debug logging
     #[vark(break_help)]
     pub debug: Option<Vec<DebugFlag>>,
 }
 
+/// Config for running with just an identity and content directory. IPs are
+/// detected from the interfaces and certs are stored in the cache dir.
+fn quickstart_config(identity: PathBuf, content_dir: Option<PathBuf>) -> Config {
+    let mut content = vec![];
+    if let Some(content_dir) = content_dir {
+        content.push(ContentConfig {
+            items: [
+                (
+                    StrSocketAddr::new("[::]:443"),
+                    [("/".to_string(), ServeMode::StaticFiles { content_dir: content_dir })].into_iter().collect(),
+                ),
+            ]
+                .into_iter()
+                .collect(),
+            access_log: None,
+            compression: None,
+            hosts: vec![],
+        });
+    }
+    return Config {
+        cache_dir: None,
+        global_addrs: vec![],
+        identity: IdentitySecretArg::Local(identity),
+        ssh_host_keys: None,
+        cert_dir: None,
+        content: content,
+        no_certifier: false,
+    };
+}
+
 async fn inner(log: &Log, tm: &TaskManager, args: Args) -> Result<(), loga::Error> {
+    let quickstart = args.config.is_none() && args.identity.is_some();
     let config = if let Some(p) = args.config {
         p.value
+    } else if let Some(identity) = args.identity {
+        quickstart_config(identity, args.content_dir)
+    } else if args.content_dir.is_some() {
+        return Err(log.err("`content_dir` requires `identity`"));
     } else if let Some(c) = match std::env::var(ENV_CONFIG) {
         Ok(c) => Some(c),
         Err(e) => match e {
@@ -131,7 +186,7 @@ async fn inner(log: &Log, tm: &TaskManager, args: Args) -> Result<(), loga::Erro
             resolver_urls: resolvers,
             publisher_urls: publishers,
         }) as Arc<dyn Publisher>;
-        let Some((certs, _)) =
+        let Some((certs, _, _)) =
             self_tls::htserve_certs(
                 log,
                 &config.cache_dir.unwrap_or_else(|| cache_dir()),
@@ -147,14 +202,20 @@ async fn inner(log: &Log, tm: &TaskManager, args: Args) -> Result<(), loga::Erro
                 Some(&publisher),
                 &identity_signer,
                 RequestCertOptions {
-                    certifier: true,
+                    certifier: !config.no_certifier,
                     signature: false,
                 },
             ).await? else {
                 return Ok(());
             };
+        let content_metrics = ContentMetrics::default();
+        let serving = !config.content.is_empty();
         for content in config.content {
-            start_serving_content(log, tm, certs.clone(), content).await?;
+            start_serving_content(log, tm, DEFAULT_SHUTDOWN_GRACE, certs.clone(), &content_metrics, content).await?;
+        }
+        if quickstart && serving {
+            let identity = identity_signer.lock().unwrap().identity()?;
+            log.log_with(loga::INFO, "Serving content", ea!(url = format!("https://{}.{}", identity, DNS_SUFFIX)));
         }
     } else {
         tm.terminate();
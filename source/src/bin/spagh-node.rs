@@ -3,38 +3,58 @@ use {
         traits_impls::AargvarkJson,
         Aargvark,
     },
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
     flowcontrol::shed,
+    futures::{
+        Stream,
+        StreamExt,
+    },
     htwrap::htserve::{
         self,
-        auth::{
-            check_auth_token_hash,
-            get_auth_token,
-            hash_auth_token,
-            AuthTokenHash,
+        handler::{
+            tls_acceptor,
+            Handler,
         },
-        handler::tls_acceptor,
         responses::{
+            body_full,
             response_200,
             response_200_json,
-            response_400,
-            response_401,
-            response_503,
         },
     },
+    http_body_util::{
+        combinators::BoxBody,
+        StreamBody,
+    },
+    hyper::body::{
+        Bytes,
+        Frame,
+    },
     loga::{
         ea,
+        DebugDisplay,
         Log,
         ResultContext,
     },
     spaghettinuum::{
+        cap_fn,
         interface::{
             config::{
                 self,
                 identity::LocalIdentitySecret,
                 node::{
                     api_config::DEFAULT_API_PORT,
-                    node_config::DEFAULT_NODE_PORT,
-                    publisher_config::DEFAULT_PUBLISHER_PORT,
+                    node_config::{
+                        NodeSecretStorage,
+                        DEFAULT_NODE_PORT,
+                    },
+                    publisher_config::{
+                        PublisherStorageConfig,
+                        DEFAULT_PUBLISHER_PORT,
+                    },
                     Config,
                 },
                 shared::{
@@ -44,48 +64,128 @@ use {
                 DebugFlag,
                 ENV_CONFIG,
             },
-            stored::shared::SerialAddr,
+            stored::{
+                identity::Identity,
+                record::addr_pref_record::build_addr_pref_key,
+                shared::SerialAddr,
+            },
             wire::{
-                api::publish::latest::InfoResponse,
+                api::{
+                    admin::latest::{
+                        DaemonStatus,
+                        DaemonSubsystems,
+                    },
+                    openapi::{
+                        build_openapi,
+                        API_ROUTE_OPENAPI,
+                    },
+                },
                 node::latest::NodeInfo,
             },
         },
         self_tls::{
             self,
             RequestCertOptions,
+            SelfTlsMonitor,
         },
         service::{
-            content::start_serving_content,
+            backup::{
+                start_backups,
+                Backups,
+            },
+            content::{
+                start_serving_content,
+                ContentMetrics,
+                ContentSite,
+            },
+            identity_status::identity_status,
             node::{
                 default_bootstrap,
+                traffic_retention,
+                transport::{
+                    start_relay_server,
+                    RelayTransport,
+                },
                 Node,
             },
             publisher::{
                 self,
+                storage::{
+                    sqlite::SqliteStorage,
+                    PublisherStorage,
+                },
+                test_addr_families,
                 Publisher,
                 API_ROUTE_PUBLISH,
             },
             resolver::{
                 self,
+                dns::DnsMetrics,
+                external_names::ExternalNames,
                 Resolver,
                 API_ROUTE_RESOLVE,
             },
+            single_port::{
+                start_serving_single_port,
+                SinglePortRoutes,
+            },
+            WatchSnapshot,
         },
         ta_res,
         ta_vis_res,
         utils::{
+            admin_auth::{
+                AdminTokens,
+                PublicOnlyHandler,
+            },
+            api_error::{
+                response_bad_request,
+                response_internal,
+                response_not_found,
+                response_overloaded,
+                response_unauthorized,
+            },
+            compress::CompressHandler,
+            db_util::{
+                db_disk_usage,
+                DbUsage,
+            },
             fs_util::{
                 self,
                 maybe_read_json,
             },
             identity_secret::get_identity_signer,
+            local_identity::write_identity_secret,
+            privacy::set_privacy_mode,
             publish_util::{
+                add_addr_pref_record,
                 add_ip_record,
                 add_ssh_host_key_records,
                 generate_publish_announce,
                 PublishArgs,
             },
+            recent_errors::{
+                log_warn_err,
+                recent_errors,
+            },
+            load_shed::{
+                LoadShed,
+                LoadShedHandler,
+            },
+            request_id::RequestIdHandler,
             system_addr::resolve_global_ip,
+            task_status::{
+                failed_tasks,
+                tasks,
+                TrackedTasks,
+            },
+            graceful::{
+                handle_https_conn,
+                serve_draining,
+                DEFAULT_SHUTDOWN_GRACE,
+            },
+            tls_util::IdentityClientCertVerifier,
+            unix_http::serve_unix,
             ResultVisErr,
             VisErr,
         },
@@ -95,7 +195,6 @@ use {
             HashMap,
             HashSet,
         },
-        fs,
         net::{
             IpAddr,
             Ipv4Addr,
@@ -104,13 +203,19 @@ use {
             SocketAddrV4,
             SocketAddrV6,
         },
-        sync::Arc,
+        path::PathBuf,
+        str::FromStr,
+        sync::{
+            Arc,
+            Mutex,
+        },
     },
     taskmanager::TaskManager,
     tokio::{
         fs::create_dir_all,
         select,
     },
+    tokio_stream::wrappers::IntervalStream,
 };
 
 #[derive(Aargvark)]
@@ -120,9 +225,24 @@ struct Args {
     /// Enable default debug logging, or specific log levels
     #[vark(break_help)]
     pub debug: Option<Vec<DebugFlag>>,
+    /// Print the OpenAPI specification for the API endpoints and exit
+    pub print_openapi: Option<()>,
+}
+
+/// A JSON lines response, sending each line as soon as it's produced.
+fn response_200_json_lines(
+    lines: impl Stream<Item = Bytes> + Send + Sync + 'static,
+) -> http::Response<htserve::responses::Body> {
+    return http::Response::builder()
+        .status(http::StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, "application/jsonl")
+        .header(http::header::CACHE_CONTROL, "no-store")
+        .body(BoxBody::new(StreamBody::new(lines.map(|l| Ok(Frame::data(l))))))
+        .unwrap();
 }
 
 async fn inner(log: &Log, tm: &TaskManager, args: Args) -> Result<(), loga::Error> {
+    let started = Utc::now();
     // Load and parse config, prep environment
     let mut debug_flags = HashSet::<DebugFlag>::new();
     if let Some(f) = &args.debug {
@@ -158,8 +278,11 @@ async fn inner(log: &Log, tm: &TaskManager, args: Args) -> Result<(), loga::Erro
             ),
         );
     };
+    set_privacy_mode(config.privacy_mode);
     let data_dir = config.persistent_dir.unwrap_or_else(|| fs_util::data_dir());
     let cache_dir = config.cache_dir.unwrap_or_else(|| fs_util::cache_dir());
+    let shutdown_grace =
+        config.shutdown_grace_period.map(std::time::Duration::from_secs).unwrap_or(DEFAULT_SHUTDOWN_GRACE);
     create_dir_all(&data_dir)
         .await
         .stack_context_with(log, "Error creating persistent data dir", ea!(path = data_dir.to_string_lossy()))?;
@@ -188,53 +311,164 @@ async fn inner(log: &Log, tm: &TaskManager, args: Args) -> Result<(), loga::Erro
             break IdentitySecretArg::Local(ident_path);
         }
         let (_, s) = LocalIdentitySecret::new();
-        fs_util::write(&ident_path, &serde_json::to_vec_pretty(&s).unwrap()).await?;
+        write_identity_secret(&ident_path, &s).await?;
         break IdentitySecretArg::Local(ident_path);
     };
     let identity_signer =
         get_identity_signer(identity_secret.clone()).await.stack_context(log, "Error loading identity")?;
 
     // Prep for api
+    let jsonrpc = config.api.as_ref().map(|a| a.jsonrpc).unwrap_or(false);
+    let load_shedding = config.api.as_ref().and_then(|a| a.load_shedding.as_ref());
+    let shed_retry_after = load_shedding.and_then(|c| c.retry_after_secs).unwrap_or(1);
+    let resolver_max_waiting_lookups = load_shedding.and_then(|c| c.resolver_max_waiting_lookups);
+    let resolver_shed =
+        load_shedding
+            .filter(|_| config.resolver.is_some())
+            .map(|c| LoadShed::new(c.resolver_max_in_flight.unwrap_or(512), shed_retry_after));
+    let publisher_shed =
+        load_shedding
+            .filter(|_| config.publisher.is_some())
+            .map(|c| LoadShed::new(c.publisher_max_in_flight.unwrap_or(256), shed_retry_after));
     let mut router = htserve::handler::PathRouter::default();
-    router.insert("/health", Box::new(htwrap::handler!(()(_r -> htserve:: responses:: Body) {
+
+    // Unhealthy while overloaded, so load balancers send requests elsewhere
+    let sheds = resolver_shed.iter().chain(publisher_shed.iter()).cloned().collect::<Vec<_>>();
+    router.insert("/health", Box::new(htwrap::handler!((sheds: Vec < LoadShed >)(_r -> htserve:: responses:: Body) {
+        if let Some(shed) = sheds.iter().find(|s| s.overloaded()) {
+            return response_overloaded(shed.retry_after());
+        }
         return response_200();
     }))).unwrap();
+    let openapi = Arc::new(build_openapi());
+    router.insert(format!("/{}", API_ROUTE_OPENAPI), Box::new(htwrap::handler!((openapi: Arc < serde_json::Value >)(
+        _r -> htserve:: responses:: Body
+    ) {
+        return response_200_json(openapi.as_ref());
+    }))).unwrap();
 
     // Start node
+    let mut listen_addrs = vec![];
     let node = {
         let log = log.fork_with_log_from(debug_level(DebugFlag::Node), ea!(sys = "node"));
         let mut bootstrap = vec![];
+        let mut protected = vec![];
         match config.node.bootstrap {
             Some(bootstrap1) => {
                 for e in bootstrap1 {
-                    bootstrap.push(NodeInfo {
+                    let info = NodeInfo {
                         ident: e.ident,
                         address: SerialAddr(
                             e.addr.resolve().stack_context(&log, "Error resolving bootstrap node address")?,
                         ),
-                    });
+                    };
+                    if e.protected {
+                        protected.push(info.clone());
+                    }
+                    bootstrap.push(info);
                 }
             },
             None => {
                 bootstrap = default_bootstrap();
             },
         }
-        Node::new(
-            &log,
-            &tm,
-            config
-                .node
-                .bind_addr
-                .unwrap_or_else(
-                    || StrSocketAddr::from(
-                        SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, DEFAULT_NODE_PORT, 0, 0)),
-                    ),
-                ),
-            &bootstrap,
-            &cache_dir,
-        ).await?
+        if let Some(relay_server) = config.node.relay_server {
+            let udp_bind_addr =
+                relay_server.udp_bind_addr.resolve().stack_context(&log, "Error resolving relay UDP bind address")?;
+            let tcp_bind_addr =
+                relay_server.tcp_bind_addr.resolve().stack_context(&log, "Error resolving relay TCP bind address")?;
+            start_relay_server(
+                &log,
+                tm,
+                udp_bind_addr,
+                tcp_bind_addr,
+                relay_server.token.get().to_string(),
+            ).await?;
+            listen_addrs.push(format!("node relay udp {}", udp_bind_addr));
+            listen_addrs.push(format!("node relay tcp {}", tcp_bind_addr));
+        }
+        if let Some(relay) = config.node.relay {
+            if config.node.request_socket_rotate_interval.is_some() {
+                return Err(log.err("Node request socket rotation can't be used with a relay"));
+            }
+            if config.node.quic {
+                return Err(log.err("Node QUIC can't be used with a relay"));
+            }
+            let socks5_proxy = match &relay.socks5_proxy {
+                Some(p) => Some(p.resolve().stack_context(&log, "Error resolving relay SOCKS5 proxy address")?),
+                None => None,
+            };
+            let transport =
+                RelayTransport::new(
+                    &log,
+                    tm,
+                    &relay.relay_addr,
+                    socks5_proxy,
+                    relay.token.get().to_string(),
+                ).stack_context(&log, "Error setting up relay transport")?;
+            listen_addrs.push(format!("node relay {}", relay.relay_addr));
+            Node::new_with_transport(
+                &log,
+                tm,
+                Arc::new(transport),
+                &bootstrap,
+                &protected,
+                config.node.peers_dir.clone(),
+                &cache_dir,
+                config.node.secret_storage.as_ref().unwrap_or(&NodeSecretStorage::Plaintext),
+                config.node.churn_snapshot_interval.map(|m| Duration::try_minutes(m.max(1) as i64).unwrap()),
+                config.node.max_stored_announcements,
+                config.node.no_store,
+                config.node.store_neighborhood_tolerance,
+                config.node.packet_workers,
+                config.node.verified_peer_window.map(|m| Duration::try_minutes(m as i64).unwrap()),
+                config.node.proximity_index,
+                config.node.prune_unresponsive_after.map(|m| Duration::try_minutes(m.max(1) as i64).unwrap()),
+                config.node.pruned_peer_memory.map(|m| Duration::try_minutes(m as i64).unwrap()),
+                config.node.clock_skew_tolerance.map(|s| Duration::try_seconds(s.max(1) as i64).unwrap()),
+            ).await?
+        } else {
+            let bind_addr =
+                config
+                    .node
+                    .bind_addr
+                    .unwrap_or_else(
+                        || StrSocketAddr::from(
+                            SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, DEFAULT_NODE_PORT, 0, 0)),
+                        ),
+                    );
+            listen_addrs.push(format!("node udp {}", bind_addr.0));
+            Node::new(
+                &log,
+                tm,
+                bind_addr,
+                &bootstrap,
+                &protected,
+                config.node.peers_dir.clone(),
+                &cache_dir,
+                config.node.secret_storage.as_ref().unwrap_or(&NodeSecretStorage::Plaintext),
+                config.node.churn_snapshot_interval.map(|m| Duration::try_minutes(m.max(1) as i64).unwrap()),
+                config.node.max_stored_announcements,
+                config.node.no_store,
+                config.node.store_neighborhood_tolerance,
+                config.node.request_socket_rotate_interval.map(|m| Duration::try_minutes(m.max(1) as i64).unwrap()),
+                config.node.packet_workers,
+                config.node.verified_peer_window.map(|m| Duration::try_minutes(m as i64).unwrap()),
+                config.node.proximity_index,
+                config.node.prune_unresponsive_after.map(|m| Duration::try_minutes(m.max(1) as i64).unwrap()),
+                config.node.pruned_peer_memory.map(|m| Duration::try_minutes(m as i64).unwrap()),
+                config.node.clock_skew_tolerance.map(|s| Duration::try_seconds(s.max(1) as i64).unwrap()),
+                config.node.quic,
+            ).await?
+        }
     };
 
+    // Databases for disk usage reporting (name, path, size limit)
+    let mut databases = vec![
+        ("node".to_string(), cache_dir.join("node.sqlite3"), None),
+        ("self_tls".to_string(), cache_dir.join("self_tls.sqlite3"), None)
+    ];
+
     // Start publisher
     let publisher;
     if let Some(publisher_config) = config.publisher {
@@ -249,22 +483,93 @@ async fn inner(log: &Log, tm: &TaskManager, args: Args) -> Result<(), loga::Erro
                 )
                 .resolve()
                 .stack_context(log, "Error resolving publisher bind address")?;
-        let advertise_ip =
-            *global_ips
-                .get(0)
-                .stack_context(log, "Running a publisher requires at least one configured global IP")?;
+        if global_ips.is_empty() {
+            return Err(log.err("Running a publisher requires at least one configured global IP"));
+        }
+
+        // Advertise every global IP the bind address can accept connections on
         let advertise_port = publisher_config.advertise_port.unwrap_or(bind_addr.port());
-        let advertise_addr = SocketAddr::new(advertise_ip, advertise_port);
+        let mut advertise_addrs = vec![];
+        for ip in &global_ips {
+            let bindable = match bind_addr.ip() {
+                IpAddr::V6(b) if b.is_unspecified() => true,
+                b => b.is_ipv4() == ip.is_ipv4(),
+            };
+            if !bindable {
+                continue;
+            }
+            advertise_addrs.push(
+                SocketAddr::new(*ip, publisher_config.advertise_port_by_ip.get(ip).cloned().unwrap_or(advertise_port)),
+            );
+        }
+        if advertise_addrs.is_empty() {
+            return Err(
+                log.err_with(
+                    "None of the global IPs are reachable through the publisher bind address",
+                    ea!(bind_addr = bind_addr, global_ips = global_ips.dbg_str()),
+                ),
+            );
+        }
+        listen_addrs.push(format!("publisher tcp {}", bind_addr));
+        let storage: Arc<dyn PublisherStorage>;
+        match publisher_config.storage.clone().unwrap_or(PublisherStorageConfig::Sqlite) {
+            PublisherStorageConfig::Sqlite => {
+                storage = Arc::new(SqliteStorage::new(log, &data_dir).await?);
+                databases.push(
+                    ("publisher".to_string(), data_dir.join("publisher.sqlite3"), publisher_config.max_db_size),
+                );
+                databases.push(("publisher_admin".to_string(), data_dir.join("publisher_admin.sqlite3"), None));
+            },
+            PublisherStorageConfig::Postgres { url, max_connections } => {
+                #[cfg(not(feature = "postgres"))]
+                {
+                    _ = (url, max_connections);
+                    return Err(
+                        log.err(
+                            "Publisher Postgres storage is configured but this build doesn't have the postgres feature",
+                        ),
+                    );
+                }
+                #[cfg(feature = "postgres")]
+                {
+                    log.log(loga::WARN, "Publisher Postgres storage is experimental");
+                    storage =
+                        Arc::new(
+                            publisher::storage::postgres::PostgresStorage::new(
+                                log,
+                                url.get(),
+                                max_connections.unwrap_or(16),
+                            ).await?,
+                        );
+                }
+            },
+        }
         let publisher1 =
-            Publisher::new(log, &tm, node.clone(), bind_addr, advertise_addr, &data_dir)
+            Publisher::new(
+                log,
+                tm,
+                node.clone(),
+                bind_addr,
+                advertise_addrs,
+                storage,
+                !publisher_config.no_read_stats,
+                publisher_config.max_db_size,
+                publisher_config.publish_policy.as_ref(),
+                publisher_config.retention.as_ref(),
+                publisher_config.authorized_resolvers.clone(),
+                publisher_config.watchdog.as_ref(),
+                publisher_config.tombstone_ttl_minutes,
+                shutdown_grace,
+            )
                 .await
                 .stack_context(log, "Error setting up publisher")?;
 
         // Publish self
-        let (identity, announcement) = generate_publish_announce(&identity_signer, vec![InfoResponse {
-            advertise_addr: advertise_addr,
-            cert_pub_hash: publisher1.pub_cert_hash(),
-        }]).map_err(|e| log.err_with("Failed to generate announcement for self publication", ea!(err = e)))?;
+        let (identity, announcement) =
+            generate_publish_announce(
+                &identity_signer,
+                vec![publisher1.info()],
+            ).map_err(|e| log.err_with("Failed to generate announcement for self publication", ea!(err = e)))?;
         publisher1.announce(&identity, announcement).await?;
         let mut publish_data = HashMap::new();
         for ip in &global_ips {
@@ -276,13 +581,55 @@ async fn inner(log: &Log, tm: &TaskManager, args: Args) -> Result<(), loga::Erro
             set: publish_data,
             ..Default::default()
         }).await?;
+
+        // If publishing both IPv4 and IPv6 addresses, periodically check that both work
+        // and tell clients which to prefer if one doesn't
+        if global_ips.iter().any(|i| i.is_ipv4()) && global_ips.iter().any(|i| i.is_ipv6()) {
+            let log = log.fork(ea!(subsys = "addr_self_test"));
+            let publisher = publisher1.clone();
+            let node = node.clone();
+            let global_ips = global_ips.clone();
+            let published = Arc::new(Mutex::new(None));
+            tm.tracked_periodic(
+                "Publisher - address family self-test",
+                Duration::try_hours(1).unwrap().to_std().unwrap(),
+                cap_fn!(()(log, publisher, node, identity, global_ips, published) {
+                    let prefer = test_addr_families(&log, &node, &global_ips, advertise_port).await;
+                    if *published.lock().unwrap() == Some(prefer) {
+                        return;
+                    }
+                    let mut args = PublishArgs::default();
+                    match prefer {
+                        Some(prefer) => {
+                            log.log_with(
+                                loga::INFO,
+                                "Only one address family is reachable, publishing preference",
+                                ea!(prefer = prefer.dbg_str()),
+                            );
+                            add_addr_pref_record(&mut args.set, vec![], 5, prefer);
+                        },
+                        None => {
+                            args.clear.insert(build_addr_pref_key(vec![]));
+                        },
+                    }
+                    match publisher.modify_values(&identity, args).await {
+                        Ok(_) => {
+                            *published.lock().unwrap() = Some(prefer);
+                        },
+                        Err(e) => {
+                            log_warn_err(&log, e.context("Error publishing address family preference"));
+                        },
+                    }
+                }),
+            );
+        }
         publisher = Some(publisher1);
     } else {
         publisher = None;
     }
 
     // Get own tls cert
-    let Some((certs, r21_certs)) =
+    let Some((certs, r21_certs, self_tls_monitor)) =
         self_tls::htserve_certs(
             &log.fork_with_log_from(debug_level(DebugFlag::SelfTls), ea!(sys = "self_tls")),
             &cache_dir,
@@ -302,72 +649,220 @@ async fn inner(log: &Log, tm: &TaskManager, args: Args) -> Result<(), loga::Erro
         };
 
     // Start resolver
-    if let Some(resolver_config) = config.resolver {
-        let resolver =
+    let mut dns_bridge = false;
+    let dns_metrics = DnsMetrics::default();
+    let mut acme_zones = vec![];
+    let resolver = if let Some(resolver_config) = config.resolver {
+        let resolver = if let Some(fixtures_path) = &resolver_config.fixtures {
+            #[cfg(not(feature = "fixtures"))]
+            {
+                _ = fixtures_path;
+                return Err(
+                    log.err("Resolver fixtures are configured but this build doesn't have the fixtures feature"),
+                );
+            }
+            #[cfg(feature = "fixtures")]
+            {
+                let fixtures =
+                    resolver::fixtures::Fixtures::load(fixtures_path)
+                        .await
+                        .stack_context(log, "Error loading resolver fixtures")?;
+                log.log(loga::WARN, "Resolver is answering from fixtures, not the network");
+                Resolver::new_fixtures(
+                    &log.fork_with_log_from(debug_level(DebugFlag::Resolve), ea!(sys = "resolver")),
+                    fixtures,
+                )
+            }
+        } else {
             Resolver::new(
                 &log.fork_with_log_from(debug_level(DebugFlag::Resolve), ea!(sys = "resolver")),
                 &tm,
                 node.clone(),
                 resolver_config.max_cache,
+                resolver_config.max_cache_entries_per_identity,
+                resolver_config.max_persisted_cache,
+                resolver_config.max_announcement_cache,
+                resolver_config.announcement_cache_ttl_minutes.map(|m| Duration::try_minutes(m as i64).unwrap()),
+                resolver_config.missing_identity_ttl_minutes.map(|m| Duration::try_minutes(m as i64).unwrap()),
+                resolver_config.max_missing_ttl_minutes.map(|m| Duration::try_minutes(m as i64).unwrap()),
+                resolver_config.max_parallel_dht_lookups,
                 &cache_dir,
                 publisher.clone(),
+                resolver_config.local_publisher_fast_path,
                 global_ips.clone(),
+                resolver_config.allow_private_publishers,
+                if resolver_config.sign_publisher_requests {
+                    Some(identity_signer.clone())
+                } else {
+                    None
+                },
+                resolver_config.suspect_report_ttl_seconds.map(|s| Duration::try_seconds(s as i64).unwrap()),
+            )
+                .await
+                .stack_context(log, "Error setting up resolver")?
+        };
+        if resolver_config.fixtures.is_none() {
+            databases.push(
+                (
+                    "resolver".to_string(),
+                    cache_dir.join("resolver.sqlite3"),
+                    resolver_config.max_persisted_cache.or(resolver_config.max_cache),
+                ),
+            );
+        }
+        if let Some(static_names) = resolver_config.static_names {
+            resolver::static_names::watch_static_names(
+                &log.fork_with_log_from(debug_level(DebugFlag::Resolve), ea!(sys = "resolver")),
+                tm,
+                &resolver,
+                static_names,
             )
                 .await
-                .stack_context(log, "Error setting up resolver")?;
+                .stack_context(log, "Error loading resolver static names")?;
+        }
+        if let Some(warm_config) = resolver_config.warm_cache {
+            resolver::warm_cache::start_warm_cache(
+                &log.fork_with_log_from(debug_level(DebugFlag::Resolve), ea!(sys = "resolver")),
+                tm,
+                &resolver,
+                warm_config,
+            )
+                .await
+                .stack_context(log, "Error setting up resolver cache warming")?;
+        }
         if let Some(dns_config) = resolver_config.dns_bridge {
-            resolver::dns::start_dns_bridge(
+            dns_bridge = true;
+            let identity = identity_signer.lock().unwrap().identity()?.to_string();
+            acme_zones.extend(
+                dns_config.hosted_zones.iter().filter(|z| z.identity == identity).map(|z| z.zone.clone()),
+            );
+            listen_addrs.extend(resolver::dns::start_dns_bridge(
                 &log.fork_with_log_from(debug_level(DebugFlag::Dns), ea!(sys = "resolver_dns")),
                 &tm,
                 &resolver,
                 r21_certs,
                 &global_ips,
+                &dns_metrics,
                 dns_config,
             )
                 .await
-                .stack_context(log, "Error setting up resolver DNS bridge")?;
+                .stack_context(log, "Error setting up resolver DNS bridge")?);
         }
         {
             let log = log.fork_with_log_from(debug_level(DebugFlag::Resolve), ea!(sys = "resolver"));
-            router
-                .insert(format!("/{}", API_ROUTE_RESOLVE), Box::new(resolver::build_api_endpoints(log, &resolver)))
-                .unwrap();
+            let external_names = match &resolver_config.external_names {
+                Some(c) => Some(
+                    Arc::new(ExternalNames::new(c).stack_context(&log, "Error setting up external name lookups")?),
+                ),
+                None => None,
+            };
+            let endpoints =
+                resolver::build_api_endpoints(
+                    log,
+                    &resolver,
+                    jsonrpc,
+                    if resolver_config.sign_responses {
+                        Some(identity_signer.clone())
+                    } else {
+                        None
+                    },
+                    external_names,
+                );
+            let endpoints: Box<dyn Handler<htserve::responses::Body>> = match &resolver_shed {
+                Some(shed) => Box::new(LoadShedHandler {
+                    inner: Arc::new(endpoints),
+                    shed: shed.clone(),
+                    queue: resolver_max_waiting_lookups.map(|max| {
+                        let resolver = resolver.clone();
+                        (Box::new(move || resolver.waiting_dht_lookups()) as Box<dyn Fn() -> usize + Send + Sync>, max)
+                    }),
+                }),
+                None => Box::new(endpoints),
+            };
+            router.insert(format!("/{}", API_ROUTE_RESOLVE), endpoints).unwrap();
+        }
+        Some(resolver)
+    } else {
+        None
+    };
+
+    // Start ACME certificate renewal
+    if !config.acme.is_empty() {
+        let Some(publisher) = &publisher else {
+            return Err(log.err("ACME certificates require the publisher to be enabled"));
+        };
+        if !dns_bridge {
+            return Err(log.err("ACME certificates require the resolver DNS bridge to be enabled"));
+        }
+        for acme_config in config.acme {
+            self_tls::acme::start_acme(
+                &log.fork_with_log_from(debug_level(DebugFlag::SelfTls), ea!(sys = "acme")),
+                tm,
+                &cache_dir,
+                publisher.clone() as Arc<dyn spaghettinuum::publishing::Publisher>,
+                &identity_signer,
+                &acme_zones,
+                acme_config,
+            )
+                .await
+                .stack_context(log, "Error setting up ACME certificate")?;
         }
     }
 
+    // Start database backups
+    let backups =
+        start_backups(
+            log,
+            tm,
+            config.backup,
+            databases.iter().map(|(name, path, _)| (name.clone(), path.clone())).collect(),
+        );
+
     // Start http api
     let log = log.fork_with_log_from(debug_level(DebugFlag::Api), ea!(sys = "api_http"));
+    let subsystems = DaemonSubsystems {
+        publisher: publisher.is_some(),
+        resolver: resolver.is_some(),
+        dns_bridge: dns_bridge,
+        api: config.api.is_some(),
+        content: config.content.is_some(),
+    };
+    let content_metrics = ContentMetrics::default();
+    let databases = Arc::new(databases);
+    let mut single_port_api = None;
+    let mut single_port_client_cert_auth = false;
     if let Some(api) = config.api {
-        if let Some(admin_token) = api.admin_token {
-            let admin_token = hash_auth_token(&match admin_token {
-                config::node::api_config::AdminToken::File(p) => String::from_utf8(
-                    fs::read(&p).context_with("Error reading admin token file", ea!(path = p.to_string_lossy()))?,
-                ).map_err(|_| loga::err_with("Admin token isn't valid utf8", ea!(path = p.to_string_lossy())))?,
-                config::node::api_config::AdminToken::Inline(p) => p,
-            });
+        let mut raw_admin_tokens = vec![];
+        for token in api.admin_token.into_iter().chain(api.admin_tokens) {
+            raw_admin_tokens.push(token.get().to_string());
+        }
+        if raw_admin_tokens.is_empty() && !api.unix_bind_addrs.is_empty() {
+            // Admin endpoints are always available over unix sockets, use an internal token
+            raw_admin_tokens.push(zbase32::encode_full_bytes(&rand::random::<[u8; 32]>()));
+        }
+        let raw_admin_token = raw_admin_tokens.first().cloned();
+        if !raw_admin_tokens.is_empty() {
+            let admin_token = AdminTokens::new(&raw_admin_tokens);
             router
                 .insert(
                     "/admin/health",
                     Box::new(
                         htwrap::handler!(
-                            (log: Log, node: Node, admin_token: AuthTokenHash)(r -> htserve:: responses:: Body) {
+                            (log: Log, node: Node, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
                                 match async {
                                     ta_vis_res!(http:: Response < htserve:: responses:: Body >);
-                                    if !check_auth_token_hash(
-                                        &admin_token,
-                                        &get_auth_token(&r.head.headers).err_external()?,
-                                    ) {
-                                        return Ok(response_401());
+                                    if !admin_token.check(&r.head.headers).err_external()? {
+                                        return Ok(response_unauthorized());
                                     }
                                     return Ok(response_200_json(node.health_detail()));
                                 }.await {
                                     Ok(r) => return r,
                                     Err(VisErr::External(e)) => {
-                                        return response_400(e);
+                                        return response_bad_request(e);
                                     },
                                     Err(VisErr::Internal(e)) => {
                                         log.log_err(loga::DEBUG, e.context("Error serving admin health endpoint"));
-                                        return response_503();
+                                        return response_internal();
                                     },
                                 }
                             }
@@ -375,27 +870,495 @@ async fn inner(log: &Log, tm: &TaskManager, args: Args) -> Result<(), loga::Erro
                     ),
                 )
                 .unwrap();
-            if let Some(publisher) = &publisher {
+            router
+                .insert(
+                    "/admin/census",
+                    Box::new(
+                        htwrap::handler!(
+                            (
+                                log: Log,
+                                node: Node,
+                                publisher: Option < Arc < Publisher >>,
+                                admin_token: AdminTokens
+                            )(r -> htserve:: responses:: Body) {
+                                match async {
+                                    ta_vis_res!(http:: Response < htserve:: responses:: Body >);
+                                    if !admin_token.check(&r.head.headers).err_external()? {
+                                        return Ok(response_unauthorized());
+                                    }
+
+                                    #[derive(serde::Deserialize)]
+                                    struct Params {
+                                        samples: Option<usize>,
+                                    }
+
+                                    let query =
+                                        serde_urlencoded::from_str::<Params>(r.query)
+                                            .context("Invalid query parameters")
+                                            .err_external()?;
+
+                                    // Check replication of all identities announced by this node's publisher
+                                    let identities = match &publisher {
+                                        Some(publisher) => publisher.list_announced_identities().await.err_internal()?,
+                                        None => vec![],
+                                    };
+                                    return Ok(
+                                        response_200_json(
+                                            node.census(query.samples.unwrap_or(8).min(64), identities).await,
+                                        ),
+                                    );
+                                }.await {
+                                    Ok(r) => return r,
+                                    Err(VisErr::External(e)) => {
+                                        return response_bad_request(e);
+                                    },
+                                    Err(VisErr::Internal(e)) => {
+                                        log.log_err(loga::DEBUG, e.context("Error serving admin census endpoint"));
+                                        return response_internal();
+                                    },
+                                }
+                            }
+                        ),
+                    ),
+                )
+                .unwrap();
+            router
+                .insert(
+                    "/admin/traffic",
+                    Box::new(
+                        htwrap::handler!(
+                            (log: Log, node: Node, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
+                                match async {
+                                    ta_vis_res!(http:: Response < htserve:: responses:: Body >);
+                                    if !admin_token.check(&r.head.headers).err_external()? {
+                                        return Ok(response_unauthorized());
+                                    }
+
+                                    #[derive(serde::Deserialize)]
+                                    struct Params {
+                                        hours: Option<i64>,
+                                    }
+
+                                    let query =
+                                        serde_urlencoded::from_str::<Params>(r.query)
+                                            .context("Invalid query parameters")
+                                            .err_external()?;
+                                    let period =
+                                        Duration::try_hours(query.hours.unwrap_or(24).max(0))
+                                            .context("Hours out of range")
+                                            .err_external()?
+                                            .min(traffic_retention());
+                                    return Ok(
+                                        response_200_json(node.traffic(Utc::now() - period).await.err_internal()?),
+                                    );
+                                }.await {
+                                    Ok(r) => return r,
+                                    Err(VisErr::External(e)) => {
+                                        return response_bad_request(e);
+                                    },
+                                    Err(VisErr::Internal(e)) => {
+                                        log.log_err(loga::DEBUG, e.context("Error serving admin traffic endpoint"));
+                                        return response_internal();
+                                    },
+                                }
+                            }
+                        ),
+                    ),
+                )
+                .unwrap();
+            router
+                .insert(
+                    "/admin/tasks",
+                    Box::new(htwrap::handler!((log: Log, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
+                        match async {
+                            ta_vis_res!(http:: Response < htserve:: responses:: Body >);
+                            if !admin_token.check(&r.head.headers).err_external()? {
+                                return Ok(response_unauthorized());
+                            }
+                            return Ok(response_200_json(tasks()));
+                        }.await {
+                            Ok(r) => return r,
+                            Err(VisErr::External(e)) => {
+                                return response_bad_request(e);
+                            },
+                            Err(VisErr::Internal(e)) => {
+                                log.log_err(loga::DEBUG, e.context("Error serving admin tasks endpoint"));
+                                return response_internal();
+                            },
+                        }
+                    })),
+                )
+                .unwrap();
+            router
+                .insert(
+                    "/admin/disk_usage",
+                    Box::new(
+                        htwrap::handler!(
+                            (
+                                log: Log,
+                                databases: Arc < Vec <(String, PathBuf, Option < u64 >) >>,
+                                admin_token: AdminTokens
+                            )(r -> htserve:: responses:: Body) {
+                                match async {
+                                    ta_vis_res!(http:: Response < htserve:: responses:: Body >);
+                                    if !admin_token.check(&r.head.headers).err_external()? {
+                                        return Ok(response_unauthorized());
+                                    }
+                                    let mut out = vec![];
+                                    for (name, path, limit) in databases.iter() {
+                                        out.push(DbUsage {
+                                            name: name.clone(),
+                                            path: path.clone(),
+                                            bytes: db_disk_usage(path),
+                                            limit: *limit,
+                                        });
+                                    }
+                                    return Ok(response_200_json(out));
+                                }.await {
+                                    Ok(r) => return r,
+                                    Err(VisErr::External(e)) => {
+                                        return response_bad_request(e);
+                                    },
+                                    Err(VisErr::Internal(e)) => {
+                                        log.log_err(loga::DEBUG, e.context("Error serving admin disk usage endpoint"));
+                                        return response_internal();
+                                    },
+                                }
+                            }
+                        ),
+                    ),
+                )
+                .unwrap();
+            router
+                .insert(
+                    "/admin/backup",
+                    Box::new(
+                        htwrap::handler!(
+                            (log: Log, backups: Backups, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
+                                match async {
+                                    ta_vis_res!(http:: Response < htserve:: responses:: Body >);
+                                    if !admin_token.check(&r.head.headers).err_external()? {
+                                        return Ok(response_unauthorized());
+                                    }
+                                    return Ok(response_200_json(backups.status()));
+                                }.await {
+                                    Ok(r) => return r,
+                                    Err(VisErr::External(e)) => {
+                                        return response_bad_request(e);
+                                    },
+                                    Err(VisErr::Internal(e)) => {
+                                        log.log_err(loga::DEBUG, e.context("Error serving admin backup endpoint"));
+                                        return response_internal();
+                                    },
+                                }
+                            }
+                        ),
+                    ),
+                )
+                .unwrap();
+            router
+                .insert(
+                    "/admin/certs",
+                    Box::new(
+                        htwrap::handler!(
+                            (
+                                log: Log,
+                                self_tls_monitor: SelfTlsMonitor,
+                                admin_token: AdminTokens
+                            )(r -> htserve:: responses:: Body) {
+                                match async {
+                                    ta_vis_res!(http:: Response < htserve:: responses:: Body >);
+                                    if !admin_token.check(&r.head.headers).err_external()? {
+                                        return Ok(response_unauthorized());
+                                    }
+                                    return Ok(response_200_json(self_tls_monitor.status()));
+                                }.await {
+                                    Ok(r) => return r,
+                                    Err(VisErr::External(e)) => {
+                                        return response_bad_request(e);
+                                    },
+                                    Err(VisErr::Internal(e)) => {
+                                        log.log_err(loga::DEBUG, e.context("Error serving admin certs endpoint"));
+                                        return response_internal();
+                                    },
+                                }
+                            }
+                        ),
+                    ),
+                )
+                .unwrap();
+            if let Some(resolver) = &resolver {
+                router
+                    .insert(
+                        "/admin/resolver_cache",
+                        Box::new(
+                            htwrap::handler!(
+                                (log: Log, resolver: Resolver, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
+                                    match async {
+                                        ta_vis_res!(http:: Response < htserve:: responses:: Body >);
+                                        if !admin_token.check(&r.head.headers).err_external()? {
+                                            return Ok(response_unauthorized());
+                                        }
+                                        match (r.head.method.clone(), r.subpath) {
+                                            (http::Method::GET, "" | "/") => {
+                                                return Ok(response_200_json(resolver.cache_stats()));
+                                            },
+                                            (method, subpath) => {
+                                                let Some(subpath) = subpath.strip_prefix("/entries") else {
+                                                    return Ok(response_not_found());
+                                                };
+                                                let identity = match subpath.trim_matches('/') {
+                                                    "" => None,
+                                                    i => Some(Identity::from_str(i).err_external()?),
+                                                };
+                                                match method {
+                                                    http::Method::GET => {
+                                                        let entries = resolver.cache_entries(identity.as_ref());
+                                                        return Ok(response_200_json(entries));
+                                                    },
+                                                    http::Method::DELETE => {
+                                                        resolver.purge_cache(identity.as_ref()).await;
+                                                        return Ok(response_200());
+                                                    },
+                                                    _ => return Ok(response_not_found()),
+                                                }
+                                            },
+                                        }
+                                    }.await {
+                                        Ok(r) => return r,
+                                        Err(VisErr::External(e)) => {
+                                            return response_bad_request(e);
+                                        },
+                                        Err(VisErr::Internal(e)) => {
+                                            log.log_err(
+                                                loga::DEBUG,
+                                                e.context("Error serving admin resolver cache endpoint"),
+                                            );
+                                            return response_internal();
+                                        },
+                                    }
+                                }
+                            ),
+                        ),
+                    )
+                    .unwrap();
+            }
+            if config.content.is_some() {
+                router
+                    .insert(
+                        "/admin/content",
+                        Box::new(
+                            htwrap::handler!(
+                                (
+                                    log: Log,
+                                    content_metrics: ContentMetrics,
+                                    admin_token: AdminTokens
+                                )(r -> htserve:: responses:: Body) {
+                                    match async {
+                                        ta_vis_res!(http:: Response < htserve:: responses:: Body >);
+                                        if !admin_token.check(&r.head.headers).err_external()? {
+                                            return Ok(response_unauthorized());
+                                        }
+                                        return Ok(response_200_json(content_metrics.stats()));
+                                    }.await {
+                                        Ok(r) => return r,
+                                        Err(VisErr::External(e)) => {
+                                            return response_bad_request(e);
+                                        },
+                                        Err(VisErr::Internal(e)) => {
+                                            log.log_err(loga::DEBUG, e.context("Error serving admin content endpoint"));
+                                            return response_internal();
+                                        },
+                                    }
+                                }
+                            ),
+                        ),
+                    )
+                    .unwrap();
+            }
+            if dns_bridge {
                 router
                     .insert(
-                        format!("/{}", API_ROUTE_PUBLISH),
+                        "/admin/dns",
                         Box::new(
-                            publisher::build_api_endpoints(
-                                &log.fork_with_log_from(debug_level(DebugFlag::Publish), ea!(sys = "publisher")),
-                                &publisher,
-                                &admin_token,
-                                &data_dir,
-                            )
-                                .await
-                                .stack_context(&log, "Error building publisher endpoints")?,
+                            htwrap::handler!(
+                                (
+                                    log: Log,
+                                    dns_metrics: DnsMetrics,
+                                    admin_token: AdminTokens
+                                )(r -> htserve:: responses:: Body) {
+                                    match async {
+                                        ta_vis_res!(http:: Response < htserve:: responses:: Body >);
+                                        if !admin_token.check(&r.head.headers).err_external()? {
+                                            return Ok(response_unauthorized());
+                                        }
+                                        return Ok(response_200_json(dns_metrics.stats()));
+                                    }.await {
+                                        Ok(r) => return r,
+                                        Err(VisErr::External(e)) => {
+                                            return response_bad_request(e);
+                                        },
+                                        Err(VisErr::Internal(e)) => {
+                                            log.log_err(loga::DEBUG, e.context("Error serving admin DNS endpoint"));
+                                            return response_internal();
+                                        },
+                                    }
+                                }
+                            ),
                         ),
                     )
                     .unwrap();
             }
+            router
+                .insert(
+                    "/admin/identity",
+                    Box::new(
+                        htwrap::handler!(
+                            (
+                                log: Log,
+                                node: Node,
+                                resolver: Option < Resolver >,
+                                publisher: Option < Arc < Publisher >>,
+                                admin_token: AdminTokens
+                            )(r -> htserve:: responses:: Body) {
+                                match async {
+                                    ta_vis_res!(http:: Response < htserve:: responses:: Body >);
+                                    if !admin_token.check(&r.head.headers).err_external()? {
+                                        return Ok(response_unauthorized());
+                                    }
+                                    let identity = match r.subpath.trim_matches('/') {
+                                        "" => return Ok(response_not_found()),
+                                        i => Identity::from_str(i).err_external()?,
+                                    };
+                                    return Ok(
+                                        response_200_json(
+                                            identity_status(
+                                                node,
+                                                publisher.as_deref(),
+                                                resolver.as_ref(),
+                                                &identity,
+                                            ).await.err_internal()?,
+                                        ),
+                                    );
+                                }.await {
+                                    Ok(r) => return r,
+                                    Err(VisErr::External(e)) => {
+                                        return response_bad_request(e);
+                                    },
+                                    Err(VisErr::Internal(e)) => {
+                                        log.log_err(loga::DEBUG, e.context("Error serving admin identity endpoint"));
+                                        return response_internal();
+                                    },
+                                }
+                            }
+                        ),
+                    ),
+                )
+                .unwrap();
+            router
+                .insert(
+                    "/admin/watch",
+                    Box::new(
+                        htwrap::handler!(
+                            (
+                                log: Log,
+                                node: Node,
+                                resolver: Option < Resolver >,
+                                publisher: Option < Arc < Publisher >>,
+                                content_metrics: ContentMetrics,
+                                dns_metrics: DnsMetrics,
+                                subsystems: DaemonSubsystems,
+                                resolver_shed: Option < LoadShed >,
+                                publisher_shed: Option < LoadShed >,
+                                admin_token: AdminTokens
+                            )(r -> htserve:: responses:: Body) {
+                                match async {
+                                    ta_vis_res!(http:: Response < htserve:: responses:: Body >);
+                                    if !admin_token.check(&r.head.headers).err_external()? {
+                                        return Ok(response_unauthorized());
+                                    }
+
+                                    #[derive(serde::Deserialize)]
+                                    struct Params {
+                                        interval_secs: Option<u64>,
+                                    }
+
+                                    let query =
+                                        serde_urlencoded::from_str::<Params>(r.query)
+                                            .context("Invalid query parameters")
+                                            .err_external()?;
+                                    let interval =
+                                        std::time::Duration::from_secs(query.interval_secs.unwrap_or(2).clamp(1, 60));
+                                    let node = node.clone();
+                                    let resolver = resolver.clone();
+                                    let publisher = publisher.clone();
+                                    let content_metrics = subsystems.content.then(|| content_metrics.clone());
+                                    let dns_metrics = subsystems.dns_bridge.then(|| dns_metrics.clone());
+                                    let resolver_shed = resolver_shed.clone();
+                                    let publisher_shed = publisher_shed.clone();
+
+                                    // Sent until the client disconnects
+                                    return Ok(
+                                        response_200_json_lines(
+                                            IntervalStream::new(tokio::time::interval(interval)).map(move |_| {
+                                                let mut line = serde_json::to_vec(&WatchSnapshot {
+                                                    time: Utc::now(),
+                                                    node: node.health_detail(),
+                                                    resolver: resolver.as_ref().map(|r| r.cache_stats()),
+                                                    publisher: publisher.as_ref().map(|p| p.stats()),
+                                                    content: content_metrics.as_ref().map(|c| c.stats()),
+                                                    dns: dns_metrics.as_ref().map(|d| d.stats()),
+                                                    resolver_load: resolver_shed.as_ref().map(|s| s.stats()),
+                                                    publisher_load: publisher_shed.as_ref().map(|s| s.stats()),
+                                                }).unwrap();
+                                                line.push(b'\n');
+                                                return Bytes::from(line);
+                                            }),
+                                        ),
+                                    );
+                                }.await {
+                                    Ok(r) => return r,
+                                    Err(VisErr::External(e)) => {
+                                        return response_bad_request(e);
+                                    },
+                                    Err(VisErr::Internal(e)) => {
+                                        log.log_err(loga::DEBUG, e.context("Error serving admin watch endpoint"));
+                                        return response_internal();
+                                    },
+                                }
+                            }
+                        ),
+                    ),
+                )
+                .unwrap();
+            if let Some(publisher) = &publisher {
+                let endpoints =
+                    publisher::build_api_endpoints(
+                        &log.fork_with_log_from(debug_level(DebugFlag::Publish), ea!(sys = "publisher")),
+                        publisher,
+                        &admin_token,
+                        api.jsonrpc,
+                    )
+                        .await
+                        .stack_context(&log, "Error building publisher endpoints")?;
+                let endpoints: Box<dyn Handler<htserve::responses::Body>> = match &publisher_shed {
+                    Some(shed) => Box::new(LoadShedHandler {
+                        inner: Arc::new(endpoints),
+                        shed: shed.clone(),
+                        queue: None,
+                    }),
+                    None => Box::new(endpoints),
+                };
+                router.insert(format!("/{}", API_ROUTE_PUBLISH), endpoints).unwrap();
+            }
         }
-        let router = Arc::new(router);
+        let router = Arc::new(RequestIdHandler {
+            log: log.clone(),
+            inner: CompressHandler::wrap(&api.compression, Arc::new(router), |b| body_full(b.to_vec())),
+            trust_client: api.trust_request_ids,
+        });
         let mut api_bind_addrs = api.bind_addrs;
-        if api_bind_addrs.is_empty() {
+        if api_bind_addrs.is_empty() && config.single_port.is_none() {
             api_bind_addrs.push(
                 StrSocketAddr::from(
                     SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, DEFAULT_API_PORT, 0, 0)),
@@ -405,42 +1368,202 @@ async fn inner(log: &Log, tm: &TaskManager, args: Args) -> Result<(), loga::Erro
                 StrSocketAddr::from(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, DEFAULT_API_PORT))),
             );
         }
+
+        // Admin endpoints are only served on the admin listeners if there are any
+        let public_router: Arc<dyn Handler<htserve::responses::Body>> = if api.admin_bind_addrs.is_empty() {
+            router.clone()
+        } else {
+            Arc::new(PublicOnlyHandler { inner: router.clone() })
+        };
+        single_port_api = Some(public_router.clone());
+        single_port_client_cert_auth = api.client_cert_auth;
+        let mut api_listeners = vec![];
         for bind_addr in api_bind_addrs {
+            api_listeners.push(("api", bind_addr, public_router.clone()));
+        }
+        for bind_addr in api.admin_bind_addrs {
+            api_listeners.push(("admin api", bind_addr, router.clone() as Arc<dyn Handler<htserve::responses::Body>>));
+        }
+        for (kind, bind_addr, routes) in api_listeners {
             let bind_addr = bind_addr.resolve().stack_context(&log, "Error resolving api bind address")?;
-            let log = log.clone();
-            let routes = router.clone();
-            let tls_acceptor = tls_acceptor(certs.clone());
-            tm.stream(
+            listen_addrs.push(format!("{} tcp {}", kind, bind_addr));
+            let tls_acceptor = if api.client_cert_auth {
+                let mut server_config =
+                    rustls::ServerConfig::builder()
+                        .with_client_cert_verifier(IdentityClientCertVerifier::new())
+                        .with_cert_resolver(certs.clone());
+                server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+                tokio_rustls::TlsAcceptor::from(Arc::new(server_config))
+            } else {
+                tls_acceptor(certs.clone())
+            };
+            serve_draining(
+                &log,
+                tm,
                 format!("API - Server ({})", bind_addr),
+                shutdown_grace,
                 tokio_stream::wrappers::TcpListenerStream::new(
                     tokio::net::TcpListener::bind(&bind_addr).await.stack_context(&log, "Error binding to address")?,
                 ),
-                move |stream| {
-                    let log = log.clone();
-                    let tls_acceptor = tls_acceptor.clone();
-                    let routes = routes.clone();
-                    async move {
-                        match async {
-                            ta_res!(());
-                            htserve::handler::root_handle_https(&log, tls_acceptor, routes, stream?).await?;
-                            return Ok(());
-                        }.await {
-                            Ok(_) => (),
-                            Err(e) => {
-                                log.log_err(loga::DEBUG, e.context("Error serving request"));
-                                return;
-                            },
-                        }
+                cap_fn!((stream, shutdown)(log, tls_acceptor, routes) {
+                    match async {
+                        ta_res!(());
+                        handle_https_conn(tls_acceptor, routes, stream?, shutdown).await?;
+                        return Ok(());
+                    }.await {
+                        Ok(_) => (),
+                        Err(e) => {
+                            log.log_err(loga::DEBUG, e.context("Error serving request"));
+                            return;
+                        },
                     }
-                },
+                }),
             );
         }
+        for unix_bind in api.unix_bind_addrs {
+            serve_unix(
+                &log,
+                tm,
+                &unix_bind.path,
+                unix_bind.mode()?,
+                shutdown_grace,
+                router.clone(),
+                raw_admin_token.clone(),
+            ).await?;
+            listen_addrs.push(format!("api unix {}", unix_bind.path.to_string_lossy()));
+        }
     }
 
     // Serve content
+    let mut single_port_content = vec![];
     if let Some(content) = config.content {
         for content in content {
-            start_serving_content(&log, tm, certs.clone(), content).await?;
+            if config.single_port.as_ref().is_some_and(|s| s.content) {
+                single_port_content.push(ContentSite::new(&log, &content_metrics, content)?);
+            } else {
+                start_serving_content(&log, tm, shutdown_grace, certs.clone(), &content_metrics, content).await?;
+            }
+        }
+    }
+
+    // Serve everything enabled on one port
+    if let Some(single_port) = config.single_port {
+        let api = if single_port.api || single_port.dns_over_https {
+            let Some(api) = single_port_api else {
+                return Err(log.err("Serving the API or DNS-over-HTTPS on the single port requires the `api` config"));
+            };
+            if single_port.dns_over_https && resolver.is_none() {
+                return Err(log.err("Serving DNS-over-HTTPS on the single port requires the resolver"));
+            }
+            Some(api)
+        } else {
+            None
+        };
+        let bind_addr =
+            single_port.bind_addr.resolve().stack_context(&log, "Error resolving single port bind address")?;
+        start_serving_single_port(
+            &log,
+            tm,
+            shutdown_grace,
+            certs.clone(),
+            single_port_client_cert_auth,
+            bind_addr,
+            SinglePortRoutes {
+                api: api,
+                full_api: single_port.api,
+                dns_over_https: single_port.dns_over_https,
+                api_hosts: single_port.api_hosts.iter().map(|h| h.to_ascii_lowercase()).collect(),
+                content: single_port_content,
+            },
+        ).await?;
+        listen_addrs.push(format!("single port tcp {}", bind_addr));
+    }
+
+    // Serve local control socket
+    {
+        let (control_socket, explicit) = match config.control_socket {
+            Some(c) => (c, true),
+            None => (config::node::api_config::UnixBindConfig {
+                path: fs_util::control_socket_path(),
+                mode: None,
+            }, false),
+        };
+        match async {
+            ta_res!(());
+            if let Some(parent) = control_socket.path.parent() {
+                create_dir_all(parent)
+                    .await
+                    .context_with("Error creating control socket dir", ea!(path = parent.to_string_lossy()))?;
+            }
+            listen_addrs.push(format!("control unix {}", control_socket.path.to_string_lossy()));
+            let identity = identity_signer.lock().unwrap().identity()?;
+            let mut router = htserve::handler::PathRouter::default();
+            router
+                .insert(
+                    "/status",
+                    Box::new(
+                        htwrap::handler!(
+                            (
+                                log: Log,
+                                node: Node,
+                                publisher: Option < Arc < Publisher >>,
+                                identity: Identity,
+                                started: DateTime < Utc >,
+                                listen_addrs: Vec < String >,
+                                subsystems: DaemonSubsystems
+                            )(_r -> htserve:: responses:: Body) {
+                                let announced_identities = match &publisher {
+                                    Some(publisher) => match publisher.list_announced_identities().await {
+                                        Ok(i) => i,
+                                        Err(e) => {
+                                            log_warn_err(log, e.context("Error listing announced identities"));
+                                            vec![]
+                                        },
+                                    },
+                                    None => vec![],
+                                };
+                                let health = node.health_detail();
+                                let failed_tasks = failed_tasks();
+                                return response_200_json(DaemonStatus {
+                                    version: env!("CARGO_PKG_VERSION").to_string(),
+                                    started: *started,
+                                    uptime_seconds: Utc::now().signed_duration_since(*started).num_seconds(),
+                                    healthy: health.responsive_neighbors > 0 && failed_tasks.is_empty(),
+                                    responsive_neighbors: health.responsive_neighbors,
+                                    unresponsive_neighbors: health.unresponsive_neighbors,
+                                    subsystems: subsystems.clone(),
+                                    listen_addrs: listen_addrs.clone(),
+                                    node_identity: node.node_identity().to_string(),
+                                    identity: *identity,
+                                    announced_identities: announced_identities,
+                                    recent_errors: recent_errors(),
+                                    failed_tasks: failed_tasks,
+                                });
+                            }
+                        ),
+                    ),
+                )
+                .unwrap();
+            serve_unix(
+                &log,
+                tm,
+                &control_socket.path,
+                control_socket.mode()?,
+                shutdown_grace,
+                Arc::new(router),
+                None,
+            ).await?;
+            return Ok(());
+        }.await {
+            Ok(_) => { },
+            Err(e) => {
+                if explicit {
+                    return Err(e.context("Error setting up control socket"));
+                }
+
+                // Default location may not be writable when not running as a service
+                log.log_err(loga::INFO, e.context("Couldn't set up control socket at default location, skipping"));
+            },
         }
     }
 
@@ -451,6 +1574,10 @@ async fn inner(log: &Log, tm: &TaskManager, args: Args) -> Result<(), loga::Erro
 #[tokio::main]
 async fn main() {
     let args = aargvark::vark::<Args>();
+    if args.print_openapi.is_some() {
+        println!("{}", serde_json::to_string_pretty(&build_openapi()).unwrap());
+        return;
+    }
     let log = &Log::new_root(if args.debug.is_some() {
         loga::DEBUG
     } else {
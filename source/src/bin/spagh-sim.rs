@@ -0,0 +1,251 @@
+use {
+    aargvark::Aargvark,
+    futures::future::join_all,
+    loga::{
+        ea,
+        Log,
+        ResultContext,
+    },
+    rand::{
+        seq::SliceRandom,
+        thread_rng,
+        Rng,
+    },
+    serde::Serialize,
+    spaghettinuum::{
+        interface::{
+            config::{
+                identity::LocalIdentitySecret,
+                node::node_config::DEFAULT_NODE_PORT,
+            },
+            stored::shared::SerialAddr,
+            wire::{
+                api::publish::latest::InfoResponse,
+                node::latest::NodeInfo,
+            },
+        },
+        service::node::{
+            sim::{
+                SimNetwork,
+                SimNetworkConfig,
+                SimNetworkStats,
+            },
+            Node,
+        },
+        utils::{
+            blob::Blob,
+            identity_secret::IdentitySigner,
+            publish_util::generate_publish_announce,
+        },
+    },
+    std::{
+        collections::BTreeMap,
+        env,
+        net::{
+            Ipv4Addr,
+            SocketAddr,
+            SocketAddrV4,
+        },
+        path::Path,
+        process,
+        sync::{
+            Arc,
+            Mutex,
+        },
+        time::Duration,
+    },
+    taskmanager::TaskManager,
+    tokio::{
+        fs::{
+            create_dir_all,
+            remove_dir_all,
+        },
+        time::sleep,
+    },
+};
+
+/// Start many nodes in this process on a simulated network, store values from
+/// random nodes, then look them up from other random nodes and print a JSON report.
+#[derive(Aargvark)]
+struct Args {
+    /// Number of nodes to start (default 200)
+    pub nodes: Option<usize>,
+    /// Fraction of datagrams to drop, 0 to 1 (default 0)
+    pub loss: Option<f64>,
+    /// Minimum datagram latency, in milliseconds (default 5)
+    pub latency_min_ms: Option<u64>,
+    /// Maximum datagram latency, in milliseconds (default 50)
+    pub latency_max_ms: Option<u64>,
+    /// Seconds to wait after starting the nodes before putting values (default 30)
+    pub settle_secs: Option<u64>,
+    /// Number of values to put and get (default 100)
+    pub puts: Option<usize>,
+    /// Enable debug logging
+    pub debug: Option<()>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+struct Report {
+    nodes: usize,
+    puts: usize,
+    /// Fraction of puts acknowledged by at least one node
+    put_success_rate: f64,
+    /// Fraction of gets that found the value
+    get_success_rate: f64,
+    /// Over successful gets
+    hops_mean: f64,
+    hops_max: usize,
+    /// Number of successful gets by hops
+    hops_histogram: BTreeMap<usize, usize>,
+    /// Responsive routing table entries per node, after settling
+    neighbors_mean: f64,
+    network: SimNetworkStats,
+}
+
+fn sim_addr(i: usize) -> SocketAddr {
+    let i = i + 1;
+    return SocketAddr::V4(
+        SocketAddrV4::new(Ipv4Addr::new(10, (i >> 16) as u8, (i >> 8) as u8, i as u8), DEFAULT_NODE_PORT),
+    );
+}
+
+async fn inner(log: &Log, tm: &TaskManager, root: &Path, args: Args) -> Result<(), loga::Error> {
+    let node_count = args.nodes.unwrap_or(200);
+    let put_count = args.puts.unwrap_or(100);
+    if node_count < 2 {
+        return Err(loga::err("At least 2 nodes are required"));
+    }
+    let net = SimNetwork::new(SimNetworkConfig {
+        loss: args.loss.unwrap_or(0.),
+        latency_min: Duration::from_millis(args.latency_min_ms.unwrap_or(5)),
+        latency_max: Duration::from_millis(args.latency_max_ms.unwrap_or(50)),
+    });
+
+    // Start nodes, each bootstrapping from a few earlier nodes
+    let mut nodes: Vec<Node> = vec![];
+    let mut infos: Vec<NodeInfo> = vec![];
+    for i in 0 .. node_count {
+        let addr = sim_addr(i);
+        let cache_dir = root.join(i.to_string());
+        create_dir_all(&cache_dir)
+            .await
+            .context_with("Error creating node cache directory", ea!(path = cache_dir.to_string_lossy()))?;
+        let bootstrap = infos.choose_multiple(&mut thread_rng(), 3).cloned().collect::<Vec<_>>();
+        let node =
+            Node::new_sim(
+                &log.fork(ea!(sim_node = i)),
+                tm,
+                net.bind(addr),
+                &bootstrap,
+                &cache_dir,
+            )
+                .await
+                .stack_context_with(log, "Error starting node", ea!(sim_node = i))?;
+        infos.push(NodeInfo {
+            ident: node.node_identity(),
+            address: SerialAddr(addr),
+        });
+        nodes.push(node);
+    }
+    log.log_with(loga::INFO, "Started nodes, waiting for network to settle", ea!(count = node_count));
+    sleep(Duration::from_secs(args.settle_secs.unwrap_or(30))).await;
+    let neighbors_mean =
+        nodes.iter().map(|n| n.health_detail().responsive_neighbors).sum::<usize>() as f64 / node_count as f64;
+
+    // Put values from random nodes
+    log.log_with(loga::INFO, "Putting values", ea!(count = put_count));
+    let puts = join_all((0 .. put_count).map(|_| {
+        let putter_i = thread_rng().gen_range(0 .. node_count);
+        let node = nodes[putter_i].clone();
+        let advertise_addr = sim_addr(putter_i);
+        async move {
+            let (_, secret) = LocalIdentitySecret::new();
+            let signer: Arc<Mutex<dyn IdentitySigner>> = Arc::new(Mutex::new(secret));
+            let (identity, announcement) = generate_publish_announce(&signer, vec![InfoResponse {
+                advertise_addr: advertise_addr,
+                advertise_addrs: vec![],
+                cert_pub_hash: Blob::new(32),
+            }]).map_err(|e| loga::err_with("Error generating announcement", ea!(err = e)))?;
+            let res = node.put(identity, announcement).await;
+            return Ok((putter_i, identity, res.accepted > 0)) as Result<_, loga::Error>;
+        }
+    })).await.into_iter().collect::<Result<Vec<_>, _>>()?;
+    let put_successes = puts.iter().filter(|(_, _, accepted)| *accepted).count();
+
+    // Get each value from a different random node
+    log.log_with(loga::INFO, "Getting values", ea!(count = put_count));
+    let gets = join_all(puts.into_iter().map(|(putter_i, identity, _)| {
+        let mut getter_i = thread_rng().gen_range(0 .. node_count - 1);
+        if getter_i >= putter_i {
+            getter_i += 1;
+        }
+        let node = nodes[getter_i].clone();
+        async move {
+            return node.get_with_hops(identity).await;
+        }
+    })).await;
+    let mut hops_histogram = BTreeMap::new();
+    let mut get_successes = 0;
+    let mut hops_total = 0;
+    for (value, hops) in gets {
+        if value.is_none() {
+            continue;
+        }
+        get_successes += 1;
+        hops_total += hops;
+        *hops_histogram.entry(hops).or_insert(0) += 1;
+    }
+    let rate = |n: usize| if put_count == 0 {
+        0.
+    } else {
+        n as f64 / put_count as f64
+    };
+    let report = Report {
+        nodes: node_count,
+        puts: put_count,
+        put_success_rate: rate(put_successes),
+        get_success_rate: rate(get_successes),
+        hops_mean: if get_successes == 0 {
+            0.
+        } else {
+            hops_total as f64 / get_successes as f64
+        },
+        hops_max: hops_histogram.keys().last().cloned().unwrap_or(0),
+        hops_histogram: hops_histogram,
+        neighbors_mean: neighbors_mean,
+        network: net.stats(),
+    };
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+
+    // Done
+    tm.terminate();
+    return Ok(());
+}
+
+#[tokio::main]
+async fn main() {
+    let args = aargvark::vark::<Args>();
+    let log = &Log::new_root(if args.debug.is_some() {
+        loga::DEBUG
+    } else {
+        loga::INFO
+    });
+    let tm = taskmanager::TaskManager::new();
+    let root = env::temp_dir().join(format!("spagh-sim-{}", process::id()));
+    match inner(log, &tm, &root, args).await.map_err(|e| {
+        tm.terminate();
+        return e;
+    }).also({
+        tm.join(log).await.context("Critical services failed")
+    }).also({
+        remove_dir_all(&root)
+            .await
+            .context_with("Error removing simulation cache directory", ea!(path = root.to_string_lossy()))
+    }) {
+        Ok(_) => { },
+        Err(e) => {
+            loga::fatal(e);
+        },
+    }
+}
@@ -2,18 +2,63 @@ use {
     htwrap::htreq,
     loga::Log,
     spaghettinuum::{
+        interface::wire::api::error::latest::ErrorCode,
         publishing::system_publisher_url_pairs,
         resolving::{
             connect_publisher_node,
             connect_resolver_node,
             default_resolver_url_pairs,
         },
+        utils::api_error::find_error_code,
     },
     std::collections::HashMap,
 };
 
 pub mod spaghlib;
 
+/// Exit code for `get --exists` when a key has no value, so scripts can tell that
+/// apart from a failed lookup.
+pub const EXIT_ABSENT: i32 = 2;
+
+/// Exit codes for errors returned by the API, so scripts can tell failures apart.
+/// Other errors exit with 1, and absent keys with `get --exists` with `EXIT_ABSENT`.
+fn exit_code(code: ErrorCode) -> i32 {
+    match code {
+        ErrorCode::BadRequest => return 10,
+        ErrorCode::BadSignature => return 11,
+        ErrorCode::Expired => return 12,
+        ErrorCode::Unauthorized => return 13,
+        ErrorCode::NotFound => return 14,
+        ErrorCode::RateLimited => return 15,
+        ErrorCode::StorageFull => return 16,
+        ErrorCode::Unavailable => return 17,
+        ErrorCode::Internal => return 18,
+        ErrorCode::Rejected => return 19,
+    }
+}
+
+#[cfg(test)]
+mod test_exit_code {
+    use {
+        super::{
+            exit_code,
+            EXIT_ABSENT,
+        },
+        spaghettinuum::interface::wire::api::error::latest::ErrorCode,
+    };
+
+    #[test]
+    fn test_distinct() {
+        let mut codes = ErrorCode::ALL.iter().map(|c| exit_code(*c)).collect::<Vec<_>>();
+        codes.push(1);
+        codes.push(EXIT_ABSENT);
+        let count = codes.len();
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), count);
+    }
+}
+
 mod args {
     use {
         aargvark::Aargvark,
@@ -32,6 +77,8 @@ mod args {
         Ping(Ping),
         /// Request values associated with provided identity and keys from a resolver
         Get(crate::spaghlib::cli_resolve::args::Query),
+        /// Look up the IP addresses and service descriptors published for a name
+        GetServices(crate::spaghlib::cli_resolve::args::QueryServices),
         Http(crate::spaghlib::cli_http::args::Http),
         Ssh(crate::spaghlib::cli_ssh::args::Ssh),
         /// Commands for managing identities
@@ -40,12 +87,18 @@ mod args {
         Publish(crate::spaghlib::cli_publish::args::Publish),
         /// Commands for node administration
         Admin(crate::spaghlib::cli_admin::args::Admin),
+        /// Commands for inspecting the local node via its control socket
+        Daemon(crate::spaghlib::cli_daemon::args::Daemon),
     }
 
     /// A small CLI for querying, publishing, and administrating spaghettinuum.
     #[derive(Aargvark)]
     pub struct Args {
         pub debug: Option<()>,
+        /// Only log warnings and errors, and don't print the results of publish and
+        /// admin commands that make changes (check commands only print problems).
+        /// Failures are still reported on stderr and in the exit code.
+        pub quiet: Option<()>,
         pub command: Command,
     }
 }
@@ -54,10 +107,12 @@ mod args {
 async fn main() {
     async fn inner() -> Result<(), loga::Error> {
         let args = aargvark::vark::<args::Args>();
-        let log = Log::new_root(match args.debug {
-            Some(_) => loga::DEBUG,
-            None => loga::INFO,
+        let log = Log::new_root(match (args.debug, args.quiet) {
+            (Some(_), _) => loga::DEBUG,
+            (None, Some(_)) => loga::WARN,
+            (None, None) => loga::INFO,
         });
+        let quiet = args.quiet.is_some();
         let log = &log;
         match args.command {
             args::Command::Ping(args) => {
@@ -89,6 +144,9 @@ async fn main() {
             args::Command::Get(args) => {
                 spaghlib::cli_resolve::run_get(log, args).await?;
             },
+            args::Command::GetServices(args) => {
+                spaghlib::cli_resolve::run_get_services(log, args).await?;
+            },
             args::Command::Http(args) => {
                 spaghlib::cli_http::run(log, args).await?;
             },
@@ -96,13 +154,16 @@ async fn main() {
                 spaghlib::cli_ssh::run(log, args).await?;
             },
             args::Command::Publish(args) => {
-                spaghlib::cli_publish::run(log, args).await?;
+                spaghlib::cli_publish::run(log, quiet, args).await?;
             },
             args::Command::Identity(args) => {
                 spaghlib::cli_identity::run(log, args).await?;
             },
             args::Command::Admin(args) => {
-                spaghlib::cli_admin::run(log, args).await?;
+                spaghlib::cli_admin::run(log, quiet, args).await?;
+            },
+            args::Command::Daemon(args) => {
+                spaghlib::cli_daemon::run(log, args).await?;
             },
         }
         return Ok(());
@@ -111,7 +172,15 @@ async fn main() {
     match inner().await {
         Ok(_) => { },
         Err(e) => {
-            loga::fatal(e);
+            match find_error_code(&e) {
+                Some(code) => {
+                    eprintln!("Exiting due to error: {}", e);
+                    std::process::exit(exit_code(code));
+                },
+                None => {
+                    loga::fatal(e);
+                },
+            }
         },
     }
 }
@@ -1,4 +1,9 @@
 use {
+    chrono::{
+        DateTime,
+        SecondsFormat,
+        Utc,
+    },
     htwrap::{
         htreq::{
             self,
@@ -6,6 +11,12 @@ use {
         },
         url::UriJoin,
     },
+    http::{
+        Request,
+        Uri,
+    },
+    http_body_util::Full,
+    hyper::body::Bytes,
     loga::{
         ea,
         Log,
@@ -14,20 +25,56 @@ use {
     serde::de::DeserializeOwned,
     spaghettinuum::{
         interface::{
-            config::ENV_API_ADMIN_TOKEN,
-            stored::identity::Identity,
-            wire::api::admin::v1::{
-                AdminAllowIdentityBody,
-                AdminIdentity,
+            config::{
+                identity::LocalIdentitySecret,
+                node::api_config::DEFAULT_API_PORT,
+                ENV_API_ADMIN_TOKEN,
+            },
+            stored::{
+                self,
+                identity::Identity,
+                record::{
+                    dns_record::{
+                        build_dns_key,
+                        RecordType,
+                    },
+                    record_utils::join_query_record_keys,
+                },
+            },
+            wire::{
+                self,
+                api::admin::v1::{
+                    AdminAllowIdentityBody,
+                    AdminIdentity,
+                    AdminMaintenance,
+                    DaemonStatus,
+                },
+                resolve::DEFAULT_RETRY_AFTER_SECS,
             },
         },
         publishing::system_publisher_url_pairs,
         resolving::{
+            connect_content,
             connect_publisher_node,
+            connect_resolver_node,
             default_resolver_url_pairs,
             UrlPair,
         },
+        service::{
+            identity_status::IdentityStatus,
+            resolver::API_ROUTE_RESOLVE,
+            WatchSnapshot,
+        },
         ta_res,
+        utils::{
+            fs_util,
+            identity_secret::IdentitySigner,
+            publish_util::{
+                self,
+                PublishArgs,
+            },
+            unix_http::unix_get,
+        },
     },
     std::{
         collections::{
@@ -35,6 +82,27 @@ use {
             HashSet,
         },
         env,
+        future::Future,
+        io::Write,
+        net::{
+            IpAddr,
+            SocketAddr,
+        },
+        pin::Pin,
+        str::FromStr,
+        sync::{
+            Arc,
+            Mutex,
+        },
+        task::{
+            Context,
+            Poll,
+        },
+        time::Duration,
+    },
+    tokio::{
+        io::AsyncWrite,
+        time::sleep,
     },
 };
 
@@ -72,9 +140,145 @@ pub mod args {
         pub identity_id: String,
     }
 
+    #[derive(Aargvark)]
+    pub struct ListAllowedIdentities {
+        /// Only list identities starting with this
+        pub prefix: Option<String>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct ListAnnouncements {
+        /// Only list identities starting with this
+        pub prefix: Option<String>,
+        /// Only list announcements published at or after this time (RFC 3339)
+        pub since: Option<String>,
+        /// Only list announcements published before this time (RFC 3339)
+        pub until: Option<String>,
+    }
+
     #[derive(Aargvark)]
     pub struct ListKeys {
         pub identity: String,
+        /// Only list keys starting with this (dotted)
+        pub prefix: Option<String>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Reannounce {
+        pub identity: String,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Census {
+        /// Number of random DHT coordinates to look up for the network size estimate.
+        /// Defaults to 8, max 64.
+        pub samples: Option<usize>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Traffic {
+        /// Include traffic from this many hours ago. Defaults to 24, max 168 (older
+        /// traffic counts are deleted).
+        pub hours: Option<usize>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Watch {
+        /// Seconds between updates. Defaults to 2, max 60.
+        pub interval: Option<u64>,
+        /// Print each update as a line of JSON instead of showing a live view
+        pub json: Option<()>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Usage {
+        /// Start of the report period (RFC 3339). Defaults to 30 days before the end.
+        pub since: Option<String>,
+        /// End of the report period (RFC 3339). Defaults to now.
+        pub until: Option<String>,
+        /// Output CSV instead of JSON
+        pub csv: Option<()>,
+    }
+
+    #[derive(Aargvark)]
+    pub enum ReverseLookup {
+        /// Find identities publishing this IP address in `A` or `AAAA` records
+        Ip(String),
+        /// Find identities publishing a TLS cert with this SPKI hash (zbase32, as in
+        /// announcements)
+        CertHash(String),
+    }
+
+    #[derive(Aargvark)]
+    pub struct AuditLog {
+        /// Start of the period (RFC 3339). Defaults to 30 days before the end.
+        pub since: Option<String>,
+        /// End of the period (RFC 3339). Defaults to now.
+        pub until: Option<String>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct CacheIdentity {
+        /// Only values for this identity. Otherwise all values.
+        pub identity: Option<String>,
+    }
+
+    #[derive(Aargvark)]
+    pub enum Cache {
+        /// Get cache sizes and hit rates
+        Stats,
+        /// List cached values (identity, key, expiry)
+        List(CacheIdentity),
+        /// Remove cached values and announcements, forcing fresh lookups
+        Purge(CacheIdentity),
+    }
+
+    #[derive(Aargvark)]
+    pub struct SelfTest {
+        /// Address of the node's DNS bridge. Defaults to port 53 on the first resolver's
+        /// address.
+        pub dns: Option<String>,
+        /// The node's host identity, for checking HTTPS with spaghettinuum-published
+        /// certs. Defaults to asking the local node via its control socket; the check is
+        /// skipped if that fails.
+        pub identity: Option<String>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct IdentityStatus {
+        pub identity: String,
+        /// Address of the node's DNS bridge. Defaults to port 53 on the first resolver's
+        /// address.
+        pub dns: Option<String>,
+        /// Print the status from the node as JSON, without checking the DNS bridge
+        pub json: Option<()>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct StartMaintenance {
+        /// How long resolvers should wait before retrying, in seconds. Defaults to 60,
+        /// max 3600.
+        pub retry_after: Option<u32>,
+    }
+
+    #[derive(Aargvark)]
+    pub enum Watchdog {
+        /// Show when the watchdog last checked and the current alerts
+        Status,
+        /// Clear the alerts, ex: after investigating identities the publisher no longer
+        /// hosts
+        Clear,
+    }
+
+    #[derive(Aargvark)]
+    pub enum Maintenance {
+        /// Show whether the publisher is in maintenance mode
+        Status,
+        /// Refuse resolve requests from other nodes, telling resolvers to retry later
+        /// and serve stale cached values meanwhile
+        Start(StartMaintenance),
+        /// Resume answering resolve requests
+        Stop,
     }
 
     #[derive(Aargvark)]
@@ -82,16 +286,73 @@ pub mod args {
     pub enum Admin {
         /// Get detailed node health information
         HealthDetail,
+        /// Estimate the DHT network size and check how widely announcements published
+        /// by this node are replicated. This may take a while.
+        Census(Census),
+        /// Get message and byte counts sent to and received from each DHT peer, by
+        /// message type
+        Traffic(Traffic),
+        /// Get request, response status, and bytes sent counts for content served by the
+        /// node
+        ContentStats,
+        /// Get query counts for the node's DNS bridge, including queries limited by
+        /// `response_rate_limit`
+        DnsStats,
+        /// Get the disk space used by each of the node's databases
+        DiskUsage,
+        /// Show when the node's databases were last backed up, any error from the last
+        /// attempt, and the backups kept
+        Backup,
+        /// Show the node's own TLS certificate expiry, the last renewal attempt and its
+        /// result, and when the next renewal is planned
+        Certs,
+        /// List the node's background tasks: when periodic tasks last ran and how long
+        /// they took, and whether any exited with an error
+        Tasks,
+        /// Show a live view of the node's neighbors, DHT activity, resolver cache hit
+        /// rates, publish counts, and content requests, updating until interrupted
+        Watch(Watch),
+        /// Inspect and clear the resolver cache
+        Cache(Cache),
         /// List identities allowed to publish
-        ListAllowedIdentities,
+        ListAllowedIdentities(ListAllowedIdentities),
         /// Register an identity with the publisher, allowing it to publish
         AllowIdentity(AllowIdentity),
         /// Unregister an identity with the publisher, disallowing it from publishing
         DisallowIdentity(DisallowIdentity),
         /// List announced identities
-        ListAnnouncements,
+        ListAnnouncements(ListAnnouncements),
         /// List keys published here for an identity
         ListKeys(ListKeys),
+        /// Get per-identity publisher usage (records, resolves served, bytes sent,
+        /// announcement time) over a period, for accounting on shared publishers. Counts
+        /// are rolled up hourly and kept for 400 days.
+        Usage(Usage),
+        /// List identities the publisher marked dormant, reactivated, or purged under its
+        /// retention policy
+        AuditLog(AuditLog),
+        /// List the identities hosted by the publisher that publish an IP address or TLS
+        /// cert, with the record keys, for tracing traffic back to a name
+        ReverseLookup(ReverseLookup),
+        /// Send a hosted identity's announcement to the network now instead of waiting
+        /// for the next scheduled announce, ex: after suspected DHT data loss. Prints how
+        /// many nodes it was sent to and how many acknowledged storing it.
+        Reannounce(Reannounce),
+        /// Put the publisher in or out of maintenance mode. Maintenance mode isn't kept
+        /// across restarts.
+        Maintenance(Maintenance),
+        /// See whether the publisher watchdog found hosted identities whose network
+        /// announcement is missing or lists other publishers
+        Watchdog(Watchdog),
+        /// Check that a deployed node works end to end: publish a record for a throwaway
+        /// identity, resolve it via the API and the DNS bridge, fetch from the node over
+        /// HTTPS verifying its published cert, then clean up. Reports the result of each
+        /// stage.
+        SelfTest(SelfTest),
+        /// Check whether an identity's name is healthy: its announcement in the network,
+        /// whether this node's publisher serves it, its root records and TLS certs as
+        /// resolved by this node, and whether the DNS bridge resolves it
+        IdentityStatus(IdentityStatus),
         /// Register and unregister identities.
         ///
         /// The JSON is an object with groups as keys, and lists of identity ids as values.
@@ -128,35 +389,677 @@ async fn api_list<
     conn: &mut Conn,
     base_url: &UrlPair,
     path: &str,
+    filter: &[(&str, String)],
     get_key: fn(&T) -> String,
 ) -> Result<Vec<T>, loga::Error> {
     let mut out = vec![];
     let admin_headers = admin_headers()?;
-    let mut res = htreq::get(log, conn, &base_url.url.join(path), &admin_headers, 1024 * 1024).await?;
+    let mut after = None;
     loop {
-        let page: Vec<T> =
-            serde_json::from_slice(&res).context("Failed to parse response page from publisher admin")?;
-        let after = match page.last() {
-            Some(a) => Some(get_key(a)),
-            None => None,
-        };
-        out.extend(page);
-        let Some(after) = after else {
-            break;
-        };
-        res =
+        let mut query = filter.to_vec();
+        if let Some(after) = after {
+            query.push(("after", after));
+        }
+        let res =
             htreq::get(
                 log,
                 conn,
-                &base_url.url.join(format!("{}?after={}", path, after)),
+                &base_url.url.join(format!("{}?{}", path, serde_urlencoded::to_string(&query).unwrap())),
                 &admin_headers,
                 1024 * 1024,
             ).await?;
+        let page: Vec<T> =
+            serde_json::from_slice(&res).context("Failed to parse response page from publisher admin")?;
+        let Some(last) = page.last() else {
+            break;
+        };
+        after = Some(get_key(last));
+        out.extend(page);
     }
     return Ok(out);
 }
 
-pub async fn run(log: &Log, config: args::Admin) -> Result<(), loga::Error> {
+// Resolution right after publishing may need a moment for the announcement to
+// reach the node
+const SELF_TEST_ATTEMPTS: usize = 10;
+const SELF_TEST_GROUP: &str = "spagh-self-test";
+
+enum StageResult {
+    Pass,
+    Fail(loga::Error),
+    Skip(String),
+}
+
+async fn self_test_retry<
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), loga::Error>>,
+>(mut f: F) -> Result<(), loga::Error> {
+    let mut errs = vec![];
+    for _ in 0 .. SELF_TEST_ATTEMPTS {
+        match f().await {
+            Ok(_) => return Ok(()),
+            Err(e) => errs.push(e),
+        }
+        sleep(Duration::from_secs(2)).await;
+    }
+    return Err(errs.pop().unwrap());
+}
+
+/// The DNS bridge address from a command line argument, or else port 53 on the
+/// first resolver's address.
+fn dns_bridge_addr(resolvers: &[UrlPair], arg: &Option<String>) -> Result<Option<SocketAddr>, loga::Error> {
+    match arg {
+        Some(a) => return Ok(
+            Some(SocketAddr::from_str(a).context_with("Invalid DNS bridge address", ea!(addr = a))?),
+        ),
+        None => return Ok(resolvers.iter().find_map(|r| r.address).map(|a| SocketAddr::new(a, 53))),
+    }
+}
+
+/// A DNS client that only queries the DNS bridge, without caching.
+fn dns_bridge_resolver(dns_addr: SocketAddr) -> hickory_resolver::TokioAsyncResolver {
+    let mut dns_config = hickory_resolver::config::ResolverConfig::new();
+    dns_config.add_name_server(
+        hickory_resolver::config::NameServerConfig::new(dns_addr, hickory_resolver::config::Protocol::Udp),
+    );
+    let mut dns_opts = hickory_resolver::config::ResolverOpts::default();
+    dns_opts.cache_size = 0;
+    return hickory_resolver::TokioAsyncResolver::tokio(dns_config, dns_opts);
+}
+
+async fn self_test(
+    log: &Log,
+    quiet: bool,
+    resolvers: &[UrlPair],
+    publishers: &[UrlPair],
+    config: args::SelfTest,
+) -> Result<(), loga::Error> {
+    let mut results = vec![];
+    let (identity, secret) = LocalIdentitySecret::new();
+    let signer: Arc<Mutex<dyn IdentitySigner>> = Arc::new(Mutex::new(secret));
+    let nonce = format!("spagh-self-test-{}", zbase32::encode_full_bytes(&rand::random::<[u8; 10]>()));
+    let key = build_dns_key(vec![], RecordType::Txt);
+    let mut ok = true;
+    macro_rules! stage{
+        ($name: expr, $f: expr) => {
+            let res = if ok {
+                match $f.await {
+                    Ok(_) => StageResult::Pass,
+                    Err(e) => {
+                        // Unread after the last stage
+                        #[allow(unused_assignments)]
+                        {
+                            ok = false;
+                        }
+                        StageResult::Fail(e)
+                    },
+                }
+            } else {
+                StageResult::Skip("An earlier stage failed".to_string())
+            };
+            results.push(($name, res));
+        };
+    }
+
+    // Publish
+    stage!("Allow identity", async {
+        ta_res!(());
+        for pair in publishers {
+            let pair = pair.join(format!("publish/admin/allowed_identities/{}", identity));
+            htreq::post_json::<()>(
+                log,
+                &mut connect_publisher_node(log, resolvers, &pair).await?,
+                &pair.url,
+                &admin_headers()?,
+                AdminAllowIdentityBody { group: SELF_TEST_GROUP.to_string() },
+                100,
+            ).await?;
+        }
+        return Ok(());
+    });
+    let allowed = ok;
+    stage!("Announce", publish_util::announce(log, resolvers, publishers, &signer));
+    stage!("Publish", publish_util::publish(log, resolvers, publishers, &signer, PublishArgs {
+        set: [
+            (
+                key.clone(),
+                stored::record::RecordValue::latest(stored::record::latest::RecordValue {
+                    ttl: 0,
+                    data: Some(
+                        serde_json::to_value(
+                            stored::record::dns_record::DnsTxt::V1(
+                                stored::record::dns_record::latest::DnsTxt(vec![nonce.clone()]),
+                            ),
+                        ).unwrap(),
+                    ),
+                }),
+            ),
+        ].into_iter().collect(),
+        ..Default::default()
+    }));
+
+    // Resolve
+    stage!("Resolve via API", self_test_retry(|| async {
+        ta_res!(());
+        let mut errs = vec![];
+        for resolver in resolvers {
+            match async {
+                ta_res!(());
+                let resp =
+                    htreq::get_json::<wire::api::resolve::v1::ResolveResp>(
+                        log,
+                        &mut connect_resolver_node(resolver).await?,
+                        &resolver
+                            .url
+                            .join(
+                                format!(
+                                    "{}/v1/{}?{}",
+                                    API_ROUTE_RESOLVE,
+                                    identity,
+                                    join_query_record_keys(&[key.clone()])
+                                ),
+                            ),
+                        &HashMap::new(),
+                        1024 * 1024,
+                    ).await?;
+                let found =
+                    resp
+                        .into_iter()
+                        .filter(|(k, _)| k == &key)
+                        .filter_map(|(_, v)| v.data)
+                        .filter_map(|v| serde_json::from_value::<stored::record::dns_record::DnsTxt>(v).ok())
+                        .any(|v| match v {
+                            stored::record::dns_record::DnsTxt::V1(v) => v.0.contains(&nonce),
+                        });
+                if !found {
+                    return Err(loga::err("Published value missing from response"));
+                }
+                return Ok(());
+            }.await {
+                Ok(_) => { },
+                Err(e) => errs.push(e.context_with("Error resolving via resolver", ea!(resolver = resolver))),
+            }
+        }
+        if !errs.is_empty() {
+            return Err(loga::agg_err("Resolving via the API failed", errs));
+        }
+        return Ok(());
+    }));
+    match dns_bridge_addr(resolvers, &config.dns)? {
+        Some(dns_addr) => {
+            stage!("Resolve via DNS bridge", self_test_retry(|| async {
+                let found =
+                    dns_bridge_resolver(dns_addr)
+                        .txt_lookup(format!("{}.s.", identity))
+                        .await
+                        .context_with("Error looking up TXT record", ea!(dns = dns_addr))?
+                        .iter()
+                        .any(|txt| txt.iter().any(|d| &**d == nonce.as_bytes()));
+                if !found {
+                    return Err(loga::err_with("Published value missing from DNS response", ea!(dns = dns_addr)));
+                }
+                return Ok(());
+            }));
+        },
+        None => {
+            results.push(
+                ("Resolve via DNS bridge", StageResult::Skip("No DNS bridge address, use `--dns`".to_string())),
+            );
+        },
+    }
+
+    // Fetch from the node using its published cert
+    let node_identity = match &config.identity {
+        Some(i) => Ok(Identity::from_str(i)?),
+        None => async {
+            ta_res!(Identity);
+            let status =
+                serde_json::from_slice::<DaemonStatus>(&unix_get(&fs_util::control_socket_path(), "/status").await?)
+                    .context("Error parsing status response from daemon")?;
+            return Ok(status.identity);
+        }.await,
+    };
+    match node_identity {
+        Ok(node_identity) => {
+            let port =
+                resolvers.first().and_then(|r| r.url.port_u16()).unwrap_or(DEFAULT_API_PORT);
+            let url = Uri::from_str(&format!("https://{}.s:{}/health", node_identity, port)).unwrap();
+            stage!("Fetch over HTTPS with published cert", self_test_retry(|| async {
+                htreq::get(log, &mut connect_content(log, resolvers, &url).await?, &url, &HashMap::new(), 1024)
+                    .await
+                    .context_with("Error fetching from node", ea!(url = url))?;
+                return Ok(());
+            }));
+        },
+        Err(e) => {
+            results.push(
+                (
+                    "Fetch over HTTPS with published cert",
+                    StageResult::Skip(
+                        format!("Couldn't get node identity from control socket, use `--identity`: {}", e),
+                    ),
+                ),
+            );
+        },
+    }
+
+    // Clean up, even if earlier stages failed
+    if allowed {
+        ok = true;
+        stage!("Clean up", async {
+            ta_res!(());
+            for pair in publishers {
+                let pair = pair.join(format!("publish/admin/allowed_identities/{}", identity));
+                htreq::delete(
+                    log,
+                    &mut connect_publisher_node(log, resolvers, &pair).await?,
+                    &pair.url,
+                    &admin_headers()?,
+                    100,
+                ).await?;
+            }
+            return Ok(());
+        });
+    }
+
+    // Report, only failures if quiet
+    let mut failed = false;
+    for (name, res) in results {
+        match res {
+            StageResult::Pass => if !quiet {
+                println!("PASS {}", name);
+            },
+            StageResult::Fail(e) => {
+                failed = true;
+                println!("FAIL {}: {}", name, e);
+            },
+            StageResult::Skip(reason) => if !quiet {
+                println!("SKIP {}: {}", name, reason);
+            },
+        }
+    }
+    if failed {
+        return Err(loga::err_with("Self-test failed", ea!(identity = identity)));
+    }
+    return Ok(());
+}
+
+// Self-published certs are renewed a week before they expire
+const IDENTITY_STATUS_CERT_WARN_DAYS: i64 = 7;
+
+enum CheckResult {
+    Ok(String),
+    Warn(String),
+    Fail(String),
+    Skip(String),
+}
+
+fn identity_status_checks(status: &IdentityStatus) -> Vec<(&'static str, CheckResult)> {
+    let mut out = vec![];
+    let fmt_time = |t: &DateTime<Utc>| t.to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    // Announcement
+    let announcement = &status.announcement;
+    out.push(("Announcement", match announcement.announced {
+        None => CheckResult::Fail("No announcement found in the network".to_string()),
+        Some(announced) => {
+            let summary =
+                format!(
+                    "Announced {}, held by {} of {} nearest nodes, lists {} publisher address(es)",
+                    fmt_time(&announced),
+                    announcement.replication.holders,
+                    announcement.replication.nearest,
+                    announcement.publishers.len()
+                );
+            let local_announced = status.publisher.as_ref().and_then(|p| p.announced);
+            if local_announced.is_some_and(|l| l > announced) {
+                CheckResult::Warn(
+                    format!("{}; the announcement stored by this publisher is newer and hasn't spread", summary),
+                )
+            } else if announcement.replication.holders * 2 < announcement.replication.nearest {
+                CheckResult::Warn(format!("{}; held by fewer than half of the nearest nodes", summary))
+            } else {
+                CheckResult::Ok(summary)
+            }
+        },
+    }));
+
+    // Publisher
+    out.push(("Publisher", match &status.publisher {
+        None => CheckResult::Skip("The node doesn't run a publisher".to_string()),
+        Some(p) => match p.announced {
+            None => CheckResult::Skip("Not hosted by this node's publisher".to_string()),
+            Some(_) if p.dormant => CheckResult::Fail(
+                "Marked dormant by the retention policy, values aren't served".to_string(),
+            ),
+            Some(_) if !p.listed => CheckResult::Fail(
+                "The announcement stored here doesn't list this publisher".to_string(),
+            ),
+            Some(_) if p.maintenance.is_some() => CheckResult::Warn(
+                "The publisher is in maintenance mode, resolvers are using cached values".to_string(),
+            ),
+            Some(_) if !p.allowed => CheckResult::Warn(
+                "Serving, but not in the allowed identities so it can't publish changes".to_string(),
+            ),
+            Some(_) => CheckResult::Ok("Serving".to_string()),
+        },
+    }));
+
+    // Records
+    let resolve = status.resolve.as_ref();
+    out.push(("Records", match (&status.publisher, resolve) {
+        (None, None) => CheckResult::Skip("The node doesn't run a publisher or resolver".to_string()),
+        (_, Some(r)) if r.error.is_some() => CheckResult::Fail(
+            format!("Resolving failed: {}", r.error.as_ref().unwrap()),
+        ),
+        (p, r) => {
+            let mut parts = vec![];
+            if let Some(p) = p.as_ref().filter(|p| p.announced.is_some()) {
+                parts.push(format!("{} key(s) published here", p.keys.len()));
+            }
+            match r {
+                Some(r) if r.dns_keys.is_empty() => {
+                    parts.push("no DNS records at the root".to_string());
+                },
+                Some(r) => {
+                    parts.push(format!("root DNS records: {}", r.dns_keys.join(", ")));
+                },
+                None => { },
+            }
+            if parts.is_empty() {
+                CheckResult::Skip("Not hosted by this node's publisher and the node doesn't run a resolver".to_string())
+            } else {
+                let text = parts.join(", ");
+                if r.is_some_and(|r| r.dns_keys.is_empty()) {
+                    CheckResult::Warn(text)
+                } else {
+                    CheckResult::Ok(text)
+                }
+            }
+        },
+    }));
+
+    // Certs
+    out.push(("TLS certs", match resolve {
+        None => CheckResult::Skip("The node doesn't run a resolver".to_string()),
+        Some(r) if r.error.is_some() => CheckResult::Skip("Resolving failed".to_string()),
+        Some(r) if r.certs.is_empty() => CheckResult::Skip("No TLS certs published".to_string()),
+        Some(r) => {
+            let now = status.time;
+            let mut expires = r.certs.iter().filter_map(|c| c.expires).collect::<Vec<_>>();
+            expires.sort();
+            match expires.last() {
+                None => CheckResult::Fail("None of the published certs could be parsed".to_string()),
+                Some(latest) if *latest < now => CheckResult::Fail(
+                    format!("All published certs expired, the latest at {}", fmt_time(latest)),
+                ),
+                Some(latest) if *latest < now + chrono::Duration::try_days(IDENTITY_STATUS_CERT_WARN_DAYS).unwrap() => {
+                    CheckResult::Warn(format!("The latest published cert expires soon, at {}", fmt_time(latest)))
+                },
+                Some(latest) => CheckResult::Ok(
+                    format!("{} cert(s) published, the latest expires {}", r.certs.len(), fmt_time(latest)),
+                ),
+            }
+        },
+    }));
+    return out;
+}
+
+async fn identity_status(
+    log: &Log,
+    quiet: bool,
+    resolvers: &[UrlPair],
+    publishers: Vec<UrlPair>,
+    config: args::IdentityStatus,
+) -> Result<(), loga::Error> {
+    let identity = Identity::from_str(&config.identity)?;
+    let Some(pair) = publishers.into_iter().next() else {
+        return Err(loga::err("No node configured to check the identity with"));
+    };
+    let pair = pair.join(format!("admin/identity/{}", identity));
+    log.log_with(loga::DEBUG, "Sending identity status request (GET)", ea!(url = pair));
+    let status =
+        htreq::get_json::<IdentityStatus>(
+            log,
+            &mut connect_publisher_node(log, resolvers, &pair).await?,
+            &pair.url,
+            &admin_headers()?,
+            1024 * 1024,
+        ).await?;
+    if config.json.is_some() {
+        println!("{}", serde_json::to_string_pretty(&status).unwrap());
+        return Ok(());
+    }
+    let mut results = identity_status_checks(&status);
+
+    // DNS bridge
+    results.push(("DNS bridge", match dns_bridge_addr(resolvers, &config.dns)? {
+        None => CheckResult::Skip("No DNS bridge address, use `--dns`".to_string()),
+        Some(dns_addr) => match dns_bridge_resolver(dns_addr).lookup_ip(format!("{}.s.", identity)).await {
+            Ok(ips) => CheckResult::Ok(
+                format!(
+                    "Resolves to {}",
+                    ips.iter().map(|i| i.to_string()).collect::<Vec<_>>().join(", ")
+                ),
+            ),
+            Err(e) => match e.kind() {
+                hickory_resolver::error::ResolveErrorKind::NoRecordsFound { .. } => CheckResult::Warn(
+                    format!("Answered by {} but no addresses", dns_addr),
+                ),
+                _ => CheckResult::Fail(format!("Lookup via {} failed: {}", dns_addr, e)),
+            },
+        },
+    }));
+
+    // Report, only problems if quiet
+    let mut failed = false;
+    for (name, res) in results {
+        match res {
+            CheckResult::Ok(text) => if !quiet {
+                println!("OK   {}: {}", name, text);
+            },
+            CheckResult::Warn(text) => println!("WARN {}: {}", name, text),
+            CheckResult::Fail(text) => {
+                failed = true;
+                println!("FAIL {}: {}", name, text);
+            },
+            CheckResult::Skip(text) => if !quiet {
+                println!("SKIP {}: {}", name, text);
+            },
+        }
+    }
+    if failed {
+        return Err(loga::err_with("Identity has problems", ea!(identity = identity)));
+    }
+    return Ok(());
+}
+
+fn watch_count(prev: Option<u64>, cur: u64, secs: f64) -> String {
+    match prev {
+        Some(prev) if secs > 0. => {
+            return format!("{} ({:+.1}/s)", cur, cur.saturating_sub(prev) as f64 / secs);
+        },
+        _ => return cur.to_string(),
+    }
+}
+
+fn watch_hit_rate(hits: u64, misses: u64) -> String {
+    if hits + misses == 0 {
+        return "-".to_string();
+    }
+    return format!("{:.1}%", hits as f64 * 100. / (hits + misses) as f64);
+}
+
+fn render_watch(prev: Option<&WatchSnapshot>, cur: &WatchSnapshot) -> String {
+    // Rates are per second since the previous snapshot
+    let secs = prev.map(|p| (cur.time - p.time).num_milliseconds() as f64 / 1000.).unwrap_or(0.);
+    let mut out = vec![];
+    out.push(format!("spagh node at {} (ctrl-c to exit)", cur.time.to_rfc3339_opts(SecondsFormat::Secs, true)));
+    out.push("".to_string());
+    let node = &cur.node;
+    out.push(
+        format!(
+            "Neighbors    {} responsive, {} unresponsive, {} quarantined",
+            node.responsive_neighbors,
+            node.unresponsive_neighbors,
+            node.quarantined_nodes
+        ),
+    );
+    out.push(
+        format!(
+            "DHT          {} finds, {} pings active, {} announcements stored",
+            node.active_finds,
+            node.active_pings,
+            node.stored_announcements
+        ),
+    );
+    out.push(
+        format!(
+            "Rejected     {} challenges, {} stale responses, {} announcements",
+            node.quarantine_rejections,
+            node.stale_rejections,
+            node.announcement_rejections
+        ),
+    );
+    if let Some(resolver) = &cur.resolver {
+        let prev = prev.and_then(|p| p.resolver.as_ref());
+        out.push(
+            format!(
+                "Resolver     {} values cached ({} hits), {} announcements cached ({} hits)",
+                resolver.value_entries,
+                watch_hit_rate(resolver.value_hits, resolver.value_misses),
+                resolver.announcement_entries,
+                watch_hit_rate(resolver.announcement_hits, resolver.announcement_misses)
+            ),
+        );
+        out.push(
+            format!(
+                "             {} DHT lookups, {} active, {} waiting",
+                watch_count(prev.map(|p| p.dht_lookups), resolver.dht_lookups, secs),
+                resolver.dht_lookups_active,
+                resolver.dht_lookups_waiting
+            ),
+        );
+    }
+    if let Some(publisher) = &cur.publisher {
+        let prev = prev.and_then(|p| p.publisher.as_ref());
+        out.push(
+            format!(
+                "Publisher    {} publishes, {} announces, {} resolves served",
+                watch_count(prev.map(|p| p.publishes), publisher.publishes, secs),
+                watch_count(prev.map(|p| p.announces), publisher.announces, secs),
+                watch_count(prev.map(|p| p.resolves), publisher.resolves, secs)
+            ),
+        );
+    }
+    if let Some(content) = &cur.content {
+        let prev = prev.and_then(|p| p.content.as_ref());
+        out.push(
+            format!(
+                "Content      {} requests ({} 2xx, {} 3xx, {} 4xx, {} 5xx), {} bytes sent",
+                watch_count(prev.map(|p| p.requests), content.requests, secs),
+                content.responses_2xx,
+                content.responses_3xx,
+                content.responses_4xx,
+                content.responses_5xx,
+                content.bytes_sent
+            ),
+        );
+    }
+    if let Some(dns) = &cur.dns {
+        let prev = prev.and_then(|p| p.dns.as_ref());
+        out.push(
+            format!(
+                "DNS          {} UDP queries, {} rate limited ({} truncated)",
+                watch_count(prev.map(|p| p.udp_queries), dns.udp_queries, secs),
+                watch_count(
+                    prev.map(|p| p.rate_limit_dropped + p.rate_limit_slipped),
+                    dns.rate_limit_dropped + dns.rate_limit_slipped,
+                    secs
+                ),
+                dns.rate_limit_slipped
+            ),
+        );
+    }
+    for (name, cur_load, prev_load) in [
+        ("Resolve API", &cur.resolver_load, prev.and_then(|p| p.resolver_load.as_ref())),
+        ("Publish API", &cur.publisher_load, prev.and_then(|p| p.publisher_load.as_ref())),
+    ] {
+        let Some(load) = cur_load else {
+            continue;
+        };
+        out.push(
+            format!(
+                "{:<12} {}/{} requests in flight, {} shed{}",
+                name,
+                load.in_flight,
+                load.max_in_flight,
+                watch_count(prev_load.map(|p| p.shed), load.shed, secs),
+                if load.overloaded {
+                    " (overloaded)"
+                } else {
+                    ""
+                }
+            ),
+        );
+    }
+    return out.join("\n");
+}
+
+/// Redraws the terminal with each snapshot line as it's received from the watch
+/// endpoint.
+struct WatchView {
+    buf: Vec<u8>,
+    last: Option<WatchSnapshot>,
+}
+
+impl WatchView {
+    fn show(&mut self, line: &[u8]) -> Result<(), loga::Error> {
+        let snapshot = serde_json::from_slice::<WatchSnapshot>(line).context("Error parsing watch snapshot")?;
+        let mut stdout = std::io::stdout();
+        writeln!(
+            stdout,
+            "{}{}{}",
+            termion::clear::All,
+            termion::cursor::Goto(1, 1),
+            render_watch(self.last.as_ref(), &snapshot)
+        ).context("Error writing to stdout")?;
+        stdout.flush().context("Error writing to stdout")?;
+        self.last = Some(snapshot);
+        return Ok(());
+    }
+}
+
+impl AsyncWrite for WatchView {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, std::io::Error>> {
+        let self1 = self.get_mut();
+        self1.buf.extend_from_slice(buf);
+        while let Some(end) = self1.buf.iter().position(|b| *b == b'\n') {
+            let line = self1.buf.drain(.. end + 1).collect::<Vec<_>>();
+            if let Err(e) = self1.show(&line[.. end]) {
+                return Poll::Ready(Err(std::io::Error::other(e.to_string())));
+            }
+        }
+        return Poll::Ready(Ok(buf.len()));
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        return Poll::Ready(Ok(()));
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        return Poll::Ready(Ok(()));
+    }
+}
+
+fn cache_entries_path(config: &args::CacheIdentity) -> String {
+    match &config.identity {
+        Some(identity) => return format!("admin/resolver_cache/entries/{}", identity),
+        None => return "admin/resolver_cache/entries".to_string(),
+    }
+}
+
+pub async fn run(log: &Log, quiet: bool, config: args::Admin) -> Result<(), loga::Error> {
     let resolvers = default_resolver_url_pairs(log)?;
     let publishers = system_publisher_url_pairs(log)?;
     match config {
@@ -173,6 +1076,279 @@ pub async fn run(log: &Log, config: args::Admin) -> Result<(), loga::Error> {
                 ).await?;
             }
         },
+        args::Admin::Census(config) => {
+            for pair in publishers {
+                let mut path = "admin/census".to_string();
+                if let Some(samples) = config.samples {
+                    path = format!("{}?samples={}", path, samples);
+                }
+                let pair = pair.join(path);
+                log.log_with(loga::DEBUG, "Sending census request (GET)", ea!(url = pair));
+                println!(
+                    "{}",
+                    htreq::get_text(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &admin_headers()?,
+                        1024 * 1024,
+                    ).await?
+                );
+            }
+        },
+        args::Admin::Traffic(config) => {
+            for pair in publishers {
+                let mut path = "admin/traffic".to_string();
+                if let Some(hours) = config.hours {
+                    path = format!("{}?hours={}", path, hours);
+                }
+                let pair = pair.join(path);
+                log.log_with(loga::DEBUG, "Sending traffic request (GET)", ea!(url = pair));
+                println!(
+                    "{}",
+                    htreq::get_text(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &admin_headers()?,
+                        1024 * 1024,
+                    ).await?
+                );
+            }
+        },
+        args::Admin::Usage(config) => {
+            let mut query = vec![];
+            if let Some(since) = config.since {
+                query.push(("since", since));
+            }
+            if let Some(until) = config.until {
+                query.push(("until", until));
+            }
+            if config.csv.is_some() {
+                query.push(("format", "csv".to_string()));
+            }
+            for pair in publishers {
+                let pair =
+                    pair.join(format!("publish/admin/usage?{}", serde_urlencoded::to_string(&query).unwrap()));
+                log.log_with(loga::DEBUG, "Sending usage request (GET)", ea!(url = pair));
+                let text =
+                    htreq::get_text(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &admin_headers()?,
+                        16 * 1024 * 1024,
+                    ).await?;
+                if config.csv.is_some() {
+                    print!("{}", text);
+                } else {
+                    println!("{}", text);
+                }
+            }
+        },
+        args::Admin::AuditLog(config) => {
+            let mut query = vec![];
+            if let Some(since) = config.since {
+                query.push(("since", since));
+            }
+            if let Some(until) = config.until {
+                query.push(("until", until));
+            }
+            for pair in publishers {
+                let pair =
+                    pair.join(format!("publish/admin/audit?{}", serde_urlencoded::to_string(&query).unwrap()));
+                log.log_with(loga::DEBUG, "Sending audit log request (GET)", ea!(url = pair));
+                println!(
+                    "{}",
+                    htreq::get_text(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &admin_headers()?,
+                        16 * 1024 * 1024,
+                    ).await?
+                );
+            }
+        },
+        args::Admin::ReverseLookup(config) => {
+            let query = match config {
+                args::ReverseLookup::Ip(ip) => (
+                    "ip",
+                    IpAddr::from_str(&ip).context_with("Invalid IP address", ea!(ip = ip))?.to_string(),
+                ),
+                args::ReverseLookup::CertHash(hash) => ("cert_hash", hash),
+            };
+            let query = serde_urlencoded::to_string(&[query]).unwrap();
+            for pair in publishers {
+                let pair = pair.join(format!("publish/admin/reverse?{}", query));
+                log.log_with(loga::DEBUG, "Sending reverse lookup request (GET)", ea!(url = pair));
+                println!(
+                    "{}",
+                    htreq::get_text(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &admin_headers()?,
+                        16 * 1024 * 1024,
+                    ).await?
+                );
+            }
+        },
+        args::Admin::ContentStats => {
+            for pair in publishers {
+                let pair = pair.join("admin/content");
+                log.log_with(loga::DEBUG, "Sending content stats request (GET)", ea!(url = pair));
+                println!(
+                    "{}",
+                    htreq::get_text(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &admin_headers()?,
+                        10 * 1024,
+                    ).await?
+                );
+            }
+        },
+        args::Admin::DnsStats => {
+            for pair in publishers {
+                let pair = pair.join("admin/dns");
+                log.log_with(loga::DEBUG, "Sending DNS stats request (GET)", ea!(url = pair));
+                println!(
+                    "{}",
+                    htreq::get_text(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &admin_headers()?,
+                        10 * 1024,
+                    ).await?
+                );
+            }
+        },
+        args::Admin::Watch(config) => {
+            let Some(pair) = publishers.into_iter().next() else {
+                return Err(loga::err("No node configured to watch"));
+            };
+            let mut path = "admin/watch".to_string();
+            if let Some(interval) = config.interval {
+                path = format!("{}?interval_secs={}", path, interval);
+            }
+            let pair = pair.join(path);
+            log.log_with(loga::DEBUG, "Sending watch request (GET)", ea!(url = pair));
+            let mut req = Request::builder().uri(pair.url.clone());
+            for (k, v) in admin_headers()? {
+                req = req.header(k, v);
+            }
+            let mut conn = connect_publisher_node(log, &resolvers, &pair).await?;
+            let (status, _, continue_send) =
+                htreq::send(
+                    log,
+                    &mut conn,
+                    Duration::from_secs(10),
+                    req.body(Full::new(Bytes::new())).unwrap(),
+                ).await?;
+            if !status.is_success() {
+                return Err(loga::err_with("Received non-success status code", ea!(status = status)));
+            }
+            if config.json.is_some() {
+                htreq::receive_stream(continue_send, tokio::io::stdout()).await?;
+            } else {
+                htreq::receive_stream(continue_send, WatchView {
+                    buf: vec![],
+                    last: None,
+                }).await?;
+            }
+        },
+        args::Admin::Tasks => {
+            for pair in publishers {
+                let pair = pair.join("admin/tasks");
+                log.log_with(loga::DEBUG, "Sending tasks request (GET)", ea!(url = pair));
+                println!(
+                    "{}",
+                    htreq::get_text(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &admin_headers()?,
+                        1024 * 1024,
+                    ).await?
+                );
+            }
+        },
+        args::Admin::DiskUsage => {
+            for pair in publishers {
+                let pair = pair.join("admin/disk_usage");
+                log.log_with(loga::DEBUG, "Sending disk usage request (GET)", ea!(url = pair));
+                println!(
+                    "{}",
+                    htreq::get_text(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &admin_headers()?,
+                        10 * 1024,
+                    ).await?
+                );
+            }
+        },
+        args::Admin::Backup => {
+            for pair in publishers {
+                let pair = pair.join("admin/backup");
+                log.log_with(loga::DEBUG, "Sending backup status request (GET)", ea!(url = pair));
+                println!(
+                    "{}",
+                    htreq::get_text(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &admin_headers()?,
+                        100 * 1024,
+                    ).await?
+                );
+            }
+        },
+        args::Admin::Certs => {
+            for pair in publishers {
+                let pair = pair.join("admin/certs");
+                log.log_with(loga::DEBUG, "Sending cert status request (GET)", ea!(url = pair));
+                println!(
+                    "{}",
+                    htreq::get_text(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &admin_headers()?,
+                        100 * 1024,
+                    ).await?
+                );
+            }
+        },
+        args::Admin::Cache(config) => {
+            for pair in publishers {
+                let mut conn = connect_publisher_node(log, &resolvers, &pair).await?;
+                match &config {
+                    args::Cache::Stats => {
+                        let pair = pair.join("admin/resolver_cache");
+                        log.log_with(loga::DEBUG, "Sending resolver cache stats request (GET)", ea!(url = pair));
+                        println!("{}", htreq::get_text(log, &mut conn, &pair.url, &admin_headers()?, 10 * 1024).await?);
+                    },
+                    args::Cache::List(config) => {
+                        let pair = pair.join(cache_entries_path(config));
+                        log.log_with(loga::DEBUG, "Sending resolver cache list request (GET)", ea!(url = pair));
+                        println!(
+                            "{}",
+                            htreq::get_text(log, &mut conn, &pair.url, &admin_headers()?, 64 * 1024 * 1024).await?
+                        );
+                    },
+                    args::Cache::Purge(config) => {
+                        let pair = pair.join(cache_entries_path(config));
+                        log.log_with(loga::DEBUG, "Sending resolver cache purge request (DELETE)", ea!(url = pair));
+                        htreq::delete(log, &mut conn, &pair.url, &admin_headers()?, 100).await?;
+                    },
+                }
+            }
+        },
         args::Admin::AllowIdentity(config) => {
             for pair in publishers {
                 let pair = pair.join(format!("publish/admin/allowed_identities/{}", config.identity_id));
@@ -187,6 +1363,80 @@ pub async fn run(log: &Log, config: args::Admin) -> Result<(), loga::Error> {
                 ).await?;
             }
         },
+        args::Admin::SelfTest(config) => {
+            self_test(log, quiet, &resolvers, &publishers, config).await?;
+        },
+        args::Admin::IdentityStatus(config) => {
+            identity_status(log, quiet, &resolvers, publishers, config).await?;
+        },
+        args::Admin::Maintenance(config) => {
+            for pair in publishers {
+                let pair = pair.join("publish/admin/maintenance");
+                let mut conn = connect_publisher_node(log, &resolvers, &pair).await?;
+                match &config {
+                    args::Maintenance::Status => {
+                        log.log_with(loga::DEBUG, "Sending maintenance status request (GET)", ea!(url = pair));
+                        println!("{}", htreq::get_text(log, &mut conn, &pair.url, &admin_headers()?, 1024).await?);
+                    },
+                    args::Maintenance::Start(config) => {
+                        log.log_with(loga::DEBUG, "Sending maintenance start request (POST)", ea!(url = pair));
+                        htreq::post_json::<()>(
+                            log,
+                            &mut conn,
+                            &pair.url,
+                            &admin_headers()?,
+                            AdminMaintenance {
+                                retry_after: config.retry_after.unwrap_or(DEFAULT_RETRY_AFTER_SECS),
+                            },
+                            100,
+                        ).await?;
+                    },
+                    args::Maintenance::Stop => {
+                        log.log_with(loga::DEBUG, "Sending maintenance stop request (DELETE)", ea!(url = pair));
+                        htreq::delete(log, &mut conn, &pair.url, &admin_headers()?, 100).await?;
+                    },
+                }
+            }
+        },
+        args::Admin::Reannounce(config) => {
+            for pair in publishers {
+                let pair = pair.join(format!("publish/admin/reannounce/{}", config.identity));
+                log.log_with(loga::DEBUG, "Sending reannounce request (POST)", ea!(url = pair));
+                let resp =
+                    htreq::post(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &admin_headers()?,
+                        vec![],
+                        1024,
+                    ).await?;
+                let resp =
+                    serde_json::from_slice::<wire::api::publish::latest::AnnounceResponse>(
+                        &resp,
+                    ).context("Error parsing reannounce response from publisher")?;
+                println!("{}", serde_json::to_string_pretty(&resp).unwrap());
+            }
+        },
+        args::Admin::Watchdog(config) => {
+            for pair in publishers {
+                let pair = pair.join("publish/admin/watchdog");
+                let mut conn = connect_publisher_node(log, &resolvers, &pair).await?;
+                match &config {
+                    args::Watchdog::Status => {
+                        log.log_with(loga::DEBUG, "Sending watchdog status request (GET)", ea!(url = pair));
+                        println!(
+                            "{}",
+                            htreq::get_text(log, &mut conn, &pair.url, &admin_headers()?, 1024 * 1024).await?
+                        );
+                    },
+                    args::Watchdog::Clear => {
+                        log.log_with(loga::DEBUG, "Sending watchdog clear request (DELETE)", ea!(url = pair));
+                        htreq::delete(log, &mut conn, &pair.url, &admin_headers()?, 100).await?;
+                    },
+                }
+            }
+        },
         args::Admin::DisallowIdentity(config) => {
             for pair in publishers {
                 let pair = pair.join(format!("publish/admin/allowed_identities/{}", config.identity_id));
@@ -200,7 +1450,11 @@ pub async fn run(log: &Log, config: args::Admin) -> Result<(), loga::Error> {
                 ).await?;
             }
         },
-        args::Admin::ListAllowedIdentities => {
+        args::Admin::ListAllowedIdentities(config) => {
+            let mut filter = vec![];
+            if let Some(prefix) = config.prefix {
+                filter.push(("prefix", prefix));
+            }
             let mut errs = vec![];
             for pair in publishers {
                 match async {
@@ -213,6 +1467,7 @@ pub async fn run(log: &Log, config: args::Admin) -> Result<(), loga::Error> {
                                 .context("Error connecting to server")?,
                             &pair,
                             "publish/admin/allowed_identities",
+                            &filter,
                             |v| v.identity.to_string(),
                         )
                             .await
@@ -230,7 +1485,17 @@ pub async fn run(log: &Log, config: args::Admin) -> Result<(), loga::Error> {
             }
             return Err(loga::agg_err("Error making request", errs));
         },
-        args::Admin::ListAnnouncements => {
+        args::Admin::ListAnnouncements(config) => {
+            let mut filter = vec![];
+            if let Some(prefix) = config.prefix {
+                filter.push(("prefix", prefix));
+            }
+            if let Some(since) = config.since {
+                filter.push(("since", since));
+            }
+            if let Some(until) = config.until {
+                filter.push(("until", until));
+            }
             let mut errs = vec![];
             for pair in publishers {
                 match async {
@@ -243,6 +1508,7 @@ pub async fn run(log: &Log, config: args::Admin) -> Result<(), loga::Error> {
                                 .context("Error connecting to server")?,
                             &pair,
                             "publish/admin/announcements",
+                            &filter,
                             |v| v.to_string(),
                         )
                             .await
@@ -261,21 +1527,28 @@ pub async fn run(log: &Log, config: args::Admin) -> Result<(), loga::Error> {
             return Err(loga::agg_err("Error making request", errs));
         },
         args::Admin::ListKeys(config) => {
+            let mut filter = vec![];
+            if let Some(prefix) = config.prefix {
+                filter.push(("prefix", prefix));
+            }
             let mut errs = vec![];
             for pair in publishers {
-                let pair = pair.join(format!("publish/admin/keys/{}", config.identity));
                 match async {
                     ta_res!(());
-                    println!(
-                        "{}",
-                        htreq::get_text(
+                    let out =
+                        api_list::<String>(
                             log,
-                            &mut connect_publisher_node(log, &resolvers, &pair).await?,
-                            &pair.url,
-                            &admin_headers()?,
-                            1024 * 1024,
-                        ).await?
-                    );
+                            &mut connect_publisher_node(log, &resolvers, &pair)
+                                .await
+                                .context("Error connecting to server")?,
+                            &pair,
+                            &format!("publish/admin/keys/{}", config.identity),
+                            &filter,
+                            |v| v.clone(),
+                        )
+                            .await
+                            .stack_context(log, "Error listing published keys")?;
+                    println!("{}", serde_json::to_string_pretty(&out).unwrap());
                     return Ok(());
                 }.await {
                     Ok(_) => {
@@ -298,6 +1571,7 @@ pub async fn run(log: &Log, config: args::Admin) -> Result<(), loga::Error> {
                         &mut conn,
                         &pair,
                         "publish/admin/allowed_identities",
+                        &[],
                         |v| v.identity.to_string(),
                     )
                         .await
@@ -0,0 +1,50 @@
+use {
+    loga::{
+        Log,
+        ResultContext,
+    },
+    spaghettinuum::{
+        interface::wire::api::admin::latest::DaemonStatus,
+        utils::{
+            fs_util,
+            unix_http::unix_get,
+        },
+    },
+};
+
+pub mod args {
+    use {
+        aargvark::Aargvark,
+        std::path::PathBuf,
+    };
+
+    #[derive(Aargvark)]
+    pub struct Status {
+        /// Path to the node's control socket. Defaults to the `SPAGH_CONTROL_SOCKET`
+        /// environment variable, or `control.sock` in the runtime directory.
+        pub socket: Option<PathBuf>,
+    }
+
+    #[derive(Aargvark)]
+    #[vark(break_help)]
+    pub enum Daemon {
+        /// Show the status of the local node via its control socket. This doesn't
+        /// require an admin token, just permission to access the socket.
+        Status(Status),
+    }
+}
+
+pub async fn run(_log: &Log, config: args::Daemon) -> Result<(), loga::Error> {
+    match config {
+        args::Daemon::Status(config) => {
+            let socket = config.socket.unwrap_or_else(fs_util::control_socket_path);
+            let body = unix_get(&socket, "/status").await?;
+            let status =
+                serde_json::from_slice::<DaemonStatus>(
+                    &body,
+                ).context("Error parsing status response from daemon")?;
+            println!("{}", serde_json::to_string_pretty(&status).unwrap());
+        },
+    }
+    return Ok(());
+}
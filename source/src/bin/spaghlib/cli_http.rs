@@ -1,4 +1,5 @@
 use {
+    super::CliResolveVia,
     http::Method,
     http_body_util::Full,
     htwrap::htreq,
@@ -11,14 +12,20 @@ use {
     },
     serde_json::json,
     spaghettinuum::{
+        interface::{
+            stored::record,
+            wire::api::resolve::v1::SuspectReason,
+        },
         resolving::{
-            resolve_for_tls,
-            default_resolver_url_pairs,
+            connect_any_ip,
+            report_suspect,
+            resolve_for_tls_via,
             ResolveTlsRes,
         },
         utils::tls_util::{
             cert_pem_hash,
             SpaghTlsClientVerifier,
+            UnverifyingVerifier,
         },
     },
     std::{
@@ -26,6 +33,7 @@ use {
             HashMap,
             HashSet,
         },
+        sync::Arc,
         time::Duration,
     },
     tokio::{
@@ -72,6 +80,24 @@ pub mod args {
         /// Write output metadata as json. If output is not a file, output will be a field
         /// in the JSON.
         pub json: Option<()>,
+        /// Don't use or update the on-disk cache of resolution results.
+        pub no_cache: Option<()>,
+        /// Print the names followed to resolve the host and how the server's TLS
+        /// certificate was verified.
+        pub verbose: Option<()>,
+        /// Don't verify the server's TLS certificate. Anyone able to intercept the
+        /// connection can read and modify it, so only use this for debugging.
+        pub insecure: Option<()>,
+        /// Resolve with this resolver instead of the system resolvers, as `IP=URL` or a
+        /// URL with an IP address host.
+        pub resolver: Option<String>,
+        /// Skip resolvers and the DHT and get the host's records directly from the
+        /// publisher with this API URL. The identity's announcement must list the
+        /// publisher.
+        pub publisher: Option<Uri>,
+        /// The identity whose records to get from `--publisher`, if the host isn't a `.s`
+        /// name.
+        pub identity: Option<String>,
     }
 }
 
@@ -127,7 +153,9 @@ pub async fn run(log: &Log, config: args::Http) -> Result<(), loga::Error> {
     }
 
     // Resolve destination
-    let ResolveTlsRes { ips, certs: certs0 } = resolve_for_tls(log, &default_resolver_url_pairs(log)?, &host).await?;
+    let via = CliResolveVia::new(log, &config.resolver, &config.publisher, &config.identity, &host.to_string()).await?;
+    let ResolveTlsRes { ips, prefer, certs: certs0, chain } =
+        resolve_for_tls_via(log, via.via(), via.cache(&config.no_cache).as_ref(), &host).await?;
     let mut certs = HashSet::new();
     for c in certs0 {
         match cert_pem_hash(&c) {
@@ -143,18 +171,60 @@ pub async fn run(log: &Log, config: args::Http) -> Result<(), loga::Error> {
         }
     }
 
-    // Now make the actual request
+    if config.verbose.is_some() {
+        if let CliResolveVia::Publisher(p) = &via {
+            eprintln!("Records from publisher: {}", p.publisher.addr);
+        }
+        if chain.len() > 1 {
+            eprintln!("Resolved via: {}", chain.join(" -> "));
+        }
+        eprintln!("Published TLS certs: {}", certs.len());
+    }
+
+    // Now make the actual request, trying each address in turn
+    let verifier;
+    let tls_config = if config.insecure.is_some() {
+        log.log(
+            loga::WARN,
+            "--insecure: NOT VERIFYING THE SERVER'S TLS CERTIFICATE, the connection may be intercepted or modified",
+        );
+        verifier = None;
+        rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(UnverifyingVerifier))
+            .with_no_client_auth()
+    } else {
+        let v = SpaghTlsClientVerifier::with_root_cas(certs)?;
+        verifier = Some(v.clone());
+        rustls::ClientConfig::builder().dangerous().with_custom_certificate_verifier(v).with_no_client_auth()
+    };
     let mut conn =
-        htreq::connect_ips(
-            ips,
-            rustls::ClientConfig::builder()
-                .dangerous()
-                .with_custom_certificate_verifier(SpaghTlsClientVerifier::with_root_cas(certs)?)
-                .with_no_client_auth(),
-            scheme,
-            host,
-            port,
-        ).await?;
+        match connect_any_ip(
+            log,
+            &ips,
+            prefer,
+            |ip| htreq::connect_ips(htreq::Ips::from(ip), tls_config.clone(), scheme.clone(), host.clone(), port),
+        ).await {
+            Ok(c) => c,
+            Err(e) => {
+                // The addresses or certs may be stale, have resolvers look them up again
+                report_suspect(
+                    log,
+                    via.via(),
+                    &chain,
+                    &[vec![record::tls_record::KEY_SUFFIX_TLS.to_string()]],
+                    SuspectReason::ConnectFailed,
+                ).await;
+                return Err(e);
+            },
+        };
+    if config.verbose.is_some() {
+        match verifier.as_ref().map(|v| v.verified_by.lock().unwrap().clone()) {
+            Some(Some(path)) => eprintln!("TLS verified via: {}", path),
+            Some(None) => eprintln!("TLS not used"),
+            None => eprintln!("TLS not verified (--insecure)"),
+        }
+    }
     let (status, headers, continue_send) = htreq::send(log, &mut conn, Duration::MAX, final_req).await?;
     log.log_with(loga::DEBUG, "Received header", ea!(status = status, headers = headers.dbg_str()));
     match config.output {
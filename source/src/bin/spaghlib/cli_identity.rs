@@ -1,17 +1,86 @@
 use {
+    htwrap::htreq,
     loga::{
+        ea,
         Log,
         ResultContext,
     },
     serde_json::json,
     spaghettinuum::{
-        interface::config::identity::LocalIdentitySecret,
-        utils::local_identity::write_identity_secret,
+        interface::{
+            config::identity::{
+                LocalIdentityFile,
+                LocalIdentitySecret,
+            },
+            stored::{
+                identity::Identity,
+                record::{
+                    record_utils::join_record_key,
+                    service_record::KEY_SUFFIX_SERVICES,
+                },
+            },
+            wire,
+        },
+        resolving::{
+            connect_resolver_node,
+            default_resolver_url_pairs,
+        },
+        service::resolver::{
+            API_ROUTE_RESOLVE,
+            HEADER_PROVENANCE,
+        },
+        ta_res,
+        utils::{
+            armor::is_armored,
+            fs_util::{
+                read,
+                write_private_atomic,
+            },
+            identity_secret::{
+                get_identity_passphrase,
+                get_new_identity_passphrase,
+            },
+            local_identity::{
+                user_identity_dir,
+                write_encrypted_identity_secret,
+                write_identity_secret,
+            },
+            vanity_identity::{
+                search_vanity,
+                validate_vanity_prefix,
+                vanity_expected_attempts,
+            },
+        },
+    },
+    std::{
+        collections::HashMap,
+        net::SocketAddr,
+        path::{
+            Path,
+            PathBuf,
+        },
+        str::FromStr,
+        sync::{
+            atomic::{
+                AtomicU64,
+                Ordering,
+            },
+            Arc,
+        },
+        time::{
+            Duration,
+            Instant,
+        },
+    },
+    tokio::{
+        fs::read_dir,
+        select,
+        task::spawn_blocking,
+        time::sleep,
     },
 };
 #[cfg(feature = "card")]
 use {
-    loga::ea,
     spaghettinuum::utils::pgp::{
         self,
     },
@@ -24,11 +93,7 @@ use {
 
 pub mod args {
     use {
-        aargvark::{
-            traits_impls::AargvarkJson,
-            Aargvark,
-        },
-        spaghettinuum::interface::config::identity::LocalIdentitySecret,
+        aargvark::Aargvark,
         std::path::PathBuf,
     };
 
@@ -36,60 +101,438 @@ pub mod args {
     pub struct NewLocalIdentity {
         /// Store the new id and secret in a file at this path
         pub path: PathBuf,
+        /// Encrypt the secret with a passphrase. The passphrase is taken from
+        /// `SPAGH_IDENTITY_PASSPHRASE` or prompted for.
+        pub encrypt: Option<()>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct NewVanityIdentity {
+        /// Store the new id and secret in a file at this path
+        pub path: PathBuf,
+        /// Characters the id should start with (after the leading characters all ids
+        /// share). Each character makes the search about 32 times longer.
+        #[vark(flag = "--prefix")]
+        pub prefix: String,
+        /// Give up after generating this many identities. Defaults to 2^32.
+        pub max_attempts: Option<u64>,
+        /// Encrypt the secret with a passphrase. The passphrase is taken from
+        /// `SPAGH_IDENTITY_PASSPHRASE` or prompted for.
+        pub encrypt: Option<()>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct EncryptLocalIdentity {
+        /// Plaintext identity file to encrypt, replaced in place
+        pub path: PathBuf,
+    }
+
+    #[derive(Aargvark)]
+    pub struct ArmorLocalIdentity {
+        /// Identity file in the older json format, replaced in place
+        pub path: PathBuf,
+    }
+
+    #[derive(Aargvark)]
+    pub struct ListIdentities {
+        /// Directory of local identity files. Defaults to `spaghettinuum/identities` in
+        /// `$XDG_CONFIG_HOME` (or `~/.config`).
+        pub dir: Option<PathBuf>,
+        /// Don't ask the resolver which publishers announce each identity
+        pub no_resolve: Option<()>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct ShowIdentity {
+        /// Identity, or the fingerprint of an identity in the standard locations
+        pub identity: String,
+        /// Directory of local identity files. Defaults to `spaghettinuum/identities` in
+        /// `$XDG_CONFIG_HOME` (or `~/.config`).
+        pub dir: Option<PathBuf>,
+        /// Don't ask the resolver which publishers announce the identity
+        pub no_resolve: Option<()>,
     }
 
     #[derive(Aargvark)]
     #[vark(break_help)]
     pub enum Identity {
+        /// List identities with secrets in the standard locations (local identity files in
+        /// the identity directory, and cards), with how to refer to them in commands
+        List(ListIdentities),
+        /// Show an identity, where its secrets are, and which publishers announce it
+        Show(ShowIdentity),
         /// Create a new local (file) identity
         NewLocal(NewLocalIdentity),
+        /// Create a new local (file) identity whose id starts with chosen characters,
+        /// searching on all cores
+        NewVanity(NewVanityIdentity),
         /// Show the id for a local identity
-        ShowLocal(AargvarkJson<LocalIdentitySecret>),
+        ShowLocal(PathBuf),
+        /// Encrypt an existing plaintext local identity file with a passphrase
+        EncryptLocal(EncryptLocalIdentity),
+        /// Rewrite a local identity file from the older json format in the armored
+        /// text format
+        ArmorLocal(ArmorLocalIdentity),
         /// List ids for usable pcsc cards (configured with curve25519/ed25519 signing keys)
         #[cfg(feature = "card")]
         ListCards,
     }
 }
 
+/// An identity secret found in a standard location.
+struct InventoryEntry {
+    /// Unknown for encrypted files in the older json format
+    identity: Option<Identity>,
+    storage: &'static str,
+    location: serde_json::Value,
+    /// How to refer to the secret in command line arguments
+    usage: String,
+}
+
+impl InventoryEntry {
+    fn to_json(&self) -> serde_json::Value {
+        return json!({
+            "storage": self.storage,
+            "location": self.location,
+            "usage": self.usage,
+        });
+    }
+}
+
+async fn local_inventory(log: &Log, dir: &Path) -> Result<Vec<InventoryEntry>, loga::Error> {
+    let mut out = vec![];
+    if !dir.exists() {
+        return Ok(out);
+    }
+    let log = log.fork(ea!(dir = dir.to_string_lossy()));
+    let mut paths = vec![];
+    let mut entries = read_dir(dir).await.stack_context(&log, "Error listing identity directory")?;
+    while let Some(entry) = entries.next_entry().await.stack_context(&log, "Error listing identity directory")? {
+        let path = entry.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    for path in paths {
+        let log = log.fork(ea!(path = path.to_string_lossy()));
+        let data = match read(&path).await {
+            Ok(d) => d,
+            Err(e) => {
+                log.log_err(loga::WARN, e.context("Error reading file, skipping"));
+                continue;
+            },
+        };
+        let file = match LocalIdentityFile::from_bytes(&data) {
+            Ok(f) => f,
+            Err(e) => {
+                log.log_err(loga::DEBUG, e.context("File isn't an identity file, skipping"));
+                continue;
+            },
+        };
+        let identity = match LocalIdentityFile::peek_identity(&data) {
+            Ok(i) => i,
+            Err(e) => {
+                log.log_err(loga::WARN, e.context("Error reading identity from file, skipping"));
+                continue;
+            },
+        };
+        out.push(InventoryEntry {
+            identity: identity,
+            storage: match file {
+                LocalIdentityFile::Plain(_) => "local",
+                LocalIdentityFile::Encrypted(_) => "local_encrypted",
+            },
+            location: json!(path.to_string_lossy()),
+            usage: format!("local {}", path.to_string_lossy()),
+        });
+    }
+    return Ok(out);
+}
+
+#[cfg(feature = "card")]
+fn card_identities(log: &Log) -> Result<Vec<(String, Identity)>, loga::Error> {
+    let mut out = vec![];
+    for card in PcscBackend::cards(None).stack_context(log, "Failed to list smart cards")? {
+        let mut card: Card<Open> = card.into();
+        let mut transaction = card.transaction().stack_context(log, "Error starting transaction with card")?;
+        let card_id = transaction.application_identifier().stack_context(log, "Error getting gpg id of card")?.ident();
+        let identity = match pgp::card_to_ident(&mut transaction) {
+            Ok(i) => match i {
+                Some(i) => i,
+                None => {
+                    continue;
+                },
+            },
+            Err(e) => {
+                log.log_err(loga::WARN, e.context_with("Error getting identity of card", ea!(card = card_id)));
+                continue;
+            },
+        };
+        out.push((card_id, identity));
+    }
+    return Ok(out);
+}
+
+/// Identity secrets in the identity directory and on cards.
+async fn inventory(log: &Log, dir: Option<PathBuf>) -> Result<Vec<InventoryEntry>, loga::Error> {
+    let mut out = vec![];
+    match dir.or_else(user_identity_dir) {
+        Some(dir) => {
+            out.extend(local_inventory(log, &dir).await?);
+        },
+        None => {
+            log.log(loga::WARN, "Couldn't determine the identity directory, skipping local identities");
+        },
+    }
+    #[cfg(feature = "card")]
+    {
+        match card_identities(log) {
+            Ok(cards) => {
+                for (card_id, identity) in cards {
+                    out.push(InventoryEntry {
+                        identity: Some(identity),
+                        storage: "card",
+                        location: json!(card_id),
+                        usage: format!("card {} -", card_id),
+                    });
+                }
+            },
+            Err(e) => {
+                log.log_err(loga::WARN, e.context("Error listing cards, skipping card identities"));
+            },
+        }
+    }
+    return Ok(out);
+}
+
+/// The publishers currently announcing an identity, per the resolver.
+async fn announcing_publishers(log: &Log, identity: &Identity) -> Result<Vec<SocketAddr>, loga::Error> {
+    // Any key works - the publisher is recorded even if there's no value for it
+    let key = join_record_key(&vec![KEY_SUFFIX_SERVICES.to_string()]);
+    let mut headers = HashMap::new();
+    headers.insert(HEADER_PROVENANCE.to_string(), "1".to_string());
+    let mut errs = vec![];
+    for pair in default_resolver_url_pairs(log)? {
+        match async {
+            ta_res!(wire::api::resolve::v1::ResolveResp);
+            let pair =
+                pair.join(format!("{}/v1/{}?{}", API_ROUTE_RESOLVE, identity, urlencoding::encode(&key)));
+            log.log_with(loga::DEBUG, "Sending query request", ea!(url = pair));
+            let body =
+                htreq::get(log, &mut connect_resolver_node(&pair).await?, &pair.url, &headers, 1024 * 1024).await?;
+            return serde_json::from_slice(&body).context("Response could not be parsed as JSON");
+        }.await {
+            Ok(resp) => {
+                let mut publishers = vec![];
+                for (_, value) in resp {
+                    let Some(publisher) = value.provenance.and_then(|p| p.publisher) else {
+                        continue;
+                    };
+                    if !publishers.contains(&publisher) {
+                        publishers.push(publisher);
+                    }
+                }
+                return Ok(publishers);
+            },
+            Err(e) => {
+                errs.push(e.context_with("Error reaching resolver", ea!(resolver = pair)));
+            },
+        }
+    }
+    return Err(loga::agg_err("Error making requests to any resolver", errs));
+}
+
+/// The publishers announcing an identity as json, or null if they couldn't be
+/// determined.
+async fn publishers_json(log: &Log, identity: &Identity, no_resolve: &Option<()>) -> serde_json::Value {
+    if no_resolve.is_some() {
+        return serde_json::Value::Null;
+    }
+    match announcing_publishers(log, identity).await {
+        Ok(p) => return json!(p),
+        Err(e) => {
+            log.log_err(
+                loga::WARN,
+                e.context_with("Error looking up publishers for identity", ea!(identity = identity)),
+            );
+            return serde_json::Value::Null;
+        },
+    }
+}
+
 pub async fn run(log: &Log, config: args::Identity) -> Result<(), loga::Error> {
     match config {
+        args::Identity::List(args) => {
+            let mut out = vec![];
+            for entry in inventory(log, args.dir).await? {
+                let mut e = entry.to_json();
+                match &entry.identity {
+                    Some(identity) => {
+                        e["id"] = json!(identity.to_string());
+                        e["fingerprint"] = json!(identity.fingerprint());
+                        e["publishers"] = publishers_json(log, identity, &args.no_resolve).await;
+                    },
+                    None => {
+                        e["id"] = serde_json::Value::Null;
+                    },
+                }
+                out.push(e);
+            }
+            println!("{}", serde_json::to_string_pretty(&out).unwrap());
+        },
+        args::Identity::Show(args) => {
+            let entries = inventory(log, args.dir).await?;
+            let identity = match Identity::from_str(&args.identity) {
+                Ok(i) => i,
+                Err(_) => entries
+                    .iter()
+                    .filter_map(|e| e.identity)
+                    .find(|i| i.fingerprint() == args.identity)
+                    .context_with("No identity with this fingerprint found", ea!(fingerprint = args.identity))?,
+            };
+            println!("{}", serde_json::to_string_pretty(&json!({
+                "id": identity.to_string(),
+                "fingerprint": identity.fingerprint(),
+                "secrets": entries
+                    .iter()
+                    .filter(|e| e.identity == Some(identity))
+                    .map(|e| e.to_json())
+                    .collect::<Vec<_>>(),
+                "publishers": publishers_json(log, &identity, &args.no_resolve).await,
+            })).unwrap());
+        },
         args::Identity::NewLocal(args) => {
             let (ident, secret) = LocalIdentitySecret::new();
-            write_identity_secret(&args.path, &secret).await.stack_context(&log, "Error creating local identity")?;
+            if args.encrypt.is_some() {
+                let passphrase = get_new_identity_passphrase().stack_context(log, "Error getting passphrase")?;
+                write_encrypted_identity_secret(&args.path, &secret, &passphrase)
+                    .await
+                    .stack_context(log, "Error creating local identity")?;
+            } else {
+                write_identity_secret(&args.path, &secret).await.stack_context(log, "Error creating local identity")?;
+            }
             println!("{}", serde_json::to_string_pretty(&json!({
                 "id": ident.to_string()
             })).unwrap());
         },
-        args::Identity::ShowLocal(p) => {
-            let secret = p.value;
+        args::Identity::NewVanity(args) => {
+            let prefix = validate_vanity_prefix(&args.prefix).stack_context(log, "Invalid prefix")?;
+            let max_attempts = args.max_attempts.unwrap_or(1 << 32);
+            let expected = vanity_expected_attempts(prefix.chars().count());
+            if expected > max_attempts as f64 {
+                log.log_with(
+                    loga::WARN,
+                    "Prefix is unlikely to be found within the attempt limit",
+                    ea!(expected = expected, max_attempts = max_attempts),
+                );
+            }
+            let passphrase = match args.encrypt {
+                Some(_) => Some(get_new_identity_passphrase().stack_context(log, "Error getting passphrase")?),
+                None => None,
+            };
+            let workers = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+            let attempts = Arc::new(AtomicU64::new(0));
+            let start = Instant::now();
+            let mut search = spawn_blocking({
+                let attempts = attempts.clone();
+                let prefix = prefix.clone();
+                move || search_vanity(&prefix, workers, max_attempts, &attempts)
+            });
+            let found = loop {
+                select!{
+                    found = &mut search => break found.context("Search thread failed")?,
+                    _ = sleep(Duration::from_secs(5)) => {
+                        let done = attempts.load(Ordering::Relaxed).min(max_attempts);
+                        eprintln!(
+                            "Tried {} ids ({:.0}/s, about {:.0} expected)",
+                            done,
+                            done as f64 / start.elapsed().as_secs_f64(),
+                            expected
+                        );
+                    },
+                }
+            };
+            let Some((ident, secret)) = found else {
+                return Err(
+                    log.err_with(
+                        "No matching identity found within the attempt limit",
+                        ea!(max_attempts = max_attempts),
+                    ),
+                );
+            };
+            match passphrase {
+                Some(passphrase) => {
+                    write_encrypted_identity_secret(&args.path, &secret, &passphrase)
+                        .await
+                        .stack_context(log, "Error creating local identity")?;
+                },
+                None => {
+                    write_identity_secret(&args.path, &secret)
+                        .await
+                        .stack_context(log, "Error creating local identity")?;
+                },
+            }
+            println!("{}", serde_json::to_string_pretty(&json!({
+                "id": ident.to_string(),
+                "attempts": attempts.load(Ordering::Relaxed).min(max_attempts),
+            })).unwrap());
+        },
+        args::Identity::ShowLocal(path) => {
+            let log = log.fork(ea!(path = path.to_string_lossy()));
+            let secret =
+                match LocalIdentityFile::from_bytes(
+                    &read(&path).await.stack_context(&log, "Error reading identity file")?,
+                ).stack_context(&log, "Error parsing identity file")? {
+                    LocalIdentityFile::Plain(s) => s,
+                    LocalIdentityFile::Encrypted(s) => {
+                        let passphrase = get_identity_passphrase().stack_context(&log, "Error getting passphrase")?;
+                        s.decrypt(&passphrase).stack_context(&log, "Error decrypting identity file")?
+                    },
+                };
             let identity = secret.identity();
             println!("{}", serde_json::to_string_pretty(&json!({
                 "id": identity.to_string()
             })).unwrap());
         },
+        args::Identity::EncryptLocal(args) => {
+            let log = log.fork(ea!(path = args.path.to_string_lossy()));
+            let secret =
+                match LocalIdentityFile::from_bytes(
+                    &read(&args.path).await.stack_context(&log, "Error reading identity file")?,
+                ).stack_context(&log, "Error parsing identity file")? {
+                    LocalIdentityFile::Plain(s) => s,
+                    LocalIdentityFile::Encrypted(_) => {
+                        return Err(log.err("Identity file is already encrypted"));
+                    },
+                };
+            let passphrase = get_new_identity_passphrase().stack_context(&log, "Error getting passphrase")?;
+            write_encrypted_identity_secret(&args.path, &secret, &passphrase)
+                .await
+                .stack_context(&log, "Error writing encrypted identity")?;
+        },
+        args::Identity::ArmorLocal(args) => {
+            let log = log.fork(ea!(path = args.path.to_string_lossy()));
+            let data = read(&args.path).await.stack_context(&log, "Error reading identity file")?;
+            if is_armored(&data) {
+                return Err(log.err("Identity file is already armored"));
+            }
+            let file = LocalIdentityFile::from_bytes(&data).stack_context(&log, "Error parsing identity file")?;
+            let identity = match &file {
+                LocalIdentityFile::Plain(s) => s.identity(),
+                LocalIdentityFile::Encrypted(s) => {
+                    let passphrase = get_identity_passphrase().stack_context(&log, "Error getting passphrase")?;
+                    s.decrypt(&passphrase).stack_context(&log, "Error decrypting identity file")?.identity()
+                },
+            };
+            write_private_atomic(&args.path, file.to_armored(&identity).as_bytes())
+                .await
+                .stack_context(&log, "Error writing armored identity")?;
+        },
         #[cfg(feature = "card")]
         args::Identity::ListCards => {
             let mut out = vec![];
-            for card in PcscBackend::cards(None).stack_context(log, "Failed to list smart cards")? {
-                let mut card: Card<Open> = card.into();
-                let mut transaction = card.transaction().stack_context(log, "Error starting transaction with card")?;
-                let card_id =
-                    transaction.application_identifier().stack_context(log, "Error getting gpg id of card")?.ident();
-                let identity = match pgp::card_to_ident(&mut transaction) {
-                    Ok(i) => match i {
-                        Some(i) => i,
-                        None => {
-                            continue;
-                        },
-                    },
-                    Err(e) => {
-                        log.log_err(
-                            loga::WARN,
-                            e.context_with("Error getting identity of card", ea!(card = card_id)),
-                        );
-                        continue;
-                    },
-                };
+            for (card_id, identity) in card_identities(log)? {
                 out.push(json!({
                     "pcsc_id": card_id,
                     "id": identity.to_string(),
@@ -1,45 +1,109 @@
 use {
+    aargvark::traits_impls::NotFlag,
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    htwrap::htreq,
+    itertools::Itertools,
     loga::{
         ea,
+        DebugDisplay,
         Log,
         ResultContext,
     },
     serde::Serialize,
+    serde_json::json,
     spaghettinuum::{
         interface::{
+            config::{
+                shared::IpVer,
+                ENV_PUBLISH_SPOOL,
+            },
             stored::{
                 self,
+                identity::Identity,
                 record::{
+                    alias_record::build_alias_key,
                     delegate_record::build_delegate_key,
                     dns_record::{
                         build_dns_key,
                         RecordType,
                     },
+                    handoff_record::build_handoff_key,
+                    key_alias_record::build_key_alias_key,
                     record_utils::{
+                        join_record_key,
+                        normalize_record_key,
                         split_dns_name,
                         split_record_key,
+                        RecordKey,
+                    },
+                    service_record::{
+                        build_services_key,
+                        KEY_SUFFIX_SERVICES,
                     },
+                    succession_record::build_succession_key,
                 },
             },
+            wire,
+        },
+        publishing::{
+            spool::PublishSpool,
+            system_publisher_url_pairs,
+        },
+        resolving::{
+            connect_publisher_node,
+            connect_resolver_node,
+            default_resolver_url_pairs,
+            UrlPair,
         },
-        publishing::system_publisher_url_pairs,
-        resolving::default_resolver_url_pairs,
+        service::{
+            publisher::API_ROUTE_PUBLISH,
+            resolver::{
+                API_ROUTE_RESOLVE,
+                HEADER_PROVENANCE,
+            },
+        },
+        ta_res,
         utils::{
+            blob::ToBlob,
             identity_secret::get_identity_signer,
             publish_util::{
                 self,
                 PublishArgs,
             },
+            signed::IdentSignatureMethods,
+            system_addr::local_resolve_global_ip,
+            tls_util::{
+                cert_der_hash,
+                cert_der_verify,
+            },
+            value_encryption::encrypt_data,
+            zone_import::import_zone,
         },
     },
     std::{
-        collections::HashMap,
+        collections::{
+            BTreeMap,
+            HashMap,
+            HashSet,
+        },
+        fs::{
+            read_dir,
+            read_to_string,
+        },
         net::{
+            IpAddr,
             Ipv4Addr,
             Ipv6Addr,
+            SocketAddr,
         },
+        path::PathBuf,
         str::FromStr,
+        time::Duration,
     },
+    tokio::time::sleep,
 };
 
 pub mod args {
@@ -51,6 +115,7 @@ pub mod args {
             },
             Aargvark,
         },
+        http::Uri,
         spaghettinuum::interface::{
             config::shared::IdentitySecretArg,
             stored,
@@ -80,10 +145,27 @@ pub mod args {
     pub struct Set {
         /// Identity to publish as
         pub identity: IdentitySecretArg,
-        /// Data to publish.  Must be json in the structure
-        /// `{KEY: {"ttl": MINUTES, "value": DATA}, ...}`. `KEY` is a string that's a
-        /// dotted list of key segments, with `/` to escape dots and escape characters.
+        /// Path to the data to publish, or `-` to read it from stdin.  Must be json in the
+        /// structure `{KEY: {"ttl": MINUTES, "value": DATA}, ...}`. `KEY` is a string
+        /// that's a dotted list of key segments, with `/` to escape dots and escape
+        /// characters.
         pub data: AargvarkJson<HashMap<String, stored::record::latest::RecordValue>>,
+        /// Keep published keys that aren't in the data. By default the data replaces
+        /// everything published for the identity.
+        pub merge: Option<()>,
+        /// Only publish if the current record set version (see `version`) matches this
+        pub if_version: Option<String>,
+        /// Encrypt the data of every value to these identities, so only they can read it
+        /// (with `spagh get --decrypt`)
+        pub encrypt_to: Option<Vec<NotFlag>>,
+        /// Don't warn about common mistakes in the records
+        pub no_lint: Option<()>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Batch {
+        /// Directory of signed publish requests (`*.json` files, as output by `sign`)
+        pub dir: PathBuf,
     }
 
     #[derive(Aargvark)]
@@ -96,15 +178,33 @@ pub mod args {
         pub ttl: u32,
         /// A list of other DNS names (`.s` spaghettinuum names or non-spaghettinuum names).
         pub delegate: Option<Vec<NotFlag>>,
-        /// A list of Ipv4 addresses
+        /// A list of Ipv4 addresses. `{{public_ipv4}}` is replaced with the detected
+        /// global IPv4 address of this host.
         pub dns_a: Option<Vec<NotFlag>>,
-        /// A list of Ipv6 addresses
+        /// A list of Ipv6 addresses. `{{public_ipv6}}` is replaced with the detected
+        /// global IPv6 address of this host.
         pub dns_aaaa: Option<Vec<NotFlag>>,
-        /// A list of valid TXT record strings
+        /// A list of valid TXT record strings. `{{public_ipv4}}` and `{{public_ipv6}}`
+        /// are replaced with the detected global addresses of this host.
         pub dns_txt: Option<Vec<NotFlag>>,
         /// Mail server names. These are automatically prioritized, with the first having
         /// priority 0, second 1, etc.
         pub dns_mx: Option<Vec<NotFlag>>,
+        /// A JSON list of service descriptors (ports, protocols, etc) for the host, in the
+        /// format of the `v1` services record.
+        pub services: Option<AargvarkJson<Vec<stored::record::service_record::latest::Service>>>,
+        /// Hand the path off to conventional DNS servers: a JSON object in the format of
+        /// the `v1` handoff record (name servers, and optionally glue addresses and DS
+        /// records). The DNS bridge answers names under the path with a referral to the
+        /// servers.
+        pub handoff: Option<AargvarkJson<stored::record::handoff_record::latest::Handoff>>,
+        /// Detect global addresses on this network interface, the same way `spagh-node`
+        /// does. If `dns_a` or `dns_aaaa` aren't specified, they're filled with the
+        /// detected addresses. Also restricts `{{public_ipv4}}` and `{{public_ipv6}}`
+        /// to this interface.
+        pub from_interface: Option<String>,
+        /// Don't warn about common mistakes in the records
+        pub no_lint: Option<()>,
     }
 
     #[derive(Aargvark)]
@@ -113,6 +213,28 @@ pub mod args {
         pub identity: IdentitySecretArg,
         /// Keys to stop publishing
         pub keys: HashSet<String>,
+        /// Only unpublish if the current record set version (see `version`) matches
+        /// this
+        pub if_version: Option<String>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Version {
+        /// Identity whose record set version to get
+        pub identity: String,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Export {
+        /// Identity whose record set to export
+        pub identity: String,
+        /// The publisher cert hash from the identity's announcement (zbase32). If
+        /// omitted, the export is checked against the hash the publisher reports for
+        /// itself.
+        pub cert_pub_hash: Option<String>,
+        /// Sign the request as this resolver identity, for publishers that only answer
+        /// authorized resolvers
+        pub resolver_identity: Option<IdentitySecretArg>,
     }
 
     #[derive(Aargvark)]
@@ -120,12 +242,124 @@ pub mod args {
         pub identity: String,
     }
 
+    #[derive(Aargvark)]
+    pub struct ReadStats {
+        /// Identity whose published keys to get read statistics for
+        pub identity: IdentitySecretArg,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Watch {
+        /// Identity whose record set to watch
+        pub identity: IdentitySecretArg,
+        /// Only print changes after this record set version, instead of starting with the
+        /// current record set
+        pub version: Option<String>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Rotate {
+        /// Identity being replaced
+        pub identity: IdentitySecretArg,
+        /// Identity replacing it. Both secrets are needed since the succession record is
+        /// signed by both.
+        pub successor: IdentitySecretArg,
+        /// TTL for the succession record, in minutes. Defaults to 1 week.
+        pub ttl: Option<u32>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Alias {
+        /// Identity to serve the target's records under
+        pub identity: IdentitySecretArg,
+        /// Identity whose records to serve
+        pub target: String,
+        /// TTL for the alias record, in minutes. Defaults to 1 hour.
+        pub ttl: Option<u32>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Verification {
+        /// Identity to publish the record for
+        pub identity: IdentitySecretArg,
+        /// The verification label, with or without the leading underscore (ex:
+        /// `acme-challenge`, `_dmarc`)
+        pub label: String,
+        /// TXT record strings, replacing any currently published for the label
+        pub values: Vec<NotFlag>,
+        /// Name under the identity the record is for, in DNS order (ex: `www`,
+        /// `a.b`). Defaults to the identity root.
+        pub host: Option<String>,
+        /// TTL for the record, in minutes. Defaults to 5 minutes.
+        pub ttl: Option<u32>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct KeyAlias {
+        /// Identity whose records to alias
+        pub identity: IdentitySecretArg,
+        /// Path to serve the target's records at, as a `.`-separated key (empty for the
+        /// identity root)
+        pub path: String,
+        /// Path whose records to serve, as a `.`-separated key (empty for the identity
+        /// root)
+        pub target: String,
+        /// TTL for the key alias record, in minutes. Defaults to 1 hour.
+        pub ttl: Option<u32>,
+    }
+
     #[derive(Aargvark)]
     pub struct Announce {
         /// Identity to advertise this publisher for
         pub identity: IdentitySecretArg,
     }
 
+    #[derive(Aargvark)]
+    pub struct ImportZone {
+        /// Identity to publish the zone's records as
+        pub identity: IdentitySecretArg,
+        /// Path to a standard DNS zone file
+        pub zonefile: PathBuf,
+        /// Zone origin (ex: `example.org.`), for relative names before any `$ORIGIN`
+        /// directive
+        pub origin: Option<String>,
+        /// Print the converted records without publishing them
+        pub dry_run: Option<()>,
+        /// Don't warn about common mistakes in the records
+        pub no_lint: Option<()>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Verify {
+        /// Identity whose published records to check
+        pub identity: IdentitySecretArg,
+    }
+
+    #[derive(Aargvark)]
+    pub struct Migrate {
+        /// Identity whose records to move
+        pub identity: IdentitySecretArg,
+        /// URL of the publisher the identity is moving from
+        pub from: Uri,
+        /// After announcing the new publishers, delete the identity's announcement and
+        /// records from the old publisher
+        pub clear_old: Option<()>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct SpoolStatus {
+        /// Spool directory, if not set with `SPAGH_PUBLISH_SPOOL`
+        pub dir: Option<PathBuf>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct SpoolFlush {
+        /// Spool directory, if not set with `SPAGH_PUBLISH_SPOOL`
+        pub dir: Option<PathBuf>,
+        /// Keep retrying (with backoff) until the spool is empty, instead of trying once
+        pub retry: Option<()>,
+    }
+
     #[derive(Aargvark)]
     #[vark(break_help)]
     pub enum Publish {
@@ -135,6 +369,13 @@ pub mod args {
         Announce(Announce),
         /// Create or replace existing publish data for an identity on a publisher server
         Set(Set),
+        /// Sign the same changes as `set` and print the publish request instead of sending
+        /// it, for sending later with `batch`
+        Sign(Set),
+        /// Send a directory of signed publish requests (for any number of identities) to
+        /// each publisher in as few requests as possible. Prints the result for each
+        /// request and fails if any weren't applied.
+        Batch(Batch),
         /// A shortcut for publishing common data, generating the appropriate key-values
         /// for you
         SetCommon(SetCommon),
@@ -142,10 +383,245 @@ pub mod args {
         Unset(Unset),
         /// Stop publishing all records for an identity
         UnsetAll(UnsetAll),
+        /// Get the current version of the records published for an identity, for
+        /// conditional updates
+        Version(Version),
+        /// Get the records published for an identity signed by each publisher, with a
+        /// timestamp, and check the signatures. The output can be submitted to a
+        /// transparency log so others can audit what the publisher served.
+        Export(Export),
+        /// Show how many times each published key has been read since the publisher
+        /// started, if the publisher tracks read statistics
+        ReadStats(ReadStats),
+        /// Print the version and records published for an identity each time they change
+        /// (ex: when another device with the same identity publishes), one JSON object
+        /// per line. Watches the first publisher.
+        Watch(Watch),
+        /// Publish a succession record for an identity pointing to a new identity, so
+        /// lookups of the old identity are redirected to the new one (ex: when rotating
+        /// keys). The new identity still needs to be announced and have its records
+        /// published.
+        Rotate(Rotate),
+        /// Publish an alias record for an identity, so the publisher serves all the
+        /// records of the target identity for it instead of its own. Both identities
+        /// must be announced on the same publisher.
+        Alias(Alias),
+        /// Publish a key alias record at a path, so the publisher serves the records at
+        /// the target path of the same identity for it (like a DNS CNAME). Other records
+        /// at the path are ignored.
+        KeyAlias(KeyAlias),
+        /// Publish TXT records at an underscore-prefixed label under a name (ex:
+        /// `_acme-challenge.www`), as used to prove control of a name by ACME DNS-01
+        /// challenges, DMARC, and site verification services. The DNS bridge answers
+        /// these even if the name is delegated elsewhere.
+        Verification(Verification),
+        /// Resolve everything an identity has published via the resolvers (through the
+        /// DHT) and compare it to what the publishers serve. Prints the differences found
+        /// and fails if there are any.
+        Verify(Verify),
+        /// Convert a DNS zone file (A, AAAA, TXT, MX, CNAME, and SRV records) to
+        /// records and publish them. Names in the zone are published at the same path
+        /// under the identity. Records that can't be converted are listed in the output.
+        ImportZone(ImportZone),
+        /// Copy the records published for an identity on another publisher to the
+        /// configured publishers (replacing what they have), then announce the configured
+        /// publishers
+        Migrate(Migrate),
+        /// List the publish requests queued in the spool because a publisher couldn't be
+        /// reached
+        SpoolStatus(SpoolStatus),
+        /// Send the publish requests queued in the spool. Fails if any are still queued
+        /// afterwards.
+        SpoolFlush(SpoolFlush),
+    }
+}
+
+fn spool_from_args(dir: Option<PathBuf>) -> Result<PublishSpool, loga::Error> {
+    if let Some(dir) = dir {
+        return Ok(PublishSpool::new(dir));
+    }
+    return PublishSpool::from_env().ok_or_else(
+        || loga::err_with("No spool directory specified", ea!(env = ENV_PUBLISH_SPOOL)),
+    );
+}
+
+/// A difference between what the publishers serve and what resolvers return.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Discrepancy {
+    /// The configured publishers serve different values for the key
+    PublishersDisagree {
+        key: String,
+        values: BTreeMap<String, Option<serde_json::Value>>,
+    },
+    /// The resolver has no value for the key and doesn't know of a publisher - the
+    /// identity may not be announced, or the announcement expired
+    NotAnnounced {
+        key: String,
+    },
+    /// The resolver returned an older value from its cache, which should be replaced
+    /// when it expires
+    PropagationLag {
+        key: String,
+        published: Option<serde_json::Value>,
+        resolved: Option<serde_json::Value>,
+        expires_in_secs: i64,
+    },
+    /// The resolver got the value from a publisher that isn't configured - the
+    /// announcement in the DHT is probably stale
+    StaleAnnouncement {
+        key: String,
+        publisher: SocketAddr,
+        announced: Option<DateTime<Utc>>,
+    },
+    /// The resolver fetched a different value from a configured publisher's address
+    /// than the publisher's API serves - something else may be answering at the
+    /// advertised address
+    WrongPublisher {
+        key: String,
+        publisher: Option<SocketAddr>,
+        published: Option<serde_json::Value>,
+        resolved: Option<serde_json::Value>,
+    },
+}
+
+/// Compare published values (by publisher URL) with resolved values.
+fn find_discrepancies(
+    publisher_addrs: &HashSet<SocketAddr>,
+    published: &HashMap<RecordKey, BTreeMap<String, Option<serde_json::Value>>>,
+    resolved: &HashMap<RecordKey, wire::resolve::v1::ResolveValue>,
+) -> Vec<Discrepancy> {
+    let mut out = vec![];
+    for (key, values) in published {
+        let key_str = join_record_key(key);
+        let published_data = values.values().next().cloned().flatten();
+        if values.values().any(|v| *v != published_data) {
+            out.push(Discrepancy::PublishersDisagree {
+                key: key_str,
+                values: values.clone(),
+            });
+            continue;
+        }
+        let resolved_value = resolved.get(key);
+        let provenance = resolved_value.and_then(|v| v.provenance.as_ref());
+        let resolved_data = resolved_value.and_then(|v| v.data.clone());
+        if let Some(publisher) = provenance.and_then(|p| p.publisher) {
+            if !publisher_addrs.contains(&publisher) {
+                out.push(Discrepancy::StaleAnnouncement {
+                    key: key_str,
+                    publisher: publisher,
+                    announced: provenance.and_then(|p| p.announced),
+                });
+                continue;
+            }
+        }
+        if resolved_data == published_data {
+            continue;
+        }
+        match provenance {
+            None => {
+                out.push(Discrepancy::NotAnnounced { key: key_str });
+            },
+            Some(p) if p.publisher.is_none() && p.announced.is_none() && resolved_data.is_none() => {
+                out.push(Discrepancy::NotAnnounced { key: key_str });
+            },
+            Some(p) if p.source != wire::resolve::v1::ResolveSource::Fetch => {
+                out.push(Discrepancy::PropagationLag {
+                    key: key_str,
+                    published: published_data,
+                    resolved: resolved_data,
+                    expires_in_secs: p.ttl_remaining,
+                });
+            },
+            Some(p) => {
+                out.push(Discrepancy::WrongPublisher {
+                    key: key_str,
+                    publisher: p.publisher,
+                    published: published_data,
+                    resolved: resolved_data,
+                });
+            },
+        }
     }
+    out.sort_by_cached_key(|d| serde_json::to_string(d).unwrap());
+    return out;
 }
 
-pub async fn run(log: &Log, config: args::Publish) -> Result<(), loga::Error> {
+const TEMPLATE_PUBLIC_IPV4: &str = "{{public_ipv4}}";
+const TEMPLATE_PUBLIC_IPV6: &str = "{{public_ipv6}}";
+
+/// Global addresses of this host, detected on first use.
+struct HostAddrs {
+    interface: Option<String>,
+    ipv4: Option<Option<IpAddr>>,
+    ipv6: Option<Option<IpAddr>>,
+}
+
+impl HostAddrs {
+    async fn get(&mut self, ver: IpVer) -> Result<Option<IpAddr>, loga::Error> {
+        let cached = match ver {
+            IpVer::V4 => &mut self.ipv4,
+            IpVer::V6 => &mut self.ipv6,
+        };
+        if let Some(found) = cached {
+            return Ok(*found);
+        }
+        let found =
+            local_resolve_global_ip(&self.interface, &Some(ver))
+                .await
+                .context("Error detecting global address of host")?;
+        *cached = Some(found);
+        return Ok(found);
+    }
+
+    /// Replace address template variables in `value`
+    async fn expand(&mut self, value: &str) -> Result<String, loga::Error> {
+        let mut value = value.to_string();
+        for (var, ver) in [(TEMPLATE_PUBLIC_IPV4, IpVer::V4), (TEMPLATE_PUBLIC_IPV6, IpVer::V6)] {
+            if !value.contains(var) {
+                continue;
+            }
+            let addr =
+                self
+                    .get(ver)
+                    .await?
+                    .context_with("No global address found to fill template variable", ea!(variable = var))?;
+            value = value.replace(var, &addr.to_string());
+        }
+        return Ok(value);
+    }
+}
+
+/// Convert `set` data to records to publish.
+fn set_data(
+    data: HashMap<String, stored::record::latest::RecordValue>,
+    encrypt_to: Option<Vec<NotFlag>>,
+) -> Result<HashMap<RecordKey, stored::record::RecordValue>, loga::Error> {
+    let mut recipients = vec![];
+    for r in encrypt_to.unwrap_or_default() {
+        recipients.push(Identity::from_str(&r.0).context_with("Invalid recipient identity", ea!(identity = r.0))?);
+    }
+    let mut set = HashMap::new();
+    for (k, mut v) in data {
+        let k = split_record_key(&k);
+        if k.last().map(|x| x.as_str()) == Some(KEY_SUFFIX_SERVICES) {
+            if let Some(data) = &v.data {
+                serde_json::from_value::<stored::record::service_record::Services>(
+                    data.clone(),
+                ).context_with("Services record data doesn't match schema", ea!(key = k.join(".")))?;
+            }
+        }
+        if !recipients.is_empty() {
+            if let Some(data) = &v.data {
+                v.data = Some(encrypt_data(&recipients, data)?);
+            }
+        }
+        set.insert(k, stored::record::RecordValue::V1(v));
+    }
+    return Ok(set);
+}
+
+pub async fn run(log: &Log, quiet: bool, config: args::Publish) -> Result<(), loga::Error> {
     let resolvers = default_resolver_url_pairs(log)?;
     let publishers = system_publisher_url_pairs(log)?;
     match config {
@@ -154,7 +630,23 @@ pub async fn run(log: &Log, config: args::Publish) -> Result<(), loga::Error> {
                 get_identity_signer(config.identity)
                     .await
                     .stack_context(&log, "Error constructing signer for identity")?;
-            publish_util::announce(log, &resolvers, &publishers, &signer).await?;
+            for (publisher, resp) in publish_util::announce(log, &resolvers, &publishers, &signer).await? {
+                if !quiet {
+                    println!("{}", serde_json::to_string_pretty(&json!({
+                        "publisher": publisher,
+                        "dht": resp,
+                    })).unwrap());
+                }
+                if let Some(resp) = resp {
+                    if resp.sent > 0 && resp.accepted == 0 {
+                        log.log_with(
+                            loga::WARN,
+                            "No DHT nodes acknowledged storing the announcement",
+                            ea!(publisher = publisher),
+                        );
+                    }
+                }
+            }
         },
         args::Publish::Set(config) => {
             let signer =
@@ -162,17 +654,110 @@ pub async fn run(log: &Log, config: args::Publish) -> Result<(), loga::Error> {
                     .await
                     .stack_context(&log, "Error constructing signer for identity")?;
             publish_util::publish(log, &resolvers, &publishers, &signer, PublishArgs {
-                set: config
-                    .data
-                    .value
-                    .into_iter()
-                    .map(|(k, v)| (split_record_key(&k), stored::record::RecordValue::V1(v)))
-                    .collect(),
+                clear_all: config.merge.is_none(),
+                set: set_data(config.data.value, config.encrypt_to)?,
+                if_version: config.if_version,
+                no_lint: config.no_lint.is_some(),
                 ..Default::default()
             }).await?;
         },
-        args::Publish::SetCommon(config) => {
+        args::Publish::Sign(config) => {
+            let signer =
+                get_identity_signer(config.identity)
+                    .await
+                    .stack_context(log, "Error constructing signer for identity")?;
+            let request = publish_util::sign_publish_request(log, &signer, PublishArgs {
+                clear_all: config.merge.is_none(),
+                set: set_data(config.data.value, config.encrypt_to)?,
+                if_version: config.if_version,
+                no_lint: config.no_lint.is_some(),
+                ..Default::default()
+            })?;
+            println!("{}", serde_json::to_string_pretty(&request).unwrap());
+        },
+        args::Publish::Batch(config) => {
+            let mut paths = vec![];
+            for entry in read_dir(&config.dir).context("Error listing batch directory")? {
+                let path = entry.context("Error listing batch directory")?.path();
+                if path.is_file() && path.extension().map(|e| e == "json").unwrap_or(false) {
+                    paths.push(path);
+                }
+            }
+            paths.sort();
+            let mut requests = vec![];
+            for path in paths {
+                requests.push(
+                    serde_json::from_str::<wire::api::publish::latest::PublishRequest>(
+                        &read_to_string(&path).context_with("Error reading request", ea!(path = path.dbg_str()))?,
+                    ).context_with("Invalid publish request", ea!(path = path.dbg_str()))?,
+                );
+            }
+            let mut failed = 0;
+            for pair in publishers {
+                let pair = pair.join(format!("{}/v1/publish_batch", API_ROUTE_PUBLISH));
+                for chunk in requests.chunks(wire::api::publish::latest::PUBLISH_BATCH_MAX) {
+                    log.log_with(
+                        loga::DEBUG,
+                        "Sending publish batch request (POST)",
+                        ea!(url = pair, count = chunk.len()),
+                    );
+                    let resp =
+                        htreq::post_json::<wire::api::publish::latest::PublishBatchResponse>(
+                            log,
+                            &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                            &pair.url,
+                            &HashMap::new(),
+                            &wire::api::publish::latest::PublishBatchRequest { requests: chunk.to_vec() },
+                            16 * 1024 * 1024,
+                        ).await?;
+                    for result in resp.results {
+                        match &result.outcome {
+                            wire::api::publish::latest::PublishBatchOutcome::Applied { .. } => {
+                                if quiet {
+                                    continue;
+                                }
+                            },
+                            _ => {
+                                failed += 1;
+                            },
+                        }
+                        println!("{}", serde_json::to_string(&json!({
+                            "publisher": pair.url.to_string(),
+                            "identity": result.identity,
+                            "outcome": result.outcome,
+                        })).unwrap());
+                    }
+                }
+            }
+            if failed > 0 {
+                return Err(loga::err_with("Some publish requests weren't applied", ea!(count = failed)));
+            }
+        },
+        args::Publish::SetCommon(mut config) => {
             let path = config.path.into_iter().map(|x| x.0).collect::<Vec<_>>();
+            let mut host_addrs = HostAddrs {
+                interface: config.from_interface.clone(),
+                ipv4: None,
+                ipv6: None,
+            };
+            if config.from_interface.is_some() {
+                let ipv4 = host_addrs.get(IpVer::V4).await?;
+                let ipv6 = host_addrs.get(IpVer::V6).await?;
+                if ipv4.is_none() && ipv6.is_none() {
+                    return Err(
+                        loga::err_with(
+                            "No global addresses found on interface",
+                            ea!(interface = config.from_interface.as_ref().unwrap()),
+                        ),
+                    );
+                }
+                if config.dns_a.is_none() {
+                    config.dns_a = ipv4.map(|i| vec![NotFlag(i.to_string())]);
+                }
+                if config.dns_aaaa.is_none() {
+                    config.dns_aaaa = ipv6.map(|i| vec![NotFlag(i.to_string())]);
+                }
+            }
 
             fn rec_val(ttl: u32, data: impl Serialize) -> stored::record::RecordValue {
                 return stored::record::RecordValue::latest(stored::record::latest::RecordValue {
@@ -206,7 +791,8 @@ pub async fn run(log: &Log, config: args::Publish) -> Result<(), loga::Error> {
             if !config_dns_a.is_empty() {
                 let mut v = vec![];
                 for r in config_dns_a {
-                    v.push(Ipv4Addr::from_str(&r.0).context("Invalid IP address for A record")?);
+                    let r = host_addrs.expand(&r.0).await?;
+                    v.push(Ipv4Addr::from_str(&r).context_with("Invalid IP address for A record", ea!(value = r))?);
                 }
                 kvs.insert(
                     build_dns_key(path.clone(), RecordType::A),
@@ -220,7 +806,10 @@ pub async fn run(log: &Log, config: args::Publish) -> Result<(), loga::Error> {
             if !config_dns_aaaa.is_empty() {
                 let mut v = vec![];
                 for r in config_dns_aaaa {
-                    v.push(Ipv6Addr::from_str(&r.0).context("Invalid IP address for AAAA record")?);
+                    let r = host_addrs.expand(&r.0).await?;
+                    v.push(
+                        Ipv6Addr::from_str(&r).context_with("Invalid IP address for AAAA record", ea!(value = r))?,
+                    );
                 }
                 kvs.insert(
                     build_dns_key(path.clone(), RecordType::Aaaa),
@@ -232,14 +821,16 @@ pub async fn run(log: &Log, config: args::Publish) -> Result<(), loga::Error> {
             }
             let config_dns_txt = config.dns_txt.unwrap_or_default();
             if !config_dns_txt.is_empty() {
+                let mut v = vec![];
+                for r in config_dns_txt {
+                    v.push(host_addrs.expand(&r.0).await?);
+                }
                 kvs.insert(
                     build_dns_key(path.clone(), RecordType::Txt),
                     rec_val(
                         config.ttl,
                         &stored::record::dns_record::DnsTxt::V1(
-                            stored::record::dns_record::latest::DnsTxt(
-                                config_dns_txt.into_iter().map(|x| x.into()).collect(),
-                            ),
+                            stored::record::dns_record::latest::DnsTxt(v),
                         ),
                     ),
                 );
@@ -258,12 +849,35 @@ pub async fn run(log: &Log, config: args::Publish) -> Result<(), loga::Error> {
                     ),
                 );
             }
+            if let Some(config_services) = config.services {
+                if !config_services.value.is_empty() {
+                    kvs.insert(
+                        build_services_key(path.clone()),
+                        rec_val(
+                            config.ttl,
+                            stored::record::service_record::Services::latest(
+                                stored::record::service_record::latest::Services(config_services.value),
+                            ),
+                        ),
+                    );
+                }
+            }
+            if let Some(config_handoff) = config.handoff {
+                if path.is_empty() {
+                    return Err(log.err("Handoffs can't be published at the identity root"));
+                }
+                kvs.insert(
+                    build_handoff_key(path.clone()),
+                    rec_val(config.ttl, stored::record::handoff_record::Handoff::latest(config_handoff.value)),
+                );
+            }
             let signer =
                 get_identity_signer(config.identity)
                     .await
                     .stack_context(&log, "Error constructing signer for identity")?;
             publish_util::publish(log, &resolvers, &publishers, &signer, PublishArgs {
                 set: kvs,
+                no_lint: config.no_lint.is_some(),
                 ..Default::default()
             }).await?;
         },
@@ -274,6 +888,7 @@ pub async fn run(log: &Log, config: args::Publish) -> Result<(), loga::Error> {
                     .stack_context(&log, "Error constructing signer for identity")?;
             publish_util::publish(log, &resolvers, &publishers, &signer, PublishArgs {
                 clear: config.keys.into_iter().map(|k| split_record_key(&k)).collect(),
+                if_version: config.if_version,
                 ..Default::default()
             }).await?;
         },
@@ -287,6 +902,647 @@ pub async fn run(log: &Log, config: args::Publish) -> Result<(), loga::Error> {
                 ..Default::default()
             }).await?;
         },
+        args::Publish::Version(config) => {
+            let identity = Identity::from_str(&config.identity).context("Invalid identity")?;
+            for pair in publishers {
+                let pair = pair.join(format!("{}/v1/version/{}", API_ROUTE_PUBLISH, identity));
+                log.log_with(loga::DEBUG, "Sending version request (GET)", ea!(url = pair));
+                let resp =
+                    htreq::get_json::<wire::api::publish::latest::RecordSetVersion>(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &HashMap::new(),
+                        1024,
+                    ).await?;
+                println!("{}", serde_json::to_string_pretty(&json!({
+                    "publisher": pair.url.to_string(),
+                    "version": resp.version,
+                })).unwrap());
+            }
+        },
+        args::Publish::Export(config) => {
+            let identity = Identity::from_str(&config.identity).context("Invalid identity")?;
+            let expect_cert_pub_hash = match &config.cert_pub_hash {
+                Some(h) => Some(
+                    zbase32::decode_full_bytes_str(h).map_err(|_| loga::err("Cert hash isn't valid zbase32"))?.blob(),
+                ),
+                None => None,
+            };
+            let signer = match config.resolver_identity {
+                Some(i) => Some(
+                    get_identity_signer(i).await.stack_context(log, "Error constructing signer for resolver identity")?,
+                ),
+                None => None,
+            };
+            for pair in publishers {
+                let cert_pub_hash = match &expect_cert_pub_hash {
+                    Some(h) => h.clone(),
+                    None => {
+                        let info_pair = pair.join(format!("{}/v1/info", API_ROUTE_PUBLISH));
+                        log.log_with(loga::DEBUG, "Sending info request (GET)", ea!(url = info_pair));
+                        htreq::get_json::<wire::api::publish::latest::InfoResponse>(
+                            log,
+                            &mut connect_publisher_node(log, &resolvers, &info_pair).await?,
+                            &info_pair.url,
+                            &HashMap::new(),
+                            100 * 1024,
+                        ).await?.cert_pub_hash
+                    },
+                };
+                let pair = pair.join(format!("{}/v1/export/{}", API_ROUTE_PUBLISH, identity));
+                let resp = match &signer {
+                    Some(signer) => {
+                        let (resolver, content) =
+                            wire::api::publish::v1::JsonSignature::sign(
+                                &mut *signer.lock().unwrap(),
+                                wire::api::publish::latest::ExportRequestContent {
+                                    requested: Utc::now(),
+                                    identity: identity,
+                                },
+                            ).stack_context(log, "Failed to sign export request")?;
+                        log.log_with(loga::DEBUG, "Sending export request (POST)", ea!(url = pair));
+                        htreq::post_json::<wire::api::publish::latest::SignedExport>(
+                            log,
+                            &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                            &pair.url,
+                            &HashMap::new(),
+                            &wire::api::publish::latest::ExportRequest {
+                                resolver: resolver,
+                                content: content,
+                            },
+                            10 * 1024 * 1024,
+                        ).await?
+                    },
+                    None => {
+                        log.log_with(loga::DEBUG, "Sending export request (GET)", ea!(url = pair));
+                        htreq::get_json::<wire::api::publish::latest::SignedExport>(
+                            log,
+                            &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                            &pair.url,
+                            &HashMap::new(),
+                            10 * 1024 * 1024,
+                        ).await?
+                    },
+                };
+
+                // Verify
+                if cert_der_hash(&resp.cert_pub_der)? != cert_pub_hash {
+                    return Err(
+                        loga::err_with(
+                            "Export cert doesn't match the publisher cert hash",
+                            ea!(publisher = pair.url, expected = cert_pub_hash),
+                        ),
+                    );
+                }
+                cert_der_verify(
+                    &resp.cert_pub_der,
+                    resp.content.as_bytes(),
+                    &resp.signature,
+                ).context_with("Export has an invalid signature", ea!(publisher = pair.url))?;
+                let content =
+                    serde_json::from_str::<wire::api::publish::latest::ExportContent>(
+                        &resp.content,
+                    ).context_with("Export content is invalid", ea!(publisher = pair.url))?;
+                if content.identity != identity {
+                    return Err(
+                        loga::err_with(
+                            "Export is for a different identity",
+                            ea!(publisher = pair.url, identity = content.identity),
+                        ),
+                    );
+                }
+                println!("{}", serde_json::to_string_pretty(&json!({
+                    "publisher": pair.url.to_string(),
+                    "cert_pub_hash": cert_pub_hash,
+                    "version": content.version,
+                    "exported": content.exported,
+                    "export": resp,
+                })).unwrap());
+            }
+        },
+        args::Publish::ReadStats(config) => {
+            let signer =
+                get_identity_signer(config.identity)
+                    .await
+                    .stack_context(log, "Error constructing signer for identity")?;
+            for pair in publishers {
+                let pair = pair.join(format!("{}/v1/read_stats", API_ROUTE_PUBLISH));
+                let (identity, content) =
+                    wire::api::publish::v1::JsonSignature::sign(
+                        &mut *signer.lock().unwrap(),
+                        wire::api::publish::latest::ReadStatsRequestContent { requested: Utc::now() },
+                    ).stack_context(log, "Failed to sign read stats request")?;
+                log.log_with(loga::DEBUG, "Sending read stats request (POST)", ea!(url = pair));
+                let resp =
+                    htreq::post_json::<Vec<wire::api::publish::latest::KeyReadStats>>(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &HashMap::new(),
+                        &wire::api::publish::latest::ReadStatsRequest {
+                            identity: identity,
+                            content: content,
+                        },
+                        1024 * 1024,
+                    ).await?;
+                println!("{}", serde_json::to_string_pretty(&json!({
+                    "publisher": pair.url.to_string(),
+                    "keys": resp,
+                })).unwrap());
+            }
+        },
+        args::Publish::Watch(config) => {
+            let signer =
+                get_identity_signer(config.identity)
+                    .await
+                    .stack_context(log, "Error constructing signer for identity")?;
+            let pair =
+                publishers
+                    .first()
+                    .stack_context(log, "No publishers configured")?
+                    .join(format!("{}/v1/watch", API_ROUTE_PUBLISH));
+            let mut version = config.version;
+            loop {
+                let (identity, content) =
+                    wire::api::publish::v1::JsonSignature::sign(
+                        &mut *signer.lock().unwrap(),
+                        wire::api::publish::latest::WatchRequestContent {
+                            requested: Utc::now(),
+                            version: version.clone(),
+                        },
+                    ).stack_context(log, "Failed to sign watch request")?;
+                log.log_with(loga::DEBUG, "Sending watch request (POST)", ea!(url = pair, version = version.dbg_str()));
+                let resp =
+                    htreq::post_json::<wire::api::publish::latest::WatchResponse>(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &pair).await?,
+                        &pair.url,
+                        &HashMap::new(),
+                        &wire::api::publish::latest::WatchRequest {
+                            identity: identity,
+                            content: content,
+                        },
+                        10 * 1024 * 1024,
+                    ).await?;
+                if !resp.changed {
+                    continue;
+                }
+                println!("{}", serde_json::to_string(&json!({
+                    "version": resp.version,
+                    "values": resp.values,
+                })).unwrap());
+                version = Some(resp.version);
+            }
+        },
+        args::Publish::Rotate(config) => {
+            let signer =
+                get_identity_signer(config.identity)
+                    .await
+                    .stack_context(log, "Error constructing signer for identity")?;
+            let successor_signer =
+                get_identity_signer(config.successor)
+                    .await
+                    .stack_context(log, "Error constructing signer for successor identity")?;
+            let predecessor = signer.lock().unwrap().identity().context("Error getting identity")?;
+            let successor =
+                successor_signer.lock().unwrap().identity().context("Error getting successor identity")?;
+            if predecessor == successor {
+                return Err(log.err("The identity and successor identity are the same"));
+            }
+            let content =
+                serde_json::to_string(&stored::record::succession_record::latest::SuccessionContent {
+                    predecessor: predecessor,
+                    successor: successor,
+                    issued: Utc::now(),
+                }).unwrap();
+            let (_, predecessor_signature) =
+                signer
+                    .lock()
+                    .unwrap()
+                    .sign(content.as_bytes())
+                    .stack_context(log, "Error signing succession record with identity")?;
+            let (_, successor_signature) =
+                successor_signer
+                    .lock()
+                    .unwrap()
+                    .sign(content.as_bytes())
+                    .stack_context(log, "Error signing succession record with successor identity")?;
+            let record =
+                stored::record::succession_record::Succession::latest(
+                    stored::record::succession_record::latest::Succession {
+                        content: content,
+                        predecessor_signature: predecessor_signature,
+                        successor_signature: successor_signature,
+                    },
+                );
+            publish_util::publish(log, &resolvers, &publishers, &signer, PublishArgs {
+                set: [
+                    (
+                        build_succession_key(),
+                        stored::record::RecordValue::latest(stored::record::latest::RecordValue {
+                            ttl: config.ttl.unwrap_or(60 * 24 * 7) as i32,
+                            data: Some(serde_json::to_value(&record).unwrap()),
+                        }),
+                    ),
+                ].into_iter().collect(),
+                ..Default::default()
+            }).await?;
+            if !quiet {
+                println!("{}", serde_json::to_string_pretty(&json!({
+                    "identity": predecessor,
+                    "successor": successor,
+                })).unwrap());
+            }
+        },
+        args::Publish::Alias(config) => {
+            let signer =
+                get_identity_signer(config.identity)
+                    .await
+                    .stack_context(log, "Error constructing signer for identity")?;
+            let identity = signer.lock().unwrap().identity().context("Error getting identity")?;
+            let target = Identity::from_str(&config.target).context("Invalid target identity")?;
+            if identity == target {
+                return Err(log.err("The identity and target identity are the same"));
+            }
+            let record =
+                stored::record::alias_record::Alias::latest(stored::record::alias_record::latest::Alias {
+                    target: target,
+                });
+            publish_util::publish(log, &resolvers, &publishers, &signer, PublishArgs {
+                set: [
+                    (
+                        build_alias_key(),
+                        stored::record::RecordValue::latest(stored::record::latest::RecordValue {
+                            ttl: config.ttl.unwrap_or(60) as i32,
+                            data: Some(serde_json::to_value(&record).unwrap()),
+                        }),
+                    ),
+                ].into_iter().collect(),
+                ..Default::default()
+            }).await?;
+        },
+        args::Publish::Verification(config) => {
+            let signer =
+                get_identity_signer(config.identity)
+                    .await
+                    .stack_context(log, "Error constructing signer for identity")?;
+            let label = config.label.trim_start_matches('_');
+            if label.is_empty() || !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+                return Err(
+                    loga::err_with(
+                        "Verification label must be letters, digits, hyphens, and underscores",
+                        ea!(label = config.label),
+                    ),
+                );
+            }
+            if config.values.is_empty() {
+                return Err(log.err("No TXT values specified"));
+            }
+            let mut path: RecordKey = match &config.host {
+                Some(host) => host.trim_end_matches('.').split('.').rev().map(|p| p.to_string()).collect(),
+                None => vec![],
+            };
+            path.push(format!("_{}", label.to_ascii_lowercase()));
+            let record =
+                stored::record::dns_record::DnsTxt::V1(
+                    stored::record::dns_record::latest::DnsTxt(config.values.into_iter().map(|v| v.0).collect()),
+                );
+            publish_util::publish(log, &resolvers, &publishers, &signer, PublishArgs {
+                set: [
+                    (
+                        build_dns_key(path, RecordType::Txt),
+                        stored::record::RecordValue::latest(stored::record::latest::RecordValue {
+                            ttl: config.ttl.unwrap_or(5) as i32,
+                            data: Some(serde_json::to_value(&record).unwrap()),
+                        }),
+                    ),
+                ].into_iter().collect(),
+                ..Default::default()
+            }).await?;
+        },
+        args::Publish::KeyAlias(config) => {
+            let signer =
+                get_identity_signer(config.identity)
+                    .await
+                    .stack_context(log, "Error constructing signer for identity")?;
+            let path = normalize_record_key(&split_record_key(&config.path));
+            let target = normalize_record_key(&split_record_key(&config.target));
+            if path == target {
+                return Err(log.err("The path and target path are the same"));
+            }
+            let record =
+                stored::record::key_alias_record::KeyAlias::latest(stored::record::key_alias_record::latest::KeyAlias {
+                    target: target,
+                });
+            publish_util::publish(log, &resolvers, &publishers, &signer, PublishArgs {
+                set: [
+                    (
+                        build_key_alias_key(path),
+                        stored::record::RecordValue::latest(stored::record::latest::RecordValue {
+                            ttl: config.ttl.unwrap_or(60) as i32,
+                            data: Some(serde_json::to_value(&record).unwrap()),
+                        }),
+                    ),
+                ].into_iter().collect(),
+                ..Default::default()
+            }).await?;
+        },
+        args::Publish::Verify(config) => {
+            let signer =
+                get_identity_signer(config.identity)
+                    .await
+                    .stack_context(log, "Error constructing signer for identity")?;
+            let identity = signer.lock().unwrap().identity().context("Error getting identity")?;
+
+            // Get what each publisher serves
+            let mut publisher_addrs = HashSet::new();
+            let mut published: HashMap<RecordKey, BTreeMap<String, Option<serde_json::Value>>> = HashMap::new();
+            for pair in &publishers {
+                let info_pair = pair.join(format!("{}/v1/info", API_ROUTE_PUBLISH));
+                log.log_with(loga::DEBUG, "Sending info request (GET)", ea!(url = info_pair));
+                let info =
+                    htreq::get_json::<wire::api::publish::latest::InfoResponse>(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &info_pair).await?,
+                        &info_pair.url,
+                        &HashMap::new(),
+                        100 * 1024,
+                    ).await?;
+                publisher_addrs.extend(info.addrs());
+                let watch_pair = pair.join(format!("{}/v1/watch", API_ROUTE_PUBLISH));
+                let (_, content) =
+                    wire::api::publish::v1::JsonSignature::sign(
+                        &mut *signer.lock().unwrap(),
+                        wire::api::publish::latest::WatchRequestContent {
+                            requested: Utc::now(),
+                            version: None,
+                        },
+                    ).stack_context(log, "Failed to sign watch request")?;
+                log.log_with(loga::DEBUG, "Sending watch request (POST)", ea!(url = watch_pair));
+                let resp =
+                    htreq::post_json::<wire::api::publish::latest::WatchResponse>(
+                        log,
+                        &mut connect_publisher_node(log, &resolvers, &watch_pair).await?,
+                        &watch_pair.url,
+                        &HashMap::new(),
+                        &wire::api::publish::latest::WatchRequest {
+                            identity: identity,
+                            content: content,
+                        },
+                        10 * 1024 * 1024,
+                    ).await?;
+                let publisher = pair.url.to_string();
+                for (key, value) in resp.values {
+                    let data = match value {
+                        stored::record::RecordValue::V1(v) => v.data,
+                    };
+                    published.entry(key).or_default().insert(publisher.clone(), data);
+                }
+            }
+
+            // Keys missing from some publishers have no value there
+            for values in published.values_mut() {
+                for pair in &publishers {
+                    values.entry(pair.url.to_string()).or_insert(None);
+                }
+            }
+
+            // Resolve the same keys via the DHT
+            let keys = published.keys().cloned().collect::<Vec<_>>();
+            let mut headers = HashMap::new();
+            headers.insert(HEADER_PROVENANCE.to_string(), "1".to_string());
+            let mut errs = vec![];
+            let mut found = None;
+            for pair in &resolvers {
+                match async {
+                    ta_res!(wire::api::resolve::v1::ResolveResp);
+                    let pair =
+                        pair.join(
+                            format!(
+                                "{}/v1/{}?{}",
+                                API_ROUTE_RESOLVE,
+                                identity,
+                                keys.iter().map(|k| urlencoding::encode(&join_record_key(k)).to_string()).join(",")
+                            ),
+                        );
+                    log.log_with(loga::DEBUG, "Sending query request", ea!(url = pair));
+                    return htreq::get_json::<wire::api::resolve::v1::ResolveResp>(
+                        log,
+                        &mut connect_resolver_node(&pair).await?,
+                        &pair.url,
+                        &headers,
+                        1024 * 1024,
+                    ).await;
+                }.await {
+                    Ok(r) => {
+                        found = Some((pair.url.to_string(), r));
+                        break;
+                    },
+                    Err(e) => {
+                        errs.push(e.context_with("Error reaching resolver", ea!(resolver = pair)));
+                    },
+                }
+            }
+            let Some((resolver, resolved)) = found else {
+                return Err(loga::agg_err("Error making requests to any resolver", errs));
+            };
+
+            // Compare
+            let discrepancies = find_discrepancies(&publisher_addrs, &published, &resolved.into_iter().collect());
+            println!("{}", serde_json::to_string_pretty(&json!({
+                "identity": identity,
+                "publishers": publishers.iter().map(|p| p.url.to_string()).collect::<Vec<_>>(),
+                "resolver": resolver,
+                "keys": keys.len(),
+                "discrepancies": discrepancies,
+            })).unwrap());
+            if !discrepancies.is_empty() {
+                return Err(
+                    loga::err_with(
+                        "Resolved records don't match published records",
+                        ea!(identity = identity, count = discrepancies.len()),
+                    ),
+                );
+            }
+        },
+        args::Publish::ImportZone(config) => {
+            let signer =
+                get_identity_signer(config.identity)
+                    .await
+                    .stack_context(log, "Error constructing signer for identity")?;
+            let identity = signer.lock().unwrap().identity().context("Error getting identity")?;
+            let zone =
+                read_to_string(
+                    &config.zonefile,
+                ).context_with("Error reading zone file", ea!(path = config.zonefile.to_string_lossy()))?;
+            let import =
+                import_zone(
+                    &zone,
+                    config.origin.as_deref(),
+                    &identity,
+                ).context_with("Error parsing zone file", ea!(path = config.zonefile.to_string_lossy()))?;
+            if !quiet || config.dry_run.is_some() {
+                println!("{}", serde_json::to_string_pretty(&json!({
+                    "records": import
+                        .records
+                        .iter()
+                        .map(|(k, v)| (join_record_key(k), v))
+                        .collect::<BTreeMap<_, _>>(),
+                    "unsupported": import.unsupported,
+                })).unwrap());
+            }
+            if config.dry_run.is_some() {
+                return Ok(());
+            }
+            if import.records.is_empty() {
+                return Err(log.err("No records in zone file could be converted"));
+            }
+            publish_util::publish(log, &resolvers, &publishers, &signer, PublishArgs {
+                set: import.records,
+                no_lint: config.no_lint.is_some(),
+                ..Default::default()
+            }).await?;
+        },
+        args::Publish::Migrate(config) => {
+            let signer =
+                get_identity_signer(config.identity)
+                    .await
+                    .stack_context(log, "Error constructing signer for identity")?;
+            let old = UrlPair::from(config.from);
+            if publishers.iter().any(|p| p.url == old.url) {
+                return Err(
+                    loga::err_with(
+                        "The old publisher is one of the configured publishers",
+                        ea!(publisher = old.url),
+                    ),
+                );
+            }
+
+            // Read the record set from the old publisher. A watch without a version returns
+            // the current record set right away, and is signed by the identity so it works
+            // even if the old publisher restricts exports.
+            let watch_pair = old.join(format!("{}/v1/watch", API_ROUTE_PUBLISH));
+            let (identity, content) =
+                wire::api::publish::v1::JsonSignature::sign(
+                    &mut *signer.lock().unwrap(),
+                    wire::api::publish::latest::WatchRequestContent {
+                        requested: Utc::now(),
+                        version: None,
+                    },
+                ).stack_context(log, "Failed to sign record set request")?;
+            log.log_with(loga::DEBUG, "Sending watch request (POST)", ea!(url = watch_pair));
+            let resp =
+                htreq::post_json::<wire::api::publish::latest::WatchResponse>(
+                    log,
+                    &mut connect_publisher_node(log, &resolvers, &watch_pair).await?,
+                    &watch_pair.url,
+                    &HashMap::new(),
+                    &wire::api::publish::latest::WatchRequest {
+                        identity: identity,
+                        content: content,
+                    },
+                    10 * 1024 * 1024,
+                )
+                    .await
+                    .context_with("Error reading records from old publisher", ea!(publisher = old.url))?;
+            if resp.values.is_empty() {
+                log.log_with(
+                    loga::WARN,
+                    "The old publisher has no records for the identity",
+                    ea!(publisher = old.url, identity = identity),
+                );
+            }
+            let keys = resp.values.len();
+
+            // Publish and announce on the new publishers
+            publish_util::publish(log, &resolvers, &publishers, &signer, PublishArgs {
+                clear_all: true,
+                set: resp.values.into_iter().collect(),
+                no_lint: true,
+                ..Default::default()
+            }).await?;
+            let announced = publish_util::announce(log, &resolvers, &publishers, &signer).await?;
+
+            // Clean up the old publisher
+            if config.clear_old.is_some() {
+                let clear_pair = old.join(format!("{}/v1/clear_identity", API_ROUTE_PUBLISH));
+                let (identity, challenge) =
+                    wire::api::publish::v1::JsonSignature::sign(
+                        &mut *signer.lock().unwrap(),
+                        (),
+                    ).stack_context(log, "Failed to sign clear identity request")?;
+                log.log_with(loga::DEBUG, "Sending clear identity request (POST)", ea!(url = clear_pair));
+                htreq::post(
+                    log,
+                    &mut connect_publisher_node(log, &resolvers, &clear_pair).await?,
+                    &clear_pair.url,
+                    &HashMap::new(),
+                    serde_json::to_vec(&wire::api::publish::latest::DeleteAnnouncementRequest {
+                        identity: identity,
+                        challenge: challenge,
+                    }).unwrap(),
+                    1024,
+                )
+                    .await
+                    .context_with("Error clearing identity on old publisher", ea!(publisher = old.url))?;
+            }
+            if !quiet {
+                println!("{}", serde_json::to_string_pretty(&json!({
+                    "old_version": resp.version,
+                    "keys": keys,
+                    "announced": announced
+                        .into_iter()
+                        .map(|(publisher, resp)| json!({
+                            "publisher": publisher,
+                            "dht": resp,
+                        }))
+                        .collect::<Vec<_>>(),
+                    "cleared_old": config.clear_old.is_some(),
+                })).unwrap());
+            }
+        },
+        args::Publish::SpoolStatus(config) => {
+            let spool = spool_from_args(config.dir)?;
+            let mut out = vec![];
+            for (path, entry) in spool.list(log).await? {
+                out.push(json!({
+                    "path": path,
+                    "publisher": entry.publisher_url,
+                    "identity": entry.request.identity,
+                    "spooled": entry.spooled,
+                    "attempts": entry.attempts,
+                    "failures": entry.failures,
+                    "last_error": entry.last_error,
+                }));
+            }
+            println!("{}", serde_json::to_string_pretty(&out).unwrap());
+        },
+        args::Publish::SpoolFlush(config) => {
+            let spool = spool_from_args(config.dir)?;
+            let mut delay = Duration::from_secs(10);
+            loop {
+                let flushed = spool.flush(log, &resolvers, None).await?;
+                if !quiet {
+                    println!("{}", serde_json::to_string(&flushed).unwrap());
+                }
+                if flushed.remaining == 0 {
+                    break;
+                }
+                if config.retry.is_none() {
+                    return Err(
+                        loga::err_with("Some publish requests are still queued", ea!(count = flushed.remaining)),
+                    );
+                }
+                log.log_with(
+                    loga::INFO,
+                    "Publish requests still queued, retrying later",
+                    ea!(count = flushed.remaining, delay = delay.as_secs()),
+                );
+                sleep(delay).await;
+                delay = (delay * 2).min(Duration::from_secs(10 * 60));
+            }
+        },
     }
     return Ok(());
 }
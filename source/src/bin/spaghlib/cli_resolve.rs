@@ -1,4 +1,5 @@
 use {
+    super::cli_resolve_cache,
     htwrap::htreq,
     itertools::Itertools,
     loga::{
@@ -6,69 +7,637 @@ use {
         Log,
         ResultContext,
     },
+    serde::Serialize,
+    serde_json::json,
     spaghettinuum::{
+        interface::{
+            stored::{
+                self,
+                identity::Identity,
+                record::{
+                    dns_record::{
+                        build_dns_key,
+                        RecordType,
+                        KEY_SUFFIX_DNS_A,
+                        KEY_SUFFIX_DNS_AAAA,
+                        KEY_SUFFIX_DNS_MX,
+                        KEY_SUFFIX_DNS_TXT,
+                    },
+                    encrypted_record::is_encrypted,
+                    record_utils::{
+                        join_dns_name,
+                        join_record_key,
+                        split_record_key,
+                        RecordKey,
+                        RecordRoot,
+                    },
+                    service_record::{
+                        Services,
+                        KEY_SUFFIX_SERVICES,
+                    },
+                },
+            },
+            wire,
+        },
         resolving::{
             connect_resolver_node,
             default_resolver_url_pairs,
+            resolve,
+            verify_signed_resolve_resp,
+            ResolveRes,
+        },
+        service::resolver::{
+            API_ROUTE_RESOLVE,
+            HEADER_PROVENANCE,
+            HEADER_SIGN,
         },
-        service::resolver::API_ROUTE_RESOLVE,
         ta_res,
+        utils::{
+            identity_secret::load_local_identity_secret,
+            value_encryption::decrypt_data,
+        },
+    },
+    std::{
+        collections::HashMap,
+        str::FromStr,
+        time::{
+            Duration,
+            Instant,
+        },
     },
-    std::collections::HashMap,
 };
 
 pub mod args {
     use {
         aargvark::Aargvark,
+        std::path::PathBuf,
     };
 
+    #[derive(Aargvark)]
+    pub enum OutputFormat {
+        /// The resolver response as JSON (default)
+        Json,
+        /// DNS-equivalent records in zone file syntax. Other keys are skipped.
+        DnsZone,
+    }
+
     #[derive(Aargvark)]
     pub struct Query {
         /// Identity to query
         pub identity: String,
         /// Keys published by the identity, to query
         pub keys: Vec<String>,
+        /// Only output the data for this key (it's added to the query if not in `keys`).
+        /// Fails if there's no value for the key.
+        pub key: Option<String>,
+        /// Output the data without a JSON wrapper. For DNS-equivalent records this is one
+        /// value per line (IP addresses, TXT strings, MX hosts), strings are output as is,
+        /// and other data is output as single-line JSON. Requires `key`.
+        pub raw: Option<()>,
+        pub output_format: Option<OutputFormat>,
+        /// Include where each value came from (the source publisher, when its
+        /// announcement was published, and whether it was cached) in the JSON output.
+        /// Responses with provenance aren't cached.
+        pub provenance: Option<()>,
+        /// Don't use or update the on-disk cache of resolution results.
+        pub no_cache: Option<()>,
+        /// Also look up the DNS-equivalent records (A, AAAA, TXT, MX) in `keys` with
+        /// the system DNS (through the `.s` DNS bridge, if configured) and print how
+        /// the results and timings differ from the resolver API instead of the normal
+        /// output. Looks up the identity's root DNS records if `keys` has none. Fails if
+        /// they differ. The on-disk cache isn't used.
+        pub compare_dns: Option<()>,
+        /// Ask the resolver to sign its response and check it was signed by this identity
+        /// (the resolver node's `identity`) within the last 5 minutes. The resolver must
+        /// have `sign_responses` enabled. The on-disk cache isn't used.
+        pub verify_resolver: Option<String>,
+        /// Don't output anything, just exit with 0 if every key in `keys` (and `key`)
+        /// has a value or 2 if any doesn't, for scripts. Failed lookups exit with 1 or an
+        /// API error code as usual. Requires at least one key.
+        pub exists: Option<()>,
+        /// Decrypt values encrypted to the identity in this local identity file (see
+        /// `spagh publish set --encrypt-to`). Encrypted values for other identities are
+        /// output as is.
+        pub decrypt: Option<PathBuf>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct QueryServices {
+        /// Name to look up services for (ex: `www.IDENT.s`), following delegations
+        pub name: String,
+        /// Don't use or update the on-disk cache of resolution results.
+        pub no_cache: Option<()>,
+    }
+}
+
+fn zone_quote(s: &str) -> String {
+    let mut out = String::new();
+    out.push('"');
+    for c in s.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
     }
+    out.push('"');
+    return out;
+}
+
+/// Returns the DNS record type and rdata strings for a DNS-equivalent record, or
+/// `None` if the key isn't a DNS-equivalent record.
+fn dns_values(key: &RecordKey, data: &serde_json::Value) -> Result<Option<(&'static str, Vec<String>)>, loga::Error> {
+    let Some(suffix) = key.last() else {
+        return Ok(None);
+    };
+    match suffix.as_str() {
+        KEY_SUFFIX_DNS_A => {
+            match serde_json::from_value::<stored::record::dns_record::DnsA>(
+                data.clone(),
+            ).context("A record doesn't match schema")? {
+                stored::record::dns_record::DnsA::V1(v) => {
+                    return Ok(Some(("A", v.0.into_iter().map(|x| x.to_string()).collect())));
+                },
+            }
+        },
+        KEY_SUFFIX_DNS_AAAA => {
+            match serde_json::from_value::<stored::record::dns_record::DnsAaaa>(
+                data.clone(),
+            ).context("AAAA record doesn't match schema")? {
+                stored::record::dns_record::DnsAaaa::V1(v) => {
+                    return Ok(Some(("AAAA", v.0.into_iter().map(|x| x.to_string()).collect())));
+                },
+            }
+        },
+        KEY_SUFFIX_DNS_TXT => {
+            match serde_json::from_value::<stored::record::dns_record::DnsTxt>(
+                data.clone(),
+            ).context("TXT record doesn't match schema")? {
+                stored::record::dns_record::DnsTxt::V1(v) => {
+                    return Ok(Some(("TXT", v.0)));
+                },
+            }
+        },
+        KEY_SUFFIX_DNS_MX => {
+            match serde_json::from_value::<stored::record::dns_record::DnsMx>(
+                data.clone(),
+            ).context("MX record doesn't match schema")? {
+                stored::record::dns_record::DnsMx::V1(v) => {
+                    return Ok(Some(("MX", v.0)));
+                },
+            }
+        },
+        _ => return Ok(None),
+    }
+}
+
+fn print_raw(key: &RecordKey, data: &serde_json::Value) -> Result<(), loga::Error> {
+    if let Some((_, values)) = dns_values(key, data)? {
+        for v in values {
+            println!("{}", v);
+        }
+    } else if let serde_json::Value::String(v) = data {
+        println!("{}", v);
+    } else {
+        println!("{}", serde_json::to_string(data).unwrap());
+    }
+    return Ok(());
+}
+
+fn print_zone(identity: &Identity, resp: &wire::api::resolve::v1::ResolveResp) -> Result<(), loga::Error> {
+    let now = chrono::Utc::now();
+    for (key, value) in resp {
+        let Some(data) = &value.data else {
+            continue;
+        };
+        let Some((record_type, values)) = dns_values(key, data)? else {
+            continue;
+        };
+        let name =
+            join_dns_name(
+                RecordRoot::S(*identity),
+                key[..key.len() - 1].to_vec(),
+            ).context_with("Key can't be represented as a DNS name", ea!(key = join_record_key(key)))?;
+        let ttl = (value.expires - now).num_seconds().max(0);
+        for (i, v) in values.into_iter().enumerate() {
+            let rdata = match record_type {
+                "TXT" => zone_quote(&v),
+                "MX" => format!("{} {}.", i, v.trim_end_matches('.')),
+                _ => v,
+            };
+            println!("{}.\t{}\tIN\t{}\t{}", name, ttl, record_type, rdata);
+        }
+    }
+    return Ok(());
+}
+
+/// The DNS record type of a DNS-equivalent record key.
+fn dns_record_type(key: &RecordKey) -> Option<&'static str> {
+    match key.last()?.as_str() {
+        KEY_SUFFIX_DNS_A => return Some("A"),
+        KEY_SUFFIX_DNS_AAAA => return Some("AAAA"),
+        KEY_SUFFIX_DNS_TXT => return Some("TXT"),
+        KEY_SUFFIX_DNS_MX => return Some("MX"),
+        _ => return None,
+    }
+}
+
+/// How the system DNS and resolver API results for a record compare.
+#[derive(Serialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum DnsComparisonResult {
+    Match,
+    /// Only the API has values - the DNS bridge, or the system DNS setup for `.s`
+    /// names, is likely the problem
+    DnsMissing,
+    /// Only DNS has values - DNS may be answering from a stale cache, or the resolver
+    /// can't reach the publisher
+    ApiMissing,
+    Different,
+}
+
+#[derive(Serialize)]
+struct DnsComparison {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: &'static str,
+    api: Vec<String>,
+    dns: Vec<String>,
+    dns_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dns_error: Option<String>,
+    result: DnsComparisonResult,
+}
+
+/// Look up a record with the system DNS, returning values formatted like
+/// `dns_values`.
+async fn lookup_system_dns(
+    resolver: &hickory_resolver::TokioAsyncResolver,
+    name: &str,
+    record_type: &str,
+) -> Result<Vec<String>, loga::Error> {
+    let lookup_type = match record_type {
+        "A" => hickory_resolver::proto::rr::RecordType::A,
+        "AAAA" => hickory_resolver::proto::rr::RecordType::AAAA,
+        "TXT" => hickory_resolver::proto::rr::RecordType::TXT,
+        _ => hickory_resolver::proto::rr::RecordType::MX,
+    };
+    let lookup = match resolver.lookup(format!("{}.", name), lookup_type).await {
+        Ok(l) => l,
+        Err(e) => match e.kind() {
+            hickory_resolver::error::ResolveErrorKind::NoRecordsFound { .. } => {
+                return Ok(vec![]);
+            },
+            _ => {
+                return Err(e).context("Error looking up record with system DNS");
+            },
+        },
+    };
+    let mut out = vec![];
+    for r in lookup.iter() {
+        match r {
+            hickory_resolver::proto::rr::RData::A(v) => out.push(v.to_string()),
+            hickory_resolver::proto::rr::RData::AAAA(v) => out.push(v.to_string()),
+            hickory_resolver::proto::rr::RData::TXT(v) => out.push(
+                v.txt_data().iter().map(|d| String::from_utf8_lossy(d).to_string()).collect::<String>(),
+            ),
+            hickory_resolver::proto::rr::RData::MX(v) => out.push(v.exchange().to_utf8()),
+            _ => { },
+        }
+    }
+    return Ok(out);
+}
+
+/// Whether every key in `keys` has a value in `resp`, for `--exists`.
+fn all_exist(keys: &[RecordKey], resp: &wire::api::resolve::v1::ResolveResp) -> bool {
+    return keys.iter().all(|k| resp.iter().any(|(k2, v)| k2 == k && v.data.is_some()));
+}
+
+/// Print a comparison of the DNS-equivalent records in `keys` resolved with the
+/// system DNS and with the resolver API (`api`, with how long it took).
+async fn compare_dns(
+    identity: &Identity,
+    keys: &[RecordKey],
+    api: Result<wire::api::resolve::v1::ResolveResp, loga::Error>,
+    api_elapsed: Duration,
+) -> Result<(), loga::Error> {
+    let resolver =
+        hickory_resolver::TokioAsyncResolver::tokio_from_system_conf().context(
+            "Error loading system DNS configuration",
+        )?;
+    let (api, api_error) = match api {
+        Ok(r) => (r.into_iter().collect::<HashMap<_, _>>(), None),
+        Err(e) => (HashMap::new(), Some(e.to_string())),
+    };
+    let mut records = vec![];
+    for key in keys {
+        let Some(record_type) = dns_record_type(key) else {
+            continue;
+        };
+        let name =
+            join_dns_name(
+                RecordRoot::S(*identity),
+                key[..key.len() - 1].to_vec(),
+            ).context_with("Key can't be represented as a DNS name", ea!(key = join_record_key(key)))?;
+        let normalize = |values: Vec<String>| {
+            let mut values = values.into_iter().map(|v| match record_type {
+                "MX" => v.trim_end_matches('.').to_string(),
+                _ => v,
+            }).collect::<Vec<_>>();
+            values.sort();
+            values
+        };
+        let api_values = match api.get(key).and_then(|v| v.data.as_ref()) {
+            Some(data) => normalize(dns_values(key, data)?.map(|(_, v)| v).unwrap_or_default()),
+            None => vec![],
+        };
+        let dns_start = Instant::now();
+        let dns = lookup_system_dns(&resolver, &name, record_type).await;
+        let dns_ms = dns_start.elapsed().as_millis();
+        let (dns_values, dns_error) = match dns {
+            Ok(v) => (normalize(v), None),
+            Err(e) => (vec![], Some(e.to_string())),
+        };
+        let result = if api_values == dns_values {
+            DnsComparisonResult::Match
+        } else if dns_values.is_empty() {
+            DnsComparisonResult::DnsMissing
+        } else if api_values.is_empty() {
+            DnsComparisonResult::ApiMissing
+        } else {
+            DnsComparisonResult::Different
+        };
+        records.push(DnsComparison {
+            name: name,
+            record_type: record_type,
+            api: api_values,
+            dns: dns_values,
+            dns_ms: dns_ms,
+            dns_error: dns_error,
+            result: result,
+        });
+    }
+    let differences = records.iter().filter(|r| r.result != DnsComparisonResult::Match).count();
+    println!("{}", serde_json::to_string_pretty(&json!({
+        "identity": identity,
+        "api_ms": api_elapsed.as_millis(),
+        "api_error": api_error,
+        "records": records,
+    })).unwrap());
+    if api_error.is_some() || differences > 0 {
+        return Err(loga::err_with("System DNS and resolver API results differ", ea!(count = differences)));
+    }
+    return Ok(());
 }
 
 pub async fn run_get(log: &Log, config: args::Query) -> Result<(), loga::Error> {
+    if config.raw.is_some() && config.key.is_none() {
+        return Err(loga::err("`raw` requires `key`"));
+    }
+    if config.exists.is_some() {
+        if config.keys.is_empty() && config.key.is_none() {
+            return Err(loga::err("`exists` requires at least one key"));
+        }
+        if config.raw.is_some() || config.output_format.is_some() || config.compare_dns.is_some() {
+            return Err(loga::err("`exists` can't be combined with other output options"));
+        }
+    }
+    let mut keys = config.keys.iter().map(|k| split_record_key(k)).collect::<Vec<_>>();
+    let select_key = config.key.as_ref().map(|k| split_record_key(k));
+    if let Some(k) = &select_key {
+        if !keys.contains(k) {
+            keys.push(k.clone());
+        }
+    }
+    let compare = config.compare_dns.is_some();
+    if compare && !keys.iter().any(|k| dns_record_type(k).is_some()) {
+        for t in [RecordType::A, RecordType::Aaaa, RecordType::Txt, RecordType::Mx] {
+            keys.push(build_dns_key(vec![], t));
+        }
+    }
+    let verify_resolver = match &config.verify_resolver {
+        Some(r) => Some(Identity::from_str(r).context("Invalid resolver identity")?),
+        None => None,
+    };
+    let mut headers = HashMap::new();
+    if verify_resolver.is_some() {
+        headers.insert(HEADER_SIGN.to_string(), "1".to_string());
+    }
+    let cache;
+    if config.provenance.is_some() {
+        headers.insert(HEADER_PROVENANCE.to_string(), "1".to_string());
+        cache = None;
+    } else if compare || verify_resolver.is_some() {
+        cache = None;
+    } else {
+        cache = cli_resolve_cache(&config.no_cache);
+    }
+    let api_start = Instant::now();
     let mut errs = vec![];
+    let mut body = None;
+    if let Some(cache) = &cache {
+        if let Some(resp) = cache.get(log, &config.identity, &keys).await {
+            body = Some(serde_json::to_vec(&resp).unwrap());
+        }
+    }
     for pair in default_resolver_url_pairs(log)? {
+        if body.is_some() {
+            break;
+        }
         match async {
-            ta_res!(());
+            ta_res!(Vec < u8 >);
             let pair =
                 pair.join(
                     format!(
                         "{}/v1/{}?{}",
                         API_ROUTE_RESOLVE,
                         config.identity,
-                        config.keys.iter().map(|k| urlencoding::encode(k)).join(",")
+                        keys.iter().map(|k| urlencoding::encode(&join_record_key(k)).to_string()).join(",")
                     ),
                 );
             log.log_with(loga::DEBUG, "Sending query request", ea!(url = pair));
+            let body =
+                htreq::get(log, &mut connect_resolver_node(&pair).await?, &pair.url, &headers, 1024 * 1024).await?;
+            let Some(resolver) = &verify_resolver else {
+                return Ok(body);
+            };
+            let values =
+                verify_signed_resolve_resp(
+                    &serde_json::from_slice::<wire::api::resolve::v1::SignedResolveResp>(
+                        &body,
+                    ).context("Signed response could not be parsed as JSON")?,
+                    resolver,
+                    &Identity::from_str(&config.identity).context("Invalid identity")?,
+                    &keys,
+                    chrono::Duration::try_minutes(5).unwrap(),
+                )?;
+            return Ok(serde_json::to_vec(&values).unwrap());
+        }.await {
+            Ok(b) => {
+                if let Some(cache) = &cache {
+                    if let Ok(resp) = serde_json::from_slice::<wire::api::resolve::v1::ResolveResp>(&b) {
+                        cache.put(log, &config.identity, &keys, &resp).await;
+                    }
+                }
+                body = Some(b);
+                break;
+            },
+            Err(e) => {
+                errs.push(e.context_with("Error reaching resolver", ea!(resolver = pair)));
+            },
+        }
+    }
+    if compare {
+        let api_elapsed = api_start.elapsed();
+        let identity = Identity::from_str(&config.identity).context("Invalid identity")?;
+        let api = match body {
+            Some(body) => serde_json::from_slice::<wire::api::resolve::v1::ResolveResp>(
+                &body,
+            ).context("Response could not be parsed as JSON"),
+            None => Err(loga::agg_err("Error making requests to any resolver", errs)),
+        };
+        return compare_dns(&identity, &keys, api, api_elapsed).await;
+    }
+    let Some(mut body) = body else {
+        return Err(loga::agg_err("Error making requests to any resolver", errs));
+    };
+    if let Some(path) = &config.decrypt {
+        let secret = load_local_identity_secret(path).await?;
+        let mut resp =
+            serde_json::from_slice::<wire::api::resolve::v1::ResolveResp>(
+                &body,
+            ).stack_context(log, "Response could not be parsed as JSON")?;
+        for (k, v) in &mut resp {
+            let Some(data) = &v.data else {
+                continue;
+            };
+            if !is_encrypted(data) {
+                continue;
+            }
+            match decrypt_data(&secret, data).context_with("Error decrypting value", ea!(key = join_record_key(k)))? {
+                Some(data) => v.data = Some(data),
+                None => {
+                    log.log_with(
+                        loga::WARN,
+                        "Value isn't encrypted to the identity, leaving it encrypted",
+                        ea!(key = join_record_key(k)),
+                    );
+                },
+            }
+        }
+        body = serde_json::to_vec(&resp).unwrap();
+    }
+    if config.exists.is_some() {
+        let resp =
+            serde_json::from_slice::<wire::api::resolve::v1::ResolveResp>(
+                &body,
+            ).stack_context(log, "Response could not be parsed as JSON")?;
+        if !all_exist(&keys, &resp) {
+            std::process::exit(crate::EXIT_ABSENT);
+        }
+        return Ok(());
+    }
+    match (select_key, config.output_format.unwrap_or(args::OutputFormat::Json)) {
+        (None, args::OutputFormat::Json) => {
             println!(
                 "{}",
                 serde_json::to_string_pretty(
                     &serde_json::from_slice::<serde_json::Value>(
-                        &htreq::get(
-                            log,
-                            &mut connect_resolver_node(&pair).await?,
-                            &pair.url,
-                            &HashMap::new(),
-                            1024 * 1024,
-                        ).await?,
+                        &body,
                     ).stack_context(log, "Response could not be parsed as JSON")?,
                 ).unwrap()
             );
-            return Ok(());
-        }.await {
-            Ok(_) => {
-                return Ok(());
-            },
-            Err(e) => {
-                errs.push(e.context_with("Error reaching resolver", ea!(resolver = pair)));
+        },
+        (select_key, format) => {
+            let mut resp =
+                serde_json::from_slice::<wire::api::resolve::v1::ResolveResp>(
+                    &body,
+                ).stack_context(log, "Response could not be parsed as JSON")?;
+            if let Some(select_key) = &select_key {
+                resp.retain(|(k, v)| k == select_key && v.data.is_some());
+                if resp.is_empty() {
+                    return Err(loga::err_with("No value for key", ea!(key = join_record_key(select_key))));
+                }
+            }
+            match format {
+                args::OutputFormat::Json => {
+                    let (key, value) = resp.remove(0);
+                    let data = value.data.unwrap();
+                    if config.raw.is_some() {
+                        print_raw(&key, &data)?;
+                    } else {
+                        println!("{}", serde_json::to_string_pretty(&data).unwrap());
+                    }
+                },
+                args::OutputFormat::DnsZone => {
+                    let identity = Identity::from_str(&config.identity).context("Invalid identity")?;
+                    print_zone(&identity, &resp)?;
+                },
+            }
+        },
+    }
+    return Ok(());
+}
+
+pub async fn run_get_services(log: &Log, config: args::QueryServices) -> Result<(), loga::Error> {
+    let ResolveRes { ips, additional: additional_records, .. } =
+        resolve(
+            log,
+            &default_resolver_url_pairs(log)?,
+            cli_resolve_cache(&config.no_cache).as_ref(),
+            &config.name,
+            &[vec![KEY_SUFFIX_SERVICES.to_string()]],
+        ).await.context("Error resolving name")?;
+    let mut services = vec![];
+    for (k, v) in additional_records {
+        if k.last().map(|x| x.as_str()) != Some(KEY_SUFFIX_SERVICES) {
+            continue;
+        }
+        let Some(data) = v.data else {
+            continue;
+        };
+        match serde_json::from_value::<Services>(data).context("Services record doesn't match schema")? {
+            Services::V1(v) => {
+                services.extend(v.0);
             },
         }
     }
-    return Err(loga::agg_err("Error making requests to any resolver", errs));
+    services.sort_by(|a, b| a.priority.cmp(&b.priority).then(b.weight.cmp(&a.weight)));
+    println!("{}", serde_json::to_string_pretty(&json!({
+        "ipv4s": ips.ipv4s,
+        "ipv6s": ips.ipv6s,
+        "services": services,
+    })).unwrap());
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test_all_exist {
+    use {
+        super::all_exist,
+        chrono::Utc,
+        serde_json::json,
+        spaghettinuum::interface::wire,
+    };
+
+    fn value(data: Option<serde_json::Value>) -> wire::resolve::v1::ResolveValue {
+        return wire::resolve::v1::ResolveValue {
+            expires: Utc::now(),
+            data: data,
+            published: None,
+            provenance: None,
+            external: None,
+        };
+    }
+
+    #[test]
+    fn test_all_exist() {
+        let a = vec!["dns".to_string(), "a".to_string()];
+        let txt = vec!["dns".to_string(), "txt".to_string()];
+        let resp = vec![(a.clone(), value(Some(json!(["192.0.2.1"])))), (txt.clone(), value(None))];
+        assert!(all_exist(&[a.clone()], &resp));
+
+        // Missing value or missing from the response
+        assert!(!all_exist(&[a.clone(), txt.clone()], &resp));
+        assert!(!all_exist(&[vec!["dns".to_string(), "mx".to_string()]], &resp));
+    }
 }
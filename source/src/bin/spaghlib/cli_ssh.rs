@@ -1,4 +1,5 @@
 use {
+    super::CliResolveVia,
     loga::{
         ea,
         DebugDisplay,
@@ -12,28 +13,52 @@ use {
         Pty,
     },
     russh_sftp::client::SftpSession,
-    spaghettinuum::utils::ssh_util::{
-        download,
-        quote,
-        run_command,
-        ssh_connect,
-        upload,
-        SshConn,
-        SshConnectHandler,
+    spaghettinuum::{
+        interface::config::shared::IdentitySecretArg,
+        utils::{
+            identity_secret::{
+                get_identity_signer,
+                IdentitySigner,
+            },
+            ssh_agent::{
+                agent_identity,
+                serve_agent,
+                ssh_authorized_key,
+            },
+            ssh_util::{
+                download,
+                quote,
+                run_command,
+                ssh_connect,
+                upload,
+                SshConn,
+                SshConnectHandler,
+            },
+        },
     },
     std::{
         env,
         path::PathBuf,
+        process,
+        sync::{
+            Arc,
+            Mutex,
+        },
     },
     termion::raw::IntoRawMode,
     tokio::{
-        fs::create_dir_all,
+        fs::{
+            create_dir_all,
+            remove_file,
+        },
         io::{
             stdin,
             stdout,
             AsyncReadExt,
             AsyncWriteExt,
         },
+        select,
+        signal::ctrl_c,
     },
 };
 
@@ -42,6 +67,8 @@ pub mod args {
         aargvark::{
             Aargvark,
         },
+        http::Uri,
+        spaghettinuum::interface::config::shared::IdentitySecretArg,
         std::path::PathBuf,
     };
 
@@ -57,7 +84,21 @@ pub mod args {
         /// Use a specific key file instead of whatever's automatically detected.
         #[vark(flag = "-i", flag = "--keyfile")]
         pub key: Option<PathBuf>,
+        /// Authenticate as this identity (its ed25519 key) instead of with SSH keys.
+        pub identity: Option<IdentitySecretArg>,
         pub command: Option<Vec<String>>,
+        /// Don't use or update the on-disk cache of resolution results.
+        pub no_cache: Option<()>,
+        /// Resolve with this resolver instead of the system resolvers, as `IP=URL` or a
+        /// URL with an IP address host.
+        pub resolver: Option<String>,
+        /// Skip resolvers and the DHT and get the host's records directly from the
+        /// publisher with this API URL. The identity's announcement must list the
+        /// publisher.
+        pub publisher: Option<Uri>,
+        /// The identity whose records to get from `--publisher`, if not the host's
+        /// identity.
+        pub publisher_identity: Option<String>,
     }
 
     #[derive(Aargvark)]
@@ -84,6 +125,8 @@ pub mod args {
         /// Use a specific key file instead of whatever's automatically detected.
         #[vark(flag = "-i", flag = "--keyfile")]
         pub key: Option<PathBuf>,
+        /// Authenticate as this identity (its ed25519 key) instead of with SSH keys.
+        pub identity: Option<IdentitySecretArg>,
         /// Absolute path to a file or directory to download.
         pub remote: PathBuf,
         pub preposition: Preposition,
@@ -97,6 +140,18 @@ pub mod args {
         /// When transfering a directory, delete files in the destination that aren't in
         /// the source so that the directory contents are equal afterwards.
         pub sync: Option<()>,
+        /// Don't use or update the on-disk cache of resolution results.
+        pub no_cache: Option<()>,
+        /// Resolve with this resolver instead of the system resolvers, as `IP=URL` or a
+        /// URL with an IP address host.
+        pub resolver: Option<String>,
+        /// Skip resolvers and the DHT and get the host's records directly from the
+        /// publisher with this API URL. The identity's announcement must list the
+        /// publisher.
+        pub publisher: Option<Uri>,
+        /// The identity whose records to get from `--publisher`, if not the host's
+        /// identity.
+        pub publisher_identity: Option<String>,
     }
 
     #[derive(Aargvark)]
@@ -111,6 +166,8 @@ pub mod args {
         /// Use a specific key file instead of whatever's automatically detected.
         #[vark(flag = "-i", flag = "--keyfile")]
         pub key: Option<PathBuf>,
+        /// Authenticate as this identity (its ed25519 key) instead of with SSH keys.
+        pub identity: Option<IdentitySecretArg>,
         /// Path to a file or directory to upload.
         pub local: PathBuf,
         pub preposition: Preposition,
@@ -124,6 +181,26 @@ pub mod args {
         /// When transfering a directory, delete files in the destination that aren't in
         /// the source so that the directory contents are equal afterwards.
         pub sync: Option<()>,
+        /// Don't use or update the on-disk cache of resolution results.
+        pub no_cache: Option<()>,
+        /// Resolve with this resolver instead of the system resolvers, as `IP=URL` or a
+        /// URL with an IP address host.
+        pub resolver: Option<String>,
+        /// Skip resolvers and the DHT and get the host's records directly from the
+        /// publisher with this API URL. The identity's announcement must list the
+        /// publisher.
+        pub publisher: Option<Uri>,
+        /// The identity whose records to get from `--publisher`, if not the host's
+        /// identity.
+        pub publisher_identity: Option<String>,
+    }
+
+    #[derive(Aargvark)]
+    pub struct SshAgent {
+        pub identity: IdentitySecretArg,
+        /// Path of the agent socket. Defaults to `spagh-ssh-agent.sock` in
+        /// `$XDG_RUNTIME_DIR`, or a file in the temp directory.
+        pub socket: Option<PathBuf>,
     }
 
     #[derive(Aargvark)]
@@ -131,12 +208,29 @@ pub mod args {
         Shell(SshShell),
         Download(SshDownload),
         Upload(SshUpload),
+        /// Run an ssh-agent offering the identity as an SSH user key, for use with
+        /// OpenSSH (`ssh`, `scp`, etc). The agent only signs SSH authentication
+        /// requests, so it's safe to forward.
+        Agent(SshAgent),
     }
 }
 
+async fn get_ssh_identity(
+    log: &Log,
+    identity: Option<IdentitySecretArg>,
+) -> Result<Option<Arc<Mutex<dyn IdentitySigner>>>, loga::Error> {
+    let Some(identity) = identity else {
+        return Ok(None);
+    };
+    return Ok(
+        Some(get_identity_signer(identity).await.stack_context(log, "Error constructing signer for identity")?),
+    );
+}
+
 pub async fn run(log: &Log, config: args::Ssh) -> Result<(), loga::Error> {
     match config {
-        args::Ssh::Shell(config) => {
+        args::Ssh::Shell(mut config) => {
+            let identity = get_ssh_identity(log, config.identity.take()).await?;
             struct Inner(args::SshShell);
 
             impl SshConnectHandler for Inner {
@@ -213,16 +307,24 @@ pub async fn run(log: &Log, config: args::Ssh) -> Result<(), loga::Error> {
                 }
             }
 
+            let host = format!("{}.s", config.host);
+            let via =
+                CliResolveVia::new(log, &config.resolver, &config.publisher, &config.publisher_identity, &host).await?;
+            let cache = via.cache(&config.no_cache);
             ssh_connect(
                 log,
+                via.via(),
+                cache.as_ref(),
                 config.user.clone(),
-                format!("{}.s", config.host),
+                host,
                 config.port,
                 config.key.clone(),
+                identity,
                 Inner(config),
             ).await?;
         },
-        args::Ssh::Download(config) => {
+        args::Ssh::Download(mut config) => {
+            let identity = get_ssh_identity(log, config.identity.take()).await?;
             struct Inner(args::SshDownload);
 
             impl SshConnectHandler for Inner {
@@ -292,16 +394,24 @@ pub async fn run(log: &Log, config: args::Ssh) -> Result<(), loga::Error> {
                 }
             }
 
+            let host = format!("{}.s", config.host);
+            let via =
+                CliResolveVia::new(log, &config.resolver, &config.publisher, &config.publisher_identity, &host).await?;
+            let cache = via.cache(&config.no_cache);
             ssh_connect(
                 log,
+                via.via(),
+                cache.as_ref(),
                 config.user.clone(),
-                format!("{}.s", config.host),
+                host,
                 config.port,
                 config.key.clone(),
+                identity,
                 Inner(config),
             ).await?;
         },
-        args::Ssh::Upload(config) => {
+        args::Ssh::Upload(mut config) => {
+            let identity = get_ssh_identity(log, config.identity.take()).await?;
             struct Inner(args::SshUpload);
 
             impl SshConnectHandler for Inner {
@@ -377,15 +487,54 @@ pub async fn run(log: &Log, config: args::Ssh) -> Result<(), loga::Error> {
                 }
             }
 
+            let host = format!("{}.s", config.host);
+            let via =
+                CliResolveVia::new(log, &config.resolver, &config.publisher, &config.publisher_identity, &host).await?;
+            let cache = via.cache(&config.no_cache);
             ssh_connect(
                 log,
+                via.via(),
+                cache.as_ref(),
                 config.user.clone(),
-                format!("{}.s", config.host),
+                host,
                 config.port,
                 config.key.clone(),
+                identity,
                 Inner(config),
             ).await?;
         },
+        args::Ssh::Agent(config) => {
+            let signer =
+                get_identity_signer(config.identity)
+                    .await
+                    .stack_context(log, "Error constructing signer for identity")?;
+            let identity = agent_identity(&signer).await?;
+            let socket = match config.socket {
+                Some(s) => s,
+                None => match env::var_os("XDG_RUNTIME_DIR") {
+                    Some(d) => PathBuf::from(d).join("spagh-ssh-agent.sock"),
+                    None => env::temp_dir().join(format!("spagh-ssh-agent-{}.sock", process::id())),
+                },
+            };
+            let socket = socket.absolutize().context("Error making agent socket path absolute")?.to_path_buf();
+            if socket.exists() {
+                return Err(
+                    loga::err_with(
+                        "Agent socket already exists, remove it if no agent is using it",
+                        ea!(path = socket.to_string_lossy()),
+                    ),
+                );
+            }
+            eprintln!("Add this line to `authorized_keys` on hosts to allow the identity to log in:");
+            eprintln!("{}", ssh_authorized_key(&identity));
+            println!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", shell_escape::escape(socket.to_string_lossy()));
+            let res = select!{
+                r = serve_agent(log, signer, identity, &socket) => r,
+                _ = ctrl_c() => Ok(()),
+            };
+            remove_file(&socket).await.log(log, loga::WARN, "Error removing agent socket");
+            res?;
+        },
     }
     return Ok(());
 }
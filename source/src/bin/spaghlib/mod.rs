@@ -1,6 +1,121 @@
+use {
+    http::Uri,
+    loga::{
+        ea,
+        Log,
+        ResultContext,
+    },
+    spaghettinuum::{
+        interface::stored::{
+            identity::Identity,
+            record::record_utils::{
+                split_dns_name,
+                RecordRoot,
+            },
+        },
+        resolving::{
+            cache::ResolveCache,
+            default_resolver_url_pairs,
+            parse_resolver_url_pair,
+            DirectPublisher,
+            ResolveVia,
+            UrlPair,
+        },
+    },
+    std::str::FromStr,
+};
+
 pub mod cli_http;
 pub mod cli_ssh;
 pub mod cli_admin;
 pub mod cli_publish;
 pub mod cli_resolve;
 pub mod cli_identity;
+pub mod cli_daemon;
+
+/// The resolve cache to use for a command, unless disabled with `--no-cache`.
+pub fn cli_resolve_cache(no_cache: &Option<()>) -> Option<ResolveCache> {
+    if no_cache.is_some() {
+        return None;
+    }
+    return ResolveCache::user_default();
+}
+
+/// Where a command gets values to resolve names: the system resolvers, a resolver
+/// given with `--resolver`, or a publisher given with `--publisher`.
+pub enum CliResolveVia {
+    System(Vec<UrlPair>),
+    Resolver(Vec<UrlPair>),
+    Publisher(DirectPublisher),
+}
+
+impl CliResolveVia {
+    /// `identity` is the identity for `--publisher`; if missing it's taken from
+    /// `name` (the name being resolved).
+    pub async fn new(
+        log: &Log,
+        resolver: &Option<String>,
+        publisher: &Option<Uri>,
+        identity: &Option<String>,
+        name: &str,
+    ) -> Result<Self, loga::Error> {
+        let resolvers = match resolver {
+            Some(r) => Some(vec![parse_resolver_url_pair(r)?]),
+            None => None,
+        };
+        let Some(publisher) = publisher else {
+            if identity.is_some() {
+                return Err(loga::err("The publisher identity is only used with --publisher"));
+            }
+            return match resolvers {
+                Some(r) => Ok(Self::Resolver(r)),
+                None => Ok(Self::System(default_resolver_url_pairs(log)?)),
+            };
+        };
+        let identity = match identity {
+            Some(i) => Identity::from_str(i).context_with("Invalid publisher identity", ea!(identity = i))?,
+            None => {
+                let dns_name =
+                    hickory_resolver::Name::from_str(name).context_with("Invalid name", ea!(name = name))?;
+                let RecordRoot::S(i) = split_dns_name(dns_name)?.0 else {
+                    return Err(
+                        loga::err_with(
+                            "Name isn't a .s name, specify the identity to use with --publisher",
+                            ea!(name = name),
+                        ),
+                    );
+                };
+                i
+            },
+        };
+
+        // The resolver is only needed to reach a publisher API at a `.s` name
+        return Ok(
+            Self::Publisher(
+                DirectPublisher::connect(
+                    log,
+                    &resolvers.unwrap_or_default(),
+                    &UrlPair::from(publisher.clone()),
+                    &identity,
+                ).await?,
+            ),
+        );
+    }
+
+    pub fn via(&self) -> ResolveVia<'_> {
+        match self {
+            CliResolveVia::System(r) => return ResolveVia::Resolvers(r),
+            CliResolveVia::Resolver(r) => return ResolveVia::Resolvers(r),
+            CliResolveVia::Publisher(p) => return ResolveVia::Publisher(p),
+        }
+    }
+
+    /// The resolve cache to use, see `cli_resolve_cache`. The cache isn't used with
+    /// `--resolver` or `--publisher` so results always come from them.
+    pub fn cache(&self, no_cache: &Option<()>) -> Option<ResolveCache> {
+        match self {
+            CliResolveVia::System(_) => return cli_resolve_cache(no_cache),
+            CliResolveVia::Resolver(_) | CliResolveVia::Publisher(_) => return None,
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! A typed client for resolving and publishing records through spaghettinuum
+//! nodes, for programs using spaghettinuum as a library.
+//!
+//! Records are read and written as the latest version of each record type (ex:
+//! `dns_record::latest::DnsA`); the versioned stored and wire formats and the
+//! requests to resolvers and publishers are handled here.
+//!
+//! ```ignore
+//! let client = Client::from_system(&log)?;
+//! let a = client.resolve::<DnsA>(&identity, &["www"]).await?;
+//! let mut changes = Changes::default();
+//! changes.set(&["www"], 60, DnsA(vec![ip]));
+//! client.publish(&signer, changes).await?;
+//! ```
+use {
+    crate::{
+        interface::{
+            stored::{
+                identity::Identity,
+                record::{
+                    self,
+                    addr_pref_record::{
+                        self,
+                        AddrPref,
+                    },
+                    alias_record::{
+                        self,
+                        Alias,
+                    },
+                    delegate_record::{
+                        self,
+                        Delegate,
+                    },
+                    dns_record::{
+                        self,
+                        DnsA,
+                        DnsAaaa,
+                        DnsMx,
+                        DnsTxt,
+                    },
+                    handoff_record::{
+                        self,
+                        Handoff,
+                    },
+                    key_alias_record::{
+                        self,
+                        KeyAlias,
+                    },
+                    record_utils::{
+                        join_query_record_keys,
+                        RecordKey,
+                    },
+                    service_record::{
+                        self,
+                        Services,
+                    },
+                    ssh_record::{
+                        self,
+                        SshHostKeys,
+                    },
+                    succession_record::{
+                        self,
+                        Succession,
+                    },
+                    tls_record::{
+                        self,
+                        TlsCerts,
+                    },
+                },
+            },
+            wire,
+        },
+        publishing::system_publisher_url_pairs,
+        resolving::{
+            self,
+            connect_resolver_node,
+            default_resolver_url_pairs,
+            UrlPair,
+        },
+        service::resolver::API_ROUTE_RESOLVE,
+        ta_res,
+        utils::{
+            identity_secret::IdentitySigner,
+            publish_util::{
+                self,
+                PublishArgs,
+            },
+        },
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    htwrap::{
+        htreq,
+        url::UriJoin,
+    },
+    loga::{
+        ea,
+        Log,
+        ResultContext,
+    },
+    std::{
+        collections::HashMap,
+        sync::{
+            Arc,
+            Mutex,
+        },
+    },
+};
+
+/// A record type that can be resolved and published. Implemented for the latest
+/// version of each record type.
+pub trait Record: Sized {
+    /// The last segment of keys for records of this type
+    const KEY_SUFFIX: &'static str;
+
+    fn from_data(data: serde_json::Value) -> Result<Self, loga::Error>;
+    fn to_data(self) -> serde_json::Value;
+}
+
+macro_rules! versioned_record{
+    ($latest: ty, $versioned: ident, $suffix: expr) => {
+        impl Record for $latest {
+            const KEY_SUFFIX: &'static str = $suffix;
+
+            fn from_data(data: serde_json::Value) -> Result<Self, loga::Error> {
+                match serde_json::from_value::<$versioned>(
+                    data,
+                ).context_with("Record doesn't match schema", ea!(suffix = $suffix))? {
+                    $versioned::V1(v) => return Ok(v),
+                }
+            }
+
+            fn to_data(self) -> serde_json::Value {
+                return serde_json::to_value(&$versioned::V1(self)).unwrap();
+            }
+        }
+    };
+}
+
+versioned_record!(dns_record::latest::DnsA, DnsA, dns_record::KEY_SUFFIX_DNS_A);
+versioned_record!(dns_record::latest::DnsAaaa, DnsAaaa, dns_record::KEY_SUFFIX_DNS_AAAA);
+versioned_record!(dns_record::latest::DnsTxt, DnsTxt, dns_record::KEY_SUFFIX_DNS_TXT);
+versioned_record!(dns_record::latest::DnsMx, DnsMx, dns_record::KEY_SUFFIX_DNS_MX);
+versioned_record!(tls_record::latest::TlsCerts, TlsCerts, tls_record::KEY_SUFFIX_TLS);
+versioned_record!(ssh_record::latest::SshHostKeys, SshHostKeys, ssh_record::KEY_SUFFIX_SSH_HOSTKEYS);
+versioned_record!(delegate_record::latest::Delegate, Delegate, delegate_record::KEY_SUFFIX_DELEGATE);
+versioned_record!(service_record::latest::Services, Services, service_record::KEY_SUFFIX_SERVICES);
+versioned_record!(succession_record::latest::Succession, Succession, succession_record::KEY_SUFFIX_SUCCESSION);
+versioned_record!(addr_pref_record::latest::AddrPref, AddrPref, addr_pref_record::KEY_SUFFIX_ADDR_PREF);
+versioned_record!(alias_record::latest::Alias, Alias, alias_record::KEY_SUFFIX_ALIAS);
+versioned_record!(handoff_record::latest::Handoff, Handoff, handoff_record::KEY_SUFFIX_HANDOFF);
+versioned_record!(key_alias_record::latest::KeyAlias, KeyAlias, key_alias_record::KEY_SUFFIX_KEY_ALIAS);
+
+/// The key for a record of type `R` under a path (ex: `["www"]`, or empty for the
+/// identity root).
+pub fn record_key<R: Record>(path: &[impl AsRef<str>]) -> RecordKey {
+    let mut out = path.iter().map(|s| s.as_ref().to_string()).collect::<RecordKey>();
+    out.push(R::KEY_SUFFIX.to_string());
+    return out;
+}
+
+/// Values returned by a resolver for a set of keys.
+pub struct Resolved(wire::api::resolve::v1::ResolveKeyValues);
+
+impl Resolved {
+    /// The record of type `R` under the path, or `None` if the identity doesn't
+    /// publish one or it wasn't requested. Fails if the record doesn't match the
+    /// type's schema.
+    pub fn get<R: Record>(&self, path: &[impl AsRef<str>]) -> Result<Option<R>, loga::Error> {
+        let Some(data) = self.0.get(&record_key::<R>(path)).and_then(|v| v.data.clone()) else {
+            return Ok(None);
+        };
+        return Ok(Some(R::from_data(data)?));
+    }
+
+    /// When the value for a key can no longer be reused, if it was requested.
+    pub fn expires(&self, key: &RecordKey) -> Option<DateTime<Utc>> {
+        return self.0.get(key).map(|v| v.expires);
+    }
+
+    /// The raw values by key.
+    pub fn into_values(self) -> HashMap<RecordKey, Option<serde_json::Value>> {
+        return self.0.into_iter().map(|(k, v)| (k, v.data)).collect();
+    }
+}
+
+/// Record changes to publish for an identity.
+#[derive(Default)]
+pub struct Changes(PublishArgs);
+
+impl Changes {
+    /// Start publishing `value` under the path, cacheable for `ttl` minutes.
+    pub fn set<R: Record>(&mut self, path: &[impl AsRef<str>], ttl: i32, value: R) {
+        self.0.set.insert(record_key::<R>(path), record::RecordValue::latest(record::latest::RecordValue {
+            ttl: ttl,
+            data: Some(value.to_data()),
+        }));
+    }
+
+    /// Stop publishing the record of type `R` under the path.
+    pub fn clear<R: Record>(&mut self, path: &[impl AsRef<str>]) {
+        self.0.clear.insert(record_key::<R>(path));
+    }
+
+    /// Stop publishing all other records, so only the records `set` here remain.
+    pub fn clear_all(&mut self) {
+        self.0.clear_all = true;
+    }
+
+    /// Only apply the changes if the identity's published record set version still
+    /// matches this.
+    pub fn if_version(&mut self, version: String) {
+        self.0.if_version = Some(version);
+    }
+}
+
+pub struct Client {
+    log: Log,
+    resolvers: Vec<UrlPair>,
+    publishers: Vec<UrlPair>,
+}
+
+impl Client {
+    pub fn new(log: &Log, resolvers: Vec<UrlPair>, publishers: Vec<UrlPair>) -> Client {
+        return Client {
+            log: log.clone(),
+            resolvers: resolvers,
+            publishers: publishers,
+        };
+    }
+
+    /// Use the resolvers and publishers configured for the system, the same as the
+    /// `spagh` CLI (`SPAGH_RESOLVERS` or the system DNS configuration, and
+    /// `SPAGH_PUBLISHERS` or the resolvers).
+    pub fn from_system(log: &Log) -> Result<Client, loga::Error> {
+        return Ok(Client::new(log, default_resolver_url_pairs(log)?, system_publisher_url_pairs(log)?));
+    }
+
+    /// Get values for keys published by an identity, trying each resolver in turn.
+    /// Delegations and successions aren't followed.
+    pub async fn resolve_keys(&self, identity: &Identity, keys: &[RecordKey]) -> Result<Resolved, loga::Error> {
+        let path = format!("{}/v1/{}?{}", API_ROUTE_RESOLVE, identity, join_query_record_keys(keys));
+        let mut errs = vec![];
+        for resolver in &self.resolvers {
+            match async {
+                ta_res!(wire::api::resolve::v1::ResolveResp);
+                return htreq::get_json::<wire::api::resolve::v1::ResolveResp>(
+                    &self.log,
+                    &mut connect_resolver_node(resolver).await?,
+                    &resolver.url.join(&path),
+                    &HashMap::new(),
+                    1024 * 1024,
+                ).await;
+            }.await {
+                Ok(r) => return Ok(Resolved(r.into_iter().collect())),
+                Err(e) => {
+                    errs.push(e.context_with("Error reaching resolver", ea!(resolver = resolver)));
+                },
+            }
+        }
+        return Err(loga::agg_err("Error making requests to any resolver", errs));
+    }
+
+    /// Get the record of type `R` published by an identity under a path.
+    pub async fn resolve<R: Record>(
+        &self,
+        identity: &Identity,
+        path: &[impl AsRef<str>],
+    ) -> Result<Option<R>, loga::Error> {
+        return self.resolve_keys(identity, &[record_key::<R>(path)]).await?.get::<R>(path);
+    }
+
+    /// Get the record of type `R` for a DNS name like `www.IDENTITY.s`, following
+    /// delegations and identity successions.
+    pub async fn resolve_name<R: Record>(&self, name: &str) -> Result<Option<R>, loga::Error> {
+        let key = vec![R::KEY_SUFFIX.to_string()];
+        let res = resolving::resolve(&self.log, &self.resolvers, None, name, std::slice::from_ref(&key)).await?;
+        let Some(data) = res.additional.get(&key).and_then(|v| v.data.clone()) else {
+            return Ok(None);
+        };
+        return Ok(Some(R::from_data(data)?));
+    }
+
+    /// Announce the configured publishers as authoritative for the signer's identity.
+    /// This is needed before published records can be resolved, and periodically
+    /// after.
+    pub async fn announce(&self, identity_signer: &Arc<Mutex<dyn IdentitySigner>>) -> Result<(), loga::Error> {
+        publish_util::announce(&self.log, &self.resolvers, &self.publishers, identity_signer).await?;
+        return Ok(());
+    }
+
+    /// Publish changes to the signer's records on each configured publisher.
+    pub async fn publish(
+        &self,
+        identity_signer: &Arc<Mutex<dyn IdentitySigner>>,
+        changes: Changes,
+    ) -> Result<(), loga::Error> {
+        publish_util::publish(&self.log, &self.resolvers, &self.publishers, identity_signer, changes.0).await?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test_client {
+    use {
+        super::{
+            record_key,
+            Changes,
+            Record,
+        },
+        crate::interface::stored::record::{
+            dns_record::{
+                latest::DnsA,
+                KEY_SUFFIX_DNS_A,
+            },
+            RecordValue,
+        },
+        std::net::Ipv4Addr,
+    };
+
+    #[test]
+    fn test_key() {
+        assert_eq!(record_key::<DnsA>(&["www"]), vec!["www".to_string(), KEY_SUFFIX_DNS_A.to_string()]);
+        assert_eq!(record_key::<DnsA>(&[] as &[&str]), vec![KEY_SUFFIX_DNS_A.to_string()]);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut changes = Changes::default();
+        changes.set(&["www"], 60, DnsA(vec![Ipv4Addr::new(192, 0, 2, 1)]));
+        let RecordValue::V1(value) = changes.0.set.remove(&record_key::<DnsA>(&["www"])).unwrap();
+        assert_eq!(value.ttl, 60);
+        let got = DnsA::from_data(value.data.unwrap()).unwrap();
+        assert_eq!(got.0, vec![Ipv4Addr::new(192, 0, 2, 1)]);
+    }
+}
@@ -37,4 +37,9 @@ pub struct Config {
     /// Content to serve, in addition to keeping certs up to date.
     #[serde(default)]
     pub content: Vec<ContentConfig>,
+    /// Don't request certs from the certifier (`certipasta`), instead generate certs
+    /// locally. The certs are still published in a spaghettinuum TLS record, so
+    /// spaghettinuum-aware clients can verify them by hash.
+    #[serde(default)]
+    pub no_certifier: bool,
 }
@@ -1,5 +1,8 @@
 use {
-    super::shared::StrSocketAddr,
+    super::shared::{
+        CompressionConfig,
+        StrSocketAddr,
+    },
     schemars::JsonSchema,
     serde::{
         Deserialize,
@@ -24,6 +27,15 @@ pub enum ServeMode {
     },
 }
 
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct AccessLogConfig {
+    /// Fraction of requests to log, between 0 and 1. Defaults to 1 (log every
+    /// request).
+    #[serde(default)]
+    pub sample: Option<f64>,
+}
+
 #[derive(Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case", deny_unknown_fields)]
 pub struct ContentConfig {
@@ -32,4 +44,17 @@ pub struct ContentConfig {
     /// Regardless of port this always serves HTTPS. For HTTP traffic you can use some
     /// other static file server.
     pub items: HashMap<StrSocketAddr, HashMap<String, ServeMode>>,
+    /// In single-port mode (see `single_port`), only serve this content for requests
+    /// to these host names (the TLS SNI, or the `Host` header if the client didn't
+    /// send SNI). The bind addresses in `items` are ignored in single-port mode. If
+    /// empty, serves requests for any host. Ignored otherwise.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Log requests (at the `info` level) with the TLS SNI, path, response status,
+    /// response size, and time to respond. Disabled if not specified.
+    #[serde(default)]
+    pub access_log: Option<AccessLogConfig>,
+    /// Compress text responses for clients that accept it. Enabled by default.
+    #[serde(default)]
+    pub compression: Option<CompressionConfig>,
 }
@@ -1,12 +1,23 @@
+use loga::{
+    ea,
+    ResultContext,
+};
 use serde::{
     Deserialize,
     Serialize,
 };
+use std::str::FromStr;
 use crate::{
     interface::stored::identity::Identity,
-    utils::blob::{
-        Blob,
-        ToBlob,
+    utils::{
+        armor::{
+            self,
+            is_armored,
+        },
+        blob::{
+            Blob,
+            ToBlob,
+        },
     },
 };
 
@@ -37,4 +48,222 @@ impl LocalIdentitySecret {
             LocalIdentitySecret::V1(v) => v.sign(message).blob(),
         }
     }
+
+    /// Sign the message itself rather than its hash, for other protocols (SSH) using
+    /// the identity key. These signatures aren't valid identity signatures.
+    pub fn sign_raw(&self, message: &[u8]) -> Blob {
+        match self {
+            LocalIdentitySecret::V1(v) => v.sign_raw(message),
+        }
+    }
+
+    /// X25519 agreement between the identity key and another ed25519 key (both
+    /// converted to X25519), for decrypting values encrypted to the identity.
+    pub fn agree(&self, other: &ed25519_dalek::VerifyingKey) -> [u8; 32] {
+        match self {
+            LocalIdentitySecret::V1(v) => v.agree(other),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedLocalIdentitySecret {
+    V1(v1::EncryptedLocalIdentitySecret),
+}
+
+impl EncryptedLocalIdentitySecret {
+    pub fn encrypt(secret: &LocalIdentitySecret, passphrase: &str) -> Result<Self, loga::Error> {
+        match secret {
+            LocalIdentitySecret::V1(s) => return Ok(
+                EncryptedLocalIdentitySecret::V1(v1::EncryptedLocalIdentitySecret::encrypt(s, passphrase)?),
+            ),
+        }
+    }
+
+    pub fn decrypt(&self, passphrase: &str) -> Result<LocalIdentitySecret, loga::Error> {
+        match self {
+            EncryptedLocalIdentitySecret::V1(s) => return Ok(LocalIdentitySecret::V1(s.decrypt(passphrase)?)),
+        }
+    }
+}
+
+/// The contents of a local identity file, either a plaintext secret or a
+/// passphrase-encrypted secret.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LocalIdentityFile {
+    Plain(LocalIdentitySecret),
+    Encrypted(EncryptedLocalIdentitySecret),
+}
+
+pub const ARMOR_KIND: &str = "IDENTITY";
+const ARMOR_TYPE_SECRET: &str = "secret";
+const ARMOR_TYPE_ENCRYPTED_SECRET: &str = "encrypted-secret";
+const ARMOR_HEADER_IDENTITY: &str = "Identity";
+
+impl LocalIdentityFile {
+    /// Parse the contents of an identity file, either armored or (older files)
+    /// json.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, loga::Error> {
+        if !is_armored(data) {
+            return serde_json::from_slice(data).context("Error parsing json in identity file");
+        }
+        let armored = armor::dearmor(ARMOR_KIND, data)?;
+        match (armored.version.as_str(), armored.type_.as_str()) {
+            ("1", ARMOR_TYPE_SECRET) => return Ok(
+                LocalIdentityFile::Plain(
+                    LocalIdentitySecret::V1(
+                        serde_json::from_slice(
+                            armored.pem.contents(),
+                        ).context("Error parsing identity secret payload")?,
+                    ),
+                ),
+            ),
+            ("1", ARMOR_TYPE_ENCRYPTED_SECRET) => return Ok(
+                LocalIdentityFile::Encrypted(
+                    EncryptedLocalIdentitySecret::V1(
+                        serde_json::from_slice(
+                            armored.pem.contents(),
+                        ).context("Error parsing encrypted identity secret payload")?,
+                    ),
+                ),
+            ),
+            _ => {
+                return Err(
+                    loga::err_with(
+                        "Unsupported identity file version or type",
+                        ea!(version = armored.version, type_ = armored.type_),
+                    ),
+                );
+            },
+        }
+    }
+
+    /// Get the identity of an identity file without decrypting it. `None` if the file
+    /// is encrypted and has no identity header (ex: older json files).
+    pub fn peek_identity(data: &[u8]) -> Result<Option<Identity>, loga::Error> {
+        match LocalIdentityFile::from_bytes(data)? {
+            LocalIdentityFile::Plain(s) => return Ok(Some(s.identity())),
+            LocalIdentityFile::Encrypted(_) => { },
+        }
+        if !is_armored(data) {
+            return Ok(None);
+        }
+        let armored = armor::dearmor(ARMOR_KIND, data)?;
+        let Some(identity) = armored.pem.headers().get(ARMOR_HEADER_IDENTITY) else {
+            return Ok(None);
+        };
+        return Ok(Some(Identity::from_str(identity).context("Invalid identity header in identity file")?));
+    }
+
+    /// Produce the armored text form of the file. The identity is included as an
+    /// informational header so files can be found by id without decrypting them.
+    pub fn to_armored(&self, identity: &Identity) -> String {
+        let (version, type_, data) = match self {
+            LocalIdentityFile::Plain(LocalIdentitySecret::V1(s)) => (
+                "1",
+                ARMOR_TYPE_SECRET,
+                serde_json::to_vec(s).unwrap(),
+            ),
+            LocalIdentityFile::Encrypted(EncryptedLocalIdentitySecret::V1(s)) => (
+                "1",
+                ARMOR_TYPE_ENCRYPTED_SECRET,
+                serde_json::to_vec(s).unwrap(),
+            ),
+        };
+        return armor::armor(
+            ARMOR_KIND,
+            version,
+            type_,
+            &[(ARMOR_HEADER_IDENTITY, &identity.to_string())],
+            &data,
+        ).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_local_identity_file {
+    use super::{
+        EncryptedLocalIdentitySecret,
+        LocalIdentityFile,
+        LocalIdentitySecret,
+    };
+
+    #[test]
+    fn test_armored_roundtrip() {
+        let (ident, secret) = LocalIdentitySecret::new();
+        let text = LocalIdentityFile::Plain(secret).to_armored(&ident);
+        assert!(text.contains(&format!("Identity: {}", ident)));
+        let LocalIdentityFile::Plain(got) = LocalIdentityFile::from_bytes(text.as_bytes()).unwrap() else {
+            panic!();
+        };
+        assert_eq!(got.identity(), ident);
+    }
+
+    #[test]
+    fn test_legacy_json() {
+        let (ident, secret) = LocalIdentitySecret::new();
+        let text = serde_json::to_vec(&secret).unwrap();
+        let LocalIdentityFile::Plain(got) = LocalIdentityFile::from_bytes(&text).unwrap() else {
+            panic!();
+        };
+        assert_eq!(got.identity(), ident);
+    }
+
+    #[test]
+    fn test_peek_encrypted() {
+        let (ident, secret) = LocalIdentitySecret::new();
+        let encrypted = EncryptedLocalIdentitySecret::encrypt(&secret, "hunter2").unwrap();
+        let text = LocalIdentityFile::Encrypted(encrypted.clone()).to_armored(&ident);
+        assert_eq!(LocalIdentityFile::peek_identity(text.as_bytes()).unwrap(), Some(ident));
+        let json = serde_json::to_vec(&LocalIdentityFile::Encrypted(encrypted)).unwrap();
+        assert_eq!(LocalIdentityFile::peek_identity(&json).unwrap(), None);
+    }
+
+    #[test]
+    fn test_decrypt_roundtrip() {
+        let (ident, secret) = LocalIdentitySecret::new();
+        let encrypted = EncryptedLocalIdentitySecret::encrypt(&secret, "hunter2").unwrap();
+        let text = LocalIdentityFile::Encrypted(encrypted).to_armored(&ident);
+        let LocalIdentityFile::Encrypted(got) = LocalIdentityFile::from_bytes(text.as_bytes()).unwrap() else {
+            panic!();
+        };
+        let got = got.decrypt("hunter2").unwrap();
+        assert_eq!(got.identity(), ident);
+        assert_eq!(got.sign(b"message"), secret.sign(b"message"));
+    }
+
+    #[test]
+    fn test_decrypt_wrong_passphrase() {
+        let (_, secret) = LocalIdentitySecret::new();
+        let encrypted = EncryptedLocalIdentitySecret::encrypt(&secret, "hunter2").unwrap();
+        assert!(encrypted.decrypt("hunter3").is_err());
+        assert!(encrypted.decrypt("").is_err());
+    }
+
+    #[test]
+    fn test_decrypt_tampered() {
+        let (_, secret) = LocalIdentitySecret::new();
+        let EncryptedLocalIdentitySecret::V1(encrypted) =
+            EncryptedLocalIdentitySecret::encrypt(&secret, "hunter2").unwrap();
+        let mut tampered = encrypted.clone();
+        tampered.ciphertext[0] ^= 1;
+        assert!(EncryptedLocalIdentitySecret::V1(tampered).decrypt("hunter2").is_err());
+        let mut tampered = encrypted.clone();
+        tampered.nonce[0] ^= 1;
+        assert!(EncryptedLocalIdentitySecret::V1(tampered).decrypt("hunter2").is_err());
+        let mut tampered = encrypted.clone();
+        tampered.kdf.salt[0] ^= 1;
+        assert!(EncryptedLocalIdentitySecret::V1(tampered).decrypt("hunter2").is_err());
+    }
+
+    #[test]
+    fn test_decrypt_kdf_limits() {
+        let (_, secret) = LocalIdentitySecret::new();
+        let EncryptedLocalIdentitySecret::V1(mut encrypted) =
+            EncryptedLocalIdentitySecret::encrypt(&secret, "hunter2").unwrap();
+        encrypted.kdf.m_cost = 16 * 1024 * 1024;
+        assert!(EncryptedLocalIdentitySecret::V1(encrypted).decrypt("hunter2").is_err());
+    }
 }
@@ -1,8 +1,21 @@
+use chacha20poly1305::{
+    aead::{
+        Aead,
+        KeyInit,
+    },
+    XChaCha20Poly1305,
+    XNonce,
+};
 use ed25519_dalek::{
     SigningKey,
     Signer,
+    VerifyingKey,
+};
+use loga::ea;
+use rand::{
+    rngs::OsRng,
+    RngCore,
 };
-use rand::rngs::OsRng;
 use serde::{
     Serialize,
     Deserialize,
@@ -59,6 +72,16 @@ impl Ed25519IdentitySecret {
     pub fn sign(&self, message: &[u8]) -> Blob {
         return self.0.sign(&hash_for_ed25519(message)).to_bytes().blob();
     }
+
+    pub fn sign_raw(&self, message: &[u8]) -> Blob {
+        return self.0.sign(message).to_bytes().blob();
+    }
+
+    /// X25519 agreement with the key converted to X25519, for decrypting values
+    /// encrypted to the identity.
+    pub fn agree(&self, other: &VerifyingKey) -> [u8; 32] {
+        return other.to_montgomery().mul_clamped(self.0.to_scalar_bytes()).to_bytes();
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -96,4 +119,107 @@ impl LocalIdentitySecret {
             LocalIdentitySecret::Ed25519(i) => i.sign(message),
         }
     }
+
+    pub fn sign_raw(&self, message: &[u8]) -> Blob {
+        match self {
+            LocalIdentitySecret::Ed25519(i) => i.sign_raw(message),
+        }
+    }
+
+    pub fn agree(&self, other: &VerifyingKey) -> [u8; 32] {
+        match self {
+            LocalIdentitySecret::Ed25519(i) => i.agree(other),
+        }
+    }
+}
+
+// Upper limits for argon2id parameters read from identity files, so a crafted file
+// can't make key derivation use unbounded memory or time. Well above the defaults
+// used when encrypting.
+const ARGON2_MAX_M_COST: u32 = 256 * 1024;
+const ARGON2_MAX_T_COST: u32 = 16;
+const ARGON2_MAX_P_COST: u32 = 16;
+
+/// Argon2id parameters used to derive the encryption key from the passphrase.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Argon2idParams {
+    pub salt: Blob,
+    /// Memory cost, in KiB
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Argon2idParams {
+    fn derive_key(&self, passphrase: &str) -> Result<chacha20poly1305::Key, loga::Error> {
+        if self.m_cost > ARGON2_MAX_M_COST || self.t_cost > ARGON2_MAX_T_COST || self.p_cost > ARGON2_MAX_P_COST {
+            return Err(
+                loga::err_with(
+                    "Argon2id parameters exceed limits",
+                    ea!(m_cost = self.m_cost, t_cost = self.t_cost, p_cost = self.p_cost),
+                ),
+            );
+        }
+        let params =
+            argon2::Params::new(
+                self.m_cost,
+                self.t_cost,
+                self.p_cost,
+                Some(32),
+            ).map_err(|e| loga::err_with("Invalid argon2id parameters", ea!(err = e)))?;
+        let mut key = chacha20poly1305::Key::default();
+        argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &self.salt, &mut key)
+            .map_err(|e| loga::err_with("Error deriving key from passphrase", ea!(err = e)))?;
+        return Ok(key);
+    }
+}
+
+/// A local identity secret encrypted with a key derived from a passphrase
+/// (argon2id + XChaCha20-Poly1305).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct EncryptedLocalIdentitySecret {
+    pub kdf: Argon2idParams,
+    pub nonce: Blob,
+    pub ciphertext: Blob,
+}
+
+impl EncryptedLocalIdentitySecret {
+    pub fn encrypt(secret: &LocalIdentitySecret, passphrase: &str) -> Result<Self, loga::Error> {
+        let mut salt = Blob::new(16);
+        OsRng.fill_bytes(&mut salt);
+        let kdf = Argon2idParams {
+            salt: salt,
+            m_cost: argon2::Params::DEFAULT_M_COST,
+            t_cost: argon2::Params::DEFAULT_T_COST,
+            p_cost: argon2::Params::DEFAULT_P_COST,
+        };
+        let key = kdf.derive_key(passphrase)?;
+        let mut nonce = Blob::new(24);
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext =
+            XChaCha20Poly1305::new(&key)
+                .encrypt(XNonce::from_slice(&nonce), secret.to_bytes().as_ref())
+                .map_err(|_| loga::err("Error encrypting identity secret"))?
+                .blob();
+        return Ok(Self {
+            kdf: kdf,
+            nonce: nonce,
+            ciphertext: ciphertext,
+        });
+    }
+
+    pub fn decrypt(&self, passphrase: &str) -> Result<LocalIdentitySecret, loga::Error> {
+        if self.nonce.len() != 24 {
+            return Err(loga::err_with("Encrypted identity nonce has wrong length", ea!(len = self.nonce.len())));
+        }
+        let key = self.kdf.derive_key(passphrase)?;
+        let plaintext =
+            XChaCha20Poly1305::new(&key)
+                .decrypt(XNonce::from_slice(&self.nonce), self.ciphertext.as_ref())
+                .map_err(|_| loga::err("Error decrypting identity secret, passphrase may be incorrect"))?;
+        return LocalIdentitySecret::from_bytes(&plaintext);
+    }
 }
@@ -10,12 +10,25 @@ use {
 pub const ENV_RESOLVER_PAIRS: &'static str = "SPAGH_RESOLVERS";
 pub const ENV_PUBLISHER_URLS: &'static str = "SPAGH_PUBLISHERS";
 
+/// A directory to queue publish requests in when a publisher can't be reached, for
+/// `spagh` and other users of `RemotePublisher`. If not set, publishing fails
+/// instead.
+pub const ENV_PUBLISH_SPOOL: &str = "SPAGH_PUBLISH_SPOOL";
+
 /// The token for making admin requests, for `spagh` CLI.
 pub const ENV_API_ADMIN_TOKEN: &'static str = "SPAGH_TOKEN";
 
 /// The JSON config (itself, not a path), for `spagh-node` and `spagh-auto`.
 pub const ENV_CONFIG: &'static str = "SPAGH_CONFIG";
 
+/// The passphrase for decrypting an encrypted local identity file. If not set, the
+/// passphrase is prompted for interactively.
+pub const ENV_IDENTITY_PASSPHRASE: &str = "SPAGH_IDENTITY_PASSPHRASE";
+
+/// The path of the `spagh-node` control socket, for `spagh-node` and `spagh daemon`
+/// commands. Defaults to `control.sock` in the runtime directory.
+pub const ENV_CONTROL_SOCKET: &str = "SPAGH_CONTROL_SOCKET";
+
 /// Persisted identity types
 pub mod identity;
 
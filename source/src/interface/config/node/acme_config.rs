@@ -0,0 +1,30 @@
+use {
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    std::path::PathBuf,
+};
+
+/// A certificate for non-`.s` names from a public ACME CA (ex: Let's Encrypt),
+/// using DNS-01 challenges.
+#[derive(Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct AcmeConfig {
+    /// The CA's ACME directory URL, ex:
+    /// `https://acme-v02.api.letsencrypt.org/directory`.
+    pub directory_url: String,
+    /// Contact URLs for the ACME account, ex: `mailto:admin@example.org`.
+    #[serde(default)]
+    pub contact: Vec<String>,
+    /// Names to include in the certificate. Wildcards (ex: `*.example.org`) are
+    /// allowed. Each name must be in one of the DNS bridge's `hosted_zones` for the
+    /// node's identity, since challenge responses are published as TXT records under
+    /// the node's identity and served by the bridge.
+    pub names: Vec<String>,
+    /// Also write the current certificate and key as `pub.pem` and `priv.pem` in this
+    /// directory whenever they change.
+    #[serde(default)]
+    pub write_certs_dir: Option<PathBuf>,
+}
@@ -1,5 +1,13 @@
 use {
-    crate::interface::config::shared::StrSocketAddr,
+    crate::interface::config::shared::{
+        CompressionConfig,
+        ConfigSecret,
+        StrSocketAddr,
+    },
+    loga::{
+        ea,
+        ResultContext,
+    },
     schemars::JsonSchema,
     serde::{
         Deserialize,
@@ -12,9 +20,25 @@ pub const DEFAULT_API_PORT: u16 = 12434;
 
 #[derive(Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-pub enum AdminToken {
-    File(PathBuf),
-    Inline(String),
+pub struct UnixBindConfig {
+    /// Path of the socket to create. Any existing file at this path is replaced.
+    pub path: PathBuf,
+    /// Permissions for the socket file, as an octal string like `660`. Defaults to
+    /// `600`.
+    #[serde(default)]
+    pub mode: Option<String>,
+}
+
+impl UnixBindConfig {
+    /// Parse the socket file mode, with the default applied.
+    pub fn mode(&self) -> Result<u32, loga::Error> {
+        match &self.mode {
+            Some(m) => {
+                return u32::from_str_radix(m, 8).context_with("Invalid unix socket mode, must be octal", ea!(mode = m));
+            },
+            None => return Ok(0o600),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, JsonSchema, Default)]
@@ -31,5 +55,96 @@ pub struct ApiConfig {
     /// on this node will work since there will be no way to register publishing
     /// identities).
     #[serde(default)]
-    pub admin_token: Option<AdminToken>,
+    pub admin_token: Option<ConfigSecret>,
+    /// More tokens accepted for admin endpoints, in addition to `admin_token` (ex: a
+    /// token per operator or automation, so each can be revoked separately).
+    #[serde(default)]
+    pub admin_tokens: Vec<ConfigSecret>,
+    /// Addresses to serve admin endpoints on (ex: `[::1]:12435` to only allow local
+    /// administration). If specified, admin endpoints aren't served on `bind_addrs`;
+    /// these addresses serve both the admin and public endpoints.
+    #[serde(default)]
+    pub admin_bind_addrs: Vec<StrSocketAddr>,
+    /// Unix domain sockets for the server to listen on, in addition to `bind_addrs`.
+    /// These serve plain HTTP (no TLS) and access is controlled by the socket file
+    /// permissions - requests over these sockets can use admin endpoints without the
+    /// admin token.
+    #[serde(default)]
+    pub unix_bind_addrs: Vec<UnixBindConfig>,
+    /// Use the `X-Request-Id` header sent by clients (if valid) as the request ID
+    /// rather than generating a new one, and keep a W3C `traceparent` header sent by
+    /// clients, so requests can be traced across services. Either way, the ID is
+    /// included in warnings logged while handling the request, passed on to DHT
+    /// lookups and publishers, and returned in the `X-Request-Id` response header.
+    #[serde(default)]
+    pub trust_request_ids: bool,
+    /// Compress responses (ex: batch resolve results) for clients that accept it.
+    /// Enabled by default.
+    #[serde(default)]
+    pub compression: Option<CompressionConfig>,
+    /// Also serve the resolver and publisher APIs as JSON-RPC 2.0, at
+    /// `/resolve/jsonrpc` and `/publish/jsonrpc`. Defaults to false.
+    #[serde(default)]
+    pub jsonrpc: bool,
+    /// Ask clients of the `bind_addrs` and `admin_bind_addrs` listeners (and the
+    /// single-port listener, if it serves the API) for a TLS client cert (optional). A
+    /// cert with a `.s` name whose key is signed by the name's identity (ex: a
+    /// `self_tls` cert) authenticates the connection as that identity, and publish
+    /// requests for the identity on it don't need to be signed, but need a recent
+    /// `published` time. The identity still needs to be allowed to publish.
+    #[serde(default)]
+    pub client_cert_auth: bool,
+    /// Refuse resolver and publisher API requests with `429` when too many are being
+    /// handled at once, rather than queueing them. Disabled if not specified.
+    #[serde(default)]
+    pub load_shedding: Option<LoadSheddingConfig>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct LoadSheddingConfig {
+    /// Max resolver API requests handled at once. Defaults to 512.
+    #[serde(default)]
+    pub resolver_max_in_flight: Option<usize>,
+    /// Also refuse resolver API requests while this many DHT lookups are waiting for
+    /// a free lookup slot (see `max_parallel_dht_lookups`). No limit if not
+    /// specified.
+    #[serde(default)]
+    pub resolver_max_waiting_lookups: Option<usize>,
+    /// Max publisher API requests (including resolve requests from other nodes)
+    /// handled at once. Admin endpoints aren't limited. Defaults to 256.
+    #[serde(default)]
+    pub publisher_max_in_flight: Option<usize>,
+    /// Seconds clients should wait before retrying, sent in the `Retry-After` header.
+    /// Defaults to 1.
+    #[serde(default)]
+    pub retry_after_secs: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SinglePortConfig {
+    /// The address to serve everything enabled below on, over HTTPS with the host
+    /// cert.
+    pub bind_addr: StrSocketAddr,
+    /// Serve the API (resolving, publishing, and admin endpoints unless
+    /// `api.admin_bind_addrs` is set) at its usual paths. Requires `api`, whose
+    /// default bind addresses aren't used in single-port mode.
+    #[serde(default)]
+    pub api: bool,
+    /// Answer DNS-over-HTTPS JSON queries at `/dns-query` (the resolver's
+    /// `/resolve/dns-query`), even if the rest of the API isn't served here. Requires
+    /// `api` and the resolver.
+    #[serde(default)]
+    pub dns_over_https: bool,
+    /// Serve `content` here instead of on its own bind addresses, choosing the
+    /// content by host name (see `hosts` in the content config). Requests for API
+    /// paths go to the API if it's enabled.
+    #[serde(default)]
+    pub content: bool,
+    /// Only serve the API and DNS-over-HTTPS for requests to these host names (the
+    /// TLS SNI, or the `Host` header if the client didn't send SNI); requests for
+    /// other hosts only get content. If empty, the API is served for any host.
+    #[serde(default)]
+    pub api_hosts: Vec<String>,
 }
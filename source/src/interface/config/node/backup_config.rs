@@ -0,0 +1,29 @@
+use {
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    std::path::PathBuf,
+};
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct BackupConfig {
+    /// Directory to write backups to. Each backup is a subdirectory named for the
+    /// time it was taken, with a consistent snapshot of each of the node's sqlite
+    /// databases.
+    pub dir: PathBuf,
+    /// Minutes between backups. Defaults to 1440 (daily).
+    #[serde(default)]
+    pub interval: Option<u64>,
+    /// How many backups to keep in `dir`, deleting the oldest after each new backup.
+    /// Defaults to 7.
+    #[serde(default)]
+    pub keep: Option<usize>,
+    /// A program and its arguments to run after each backup, ex: to upload it
+    /// elsewhere. The path of the new backup directory is appended as the last
+    /// argument. The backup is marked failed if the command fails.
+    #[serde(default)]
+    pub command: Option<Vec<String>>,
+}
@@ -18,12 +18,14 @@ pub mod publisher_config;
 pub mod resolver_config;
 pub mod node_config;
 pub mod api_config;
+pub mod acme_config;
+pub mod backup_config;
 
 #[derive(Serialize, Deserialize, JsonSchema, Default)]
 #[serde(rename_all = "snake_case")]
 pub struct Config {
     /// Where persistent files will be placed. You may want to back this up
-    /// periodically. If not specified, uses a default directory based on the
+    /// periodically (see `backup`). If not specified, uses a default directory based on the
     /// `DATA_DIRECTORY` environment variable.
     pub persistent_dir: Option<PathBuf>,
     /// Where cache files will be placed. If not specified, uses a default directory
@@ -61,7 +63,44 @@ pub struct Config {
     /// Additionally serve more HTTP content, using the host cert.
     #[serde(default)]
     pub content: Option<Vec<ContentConfig>>,
-    /// Disable certifier signature of certs (still verifiable via spagh record)
+    /// Don't request certs from the certifier (`certipasta`), instead generate certs
+    /// locally. The certs are still verifiable via the published spaghettinuum TLS
+    /// record and the identity signature extension. The publisher's resolver-facing
+    /// server always uses its own long-lived self-signed cert, pinned by hash in
+    /// announcements, so the publisher works fully without the certifier.
     #[serde(default)]
     pub no_certifier: bool,
+    /// A local unix socket for `spagh daemon` commands (status, etc). Access is
+    /// controlled by the socket file permissions.
+    ///
+    /// Defaults to the path in `SPAGH_CONTROL_SOCKET`, or `control.sock` in the
+    /// runtime directory (based on `RUNTIME_DIRECTORY`), with mode `600`. If the
+    /// default location isn't writable the control socket is skipped.
+    #[serde(default)]
+    pub control_socket: Option<api_config::UnixBindConfig>,
+    /// When shutting down, how long (in seconds) to let in-flight HTTP requests (API,
+    /// content, publisher) finish before closing their connections. Listeners stop
+    /// accepting new connections right away. Defaults to 10.
+    #[serde(default)]
+    pub shutdown_grace_period: Option<u64>,
+    /// Certificates to obtain from public ACME CAs for non-`.s` names hosted by the
+    /// DNS bridge. Requires the publisher and the DNS bridge. Certificates are renewed
+    /// automatically and stored in the cache directory.
+    #[serde(default)]
+    pub acme: Vec<acme_config::AcmeConfig>,
+    /// Minimize identifying data in logs and accounting, for privacy-sensitive
+    /// environments. Identities and addresses in info and warning logs are truncated
+    /// or masked, content access logs don't include client addresses, and node traffic
+    /// is only counted in total rather than per peer. Debug logs aren't affected.
+    #[serde(default)]
+    pub privacy_mode: bool,
+    /// Periodically back up the node's sqlite databases. Backups use sqlite's online
+    /// backup, so they're consistent even while the node is writing.
+    #[serde(default)]
+    pub backup: Option<backup_config::BackupConfig>,
+    /// Serve the API, DNS-over-HTTPS, and content together on one HTTPS port, for
+    /// container platforms that only expose one port. Requests are routed by host
+    /// name and path. Disabled if not specified.
+    #[serde(default)]
+    pub single_port: Option<api_config::SinglePortConfig>,
 }
@@ -4,8 +4,14 @@ use {
         Deserialize,
         Serialize,
     },
+    std::path::PathBuf,
     crate::interface::{
-        config::shared::StrSocketAddr,
+        config::{
+            shared::{
+                ConfigSecret,
+                StrSocketAddr,
+            },
+        },
         stored::node_identity::NodeIdentity,
     },
 };
@@ -19,6 +25,56 @@ pub struct BootstrapConfig {
     pub addr: StrSocketAddr,
     /// Node ID at that address.
     pub ident: NodeIdentity,
+    /// Never replace this peer in the routing table, and keep contacting it at this
+    /// address whenever it's unresponsive or missing. Use for anchor nodes in small
+    /// networks.
+    #[serde(default)]
+    pub protected: bool,
+}
+
+/// A file in the peers directory (see `NodeConfig::peers_dir`).
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum DeclaredPeer {
+    /// Keep the peer in the routing table like a protected bootstrap node.
+    Pinned {
+        addr: StrSocketAddr,
+        ident: NodeIdentity,
+    },
+    /// Remove the peer from the routing table and don't add it again.
+    Tombstone {
+        ident: NodeIdentity,
+    },
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct NodeRelayConfig {
+    /// The relay's TCP address (`tcp_bind_addr` on the relay), as `host:port`. With
+    /// `socks5_proxy` the host is resolved by the proxy, so it can be a Tor `.onion`
+    /// address.
+    pub relay_addr: String,
+    /// Connect to the relay through this SOCKS5 proxy (ex: Tor's `127.0.0.1:9050`).
+    /// Connects directly if not specified.
+    #[serde(default)]
+    pub socks5_proxy: Option<StrSocketAddr>,
+    /// Must match the relay's token. This is sent in cleartext, so the connection to
+    /// the relay must be private (ex: a Tor onion service or VPN).
+    pub token: ConfigSecret,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct NodeRelayServerConfig {
+    /// The UDP address to relay datagrams from. Other nodes see this as the relayed
+    /// node's address.
+    pub udp_bind_addr: StrSocketAddr,
+    /// The TCP address the relayed node connects to. The relay connection isn't
+    /// encrypted, so only expose this on a private network or as a Tor onion
+    /// service.
+    pub tcp_bind_addr: StrSocketAddr,
+    /// Connections must present this token to be relayed.
+    pub token: ConfigSecret,
 }
 
 #[derive(Deserialize, Serialize, JsonSchema, Default)]
@@ -34,4 +90,111 @@ pub struct NodeConfig {
     /// Defaults to the current `antipasta` node at time of build.
     #[serde(default)]
     pub bootstrap: Option<Vec<BootstrapConfig>>,
+    /// Snapshot the routing table at this interval (in minutes) and log how many
+    /// neighbors joined, left, or flapped (switched between responsive and
+    /// unresponsive) since the previous snapshot. The latest summary is also included
+    /// in the admin health detail. Disabled if not specified.
+    #[serde(default)]
+    pub churn_snapshot_interval: Option<u32>,
+    /// Maximum number of announcements to store on behalf of other nodes. When full,
+    /// the least recently received announcements are dropped. Defaults to 65536.
+    #[serde(default)]
+    pub max_stored_announcements: Option<usize>,
+    /// Run as a client node: decline storing announcements for other nodes. The node
+    /// still participates in lookups and announces identities from its own publisher.
+    /// Neighbors are told about this so they don't send it store requests.
+    #[serde(default)]
+    pub no_store: bool,
+    /// How many received packets to handle concurrently. Packets from the same
+    /// address are always handled in the order received. Defaults to the number of
+    /// CPUs.
+    #[serde(default)]
+    pub packet_workers: Option<usize>,
+    /// A directory of peer declarations, one JSON file (`*.json`) per peer, each a
+    /// `DeclaredPeer`. At startup and every few minutes the routing table is
+    /// reconciled with it: pinned peers are added and kept like protected bootstrap
+    /// nodes, and tombstoned peers are removed and refused. Changes to the directory
+    /// are picked up without a restart.
+    #[serde(default)]
+    pub peers_dir: Option<PathBuf>,
+    /// Send find and challenge requests from a separate UDP socket on a random port,
+    /// replaced at this interval (in minutes). Responses are only accepted on the
+    /// socket the request was sent from, so off-path attackers need to guess the port
+    /// to spoof them, and NAT mappings for outgoing requests are less predictable.
+    /// Disabled if not specified (all traffic uses `bind_addr`).
+    #[serde(default)]
+    pub request_socket_rotate_interval: Option<u32>,
+    /// How to protect the node secret at rest. Defaults to `plaintext`.
+    #[serde(default)]
+    pub secret_storage: Option<NodeSecretStorage>,
+    /// Reject store requests for announcements when this node knows of more than
+    /// this many nodes, beyond the 8 that should store each announcement, that are
+    /// closer to the announcement's identity. This limits how many announcements
+    /// others can make the node store. Larger values tolerate more disagreement
+    /// between routing tables. Defaults to 8.
+    #[serde(default)]
+    pub store_neighborhood_tolerance: Option<usize>,
+    /// Add nodes without a new challenge when they contact this node from the same
+    /// address they answered a challenge from within this many minutes. Contact from
+    /// a different address is always challenged. This cuts handshake traffic in
+    /// stable networks. Disabled if not specified (every new contact is challenged).
+    #[serde(default)]
+    pub verified_peer_window: Option<u32>,
+    /// Keep an index of this many neighbors that answer lookups fastest, regardless
+    /// of their distance, and also send the first hop of lookups for identities far
+    /// from this node to the fastest of them. This lowers lookup latency for nodes
+    /// whose own coordinate is far from the identities they resolve, at the cost of a
+    /// few more requests. Disabled if not specified.
+    #[serde(default)]
+    pub proximity_index: Option<usize>,
+    /// Remove neighbors that have been unresponsive for this many minutes, even if
+    /// no other node is available to replace them. Protected nodes are never removed.
+    /// Defaults to 1440 (one day).
+    #[serde(default)]
+    pub prune_unresponsive_after: Option<u32>,
+    /// After removing an unresponsive neighbor, ignore it for this many minutes so
+    /// outdated lists from other nodes don't add it right back. Defaults to 60.
+    #[serde(default)]
+    pub pruned_peer_memory: Option<u32>,
+    /// How far, in seconds, stamps on other nodes' messages can be from this node's
+    /// clock (either direction), and how far in the future announcement dates can be,
+    /// before they're rejected. Raise this if hosts' clocks can't be kept in sync. The
+    /// node health shows the estimated skew from other nodes. Defaults to 60.
+    #[serde(default)]
+    pub clock_skew_tolerance: Option<u32>,
+    /// Experimental. Also accept QUIC on `bind_addr` (sharing the UDP port) and send
+    /// messages to neighbors that accept it over QUIC instead of datagrams, for
+    /// congestion control and messages larger than a datagram. Neighbors advertise
+    /// QUIC after answering a challenge; others keep getting datagrams. Requires a
+    /// build with the `quic` feature.
+    #[serde(default)]
+    pub quic: bool,
+    /// Send and receive node traffic through a relay instead of binding `bind_addr`,
+    /// so other nodes see the relay's address instead of this host's. The connection
+    /// to the relay can go through a SOCKS5 proxy like Tor. Not compatible with
+    /// `request_socket_rotate_interval` or `quic`.
+    #[serde(default)]
+    pub relay: Option<NodeRelayConfig>,
+    /// Run a relay for another node (see `relay`), in addition to this node.
+    #[serde(default)]
+    pub relay_server: Option<NodeRelayServerConfig>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeSecretStorage {
+    /// Encrypt the secret with a key kept in the OS keyring (Secret Service on Linux,
+    /// Keychain on MacOS, Credential Manager on Windows). The unencrypted secret is
+    /// kept in the node database too, and used (with a warning) if the keyring isn't
+    /// available.
+    Keyring,
+    /// Encrypt the secret with a key that's protected by external commands, ex: a KMS
+    /// client. The `seal` command gets the key on stdin and must write the encrypted
+    /// key to stdout, and `unseal` the reverse. Each is a program and its arguments.
+    Command {
+        seal: Vec<String>,
+        unseal: Vec<String>,
+    },
+    /// Store the secret unencrypted in the node database.
+    Plaintext,
 }
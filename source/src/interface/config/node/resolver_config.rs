@@ -8,8 +8,145 @@ use {
         Deserialize,
         Serialize,
     },
+    std::{
+        net::{
+            Ipv4Addr,
+            Ipv6Addr,
+        },
+        path::PathBuf,
+    },
 };
 
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsUpstreamStrategy {
+    /// Query upstreams one at a time in order, moving to the next if one fails.
+    Failover,
+    /// Query all healthy upstreams at once and use the first successful response.
+    Race,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsClientSubnet {
+    /// Pass on the EDNS Client Subnet option sent by the client, if any.
+    Forward,
+    /// Remove the option. Upstreams only see the bridge's address, at the cost of
+    /// less accurate answers from geo-aware upstreams.
+    Strip,
+    /// Send the address of the client querying the bridge, truncated to these prefix
+    /// lengths. A prefix of 0 asks the upstream not to use the client subnet at all.
+    FromClient {
+        ipv4_prefix: u8,
+        ipv6_prefix: u8,
+    },
+    /// Send this subnet (ex: `203.0.113.0/24`) for all clients.
+    Fixed(String),
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsLocalViewName {
+    /// A single name, ex: `www.IDENT.s`. Subdomains aren't included.
+    Name(String),
+    /// All names under the identity.
+    Identity(String),
+}
+
+/// Answers for clients on a local network, overriding the published records. For
+/// example, names hosted on the same LAN can resolve to their private addresses
+/// rather than a public address that would need to be hairpinned.
+#[derive(Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct DnsLocalView {
+    /// Subnets of clients this view applies to (ex: `192.168.0.0/24`, `fd00::/8`).
+    pub clients: Vec<String>,
+    /// Names this view applies to.
+    pub names: Vec<DnsLocalViewName>,
+    /// Addresses to answer `A` queries with. If empty, matching `A` queries get no
+    /// records.
+    #[serde(default)]
+    pub ipv4: Vec<Ipv4Addr>,
+    /// Addresses to answer `AAAA` queries with. If empty, matching `AAAA` queries get
+    /// no records.
+    #[serde(default)]
+    pub ipv6: Vec<Ipv6Addr>,
+}
+
+/// A non-`.s` zone answered from an identity's records, for domains whose NS
+/// records point to this bridge.
+#[derive(Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct DnsHostedZone {
+    /// The zone name, ex: `example.org`. Names in the zone are answered from the
+    /// identity's records at the same path (ex: `www.example.org` is answered like
+    /// `www.IDENT.s`).
+    pub zone: String,
+    /// The identity whose records are served for the zone.
+    pub identity: String,
+}
+
+/// Response rate limiting for UDP queries, so the bridge can't be used to reflect
+/// and amplify traffic at a spoofed address. Each client subnet gets a token bucket;
+/// queries beyond the limit are dropped or, occasionally, answered with an empty
+/// truncated response so real clients can retry over TCP.
+#[derive(Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct DnsResponseRateLimit {
+    /// Responses per second allowed for each client subnet.
+    pub responses_per_second: u32,
+    /// Responses a subnet can get in a burst above the rate. Defaults to
+    /// `responses_per_second`.
+    #[serde(default)]
+    pub burst: Option<u32>,
+    /// Answer every Nth limited query with an empty truncated response instead of
+    /// dropping it. 0 drops all limited queries. Defaults to 2.
+    #[serde(default)]
+    pub slip: Option<u32>,
+    /// Prefix length of IPv4 client subnets. Defaults to 24.
+    #[serde(default)]
+    pub ipv4_prefix: Option<u8>,
+    /// Prefix length of IPv6 client subnets. Defaults to 56.
+    #[serde(default)]
+    pub ipv6_prefix: Option<u8>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct DnsTtlConfig {
+    /// Records expiring sooner are answered with this TTL (seconds), so clients don't
+    /// query again for every lookup. Defaults to 30.
+    #[serde(default)]
+    pub min: Option<u32>,
+    /// Records expiring later are answered with this TTL (seconds), so changes reach
+    /// clients eventually. Defaults to 86400 (1 day).
+    #[serde(default)]
+    pub max: Option<u32>,
+    /// Shorten each response's TTLs by a random amount up to this percent of the TTL
+    /// above `min`, so clients that looked up a name together don't all query again at
+    /// once. Defaults to 0.
+    #[serde(default)]
+    pub jitter_percent: Option<u8>,
+}
+
+/// An entry in the static names file (see `ResolverConfig::static_names`).
+#[derive(Deserialize, Serialize, JsonSchema, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StaticName {
+    /// An alias for an identity: the name and names under it are answered from the
+    /// identity's records at the same path (ex: if `lab.internal` is an alias,
+    /// `www.lab.internal` is answered like `www.IDENT.s`).
+    Identity(String),
+    /// Answer `A` and `AAAA` queries for just this name with these addresses. Other
+    /// query types get no records.
+    Addresses {
+        #[serde(default)]
+        ipv4: Vec<Ipv4Addr>,
+        #[serde(default)]
+        ipv6: Vec<Ipv6Addr>,
+    },
+}
+
 #[derive(Deserialize, Serialize, JsonSchema, Default)]
 #[serde(rename_all = "snake_case")]
 pub struct DnsBridgeConfig {
@@ -25,23 +162,212 @@ pub struct DnsBridgeConfig {
     /// disable.
     #[serde(default)]
     pub tcp_bind_addrs: Option<Vec<StrSocketAddr>>,
-    /// Upstream resolvers, such as for non-`.s` names. Each address port defaults to
-    /// port 53 if no ADN, otherwise 853. If not specified, uses system resolvers.
+    /// Upstream resolvers, such as for non-`.s` names, in order of preference. Each
+    /// address port defaults to port 53 if no ADN, otherwise 853. If not specified,
+    /// uses system resolvers.
+    ///
+    /// Upstreams that fail repeatedly are skipped until they pass a periodic health
+    /// check.
     #[serde(default)]
     pub upstream: Option<Vec<AdnSocketAddr>>,
+    /// How to choose between multiple upstreams. Defaults to `failover`.
+    #[serde(default)]
+    pub upstream_strategy: Option<DnsUpstreamStrategy>,
+    /// What to do with the EDNS Client Subnet option in queries forwarded upstream.
+    /// Defaults to `forward`.
+    #[serde(default)]
+    pub upstream_client_subnet: Option<DnsClientSubnet>,
+    /// Randomize the letter case of names in queries forwarded upstream (0x20
+    /// encoding) and reject responses that don't echo it exactly, making spoofed
+    /// responses harder to get accepted. Only enable this if all upstreams preserve
+    /// query case.
+    #[serde(default)]
+    pub upstream_randomize_case: bool,
+    /// QNAME minimization (RFC 7816) when the upstreams are authoritative servers:
+    /// before forwarding a query, look up the name's ancestors one label at a time
+    /// from the top, and answer `NXDOMAIN` without sending the full name if one of
+    /// them doesn't exist. Ancestors found are remembered for their TTL so later
+    /// queries start below them, and at most 10 are looked up per query.
+    ///
+    /// Has no effect if any upstream offers recursion, since a recursive resolver
+    /// needs the full name anyway (and can minimize its own queries).
+    #[serde(default)]
+    pub upstream_minimize_names: bool,
+    /// Only answer queries for the `s.` zone (and `synthetic_self_record`), for use as
+    /// a stub zone target behind another recursive resolver. Queries for other names
+    /// are refused rather than forwarded upstream, and zone transfers and updates get
+    /// `NOTAUTH`. The `upstream` settings are ignored.
+    #[serde(default)]
+    pub authoritative_only: bool,
     /// Create a synthetic A/AAAA record with this name pointing to this host. This
     /// uses the global addresses specified in the root config.
     #[serde(default)]
     pub synthetic_self_record: Option<String>,
+    /// Names of the authoritative nameservers for the `s.` zone, returned as NS
+    /// records. The first is also used as the primary nameserver in the SOA record.
+    /// Defaults to `synthetic_self_record` if set, otherwise `localhost.`.
+    #[serde(default)]
+    pub zone_nameservers: Option<Vec<String>>,
+    /// Responsible party mailbox for the `s.` zone SOA record, in DNS name form (ex:
+    /// `hostmaster.example.org.`). Defaults to `hostmaster.s.`.
+    #[serde(default)]
+    pub zone_contact: Option<String>,
+    /// Serial for the `s.` zone SOA record. Defaults to 1.
+    #[serde(default)]
+    pub zone_serial: Option<u32>,
+    /// Split DNS: `A` and `AAAA` answers for specific clients and names, replacing
+    /// the published records. The first view matching both the client and the name
+    /// is used. Other query types are answered normally.
+    #[serde(default)]
+    pub local_views: Vec<DnsLocalView>,
+    /// Zones outside `s.` this bridge is authoritative for. Queries for these names
+    /// are never forwarded upstream, and are answered even if `authoritative_only` is
+    /// set.
+    #[serde(default)]
+    pub hosted_zones: Vec<DnsHostedZone>,
+    /// Answer `A` and `AAAA` queries for delegated names pointing outside the `s.`
+    /// zone with the target's records, looked up upstream, instead of a `CNAME`. The
+    /// TTL is the lowest of the delegation's and the target's. This helps clients and
+    /// situations that can't follow a `CNAME` (ex: at a hosted zone apex). Falls back
+    /// to the `CNAME` if the lookup fails. Not available with `authoritative_only`.
+    #[serde(default)]
+    pub flatten_cnames: bool,
+    /// Limit responses to UDP queries per client subnet. Defaults to no limit.
+    #[serde(default)]
+    pub response_rate_limit: Option<DnsResponseRateLimit>,
+    /// Limits and jitter for the TTLs of records answered from spaghettinuum records.
+    /// Defaults to the `DnsTtlConfig` defaults.
+    #[serde(default)]
+    pub ttl: Option<DnsTtlConfig>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct ExternalNamesConfig {
+    /// DNS servers to look up external names with, in order of preference. Defaults
+    /// to the system resolvers.
+    #[serde(default)]
+    pub upstream: Option<Vec<StrSocketAddr>>,
+    /// The most names to look up for a single lookup response; further names are left
+    /// unresolved. Defaults to 16.
+    #[serde(default)]
+    pub max_names: Option<usize>,
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct WarmCacheConfig {
+    /// Identities to keep warm
+    #[serde(default)]
+    pub identities: Vec<String>,
+    /// A file of more identities to keep warm, one per line. Blank lines and lines
+    /// starting with `#` are ignored. Read at startup.
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    /// Keys to look up for each identity, as `.`-separated keys. Defaults to the
+    /// identity root's A, AAAA, and TXT records, which the DNS bridge requests
+    /// together.
+    #[serde(default)]
+    pub keys: Option<Vec<String>>,
 }
 
 #[derive(Deserialize, Serialize, JsonSchema, Default)]
 #[serde(rename_all = "snake_case")]
 pub struct ResolverConfig {
-    /// Maximum number of entries (identity, key pairs) in resolver cache.
+    /// Maximum size of the record value cache (bytes, roughly). Defaults to about
+    /// 64MiB.
     #[serde(default)]
     pub max_cache: Option<u64>,
+    /// Maximum number of record values to cache for a single identity, so a few
+    /// identities with large record sets can't fill the cache. When exceeded, the
+    /// identity's values expiring soonest are evicted. Defaults to no limit.
+    #[serde(default)]
+    pub max_cache_entries_per_identity: Option<usize>,
+    /// Maximum size of the record value cache persisted to disk at shutdown (bytes,
+    /// roughly). Expired values aren't persisted, and values expiring soonest are
+    /// dropped first. Defaults to `max_cache`.
+    #[serde(default)]
+    pub max_persisted_cache: Option<u64>,
+    /// Maximum number of announcements (identity to publisher list lookups) in the
+    /// announcement cache. Defaults to 4096.
+    #[serde(default)]
+    pub max_announcement_cache: Option<u64>,
+    /// How long to cache announcements, in minutes. Announcements change rarely so
+    /// this is separate from (and typically longer than) record value TTLs. Defaults
+    /// to 60.
+    #[serde(default)]
+    pub announcement_cache_ttl_minutes: Option<u32>,
+    /// How long to remember that an identity has no announcement, in minutes, so
+    /// repeated lookups don't query the DHT. Newly announced identities may take this
+    /// long to become resolvable. Set to 0 to disable. Defaults to 1.
+    #[serde(default)]
+    pub missing_identity_ttl_minutes: Option<u32>,
+    /// The longest to cache a publisher's answer that a key has no value, in minutes.
+    /// Publishers set this per identity (`missing_ttl` when publishing); this caps it.
+    /// Defaults to no limit.
+    #[serde(default)]
+    pub max_missing_ttl_minutes: Option<u32>,
+    /// Clients can report values that seem wrong (ex: none of the addresses accepted a
+    /// connection), and the resolver looks them up again in the background. If the
+    /// values didn't change, cache them for at most this many seconds so a fix at the
+    /// publisher is picked up soon. Defaults to no limit.
+    #[serde(default)]
+    pub suspect_report_ttl_seconds: Option<u32>,
+    /// Maximum number of DHT lookups for announcements (cache misses) to run at once.
+    /// Further lookups wait in order for one to finish, so bursts of queries don't
+    /// overwhelm the node. Queue times are shown in the resolver cache stats.
+    /// Defaults to 64.
+    #[serde(default)]
+    pub max_parallel_dht_lookups: Option<usize>,
+    /// Connect to publishers announced at addresses that aren't globally routable
+    /// (loopback, private, link-local, etc). Anyone can announce any publisher address,
+    /// so by default these are skipped to keep announcements from steering the
+    /// resolver into internal networks. Enable this for testing or private networks.
+    #[serde(default)]
+    pub allow_private_publishers: bool,
+    /// Sign requests to publishers with this node's `identity`, for publishers that only
+    /// answer authorized resolvers (see `authorized_resolvers` in the publisher config).
+    #[serde(default)]
+    pub sign_publisher_requests: bool,
+    /// Sign lookup API responses with this node's `identity` when the client asks
+    /// (the `x-spagh-sign` header), so clients can check the values came from this
+    /// resolver even through proxies or TLS terminated elsewhere. Each signed response
+    /// uses the identity's signer, so avoid this with slow signers like hardware
+    /// tokens.
+    #[serde(default)]
+    pub sign_responses: bool,
     /// The DNS bridge exposes specific spaghettinuum `dns/` records over DNS.
     #[serde(default)]
     pub dns_bridge: Option<DnsBridgeConfig>,
+    /// A JSON file of names answered locally by the DNS bridge and the DNS JSON API
+    /// before looking anything up, in the format `{NAME: STATIC_NAME, ...}` (see
+    /// `StaticName`). Names can be inside or outside the `s.` zone; the most specific
+    /// entry matching a query is used. The file is reloaded when it changes; if it
+    /// becomes invalid the previous names are kept.
+    #[serde(default)]
+    pub static_names: Option<PathBuf>,
+    /// Answer lookups from records in this JSON file instead of the DHT and
+    /// publishers, for testing the DNS bridge. The format is `{IDENTITY: {KEY:
+    /// {"ttl": MINUTES, "data": DATA}, ...}, ...}`. Only available if built with the
+    /// `fixtures` feature.
+    #[serde(default)]
+    pub fixtures: Option<PathBuf>,
+    /// Look up external DNS names referenced by record values (`MX` hosts, delegate
+    /// targets, and handoff name servers without glue) and include their addresses
+    /// with the values in lookup API responses, so clients don't need a second
+    /// lookup. Addresses are cached per their DNS TTLs. Disabled if not specified.
+    #[serde(default)]
+    pub external_names: Option<ExternalNamesConfig>,
+    /// Answer lookups for identities announced on this node's publisher directly from
+    /// the publisher, before checking the cache or looking up the announcement in the
+    /// DHT. Self-hosted names resolve immediately and see changes right away, even if
+    /// the DHT is degraded. Has no effect if the node doesn't run a publisher.
+    #[serde(default)]
+    pub local_publisher_fast_path: bool,
+    /// Look up these identities at startup and refresh their values shortly before
+    /// they expire, so the first queries for known popular names after a restart (and
+    /// later queries) don't wait for the DHT and publishers. Disabled if not
+    /// specified.
+    #[serde(default)]
+    pub warm_cache: Option<WarmCacheConfig>,
 }
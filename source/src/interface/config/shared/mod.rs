@@ -90,6 +90,12 @@ impl From<SocketAddr> for StrSocketAddr {
     }
 }
 
+impl std::fmt::Debug for StrSocketAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return std::fmt::Display::fmt(self, f);
+    }
+}
+
 impl std::fmt::Display for StrSocketAddr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         format!(
@@ -364,3 +370,139 @@ pub enum GlobalAddrConfig {
     /// must reply with the ip address as plain text.
     Lookup(GlobalAddrLookupConfig),
 }
+
+/// Compression of HTTP responses, for clients that accept it (with brotli or gzip).
+/// Only text, JSON, XML, and similar content is compressed.
+#[derive(Deserialize, Serialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct CompressionConfig {
+    /// Send all responses uncompressed.
+    #[serde(default)]
+    pub disable: bool,
+    /// Don't compress responses smaller than this many bytes. Defaults to 1024.
+    #[serde(default)]
+    pub min_size: Option<usize>,
+}
+
+/// Where a secret config value comes from, if not inline.
+#[derive(Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigSecretSource {
+    /// The value itself, same as a plain string
+    Inline(String),
+    /// The value of this environment variable
+    Env(String),
+    /// The contents of this file (ex: `/run/secrets/x`), without trailing newlines
+    File(PathBuf),
+}
+
+#[derive(Deserialize, Serialize, JsonSchema, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+enum ConfigSecretRepr {
+    Inline(String),
+    Source(ConfigSecretSource),
+}
+
+/// A config value that may be a secret: a string, or `{"env": "VAR"}` or `{"file":
+/// "/path"}` to read it when the config is loaded so it doesn't need to be in the
+/// config itself. Serializes as it was specified, not the read value.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ConfigSecret {
+    source: ConfigSecretRepr,
+    value: String,
+}
+
+impl ConfigSecret {
+    pub fn inline(value: impl ToString) -> ConfigSecret {
+        let value = value.to_string();
+        return ConfigSecret {
+            source: ConfigSecretRepr::Inline(value.clone()),
+            value: value,
+        };
+    }
+
+    pub fn read(source: ConfigSecretSource) -> Result<ConfigSecret, loga::Error> {
+        let value = match &source {
+            ConfigSecretSource::Inline(v) => v.clone(),
+            ConfigSecretSource::Env(k) => std::env::var(
+                k,
+            ).context_with("Error reading config secret from environment variable", ea!(var = k))?,
+            ConfigSecretSource::File(p) => std::fs::read_to_string(p)
+                .context_with("Error reading config secret file", ea!(path = p.to_string_lossy()))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        };
+        return Ok(ConfigSecret {
+            source: ConfigSecretRepr::Source(source),
+            value: value,
+        });
+    }
+
+    pub fn get(&self) -> &str {
+        return &self.value;
+    }
+}
+
+impl std::fmt::Debug for ConfigSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f.write_str("ConfigSecret(..)");
+    }
+}
+
+impl Serialize for ConfigSecret {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer {
+        return self.source.serialize(serializer);
+    }
+}
+
+impl<'t> Deserialize<'t> for ConfigSecret {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'t> {
+        match ConfigSecretRepr::deserialize(deserializer)? {
+            ConfigSecretRepr::Inline(v) => return Ok(ConfigSecret::inline(v)),
+            ConfigSecretRepr::Source(s) => return ConfigSecret::read(
+                s,
+            ).map_err(|e| <D::Error as serde::de::Error>::custom(e.to_string())),
+        }
+    }
+}
+
+impl JsonSchema for ConfigSecret {
+    fn schema_name() -> String {
+        return "ConfigSecret".to_string();
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        return ConfigSecretRepr::json_schema(gen);
+    }
+}
+
+#[cfg(test)]
+mod test_config_secret {
+    use {
+        super::ConfigSecret,
+        std::env,
+    };
+
+    #[test]
+    fn test_sources() {
+        let v = serde_json::from_str::<ConfigSecret>("\"abc\"").unwrap();
+        assert_eq!(v.get(), "abc");
+        assert_eq!(serde_json::to_string(&v).unwrap(), "\"abc\"");
+        let v = serde_json::from_str::<ConfigSecret>("{\"inline\": \"abc\"}").unwrap();
+        assert_eq!(v.get(), "abc");
+        let path = env::temp_dir().join(format!("spagh-test-config-secret-{}", std::process::id()));
+        std::fs::write(&path, "def\n").unwrap();
+        let src = serde_json::json!({
+            "file": path
+        });
+        let v = serde_json::from_value::<ConfigSecret>(src.clone()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(v.get(), "def");
+        assert_eq!(serde_json::to_value(&v).unwrap(), src);
+        assert!(serde_json::from_str::<ConfigSecret>("{\"env\": \"SPAGH_TEST_CONFIG_SECRET_UNSET\"}").is_err());
+    }
+}
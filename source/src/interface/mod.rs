@@ -3,3 +3,5 @@
 pub mod stored;
 pub mod wire;
 pub mod config;
+#[cfg(test)]
+mod test_golden;
@@ -1,19 +1,31 @@
 use good_ormning_runtime::sqlite::GoodOrmningCustomString;
+use schemars::JsonSchema;
 use serde::{
     Deserialize,
     Serialize,
 };
+use crate::utils::signed::IdentSignatureMethods;
 
 pub mod v1;
 
 pub use v1 as latest;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum Announcement {
     V1(v1::Announcement),
 }
 
+impl Announcement {
+    /// Parse the content without checking the signature, for announcements that were
+    /// verified when received. Fails if the content is malformed.
+    pub fn parse(&self) -> Result<latest::AnnouncementContent, loga::Error> {
+        match self {
+            Announcement::V1(a) => return a.parse(),
+        }
+    }
+}
+
 impl GoodOrmningCustomString<Announcement> for Announcement {
     fn to_sql<'a>(value: &'a Announcement) -> std::borrow::Cow<'a, str> {
         return serde_json::to_string(value).unwrap().into();
@@ -1,6 +1,7 @@
 use std::{
     marker::PhantomData,
 };
+use schemars::JsonSchema;
 use serde::{
     de::DeserializeOwned,
     Deserialize,
@@ -45,4 +46,29 @@ impl<T: Serialize + DeserializeOwned + std::fmt::Debug, I> std::fmt::Debug for B
     }
 }
 
+impl<T: Serialize + DeserializeOwned, I> JsonSchema for BincodeSignature<T, I> {
+    fn schema_name() -> String {
+        return "BincodeSignature".to_string();
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        return schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::Object.into()),
+            metadata: Some(Box::new(schemars::schema::Metadata {
+                description: Some("A bincode-serialized message and the identity's signature of it".to_string()),
+                ..Default::default()
+            })),
+            object: Some(Box::new(schemars::schema::ObjectValidation {
+                required: ["message".to_string(), "signature".to_string()].into_iter().collect(),
+                properties: [
+                    ("message".to_string(), gen.subschema_for::<Blob>()),
+                    ("signature".to_string(), gen.subschema_for::<Blob>()),
+                ].into_iter().collect(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }.into();
+    }
+}
+
 pub type Announcement = BincodeSignature<AnnouncementContent, Identity>;
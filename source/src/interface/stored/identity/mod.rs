@@ -13,6 +13,7 @@ use loga::{
 };
 use schemars::JsonSchema;
 use sha2::{
+    Sha256,
     Sha512,
     Digest,
 };
@@ -114,4 +115,17 @@ impl Identity {
             Identity::V1(v) => v.verify(message, signature),
         }
     }
+
+    /// A short (not collision resistant) form of the identity for people to compare
+    /// at a glance, like `abcd-efgh-ijkl-mnop`.
+    pub fn fingerprint(&self) -> String {
+        let hash = <Sha256 as Digest>::digest(self.to_bytes());
+        let encoded = zbase32::encode_full_bytes(&hash[..10]);
+        return encoded
+            .as_bytes()
+            .chunks(4)
+            .map(|c| String::from_utf8_lossy(c).to_string())
+            .collect::<Vec<_>>()
+            .join("-");
+    }
 }
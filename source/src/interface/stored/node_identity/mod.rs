@@ -134,3 +134,22 @@ impl GoodOrmningCustomString<NodeSecret> for NodeSecret {
         );
     }
 }
+
+versioned!(
+    SealedNodeSecret,
+    Debug,
+    Clone;
+    (V1, 1, v1::SealedNodeSecret)
+);
+
+impl GoodOrmningCustomString<SealedNodeSecret> for SealedNodeSecret {
+    fn to_sql<'a>(value: &'a SealedNodeSecret) -> std::borrow::Cow<'a, str> {
+        return zbase32::encode_full_bytes(&value.to_bytes()).into();
+    }
+
+    fn from_sql(value: String) -> Result<SealedNodeSecret, String> {
+        return SealedNodeSecret::from_bytes(
+            &zbase32::decode_full_bytes_str(&value).map_err(|e| e.to_string())?,
+        ).map_err(|e| e.to_string());
+    }
+}
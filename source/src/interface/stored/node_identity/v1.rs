@@ -163,3 +163,32 @@ impl NodeSecretMethods for NodeSecret {
         }
     }
 }
+
+/// Where the key that encrypts a sealed node secret is kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SealedNodeSecretKey {
+    /// In the OS keyring, in the entry with this user name.
+    Keyring(String),
+    /// Stored here, encrypted by the configured seal command.
+    Command(Blob),
+}
+
+/// A node secret encrypted for storage at rest (XChaCha20-Poly1305).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SealedNodeSecret {
+    pub key: SealedNodeSecretKey,
+    pub nonce: Blob,
+    pub ciphertext: Blob,
+}
+
+impl SealedNodeSecret {
+    pub fn from_bytes(message: &[u8]) -> Result<Self, loga::Error> {
+        return bincode::deserialize(message).context("Bincode decode failed");
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        return bincode::serialize(self).unwrap();
+    }
+}
@@ -0,0 +1,32 @@
+use {
+    super::record_utils::RecordKey,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+pub mod v1;
+
+pub use v1 as latest;
+
+pub const KEY_SUFFIX_ADDR_PREF: &str = "addr_pref";
+
+pub fn build_addr_pref_key(head: RecordKey) -> RecordKey {
+    let mut out = head;
+    out.push(KEY_SUFFIX_ADDR_PREF.to_string());
+    return out;
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AddrPref {
+    V1(v1::AddrPref),
+}
+
+impl AddrPref {
+    pub fn latest(data: latest::AddrPref) -> Self {
+        return Self::V1(data);
+    }
+}
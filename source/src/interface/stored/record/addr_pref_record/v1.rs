@@ -0,0 +1,23 @@
+use {
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+/// A hint for which address family (A or AAAA records at the same path) clients
+/// should try first. Published when the host found it isn't reachable over the
+/// other family.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AddrPref {
+    pub prefer: IpFamily,
+}
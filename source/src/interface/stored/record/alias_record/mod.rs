@@ -0,0 +1,43 @@
+use {
+    super::record_utils::RecordKey,
+    crate::interface::stored::identity::Identity,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+pub mod v1;
+
+pub use v1 as latest;
+
+pub const KEY_SUFFIX_ALIAS: &str = "alias";
+
+/// The alias record is only meaningful at the identity root.
+pub fn build_alias_key() -> RecordKey {
+    return vec![KEY_SUFFIX_ALIAS.to_string()];
+}
+
+/// The maximum number of aliases the publisher follows when serving values, to
+/// bound the work done for long or cyclic chains.
+pub const ALIAS_MAX_HOPS: usize = 4;
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Alias {
+    V1(v1::Alias),
+}
+
+impl Alias {
+    pub fn latest(data: latest::Alias) -> Self {
+        return Self::V1(data);
+    }
+
+    /// The identity whose records are served instead.
+    pub fn target(&self) -> Identity {
+        match self {
+            Alias::V1(a) => return a.target,
+        }
+    }
+}
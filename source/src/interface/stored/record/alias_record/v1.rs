@@ -0,0 +1,19 @@
+use {
+    crate::interface::stored::identity::Identity,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+/// Serve all records from another identity under this identity (like a DNS
+/// `CNAME` for the whole record set). The publisher follows the alias when
+/// answering lookups, so both identities must be published on the same publisher.
+/// Other records published for this identity are ignored, except the alias record
+/// itself.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Alias {
+    pub target: Identity,
+}
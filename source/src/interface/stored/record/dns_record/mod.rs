@@ -18,6 +18,14 @@ pub const KEY_SUFFIX_DNS_AAAA: &'static str = "dns/aaaa";
 pub const KEY_SUFFIX_DNS_TXT: &'static str = "dns/txt";
 pub const KEY_SUFFIX_DNS_MX: &'static str = "dns/mx";
 
+/// Max values in a single DNS record. Publishers reject records with more, and the
+/// DNS bridge ignores any values past this.
+pub const MAX_DNS_VALUES: usize = 64;
+
+/// Max total size of a DNS record's values in DNS wire format. Publishers reject
+/// larger records.
+pub const MAX_DNS_RDATA_BYTES: usize = 8 * 1024;
+
 #[derive(Clone, Copy)]
 pub enum RecordType {
     A,
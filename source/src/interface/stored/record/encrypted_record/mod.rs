@@ -0,0 +1,39 @@
+use {
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+pub mod v1;
+
+pub use v1 as latest;
+
+/// Record data encrypted to recipient identities, published in place of the
+/// record's normal data at any key. The `encrypted` tag keeps it from being
+/// mistaken for the data of any record type, so publishers and resolvers pass it
+/// through unchanged.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptedData {
+    Encrypted(Encrypted),
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Encrypted {
+    V1(v1::Encrypted),
+}
+
+impl Encrypted {
+    pub fn latest(data: latest::Encrypted) -> Self {
+        return Self::V1(data);
+    }
+}
+
+/// Whether record data is tagged as encrypted (see `EncryptedData`), without
+/// checking the encrypted value.
+pub fn is_encrypted(data: &serde_json::Value) -> bool {
+    return data.as_object().is_some_and(|o| o.len() == 1 && o.contains_key("encrypted"));
+}
@@ -0,0 +1,31 @@
+use {
+    crate::utils::blob::Blob,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+/// JSON record data encrypted with a random data key (XChaCha20-Poly1305), with
+/// the data key wrapped for each recipient.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Encrypted {
+    pub recipients: Vec<Recipient>,
+    pub nonce: Blob,
+    pub ciphertext: Blob,
+}
+
+/// The data key wrapped for one recipient identity, like age's `ssh-ed25519`
+/// recipients: the wrapping key is derived from an X25519 agreement between an
+/// ephemeral key and the recipient's ed25519 identity key (both converted to
+/// X25519). Which identity it's for isn't recorded.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Recipient {
+    /// The ephemeral ed25519 public key
+    pub ephemeral: Blob,
+    pub nonce: Blob,
+    pub wrapped_key: Blob,
+}
@@ -0,0 +1,32 @@
+use {
+    super::record_utils::RecordKey,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+pub mod v1;
+
+pub use v1 as latest;
+
+pub const KEY_SUFFIX_HANDOFF: &str = "handoff";
+
+pub fn build_handoff_key(head: RecordKey) -> RecordKey {
+    let mut out = head;
+    out.push(KEY_SUFFIX_HANDOFF.to_string());
+    return out;
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Handoff {
+    V1(v1::Handoff),
+}
+
+impl Handoff {
+    pub fn latest(data: latest::Handoff) -> Self {
+        return Self::V1(data);
+    }
+}
@@ -0,0 +1,44 @@
+use {
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    std::{
+        collections::BTreeMap,
+        net::IpAddr,
+    },
+};
+
+/// Hands the subtree at this path off to conventional authoritative DNS servers.
+/// The DNS bridge answers queries for the name and all names under it with a
+/// referral to the servers (`NS` records, and `DS` records if present) rather than
+/// answering from the identity's records.
+///
+/// Like delegate records, handoff records for all prefixes of a query path should
+/// be queried, with the shortest prefix that has a handoff used.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Handoff {
+    /// Host names of the authoritative servers for the subtree (ex:
+    /// `ns1.example.org`).
+    pub name_servers: Vec<String>,
+    /// Addresses of name servers whose names are within the subtree (glue), by name
+    /// server host name.
+    #[serde(default)]
+    pub glue: BTreeMap<String, Vec<IpAddr>>,
+    /// Delegation signer records, if the servers sign the subtree with DNSSEC.
+    #[serde(default)]
+    pub ds: Vec<HandoffDs>,
+}
+
+/// A DNS `DS` record.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct HandoffDs {
+    pub key_tag: u16,
+    pub algorithm: u8,
+    pub digest_type: u8,
+    /// Hex-encoded digest
+    pub digest: String,
+}
@@ -0,0 +1,43 @@
+use {
+    super::record_utils::RecordKey,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+pub mod v1;
+
+pub use v1 as latest;
+
+pub const KEY_SUFFIX_KEY_ALIAS: &str = "key_alias";
+
+pub fn build_key_alias_key(head: RecordKey) -> RecordKey {
+    let mut out = head;
+    out.push(KEY_SUFFIX_KEY_ALIAS.to_string());
+    return out;
+}
+
+/// The maximum number of key aliases the publisher follows when serving a value,
+/// to bound the work done for long chains.
+pub const KEY_ALIAS_MAX_HOPS: usize = 4;
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyAlias {
+    V1(v1::KeyAlias),
+}
+
+impl KeyAlias {
+    pub fn latest(data: latest::KeyAlias) -> Self {
+        return Self::V1(data);
+    }
+
+    /// The path whose records are served instead.
+    pub fn target(&self) -> &RecordKey {
+        match self {
+            KeyAlias::V1(a) => return &a.target,
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use {
+    crate::interface::stored::record::record_utils::RecordKey,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+/// Serve the records at another path of the same identity for the path of this
+/// record (like a DNS `CNAME`): a lookup of `PATH + [SUFFIX]` gets the value of
+/// `target + [SUFFIX]`, for every record type except the key alias itself. The
+/// publisher follows the alias when answering lookups. Other records published at
+/// this path are ignored, paths under it aren't affected.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct KeyAlias {
+    /// The path within the identity to serve records from (empty for the identity
+    /// root)
+    pub target: RecordKey,
+}
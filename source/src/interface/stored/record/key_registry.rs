@@ -0,0 +1,208 @@
+//! Record types (final key segments) with a predefined meaning, and the naming
+//! convention for custom record types so applications don't collide with them or
+//! each other.
+//!
+//! Custom record types should be `VENDOR/NAME`, where `VENDOR` is something the
+//! application controls, like a project name (ex: `acme/build_cache`). Bare names
+//! and the `dns/` and `spagh/` namespaces are reserved for predefined record types.
+use {
+    super::{
+        addr_pref_record::KEY_SUFFIX_ADDR_PREF,
+        alias_record::KEY_SUFFIX_ALIAS,
+        delegate_record::KEY_SUFFIX_DELEGATE,
+        dns_record::{
+            KEY_SUFFIX_DNS_A,
+            KEY_SUFFIX_DNS_AAAA,
+            KEY_SUFFIX_DNS_MX,
+            KEY_SUFFIX_DNS_TXT,
+        },
+        handoff_record::KEY_SUFFIX_HANDOFF,
+        key_alias_record::KEY_SUFFIX_KEY_ALIAS,
+        record_utils::RecordKey,
+        service_record::KEY_SUFFIX_SERVICES,
+        ssh_record::KEY_SUFFIX_SSH_HOSTKEYS,
+        succession_record::KEY_SUFFIX_SUCCESSION,
+        tls_record::KEY_SUFFIX_TLS,
+    },
+    loga::ea,
+};
+
+pub const KEY_NAMESPACE_DELIM: char = '/';
+
+/// Namespaces reserved for predefined record types.
+pub const RESERVED_KEY_NAMESPACES: &[&str] = &["dns", "spagh"];
+
+pub struct WellKnownKey {
+    pub suffix: &'static str,
+    pub description: &'static str,
+    /// Only meaningful at the identity root (the key is just the suffix)
+    pub root_only: bool,
+}
+
+pub const WELL_KNOWN_KEYS: &[WellKnownKey] = &[
+    WellKnownKey {
+        suffix: KEY_SUFFIX_DNS_A,
+        description: "DNS A record (IPv4 addresses)",
+        root_only: false,
+    },
+    WellKnownKey {
+        suffix: KEY_SUFFIX_DNS_AAAA,
+        description: "DNS AAAA record (IPv6 addresses)",
+        root_only: false,
+    },
+    WellKnownKey {
+        suffix: KEY_SUFFIX_DNS_TXT,
+        description: "DNS TXT record",
+        root_only: false,
+    },
+    WellKnownKey {
+        suffix: KEY_SUFFIX_DNS_MX,
+        description: "DNS MX record (mail servers, in priority order)",
+        root_only: false,
+    },
+    WellKnownKey {
+        suffix: KEY_SUFFIX_DELEGATE,
+        description: "Delegation of the key prefix to other names",
+        root_only: false,
+    },
+    WellKnownKey {
+        suffix: KEY_SUFFIX_HANDOFF,
+        description: "Handoff of the key prefix to conventional DNS servers",
+        root_only: false,
+    },
+    WellKnownKey {
+        suffix: KEY_SUFFIX_KEY_ALIAS,
+        description: "Alias of the key prefix to another prefix of the same identity",
+        root_only: false,
+    },
+    WellKnownKey {
+        suffix: KEY_SUFFIX_SSH_HOSTKEYS,
+        description: "SSH host keys",
+        root_only: false,
+    },
+    WellKnownKey {
+        suffix: KEY_SUFFIX_TLS,
+        description: "TLS certificates",
+        root_only: false,
+    },
+    WellKnownKey {
+        suffix: KEY_SUFFIX_SERVICES,
+        description: "Service endpoints",
+        root_only: false,
+    },
+    WellKnownKey {
+        suffix: KEY_SUFFIX_ADDR_PREF,
+        description: "Address family preference",
+        root_only: false,
+    },
+    WellKnownKey {
+        suffix: KEY_SUFFIX_ALIAS,
+        description: "Alias of the whole identity to another identity",
+        root_only: true,
+    },
+    WellKnownKey {
+        suffix: KEY_SUFFIX_SUCCESSION,
+        description: "Succession of the identity to a new identity",
+        root_only: true,
+    },
+];
+
+pub fn well_known_key(suffix: &str) -> Option<&'static WellKnownKey> {
+    return WELL_KNOWN_KEYS.iter().find(|k| k.suffix == suffix);
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeySuffixKind<'a> {
+    /// A predefined record type
+    WellKnown,
+    /// A custom record type following the `VENDOR/NAME` convention
+    Vendor {
+        vendor: &'a str,
+        name: &'a str,
+    },
+    /// Only differs from a predefined record type by case, so may be mistaken for it
+    /// by people but not by software
+    ShadowsWellKnown(&'static str),
+    /// In a namespace reserved for predefined record types, but not one of them
+    ReservedNamespace(&'a str),
+    /// A custom record type without a vendor namespace
+    Bare,
+}
+
+pub fn classify_key_suffix(suffix: &str) -> KeySuffixKind<'_> {
+    if well_known_key(suffix).is_some() {
+        return KeySuffixKind::WellKnown;
+    }
+    if let Some(k) = WELL_KNOWN_KEYS.iter().find(|k| k.suffix.eq_ignore_ascii_case(suffix)) {
+        return KeySuffixKind::ShadowsWellKnown(k.suffix);
+    }
+    let Some((vendor, name)) = suffix.split_once(KEY_NAMESPACE_DELIM) else {
+        return KeySuffixKind::Bare;
+    };
+    if RESERVED_KEY_NAMESPACES.iter().any(|r| r.eq_ignore_ascii_case(vendor)) {
+        return KeySuffixKind::ReservedNamespace(vendor);
+    }
+    if vendor.is_empty() || name.is_empty() {
+        return KeySuffixKind::Bare;
+    }
+    return KeySuffixKind::Vendor {
+        vendor: vendor,
+        name: name,
+    };
+}
+
+/// Build a key for a custom record type following the `VENDOR/NAME` convention.
+/// Fails if the vendor is empty, contains `/`, or is a reserved namespace, or if the
+/// name is empty.
+pub fn build_vendor_key(head: RecordKey, vendor: &str, name: &str) -> Result<RecordKey, loga::Error> {
+    if vendor.is_empty() || name.is_empty() {
+        return Err(
+            loga::err_with("Custom record type vendor and name must not be empty", ea!(vendor = vendor, name = name)),
+        );
+    }
+    if vendor.contains(KEY_NAMESPACE_DELIM) {
+        return Err(loga::err_with("Custom record type vendor must not contain `/`", ea!(vendor = vendor)));
+    }
+    if RESERVED_KEY_NAMESPACES.iter().any(|r| r.eq_ignore_ascii_case(vendor)) {
+        return Err(
+            loga::err_with("Custom record type vendor is reserved for predefined record types", ea!(vendor = vendor)),
+        );
+    }
+    let mut out = head;
+    out.push(format!("{}{}{}", vendor, KEY_NAMESPACE_DELIM, name));
+    return Ok(out);
+}
+
+#[cfg(test)]
+mod test_key_registry {
+    use super::{
+        build_vendor_key,
+        classify_key_suffix,
+        KeySuffixKind,
+    };
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify_key_suffix("dns/aaaa"), KeySuffixKind::WellKnown);
+        assert_eq!(classify_key_suffix("ssh_hostkeys"), KeySuffixKind::WellKnown);
+        assert_eq!(classify_key_suffix("DNS/A"), KeySuffixKind::ShadowsWellKnown("dns/a"));
+        assert_eq!(classify_key_suffix("Delegate"), KeySuffixKind::ShadowsWellKnown("delegate"));
+        assert_eq!(classify_key_suffix("dns/srv"), KeySuffixKind::ReservedNamespace("dns"));
+        assert_eq!(classify_key_suffix("acme/cache"), KeySuffixKind::Vendor {
+            vendor: "acme",
+            name: "cache",
+        });
+        assert_eq!(classify_key_suffix("cache"), KeySuffixKind::Bare);
+    }
+
+    #[test]
+    fn test_build_vendor_key() {
+        assert_eq!(
+            build_vendor_key(vec!["www".to_string()], "acme", "cache").unwrap(),
+            vec!["www".to_string(), "acme/cache".to_string()]
+        );
+        assert!(build_vendor_key(vec![], "dns", "srv").is_err());
+        assert!(build_vendor_key(vec![], "a/b", "c").is_err());
+        assert!(build_vendor_key(vec![], "", "c").is_err());
+    }
+}
@@ -11,8 +11,16 @@ pub mod dns_record;
 pub mod tls_record;
 pub mod ssh_record;
 pub mod delegate_record;
+pub mod service_record;
+pub mod succession_record;
+pub mod addr_pref_record;
+pub mod alias_record;
+pub mod key_alias_record;
+pub mod handoff_record;
+pub mod encrypted_record;
 pub mod v1;
 pub mod record_utils;
+pub mod key_registry;
 
 pub use v1 as latest;
 
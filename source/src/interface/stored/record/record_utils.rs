@@ -134,7 +134,8 @@ pub fn split_query_record_keys(keys: &str) -> Vec<RecordKey> {
     }).collect();
 }
 
-fn domain_part_raw_to_string(part: &str) -> Result<String, loga::Error> {
+/// Decode a DNS name label as sent on the wire (punycode for international names).
+pub fn domain_part_raw_to_string(part: &str) -> Result<String, loga::Error> {
     let (part1, e) = Uts46::new().to_unicode(part.as_bytes(), AsciiDenyList::URL, Hyphens::Check);
     e.context_with("DNS name part isn't valid international domain segment", ea!(part = part))?;
     return Ok(part1.to_string());
@@ -148,6 +149,82 @@ fn domain_part_string_to_raw(part: &str) -> Result<String, loga::Error> {
     return Ok(part1.to_string());
 }
 
+/// Normalize a key segment so equivalent international names compare equal.
+/// Punycode (`xn--`) segments are decoded and non-ASCII segments are case-folded
+/// and NFC-normalized per UTS 46, the same as names arriving via DNS. Other ASCII
+/// segments, and segments that aren't valid international domain labels, are left
+/// as is.
+pub fn normalize_key_part(part: &str) -> String {
+    if part.is_ascii() && !part.to_ascii_lowercase().starts_with("xn--") {
+        return part.to_string();
+    }
+    let (part1, e) = Uts46::new().to_unicode(part.as_bytes(), AsciiDenyList::URL, Hyphens::Check);
+    if e.is_err() {
+        return part.to_string();
+    }
+    return part1.to_string();
+}
+
+/// Normalize each segment of a key with `normalize_key_part`.
+pub fn normalize_record_key(key: &RecordKey) -> RecordKey {
+    return key.iter().map(|p| normalize_key_part(p)).collect();
+}
+
+#[cfg(test)]
+mod test_normalize_record_key {
+    use {
+        super::{
+            join_dns_name,
+            normalize_key_part,
+            normalize_record_key,
+            split_dns_name,
+            RecordRoot,
+        },
+        hickory_proto::rr::LowerName,
+        std::str::FromStr,
+    };
+
+    #[test]
+    fn test_punycode() {
+        assert_eq!(normalize_key_part("xn--caf-dma"), "café");
+    }
+
+    #[test]
+    fn test_case() {
+        assert_eq!(normalize_key_part("CAFÉ"), "café");
+    }
+
+    #[test]
+    fn test_nfc() {
+        assert_eq!(normalize_key_part("cafe\u{301}"), "café");
+    }
+
+    #[test]
+    fn test_ascii_unchanged() {
+        assert_eq!(
+            normalize_record_key(&vec!["Www".to_string(), "dns/aaaa".to_string(), "ssh_hostkeys".to_string()]),
+            vec!["Www".to_string(), "dns/aaaa".to_string(), "ssh_hostkeys".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_invalid_unchanged() {
+        assert_eq!(normalize_key_part("ab--cé"), "ab--cé");
+    }
+
+    #[test]
+    fn test_dns_round_trip() {
+        let name = join_dns_name(RecordRoot::Dns("example".to_string()), vec!["Café".to_string()]).unwrap();
+        assert_eq!(name, "xn--caf-dma.example");
+        let (root, key) = split_dns_name(LowerName::from_str(&name).unwrap()).unwrap();
+        let RecordRoot::Dns(root) = root else {
+            panic!();
+        };
+        assert_eq!(root, "example");
+        assert_eq!(key, normalize_record_key(&vec!["Café".to_string()]));
+    }
+}
+
 pub fn split_dns_path(name: &str) -> Result<RecordKey, loga::Error> {
     let mut path = vec![];
     for part in name.split(".") {
@@ -198,6 +275,7 @@ pub fn split_dns_name(name: impl Into<hickory_resolver::Name>) -> Result<(Record
 mod test_split_dns_name {
     use {
         super::{
+            join_dns_name,
             split_dns_name,
             RecordRoot,
         },
@@ -214,6 +292,17 @@ mod test_split_dns_name {
         assert_eq!(root, "other");
         assert_eq!(key, vec!["something".to_string(), "1".to_string()]);
     }
+
+    #[test]
+    fn test_underscore() {
+        let (root, key) = split_dns_name(LowerName::from_str("_acme-challenge.www.other").unwrap()).unwrap();
+        let RecordRoot::Dns(root) = root else {
+            panic!();
+        };
+        assert_eq!(root, "other");
+        assert_eq!(key, vec!["www".to_string(), "_acme-challenge".to_string()]);
+        assert_eq!(join_dns_name(RecordRoot::Dns(root), key).unwrap(), "_acme-challenge.www.other");
+    }
 }
 
 pub fn join_dns_name(root: RecordRoot, path: RecordKey) -> Result<String, loga::Error> {
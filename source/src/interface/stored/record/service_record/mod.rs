@@ -0,0 +1,32 @@
+use {
+    super::record_utils::RecordKey,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+pub mod v1;
+
+pub use v1 as latest;
+
+pub const KEY_SUFFIX_SERVICES: &str = "services";
+
+pub fn build_services_key(head: RecordKey) -> RecordKey {
+    let mut out = head;
+    out.push(KEY_SUFFIX_SERVICES.to_string());
+    return out;
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Services {
+    V1(v1::Services),
+}
+
+impl Services {
+    pub fn latest(data: latest::Services) -> Self {
+        return Self::V1(data);
+    }
+}
@@ -0,0 +1,42 @@
+use {
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceProtocol {
+    Tcp,
+    Udp,
+    Sctp,
+}
+
+/// A single service endpoint.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Service {
+    /// Application protocol name, ex: `http`, `imap`, `minecraft`
+    pub name: String,
+    /// Transport protocol
+    pub protocol: ServiceProtocol,
+    pub port: u16,
+    /// Lower values should be tried first, like DNS SRV records. Defaults to 0.
+    #[serde(default)]
+    pub priority: u16,
+    /// Relative weight for choosing between services with the same priority, like DNS
+    /// SRV records. Defaults to 0.
+    #[serde(default)]
+    pub weight: u16,
+    /// Human-readable description of the service
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// A list of services available at the host of the same path (the A/AAAA records
+/// with the same key prefix).
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Services(pub Vec<Service>);
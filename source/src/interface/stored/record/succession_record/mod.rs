@@ -0,0 +1,44 @@
+use {
+    super::record_utils::RecordKey,
+    crate::interface::stored::identity::Identity,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+pub mod v1;
+
+pub use v1 as latest;
+
+pub const KEY_SUFFIX_SUCCESSION: &str = "succession";
+
+/// The succession record is only meaningful at the identity root.
+pub fn build_succession_key() -> RecordKey {
+    return vec![KEY_SUFFIX_SUCCESSION.to_string()];
+}
+
+/// The maximum number of successions followed when resolving an identity, to
+/// bound the work done for long or malicious chains.
+pub const SUCCESSION_MAX_HOPS: usize = 4;
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Succession {
+    V1(v1::Succession),
+}
+
+impl Succession {
+    pub fn latest(data: latest::Succession) -> Self {
+        return Self::V1(data);
+    }
+
+    /// Verify the record was published by `predecessor` and return the successor
+    /// identity.
+    pub fn verify(&self, predecessor: &Identity) -> Result<Identity, loga::Error> {
+        match self {
+            Succession::V1(s) => return s.verify(predecessor),
+        }
+    }
+}
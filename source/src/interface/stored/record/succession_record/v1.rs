@@ -0,0 +1,73 @@
+use {
+    crate::{
+        interface::stored::identity::Identity,
+        utils::blob::Blob,
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    loga::ea,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+/// The signed body of a succession record.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SuccessionContent {
+    /// The identity being replaced
+    pub predecessor: Identity,
+    /// The identity replacing it
+    pub successor: Identity,
+    pub issued: DateTime<Utc>,
+}
+
+/// A statement that an identity has been replaced by another identity (ex: after
+/// key rotation). Lookups of the predecessor are redirected to the successor.
+///
+/// The content is signed by both identities, so the successor can't be claimed by
+/// a third party and the predecessor owner proves they authorized the move.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Succession {
+    /// JSON-serialized `SuccessionContent`
+    pub content: String,
+    /// Signature of `content` by the predecessor (zbase32)
+    #[schemars(with = "String")]
+    pub predecessor_signature: Blob,
+    /// Signature of `content` by the successor (zbase32)
+    #[schemars(with = "String")]
+    pub successor_signature: Blob,
+}
+
+impl Succession {
+    pub fn verify(&self, predecessor: &Identity) -> Result<Identity, loga::Error> {
+        let content =
+            serde_json::from_str::<SuccessionContent>(
+                &self.content,
+            ).map_err(|e| loga::err_with("Succession content isn't valid JSON", ea!(err = e)))?;
+        if content.predecessor != *predecessor {
+            return Err(
+                loga::err_with(
+                    "Succession record predecessor doesn't match identity",
+                    ea!(predecessor = content.predecessor, identity = predecessor),
+                ),
+            );
+        }
+        if content.successor == *predecessor {
+            return Err(loga::err("Succession record names the identity as its own successor"));
+        }
+        predecessor
+            .verify(self.content.as_bytes(), &self.predecessor_signature)
+            .map_err(|e| loga::err_with("Invalid predecessor signature on succession record", ea!(err = e)))?;
+        content
+            .successor
+            .verify(self.content.as_bytes(), &self.successor_signature)
+            .map_err(|e| loga::err_with("Invalid successor signature on succession record", ea!(err = e)))?;
+        return Ok(content.successor);
+    }
+}
@@ -0,0 +1,534 @@
+//! Golden encodings of the versioned wire and stored types, in `golden/`. Nodes,
+//! publishers, and resolvers of different releases exchange and store these, so an
+//! encoding changing silently breaks mixed-version networks.
+//!
+//! Bincode encodings must match exactly. JSON encodings may gain fields (ex: with
+//! `#[serde(default)]`), but every field in the golden file must still be encoded
+//! the same way. Both must still decode.
+//!
+//! Missing golden files are written and the test fails, so check the new files in.
+//! Don't regenerate a file when its encoding changes; add a new version with its
+//! own golden cases instead. The `*_case` functions match every version so adding
+//! one doesn't compile until it has a case.
+use {
+    crate::{
+        interface::{
+            config::identity::LocalIdentitySecret,
+            stored::{
+                self,
+                announcement::Announcement,
+                identity::Identity,
+                node_identity::{
+                    NodeIdentity,
+                    NodeSecret,
+                    NodeSecretMethods,
+                },
+                record::RecordValue,
+                shared::SerialAddr,
+            },
+            wire,
+        },
+        utils::{
+            blob::{
+                Blob,
+                ToBlob,
+            },
+            signed::NodeIdentSignatureMethods,
+        },
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    serde::{
+        de::DeserializeOwned,
+        Serialize,
+    },
+    serde_json::json,
+    std::{
+        collections::HashSet,
+        fmt::Write,
+        path::PathBuf,
+    },
+};
+
+fn golden_dir() -> PathBuf {
+    return PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden");
+}
+
+fn to_hex(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, b) in data.iter().enumerate() {
+        if i > 0 && i % 32 == 0 {
+            out.push('\n');
+        }
+        write!(out, "{:02x}", b).unwrap();
+    }
+    out.push('\n');
+    return out;
+}
+
+fn from_hex(text: &str) -> Vec<u8> {
+    let text = text.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    return (0 .. text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i .. i + 2], 16).unwrap())
+        .collect();
+}
+
+/// Whether `have` has every field in `want` with the same value (`have` may have
+/// more object fields).
+fn json_contains(have: &serde_json::Value, want: &serde_json::Value) -> bool {
+    match (have, want) {
+        (serde_json::Value::Object(have), serde_json::Value::Object(want)) => {
+            return want.iter().all(|(k, w)| have.get(k).is_some_and(|h| json_contains(h, w)));
+        },
+        (serde_json::Value::Array(have), serde_json::Value::Array(want)) => {
+            return have.len() == want.len() && have.iter().zip(want).all(|(h, w)| json_contains(h, w));
+        },
+        _ => return have == want,
+    }
+}
+
+#[derive(Default)]
+struct Golden {
+    written: Vec<String>,
+}
+
+impl Golden {
+    /// Compare an encoding with the golden file, and check that `decode` (decoding
+    /// then reencoding) still reads the golden encoding.
+    fn check_bytes(&mut self, name: &str, data: &[u8], decode: impl Fn(&[u8]) -> Result<Vec<u8>, String>) {
+        let path = golden_dir().join(format!("{}.bin.hex", name));
+        let Ok(golden) = std::fs::read_to_string(&path) else {
+            std::fs::create_dir_all(golden_dir()).unwrap();
+            std::fs::write(&path, to_hex(data)).unwrap();
+            self.written.push(path.to_string_lossy().to_string());
+            return;
+        };
+        let golden = from_hex(&golden);
+        assert_eq!(
+            to_hex(data),
+            to_hex(&golden),
+            "Bincode encoding of [{}] changed, which breaks compatibility; add a new version instead",
+            name
+        );
+        match decode(&golden) {
+            Ok(reencoded) => assert_eq!(
+                to_hex(&reencoded),
+                to_hex(&golden),
+                "Golden encoding of [{}] doesn't reencode the same",
+                name
+            ),
+            Err(e) => panic!("Golden encoding of [{}] no longer decodes: {}", name, e),
+        }
+    }
+
+    fn check_bincode<T: Serialize + DeserializeOwned>(&mut self, name: &str, value: &T) {
+        self.check_bytes(
+            name,
+            &bincode::serialize(value).unwrap(),
+            |data| bincode::deserialize::<T>(data)
+                .map(|v| bincode::serialize(&v).unwrap())
+                .map_err(|e| e.to_string()),
+        );
+    }
+
+    fn check_json<T: Serialize + DeserializeOwned>(&mut self, name: &str, value: &T) {
+        let path = golden_dir().join(format!("{}.json", name));
+        let have = serde_json::to_value(value).unwrap();
+        let Ok(golden) = std::fs::read(&path) else {
+            std::fs::create_dir_all(golden_dir()).unwrap();
+            std::fs::write(&path, serde_json::to_string_pretty(&have).unwrap() + "\n").unwrap();
+            self.written.push(path.to_string_lossy().to_string());
+            return;
+        };
+        let golden = serde_json::from_slice::<serde_json::Value>(&golden).unwrap();
+        assert!(
+            json_contains(&have, &golden),
+            "JSON encoding of [{}] changed, which breaks compatibility; add a new version instead\nhave: {}\nwant: {}",
+            name,
+            have,
+            golden
+        );
+        if let Err(e) = serde_json::from_value::<T>(golden) {
+            panic!("Golden JSON of [{}] no longer decodes: {}", name, e);
+        }
+    }
+
+    fn finish(self) {
+        if !self.written.is_empty() {
+            panic!("Wrote new golden files, check them in and rerun: {:?}", self.written);
+        }
+    }
+}
+
+fn stamp() -> DateTime<Utc> {
+    return DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+}
+
+fn identity_secret() -> LocalIdentitySecret {
+    return serde_json::from_value(json!({
+        "v1": {
+            "ed25519": zbase32::encode_full_bytes(&[7u8; 32])
+        }
+    })).unwrap();
+}
+
+fn node_secret() -> NodeSecret {
+    return NodeSecret::V1(
+        stored::node_identity::v1::NodeSecret::from_bytes(&bincode::serialize(&(0u32, [9u8; 32])).unwrap()).unwrap(),
+    );
+}
+
+fn addr() -> SerialAddr {
+    return SerialAddr("192.0.2.1:43890".parse().unwrap());
+}
+
+fn addr6() -> SerialAddr {
+    return SerialAddr("[2001:db8::1]:43890".parse().unwrap());
+}
+
+fn announcement() -> Announcement {
+    let message = bincode::serialize(&stored::announcement::v1::AnnouncementContent {
+        publishers: vec![stored::announcement::v1::AnnouncementPublisher {
+            addr: addr(),
+            cert_hash: vec![3u8; 32].blob(),
+        }, stored::announcement::v1::AnnouncementPublisher {
+            addr: addr6(),
+            cert_hash: vec![3u8; 32].blob(),
+        }],
+        announced: stamp(),
+    }).unwrap().blob();
+    return Announcement::V1(stored::announcement::v1::Announcement {
+        signature: identity_secret().sign(&message),
+        message: message,
+        _p: Default::default(),
+    });
+}
+
+fn identity_case(v: &Identity) -> &'static str {
+    match v {
+        Identity::V1(_) => return "identity_v1",
+    }
+}
+
+fn node_identity_case(v: &NodeIdentity) -> &'static str {
+    match v {
+        NodeIdentity::V1(_) => return "node_identity_v1",
+    }
+}
+
+fn announcement_case(v: &Announcement) -> &'static str {
+    match v {
+        Announcement::V1(_) => return "announcement_v1",
+    }
+}
+
+fn protocol_case(v: &wire::node::Protocol) -> &'static str {
+    match v {
+        wire::node::Protocol::V1(_) => return "node_v1",
+        wire::node::Protocol::V2(_) => return "node_v2",
+    }
+}
+
+fn node_state_case(v: &wire::node::NodeState) -> &'static str {
+    match v {
+        wire::node::NodeState::V1(_) => return "node_state_v1",
+    }
+}
+
+fn record_value_case(v: &RecordValue) -> &'static str {
+    match v {
+        RecordValue::V1(_) => return "record_value_v1",
+    }
+}
+
+fn resolve_request_case(v: &wire::resolve::ResolveRequest) -> &'static str {
+    match v {
+        wire::resolve::ResolveRequest::V1(_) => return "resolve_request_v1",
+        wire::resolve::ResolveRequest::V1Signed(_) => return "resolve_request_v1_signed",
+    }
+}
+
+fn check_protocol(golden: &mut Golden, names: &mut HashSet<String>, message: &str, p: wire::node::Protocol) {
+    let name = format!("{}_{}", protocol_case(&p), message);
+    assert!(names.insert(name.clone()), "Duplicate golden case {}", name);
+    golden.check_bytes(
+        &name,
+        &p.to_bytes(),
+        |data| wire::node::Protocol::from_bytes(data).map(|p| p.to_bytes()).map_err(|e| e.to_string()),
+    );
+}
+
+#[test]
+fn test_golden_stored() {
+    let mut golden = Golden::default();
+    let identity = identity_secret().identity();
+    golden.check_bytes(
+        identity_case(&identity),
+        &identity.to_bytes(),
+        |data| Identity::from_bytes(data).map(|v| v.to_bytes()).map_err(|e| e.to_string()),
+    );
+    golden.check_json(identity_case(&identity), &identity);
+    let node_identity = node_secret().get_identity();
+    golden.check_bytes(
+        node_identity_case(&node_identity),
+        &node_identity.to_bytes(),
+        |data| NodeIdentity::from_bytes(data).map(|v| v.to_bytes()).map_err(|e| e.to_string()),
+    );
+    let announcement = announcement();
+    golden.check_bincode(announcement_case(&announcement), &announcement);
+    golden.check_json(announcement_case(&announcement), &announcement);
+    let record_value = RecordValue::V1(stored::record::v1::RecordValue {
+        ttl: 60,
+        data: Some(json!(["192.0.2.1"])),
+    });
+    golden.check_json(record_value_case(&record_value), &record_value);
+    let node_state = wire::node::NodeState::V1(wire::node::v1::NodeState {
+        node: wire::node::v1::NodeInfo {
+            ident: node_identity,
+            address: addr(),
+        },
+        unresponsive: false,
+        alt_addresses: vec![addr6()],
+        max_datagram: Some(1024),
+    });
+    golden.check_json(node_state_case(&node_state), &node_state);
+    golden.finish();
+}
+
+#[test]
+fn test_golden_node() {
+    let mut golden = Golden::default();
+    let mut names = HashSet::new();
+    let secret = node_secret();
+    let sender = secret.get_identity();
+    let identity = identity_secret().identity();
+    let challenge = vec![5u8; 32].blob();
+    let nodes = vec![wire::node::v1::NodeInfo {
+        ident: sender,
+        address: addr(),
+    }, wire::node::v1::NodeInfo {
+        ident: sender,
+        address: addr6(),
+    }];
+    let value = announcement();
+
+    // V1
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "find_request",
+        wire::node::Protocol::V1(wire::node::v1::Message::FindRequest(wire::node::v1::FindRequest {
+            sender: sender,
+            challenge: challenge.clone(),
+            goal: wire::node::v1::FindGoal::Identity(identity),
+        })),
+    );
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "find_response",
+        wire::node::Protocol::V1(wire::node::v1::Message::FindResponse(wire::node::v1::FindResponse {
+            sender: sender,
+            content: wire::node::v1::BincodeSignature::sign(&secret, wire::node::v1::FindResponseContent {
+                goal: wire::node::v1::FindGoal::Identity(identity),
+                challenge: challenge.clone(),
+                sender: sender,
+                nodes: nodes.clone(),
+                value: Some(value.clone()),
+            }),
+        })),
+    );
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "store",
+        wire::node::Protocol::V1(wire::node::v1::Message::Store(wire::node::v1::StoreRequest {
+            key: identity,
+            value: value.clone(),
+        })),
+    );
+    check_protocol(&mut golden, &mut names, "ping", wire::node::Protocol::V1(wire::node::v1::Message::Ping));
+    check_protocol(&mut golden, &mut names, "pung", wire::node::Protocol::V1(wire::node::v1::Message::Pung(sender)));
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "challenge_response",
+        wire::node::Protocol::V1(wire::node::v1::Message::ChallengeResponse(wire::node::v1::ChallengeResponse {
+            sender: sender,
+            signature: secret.sign(&challenge),
+        })),
+    );
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "goodbye",
+        wire::node::Protocol::V1(wire::node::v1::Message::Goodbye(wire::node::v1::Goodbye {
+            sender: sender,
+            content: wire::node::v1::BincodeSignature::sign(&secret, wire::node::v1::GoodbyeContent {
+                sender: sender,
+                stamp: stamp(),
+            }),
+        })),
+    );
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "error",
+        wire::node::Protocol::V1(wire::node::v1::Message::Error(wire::node::v1::ErrorResponse {
+            sender: sender,
+            request: wire::node::v1::ErrorRequest::Store(identity),
+            code: wire::node::v1::ErrorCode::BadSignature,
+        })),
+    );
+
+    // V2
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "find_response",
+        wire::node::Protocol::V2(wire::node::v2::Message::FindResponse(wire::node::v2::FindResponse {
+            sender: sender,
+            content: wire::node::v2::BincodeSignature::sign(&secret, wire::node::v2::FindResponseContent {
+                goal: wire::node::v2::FindGoal::Identity(identity),
+                challenge: challenge.clone(),
+                sender: sender,
+                nodes: nodes.clone(),
+                value: Some(value.clone()),
+                stamp: stamp(),
+            }),
+        })),
+    );
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "challenge_response",
+        wire::node::Protocol::V2(wire::node::v2::Message::ChallengeResponse(wire::node::v2::ChallengeResponse {
+            sender: sender,
+            content: wire::node::v2::BincodeSignature::sign(&secret, wire::node::v2::ChallengeResponseContent {
+                challenge: challenge.clone(),
+                stamp: stamp(),
+            }),
+        })),
+    );
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "versions",
+        wire::node::Protocol::V2(wire::node::v2::Message::Versions(wire::node::v2::Versions {
+            sender: sender,
+            versions: vec![1, 2],
+        })),
+    );
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "mtu_probe",
+        wire::node::Protocol::V2(wire::node::v2::Message::MtuProbe(wire::node::v2::MtuProbe {
+            sender: sender,
+            size: 512,
+            padding: Blob::new(16),
+        })),
+    );
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "find_value_response",
+        wire::node::Protocol::V2(wire::node::v2::Message::FindValueResponse(wire::node::v2::FindValueResponse {
+            sender: sender,
+            identity: identity,
+            challenge: challenge.clone(),
+            value: value.clone(),
+        })),
+    );
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "ping_nonce",
+        wire::node::Protocol::V2(wire::node::v2::Message::PingNonce(challenge.clone())),
+    );
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "signed_pung",
+        wire::node::Protocol::V2(wire::node::v2::Message::SignedPung(wire::node::v2::SignedPung {
+            sender: sender,
+            content: wire::node::v2::BincodeSignature::sign(
+                &secret,
+                wire::node::v2::PungContent { nonce: challenge.clone() },
+            ),
+        })),
+    );
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "transports",
+        wire::node::Protocol::V2(wire::node::v2::Message::Transports(wire::node::v2::Transports {
+            sender: sender,
+            quic: true,
+        })),
+    );
+    check_protocol(
+        &mut golden,
+        &mut names,
+        "transport_hints",
+        wire::node::Protocol::V2(wire::node::v2::Message::TransportHints(wire::node::v2::TransportHints {
+            sender: sender,
+            relayed: true,
+        })),
+    );
+    golden.finish();
+}
+
+#[test]
+fn test_golden_api() {
+    let mut golden = Golden::default();
+    let secret = identity_secret();
+    let identity = secret.identity();
+    let content = wire::api::publish::v1::PublishRequestContent {
+        missing_ttl: Some(5),
+        clear_all: false,
+        clear: [vec!["old".to_string()]].into_iter().collect(),
+        set: vec![(vec!["dns".to_string(), "a".to_string()], RecordValue::V1(stored::record::v1::RecordValue {
+            ttl: 60,
+            data: Some(json!(["192.0.2.1"])),
+        }))],
+        if_version: None,
+        published: Some(stamp()),
+    };
+    let message = serde_json::to_string(&content).unwrap();
+    golden.check_json("publish_request_v1", &wire::api::publish::v1::PublishRequest {
+        identity: identity,
+        content: wire::api::publish::v1::JsonSignature {
+            signature: secret.sign(message.as_bytes()),
+            message: message,
+            _p: Default::default(),
+        },
+    });
+    golden.check_json("announce_request_v1", &wire::api::publish::v1::AnnounceRequest {
+        identity: identity,
+        announcement: announcement(),
+    });
+    let request = wire::resolve::v1::ResolveRequest {
+        ident: identity,
+        keys: vec![vec!["dns".to_string(), "a".to_string()]],
+    };
+    let plain = wire::resolve::ResolveRequest::V1(request.clone());
+    golden.check_json(resolve_request_case(&plain), &plain);
+    let message = serde_json::to_string(&wire::resolve::v1::SignedResolveRequestContent {
+        requested: stamp(),
+        request: request,
+    }).unwrap();
+    let signed = wire::resolve::ResolveRequest::V1Signed(wire::resolve::v1::SignedResolveRequest {
+        resolver: identity,
+        content: wire::api::publish::v1::JsonSignature {
+            signature: secret.sign(message.as_bytes()),
+            message: message,
+            _p: Default::default(),
+        },
+    });
+    golden.check_json(resolve_request_case(&signed), &signed);
+    golden.finish();
+}
@@ -1,20 +1,294 @@
 use {
-    crate::interface::stored::identity::Identity,
+    crate::interface::stored::{
+        identity::Identity,
+        record::record_utils::RecordKey,
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    good_ormning_runtime::sqlite::GoodOrmningCustomString,
+    schemars::JsonSchema,
     serde::{
         Deserialize,
         Serialize,
     },
 };
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct AdminAllowIdentityBody {
     pub group: String,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct AdminIdentity {
     pub identity: Identity,
     pub group: String,
 }
+
+/// Publisher maintenance mode. While enabled, resolve requests from other nodes are
+/// refused and resolvers serve stale cached values instead.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AdminMaintenance {
+    /// How long resolvers should wait before retrying, in seconds
+    pub retry_after: u32,
+}
+
+/// Publisher usage by one identity over a report period.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AdminIdentityUsage {
+    pub identity: Identity,
+    /// Keys with values currently published
+    pub records: u64,
+    /// Resolve requests for the identity served in the period
+    pub resolves: u64,
+    /// Response body bytes sent for those requests
+    pub bytes: u64,
+    /// When the identity's current announcement was published, or null if it isn't
+    /// announced by this publisher
+    pub announced: Option<DateTime<Utc>>,
+}
+
+/// Per-identity publisher usage, for accounting on shared publishers.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AdminUsageReport {
+    /// Start of the earliest rollup period included. Counts are rolled up hourly, so
+    /// this may be up to an hour before the requested start.
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Announced identities and identities with resolves in the period, ordered by
+    /// identity
+    pub identities: Vec<AdminIdentityUsage>,
+}
+
+impl AdminUsageReport {
+    /// The identities as CSV with a header row.
+    pub fn to_csv(&self) -> String {
+        let mut out = "identity,records,resolves,bytes,announced\n".to_string();
+        for i in &self.identities {
+            out.push_str(
+                &format!(
+                    "{},{},{},{},{}\n",
+                    i.identity,
+                    i.records,
+                    i.resolves,
+                    i.bytes,
+                    i.announced.map(|a| a.to_rfc3339()).unwrap_or_default()
+                ),
+            );
+        }
+        return out;
+    }
+}
+
+/// A publisher retention event.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminAuditEvent {
+    /// The identity hasn't announced or published values within the retention period
+    Dormant,
+    /// The identity announced or published values again while dormant
+    Reactivated,
+    /// The identity's announcement and values were deleted after being dormant for the
+    /// purge period
+    Purged,
+}
+
+impl GoodOrmningCustomString<AdminAuditEvent> for AdminAuditEvent {
+    fn to_sql<'a>(value: &'a AdminAuditEvent) -> std::borrow::Cow<'a, str> {
+        return serde_json::to_string(value).unwrap().into();
+    }
+
+    fn from_sql(value: String) -> Result<AdminAuditEvent, String> {
+        return serde_json::from_str(&value).map_err(|e| e.to_string());
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AdminAuditEntry {
+    pub time: DateTime<Utc>,
+    pub identity: Identity,
+    pub event: AdminAuditEvent,
+}
+
+/// A hosted identity's record publishing the address or certificate in a reverse
+/// lookup.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AdminReverseMatch {
+    pub identity: Identity,
+    pub key: RecordKey,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PublisherWatchdogProblem {
+    /// No announcement for the identity was found in the network
+    Missing,
+    /// The announcement in the network doesn't list this publisher. The identity may
+    /// have moved to other publishers, or its key may have been used by someone else.
+    Unexpected {
+        /// The addresses of the publishers the announcement lists
+        publishers: Vec<String>,
+        announced: DateTime<Utc>,
+    },
+    /// The announcement in the network couldn't be parsed
+    Malformed,
+}
+
+/// Also the body of watchdog webhook requests.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PublisherWatchdogAlert {
+    pub identity: Identity,
+    /// When the problem was first seen
+    pub since: DateTime<Utc>,
+    pub problem: PublisherWatchdogProblem,
+}
+
+/// The state of the publisher watchdog, which checks that announcements in the
+/// network for hosted identities list this publisher.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PublisherWatchdogStatus {
+    /// Whether the watchdog is configured
+    pub enabled: bool,
+    /// When the last check finished
+    pub last_check: Option<DateTime<Utc>>,
+    /// Current problems. Alerts for identities that are still hosted are removed once
+    /// a check finds no problem; others are kept until cleared.
+    pub alerts: Vec<PublisherWatchdogAlert>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct RecentError {
+    pub time: DateTime<Utc>,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct DaemonSubsystems {
+    pub publisher: bool,
+    pub resolver: bool,
+    pub dns_bridge: bool,
+    pub api: bool,
+    pub content: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct DaemonStatus {
+    pub version: String,
+    pub started: DateTime<Utc>,
+    pub uptime_seconds: i64,
+    /// True if the node has at least one responsive neighbor and no critical tasks
+    /// have failed
+    pub healthy: bool,
+    pub responsive_neighbors: usize,
+    pub unresponsive_neighbors: usize,
+    pub subsystems: DaemonSubsystems,
+    /// Addresses of all listening sockets, including unix sockets
+    pub listen_addrs: Vec<String>,
+    pub node_identity: String,
+    /// The host identity, used for self-publishing and TLS certs
+    pub identity: Identity,
+    /// Identities announced by this node's publisher, if enabled
+    pub announced_identities: Vec<Identity>,
+    /// Recent warnings, oldest first
+    pub recent_errors: Vec<RecentError>,
+    /// Critical tasks that exited with an error. The node may not be working
+    /// properly if any are listed.
+    pub failed_tasks: Vec<TaskStatus>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskKind {
+    /// Runs once until it exits
+    Task,
+    /// Runs once, and the node shuts down if it exits with an error
+    Critical,
+    /// Runs at an interval
+    Periodic,
+    /// Runs for each item from a queue
+    Stream,
+}
+
+/// The state of a background task.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TaskStatus {
+    pub name: String,
+    pub kind: TaskKind,
+    pub started: DateTime<Utc>,
+    /// For tasks that run once, false once the task exits
+    pub running: bool,
+    /// For periodic and stream tasks, the number of completed runs
+    pub runs: u64,
+    /// For periodic and stream tasks, when the last completed run started
+    pub last_run: Option<DateTime<Utc>>,
+    /// For periodic and stream tasks, how long the last completed run took
+    pub last_run_ms: Option<u64>,
+    /// When the task exited, if it did
+    pub exited: Option<DateTime<Utc>>,
+    /// The error the task exited with, if any
+    pub error: Option<String>,
+}
+
+/// The state of periodic database backups.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct BackupStatus {
+    /// Whether backups are configured
+    pub enabled: bool,
+    /// When the last backup attempt started
+    pub last_attempt: Option<DateTime<Utc>>,
+    /// When the last successful backup was taken
+    pub last_success: Option<DateTime<Utc>>,
+    /// The directory of the last successful backup
+    pub last_success_path: Option<String>,
+    /// The error from the last backup attempt, if it failed
+    pub last_error: Option<String>,
+    /// Backups currently kept, oldest first
+    pub kept: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfTlsCertifier {
+    /// Signed by the `certipasta` `.s` CA
+    Certipasta,
+    /// Self-signed, with the identity signature extension
+    SelfSigned,
+}
+
+/// The state of the node's own TLS certificate, which is renewed some time before
+/// it expires.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SelfTlsStatus {
+    /// Where certificates are obtained
+    pub certifier: SelfTlsCertifier,
+    /// When the certificate being served expires
+    pub current_expiry: Option<DateTime<Utc>>,
+    /// When a renewed certificate that's published but not served yet expires
+    pub pending_expiry: Option<DateTime<Utc>>,
+    /// When the renewed certificate will start being served
+    pub pending_after: Option<DateTime<Utc>>,
+    /// When the next renewal will be requested
+    pub next_renewal: Option<DateTime<Utc>>,
+    /// When a certificate was last requested
+    pub last_attempt: Option<DateTime<Utc>>,
+    /// When a certificate was last received
+    pub last_success: Option<DateTime<Utc>>,
+    /// The error from the last request, if it failed
+    pub last_error: Option<String>,
+}
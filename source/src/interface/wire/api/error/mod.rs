@@ -0,0 +1,3 @@
+pub mod v1;
+
+pub use v1 as latest;
@@ -0,0 +1,74 @@
+use {
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+};
+
+/// What went wrong with an API request, for clients to branch on. The message is
+/// for humans and may change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The request was malformed or didn't match the schema (`400`)
+    BadRequest,
+    /// The request signature couldn't be verified (`400`)
+    BadSignature,
+    /// The request timestamp is too far from the server's current time (`400`)
+    Expired,
+    /// The requester isn't allowed to do this (`401`)
+    Unauthorized,
+    /// The requested thing doesn't exist or isn't available (`404`)
+    NotFound,
+    /// Too many requests, try again later (`429`)
+    RateLimited,
+    /// The publisher database is over its size limit (`507`)
+    StorageFull,
+    /// The publisher's publish policy rejected the request (`403`)
+    Rejected,
+    /// The node is in maintenance mode, try again after `Retry-After` (`503`)
+    Unavailable,
+    /// Something went wrong on the server (`503`)
+    Internal,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 10] =
+        [
+            ErrorCode::BadRequest,
+            ErrorCode::BadSignature,
+            ErrorCode::Expired,
+            ErrorCode::Unauthorized,
+            ErrorCode::NotFound,
+            ErrorCode::RateLimited,
+            ErrorCode::StorageFull,
+            ErrorCode::Rejected,
+            ErrorCode::Unavailable,
+            ErrorCode::Internal,
+        ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => return "bad_request",
+            ErrorCode::BadSignature => return "bad_signature",
+            ErrorCode::Expired => return "expired",
+            ErrorCode::Unauthorized => return "unauthorized",
+            ErrorCode::NotFound => return "not_found",
+            ErrorCode::RateLimited => return "rate_limited",
+            ErrorCode::StorageFull => return "storage_full",
+            ErrorCode::Rejected => return "rejected",
+            ErrorCode::Unavailable => return "unavailable",
+            ErrorCode::Internal => return "internal",
+        }
+    }
+}
+
+/// The body of error responses from the API (except where an endpoint documents a
+/// different body, like `409` from `publish`).
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ApiError {
+    pub error: ErrorCode,
+    pub message: String,
+}
@@ -1,3 +1,5 @@
 pub mod publish;
 pub mod resolve;
 pub mod admin;
+pub mod error;
+pub mod openapi;
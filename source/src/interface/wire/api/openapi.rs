@@ -0,0 +1,765 @@
+use {
+    crate::{
+        interface::{
+            stored::identity::Identity,
+            wire,
+        },
+        service::{
+            content::ContentStats,
+            identity_status::IdentityStatus,
+            node::{
+                Census,
+                HealthDetail,
+                TrafficReport,
+            },
+            publisher::API_ROUTE_PUBLISH,
+            resolver::{
+                dns::DnsStats,
+                CacheEntry,
+                CacheStats,
+                API_ROUTE_RESOLVE,
+            },
+            WatchSnapshot,
+        },
+        utils::db_util::DbUsage,
+    },
+    schemars::{
+        gen::{
+            SchemaGenerator,
+            SchemaSettings,
+        },
+        JsonSchema,
+    },
+    serde_json::{
+        json,
+        Map,
+        Value,
+    },
+};
+
+pub const API_ROUTE_OPENAPI: &str = "openapi.json";
+
+fn json_content<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    return json!({
+        "application/json": {
+            "schema": gen.subschema_for::<T>()
+        }
+    });
+}
+
+fn json_body<T: JsonSchema>(gen: &mut SchemaGenerator) -> Value {
+    return json!({
+        "required": true,
+        "content": json_content::<T>(gen),
+    });
+}
+
+fn json_response<T: JsonSchema>(gen: &mut SchemaGenerator, description: &str) -> Value {
+    return json!({
+        "description": description,
+        "content": json_content::<T>(gen),
+    });
+}
+
+fn empty_response(description: &str) -> Value {
+    return json!({
+        "description": description
+    });
+}
+
+/// An error response with an `ApiError` body. The schema is added to the
+/// components in `build_openapi`.
+fn error_response(description: &str) -> Value {
+    return json!({
+        "description": description,
+        "content": {
+            "application/json": {
+                "schema": {
+                    "$ref": "#/components/schemas/ApiError"
+                }
+            }
+        },
+    });
+}
+
+fn param(location: &str, name: &str, description: &str, required: bool) -> Value {
+    return json!({
+        "name": name,
+        "in": location,
+        "description": description,
+        "required": required,
+        "schema": {
+            "type": "string"
+        },
+    });
+}
+
+fn identity_param() -> Value {
+    return param("path", "identity", "Identity (zbase32 string)", true);
+}
+
+fn after_param() -> Value {
+    return param("query", "after", "Return entries after this one, for paging", false);
+}
+
+fn prefix_param() -> Value {
+    return param("query", "prefix", "Only return entries starting with this", false);
+}
+
+fn limit_param() -> Value {
+    return param("query", "limit", "Maximum entries to return, defaults to 50, up to 1000", false);
+}
+
+struct Operation {
+    summary: &'static str,
+    admin: bool,
+    parameters: Vec<Value>,
+    body: Option<Value>,
+    responses: Vec<(u16, Value)>,
+}
+
+impl Operation {
+    fn build(self) -> Value {
+        let mut responses = Map::new();
+        for (code, resp) in self.responses {
+            responses.insert(code.to_string(), resp);
+        }
+        responses.entry("400".to_string()).or_insert_with(|| error_response("Invalid request"));
+        if self.admin {
+            responses.insert("401".to_string(), error_response("Missing or invalid admin token"));
+        }
+        responses.insert("503".to_string(), error_response("Internal error"));
+        let mut out = Map::new();
+        out.insert("summary".to_string(), json!(self.summary));
+        if !self.parameters.is_empty() {
+            out.insert("parameters".to_string(), Value::Array(self.parameters));
+        }
+        if let Some(body) = self.body {
+            out.insert("requestBody".to_string(), body);
+        }
+        out.insert("responses".to_string(), Value::Object(responses));
+        if self.admin {
+            out.insert("security".to_string(), json!([{
+                "admin_token": []
+            }]));
+        }
+        return Value::Object(out);
+    }
+}
+
+/// Generate an OpenAPI 3 specification for all endpoints served on the
+/// `spagh-node` API port. Some endpoints are only available when the
+/// corresponding subsystem (publisher, resolver, content) is enabled.
+pub fn build_openapi() -> Value {
+    let mut gen = SchemaSettings::openapi3().into_generator();
+    let mut paths = Map::new();
+    let mut add = |path: String, method: &str, op: Operation| {
+        paths
+            .entry(path)
+            .or_insert_with(|| Value::Object(Map::new()))
+            .as_object_mut()
+            .unwrap()
+            .insert(method.to_string(), op.build());
+    };
+
+    // General
+    add("/health".to_string(), "get", Operation {
+        summary: "Check that the node is running",
+        admin: false,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, empty_response("The node is running"))],
+    });
+    add(format!("/{}", API_ROUTE_OPENAPI), "get", Operation {
+        summary: "Get this OpenAPI specification",
+        admin: false,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, empty_response("OpenAPI specification JSON"))],
+    });
+
+    // Resolver
+    add(format!("/{}/v1/{{identity}}", API_ROUTE_RESOLVE), "get", Operation {
+        summary: "Resolve record values for an identity",
+        admin: false,
+        parameters: vec![
+            identity_param(),
+            param(
+                "query",
+                "keys",
+                "The entire query string is a comma separated list of url-encoded record keys, each a dotted list of key segments",
+                false,
+            )
+        ],
+        body: None,
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::resolve::v1::ResolveResp>(
+                    &mut gen,
+                    "Values for each requested key. With the `X-Spagh-Sign` header, a `SignedResolveResp` instead",
+                ),
+            )
+        ],
+    });
+    add(format!("/{}/dns-query", API_ROUTE_RESOLVE), "get", Operation {
+        summary: "Resolve a .s name as DNS records, in the application/dns-json (DNS-over-HTTPS JSON) format",
+        admin: false,
+        parameters: vec![
+            param("query", "name", "The name to look up", true),
+            param("query", "type", "Record type name or number, defaults to A", false)
+        ],
+        body: None,
+        responses: vec![
+            (200, json_response::<wire::api::resolve::v1::DnsJsonResponse>(&mut gen, "DNS response and records"))
+        ],
+    });
+    add(format!("/{}/suspect", API_ROUTE_RESOLVE), "post", Operation {
+        summary: "Report resolved values that seem wrong, so the resolver looks them up again in the background",
+        admin: false,
+        parameters: vec![],
+        body: Some(json_body::<wire::api::resolve::v1::SuspectReport>(&mut gen)),
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::resolve::v1::SuspectReportResponse>(
+                    &mut gen,
+                    "Whether the report was accepted",
+                ),
+            )
+        ],
+    });
+    add(format!("/{}/health", API_ROUTE_RESOLVE), "get", Operation {
+        summary: "Check that the resolver can reach the DHT, upstream DNS servers, and its cache database",
+        admin: false,
+        parameters: vec![],
+        body: None,
+        responses: vec![
+            (200, json_response::<wire::api::resolve::v1::ResolverHealth>(&mut gen, "No component failed")),
+            (
+                503,
+                json_response::<wire::api::resolve::v1::ResolverHealth>(
+                    &mut gen,
+                    "A component failed; the body has the status of each",
+                ),
+            )
+        ],
+    });
+    add(format!("/{}/jsonrpc", API_ROUTE_RESOLVE), "post", Operation {
+        summary: "JSON-RPC 2.0 interface to the resolver, if enabled in the API config",
+        admin: false,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, empty_response("A JSON-RPC response, or an empty body for notifications"))],
+    });
+
+    // Publisher
+    add(format!("/{}/v1/announce", API_ROUTE_PUBLISH), "post", Operation {
+        summary: "Announce this publisher as the authority for an identity",
+        admin: false,
+        parameters: vec![],
+        body: Some(json_body::<wire::api::publish::v1::AnnounceRequest>(&mut gen)),
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::publish::v1::AnnounceResponse>(
+                    &mut gen,
+                    "Announced; the body says how many DHT nodes acknowledged storing the announcement",
+                ),
+            )
+        ],
+    });
+    add(format!("/{}/v1/clear_identity", API_ROUTE_PUBLISH), "post", Operation {
+        summary: "Stop announcing and publishing all values for an identity",
+        admin: false,
+        parameters: vec![],
+        body: Some(json_body::<wire::api::publish::v1::DeleteAnnouncementRequest>(&mut gen)),
+        responses: vec![(200, empty_response("Cleared"))],
+    });
+    add(format!("/{}/v1/publish", API_ROUTE_PUBLISH), "post", Operation {
+        summary: "Set and clear published values for an identity",
+        admin: false,
+        parameters: vec![],
+        body: Some(json_body::<wire::api::publish::v1::PublishRequest>(&mut gen)),
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::publish::v1::RecordSetVersion>(
+                    &mut gen,
+                    "The new version of the identity's record set",
+                ),
+            ),
+            (
+                400,
+                json_response::<wire::api::publish::v1::RejectedRecords>(
+                    &mut gen,
+                    "Invalid request. If records were rejected for being too large, the body lists them.",
+                ),
+            ),
+            (401, error_response("Identity isn't allowed to publish here")),
+            (403, error_response("The publisher's publish policy rejected the request; the message has the reason")),
+            (
+                409,
+                json_response::<wire::api::publish::v1::RecordSetVersion>(
+                    &mut gen,
+                    "`if_version` didn't match the current version; the body has the current version",
+                ),
+            ),
+            (507, error_response("The publisher database is over its size limit"))
+        ],
+    });
+    add(format!("/{}/v1/publish_batch", API_ROUTE_PUBLISH), "post", Operation {
+        summary: "Set and clear published values for multiple identities, each applied on its own",
+        admin: false,
+        parameters: vec![],
+        body: Some(json_body::<wire::api::publish::v1::PublishBatchRequest>(&mut gen)),
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::publish::v1::PublishBatchResponse>(
+                    &mut gen,
+                    "The outcome of each request, in request order",
+                ),
+            ),
+            (400, error_response("Invalid request, or more than 1000 requests"))
+        ],
+    });
+    add(format!("/{}/v1/version/{{identity}}", API_ROUTE_PUBLISH), "get", Operation {
+        summary: "Get the current version of the records published for an identity",
+        admin: false,
+        parameters: vec![identity_param()],
+        body: None,
+        responses: vec![
+            (200, json_response::<wire::api::publish::v1::RecordSetVersion>(&mut gen, "The current version"))
+        ],
+    });
+    add(format!("/{}/v1/announcement/{{identity}}", API_ROUTE_PUBLISH), "get", Operation {
+        summary: "Get the announcement for an identity stored on the publisher, signed by the identity",
+        admin: false,
+        parameters: vec![identity_param()],
+        body: None,
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::publish::v1::AnnouncementResponse>(
+                    &mut gen,
+                    "The announcement, if the identity announced this publisher",
+                ),
+            )
+        ],
+    });
+    add(format!("/{}/v1/export/{{identity}}", API_ROUTE_PUBLISH), "get", Operation {
+        summary: "Get the records published for an identity, signed by the publisher",
+        admin: false,
+        parameters: vec![identity_param()],
+        body: None,
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::publish::v1::SignedExport>(
+                    &mut gen,
+                    "The record set with the publisher's signature and cert",
+                ),
+            ),
+            (401, error_response("The publisher only serves values to authorized resolvers"))
+        ],
+    });
+    add(format!("/{}/v1/export/{{identity}}", API_ROUTE_PUBLISH), "post", Operation {
+        summary: "Get the records published for an identity, signed by the publisher, as an authorized resolver",
+        admin: false,
+        parameters: vec![identity_param()],
+        body: Some(json_body::<wire::api::publish::v1::ExportRequest>(&mut gen)),
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::publish::v1::SignedExport>(
+                    &mut gen,
+                    "The record set with the publisher's signature and cert",
+                ),
+            ),
+            (
+                400,
+                error_response(
+                    "The request signature is invalid or the request time is too far from the publisher's current time",
+                ),
+            ),
+            (401, error_response("The resolver isn't authorized or the request is for a different identity"))
+        ],
+    });
+    add(format!("/{}/v1/read_stats", API_ROUTE_PUBLISH), "post", Operation {
+        summary: "Get read statistics for the keys published for an identity",
+        admin: false,
+        parameters: vec![],
+        body: Some(json_body::<wire::api::publish::v1::ReadStatsRequest>(&mut gen)),
+        responses: vec![
+            (200, json_response::<Vec<wire::api::publish::v1::KeyReadStats>>(&mut gen, "Statistics for each key")),
+            (401, error_response("Identity isn't allowed to publish here")),
+            (404, error_response("The publisher doesn't track read statistics"))
+        ],
+    });
+    add(format!("/{}/v1/watch", API_ROUTE_PUBLISH), "post", Operation {
+        summary: "Wait for the record set published for an identity to change",
+        admin: false,
+        parameters: vec![],
+        body: Some(json_body::<wire::api::publish::v1::WatchRequest>(&mut gen)),
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::publish::v1::WatchResponse>(
+                    &mut gen,
+                    "The new record set, or `changed` false if nothing changed within a minute",
+                ),
+            ),
+            (401, error_response("Identity isn't allowed to publish here"))
+        ],
+    });
+    add(format!("/{}/v1/info", API_ROUTE_PUBLISH), "get", Operation {
+        summary: "Get publisher information needed for announcements",
+        admin: false,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, json_response::<wire::api::publish::v1::InfoResponse>(&mut gen, "Publisher information"))],
+    });
+    add(format!("/{}/jsonrpc", API_ROUTE_PUBLISH), "post", Operation {
+        summary: "JSON-RPC 2.0 interface to the publisher, if enabled in the API config",
+        admin: false,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, empty_response("A JSON-RPC response, or an empty body for notifications"))],
+    });
+
+    // Publisher admin
+    add(format!("/{}/admin/allowed_identities", API_ROUTE_PUBLISH), "get", Operation {
+        summary: "List identities allowed to publish",
+        admin: true,
+        parameters: vec![after_param(), prefix_param(), limit_param()],
+        body: None,
+        responses: vec![
+            (200, json_response::<Vec<wire::api::admin::v1::AdminIdentity>>(&mut gen, "A page of allowed identities"))
+        ],
+    });
+    add(format!("/{}/admin/allowed_identities/{{identity}}", API_ROUTE_PUBLISH), "post", Operation {
+        summary: "Allow an identity to publish",
+        admin: true,
+        parameters: vec![identity_param()],
+        body: Some(json_body::<wire::api::admin::v1::AdminAllowIdentityBody>(&mut gen)),
+        responses: vec![(200, empty_response("Allowed"))],
+    });
+    add(format!("/{}/admin/allowed_identities/{{identity}}", API_ROUTE_PUBLISH), "delete", Operation {
+        summary: "Disallow an identity from publishing and clear its published data",
+        admin: true,
+        parameters: vec![identity_param()],
+        body: None,
+        responses: vec![(200, empty_response("Disallowed"))],
+    });
+    add(format!("/{}/admin/keys/{{identity}}", API_ROUTE_PUBLISH), "get", Operation {
+        summary: "List keys published for an identity",
+        admin: true,
+        parameters: vec![identity_param(), after_param(), prefix_param(), limit_param()],
+        body: None,
+        responses: vec![(200, json_response::<Vec<String>>(&mut gen, "A page of dotted record keys"))],
+    });
+    add(format!("/{}/admin/maintenance", API_ROUTE_PUBLISH), "get", Operation {
+        summary: "Get the publisher maintenance mode",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![
+            (
+                200,
+                json_response::<Option<wire::api::admin::v1::AdminMaintenance>>(
+                    &mut gen,
+                    "The maintenance mode settings, or null if not in maintenance mode",
+                ),
+            )
+        ],
+    });
+    add(format!("/{}/admin/maintenance", API_ROUTE_PUBLISH), "post", Operation {
+        summary: "Start maintenance mode: refuse resolve requests from other nodes with 503 and Retry-After",
+        admin: true,
+        parameters: vec![],
+        body: Some(json_body::<wire::api::admin::v1::AdminMaintenance>(&mut gen)),
+        responses: vec![(200, empty_response("Maintenance mode started"))],
+    });
+    add(format!("/{}/admin/maintenance", API_ROUTE_PUBLISH), "delete", Operation {
+        summary: "Stop maintenance mode",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, empty_response("Maintenance mode stopped"))],
+    });
+    add(format!("/{}/admin/reannounce/{{identity}}", API_ROUTE_PUBLISH), "post", Operation {
+        summary: "Send a hosted identity's announcement to the network now, outside the regular schedule",
+        admin: true,
+        parameters: vec![identity_param()],
+        body: None,
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::publish::v1::AnnounceResponse>(
+                    &mut gen,
+                    "How many nodes the announcement was sent to and how many acknowledged storing it",
+                ),
+            ),
+            (404, error_response("The identity has no announcement on this publisher"))
+        ],
+    });
+    add(format!("/{}/admin/watchdog", API_ROUTE_PUBLISH), "get", Operation {
+        summary: "Get the publisher watchdog's last check time and current alerts",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::admin::v1::PublisherWatchdogStatus>(
+                    &mut gen,
+                    "The watchdog state and alerts, oldest first",
+                ),
+            )
+        ],
+    });
+    add(format!("/{}/admin/watchdog", API_ROUTE_PUBLISH), "delete", Operation {
+        summary: "Clear the publisher watchdog's alerts",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, empty_response("Alerts cleared"))],
+    });
+    add(format!("/{}/admin/announcements", API_ROUTE_PUBLISH), "get", Operation {
+        summary: "List identities announced by this publisher",
+        admin: true,
+        parameters: vec![
+            after_param(),
+            prefix_param(),
+            limit_param(),
+            param("query", "since", "Only announcements published at or after this time (RFC 3339)", false),
+            param("query", "until", "Only announcements published before this time (RFC 3339)", false)
+        ],
+        body: None,
+        responses: vec![(200, json_response::<Vec<Identity>>(&mut gen, "A page of announced identities"))],
+    });
+    add(format!("/{}/admin/usage", API_ROUTE_PUBLISH), "get", Operation {
+        summary: "Get per-identity records, resolves served, bytes sent, and announcement times over a time window",
+        admin: true,
+        parameters: vec![
+            param("query", "since", "Start of the window (RFC 3339). Defaults to 30 days before `until`.", false),
+            param("query", "until", "End of the window (RFC 3339). Defaults to now.", false),
+            param("query", "format", "`json` (default) or `csv`", false)
+        ],
+        body: None,
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::admin::v1::AdminUsageReport>(
+                    &mut gen,
+                    "The usage report, or CSV with a header row if `format` is `csv`",
+                ),
+            )
+        ],
+    });
+
+    add(format!("/{}/admin/reverse", API_ROUTE_PUBLISH), "get", Operation {
+        summary: "Find hosted identities publishing an IP address (A/AAAA records) or TLS cert",
+        admin: true,
+        parameters: vec![
+            param("query", "ip", "An IPv4 or IPv6 address", false),
+            param("query", "cert_hash", "The SHA-256 hash of a cert's SPKI, zbase32", false)
+        ],
+        body: None,
+        responses: vec![
+            (
+                200,
+                json_response::<Vec<wire::api::admin::v1::AdminReverseMatch>>(
+                    &mut gen,
+                    "Each identity and record key publishing it",
+                ),
+            )
+        ],
+    });
+
+    add(format!("/{}/admin/audit", API_ROUTE_PUBLISH), "get", Operation {
+        summary: "List identity retention events (identities going dormant, reactivating, or purged)",
+        admin: true,
+        parameters: vec![
+            param("query", "since", "Start of the window (RFC 3339). Defaults to 30 days before `until`.", false),
+            param("query", "until", "End of the window (RFC 3339). Defaults to now.", false)
+        ],
+        body: None,
+        responses: vec![
+            (200, json_response::<Vec<wire::api::admin::v1::AdminAuditEntry>>(&mut gen, "Events, oldest first"))
+        ],
+    });
+
+    // Node admin
+    add("/admin/health".to_string(), "get", Operation {
+        summary: "Get detailed node health information",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, json_response::<HealthDetail>(&mut gen, "Node health"))],
+    });
+    add("/admin/census".to_string(), "get", Operation {
+        summary: "Estimate the network size and check replication of announcements published by this node",
+        admin: true,
+        parameters: vec![
+            param(
+                "query",
+                "samples",
+                "Number of random coordinates to look up for the size estimate. Defaults to 8, max 64.",
+                false,
+            )
+        ],
+        body: None,
+        responses: vec![(200, json_response::<Census>(&mut gen, "Census results"))],
+    });
+    add("/admin/traffic".to_string(), "get", Operation {
+        summary: "Get DHT message and byte counts per peer and message type",
+        admin: true,
+        parameters: vec![
+            param(
+                "query",
+                "hours",
+                "Include traffic from this many hours ago. Defaults to 24, max 168.",
+                false,
+            )
+        ],
+        body: None,
+        responses: vec![(200, json_response::<TrafficReport>(&mut gen, "Traffic counts"))],
+    });
+    add("/admin/tasks".to_string(), "get", Operation {
+        summary: "List the node's background tasks, with run times and exit errors",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![
+            (200, json_response::<Vec<wire::api::admin::v1::TaskStatus>>(&mut gen, "Tasks, ordered by name"))
+        ],
+    });
+    add("/admin/disk_usage".to_string(), "get", Operation {
+        summary: "Get disk space used by each of the node's databases",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, json_response::<Vec<DbUsage>>(&mut gen, "Usage for each database"))],
+    });
+    add("/admin/backup".to_string(), "get", Operation {
+        summary: "Get the status of periodic database backups",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::admin::v1::BackupStatus>(&mut gen, "The last backup attempt and kept backups"),
+            )
+        ],
+    });
+    add("/admin/certs".to_string(), "get", Operation {
+        summary: "Get the status of the node's own TLS certificate renewal",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![
+            (
+                200,
+                json_response::<wire::api::admin::v1::SelfTlsStatus>(
+                    &mut gen,
+                    "Certificate expiry and the last and next renewal",
+                ),
+            )
+        ],
+    });
+    add("/admin/resolver_cache".to_string(), "get", Operation {
+        summary: "Get resolver cache sizes and hit rates",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, json_response::<CacheStats>(&mut gen, "Cache statistics"))],
+    });
+    add("/admin/resolver_cache/entries".to_string(), "get", Operation {
+        summary: "List values in the resolver cache",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, json_response::<Vec<CacheEntry>>(&mut gen, "Cached values"))],
+    });
+    add("/admin/resolver_cache/entries".to_string(), "delete", Operation {
+        summary: "Clear the resolver cache",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, empty_response("Cleared"))],
+    });
+    add("/admin/resolver_cache/entries/{identity}".to_string(), "get", Operation {
+        summary: "List values in the resolver cache for an identity",
+        admin: true,
+        parameters: vec![identity_param()],
+        body: None,
+        responses: vec![(200, json_response::<Vec<CacheEntry>>(&mut gen, "Cached values"))],
+    });
+    add("/admin/resolver_cache/entries/{identity}".to_string(), "delete", Operation {
+        summary: "Remove cached values and the cached announcement for an identity from the resolver cache",
+        admin: true,
+        parameters: vec![identity_param()],
+        body: None,
+        responses: vec![(200, empty_response("Removed"))],
+    });
+    add("/admin/content".to_string(), "get", Operation {
+        summary: "Get request counts for content served by the node",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, json_response::<ContentStats>(&mut gen, "Content statistics"))],
+    });
+    add("/admin/dns".to_string(), "get", Operation {
+        summary: "Get query and response rate limiting counts for the DNS bridge",
+        admin: true,
+        parameters: vec![],
+        body: None,
+        responses: vec![(200, json_response::<DnsStats>(&mut gen, "DNS bridge statistics"))],
+    });
+    add("/admin/identity/{identity}".to_string(), "get", Operation {
+        summary: "Get an identity's announcement, hosting by this node's publisher, and resolved records and certs",
+        admin: true,
+        parameters: vec![identity_param()],
+        body: None,
+        responses: vec![(200, json_response::<IdentityStatus>(&mut gen, "Identity status"))],
+    });
+    add("/admin/watch".to_string(), "get", Operation {
+        summary: "Stream node, resolver, publisher, content, and DNS bridge statistics until the client disconnects",
+        admin: true,
+        parameters: vec![
+            param("query", "interval_secs", "Seconds between snapshots. Defaults to 2, min 1, max 60.", false)
+        ],
+        body: None,
+        responses: vec![
+            (
+                200,
+                json_response::<WatchSnapshot>(
+                    &mut gen,
+                    "Snapshots as JSON lines (`application/jsonl`), one per interval starting immediately",
+                ),
+            )
+        ],
+    });
+    gen.subschema_for::<wire::api::error::latest::ApiError>();
+    return json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "spagh-node API",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": gen.take_definitions(),
+            "securitySchemes": {
+                "admin_token": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "The admin token from the node config",
+                },
+            },
+        },
+    });
+}
@@ -5,6 +5,10 @@ use std::{
     marker::PhantomData,
     net::SocketAddr,
 };
+use chrono::{
+    DateTime,
+    Utc,
+};
 use schemars::JsonSchema;
 use serde::{
     de::DeserializeOwned,
@@ -32,6 +36,33 @@ pub struct JsonSignature<T: Serialize + DeserializeOwned, I> {
     pub _p: PhantomData<(T, I)>,
 }
 
+impl<T: Serialize + DeserializeOwned, I> JsonSchema for JsonSignature<T, I> {
+    fn schema_name() -> String {
+        return "JsonSignature".to_string();
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        return schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::Object.into()),
+            metadata: Some(Box::new(schemars::schema::Metadata {
+                description: Some(
+                    "A JSON-serialized message (as a string) and the identity's signature of it".to_string(),
+                ),
+                ..Default::default()
+            })),
+            object: Some(Box::new(schemars::schema::ObjectValidation {
+                required: ["message".to_string(), "signature".to_string()].into_iter().collect(),
+                properties: [
+                    ("message".to_string(), gen.subschema_for::<String>()),
+                    ("signature".to_string(), gen.subschema_for::<Blob>()),
+                ].into_iter().collect(),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }.into();
+    }
+}
+
 impl<T: Serialize + DeserializeOwned, I> std::fmt::Debug for JsonSignature<T, I> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f
@@ -42,27 +73,62 @@ impl<T: Serialize + DeserializeOwned, I> std::fmt::Debug for JsonSignature<T, I>
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct AnnounceRequest {
     pub identity: Identity,
     pub announcement: stored::announcement::Announcement,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AnnounceResponse {
+    /// How many of the closest DHT nodes the announcement was sent to.
+    pub sent: usize,
+    /// How many of those nodes acknowledged storing the announcement.
+    pub accepted: usize,
+    /// A newer announcement for the identity was found in the network, so this one
+    /// was dropped.
+    pub superseded: bool,
+}
+
+/// The announcement a publisher has for an identity, signed by the identity.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct AnnouncementResponse {
+    /// Missing if the identity hasn't announced this publisher
+    pub announcement: Option<stored::announcement::Announcement>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct DeleteAnnouncementRequest {
     pub identity: Identity,
     pub challenge: JsonSignature<(), Identity>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct InfoResponse {
+    /// The publisher's first advertised address
     pub advertise_addr: SocketAddr,
+    /// All addresses the publisher is reachable at (ex: IPv4 and IPv6), including
+    /// `advertise_addr`. Empty from older publishers.
+    #[serde(default)]
+    pub advertise_addrs: Vec<SocketAddr>,
     pub cert_pub_hash: Blob,
 }
 
+impl InfoResponse {
+    /// All advertised addresses.
+    pub fn addrs(&self) -> Vec<SocketAddr> {
+        if self.advertise_addrs.is_empty() {
+            return vec![self.advertise_addr];
+        }
+        return self.advertise_addrs.clone();
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
 #[serde(rename_all = "snake_case")]
 pub struct PublishRequestContent {
@@ -76,11 +142,236 @@ pub struct PublishRequestContent {
     pub clear: HashSet<RecordKey>,
     /// Start publishing values for keys
     pub set: Vec<(RecordKey, RecordValue)>,
+    /// Only apply the changes if the identity's current record set version matches
+    /// this. The request is rejected with 409 if it doesn't.
+    #[serde(default)]
+    pub if_version: Option<String>,
+    /// When the request was made. Publishers record this as the publish time of the
+    /// set values (or the time received, if missing or in the future), so replicated
+    /// publishers sent the same request agree on which values are newest.
+    #[serde(default)]
+    pub published: Option<DateTime<Utc>>,
+}
+
+/// Sent to the publisher's configured publish policy endpoint for each publish
+/// request before it's accepted.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PublishPolicyRequest {
+    pub identity: Identity,
+    pub content: PublishRequestContent,
+}
+
+/// The response from a publish policy endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PublishPolicyResponse {
+    pub allow: bool,
+    /// Why the request was rejected, returned to the publishing client
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// The version of the full set of records published for an identity. This changes
+/// whenever any record is set or cleared. Returned by `publish` and `version`
+/// requests, and in the body of 409 responses when a conditional publish fails.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RecordSetVersion {
+    pub version: String,
 }
 
-#[derive(Serialize, Deserialize)]
+/// Why a record in a publish request was rejected.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordProblem {
+    /// The DNS record has more values than allowed (after removing duplicates)
+    TooManyValues {
+        key: RecordKey,
+        values: usize,
+        max: usize,
+    },
+    /// The DNS record's values are larger than allowed in DNS wire format
+    TooLarge {
+        key: RecordKey,
+        bytes: usize,
+        max: usize,
+    },
+}
+
+/// The body of 400 responses when records in a publish request are rejected.
+/// Nothing is changed if any record is rejected.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct RejectedRecords {
+    pub problems: Vec<RecordProblem>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
 #[serde(rename_all = "snake_case")]
 pub struct PublishRequest {
     pub identity: Identity,
     pub content: JsonSignature<PublishRequestContent, Identity>,
 }
+
+/// Max requests in a publish batch.
+pub const PUBLISH_BATCH_MAX: usize = 1000;
+
+/// Publish requests for multiple identities. Each request is applied (atomically)
+/// or rejected on its own, in order.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PublishBatchRequest {
+    pub requests: Vec<PublishRequest>,
+}
+
+/// What happened to one request in a publish batch. Each corresponds to a status
+/// of the single-identity publish endpoint.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PublishBatchOutcome {
+    /// The changes were applied, resulting in this record set version
+    Applied {
+        version: String,
+    },
+    /// `if_version` didn't match the current record set version and nothing was
+    /// changed
+    VersionMismatch {
+        current: String,
+    },
+    /// The request signature couldn't be verified
+    BadSignature,
+    /// The request wasn't signed (the connection was authenticated with a client
+    /// cert) and `published` was missing or too far from the publisher's current time
+    Expired,
+    /// The identity isn't allowed to publish here
+    Unauthorized,
+    /// The publisher's publish policy rejected the request
+    Rejected {
+        reason: String,
+    },
+    /// The publisher database is over its size limit
+    StorageFull,
+    /// Records were rejected and nothing was changed
+    InvalidRecords {
+        problems: Vec<RecordProblem>,
+    },
+    /// Something went wrong on the publisher; the request may be retried
+    Internal,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PublishBatchResult {
+    pub identity: Identity,
+    pub outcome: PublishBatchOutcome,
+}
+
+/// Results for each request in a publish batch, in request order.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PublishBatchResponse {
+    pub results: Vec<PublishBatchResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ReadStatsRequestContent {
+    /// Requests are rejected if this is too far from the publisher's current time, to
+    /// prevent replay.
+    pub requested: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ReadStatsRequest {
+    pub identity: Identity,
+    pub content: JsonSignature<ReadStatsRequestContent, Identity>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct WatchRequestContent {
+    /// Requests are rejected if this is too far from the publisher's current time, to
+    /// prevent replay.
+    pub requested: DateTime<Utc>,
+    /// The record set version the client last saw. The request waits until the
+    /// current version is different. If empty, returns immediately.
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct WatchRequest {
+    pub identity: Identity,
+    pub content: JsonSignature<WatchRequestContent, Identity>,
+}
+
+/// The result of a watch request, when the record set changes or the publisher
+/// stops waiting.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct WatchResponse {
+    /// The current record set version
+    pub version: String,
+    /// False if the version didn't change before the publisher stopped waiting; send
+    /// the request again to keep watching
+    pub changed: bool,
+    /// The full current record set, if changed
+    pub values: Vec<(RecordKey, RecordValue)>,
+}
+
+/// How often a published key has been resolved since the publisher started.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct KeyReadStats {
+    pub key: RecordKey,
+    pub reads: u64,
+    pub last_read: DateTime<Utc>,
+}
+
+/// An identity's full record set as served by a publisher at a point in time.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ExportContent {
+    pub identity: Identity,
+    /// The record set version, as returned by `version`
+    pub version: String,
+    /// When the publisher made the export
+    pub exported: DateTime<Utc>,
+    pub values: Vec<(RecordKey, RecordValue)>,
+}
+
+/// An export signed by the publisher, for submitting to a transparency log. Anyone
+/// can check it: `signature` is the P-256 ECDSA signature (DER) of `content` by the
+/// key of the publisher cert `cert_pub_der`, and the hash of that cert is the
+/// `cert_hash` in the identity's announcement.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SignedExport {
+    pub cert_pub_der: Blob,
+    /// JSON-serialized `ExportContent`
+    pub content: String,
+    pub signature: Blob,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ExportRequestContent {
+    /// Requests are rejected if this is too far from the publisher's current time, to
+    /// prevent replay.
+    pub requested: DateTime<Utc>,
+    /// The identity to export, must match the path
+    pub identity: Identity,
+}
+
+/// An export request signed by a resolver, required by publishers that only answer
+/// authorized resolvers.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ExportRequest {
+    /// The identity of the resolver making the request
+    pub resolver: Identity,
+    pub content: JsonSignature<ExportRequestContent, Identity>,
+}
@@ -1,10 +1,155 @@
 use {
     crate::interface::{
-        stored::record::record_utils::RecordKey,
-        wire,
+        stored::{
+            identity::Identity,
+            record::record_utils::RecordKey,
+        },
+        wire::{
+            self,
+            api::publish::v1::JsonSignature,
+        },
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
     },
     std::collections::HashMap,
 };
 
 pub type ResolveResp = Vec<(RecordKey, wire::resolve::v1::ResolveValue)>;
 pub type ResolveKeyValues = HashMap<RecordKey, wire::resolve::v1::ResolveValue>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SignedResolveRespContent {
+    /// When the resolver answered. Clients decide how old a response they accept.
+    pub signed: DateTime<Utc>,
+    /// The identity that was queried
+    pub identity: Identity,
+    /// The keys that were queried
+    pub keys: Vec<RecordKey>,
+    pub values: ResolveResp,
+}
+
+/// A lookup response signed by the resolver, returned instead of `ResolveResp` if
+/// the request has the `x-spagh-sign` header.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SignedResolveResp {
+    /// The identity of the resolver that signed the response
+    pub resolver: Identity,
+    pub content: JsonSignature<SignedResolveRespContent, Identity>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+pub struct DnsJsonQuestion {
+    pub name: String,
+    /// Numeric DNS record type
+    #[serde(rename = "type")]
+    pub type_: u16,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+pub struct DnsJsonAnswer {
+    pub name: String,
+    /// Numeric DNS record type
+    #[serde(rename = "type")]
+    pub type_: u16,
+    #[serde(rename = "TTL")]
+    pub ttl: u32,
+    /// The record data in zone file presentation format
+    pub data: String,
+}
+
+/// A response in the `application/dns-json` format used by DNS-over-HTTPS JSON
+/// APIs.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+pub struct DnsJsonResponse {
+    /// DNS response code (`0` NOERROR, `3` NXDOMAIN, `5` REFUSED, ...)
+    #[serde(rename = "Status")]
+    pub status: u16,
+    #[serde(rename = "TC")]
+    pub tc: bool,
+    #[serde(rename = "RD")]
+    pub rd: bool,
+    #[serde(rename = "RA")]
+    pub ra: bool,
+    #[serde(rename = "AD")]
+    pub ad: bool,
+    #[serde(rename = "CD")]
+    pub cd: bool,
+    #[serde(rename = "Question")]
+    pub question: Vec<DnsJsonQuestion>,
+    #[serde(rename = "Answer", default, skip_serializing_if = "Vec::is_empty")]
+    pub answer: Vec<DnsJsonAnswer>,
+    /// For names handed off to conventional DNS servers, the `NS` (and `DS`) records
+    /// of the referral
+    #[serde(rename = "Authority", default, skip_serializing_if = "Vec::is_empty")]
+    pub authority: Vec<DnsJsonAnswer>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    Failed,
+    /// The component isn't used by this resolver
+    Disabled,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct HealthComponent {
+    pub status: HealthStatus,
+    /// Details for humans, may change
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+/// The result of the resolver health check.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ResolverHealth {
+    /// No component failed
+    pub healthy: bool,
+    /// A DHT lookup (of the node's own id) got responses from other nodes
+    pub dht: HealthComponent,
+    /// At least one upstream DNS server answered (only if the DNS bridge forwards
+    /// queries upstream)
+    pub upstream_dns: HealthComponent,
+    /// The cache database is writable
+    pub cache_db: HealthComponent,
+}
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SuspectReason {
+    /// None of the resolved addresses accepted a connection
+    ConnectFailed,
+    /// The host's TLS cert or SSH host key didn't match the resolved ones
+    KeyMismatch,
+    Other,
+}
+
+/// A client's report that values it got from the resolver seem wrong, so the
+/// resolver should look them up again instead of answering from its cache until
+/// they expire.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SuspectReport {
+    pub identity: Identity,
+    pub keys: Vec<RecordKey>,
+    pub reason: SuspectReason,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SuspectReportResponse {
+    /// The values are being looked up again. False if the identity was reported
+    /// recently and the report was ignored.
+    pub revalidating: bool,
+}
@@ -6,13 +6,15 @@ use serde::{
 use crate::versioned;
 
 pub mod v1;
+pub mod v2;
 
-pub use v1 as latest;
+pub use v2 as latest;
 
 versioned!(
     Protocol,
     Debug;
-    (V1, 1, v1::Message)
+    (V1, 1, v1::Message),
+    (V2, 2, v2::Message)
 );
 
 #[derive(Serialize, Deserialize)]
@@ -15,6 +15,7 @@ use crate::utils::blob::{
     Blob,
     ToBlob,
 };
+use crate::utils::versioned::VerInt;
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
 #[serde(rename_all = "snake_case")]
@@ -25,12 +26,6 @@ pub struct BincodeSignature<T: Serialize + DeserializeOwned, I> {
     pub _p: PhantomData<(T, I)>,
 }
 
-impl<T: Serialize + DeserializeOwned, I> BincodeSignature<T, I> {
-    pub fn parse_unwrap(&self) -> T {
-        return bincode::deserialize(&self.message).unwrap();
-    }
-}
-
 impl<T: Serialize + DeserializeOwned + Debug, I> std::fmt::Debug for BincodeSignature<T, I> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         format_args!("(sig: {}) ", &zbase32::encode_full_bytes(&self.signature)[..8]).fmt(f)?;
@@ -115,11 +110,42 @@ pub struct StoreRequest {
     pub value: Announcement,
 }
 
+/// Acknowledges a `Store`.
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub struct StoreResponse {
+    pub sender: NodeIdentity,
+    pub key: Identity,
+    /// False if the node already had a newer value
+    pub accepted: bool,
+}
+
+/// Reply to a `Store` from a node that doesn't store values for other nodes.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct StoreDeclined {
+    pub sender: NodeIdentity,
     pub key: Identity,
-    pub value: Announcement,
+}
+
+/// Sent alongside a challenge response by nodes with reduced capabilities, so
+/// neighbors can avoid sending them requests they won't handle.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Capabilities {
+    pub sender: NodeIdentity,
+    /// The node accepts `Store` requests for values it didn't publish.
+    pub store: bool,
+}
+
+/// Sent alongside a challenge response, so neighbors can pick the highest
+/// protocol version both nodes support when sending. Nodes that don't send this
+/// only support v1.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Versions {
+    pub sender: NodeIdentity,
+    pub versions: Vec<VerInt>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -129,6 +155,76 @@ pub struct ChallengeResponse {
     pub signature: Blob,
 }
 
+/// A sample of the sender's routing table, offered to neighbors to speed up
+/// bootstrapping. Receivers must challenge any unknown nodes before adding them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct PeerExchangeContent {
+    pub sender: NodeIdentity,
+    pub nodes: Vec<NodeInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct PeerExchange {
+    pub sender: NodeIdentity,
+    pub content: BincodeSignature<PeerExchangeContent, NodeIdentity>,
+}
+
+/// Sent to neighbors on graceful shutdown so they can stop routing to the sender
+/// immediately rather than waiting for pings to time out. `stamp` limits replays.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct GoodbyeContent {
+    pub sender: NodeIdentity,
+    pub stamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct Goodbye {
+    pub sender: NodeIdentity,
+    pub content: BincodeSignature<GoodbyeContent, NodeIdentity>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// A signature in the request didn't verify
+    BadSignature,
+    /// The request was truncated because it didn't fit in a packet
+    TooLarge,
+    /// The sender sent too many requests, try again later
+    Throttled,
+    /// The value was valid but wasn't stored (ex: its announcement time is in the
+    /// future)
+    NotStored,
+    /// The receiver knows of enough nodes closer to the value's coordinate that it
+    /// isn't one of the nodes that should store it
+    NotNearest,
+}
+
+/// Which request an `ErrorResponse` is for.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorRequest {
+    Store(Identity),
+    Find(FindGoal),
+    /// The request couldn't be parsed
+    Unknown,
+}
+
+/// Tells the sender a request was rejected, so it can tell rejection from loss.
+/// Only sent in reply to requests at least as large as the response (so it can't be
+/// used for amplification), and never in reply to another `ErrorResponse`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct ErrorResponse {
+    pub sender: NodeIdentity,
+    pub request: ErrorRequest,
+    pub code: ErrorCode,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Message {
@@ -139,6 +235,13 @@ pub enum Message {
     Pung(NodeIdentity),
     Challenge(Blob),
     ChallengeResponse(ChallengeResponse),
+    PeerExchange(PeerExchange),
+    StoreDeclined(StoreDeclined),
+    Capabilities(Capabilities),
+    StoreResponse(StoreResponse),
+    Versions(Versions),
+    Goodbye(Goodbye),
+    Error(ErrorResponse),
 }
 
 impl Message {
@@ -156,4 +259,12 @@ impl Message {
 pub struct NodeState {
     pub node: NodeInfo,
     pub unresponsive: bool,
+    /// Verified addresses for the node in the other IP family, used if
+    /// `node.address` can't be reached.
+    #[serde(default)]
+    pub alt_addresses: Vec<SerialAddr>,
+    /// The largest datagram (bytes) MTU probing confirmed reaches the node, if it's
+    /// been probed. Messages to the node are kept within this.
+    #[serde(default)]
+    pub max_datagram: Option<u16>,
 }
@@ -0,0 +1,245 @@
+//! Adds replay protection to find and challenge responses: both are stamped with
+//! the time they were sent, and challenge responses sign a structure with the
+//! challenge instead of the raw challenge bytes.
+//!
+//! Later additions: MTU probing, fetching values left out of find responses to fit
+//! in a datagram, pings with a nonce the signed reply must echo, advertising
+//! transports other than datagrams, and hints about how a node is reached.
+use serde::{
+    Serialize,
+    Deserialize,
+};
+use crate::interface::stored::announcement::Announcement;
+use crate::interface::stored::identity::Identity;
+use crate::interface::stored::node_identity::NodeIdentity;
+use crate::utils::blob::{
+    Blob,
+    ToBlob,
+};
+pub use super::v1::{
+    BincodeSignature,
+    Capabilities,
+    DhtCoord,
+    ErrorCode,
+    ErrorRequest,
+    ErrorResponse,
+    FindGoal,
+    FindRequest,
+    Goodbye,
+    GoodbyeContent,
+    NodeInfo,
+    NodeState,
+    PeerExchange,
+    PeerExchangeContent,
+    StoreDeclined,
+    StoreRequest,
+    StoreResponse,
+    Versions,
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct FindResponseContent {
+    pub goal: FindGoal,
+    pub challenge: Blob,
+    pub sender: NodeIdentity,
+    pub nodes: Vec<NodeInfo>,
+    pub value: Option<Announcement>,
+    pub stamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct FindResponse {
+    pub sender: NodeIdentity,
+    pub content: BincodeSignature<FindResponseContent, NodeIdentity>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct ChallengeResponseContent {
+    pub challenge: Blob,
+    pub stamp: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct ChallengeResponse {
+    pub sender: NodeIdentity,
+    pub content: BincodeSignature<ChallengeResponseContent, NodeIdentity>,
+}
+
+/// Padded so the encoded message is `size` bytes, to check whether datagrams that
+/// large reach the receiver. The receiver replies with `MtuProbeAck`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct MtuProbe {
+    pub sender: NodeIdentity,
+    pub size: u16,
+    pub padding: Blob,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct MtuProbeAck {
+    pub sender: NodeIdentity,
+    pub size: u16,
+}
+
+/// Sent just before a find response that left out the value to fit in a datagram
+/// to the requester. `challenge` is the find request's. The requester can fetch
+/// the value on its own with `FindValueRequest`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct FindValueOmitted {
+    pub sender: NodeIdentity,
+    pub identity: Identity,
+    pub challenge: Blob,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct FindValueRequest {
+    pub sender: NodeIdentity,
+    pub identity: Identity,
+    pub challenge: Blob,
+}
+
+/// The value isn't signed by the sender since the announcement is signed by the
+/// identity.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct FindValueResponse {
+    pub sender: NodeIdentity,
+    pub identity: Identity,
+    pub challenge: Blob,
+    pub value: Announcement,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct PungContent {
+    pub nonce: Blob,
+}
+
+/// Reply to `PingNonce`, echoing the nonce so spoofed replies to a ping can't mark
+/// a dead node responsive.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct SignedPung {
+    pub sender: NodeIdentity,
+    pub content: BincodeSignature<PungContent, NodeIdentity>,
+}
+
+/// Sent alongside a challenge response by nodes that accept messages over
+/// transports other than datagrams on their node port.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct Transports {
+    pub sender: NodeIdentity,
+    /// The node accepts QUIC connections on its node port (experimental).
+    pub quic: bool,
+}
+
+/// Sent alongside a challenge response by nodes whose address isn't their own.
+/// Peer addresses are opaque to the protocol, so this is informational: nodes keep
+/// messaging the address the challenge response came from.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub struct TransportHints {
+    pub sender: NodeIdentity,
+    /// The node is reached through a relay (ex: to hide its IP address), so its
+    /// address is the relay's.
+    pub relayed: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Message {
+    FindRequest(FindRequest),
+    FindResponse(FindResponse),
+    Store(StoreRequest),
+    Ping,
+    Pung(NodeIdentity),
+    Challenge(Blob),
+    ChallengeResponse(ChallengeResponse),
+    PeerExchange(PeerExchange),
+    StoreDeclined(StoreDeclined),
+    Capabilities(Capabilities),
+    StoreResponse(StoreResponse),
+    Versions(Versions),
+    Goodbye(Goodbye),
+    Error(ErrorResponse),
+    MtuProbe(MtuProbe),
+    MtuProbeAck(MtuProbeAck),
+    FindValueOmitted(FindValueOmitted),
+    FindValueRequest(FindValueRequest),
+    FindValueResponse(FindValueResponse),
+    /// A ping with a random nonce; the receiver replies with `SignedPung`.
+    PingNonce(Blob),
+    SignedPung(SignedPung),
+    Transports(Transports),
+    TransportHints(TransportHints),
+}
+
+impl Message {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Message, loga::Error> {
+        return Ok(bincode::deserialize(bytes)?);
+    }
+
+    pub fn to_bytes(&self) -> Blob {
+        return bincode::serialize(self).unwrap().blob();
+    }
+
+    /// Convert a v1 message. Find and challenge responses are signed differently in
+    /// each version so they can't be converted.
+    pub fn from_v1(m: super::v1::Message) -> Result<Message, Box<super::v1::Message>> {
+        match m {
+            super::v1::Message::FindRequest(m) => return Ok(Message::FindRequest(m)),
+            super::v1::Message::Store(m) => return Ok(Message::Store(m)),
+            super::v1::Message::Ping => return Ok(Message::Ping),
+            super::v1::Message::Pung(m) => return Ok(Message::Pung(m)),
+            super::v1::Message::Challenge(m) => return Ok(Message::Challenge(m)),
+            super::v1::Message::PeerExchange(m) => return Ok(Message::PeerExchange(m)),
+            super::v1::Message::StoreDeclined(m) => return Ok(Message::StoreDeclined(m)),
+            super::v1::Message::Capabilities(m) => return Ok(Message::Capabilities(m)),
+            super::v1::Message::StoreResponse(m) => return Ok(Message::StoreResponse(m)),
+            super::v1::Message::Versions(m) => return Ok(Message::Versions(m)),
+            super::v1::Message::Goodbye(m) => return Ok(Message::Goodbye(m)),
+            super::v1::Message::Error(m) => return Ok(Message::Error(m)),
+            m @ super::v1::Message::FindResponse(_) | m @ super::v1::Message::ChallengeResponse(_) => {
+                return Err(Box::new(m));
+            },
+        }
+    }
+
+    /// Convert to a v1 message, for nodes that only support v1. Find and challenge
+    /// responses (see `from_v1`) and messages added in v2 can't be converted.
+    pub fn to_v1(self) -> Result<super::v1::Message, Box<Message>> {
+        match self {
+            Message::FindRequest(m) => return Ok(super::v1::Message::FindRequest(m)),
+            Message::Store(m) => return Ok(super::v1::Message::Store(m)),
+            Message::Ping => return Ok(super::v1::Message::Ping),
+            Message::Pung(m) => return Ok(super::v1::Message::Pung(m)),
+            Message::Challenge(m) => return Ok(super::v1::Message::Challenge(m)),
+            Message::PeerExchange(m) => return Ok(super::v1::Message::PeerExchange(m)),
+            Message::StoreDeclined(m) => return Ok(super::v1::Message::StoreDeclined(m)),
+            Message::Capabilities(m) => return Ok(super::v1::Message::Capabilities(m)),
+            Message::StoreResponse(m) => return Ok(super::v1::Message::StoreResponse(m)),
+            Message::Versions(m) => return Ok(super::v1::Message::Versions(m)),
+            Message::Goodbye(m) => return Ok(super::v1::Message::Goodbye(m)),
+            Message::Error(m) => return Ok(super::v1::Message::Error(m)),
+            m @ Message::FindResponse(_) |
+            m @ Message::ChallengeResponse(_) |
+            m @ Message::MtuProbe(_) |
+            m @ Message::MtuProbeAck(_) |
+            m @ Message::FindValueOmitted(_) |
+            m @ Message::FindValueRequest(_) |
+            m @ Message::FindValueResponse(_) |
+            m @ Message::PingNonce(_) |
+            m @ Message::SignedPung(_) |
+            m @ Message::Transports(_) |
+            m @ Message::TransportHints(_) => return Err(Box::new(m)),
+        }
+    }
+}
@@ -8,6 +8,20 @@ use {
 pub const DNS_SUFFIX: &str = "s";
 pub const DNS_DOT_SUFFIX: &str = ".s";
 
+/// A publisher in maintenance mode responds to resolve requests with `503` and a
+/// `Retry-After` header (seconds). Resolvers back off from the publisher for that
+/// long, up to this limit.
+pub const MAX_RETRY_AFTER_SECS: u32 = 60 * 60;
+
+/// The wait used if a publisher in maintenance mode doesn't say how long to wait.
+pub const DEFAULT_RETRY_AFTER_SECS: u32 = 60;
+
+// Publishers send an `ETag` with resolve responses, covering each value's TTL, data,
+// and publish time. A resolver refreshing the same keys can send it back in
+// `If-None-Match`; if nothing changed the publisher responds `304` with no body, and
+// the resolver reuses the previous values with their expirations moved forward by
+// the time since the previous response.
+
 pub mod v1;
 
 pub use v1 as latest;
@@ -16,4 +30,7 @@ pub use v1 as latest;
 #[serde(rename_all = "snake_case")]
 pub enum ResolveRequest {
     V1(v1::ResolveRequest),
+    /// A `V1` request signed by the resolver, for publishers that only answer
+    /// authorized resolvers
+    V1Signed(v1::SignedResolveRequest),
 }
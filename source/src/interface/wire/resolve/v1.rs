@@ -1,7 +1,10 @@
 use {
-    crate::interface::stored::{
-        identity::Identity,
-        record::record_utils::RecordKey,
+    crate::interface::{
+        stored::{
+            identity::Identity,
+            record::record_utils::RecordKey,
+        },
+        wire::api::publish::v1::JsonSignature,
     },
     chrono::{
         DateTime,
@@ -12,9 +15,65 @@ use {
         Deserialize,
         Serialize,
     },
-    std::collections::HashMap,
+    std::{
+        collections::{
+            BTreeMap,
+            HashMap,
+        },
+        net::{
+            IpAddr,
+            SocketAddr,
+        },
+    },
 };
 
+/// How a resolver got a value.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolveSource {
+    /// Fetched from a publisher for this lookup
+    Fetch,
+    /// From the resolver cache
+    Cache,
+    /// From the resolver cache after it expired, because all the identity's
+    /// publishers were in maintenance
+    StaleCache,
+    /// Read from the publisher in the same node, without a DHT lookup
+    Local,
+}
+
+/// Where a resolved value came from, for debugging.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ResolveProvenance {
+    pub source: ResolveSource,
+    /// The publisher the value was fetched from, if known (values restored from the
+    /// persisted cache at startup have no publisher)
+    #[serde(default)]
+    pub publisher: Option<SocketAddr>,
+    /// When the announcement listing the publisher was published, if known
+    #[serde(default)]
+    pub announced: Option<DateTime<Utc>>,
+    /// Seconds until the value expires from the resolver cache
+    pub ttl_remaining: i64,
+    /// How many other publishers returned a different value for the key, which lost
+    /// to this one
+    #[serde(default)]
+    pub conflicts: usize,
+}
+
+/// The addresses of an external DNS name referenced by a value.
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ResolveExternalName {
+    pub addrs: Vec<IpAddr>,
+    /// When the addresses expire, per the DNS TTLs
+    pub expires: DateTime<Utc>,
+    /// Set if the lookup failed or the name had no addresses
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub struct ResolveValue {
@@ -22,6 +81,17 @@ pub struct ResolveValue {
     /// This should be far enough in the future to ignore when not storing the results.
     pub expires: DateTime<Utc>,
     pub data: Option<serde_json::Value>,
+    /// When the value was published, per the publisher. Missing if the key has no
+    /// value or the publisher doesn't record this.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published: Option<DateTime<Utc>>,
+    /// Where the value came from. Resolvers only include this when requested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<ResolveProvenance>,
+    /// Addresses of external DNS names in the value (ex: `MX` hosts), by name (fully
+    /// qualified). Only included by resolvers configured to look up external names.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external: Option<BTreeMap<String, ResolveExternalName>>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
@@ -31,5 +101,22 @@ pub struct ResolveRequest {
     pub keys: Vec<RecordKey>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SignedResolveRequestContent {
+    /// Requests are rejected if this is too far from the publisher's current time, to
+    /// prevent replay.
+    pub requested: DateTime<Utc>,
+    pub request: ResolveRequest,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct SignedResolveRequest {
+    /// The identity of the resolver making the request
+    pub resolver: Identity,
+    pub content: JsonSignature<SignedResolveRequestContent, Identity>,
+}
+
 pub type ResolveResp = Vec<(RecordKey, ResolveValue)>;
 pub type ResolveKeyValues = HashMap<RecordKey, ResolveValue>;
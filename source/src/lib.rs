@@ -1,6 +1,7 @@
 //! Spaghettinuum may be used as a library. The library provides both complete
 //! server objects as well as methods for creating requests, signing messages, and
 //! other peripheral activities.
+pub mod client;
 pub mod interface;
 pub mod publishing;
 pub mod resolving;
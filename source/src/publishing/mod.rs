@@ -31,6 +31,8 @@ use {
     },
 };
 
+pub mod spool;
+
 pub fn system_publisher_url_pairs(log: &Log) -> Result<Vec<UrlPair>, loga::Error> {
     shed!{
         let Some(raw_publishers) = env::var_os(ENV_PUBLISHER_URLS) else {
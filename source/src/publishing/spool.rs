@@ -0,0 +1,446 @@
+//! A local on-disk queue of signed publish requests (`SPAGH_PUBLISH_SPOOL`), for
+//! when a publisher can't be reached. Queued requests are sent in the order they
+//! were queued, before any later requests for the same publisher and identity.
+use {
+    crate::{
+        interface::{
+            config::ENV_PUBLISH_SPOOL,
+            stored::identity::Identity,
+            wire::api::publish::latest::PublishRequest,
+        },
+        resolving::UrlPair,
+        ta_res,
+        utils::publish_util::{
+            send_publish,
+            SendPublishError,
+        },
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    http::Uri,
+    loga::{
+        ea,
+        ErrContext,
+        Log,
+        ResultContext,
+    },
+    rand::{
+        thread_rng,
+        Rng,
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    std::{
+        collections::HashSet,
+        env,
+        net::IpAddr,
+        path::{
+            Path,
+            PathBuf,
+        },
+        str::FromStr,
+    },
+    tokio::fs::{
+        create_dir_all,
+        read,
+        read_dir,
+        remove_file,
+        rename,
+        write,
+    },
+};
+
+/// Drop a queued request after the publisher fails it this many times (ex: it was
+/// rejected), so it doesn't hold up later requests forever.
+pub const MAX_REQUEST_FAILURES: u32 = 5;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SpooledPublish {
+    pub publisher_address: Option<IpAddr>,
+    pub publisher_url: String,
+    pub request: PublishRequest,
+    pub spooled: DateTime<Utc>,
+    /// Attempts to send the request, including ones where the publisher couldn't be
+    /// reached
+    pub attempts: u32,
+    /// Attempts where the publisher was reached but the request failed
+    pub failures: u32,
+    pub last_error: Option<String>,
+}
+
+impl SpooledPublish {
+    fn publisher(&self) -> Result<UrlPair, loga::Error> {
+        return Ok(UrlPair {
+            address: self.publisher_address,
+            url: Uri::from_str(
+                &self.publisher_url,
+            ).context_with("Invalid publisher url in spooled request", ea!(url = self.publisher_url))?,
+        });
+    }
+
+    fn matches(&self, publisher: &UrlPair, identity: &Identity) -> bool {
+        return self.publisher_address == publisher.address && self.publisher_url == publisher.url.to_string() &&
+            &self.request.identity == identity;
+    }
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct SpoolFlush {
+    /// Requests sent and removed from the spool
+    pub sent: usize,
+    /// Requests removed from the spool after failing too many times
+    pub dropped: usize,
+    /// Requests still in the spool
+    pub remaining: usize,
+}
+
+pub struct PublishSpool {
+    pub dir: PathBuf,
+}
+
+impl PublishSpool {
+    pub fn new(dir: PathBuf) -> Self {
+        return Self { dir: dir };
+    }
+
+    /// The spool configured with `SPAGH_PUBLISH_SPOOL`, if any.
+    pub fn from_env() -> Option<Self> {
+        return Some(Self::new(PathBuf::from(env::var_os(ENV_PUBLISH_SPOOL)?)));
+    }
+
+    async fn write_entry(&self, path: &Path, entry: &SpooledPublish) -> Result<(), loga::Error> {
+        create_dir_all(&self.dir)
+            .await
+            .context_with("Error creating publish spool directory", ea!(path = self.dir.to_string_lossy()))?;
+
+        // Write then move so a crash never leaves a partial entry
+        let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+        write(&temp_path, serde_json::to_vec_pretty(entry).unwrap())
+            .await
+            .context_with("Error writing spooled publish request", ea!(path = temp_path.to_string_lossy()))?;
+        rename(&temp_path, path)
+            .await
+            .context_with("Error moving spooled publish request into place", ea!(path = path.to_string_lossy()))?;
+        return Ok(());
+    }
+
+    /// Queue a request to send to a publisher later.
+    pub async fn push(
+        &self,
+        publisher: &UrlPair,
+        request: &PublishRequest,
+        error: Option<String>,
+    ) -> Result<(), loga::Error> {
+        let now = Utc::now();
+
+        // Names sort in the order requests were queued
+        let path =
+            self
+                .dir
+                .join(
+                    format!(
+                        "{:020}-{:08x}.json",
+                        now.timestamp_nanos_opt().unwrap_or_default(),
+                        thread_rng().gen::<u32>()
+                    ),
+                );
+        self.write_entry(&path, &SpooledPublish {
+            publisher_address: publisher.address,
+            publisher_url: publisher.url.to_string(),
+            request: request.clone(),
+            spooled: now,
+            attempts: if error.is_some() {
+                1
+            } else {
+                0
+            },
+            failures: 0,
+            last_error: error,
+        }).await?;
+        return Ok(());
+    }
+
+    /// All queued requests, oldest first. Entries that can't be read are logged and
+    /// skipped.
+    pub async fn list(&self, log: &Log) -> Result<Vec<(PathBuf, SpooledPublish)>, loga::Error> {
+        let mut paths = vec![];
+        let mut entries = match read_dir(&self.dir).await {
+            Ok(e) => e,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => {
+                return Err(
+                    e.context_with("Error listing publish spool directory", ea!(path = self.dir.to_string_lossy())),
+                );
+            },
+        };
+        while let Some(entry) = entries.next_entry().await.context("Error listing publish spool directory")? {
+            let path = entry.path();
+            if path.extension().map(|e| e == "json").unwrap_or(false) {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        let mut out = vec![];
+        for path in paths {
+            match async {
+                ta_res!(SpooledPublish);
+                return serde_json::from_slice::<SpooledPublish>(
+                    &read(&path).await.context("Error reading spooled publish request")?,
+                ).context("Error parsing spooled publish request");
+            }.await {
+                Ok(e) => out.push((path, e)),
+                Err(e) => {
+                    log.log_err(loga::WARN, e.context_with("Skipping spool entry", ea!(path = path.to_string_lossy())));
+                },
+            }
+        }
+        return Ok(out);
+    }
+
+    /// Try to send queued requests, oldest first. If `only` is set, only requests for
+    /// that publisher and identity are sent. Once a request fails, later requests for
+    /// the same publisher and identity stay queued so they aren't applied out of
+    /// order.
+    pub async fn flush(
+        &self,
+        log: &Log,
+        resolvers: &[UrlPair],
+        only: Option<(&UrlPair, &Identity)>,
+    ) -> Result<SpoolFlush, loga::Error> {
+        let mut out = SpoolFlush::default();
+        let mut blocked = HashSet::new();
+        for (path, mut entry) in self.list(log).await? {
+            if let Some((publisher, identity)) = only {
+                if !entry.matches(publisher, identity) {
+                    continue;
+                }
+            }
+            let queue_key = (entry.publisher_address, entry.publisher_url.clone(), entry.request.identity);
+            if blocked.contains(&queue_key) {
+                out.remaining += 1;
+                continue;
+            }
+            let log = log.fork(ea!(publisher = entry.publisher_url, identity = entry.request.identity));
+            entry.attempts += 1;
+            let res = match entry.publisher() {
+                Ok(publisher) => send_publish(&log, resolvers, &publisher, &entry.request).await,
+                Err(e) => Err(SendPublishError::Request(e)),
+            };
+            match res {
+                Ok(_) => {
+                    remove_file(&path)
+                        .await
+                        .context_with(
+                            "Error removing sent request from publish spool",
+                            ea!(path = path.to_string_lossy()),
+                        )?;
+                    log.log_with(loga::INFO, "Sent spooled publish request", ea!(spooled = entry.spooled));
+                    out.sent += 1;
+                    continue;
+                },
+                Err(SendPublishError::Connect(e)) => {
+                    entry.last_error = Some(e.to_string());
+                    log.log_err(loga::DEBUG, e.context("Publisher still unreachable, keeping spooled request"));
+                },
+                Err(SendPublishError::Request(e)) => {
+                    entry.failures += 1;
+                    entry.last_error = Some(e.to_string());
+                    if entry.failures >= MAX_REQUEST_FAILURES {
+                        log.log_err(
+                            loga::WARN,
+                            e.context_with(
+                                "Spooled publish request failed too many times, dropping it",
+                                ea!(spooled = entry.spooled, failures = entry.failures),
+                            ),
+                        );
+                        remove_file(&path)
+                            .await
+                            .context_with(
+                                "Error removing failed request from publish spool",
+                                ea!(path = path.to_string_lossy()),
+                            )?;
+                        out.dropped += 1;
+                        continue;
+                    }
+                    log.log_err(loga::WARN, e.context("Spooled publish request failed, keeping it to retry"));
+                },
+            }
+            self.write_entry(&path, &entry).await?;
+            blocked.insert(queue_key);
+            out.remaining += 1;
+        }
+        return Ok(out);
+    }
+}
+
+#[cfg(test)]
+mod test_spool {
+    use {
+        super::PublishSpool,
+        crate::{
+            interface::{
+                config::identity::LocalIdentitySecret,
+                wire::api::publish::latest::{
+                    JsonSignature,
+                    PublishRequest,
+                    PublishRequestContent,
+                },
+            },
+            resolving::UrlPair,
+            utils::signed::IdentSignatureMethods,
+        },
+        http::Uri,
+        loga::Log,
+        std::{
+            env,
+            path::PathBuf,
+            str::FromStr,
+            sync::{
+                atomic::{
+                    AtomicUsize,
+                    Ordering,
+                },
+                Arc,
+            },
+        },
+        tokio::{
+            io::{
+                AsyncReadExt,
+                AsyncWriteExt,
+            },
+            net::TcpListener,
+        },
+    };
+
+    fn spool_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("spagh-test-spool-{}-{}", name, std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        return dir;
+    }
+
+    fn request() -> PublishRequest {
+        let (_, mut secret) = LocalIdentitySecret::new();
+        let (identity, content) = JsonSignature::sign(&mut secret, PublishRequestContent::default()).unwrap();
+        return PublishRequest {
+            identity: identity,
+            content: content,
+        };
+    }
+
+    fn publisher_at(port: u16) -> UrlPair {
+        return UrlPair {
+            address: Some("127.0.0.1".parse().unwrap()),
+            url: Uri::from_str(&format!("http://127.0.0.1:{}", port)).unwrap(),
+        };
+    }
+
+    /// A publisher that accepts every request, and the number of requests it
+    /// received.
+    async fn serve_publisher() -> (UrlPair, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let publisher = publisher_at(listener.local_addr().unwrap().port());
+        let received = Arc::new(AtomicUsize::new(0));
+        tokio::spawn({
+            let received = received.clone();
+            async move {
+                loop {
+                    let (mut conn, _) = listener.accept().await.unwrap();
+                    let mut req = vec![];
+                    let mut buf = [0u8; 4096];
+
+                    // Read the headers, then the body
+                    let body_start = loop {
+                        let len = conn.read(&mut buf).await.unwrap();
+                        req.extend_from_slice(&buf[..len]);
+                        if let Some(i) = req.windows(4).position(|w| w == b"\r\n\r\n") {
+                            break i + 4;
+                        }
+                    };
+                    let headers = String::from_utf8_lossy(&req[..body_start]).to_ascii_lowercase();
+                    let chunked = headers.contains("transfer-encoding: chunked");
+                    let body_len =
+                        headers
+                            .lines()
+                            .find_map(|l| l.strip_prefix("content-length:"))
+                            .map(|l| l.trim().parse::<usize>().unwrap())
+                            .unwrap_or(0);
+                    while if chunked {
+                        !req.ends_with(b"0\r\n\r\n")
+                    } else {
+                        req.len() < body_start + body_len
+                    } {
+                        let len = conn.read(&mut buf).await.unwrap();
+                        req.extend_from_slice(&buf[..len]);
+                    }
+                    received.fetch_add(1, Ordering::Relaxed);
+                    conn.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n").await.unwrap();
+
+                    // Leave closing to the client, or it may see the connection fail before the
+                    // response
+                    while conn.read(&mut buf).await.unwrap_or(0) > 0 { }
+                }
+            }
+        });
+        return (publisher, received);
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let log = Log::new();
+        let dir = spool_dir("replay");
+        let spool = PublishSpool::new(dir.clone());
+        let (publisher, received) = serve_publisher().await;
+        let first = request();
+        let second = request();
+        spool.push(&publisher, &first, None).await.unwrap();
+        spool.push(&publisher, &second, None).await.unwrap();
+        let listed = spool.list(&log).await.unwrap();
+        assert_eq!(
+            listed.iter().map(|(_, e)| e.request.identity).collect::<Vec<_>>(),
+            vec![first.identity, second.identity]
+        );
+
+        // Kept while the publisher is unreachable
+        let unreachable = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            publisher_at(listener.local_addr().unwrap().port())
+        };
+        let other = request();
+        spool.push(&unreachable, &other, None).await.unwrap();
+        let flushed = spool.flush(&log, &[], None).await.unwrap();
+        assert_eq!((flushed.sent, flushed.dropped, flushed.remaining), (2, 0, 1));
+        assert_eq!(received.load(Ordering::Relaxed), 2);
+        let listed = spool.list(&log).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].1.request.identity, other.identity);
+        assert_eq!(listed[0].1.attempts, 1);
+        assert!(listed[0].1.last_error.is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_skipped() {
+        let log = Log::new();
+        let dir = spool_dir("corrupt");
+        let spool = PublishSpool::new(dir.clone());
+        let (publisher, received) = serve_publisher().await;
+        spool.push(&publisher, &request(), None).await.unwrap();
+
+        // Sorts before the valid entries
+        let corrupt = dir.join(format!("{:020}-{:08x}.json", 0, 0));
+        std::fs::write(&corrupt, b"{\"publisher_url\": ").unwrap();
+        spool.push(&publisher, &request(), None).await.unwrap();
+        assert_eq!(spool.list(&log).await.unwrap().len(), 2);
+        let flushed = spool.flush(&log, &[], None).await.unwrap();
+        assert_eq!((flushed.sent, flushed.dropped, flushed.remaining), (2, 0, 0));
+        assert_eq!(received.load(Ordering::Relaxed), 2);
+
+        // Left for the user to inspect
+        assert!(corrupt.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
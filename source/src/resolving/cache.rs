@@ -0,0 +1,157 @@
+//! An on-disk cache of resolver responses for short-lived processes (the CLI), so
+//! repeated commands against the same hosts don't wait on the resolver each time.
+//! Responses are kept until the earliest expiration of the values in them, which
+//! the resolver bounds by both the record TTLs and the announcement expiry.
+use {
+    crate::{
+        interface::{
+            stored::record::record_utils::RecordKey,
+            wire::api::resolve::v1::ResolveResp,
+        },
+        ta_res,
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    loga::{
+        ea,
+        ErrContext,
+        Log,
+        ResultContext,
+    },
+    sha2::{
+        Digest,
+        Sha256,
+    },
+    std::{
+        env,
+        path::PathBuf,
+    },
+    tokio::fs::{
+        create_dir_all,
+        read,
+        remove_file,
+        rename,
+        write,
+    },
+};
+
+pub struct ResolveCache {
+    dir: PathBuf,
+}
+
+impl ResolveCache {
+    pub fn new(dir: PathBuf) -> Self {
+        return Self { dir: dir };
+    }
+
+    /// The cache for the current user, in `spaghettinuum/resolve` in
+    /// `$XDG_CACHE_HOME` (or `~/.cache`). `None` if neither can be determined.
+    pub fn user_default() -> Option<Self> {
+        let base = match env::var_os("XDG_CACHE_HOME") {
+            Some(d) => PathBuf::from(d),
+            None => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+        };
+        return Some(Self::new(base.join("spaghettinuum").join("resolve")));
+    }
+
+    fn path(&self, identity: &str, keys: &[RecordKey]) -> PathBuf {
+        let hash = Sha256::digest(serde_json::to_vec(&(identity, keys)).unwrap());
+        return self.dir.join(format!("{}.json", zbase32::encode_full_bytes(&hash)));
+    }
+
+    /// Get the cached response for a query, if there is one and it hasn't expired.
+    /// Problems with the cache are logged and treated as misses.
+    pub async fn get(&self, log: &Log, identity: &str, keys: &[RecordKey]) -> Option<ResolveResp> {
+        let path = self.path(identity, keys);
+        let data = read(&path).await.ok()?;
+        let resp = match serde_json::from_slice::<ResolveResp>(&data) {
+            Ok(r) => r,
+            Err(e) => {
+                log.log_err(
+                    loga::DEBUG,
+                    e.context_with(
+                        "Error parsing cached resolve response, ignoring",
+                        ea!(path = path.to_string_lossy()),
+                    ),
+                );
+                return None;
+            },
+        };
+        if cache_expires(&resp).is_none_or(|e| e <= Utc::now()) {
+            _ = remove_file(&path).await;
+            return None;
+        }
+        log.log_with(loga::DEBUG, "Using cached resolve response", ea!(identity = identity));
+        return Some(resp);
+    }
+
+    /// Store a response for a query. Problems with the cache are logged and
+    /// otherwise ignored.
+    pub async fn put(&self, log: &Log, identity: &str, keys: &[RecordKey], resp: &ResolveResp) {
+        if cache_expires(resp).is_none() {
+            return;
+        }
+        let path = self.path(identity, keys);
+        match async {
+            ta_res!(());
+            create_dir_all(&self.dir).await.context("Error creating cache directory")?;
+
+            // Write then move so concurrent processes never see partial entries
+            let temp_path = path.with_extension(format!("{}.tmp", std::process::id()));
+            write(&temp_path, serde_json::to_vec(resp).unwrap()).await.context("Error writing cache entry")?;
+            rename(&temp_path, &path).await.context("Error moving cache entry into place")?;
+            return Ok(());
+        }.await {
+            Ok(_) => { },
+            Err(e) => {
+                log.log_err(
+                    loga::DEBUG,
+                    e.context_with("Error caching resolve response", ea!(path = path.to_string_lossy())),
+                );
+            },
+        }
+    }
+}
+
+/// When a cached response stops being usable: the earliest expiration of the
+/// values in it. `None` if the response is empty.
+fn cache_expires(resp: &ResolveResp) -> Option<DateTime<Utc>> {
+    return resp.iter().map(|(_, v)| v.expires).min();
+}
+
+#[cfg(test)]
+mod test_resolve_cache {
+    use {
+        super::cache_expires,
+        crate::interface::wire::resolve::v1::ResolveValue,
+        chrono::{
+            Duration,
+            Utc,
+        },
+    };
+
+    fn value(expires_mins: i64) -> ResolveValue {
+        return ResolveValue {
+            expires: Utc::now() + Duration::try_minutes(expires_mins).unwrap(),
+            data: None,
+            published: None,
+            provenance: None,
+            external: None,
+        };
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(cache_expires(&vec![]).is_none());
+    }
+
+    #[test]
+    fn test_earliest() {
+        let a = value(10);
+        let b = value(5);
+        let want = b.expires;
+        assert_eq!(cache_expires(&vec![(vec!["a".to_string()], a), (vec!["b".to_string()], b)]), Some(want));
+    }
+}
@@ -1,4 +1,5 @@
 use {
+    self::cache::ResolveCache,
     crate::{
         interface::{
             config::{
@@ -6,8 +7,18 @@ use {
                 ENV_RESOLVER_PAIRS,
             },
             stored::{
+                announcement::{
+                    latest::AnnouncementPublisher,
+                    Announcement,
+                },
+                identity::Identity,
                 record::{
                     self,
+                    addr_pref_record::{
+                        build_addr_pref_key,
+                        latest::IpFamily,
+                        AddrPref,
+                    },
                     delegate_record::{
                         build_delegate_key,
                         Delegate,
@@ -19,24 +30,49 @@ use {
                         RecordKey,
                         RecordRoot,
                     },
+                    succession_record::{
+                        build_succession_key,
+                        Succession,
+                        SUCCESSION_MAX_HOPS,
+                    },
                 },
             },
             wire::{
                 self,
-                api::resolve::v1::ResolveKeyValues,
+                api::resolve::v1::{
+                    ResolveKeyValues,
+                    ResolveResp,
+                    SignedResolveResp,
+                },
             },
         },
-        service::resolver::API_ROUTE_RESOLVE,
-        utils::tls_util::{
-            cert_pem_hash,
-            SpaghTlsClientVerifier,
-            UnverifyingVerifier,
+        service::{
+            publisher::API_ROUTE_PUBLISH,
+            resolver::{
+                SingleKeyVerifier,
+                API_ROUTE_RESOLVE,
+            },
+        },
+        ta_res,
+        utils::{
+            reference_chain::ReferenceChain,
+            signed::IdentSignatureMethods,
+            tls_util::{
+                cert_pem_hash,
+                SpaghTlsClientVerifier,
+                UnverifyingVerifier,
+            },
         },
     },
     flowcontrol::{
         shed,
         superif,
     },
+    futures::{
+        stream::FuturesUnordered,
+        Future,
+        StreamExt,
+    },
     http::Uri,
     htwrap::{
         htreq::{
@@ -71,10 +107,29 @@ use {
         env,
         net::IpAddr,
         str::FromStr,
-        sync::Arc,
+        sync::{
+            Arc,
+            Mutex,
+        },
+        time::Duration,
+    },
+    tokio::{
+        select,
+        time::{
+            sleep,
+            timeout,
+        },
     },
 };
 
+pub mod cache;
+
+/// How long to wait for a connection to a single address before giving up on it.
+pub const CONNECT_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait for a connection attempt before also trying the next address.
+const CONNECT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
 /// For TLS (cert-based identity verification) a connection may need to be made to
 /// a domain name whose address can't be resolved, and must instead be provided
 /// over a separate channel (ex: DoT via manual configuration or RA/DHCP ADN).
@@ -115,6 +170,30 @@ impl std::fmt::Display for UrlPair {
     }
 }
 
+/// Parse a resolver given on the command line, either as `IP=URL` (like in
+/// `SPAGH_RESOLVERS`) or as a URL with an IP address for the host.
+pub fn parse_resolver_url_pair(text: &str) -> Result<UrlPair, loga::Error> {
+    if let Some((ip, url)) = text.split_once("=") {
+        return Ok(UrlPair {
+            address: Some(
+                IpAddr::from_str(ip).context_with("Couldn't parse IP addr in resolver URL pair", ea!(ip = ip))?,
+            ),
+            url: Uri::from_str(url).context_with("Couldn't parse URL in resolver URL pair", ea!(url = url))?,
+        });
+    }
+    let url = Uri::from_str(text).context_with("Couldn't parse resolver URL", ea!(url = text))?;
+    let (_, host, _) = uri_parts(&url).context_with("Resolver URL incomplete", ea!(url = text))?;
+    let htreq::Host::Ip(ip) = host else {
+        return Err(
+            loga::err_with("Resolver URL host isn't an IP address, use the form IP=URL instead", ea!(url = text)),
+        );
+    };
+    return Ok(UrlPair {
+        address: Some(ip),
+        url: url,
+    });
+}
+
 /// This returns a list of ip address/url pairs of resolvers on the system.
 pub fn default_resolver_url_pairs(log: &Log) -> Result<Vec<UrlPair>, loga::Error> {
     let mut out = vec![];
@@ -182,6 +261,114 @@ pub fn default_resolver_url_pairs(log: &Log) -> Result<Vec<UrlPair>, loga::Error
     return Ok(out);
 }
 
+/// Addresses in the order to try connecting, alternating between IPv6 and IPv4
+/// starting with IPv6, or starting with `prefer` if the host published a
+/// preference.
+pub fn connect_order(ips: &Ips, prefer: Option<IpFamily>) -> Vec<IpAddr> {
+    let mut out = vec![];
+    let mut ipv6s = ips.ipv6s.iter().map(|i| IpAddr::V6(*i));
+    let mut ipv4s = ips.ipv4s.iter().map(|i| IpAddr::V4(*i));
+    loop {
+        let ipv6 = ipv6s.next();
+        let ipv4 = ipv4s.next();
+        if ipv6.is_none() && ipv4.is_none() {
+            break;
+        }
+        match prefer {
+            Some(IpFamily::V4) => {
+                out.extend(ipv4);
+                out.extend(ipv6);
+            },
+            Some(IpFamily::V6) | None => {
+                out.extend(ipv6);
+                out.extend(ipv4);
+            },
+        }
+    }
+    return out;
+}
+
+/// Reorder addresses to alternate between address families, starting with the
+/// family of the first address and otherwise keeping their order (RFC 8305
+/// section 4).
+pub fn interleave_families<T>(addrs: Vec<T>, is_ipv6: impl Fn(&T) -> bool) -> Vec<T> {
+    let Some(first_ipv6) = addrs.first().map(&is_ipv6) else {
+        return addrs;
+    };
+    let (first, second): (Vec<T>, Vec<T>) = addrs.into_iter().partition(|a| is_ipv6(a) == first_ipv6);
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    let mut out = vec![];
+    loop {
+        let a = first.next();
+        let b = second.next();
+        if a.is_none() && b.is_none() {
+            break;
+        }
+        out.extend(a);
+        out.extend(b);
+    }
+    return out;
+}
+
+/// Connect to one of several addresses, Happy Eyeballs style (RFC 8305). Addresses
+/// are tried in the order given; if an attempt hasn't finished shortly the next
+/// address is tried in parallel, and a failed attempt immediately moves on to the
+/// next address. Each attempt is limited to `CONNECT_ATTEMPT_TIMEOUT`. Returns the
+/// first successful connection and its address.
+pub async fn connect_staggered<
+    A: Clone + std::fmt::Display,
+    T,
+    F: Fn(A) -> Fut,
+    Fut: Future<Output = Result<T, loga::Error>>,
+>(log: &Log, order: Vec<A>, connect: F) -> Result<(A, T), loga::Error> {
+    let mut order = order.into_iter();
+    let mut pending = FuturesUnordered::new();
+    let mut errs = vec![];
+    loop {
+        let res = if let Some(addr) = order.next() {
+            let attempt = connect(addr.clone());
+            pending.push(async move {
+                match timeout(CONNECT_ATTEMPT_TIMEOUT, attempt).await {
+                    Ok(r) => return (addr, r),
+                    Err(_) => return (addr, Err(loga::err("Timed out connecting"))),
+                }
+            });
+            select!{
+                r = pending.next() => r,
+                _ = sleep(CONNECT_ATTEMPT_DELAY) => continue,
+            }
+        } else {
+            pending.next().await
+        };
+        let Some((addr, res)) = res else {
+            break;
+        };
+        match res {
+            Ok(c) => return Ok((addr, c)),
+            Err(e) => {
+                log.log_with(loga::DEBUG, "Connection attempt failed, trying other addresses", ea!(addr = addr));
+                errs.push(e.context_with("Error connecting to address", ea!(addr = addr)));
+            },
+        }
+    }
+    if errs.is_empty() {
+        return Err(loga::err("Host has no addresses"));
+    }
+    return Err(loga::agg_err("Couldn't connect to any of the host's addresses", errs));
+}
+
+/// Connect to a host with multiple addresses with `connect_staggered`, trying
+/// addresses in `connect_order`.
+pub async fn connect_any_ip<
+    T,
+    F: Fn(IpAddr) -> Fut,
+    Fut: Future<Output = Result<T, loga::Error>>,
+>(log: &Log, ips: &Ips, prefer: Option<IpFamily>, connect: F) -> Result<T, loga::Error> {
+    let (_, conn) = connect_staggered(log, connect_order(ips, prefer), connect).await?;
+    return Ok(conn);
+}
+
 /// Connect to a publisher node which either might be colocated with the resolver
 /// (full url pair) or standalone, resolved via a separate resolver (just a url).
 pub async fn connect_publisher_node(log: &Log, resolvers: &[UrlPair], pair: &UrlPair) -> Result<Conn, loga::Error> {
@@ -190,29 +377,29 @@ pub async fn connect_publisher_node(log: &Log, resolvers: &[UrlPair], pair: &Url
     if pair.address.is_some() {
         return Ok(connect_resolver_node(pair).await?);
     } else {
-        let ResolveTlsRes { ips, certs } =
-            resolve_for_tls(&log, resolvers, &host).await.stack_context(&log, "Error resolving host")?;
+        let ResolveTlsRes { ips, prefer, certs, .. } =
+            resolve_for_tls(&log, resolvers, None, &host).await.stack_context(&log, "Error resolving host")?;
         let mut cert_hashes = HashSet::new();
         for cert in certs {
             cert_hashes.insert(cert_pem_hash(&cert).stack_context(&log, "Invalid cert for host")?);
         }
-        return Ok(
-            connect_ips(
-                ips,
-                rustls::ClientConfig::builder()
-                    .dangerous()
-                    .with_custom_certificate_verifier(Arc::new(SpaghTlsClientVerifier {
-                        hashes: cert_hashes,
-                        inner: None,
-                    }))
-                    .with_no_client_auth(),
-                scheme,
-                host,
-                port,
-            )
-                .await
-                .stack_context(&log, "Failed to establish connection")?,
-        );
+        let tls_config =
+            rustls::ClientConfig::builder()
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(SpaghTlsClientVerifier {
+                    hashes: cert_hashes,
+                    inner: None,
+                    verified_by: Mutex::new(None),
+                }))
+                .with_no_client_auth();
+        return connect_any_ip(
+            &log,
+            &ips,
+            prefer,
+            |ip| connect_ips(Ips::from(ip), tls_config.clone(), scheme.clone(), host.clone(), port),
+        )
+            .await
+            .stack_context(&log, "Failed to establish connection");
     }
 }
 
@@ -221,26 +408,26 @@ pub async fn connect_publisher_node(log: &Log, resolvers: &[UrlPair], pair: &Url
 /// distributed certificate verification.
 pub async fn connect_content(log: &Log, resolvers: &[UrlPair], url: &Uri) -> Result<Conn, loga::Error> {
     let (scheme, host, port) = uri_parts(&url)?;
-    let ResolveTlsRes { ips, certs } = resolve_for_tls(log, resolvers, &host).await?;
+    let ResolveTlsRes { ips, prefer, certs, .. } = resolve_for_tls(log, resolvers, None, &host).await?;
     let mut cert_hashes = HashSet::new();
     for cert in certs {
         cert_hashes.insert(cert_pem_hash(&cert).stack_context(&log, "Invalid cert for host")?);
     }
-    return Ok(
-        htreq::connect_ips(
-            ips,
-            rustls::ClientConfig::builder()
-                .dangerous()
-                .with_custom_certificate_verifier(Arc::new(SpaghTlsClientVerifier {
-                    hashes: cert_hashes,
-                    inner: None,
-                }))
-                .with_no_client_auth(),
-            scheme,
-            host,
-            port,
-        ).await?,
-    );
+    let tls_config =
+        rustls::ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(SpaghTlsClientVerifier {
+                hashes: cert_hashes,
+                inner: None,
+                verified_by: Mutex::new(None),
+            }))
+            .with_no_client_auth();
+    return connect_any_ip(
+        log,
+        &ips,
+        prefer,
+        |ip| htreq::connect_ips(Ips::from(ip), tls_config.clone(), scheme.clone(), host.clone(), port),
+    ).await;
 }
 
 /// Connect to a resolver node. A full UrlPair (with both ip address and domain
@@ -254,6 +441,7 @@ pub async fn connect_resolver_node(pair: &UrlPair) -> Result<Conn, loga::Error>
         Arc::new(SpaghTlsClientVerifier {
             hashes: Default::default(),
             inner: None,
+            verified_by: Mutex::new(None),
         }) as Arc<dyn ServerCertVerifier>
     } else {
         Arc::new(UnverifyingVerifier)
@@ -274,21 +462,211 @@ pub async fn connect_resolver_node(pair: &UrlPair) -> Result<Conn, loga::Error>
     );
 }
 
-/// Resolve ip addresses for a host plus any additional keys. Delegation records
-/// are followed, with all returned results being from the final hop. The returned
-/// hash map contains only exact keys in additional keys - prefixes due to
-/// delegation or the initial host name are trimmed before returning.
+/// Check a signed lookup response: that it was signed by `resolver`, answers the
+/// query for `keys` of `identity`, and was signed no more than `max_age` ago (and
+/// not in the future, allowing the same for clock skew). This doesn't need the
+/// resolver, so saved responses can be checked later. Returns the values.
+pub fn verify_signed_resolve_resp(
+    resp: &SignedResolveResp,
+    resolver: &Identity,
+    identity: &Identity,
+    keys: &[RecordKey],
+    max_age: chrono::Duration,
+) -> Result<ResolveResp, loga::Error> {
+    if &resp.resolver != resolver {
+        return Err(
+            loga::err_with(
+                "Response was signed by a different resolver",
+                ea!(expected = resolver, got = resp.resolver),
+            ),
+        );
+    }
+    let Ok(content) = resp.content.verify(resolver) else {
+        return Err(loga::err_with("Response signature is invalid", ea!(resolver = resolver)));
+    };
+    if &content.identity != identity || content.keys != keys {
+        return Err(loga::err("Signed response is for a different query"));
+    }
+    let age = chrono::Utc::now() - content.signed;
+    if age > max_age || -age > max_age {
+        return Err(
+            loga::err_with(
+                "Signed response time is too far from the current time",
+                ea!(signed = content.signed.to_rfc3339()),
+            ),
+        );
+    }
+    return Ok(content.values);
+}
+
+/// A publisher to query directly for a single identity, skipping resolvers and the
+/// DHT (ex: to debug a broken resolution chain). Values are only accepted from a
+/// publisher presenting the TLS cert listed in the identity's signed announcement.
+#[derive(Clone)]
+pub struct DirectPublisher {
+    pub identity: Identity,
+    pub publisher: AnnouncementPublisher,
+}
+
+impl DirectPublisher {
+    /// Find the publisher with API at `api` and check that the identity's announcement
+    /// (as stored on the publisher, signed by the identity) lists it. `resolvers` are
+    /// only used if the API URL host is a `.s` name.
+    pub async fn connect(
+        log: &Log,
+        resolvers: &[UrlPair],
+        api: &UrlPair,
+        identity: &Identity,
+    ) -> Result<Self, loga::Error> {
+        let log = log.fork(ea!(publisher = api, identity = identity));
+        let info_url = api.join(format!("{}/v1/info", API_ROUTE_PUBLISH));
+        let info =
+            htreq::get_json::<wire::api::publish::latest::InfoResponse>(
+                &log,
+                &mut connect_publisher_node(&log, resolvers, &info_url).await?,
+                &info_url.url,
+                &HashMap::new(),
+                100 * 1024,
+            )
+                .await
+                .stack_context(&log, "Error getting publisher info")?;
+        let announcement_url = api.join(format!("{}/v1/announcement/{}", API_ROUTE_PUBLISH, identity));
+        let announcement =
+            htreq::get_json::<wire::api::publish::latest::AnnouncementResponse>(
+                &log,
+                &mut connect_publisher_node(&log, resolvers, &announcement_url).await?,
+                &announcement_url.url,
+                &HashMap::new(),
+                100 * 1024,
+            )
+                .await
+                .stack_context(&log, "Error getting identity's announcement from publisher")?
+                .announcement;
+        let Some(announcement) = announcement else {
+            return Err(log.err("Publisher has no announcement for the identity"));
+        };
+        let content = match &announcement {
+            Announcement::V1(a) => a.verify(identity),
+        };
+        let Ok(content) = content else {
+            return Err(log.err("Announcement from publisher isn't validly signed by the identity"));
+        };
+        let Some(publisher) = content.publishers.into_iter().find(|p| p.cert_hash == info.cert_pub_hash) else {
+            return Err(
+                log.err_with(
+                    "Identity's announcement doesn't list this publisher",
+                    ea!(cert_hash = zbase32::encode_full_bytes(&info.cert_pub_hash)),
+                ),
+            );
+        };
+        log.log_with(
+            loga::DEBUG,
+            "Identity's announcement lists publisher, querying it directly",
+            ea!(addr = publisher.addr.0),
+        );
+        return Ok(Self {
+            identity: *identity,
+            publisher: publisher,
+        });
+    }
+
+    async fn get(&self, log: &Log, keys: &[RecordKey]) -> Result<ResolveResp, loga::Error> {
+        let url = Uri::from_str(&format!("https://{}", self.publisher.addr)).unwrap();
+        let (scheme, host, port) = uri_parts(&url)?;
+        let mut conn =
+            connect_ips(
+                Ips::from(self.publisher.addr.0.ip()),
+                rustls::ClientConfig::builder()
+                    .dangerous()
+                    .with_custom_certificate_verifier(SingleKeyVerifier::new(self.publisher.cert_hash.clone()))
+                    .with_no_client_auth(),
+                scheme,
+                host,
+                port,
+            )
+                .await
+                .context_with("Error connecting to publisher", ea!(url = url))?;
+        let body =
+            htreq::post(
+                log,
+                &mut conn,
+                &url,
+                &HashMap::new(),
+                serde_json::to_vec(&wire::resolve::ResolveRequest::V1(wire::resolve::v1::ResolveRequest {
+                    ident: self.identity,
+                    keys: keys.to_vec(),
+                })).unwrap(),
+                1024 * 1024,
+            )
+                .await
+                .context("Error requesting values from publisher")?;
+        return serde_json::from_slice::<ResolveResp>(&body).context("Publisher response doesn't match schema");
+    }
+}
+
+/// Where `resolve_via` gets values from.
+#[derive(Clone, Copy)]
+pub enum ResolveVia<'a> {
+    /// Ask resolvers, which find the identity's publishers in the DHT
+    Resolvers(&'a [UrlPair]),
+    /// Ask a single publisher directly. Only names under the publisher's identity can
+    /// be resolved, so delegations and successions to other identities fail.
+    Publisher(&'a DirectPublisher),
+}
+
+pub struct ResolveRes {
+    /// IP addresses of host (from A and AAAA records)
+    pub ips: htreq::Ips,
+    /// The address family the host asks clients to try first, if any
+    pub prefer: Option<IpFamily>,
+    /// Values for the requested additional keys
+    pub additional: HashMap<RecordKey, wire::resolve::v1::ResolveValue>,
+    /// The `.s` names visited, following delegations and identity successions from
+    /// the original name. Empty for non-`.s` names.
+    pub chain: Vec<String>,
+}
+
+/// The `.s` DNS name for a path under an identity, to name hops in reference
+/// chains.
+fn chain_name(root: &Identity, path: &RecordKey) -> String {
+    let mut parts = path.iter().rev().cloned().collect::<Vec<_>>();
+    parts.push(root.to_string());
+    parts.push("s".to_string());
+    return parts.join(".");
+}
+
+/// Resolve ip addresses for a host plus any additional keys. Delegation and
+/// identity succession records are followed (within the limits in
+/// `reference_chain`), with all returned results being from the final hop. The returned hash map contains only exact keys in additional
+/// keys - prefixes due to delegation or the initial host name are trimmed before
+/// returning. The host's address family preference is also returned, if it
+/// published one.
 ///
 /// `name` is a DNS name like `a.b.identity.s` - however this can handle both
 /// spaghettinuum and non- names.
+///
+/// If `cache` is provided, resolver responses are taken from it when fresh and
+/// stored in it otherwise.
 pub async fn resolve(
     log: &Log,
     resolvers: &[UrlPair],
+    cache: Option<&ResolveCache>,
     name: &str,
     additional_keys: &[RecordKey],
-) -> Result<(htreq::Ips, HashMap<RecordKey, wire::resolve::v1::ResolveValue>), loga::Error> {
+) -> Result<ResolveRes, loga::Error> {
+    return resolve_via(log, ResolveVia::Resolvers(resolvers), cache, name, additional_keys).await;
+}
+
+/// Like `resolve` but getting values from `via`. `cache` isn't used when asking a
+/// publisher directly.
+pub async fn resolve_via(
+    log: &Log,
+    via: ResolveVia<'_>,
+    cache: Option<&ResolveCache>,
+    name: &str,
+    additional_keys: &[RecordKey],
+) -> Result<ResolveRes, loga::Error> {
     let log = log.fork(ea!(name = name));
-    if resolvers.is_empty() { }
     let (root, mut path) =
         split_dns_name(
             hickory_resolver::Name::from_str(name).stack_context(&log, "Error parsing name to resolve as DNS name")?,
@@ -296,51 +674,89 @@ pub async fn resolve(
     let mut root = match root {
         RecordRoot::S(i) => i,
         RecordRoot::Dns(name) => {
-            return Ok(
-                (
-                    htreq::resolve(&htreq::Host::Name(name.to_string()))
-                        .await
-                        .stack_context(&log, "Error resolving normal DNS name")?,
-                    HashMap::new(),
-                ),
-            );
+            return Ok(ResolveRes {
+                ips: htreq::resolve(&htreq::Host::Name(name.to_string()))
+                    .await
+                    .stack_context(&log, "Error resolving normal DNS name")?,
+                prefer: None,
+                additional: HashMap::new(),
+                chain: vec![],
+            });
         },
         RecordRoot::Ip(ip) => {
-            return Ok((htreq::Ips::from(ip), HashMap::new()));
+            return Ok(ResolveRes {
+                ips: htreq::Ips::from(ip),
+                prefer: None,
+                additional: HashMap::new(),
+                chain: vec![],
+            });
         },
     };
 
-    // Resolve, repeatedly following delegations
+    // Resolve, repeatedly following delegations and identity successions
+    let key_succession = build_succession_key();
+    let mut succession_hops = 0;
+    let mut chain = ReferenceChain::new(chain_name(&root, &path));
     'delegated : loop {
         // Look up information required to connect
         let mut keys_delegate = vec![];
         for i in 1 ..= path.len() {
             keys_delegate.push(build_delegate_key(path[..i].to_vec()));
         }
+        chain.visit(keys_delegate.len() + 1).stack_context(&log, "Error resolving name")?;
         let key_aaaa = build_dns_key(path.clone(), record::dns_record::RecordType::Aaaa);
         let key_a = build_dns_key(path.clone(), record::dns_record::RecordType::A);
+        let key_addr_pref = build_addr_pref_key(path.clone());
         let mut keys = vec![];
         keys.extend(keys_delegate.clone());
         keys.push(key_aaaa.clone());
         keys.push(key_a.clone());
+        keys.push(key_addr_pref.clone());
+        keys.push(key_succession.clone());
         keys.extend(additional_keys.iter().map(|x| {
             let mut out = path.clone();
             out.extend(x.clone());
             out
         }));
-        let query_path = format!("{}/v1/{}?{}", API_ROUTE_RESOLVE, root.to_string(), join_query_record_keys(&keys));
+        let root_str = root.to_string();
+        let query_path = format!("{}/v1/{}?{}", API_ROUTE_RESOLVE, root_str, join_query_record_keys(&keys));
         let mut resolved = shed!{
             'done _;
+            let resolvers = match via {
+                ResolveVia::Resolvers(r) => r,
+                ResolveVia::Publisher(publisher) => {
+                    if root != publisher.identity {
+                        return Err(
+                            log.err_with(
+                                "Name is under a different identity than the publisher was given for",
+                                ea!(ident = root, publisher_ident = publisher.identity),
+                            ),
+                        );
+                    }
+                    break 'done publisher
+                        .get(&log, &keys)
+                        .await
+                        .stack_context(&log, "Error getting values from publisher")?;
+                },
+            };
+            if let Some(cache) = cache {
+                if let Some(r) = cache.get(&log, &root_str, &keys).await {
+                    break 'done r;
+                }
+            }
             let mut errs = vec![];
             for resolver_url in resolvers {
                 match htreq::get_json::<wire::api::resolve::v1::ResolveResp>(
                     &log,
-                    &mut connect_resolver_node(&resolver_url).await?,
+                    &mut connect_resolver_node(resolver_url).await?,
                     &resolver_url.url.join(&query_path),
                     &HashMap::new(),
                     1024 * 1024,
                 ).await {
                     Ok(r) => {
+                        if let Some(cache) = cache {
+                            cache.put(&log, &root_str, &keys, &r).await;
+                        }
                         break 'done r;
                     },
                     Err(e) => {
@@ -353,6 +769,37 @@ pub async fn resolve(
             return Err(log.agg_err("Error making requests to any resolver", errs));
         }.into_iter().collect::<ResolveKeyValues>();
 
+        // Check if the identity was replaced, repeat with the successor
+        shed!{
+            let Some(succession) = resolved.remove(&key_succession).and_then(|c| c.data) else {
+                break;
+            };
+            let successor = match serde_json::from_value::<Succession>(succession) {
+                Ok(s) => s.verify(&root),
+                Err(e) => Err(e.context("Succession record doesn't match schema")),
+            };
+            let successor = match successor {
+                Ok(s) => s,
+                Err(e) => {
+                    log.log_err(loga::DEBUG, e.context_with("Ignoring invalid succession record", ea!(ident = root)));
+                    break;
+                },
+            };
+            if succession_hops >= SUCCESSION_MAX_HOPS {
+                log.log_with(
+                    loga::DEBUG,
+                    "Succession chain too long, not following further",
+                    ea!(ident = root, successor = successor),
+                );
+                break;
+            }
+            succession_hops += 1;
+            chain.follow(chain_name(&successor, &path)).stack_context(&log, "Error following identity succession")?;
+            log.log_with(loga::DEBUG, "Following identity succession", ea!(ident = root, successor = successor));
+            root = successor;
+            continue 'delegated;
+        }
+
         // Check if delegated, repeat
         for key_delegate in keys_delegate {
             shed!{
@@ -364,12 +811,18 @@ pub async fn resolve(
                 };
                 match delegate {
                     Delegate::V1(d) => {
+                        chain.fanout(d.0.len()).stack_context(&log, "Error following delegation")?;
                         let Some((choose_root, mut choose_head)) =
                             d.0.as_slice().choose(&mut thread_rng()).cloned() else {
-                                return Ok((Ips {
-                                    ipv4s: vec![],
-                                    ipv6s: vec![],
-                                }, HashMap::new()));
+                                return Ok(ResolveRes {
+                                    ips: Ips {
+                                        ipv4s: vec![],
+                                        ipv6s: vec![],
+                                    },
+                                    prefer: None,
+                                    additional: HashMap::new(),
+                                    chain: chain.names().to_vec(),
+                                });
                             };
 
                         // Replace prefix of head
@@ -378,12 +831,14 @@ pub async fn resolve(
                         superif!({
                             match choose_root {
                                 RecordRoot::S(choose_root) => {
+                                    chain
+                                        .follow(chain_name(&choose_root, &path))
+                                        .stack_context(&log, "Error following delegation")?;
                                     root = choose_root.clone();
                                     continue 'delegated;
                                 },
                                 RecordRoot::Dns(dns_root) => {
-                                    let mut dns_path = vec![];
-                                    dns_path.reserve(path.len() + 1);
+                                    let mut dns_path = Vec::with_capacity(path.len() + 1);
                                     for e in path.iter().rev() {
                                         dns_path.push(
                                             punycode::encode_str(
@@ -402,7 +857,12 @@ pub async fn resolve(
                                 RecordRoot::Ip(ip) => break 'external htreq::Ips::from(ip),
                             }
                         } ips = 'external {
-                            return Ok((ips, HashMap::new()));
+                            return Ok(ResolveRes {
+                                ips: ips,
+                                prefer: None,
+                                additional: HashMap::new(),
+                                chain: chain.names().to_vec(),
+                            });
                         })
                     },
                 }
@@ -470,20 +930,119 @@ pub async fn resolve(
         if ips.ipv4s.is_empty() && ips.ipv6s.is_empty() {
             return Err(log.err("Couldn't resolve name to any IP addresses"));
         }
-        return Ok((ips, resolved.into_iter().filter_map(|(mut k, v)| {
-            if !k.starts_with(&path) {
-                return None;
+        let prefer = shed!{
+            let Some(r) = resolved.remove(&key_addr_pref).and_then(|r| r.data) else {
+                break None;
+            };
+            match serde_json::from_value::<AddrPref>(r) {
+                Ok(AddrPref::V1(r)) => break Some(r.prefer),
+                Err(e) => {
+                    log.log_err(
+                        loga::DEBUG,
+                        e.context("Couldn't parse address preference record into expected JSON format, ignoring"),
+                    );
+                    break None;
+                },
             }
-            return Some((k.split_off(path.len()), v));
-        }).collect::<HashMap::<_, _>>()));
+        };
+        return Ok(ResolveRes {
+            ips: ips,
+            prefer: prefer,
+            additional: resolved.into_iter().filter_map(|(mut k, v)| {
+                if !k.starts_with(&path) {
+                    return None;
+                }
+                return Some((k.split_off(path.len()), v));
+            }).collect::<HashMap::<_, _>>(),
+            chain: chain.names().to_vec(),
+        });
+    }
+}
+
+/// Tell resolvers the values for the last name in `chain` (from `ResolveRes`) seem
+/// wrong, ex: none of the addresses accepted a connection, so they look them up
+/// again in the background. The host's addresses are always reported, along with
+/// `extra_keys` (relative to the name, like `additional_keys` when resolving).
+/// Best effort: errors are only logged. Does nothing when asking a publisher
+/// directly.
+pub async fn report_suspect(
+    log: &Log,
+    via: ResolveVia<'_>,
+    chain: &[String],
+    extra_keys: &[RecordKey],
+    reason: wire::api::resolve::v1::SuspectReason,
+) {
+    let ResolveVia::Resolvers(resolvers) = via else {
+        return;
+    };
+    let Some(name) = chain.last() else {
+        return;
+    };
+    let log = log.fork(ea!(name = name));
+    let parsed = hickory_resolver::Name::from_str(name).context("Invalid DNS name").and_then(split_dns_name);
+    let (root, path) = match parsed {
+        Ok(r) => r,
+        Err(e) => {
+            log.log_err(loga::DEBUG, e.context("Error parsing name to report as suspect"));
+            return;
+        },
+    };
+    let RecordRoot::S(identity) = root else {
+        return;
+    };
+    let mut keys = vec![
+        build_dns_key(path.clone(), record::dns_record::RecordType::Aaaa),
+        build_dns_key(path.clone(), record::dns_record::RecordType::A),
+        build_addr_pref_key(path.clone())
+    ];
+    keys.extend(extra_keys.iter().map(|x| {
+        let mut out = path.clone();
+        out.extend(x.clone());
+        out
+    }));
+    let report = wire::api::resolve::v1::SuspectReport {
+        identity: identity,
+        keys: keys,
+        reason: reason,
+    };
+    for resolver_url in resolvers {
+        match async {
+            ta_res!(wire::api::resolve::v1::SuspectReportResponse);
+            return htreq::post_json::<wire::api::resolve::v1::SuspectReportResponse>(
+                &log,
+                &mut connect_resolver_node(resolver_url).await?,
+                &resolver_url.url.join(format!("{}/suspect", API_ROUTE_RESOLVE)),
+                &HashMap::new(),
+                &report,
+                1024,
+            ).await;
+        }.await {
+            Ok(r) => {
+                log.log_with(
+                    loga::DEBUG,
+                    "Reported suspect values to resolver",
+                    ea!(resolver = resolver_url, revalidating = r.revalidating),
+                );
+            },
+            Err(e) => {
+                log.log_err(
+                    loga::DEBUG,
+                    e.context_with("Error reporting suspect values to resolver", ea!(resolver = resolver_url)),
+                );
+            },
+        }
     }
 }
 
 pub struct ResolveTlsRes {
     /// IP addresses of host (from A and AAAA records)
     pub ips: htreq::Ips,
+    /// The address family the host asks clients to try first, if any
+    pub prefer: Option<IpFamily>,
     /// TLS public keys (PEM) for the host
     pub certs: Vec<String>,
+    /// The `.s` names visited resolving the host, see `ResolveRes::chain`
+    pub chain: Vec<String>,
 }
 
 /// Like `resolve` but also requests TLS certs for the host. This should be
@@ -491,10 +1050,22 @@ pub struct ResolveTlsRes {
 pub async fn resolve_for_tls(
     log: &Log,
     resolvers: &[UrlPair],
+    cache: Option<&ResolveCache>,
+    host: &htreq::Host,
+) -> Result<ResolveTlsRes, loga::Error> {
+    return resolve_for_tls_via(log, ResolveVia::Resolvers(resolvers), cache, host).await;
+}
+
+/// Like `resolve_for_tls` but getting values from `via`, see `resolve_via`.
+pub async fn resolve_for_tls_via(
+    log: &Log,
+    via: ResolveVia<'_>,
+    cache: Option<&ResolveCache>,
     host: &htreq::Host,
 ) -> Result<ResolveTlsRes, loga::Error> {
     let tls_key = vec![record::tls_record::KEY_SUFFIX_TLS.to_string()];
-    let (ips, mut additional_records) = resolve(log, resolvers, &host.to_string(), &[tls_key.clone()]).await?;
+    let ResolveRes { ips, prefer, additional: mut additional_records, chain } =
+        resolve_via(log, via, cache, &host.to_string(), std::slice::from_ref(&tls_key)).await?;
     let mut certs = vec![];
     shed!{
         let Some(r) = additional_records.remove(&tls_key) else {
@@ -524,6 +1095,110 @@ pub async fn resolve_for_tls(
     };
     return Ok(ResolveTlsRes {
         ips: ips,
+        prefer: prefer,
         certs: certs,
+        chain: chain,
     });
 }
+
+#[cfg(test)]
+mod test_verify_signed_resolve_resp {
+    use {
+        super::verify_signed_resolve_resp,
+        crate::{
+            interface::{
+                config::identity::LocalIdentitySecret,
+                wire::api::{
+                    publish::v1::JsonSignature,
+                    resolve::v1::{
+                        SignedResolveResp,
+                        SignedResolveRespContent,
+                    },
+                },
+            },
+            utils::signed::IdentSignatureMethods,
+        },
+        chrono::{
+            Duration,
+            Utc,
+        },
+    };
+
+    #[test]
+    fn test_verify() {
+        let (resolver, mut resolver_secret) = LocalIdentitySecret::new();
+        let (identity, _) = LocalIdentitySecret::new();
+        let keys = vec![vec!["www".to_string(), "dns_a".to_string()]];
+        let sign = |secret: &mut LocalIdentitySecret, signed| {
+            let (resolver, content) = JsonSignature::sign(secret, SignedResolveRespContent {
+                signed: signed,
+                identity: identity,
+                keys: keys.clone(),
+                values: vec![],
+            }).unwrap();
+            return SignedResolveResp {
+                resolver: resolver,
+                content: content,
+            };
+        };
+        let max_age = Duration::try_minutes(5).unwrap();
+        let resp = sign(&mut resolver_secret, Utc::now());
+        assert!(verify_signed_resolve_resp(&resp, &resolver, &identity, &keys, max_age).is_ok());
+
+        // Wrong query
+        assert!(verify_signed_resolve_resp(&resp, &resolver, &identity, &[], max_age).is_err());
+
+        // Wrong resolver
+        let (other, mut other_secret) = LocalIdentitySecret::new();
+        let other_resp = sign(&mut other_secret, Utc::now());
+        assert!(verify_signed_resolve_resp(&other_resp, &resolver, &identity, &keys, max_age).is_err());
+        assert!(verify_signed_resolve_resp(&other_resp, &other, &identity, &keys, max_age).is_ok());
+
+        // Too old
+        let old = sign(&mut resolver_secret, Utc::now() - Duration::try_minutes(10).unwrap());
+        assert!(verify_signed_resolve_resp(&old, &resolver, &identity, &keys, max_age).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_parse_resolver_url_pair {
+    use {
+        super::parse_resolver_url_pair,
+        std::net::IpAddr,
+    };
+
+    #[test]
+    fn test_parse() {
+        let pair = parse_resolver_url_pair("10.0.0.1=https://resolver.example.org:12434").unwrap();
+        assert_eq!(pair.address, Some("10.0.0.1".parse::<IpAddr>().unwrap()));
+        assert_eq!(pair.url.host(), Some("resolver.example.org"));
+        let pair = parse_resolver_url_pair("https://10.0.0.2:12434").unwrap();
+        assert_eq!(pair.address, Some("10.0.0.2".parse::<IpAddr>().unwrap()));
+
+        // Named hosts need an address
+        assert!(parse_resolver_url_pair("https://resolver.example.org:12434").is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_interleave_families {
+    use {
+        super::interleave_families,
+        std::net::SocketAddr,
+    };
+
+    #[test]
+    fn test_interleave() {
+        let addrs = ["[2001:db8::1]:1", "[2001:db8::2]:1", "192.0.2.1:1", "192.0.2.2:1", "192.0.2.3:1"]
+            .into_iter()
+            .map(|a| a.parse::<SocketAddr>().unwrap())
+            .collect::<Vec<_>>();
+        let order = interleave_families(addrs.clone(), |a| a.is_ipv6());
+        assert_eq!(order, vec![addrs[0], addrs[2], addrs[1], addrs[3], addrs[4]]);
+
+        // Starts with the family of the first address
+        let order = interleave_families(vec![addrs[2], addrs[0], addrs[3], addrs[1]], |a| a.is_ipv6());
+        assert_eq!(order, vec![addrs[2], addrs[0], addrs[3], addrs[1]]);
+        assert!(interleave_families(Vec::<SocketAddr>::new(), |a| a.is_ipv6()).is_empty());
+    }
+}
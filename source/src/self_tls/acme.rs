@@ -0,0 +1,382 @@
+//! Certificates for non-`.s` names from public ACME CAs. DNS-01 challenge
+//! responses are published as TXT records under the node identity, and the DNS
+//! bridge serves them for the hosted zone containing each name.
+use {
+    crate::{
+        interface::{
+            config::node::acme_config::AcmeConfig,
+            stored::{
+                self,
+                record::record_utils::RecordKey,
+            },
+        },
+        publishing::Publisher,
+        utils::{
+            fs_util::{
+                maybe_read,
+                maybe_read_json,
+                write,
+            },
+            identity_secret::IdentitySigner,
+            publish_util::PublishArgs,
+            recent_errors::log_warn_err,
+            task_status::TrackedTasks,
+            time_util::ToInstant,
+            tls_util::{
+                encode_priv_pem,
+                extract_expiry,
+            },
+        },
+    },
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    der::Encode,
+    instant_acme::{
+        Account,
+        AccountCredentials,
+        AuthorizationStatus,
+        ChallengeType,
+        Identifier,
+        NewAccount,
+        NewOrder,
+        OrderStatus,
+    },
+    loga::{
+        ea,
+        Log,
+        ResultContext,
+    },
+    p256::pkcs8::EncodePrivateKey,
+    std::{
+        collections::{
+            HashMap,
+            HashSet,
+        },
+        path::Path,
+        str::FromStr,
+        sync::{
+            Arc,
+            Mutex,
+        },
+    },
+    taskmanager::TaskManager,
+    tokio::{
+        fs::create_dir_all,
+        select,
+        time::{
+            sleep,
+            sleep_until,
+        },
+    },
+    x509_cert::builder::Builder,
+};
+
+const CHALLENGE_LABEL: &str = "_acme-challenge";
+
+/// How long before expiry to renew.
+fn renew_before() -> Duration {
+    return Duration::try_days(30).unwrap();
+}
+
+/// TTL (minutes) of the published challenge records. Short, so a retried challenge
+/// isn't shadowed by a cached old value.
+const CHALLENGE_TTL: i32 = 1;
+
+fn normalize_name(name: &str) -> String {
+    return name.trim_end_matches('.').to_ascii_lowercase();
+}
+
+/// The key of the TXT record for a name's DNS-01 challenge, relative to the
+/// identity hosting the zone containing the name. Wildcard names share the
+/// challenge record of their base name.
+fn challenge_key(hosted_zones: &[String], name: &str) -> Result<RecordKey, loga::Error> {
+    let name = normalize_name(name);
+    let name = name.strip_prefix("*.").unwrap_or(&name);
+    let mut best: Option<String> = None;
+    for zone in hosted_zones {
+        let zone = normalize_name(zone);
+        if name != zone && !name.ends_with(&format!(".{}", zone)) {
+            continue;
+        }
+        if best.as_ref().map(|b| b.len() < zone.len()).unwrap_or(true) {
+            best = Some(zone);
+        }
+    }
+    let zone = best.context_with("Name isn't in any DNS bridge hosted zone for the node identity", ea!(name = name))?;
+    let mut key = name[..name.len() - zone.len()]
+        .trim_end_matches('.')
+        .split('.')
+        .filter(|p| !p.is_empty())
+        .rev()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>();
+    key.push(CHALLENGE_LABEL.to_string());
+    return Ok(stored::record::dns_record::build_dns_key(key, stored::record::dns_record::RecordType::Txt));
+}
+
+/// Load the stored account or register a new one.
+async fn get_account(dir: &Path, config: &AcmeConfig) -> Result<Account, loga::Error> {
+    let account_path = dir.join("account.json");
+    if let Some(credentials) = maybe_read_json::<AccountCredentials>(&account_path).await? {
+        return Account::from_credentials(credentials).await.context("Error restoring stored ACME account credentials");
+    }
+    let contact = config.contact.iter().map(|c| c.as_str()).collect::<Vec<_>>();
+    let (account, credentials) = Account::create(&NewAccount {
+        contact: &contact,
+        terms_of_service_agreed: true,
+        only_return_existing: false,
+    }, &config.directory_url, None).await.context("Error creating ACME account")?;
+    write(&account_path, &serde_json::to_vec(&credentials).unwrap()).await?;
+    return Ok(account);
+}
+
+/// Run an order to completion, returning the certificate chain and private key as
+/// PEM.
+async fn order_cert(
+    log: &Log,
+    dir: &Path,
+    publisher: &Arc<dyn Publisher>,
+    identity_signer: &Arc<Mutex<dyn IdentitySigner>>,
+    challenge_keys: &HashMap<String, RecordKey>,
+    config: &AcmeConfig,
+) -> Result<(String, String), loga::Error> {
+    let account = get_account(dir, config).await?;
+    let identifiers = config.names.iter().map(|n| Identifier::Dns(normalize_name(n))).collect::<Vec<_>>();
+    let mut order =
+        account
+            .new_order(&NewOrder { identifiers: &identifiers })
+            .await
+            .context("Error creating ACME order")?;
+
+    // Publish challenge responses
+    let mut values = HashMap::<RecordKey, Vec<String>>::new();
+    let mut challenge_urls = vec![];
+    for authz in order.authorizations().await.context("Error getting ACME order authorizations")? {
+        match authz.status {
+            AuthorizationStatus::Pending => { },
+            AuthorizationStatus::Valid => continue,
+            s => {
+                return Err(loga::err_with("ACME authorization can't be completed", ea!(status = format!("{:?}", s))));
+            },
+        }
+        let Identifier::Dns(name) = &authz.identifier;
+        let challenge =
+            authz
+                .challenges
+                .iter()
+                .find(|c| c.r#type == ChallengeType::Dns01)
+                .context_with("CA offered no DNS-01 challenge", ea!(name = name))?;
+        let key =
+            challenge_keys
+                .get(name.strip_prefix("*.").unwrap_or(name))
+                .context_with("CA requested authorization for an unexpected name", ea!(name = name))?;
+        values.entry(key.clone()).or_default().push(order.key_authorization(challenge).dns_value());
+        challenge_urls.push(challenge.url.clone());
+    }
+    let published_keys = values.keys().cloned().collect::<HashSet<_>>();
+    if !values.is_empty() {
+        let mut set = HashMap::new();
+        for (key, v) in values {
+            set.insert(key, stored::record::RecordValue::V1(stored::record::latest::RecordValue {
+                ttl: CHALLENGE_TTL,
+                data: Some(
+                    serde_json::to_value(
+                        stored::record::dns_record::DnsTxt::V1(stored::record::dns_record::latest::DnsTxt(v)),
+                    ).unwrap(),
+                ),
+            }));
+        }
+        publisher.publish(log, identity_signer, PublishArgs {
+            set: set,
+            ..Default::default()
+        }).await.context("Error publishing ACME challenge records")?;
+    }
+    let res = async {
+        for url in &challenge_urls {
+            order.set_challenge_ready(url).await.context("Error marking ACME challenge ready")?;
+        }
+
+        // Wait for validation
+        let mut delay = std::time::Duration::from_secs(5);
+        for _ in 0 .. 10 {
+            sleep(delay).await;
+            let state = order.refresh().await.context("Error refreshing ACME order state")?;
+            match state.status {
+                OrderStatus::Ready => break,
+                OrderStatus::Invalid => {
+                    return Err(
+                        loga::err_with(
+                            "CA rejected ACME order",
+                            ea!(authorizations = format!("{:?}", state.authorizations)),
+                        ),
+                    );
+                },
+                _ => { },
+            }
+            delay *= 2;
+        }
+
+        // Finalize with a new key
+        let priv_key = p256::ecdsa::SigningKey::random(&mut rand::thread_rng());
+        let mut csr_builder =
+            x509_cert::builder::RequestBuilder::new(
+                x509_cert::name::RdnSequence::from_str(
+                    &format!("CN={}", normalize_name(&config.names[0])),
+                ).context("Error building CSR subject")?,
+                &priv_key,
+            ).context("Error creating CSR")?;
+        let mut alt_names = vec![];
+        for name in &config.names {
+            alt_names.push(
+                x509_cert::ext::pkix::name::GeneralName::DnsName(
+                    der::asn1::Ia5String::new(
+                        &normalize_name(name),
+                    ).context_with("Invalid name for CSR", ea!(name = name))?,
+                ),
+            );
+        }
+        csr_builder
+            .add_extension(&x509_cert::ext::pkix::SubjectAltName(alt_names))
+            .context("Error adding names to CSR")?;
+        let csr =
+            csr_builder
+                .build::<p256::ecdsa::DerSignature>()
+                .context("Error signing CSR")?
+                .to_der()
+                .context("Error encoding CSR")?;
+        order.finalize(&csr).await.context("Error finalizing ACME order")?;
+        let mut delay = std::time::Duration::from_secs(1);
+        for _ in 0 .. 10 {
+            if let Some(pub_pem) = order.certificate().await.context("Error fetching ACME certificate")? {
+                return Ok((pub_pem, encode_priv_pem(priv_key.to_pkcs8_der().unwrap().as_bytes())));
+            }
+            sleep(delay).await;
+            delay *= 2;
+        }
+        return Err(loga::err("Timed out waiting for CA to issue certificate"));
+    }.await;
+
+    // Clean up challenge responses
+    if !published_keys.is_empty() {
+        if let Err(e) = publisher.publish(log, identity_signer, PublishArgs {
+            clear: published_keys,
+            ..Default::default()
+        }).await {
+            log_warn_err(log, e.context("Error removing ACME challenge records"));
+        }
+    }
+    return res;
+}
+
+/// Start a task that obtains and renews a certificate from an ACME CA. The current
+/// certificate and key are stored as `pub.pem` and `priv.pem` in a directory under
+/// `acme` in the cache dir.
+///
+/// `hosted_zones` are the DNS bridge hosted zones served from the node identity.
+pub async fn start_acme(
+    log: &Log,
+    tm: &TaskManager,
+    cache_dir: &Path,
+    publisher: Arc<dyn Publisher>,
+    identity_signer: &Arc<Mutex<dyn IdentitySigner>>,
+    hosted_zones: &[String],
+    config: AcmeConfig,
+) -> Result<(), loga::Error> {
+    let first_name = config.names.first().context("ACME certificate config has no names")?;
+    let log = log.fork(ea!(name = first_name));
+    let mut challenge_keys = HashMap::new();
+    for name in &config.names {
+        let key = challenge_key(hosted_zones, name)?;
+        let name = normalize_name(name);
+        challenge_keys.insert(name.strip_prefix("*.").unwrap_or(&name).to_string(), key);
+    }
+    let dir = cache_dir.join("acme").join(normalize_name(first_name).replace('*', "_"));
+    create_dir_all(&dir).await.context_with("Error creating ACME cache dir", ea!(path = dir.to_string_lossy()))?;
+    tm.tracked_task("ACME - Certificate renewal", {
+        let tm = tm.clone();
+        let identity_signer = identity_signer.clone();
+        async move {
+            async fn write_certs(dir: &Path, pub_pem: &str, priv_pem: &str) -> Result<(), loga::Error> {
+                write(dir.join("pub.pem"), pub_pem.as_bytes()).await.context("Error writing new pub.pem")?;
+                write(dir.join("priv.pem"), priv_pem.as_bytes()).await.context("Error writing new priv.pem")?;
+                return Ok(());
+            }
+
+            let mut backoff = std::time::Duration::from_secs(60);
+            loop {
+                // Wait until renewal is due
+                let expiry = match maybe_read(dir.join("pub.pem")).await {
+                    Ok(Some(pub_pem)) => extract_expiry(&pub_pem).ok(),
+                    Ok(None) => None,
+                    Err(e) => {
+                        log_warn_err(&log, e);
+                        None
+                    },
+                };
+                if let Some(expiry) = expiry {
+                    let renew_at: DateTime<Utc> = expiry - renew_before();
+                    log.log_with(loga::DEBUG, "Sleeping until cert needs renewal", ea!(deadline = renew_at));
+                    select!{
+                        _ = tm.until_terminate() => {
+                            return;
+                        }
+                        _ = sleep_until(renew_at.to_instant()) =>(),
+                    }
+                }
+
+                // Order
+                let res = select!{
+                    _ = tm.until_terminate() => {
+                        return;
+                    }
+                    r = order_cert(&log, &dir, &publisher, &identity_signer, &challenge_keys, &config) => r,
+                };
+                match res {
+                    Ok((pub_pem, priv_pem)) => {
+                        backoff = std::time::Duration::from_secs(60);
+                        log.log(loga::INFO, "Obtained new ACME certificate");
+                        let mut dirs = vec![dir.clone()];
+                        dirs.extend(config.write_certs_dir.clone());
+                        for dir in dirs {
+                            if let Err(e) = write_certs(&dir, &pub_pem, &priv_pem).await {
+                                log_warn_err(&log, e);
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        log_warn_err(&log, e.context_with("Error obtaining ACME certificate, retrying", ea!(
+                            delay_secs = backoff.as_secs()
+                        )));
+                        select!{
+                            _ = tm.until_terminate() => {
+                                return;
+                            }
+                            _ = sleep(backoff) =>(),
+                        }
+                        backoff = (backoff * 2).min(std::time::Duration::from_secs(6 * 60 * 60));
+                    },
+                }
+            }
+        }
+    });
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test_acme {
+    use super::challenge_key;
+
+    #[test]
+    fn test_challenge_key() {
+        let zones = vec!["example.org".to_string(), "sub.example.org.".to_string()];
+        assert_eq!(challenge_key(&zones, "example.org").unwrap(), vec!["_acme-challenge", "dns/txt"]);
+        assert_eq!(challenge_key(&zones, "*.example.org").unwrap(), vec!["_acme-challenge", "dns/txt"]);
+        assert_eq!(challenge_key(&zones, "a.b.Example.org.").unwrap(), vec!["b", "a", "_acme-challenge", "dns/txt"]);
+        assert_eq!(challenge_key(&zones, "www.sub.example.org").unwrap(), vec!["www", "_acme-challenge", "dns/txt"]);
+        assert!(challenge_key(&zones, "example.com").is_err());
+        assert!(challenge_key(&zones, "badexample.org").is_err());
+    }
+}
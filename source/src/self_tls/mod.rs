@@ -35,6 +35,10 @@ use {
             },
             wire::{
                 self,
+                api::admin::v1::{
+                    SelfTlsCertifier,
+                    SelfTlsStatus,
+                },
                 resolve::DNS_DOT_SUFFIX,
             },
         },
@@ -49,6 +53,8 @@ use {
             fs_util::write,
             identity_secret::IdentitySigner,
             publish_util,
+            recent_errors::log_warn_err,
+            task_status::TrackedTasks,
             time_util::ToInstant,
             tls_util::{
                 create_leaf_cert_der_local,
@@ -112,6 +118,11 @@ use {
 
 pub mod db;
 
+/// The latest `self_tls.sqlite3` schema version, from `buildlib/self_tls`.
+pub const DB_SCHEMA_VERSION: usize = 0;
+
+pub mod acme;
+
 pub const CERTIFIER_URL: &'static str = "https://certipasta.isandrew.com";
 
 pub fn publish_ssl_ttl() -> Duration {
@@ -208,14 +219,53 @@ pub async fn request_cert(
     });
 }
 
+/// The state of cert renewal, for the admin api.
+#[derive(Clone)]
+pub struct SelfTlsMonitor {
+    status: Arc<Mutex<SelfTlsStatus>>,
+}
+
+impl SelfTlsMonitor {
+    pub fn new(options: RequestCertOptions) -> SelfTlsMonitor {
+        return SelfTlsMonitor { status: Arc::new(Mutex::new(SelfTlsStatus {
+            certifier: if options.certifier {
+                SelfTlsCertifier::Certipasta
+            } else {
+                SelfTlsCertifier::SelfSigned
+            },
+            current_expiry: None,
+            pending_expiry: None,
+            pending_after: None,
+            next_renewal: None,
+            last_attempt: None,
+            last_success: None,
+            last_error: None,
+        })) };
+    }
+
+    pub fn status(&self) -> SelfTlsStatus {
+        return self.status.lock().unwrap().clone();
+    }
+
+    /// Update the current and pending cert details.
+    fn set_state(&self, state: &stored::self_tls::latest::SelfTlsState) {
+        let mut status = self.status.lock().unwrap();
+        status.current_expiry = extract_expiry(state.current.pub_pem.as_bytes()).ok();
+        status.pending_expiry =
+            state.pending.as_ref().and_then(|p| extract_expiry(p.pair.pub_pem.as_bytes()).ok());
+        status.pending_after = state.pending.as_ref().map(|p| p.after);
+    }
+}
+
 /// Produces a stream of TLS cert pairs, with a new pair some time before the
-/// previous pair expires.
+/// previous pair expires. Renewal attempts are recorded in `monitor`.
 pub async fn request_cert_stream(
     log: &Log,
     tm: &TaskManager,
     signer: Arc<Mutex<dyn IdentitySigner>>,
     options: RequestCertOptions,
     initial_pair: CertPair,
+    monitor: SelfTlsMonitor,
 ) -> Result<watch::Receiver<CertPair>, loga::Error> {
     let log = &log.fork(ea!(sys = "self_tls"));
 
@@ -227,7 +277,7 @@ pub async fn request_cert_stream(
     let refresh_at =
         decide_refresh_at(&initial_pair.pub_pem).context("Error extracting expiration time from cert pem")?;
     let (certs_stream_tx, certs_stream_rx) = channel(initial_pair);
-    tm.critical_task("API - Self-TLS refresher", {
+    tm.tracked_critical_task("API - Self-TLS refresher", {
         let tm = tm.clone();
         let log = log.clone();
         async move {
@@ -236,6 +286,7 @@ pub async fn request_cert_stream(
             let log = &log;
             loop {
                 log.log_with(loga::DEBUG, "Sleeping until cert needs refresh", ea!(deadline = refresh_at));
+                monitor.status.lock().unwrap().next_renewal = Some(refresh_at);
                 select!{
                     _ = tm.until_terminate() => {
                         break;
@@ -247,12 +298,17 @@ pub async fn request_cert_stream(
                 let certs = shed!{
                     'ok _;
                     for _ in 0 .. max_tries {
+                        monitor.status.lock().unwrap().last_attempt = Some(Utc::now());
                         match request_cert(log, signer.clone(), options).await {
                             Ok(certs) => {
+                                let mut status = monitor.status.lock().unwrap();
+                                status.last_success = status.last_attempt;
+                                status.last_error = None;
                                 break 'ok Some(certs);
                             },
                             Err(e) => {
-                                log.log_err(loga::WARN, e.context("Error getting new certs"));
+                                monitor.status.lock().unwrap().last_error = Some(e.to_string());
+                                log_warn_err(log, e.context("Error getting new certs"));
                                 sleep(backoff).await;
                                 backoff = backoff * 2;
                             },
@@ -306,6 +362,7 @@ impl rustls_21::server::ResolvesServerCert for Rustls21SimpleResolvesServerCert
 /// provided location.
 ///
 /// Returns `None` if the task manager is shut down before initial setup completes.
+/// Otherwise returns the cert resolvers and a monitor with the renewal status.
 pub async fn htserve_certs(
     log: &Log,
     cache_dir: &Path,
@@ -314,8 +371,12 @@ pub async fn htserve_certs(
     publisher: Option<&Arc<dyn Publisher>>,
     identity_signer: &Arc<Mutex<dyn IdentitySigner>>,
     options: RequestCertOptions,
-) -> Result<Option<(Arc<dyn ResolvesServerCert>, Arc<dyn rustls_21::server::ResolvesServerCert>)>, loga::Error> {
+) -> Result<
+    Option<(Arc<dyn ResolvesServerCert>, Arc<dyn rustls_21::server::ResolvesServerCert>, SelfTlsMonitor)>,
+    loga::Error,
+> {
     let identity = identity_signer.lock().unwrap().identity()?;
+    let monitor = SelfTlsMonitor::new(options);
     create_dir_all(cache_dir)
         .await
         .context_with("Error creating htserve cache dir", ea!(path = cache_dir.to_string_lossy()))?;
@@ -353,7 +414,7 @@ pub async fn htserve_certs(
         return Ok(());
     }
 
-    let db_pool = db_util::setup_db(&cache_dir.join("self_tls.sqlite3"), db::migrate).await?;
+    let db_pool = db_util::setup_db(&cache_dir.join("self_tls.sqlite3"), DB_SCHEMA_VERSION, db::migrate).await?;
     db_pool.tx(|conn| Ok(db::api_certs_setup(conn)?)).await?;
 
     // Prepare initial state, either restoring or getting from scratch
@@ -378,14 +439,19 @@ pub async fn htserve_certs(
         },
         None => {
             let pair = loop {
+                monitor.status.lock().unwrap().last_attempt = Some(Utc::now());
                 match select!{
                     c = request_cert(&log, identity_signer.clone(), options) => c,
                     _ = tm.until_terminate() => {
                         return Ok(None);
                     }
                 } {
-                    Ok(p) => break p,
+                    Ok(p) => {
+                        monitor.status.lock().unwrap().last_success = Some(Utc::now());
+                        break p;
+                    },
                     Err(e) => {
+                        monitor.status.lock().unwrap().last_error = Some(e.to_string());
                         log.log_err(
                             loga::WARN,
                             e.context_with("Error fetching initial certificates, retrying", ea!(subsys = "self_tls")),
@@ -407,6 +473,7 @@ pub async fn htserve_certs(
     };
 
     // Set initial certs
+    monitor.set_state(&state);
     let latest_certs =
         Arc::new(
             SimpleResolvesServerCert(
@@ -444,9 +511,16 @@ pub async fn htserve_certs(
     // Start refresh loop
     let mut cert_stream =
         WatchStream::new(
-            request_cert_stream(&log, &tm, identity_signer.clone(), options, state.current.clone()).await?,
+            request_cert_stream(
+                log,
+                tm,
+                identity_signer.clone(),
+                options,
+                state.current.clone(),
+                monitor.clone(),
+            ).await?,
         );
-    tm.critical_task("API - Process new certs", {
+    tm.tracked_critical_task("API - Process new certs", {
         let cache_dir = cache_dir.to_path_buf();
         let tm = tm.clone();
         let log = log.clone();
@@ -454,6 +528,7 @@ pub async fn htserve_certs(
         let identity_signer = identity_signer.clone();
         let latest_certs = latest_certs.clone();
         let r21_latest_certs = r21_latest_certs.clone();
+        let monitor = monitor.clone();
         async move {
             loop {
                 // Wait for pending certs and swap
@@ -475,7 +550,7 @@ pub async fn htserve_certs(
                             }).await.unwrap();
                         },
                         Err(e) => {
-                            log.log_err(loga::WARN, e.context("New certs are invalid"));
+                            log_warn_err(&log, e.context("New certs are invalid"));
                             return Ok(());
                         },
                     };
@@ -489,7 +564,7 @@ pub async fn htserve_certs(
                             }).await.unwrap();
                         },
                         Err(e) => {
-                            log.log_err(loga::WARN, e.context("New certs are invalid"));
+                            log_warn_err(&log, e.context("New certs are invalid"));
                             return Ok(());
                         },
                     };
@@ -497,6 +572,7 @@ pub async fn htserve_certs(
                         publish_tls_certs(&log, publisher, &identity_signer, &state).await?;
                     }
                     state.current = pending.pair;
+                    monitor.set_state(&state);
                     write(cache_dir.join("pub.pem"), state.current.pub_pem.as_bytes())
                         .await
                         .context("Error writing new pub.pem")?;
@@ -521,11 +597,14 @@ pub async fn htserve_certs(
                     identity: identity.clone(),
                     pair: new_pair,
                 });
+                monitor.set_state(&state);
                 if let Some(publisher) = publisher.as_ref() {
                     publish_tls_certs(&log, publisher, &identity_signer, &state).await?;
                 }
             }
         }
     });
-    return Ok(Some((latest_certs, r21_latest_certs as Arc<dyn rustls_21::server::ResolvesServerCert>)));
+    return Ok(
+        Some((latest_certs, r21_latest_certs as Arc<dyn rustls_21::server::ResolvesServerCert>, monitor)),
+    );
 }
@@ -0,0 +1,192 @@
+use {
+    crate::{
+        cap_fn,
+        interface::{
+            config::node::backup_config::BackupConfig,
+            wire::api::admin::v1::BackupStatus,
+        },
+        ta_res,
+        utils::{
+            db_util::db_backup,
+            recent_errors::log_warn_err,
+            task_status::TrackedTasks,
+        },
+    },
+    chrono::{
+        NaiveDateTime,
+        Utc,
+    },
+    loga::{
+        ea,
+        ErrContext,
+        Log,
+        ResultContext,
+    },
+    std::{
+        path::{
+            Path,
+            PathBuf,
+        },
+        sync::{
+            Arc,
+            Mutex,
+        },
+    },
+    taskmanager::TaskManager,
+    tokio::{
+        fs::{
+            create_dir_all,
+            read_dir,
+            remove_dir_all,
+            rename,
+        },
+        process::Command,
+        task::spawn_blocking,
+    },
+};
+
+/// Backup directories are named with the time they were taken in this format, so
+/// sorting by name sorts by age.
+const BACKUP_NAME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Periodic snapshots of the node's databases, with the status of the last
+/// attempt for the admin api.
+#[derive(Clone)]
+pub struct Backups {
+    status: Arc<Mutex<BackupStatus>>,
+}
+
+impl Backups {
+    pub fn status(&self) -> BackupStatus {
+        return self.status.lock().unwrap().clone();
+    }
+}
+
+/// Existing backups in the backup directory, oldest first. Other files are
+/// ignored.
+async fn list_backups(dir: &Path) -> Result<Vec<String>, loga::Error> {
+    let mut out = vec![];
+    let mut entries = match read_dir(dir).await {
+        Ok(e) => e,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(out),
+        Err(e) => return Err(e.context("Error listing backup directory")),
+    };
+    while let Some(entry) = entries.next_entry().await.context("Error listing backup directory")? {
+        let Some(name) = entry.file_name().to_str().map(|n| n.to_string()) else {
+            continue;
+        };
+        if NaiveDateTime::parse_from_str(&name, BACKUP_NAME_FORMAT).is_err() {
+            continue;
+        }
+        out.push(name);
+    }
+    out.sort();
+    return Ok(out);
+}
+
+/// Snapshot each database (name, path) into a new backup directory, run the
+/// configured command, then delete old backups. Returns the new backup directory.
+async fn backup(config: &BackupConfig, databases: &[(String, PathBuf)]) -> Result<PathBuf, loga::Error> {
+    let name = Utc::now().format(BACKUP_NAME_FORMAT).to_string();
+    let dest = config.dir.join(&name);
+    let log = Log::new().fork(ea!(dest = dest.to_string_lossy()));
+
+    // Write to a temporary directory first so incomplete backups are never listed
+    let partial = config.dir.join(format!(".{}.partial", name));
+    create_dir_all(&partial).await.stack_context(&log, "Error creating backup directory")?;
+    let res = async {
+        ta_res!(());
+        for (db_name, path) in databases {
+            if !path.exists() {
+                continue;
+            }
+            let path = path.clone();
+            let db_dest = partial.join(format!("{}.sqlite3", db_name));
+            spawn_blocking(move || db_backup(&path, &db_dest))
+                .await
+                .stack_context(&log, "Backup task failed")?
+                .stack_context_with(&log, "Error backing up database", ea!(database = db_name))?;
+        }
+        return Ok(());
+    }.await;
+    if let Err(e) = res {
+        _ = remove_dir_all(&partial).await;
+        return Err(e);
+    }
+    rename(&partial, &dest).await.stack_context(&log, "Error moving completed backup into place")?;
+
+    // Hand off
+    if let Some(command) = &config.command {
+        let Some((program, args)) = command.split_first() else {
+            return Err(loga::err("Backup command is empty"));
+        };
+        let status =
+            Command::new(program)
+                .args(args)
+                .arg(&dest)
+                .status()
+                .await
+                .stack_context_with(&log, "Error running backup command", ea!(command = program))?;
+        if !status.success() {
+            return Err(log.err_with("Backup command failed", ea!(command = program, status = status)));
+        }
+    }
+
+    // Rotate
+    let keep = config.keep.unwrap_or(7).max(1);
+    let existing = list_backups(&config.dir).await?;
+    if existing.len() > keep {
+        for old in &existing[.. existing.len() - keep] {
+            remove_dir_all(config.dir.join(old))
+                .await
+                .stack_context_with(&log, "Error deleting old backup", ea!(backup = old))?;
+        }
+    }
+    return Ok(dest);
+}
+
+/// Start taking backups of the databases (name, path) if configured.
+pub fn start_backups(
+    log: &Log,
+    tm: &TaskManager,
+    config: Option<BackupConfig>,
+    databases: Vec<(String, PathBuf)>,
+) -> Backups {
+    let status = Arc::new(Mutex::new(BackupStatus {
+        enabled: config.is_some(),
+        last_attempt: None,
+        last_success: None,
+        last_success_path: None,
+        last_error: None,
+        kept: vec![],
+    }));
+    if let Some(config) = config {
+        let log = log.fork(ea!(subsys = "backup"));
+        let config = Arc::new(config);
+        let databases = Arc::new(databases);
+        tm.tracked_periodic(
+            "Node - database backup",
+            std::time::Duration::from_secs(config.interval.unwrap_or(24 * 60).max(1) * 60),
+            cap_fn!(()(log, config, databases, status) {
+                status.lock().unwrap().last_attempt = Some(Utc::now());
+                let res = backup(&config, &databases).await;
+                let kept = list_backups(&config.dir).await.unwrap_or_default();
+                let mut status = status.lock().unwrap();
+                status.kept = kept;
+                match res {
+                    Ok(dest) => {
+                        log.log_with(loga::INFO, "Backed up databases", ea!(dest = dest.to_string_lossy()));
+                        status.last_success = status.last_attempt;
+                        status.last_success_path = Some(dest.to_string_lossy().to_string());
+                        status.last_error = None;
+                    },
+                    Err(e) => {
+                        status.last_error = Some(e.to_string());
+                        log_warn_err(&log, e.context("Error backing up databases"));
+                    },
+                }
+            }),
+        );
+    }
+    return Backups { status: status };
+}
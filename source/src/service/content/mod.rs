@@ -2,12 +2,26 @@ use {
     crate::{
         cap_block,
         cap_fn,
-        interface::config::content::{
-            ContentConfig,
-            ServeMode,
+        interface::config::{
+            content::{
+                AccessLogConfig,
+                ContentConfig,
+                ServeMode,
+            },
+            shared::CompressionConfig,
         },
         ta_res,
-        utils::fs_util::maybe_read,
+        utils::{
+            compress::CompressHandler,
+            fs_util::maybe_read,
+            graceful::{
+                drain_conn,
+                serve_draining,
+                ShutdownSignal,
+            },
+            privacy::redact_addr,
+            recent_errors::log_warn_err,
+        },
     },
     async_trait::async_trait,
     flowcontrol::shed,
@@ -16,6 +30,7 @@ use {
         Method,
         Request,
         Response,
+        StatusCode,
         Uri,
     },
     http_body_util::{
@@ -26,10 +41,21 @@ use {
         htreq,
         htserve::{
             self,
-            handler::Handler,
+            handler::{
+                Handler,
+                HandlerArgs,
+            },
         },
     },
-    hyper::body::Bytes,
+    hyper::body::{
+        Body,
+        Bytes,
+        Incoming,
+    },
+    hyper_util::rt::{
+        TokioExecutor,
+        TokioIo,
+    },
     loga::{
         ea,
         ErrContext,
@@ -41,15 +67,35 @@ use {
         server::ResolvesServerCert,
         ServerConfig,
     },
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
     std::{
         collections::BTreeMap,
+        convert::Infallible,
+        net::SocketAddr,
         path::PathBuf,
         str::FromStr,
-        sync::Arc,
+        sync::{
+            atomic::{
+                AtomicU64,
+                Ordering,
+            },
+            Arc,
+        },
+        time::{
+            Duration,
+            Instant,
+        },
     },
     taskmanager::TaskManager,
     tokio::{
-        net::TcpListener,
+        net::{
+            TcpListener,
+            TcpStream,
+        },
         spawn,
     },
     tokio_rustls::TlsAcceptor,
@@ -104,7 +150,7 @@ impl htserve::handler::Handler<BoxBody<Bytes, RespErr>> for StaticFilesHandler {
         }.await {
             Ok(r) => r,
             Err(e) => {
-                self.log.log_err(loga::WARN, e.context_with("Error serving response", ea!(url = args.head.uri)));
+                log_warn_err(&self.log, e.context_with("Error serving response", ea!(url = args.head.uri)));
                 return Response::builder()
                     .status(503)
                     .body(
@@ -196,7 +242,7 @@ impl htserve::handler::Handler<BoxBody<Bytes, RespErr>> for ReverseProxyHandler
                 return r;
             },
             Err(e) => {
-                self.log.log_err(loga::WARN, e.context("Encountered error talking with upstream"));
+                log_warn_err(&self.log, e.context("Encountered error talking with upstream"));
                 return Response::builder()
                     .status(503)
                     .body(
@@ -209,7 +255,7 @@ impl htserve::handler::Handler<BoxBody<Bytes, RespErr>> for ReverseProxyHandler
 }
 
 #[derive(Debug)]
-struct RespErr(String);
+pub struct RespErr(String);
 
 impl std::fmt::Display for RespErr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -219,10 +265,253 @@ impl std::fmt::Display for RespErr {
 
 impl std::error::Error for RespErr { }
 
+#[derive(Default)]
+struct ContentCounters {
+    requests: AtomicU64,
+    responses_2xx: AtomicU64,
+    responses_3xx: AtomicU64,
+    responses_4xx: AtomicU64,
+    responses_5xx: AtomicU64,
+    bytes_sent: AtomicU64,
+}
+
+/// Request and response counts for content servers since startup.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct ContentStats {
+    pub requests: u64,
+    pub responses_2xx: u64,
+    pub responses_3xx: u64,
+    pub responses_4xx: u64,
+    pub responses_5xx: u64,
+    /// Response body bytes sent
+    pub bytes_sent: u64,
+}
+
+/// Shared counters for content servers. Clones refer to the same counters.
+#[derive(Clone, Default)]
+pub struct ContentMetrics(Arc<ContentCounters>);
+
+impl ContentMetrics {
+    pub fn stats(&self) -> ContentStats {
+        let c = &self.0;
+        return ContentStats {
+            requests: c.requests.load(Ordering::Relaxed),
+            responses_2xx: c.responses_2xx.load(Ordering::Relaxed),
+            responses_3xx: c.responses_3xx.load(Ordering::Relaxed),
+            responses_4xx: c.responses_4xx.load(Ordering::Relaxed),
+            responses_5xx: c.responses_5xx.load(Ordering::Relaxed),
+            bytes_sent: c.bytes_sent.load(Ordering::Relaxed),
+        };
+    }
+
+    fn record_response(&self, status: StatusCode) {
+        let c = &self.0;
+        c.requests.fetch_add(1, Ordering::Relaxed);
+        let counter = match status.as_u16() {
+            200 ..= 299 => &c.responses_2xx,
+            300 ..= 399 => &c.responses_3xx,
+            400 ..= 499 => &c.responses_4xx,
+            500 ..= 599 => &c.responses_5xx,
+            _ => return,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Handle one request with a content router, counting it in `metrics` and logging
+/// it if `access_log` is set.
+async fn handle_request(
+    log: &Log,
+    handler: &Arc<dyn Handler<BoxBody<Bytes, RespErr>>>,
+    metrics: &ContentMetrics,
+    access_log: &Option<AccessLogConfig>,
+    sni: Option<&str>,
+    peer_addr: SocketAddr,
+    req: Request<Incoming>,
+) -> Response<BoxBody<Bytes, RespErr>> {
+    let start = Instant::now();
+    let (head, body) = req.into_parts();
+    let path = head.uri.path().to_string();
+    let query = head.uri.query().unwrap_or("").to_string();
+    let resp = handler.handle(HandlerArgs {
+        peer_addr: peer_addr,
+        query: &query,
+        head: &head,
+        subpath: &path,
+        body: body,
+    }).await;
+    let (parts, body) = resp.into_parts();
+    metrics.record_response(parts.status);
+    if let Some(access_log) = access_log {
+        let sample = access_log.sample.unwrap_or(1.);
+        if sample >= 1. || rand::random::<f64>() < sample {
+            let bytes = match body.size_hint().exact() {
+                Some(b) => b.to_string(),
+                None => "streamed".to_string(),
+            };
+            log.log_with(
+                loga::INFO,
+                "Request",
+                ea!(
+                    sni = sni.unwrap_or(""),
+                    peer = redact_addr(&peer_addr),
+                    method = head.method,
+                    path = path,
+                    status = parts.status.as_u16(),
+                    bytes = bytes,
+                    duration_ms = start.elapsed().as_millis()
+                ),
+            );
+        }
+    }
+
+    // Count body bytes as they're sent, since proxied responses are streamed
+    let metrics = metrics.clone();
+    let body = body.map_frame(move |frame| {
+        if let Some(data) = frame.data_ref() {
+            metrics.0.bytes_sent.fetch_add(data.len() as u64, Ordering::Relaxed);
+        }
+        return frame;
+    }).boxed();
+    return Response::from_parts(parts, body);
+}
+
+async fn handle_conn(
+    log: Log,
+    tls_acceptor: TlsAcceptor,
+    handler: Arc<dyn Handler<BoxBody<Bytes, RespErr>>>,
+    metrics: ContentMetrics,
+    access_log: Option<AccessLogConfig>,
+    stream: TcpStream,
+    shutdown: ShutdownSignal,
+) -> Result<(), loga::Error> {
+    let peer_addr = stream.peer_addr().context("Error getting peer address of connection")?;
+    let stream = tls_acceptor.accept(stream).await.context("Error during TLS handshake")?;
+    let sni = stream.get_ref().1.server_name().map(|s| s.to_string());
+    drain_conn(
+        hyper_util::server::conn::auto::Builder::new(TokioExecutor::new()).serve_connection(
+            TokioIo::new(stream),
+            hyper::service::service_fn(move |req: Request<Incoming>| {
+                let log = log.clone();
+                let handler = handler.clone();
+                let metrics = metrics.clone();
+                let access_log = access_log.clone();
+                let sni = sni.clone();
+                async move {
+                    return Ok(
+                        handle_request(&log, &handler, &metrics, &access_log, sni.as_deref(), peer_addr, req).await,
+                    ) as Result<_, Infallible>;
+                }
+            }),
+        ),
+        |c| c.graceful_shutdown(),
+        shutdown,
+    )
+        .await
+        .map_err(|e| loga::err_with("Error serving HTTP connection", ea!(err = e)))?;
+    return Ok(());
+}
+
+/// A compressing router for content served at subpaths.
+fn build_router(
+    log: &Log,
+    compression: &Option<CompressionConfig>,
+    subpaths: impl IntoIterator<Item = (String, ServeMode)>,
+) -> Result<Arc<dyn Handler<BoxBody<Bytes, RespErr>>>, loga::Error> {
+    let mut routes = BTreeMap::new();
+    for (subpath, mode) in subpaths {
+        let handler: Box<dyn Handler<BoxBody<Bytes, RespErr>>> = match mode {
+            ServeMode::StaticFiles { content_dir } => Box::new(StaticFilesHandler {
+                log: log.clone(),
+                content_dir: content_dir,
+            }),
+            ServeMode::ReverseProxy { upstream_url } => Box::new(ReverseProxyHandler {
+                log: log.clone(),
+                upstream_url: Uri::from_str(
+                    &upstream_url,
+                ).stack_context(log, "Unable to parse upstream address as url")?,
+            }),
+        };
+        if routes.insert(subpath.clone(), handler).is_some() {
+            return Err(log.err_with("Content subpath configured more than once", ea!(subpath = subpath)));
+        }
+    }
+    return Ok(
+        CompressHandler::wrap(
+            compression,
+            Arc::new(
+                htserve::handler::PathRouter::new(
+                    routes,
+                ).map_err(
+                    |e| loga::agg_err(
+                        "One or more errors setting up content router",
+                        e.into_iter().map(loga::err).collect(),
+                    ),
+                )?,
+            ),
+            |b| BoxBody::new(http_body_util::Full::new(b).map_err(|e| RespErr(e.to_string()))),
+        ),
+    );
+}
+
+/// The content from one `ContentConfig`, served on a listener shared with other
+/// services (single-port mode). The subpaths for all its bind addresses are served
+/// together.
+pub struct ContentSite {
+    log: Log,
+    hosts: Vec<String>,
+    handler: Arc<dyn Handler<BoxBody<Bytes, RespErr>>>,
+    metrics: ContentMetrics,
+    access_log: Option<AccessLogConfig>,
+}
+
+impl ContentSite {
+    pub fn new(log: &Log, metrics: &ContentMetrics, content: ContentConfig) -> Result<ContentSite, loga::Error> {
+        let log = log.fork(ea!(sys = "serve", hosts = content.hosts.join(", ")));
+        let handler =
+            build_router(
+                &log,
+                &content.compression,
+                content.items.into_values().flat_map(|subpaths| subpaths.into_iter()),
+            )?;
+        return Ok(ContentSite {
+            log: log,
+            hosts: content.hosts.iter().map(|h| h.to_ascii_lowercase()).collect(),
+            handler: handler,
+            metrics: metrics.clone(),
+            access_log: content.access_log,
+        });
+    }
+
+    /// Whether this site serves requests for `host` (lowercase, without port).
+    pub fn serves_host(&self, host: Option<&str>) -> bool {
+        if self.hosts.is_empty() {
+            return true;
+        }
+        let Some(host) = host else {
+            return false;
+        };
+        return self.hosts.iter().any(|h| h == host);
+    }
+
+    pub async fn handle(
+        &self,
+        sni: Option<&str>,
+        peer_addr: SocketAddr,
+        req: Request<Incoming>,
+    ) -> Response<BoxBody<Bytes, RespErr>> {
+        return handle_request(&self.log, &self.handler, &self.metrics, &self.access_log, sni, peer_addr, req).await;
+    }
+}
+
+/// Start content servers. `metrics` is updated with counts of all requests served.
 pub async fn start_serving_content(
     log: &Log,
     tm: &TaskManager,
+    shutdown_grace: Duration,
     resolves_cert: Arc<dyn ResolvesServerCert>,
+    metrics: &ContentMetrics,
     content: ContentConfig,
 ) -> Result<(), loga::Error> {
     let tls_acceptor = TlsAcceptor::from(Arc::new({
@@ -230,59 +519,32 @@ pub async fn start_serving_content(
         server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
         server_config
     }));
+    let access_log = content.access_log;
+    let compression = content.compression;
     for (addr, subpaths) in content.items {
-        let mut routes = BTreeMap::new();
-        for (subpath, mode) in subpaths {
-            let handler: Box<dyn Handler<BoxBody<Bytes, RespErr>>>;
-            match mode {
-                ServeMode::StaticFiles { content_dir } => {
-                    handler = Box::new(StaticFilesHandler {
-                        log: log.clone(),
-                        content_dir: content_dir,
-                    });
-                },
-                ServeMode::ReverseProxy { upstream_url } => {
-                    handler = Box::new(ReverseProxyHandler {
-                        log: log.clone(),
-                        upstream_url: Uri::from_str(
-                            &upstream_url,
-                        ).stack_context(log, "Unable to parse upstream address as url")?,
-                    });
-                },
-            }
-            routes.insert(subpath, handler);
-        }
         let log = log.fork(ea!(sys = "serve", bind_addr = addr));
-        let handler =
-            Arc::new(
-                htserve::handler::PathRouter::new(
-                    routes,
-                ).map_err(
-                    |e| loga::agg_err(
-                        "One or more errors setting up content router",
-                        e.into_iter().map(loga::err).collect(),
-                    ),
-                )?,
-            );
-        tm.critical_stream(
+        let handler = build_router(&log, &compression, subpaths)?;
+        serve_draining(
+            &log,
+            tm,
             format!("Serve - content ({})", addr),
+            shutdown_grace,
             TcpListenerStream::new(
                 TcpListener::bind(addr.resolve().stack_context(&log, "Error resolving bind address for server")?)
                     .await
                     .stack_context(&log, "Error binding to address")?,
             ),
-            cap_fn!((stream)(log, tls_acceptor, handler) {
+            cap_fn!((stream, shutdown)(log, tls_acceptor, handler, metrics, access_log) {
                 let stream = match stream {
                     Ok(s) => s,
                     Err(e) => {
                         log.log_err(loga::DEBUG, e.context("Error opening peer stream"));
-                        return Ok(());
+                        return;
                     },
                 };
-                htserve::handler::root_handle_https(&log, tls_acceptor, handler, stream)
+                handle_conn(log.clone(), tls_acceptor, handler, metrics, access_log, stream, shutdown)
                     .await
-                    .log(&log, loga::DEBUG, "Error initiating request handling");
-                return Ok(());
+                    .log(&log, loga::DEBUG, "Error handling connection");
             }),
         );
     }
@@ -0,0 +1,164 @@
+//! Everything the node can tell about one identity's health at once (its
+//! announcement in the network, how this node's publisher hosts it, and what
+//! resolves), for `spagh admin identity-status`.
+use {
+    super::{
+        node::{
+            CensusReplication,
+            Node,
+        },
+        publisher::{
+            Publisher,
+            PublisherIdentityStatus,
+        },
+        resolver::Resolver,
+    },
+    crate::{
+        interface::stored::{
+            identity::Identity,
+            record::{
+                dns_record::{
+                    build_dns_key,
+                    RecordType,
+                },
+                tls_record::{
+                    TlsCerts,
+                    KEY_SUFFIX_TLS,
+                },
+            },
+        },
+        utils::tls_util::extract_expiry,
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    std::net::SocketAddr,
+};
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct IdentityAnnouncementStatus {
+    /// When the newest announcement found in the network was made. Null if none was
+    /// found.
+    pub announced: Option<DateTime<Utc>>,
+    /// Publisher addresses listed in the announcement
+    pub publishers: Vec<SocketAddr>,
+    pub replication: CensusReplication,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct IdentityCertStatus {
+    /// Null if the cert couldn't be parsed
+    pub expires: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct IdentityResolveStatus {
+    /// Set if resolving the identity's root records failed
+    pub error: Option<String>,
+    /// DNS record keys at the root of the name that have values (ex: `dns/a`)
+    pub dns_keys: Vec<String>,
+    /// Published TLS certs
+    pub certs: Vec<IdentityCertStatus>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct IdentityStatus {
+    pub time: DateTime<Utc>,
+    pub identity: Identity,
+    pub announcement: IdentityAnnouncementStatus,
+    /// Null if the node doesn't run a publisher
+    pub publisher: Option<PublisherIdentityStatus>,
+    /// Lookup of the root records via this node's resolver (possibly from cache).
+    /// Null if the node doesn't run a resolver.
+    pub resolve: Option<IdentityResolveStatus>,
+}
+
+async fn resolve_status(resolver: &Resolver, identity: &Identity) -> IdentityResolveStatus {
+    let dns_keys = [RecordType::A, RecordType::Aaaa, RecordType::Txt, RecordType::Mx]
+        .into_iter()
+        .map(|t| build_dns_key(vec![], t))
+        .collect::<Vec<_>>();
+    let tls_key = vec![KEY_SUFFIX_TLS.to_string()];
+    let mut request_keys = dns_keys.clone();
+    request_keys.push(tls_key.clone());
+    let values = match resolver.get(identity, request_keys).await {
+        Ok(v) => v,
+        Err(e) => return IdentityResolveStatus {
+            error: Some(e.to_string()),
+            dns_keys: vec![],
+            certs: vec![],
+        },
+    };
+    let mut out = IdentityResolveStatus {
+        error: None,
+        dns_keys: vec![],
+        certs: vec![],
+    };
+    for k in dns_keys {
+        if values.get(&k).is_some_and(|v| v.data.is_some()) {
+            out.dns_keys.push(k.join("/"));
+        }
+    }
+    if let Some(data) = values.get(&tls_key).and_then(|v| v.data.clone()) {
+        match serde_json::from_value::<TlsCerts>(data) {
+            Ok(TlsCerts::V1(certs)) => {
+                for cert in certs.0 {
+                    out.certs.push(IdentityCertStatus { expires: extract_expiry(cert.as_bytes()).ok() });
+                }
+            },
+            Err(e) => {
+                out.error = Some(format!("Published TLS certs record is invalid: {}", e));
+            },
+        }
+    }
+    return out;
+}
+
+/// Collect the state of the identity from each service the node runs.
+pub async fn identity_status(
+    node: &Node,
+    publisher: Option<&Publisher>,
+    resolver: Option<&Resolver>,
+    identity: &Identity,
+) -> Result<IdentityStatus, loga::Error> {
+    let (announcement, replication) = node.get_with_replication(*identity).await;
+    let announcement = match announcement.and_then(|a| a.parse().ok()) {
+        Some(a) => {
+            IdentityAnnouncementStatus {
+                announced: Some(a.announced),
+                publishers: a.publishers.into_iter().map(|p| p.addr.0).collect(),
+                replication: replication,
+            }
+        },
+        None => IdentityAnnouncementStatus {
+            announced: None,
+            publishers: vec![],
+            replication: replication,
+        },
+    };
+    let publisher = match publisher {
+        Some(p) => Some(p.identity_status(identity).await?),
+        None => None,
+    };
+    let resolve = match resolver {
+        Some(r) => Some(resolve_status(r, identity).await),
+        None => None,
+    };
+    return Ok(IdentityStatus {
+        time: Utc::now(),
+        identity: *identity,
+        announcement: announcement,
+        publisher: publisher,
+        resolve: resolve,
+    });
+}
@@ -10,3 +10,35 @@ pub mod publisher;
 
 /// Methods for serving http content (static/reverse proxy)
 pub mod content;
+
+/// Serving the API and content together on one port
+pub mod single_port;
+
+/// Periodic database backups
+pub mod backup;
+
+/// Combined status of one identity across services
+pub mod identity_status;
+
+/// One sample from the admin watch endpoint: the stats of each service the node
+/// runs, at a point in time.
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct WatchSnapshot {
+    pub time: chrono::DateTime<chrono::Utc>,
+    pub node: node::HealthDetail,
+    /// Null if the node doesn't run a resolver
+    pub resolver: Option<resolver::CacheStats>,
+    /// Null if the node doesn't run a publisher
+    pub publisher: Option<publisher::PublisherStats>,
+    /// Null if the node doesn't serve content
+    pub content: Option<content::ContentStats>,
+    /// Null if the node doesn't run the DNS bridge
+    pub dns: Option<resolver::dns::DnsStats>,
+    /// Null if the node doesn't run a resolver or load shedding is disabled
+    #[serde(default)]
+    pub resolver_load: Option<crate::utils::load_shed::LoadShedStats>,
+    /// Null if the node doesn't run a publisher or load shedding is disabled
+    #[serde(default)]
+    pub publisher_load: Option<crate::utils::load_shed::LoadShedStats>,
+}
@@ -0,0 +1,116 @@
+//! Estimating how far this node's clock is from other nodes' clocks, from the
+//! stamps on signed messages they send (find and challenge responses, goodbyes).
+//! Stamps and announcement dates are checked against the local clock, so a node
+//! with a wrong clock rejects valid messages from everyone.
+use {
+    crate::interface::stored::node_identity::NodeIdentity,
+    chrono::Duration,
+    std::collections::HashMap,
+    tokio::time::Instant,
+};
+
+/// Samples from at most this many peers are kept; the oldest is replaced when a new
+/// peer is heard from.
+pub const MAX_SKEW_PEERS: usize = 64;
+
+/// No estimate is made with samples from fewer peers than this.
+pub const MIN_SKEW_PEERS: usize = 3;
+
+// Samples older than this are ignored
+fn sample_max_age() -> std::time::Duration {
+    return std::time::Duration::from_secs(60 * 60);
+}
+
+#[derive(Default)]
+pub struct ClockSkew {
+    // The latest offset per peer (the peer's stamp minus the local time when the
+    // message was received), with when it was received
+    samples: HashMap<NodeIdentity, (Instant, Duration)>,
+}
+
+impl ClockSkew {
+    pub fn observe(&mut self, now: Instant, peer: NodeIdentity, offset: Duration) {
+        if !self.samples.contains_key(&peer) && self.samples.len() >= MAX_SKEW_PEERS {
+            let oldest = self.samples.iter().min_by_key(|(_, (at, _))| *at).map(|(k, _)| *k);
+            if let Some(oldest) = oldest {
+                self.samples.remove(&oldest);
+            }
+        }
+        self.samples.insert(peer, (now, offset));
+    }
+
+    /// How far other nodes' clocks are ahead of this node's (negative if behind),
+    /// as the median of each peer's latest offset. Message latency makes this
+    /// slightly low, but using the median means a few peers with wrong clocks (or
+    /// lying) don't move it much. `None` if too few peers were heard from recently.
+    pub fn estimate(&self, now: Instant) -> Option<Duration> {
+        let mut offsets =
+            self
+                .samples
+                .values()
+                .filter(|(at, _)| now.saturating_duration_since(*at) < sample_max_age())
+                .map(|(_, offset)| *offset)
+                .collect::<Vec<_>>();
+        if offsets.len() < MIN_SKEW_PEERS {
+            return None;
+        }
+        offsets.sort();
+        return Some(offsets[offsets.len() / 2]);
+    }
+}
+
+/// Whether an estimated skew is large enough that messages from many peers are
+/// likely being rejected (or soon will be): more than half of `tolerance`.
+pub fn skew_exceeds(skew: Duration, tolerance: Duration) -> bool {
+    return skew.abs() * 2 > tolerance;
+}
+
+#[cfg(test)]
+mod test_clock_skew {
+    use {
+        super::{
+            skew_exceeds,
+            ClockSkew,
+            MAX_SKEW_PEERS,
+        },
+        crate::interface::stored::node_identity::NodeIdentity,
+        chrono::Duration,
+        tokio::time::Instant,
+    };
+
+    #[test]
+    fn test_estimate() {
+        let now = Instant::now();
+        let idents = (0 .. 4).map(|_| NodeIdentity::new().0).collect::<Vec<_>>();
+        let mut skew = ClockSkew::default();
+        skew.observe(now, idents[0], Duration::try_seconds(30).unwrap());
+        skew.observe(now, idents[1], Duration::try_seconds(31).unwrap());
+        assert_eq!(skew.estimate(now), None);
+
+        // One wildly wrong peer doesn't move the estimate much
+        skew.observe(now, idents[2], Duration::try_days(-300).unwrap());
+        skew.observe(now, idents[3], Duration::try_seconds(29).unwrap());
+        assert_eq!(skew.estimate(now), Some(Duration::try_seconds(30).unwrap()));
+
+        // Newer samples from a peer replace older ones
+        skew.observe(now, idents[2], Duration::try_seconds(32).unwrap());
+        assert_eq!(skew.estimate(now), Some(Duration::try_seconds(31).unwrap()));
+    }
+
+    #[test]
+    fn test_max_peers() {
+        let now = Instant::now();
+        let mut skew = ClockSkew::default();
+        for _ in 0 .. MAX_SKEW_PEERS + 10 {
+            skew.observe(now, NodeIdentity::new().0, Duration::zero());
+        }
+        assert_eq!(skew.samples.len(), MAX_SKEW_PEERS);
+    }
+
+    #[test]
+    fn test_skew_exceeds() {
+        let tolerance = Duration::try_minutes(1).unwrap();
+        assert!(!skew_exceeds(Duration::try_seconds(20).unwrap(), tolerance));
+        assert!(skew_exceeds(Duration::try_seconds(-40).unwrap(), tolerance));
+    }
+}
@@ -2,7 +2,10 @@ use {
     crate::{
         cap_fn,
         interface::{
-            config::shared::StrSocketAddr,
+            config::{
+                node::node_config::NodeSecretStorage,
+                shared::StrSocketAddr,
+            },
             stored::{
                 self,
                 identity::Identity,
@@ -17,7 +20,11 @@ use {
             wire::{
                 self,
                 node::{
-                    latest::FindGoal,
+                    latest::{
+                        ErrorCode,
+                        ErrorRequest,
+                        FindGoal,
+                    },
                     v1::DhtCoord,
                 },
             },
@@ -25,19 +32,32 @@ use {
         utils::{
             blob::Blob,
             db_util::setup_db,
+            privacy::{
+                privacy_mode,
+                redact_addr,
+                redact_id,
+            },
+            recent_errors::log_warn_err,
+            request_id::current_request_id,
             signed::{
                 IdentSignatureMethods,
                 NodeIdentSignatureMethods,
             },
+            task_status::TrackedTasks,
             time_util::ToInstant,
+            udp_batch::send_to_many,
+            versioned::VerInt,
         },
     }, chrono::{
         DateTime,
         Duration,
         Utc,
-    }, constant_time_eq::constant_time_eq, flowcontrol::shed, futures::channel::mpsc::{
-        unbounded,
-        UnboundedSender,
+    }, constant_time_eq::constant_time_eq, deadpool_sqlite::Pool, flowcontrol::shed, futures::{
+        channel::mpsc::{
+            unbounded,
+            UnboundedSender,
+        },
+        StreamExt,
     }, generic_array::{
         ArrayLength,
         GenericArray,
@@ -50,18 +70,35 @@ use {
     }, manual_future::{
         ManualFuture,
         ManualFutureCompleter,
-    }, rand::RngCore, serde::{
+    }, rand::{
+        seq::SliceRandom,
+        thread_rng,
+        RngCore,
+    }, schemars::JsonSchema, serde::{
         Deserialize,
         Serialize,
     }, sha2::Digest, std::{
         collections::{
             hash_map::Entry,
+            BTreeMap,
             HashMap,
             HashSet,
+            VecDeque,
         },
         fmt::Debug,
-        net::SocketAddr,
-        path::Path,
+        hash::{
+            DefaultHasher,
+            Hash,
+            Hasher,
+        },
+        net::{
+            IpAddr,
+            SocketAddr,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
         str::FromStr,
         sync::{
             atomic::{
@@ -75,11 +112,31 @@ use {
     }, taskmanager::TaskManager, tokio::{
         net::UdpSocket,
         select,
-        time::sleep,
-    }
+        sync::{
+            broadcast,
+            mpsc,
+        },
+        time::{
+            sleep,
+            sleep_until,
+            Instant,
+        },
+    }, tokio_stream::wrappers::ReceiverStream
 };
 
+pub mod clock_skew;
 pub mod db;
+pub mod peers_dir;
+
+/// The latest `node.sqlite3` schema version, from `buildlib/node`. Bump this when
+/// adding a version there.
+pub const DB_SCHEMA_VERSION: usize = 2;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod secret_storage;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod transport;
 
 pub fn default_bootstrap() -> Vec<wire::node::latest::NodeInfo> {
     return vec![wire::node::latest::NodeInfo {
@@ -95,16 +152,103 @@ const NEIGHBORHOOD: usize = 1 << NEIGHBORHOOD_BITS;
 const HASH_BITS: usize = 256;
 const BUCKET_COUNT: usize = HASH_BITS - NEIGHBORHOOD_BITS + 1;
 const PARALLEL: usize = 3;
+// Finds start with the `PARALLEL` fastest of this many nearest neighbors
+const PARALLEL_CANDIDATES: usize = PARALLEL * 2;
+// Max nodes offered in a peer exchange message, also limited by the 1024 byte
+// packet size
+const PEER_EXCHANGE_COUNT: usize = NEIGHBORHOOD;
+// Caps on in-progress request state, to bound memory use under load. When full,
+// the oldest lowest-priority state is evicted.
+const QUARANTINE_MAX: usize = 256;
+const FIND_STATES_MAX: usize = 256;
+const PING_STATES_MAX: usize = 1024;
+// Bursts of store replication messages are sent in batches of this many, with a
+// pause between batches so large stores don't overflow socket or network buffers
+const REPLICATION_BATCH: usize = 32;
+const REPLICATION_BATCH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(20);
+// Store messages for close neighbors that stopped responding within this window
+// are queued and resent if they respond again...
+const STORE_RETRY_WINDOW: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+// ...within this long...
+const STORE_RETRY_EXPIRY: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+// ...keeping at most this many, dropping the oldest
+const STORE_RETRY_MAX: usize = 4096;
+// How often neighbors with queued store messages are pinged to see if they're back
+const STORE_RETRY_PING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+// Peers counted separately in each traffic rollup period; traffic from further
+// peers is counted as `other`
+const TRAFFIC_PEERS_MAX: usize = 4096;
+// Routing table changes (joined, left, or changed responsiveness) between
+// rebalance checks at least this fraction of the previous table trigger
+// re-replication of stored values...
+const REBALANCE_CHANGE_FRACTION: f64 = 0.25;
+// ...if at least this many nodes changed, so small tables don't rebalance on every
+// check
+const REBALANCE_CHANGE_MIN: usize = 8;
+// Received packets queued per worker before receiving waits
+const PACKET_QUEUE: usize = 256;
+// Receive buffer size, and the largest datagram sent to nodes that haven't been
+// MTU probed
+const DATAGRAM_MAX: usize = 1024;
+// Datagram sizes checked by MTU probing
+const MTU_PROBE_SIZES: [u16; 3] = [1024, 768, 512];
+// Find responses that don't fit in a datagram to the requester are trimmed to this
+// many nodes before leaving out the value
+const FIND_RESPONSE_MIN_NODES: usize = PARALLEL;
 
 fn req_timeout() -> Duration {
     return Duration::try_seconds(2).unwrap();
 }
 
+// Goodbye messages and (v2) find and challenge responses with stamps further than
+// this from the current time (either direction, for clock skew) are rejected, as are
+// store requests for announcements dated further than this in the future. See
+// `clock_skew_tolerance`.
+fn default_clock_skew_tolerance() -> Duration {
+    return Duration::try_minutes(1).unwrap();
+}
+
+fn clock_skew_check_interval() -> Duration {
+    return Duration::try_minutes(10).unwrap();
+}
+
 // All stored values expire after 24h
 fn store_expire_duration() -> Duration {
     return Duration::try_hours(24).unwrap();
 }
 
+fn protected_retry_interval() -> Duration {
+    return Duration::try_minutes(1).unwrap();
+}
+
+fn peers_reconcile_interval() -> Duration {
+    return Duration::try_minutes(5).unwrap();
+}
+
+// Matches the ping interval, so nodes that stopped responding are noticed by the
+// next check
+fn rebalance_check_interval() -> Duration {
+    return Duration::try_minutes(10).unwrap();
+}
+
+fn prune_check_interval() -> Duration {
+    return Duration::try_minutes(10).unwrap();
+}
+
+fn routing_check_interval() -> Duration {
+    return Duration::try_minutes(10).unwrap();
+}
+
+fn traffic_rollup_interval() -> Duration {
+    return Duration::try_hours(1).unwrap();
+}
+
+// Traffic rollups older than this are deleted
+pub fn traffic_retention() -> Duration {
+    return Duration::try_days(7).unwrap();
+}
+
 fn dist_<N: ArrayLength<u8>>(a: &GenericArray<u8, N>, b: &GenericArray<u8, N>) -> (usize, GenericArray<u8, N>) {
     let mut leading_zeros = 0usize;
     let mut first_one = false;
@@ -202,262 +346,2919 @@ struct ValueState {
     received: DateTime<Utc>,
 }
 
-struct NextPingTimeout {
-    end: DateTime<Utc>,
-    key: (node_identity::NodeIdentity, usize),
+/// Drop the least recently received values until the store is within `max`
+/// entries, returning the number dropped.
+fn trim_store(store: &mut HashMap<Identity, ValueState>, max: usize) -> usize {
+    let mut dropped = 0;
+    while store.len() > max {
+        let Some(oldest) = store.iter().min_by_key(|(_, v)| v.received).map(|(k, _)| *k) else {
+            break;
+        };
+        store.remove(&oldest);
+        dropped += 1;
+    }
+    return dropped;
 }
 
-struct NextChallengeTimeout {
-    end: DateTime<Utc>,
-    key: (node_identity::NodeIdentity, usize),
+/// Count the nodes that are closer to `key_coord` than `own_coord` is.
+fn count_closer(own_coord: &DhtCoord, key_coord: &DhtCoord, nodes: impl Iterator<Item = DhtCoord>) -> usize {
+    let own_dist = dist(own_coord, key_coord).1;
+    return nodes.filter(|c| dist(c, key_coord).1 < own_dist).count();
 }
 
-struct Buckets {
-    buckets: [Vec<wire::node::latest::NodeState>; BUCKET_COUNT],
-    addrs: HashMap<SocketAddr, NodeIdentity>,
+/// Record the protocol versions a neighbor advertised. Versions messages aren't
+/// signed, so versions already seen from the neighbor are kept - otherwise a
+/// spoofed message could downgrade it to unauthenticated v1 pings.
+fn merge_peer_versions(seen: &mut Vec<VerInt>, advertised: Vec<VerInt>) {
+    for v in advertised {
+        if !seen.contains(&v) {
+            seen.push(v);
+        }
+    }
 }
 
-struct NodeInner {
-    log: Log,
-    own_ident: node_identity::NodeIdentity,
-    own_coord: DhtCoord,
-    own_secret: node_identity::NodeSecret,
-    buckets: Mutex<Buckets>,
-    store: Mutex<HashMap<Identity, ValueState>>,
-    dirty: AtomicBool,
-    socket: UdpSocket,
-    next_req_id: AtomicUsize,
-    find_timeouts: UnboundedSender<NextFindTimeout>,
-    find_states: Mutex<HashMap<FindGoal, FindState>>,
-    ping_states: Mutex<HashMap<node_identity::NodeIdentity, PingState>>,
-    challenge_timeouts: UnboundedSender<NextChallengeTimeout>,
-    challenge_states: Mutex<HashMap<node_identity::NodeIdentity, ChallengeState>>,
-}
+#[cfg(test)]
+mod test_merge_peer_versions {
+    use super::merge_peer_versions;
 
-#[derive(Clone)]
-pub struct Node(Arc<NodeInner>);
+    #[test]
+    fn test_upgrade() {
+        let mut seen = vec![1];
+        merge_peer_versions(&mut seen, vec![1, 2]);
+        assert_eq!(seen, vec![1, 2]);
+    }
 
-#[derive(Clone, Debug)]
-struct OutstandingNodeEntry {
-    dist: DhtCoord,
-    bucket_i: usize,
-    challenge: Blob,
-    node: wire::node::latest::NodeInfo,
+    #[test]
+    fn test_no_downgrade() {
+        let mut seen = vec![1, 2];
+        merge_peer_versions(&mut seen, vec![1]);
+        assert_eq!(seen, vec![1, 2]);
+    }
 }
 
-#[derive(Clone)]
-enum NearestNodeEntryNode {
-    Self_,
-    Node(wire::node::latest::NodeInfo),
+/// Find response times are compared in steps of this, so peers with similar times
+/// are ordered by distance.
+const RTT_STEP_MS: u128 = 25;
+
+/// A peer's average find response time, in `RTT_STEP_MS` steps. Peers that haven't
+/// answered a find yet are ranked below peers known to be fast, but above slow
+/// ones.
+fn rtt_step(rtt: Option<std::time::Duration>) -> u128 {
+    return rtt.unwrap_or(req_timeout().to_std().unwrap() / 4).as_millis() / RTT_STEP_MS;
 }
 
-#[derive(Clone)]
-struct NearestNodeEntry {
+/// Order of the peers to send a find to. Closer peers go first, but peers at a
+/// similar distance to the goal (the same number of leading bits in common) go
+/// fastest first, since a quick answer from one gets the lookup about as close as
+/// a slow answer from the other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct FindRank {
+    common_bits: std::cmp::Reverse<usize>,
+    rtt_step: u128,
     dist: DhtCoord,
-    node: NearestNodeEntryNode,
 }
 
-struct FindState {
-    req_id: usize,
-    goal: FindGoal,
-    updated: DateTime<Utc>,
-    nearest: Vec<NearestNodeEntry>,
-    outstanding: Vec<OutstandingNodeEntry>,
-    seen: HashSet<node_identity::NodeIdentity>,
-    // For storing value, or retrieving value. Only used for identity searches (None
-    // otherwise).
-    value: Option<stored::announcement::Announcement>,
-    futures: Vec<ManualFutureCompleter<FindResult>>,
+/// * `bucket_i`, `dist`: The peer's distance to the goal, from `dist`
+fn find_rank(bucket_i: usize, dist: DhtCoord, rtt: Option<std::time::Duration>) -> FindRank {
+    return FindRank {
+        common_bits: std::cmp::Reverse(bucket_i),
+        rtt_step: rtt_step(rtt),
+        dist: dist,
+    };
 }
 
-struct FindResult {
-    nearest: Vec<NearestNodeEntry>,
-    value: Option<stored::announcement::Announcement>,
-}
+#[cfg(test)]
+mod test_find_rank {
+    use {
+        super::{
+            dist,
+            find_rank,
+            DhtCoord,
+        },
+        generic_array::GenericArray,
+        std::time::Duration,
+    };
 
-struct PingState {
-    req_id: usize,
-    bucket_i: usize,
-}
+    fn coord(first: u8) -> DhtCoord {
+        let mut c = [0u8; 32];
+        c[0] = first;
+        return DhtCoord(GenericArray::from(c));
+    }
 
-struct ChallengeState {
-    req_id: usize,
-    challenge: Blob,
-    node: wire::node::latest::NodeInfo,
+    fn pick(candidates: &[(DhtCoord, Option<Duration>)]) -> DhtCoord {
+        let goal = coord(0);
+        return candidates.iter().min_by_key(|(c, rtt)| {
+            let (bucket_i, d) = dist(c, &goal);
+            find_rank(bucket_i, d, *rtt)
+        }).unwrap().0;
+    }
+
+    #[test]
+    fn test_similar_distance_faster() {
+        // Same leading bits in common with the goal, the slightly farther fast peer is
+        // picked
+        assert_eq!(
+            pick(&[(coord(0b10), Some(Duration::from_millis(400))), (coord(0b11), Some(Duration::from_millis(30)))]),
+            coord(0b11)
+        );
+    }
+
+    #[test]
+    fn test_closer_wins() {
+        assert_eq!(
+            pick(&[(coord(0b1), Some(Duration::from_millis(400))), (coord(0b11), Some(Duration::from_millis(30)))]),
+            coord(0b1)
+        );
+    }
+
+    #[test]
+    fn test_similar_rtt_by_distance() {
+        assert_eq!(
+            pick(&[(coord(0b11), Some(Duration::from_millis(30))), (coord(0b10), Some(Duration::from_millis(35)))]),
+            coord(0b10)
+        );
+    }
 }
 
-fn generate_challenge() -> Blob {
-    let mut out = Blob::new(32);
-    rand::thread_rng().fill_bytes(out.as_mut());
-    return out;
+#[cfg(test)]
+mod test_count_closer {
+    use super::*;
+
+    fn coord_from_int(n: usize) -> DhtCoord {
+        let mut n = n.to_le_bytes().to_vec();
+        n.resize(HASH_BITS / 8, 0u8);
+        n.reverse();
+        return DhtCoord(GenericArray::<u8, generic_array::typenum::U32>::from_slice(&n).to_owned());
+    }
+
+    #[test]
+    fn test_closest() {
+        assert_eq!(count_closer(&coord_from_int(0), &coord_from_int(0), (1 .. 32).map(coord_from_int)), 0);
+    }
+
+    #[test]
+    fn test_some_closer() {
+        assert_eq!(count_closer(&coord_from_int(5), &coord_from_int(0), (0 .. 32).map(coord_from_int)), 5);
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-struct Persisted {
-    own_secret: node_identity::NodeSecret,
-    initial_buckets: Vec<Vec<wire::node::latest::NodeState>>,
+struct QueuedStore {
+    to: NodeIdentity,
+    key: Identity,
+    value: stored::announcement::Announcement,
+    queued: Instant,
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub struct HealthDetail {
-    pub responsive_neighbors: usize,
-    pub unresponsive_neighbors: usize,
-    pub active_finds: usize,
-    pub active_challenges: usize,
-    pub active_pings: usize,
+/// Store messages for close neighbors that stopped responding recently, resent if
+/// they respond again before the messages expire. Neighbors unresponsive for
+/// longer than `STORE_RETRY_WINDOW` are assumed gone and don't get queued messages.
+#[derive(Default)]
+struct StoreRetries {
+    // When each currently unresponsive neighbor was marked unresponsive
+    unresponsive_since: HashMap<NodeIdentity, Instant>,
+    // Oldest first
+    queue: VecDeque<QueuedStore>,
 }
 
-impl Node {
-    /// Creates and starts a new node within the task manager. Waits until the socket
-    /// is open. Bootstrapping is asynchronous; you should wait until a sufficient
-    /// number of peers are found before doing anything automatically.
-    ///
-    /// * `bootstrap`: Nodes to connect to to join network. Ignored if restoring persisted
-    ///   data. Ignores own id if present.
-    ///
-    /// * `cache_dir`: Save state to this file before shutting down to make next startup
-    ///   faster
-    pub async fn new(
-        log: &Log,
-        tm: &TaskManager,
-        bind_addr: StrSocketAddr,
-        bootstrap: &[wire::node::latest::NodeInfo],
-        cache_dir: &Path,
-    ) -> Result<Node, loga::Error> {
-        let mut do_bootstrap = false;
-        let own_ident;
-        let own_secret;
-        let mut initial_buckets = Buckets {
-            buckets: array_init::array_init(|_| vec![]),
-            addrs: HashMap::new(),
-        };
-        let db_pool =
-            setup_db(&cache_dir.join("node.sqlite3"), db::migrate)
-                .await
-                .stack_context(log, "Error initializing database")?;
-        let db = db_pool.get().await.stack_context(log, "Error getting database connection")?;
-        match db
-            .interact(|conn| db::secret_get(&conn))
-            .await
-            .stack_context(log, "Error interacting with database")?
-            .stack_context(log, "Error retrieving secret")? {
-            Some(s) => {
-                own_ident = s.get_identity();
-                own_secret = s;
-            },
-            None => {
-                (own_ident, own_secret) = node_identity::NodeIdentity::new();
-            },
+impl StoreRetries {
+    /// Record a neighbor's responsiveness changing. When it becomes responsive again,
+    /// returns the unexpired messages queued for it.
+    fn mark(
+        &mut self,
+        ident: &NodeIdentity,
+        unresponsive: bool,
+        now: Instant,
+    ) -> Vec<(Identity, stored::announcement::Announcement)> {
+        if unresponsive {
+            self.unresponsive_since.entry(*ident).or_insert(now);
+            return vec![];
         }
-        let own_coord = node_ident_coord(&own_ident);
-        {
-            let mut no_neighbors = true;
-            for e in db
-                .interact(|conn| db::neighbors_get(&conn))
-                .await
-                .stack_context(log, "Error interacting with database")?
-                .stack_context(log, "Error retrieving old neighbors")? {
-                let state = match e {
-                    wire::node::NodeState::V1(s) => s,
-                };
-                let (leading_zeros, _) = dist(&node_ident_coord(&state.node.ident), &own_coord);
-                match initial_buckets.addrs.entry(state.node.address.0) {
-                    Entry::Occupied(v) => {
-                        log.log_with(
-                            loga::WARN,
-                            "Duplicate neighbor address in database, skipping",
-                            ea!(addr = state.node.address, ident1 = state.node.ident, ident2 = v.get()),
-                        );
-                        continue;
-                    },
-                    Entry::Vacant(v) => {
-                        v.insert(state.node.ident);
-                    },
-                }
-                log.log_with(
-                    loga::DEBUG,
-                    "Restoring neighbor",
-                    ea!(ident = state.node.ident, addr = state.node.address),
-                );
-                initial_buckets.buckets[leading_zeros].push(state);
-                no_neighbors = false;
-            }
-            if no_neighbors {
-                do_bootstrap = true;
+        self.unresponsive_since.remove(ident);
+        let mut out = vec![];
+        self.queue.retain(|q| {
+            if &q.to != ident {
+                return true;
             }
-        }
-        log.log_with(loga::INFO, "Starting", ea!(own_node_ident = own_ident));
-        let sock = {
-            let log = log.fork(ea!(addr = bind_addr));
-            UdpSocket::bind(bind_addr.resolve()?).await.stack_context(&log, "Failed to open node UDP port")?
-        };
-        let (find_timeout_write, find_timeout_recv) = unbounded::<NextFindTimeout>();
-        let (ping_timeout_write, ping_timeout_recv) = unbounded::<NextPingTimeout>();
-        let (challenge_timeout_write, challenge_timeout_recv) = unbounded::<NextChallengeTimeout>();
-        let dir = Node(Arc::new(NodeInner {
-            log: log.clone(),
-            own_ident: node_identity::NodeIdentity::V1(match own_ident {
-                node_identity::NodeIdentity::V1(i) => i,
-            }),
-            own_secret: node_identity::NodeSecret::V1(match own_secret {
-                node_identity::NodeSecret::V1(s) => s,
-            }),
-            own_coord: own_coord,
-            buckets: Mutex::new(initial_buckets),
-            dirty: AtomicBool::new(do_bootstrap),
-            store: Mutex::new(HashMap::new()),
-            socket: sock,
-            next_req_id: AtomicUsize::new(0),
-            find_timeouts: find_timeout_write,
-            find_states: Mutex::new(HashMap::new()),
-            ping_states: Mutex::new(HashMap::new()),
-            challenge_timeouts: challenge_timeout_write,
-            challenge_states: Mutex::new(HashMap::new()),
-        }));
-        if do_bootstrap {
-            log.log_with(loga::DEBUG, "No neighbors, bootstrapping", ea!(count = bootstrap.len()));
-            for b in bootstrap {
-                if b.ident == dir.0.own_ident {
-                    continue;
-                }
-                if !dir.add_good_node(b.ident.clone(), Some(b.clone())) {
-                    panic!("");
-                }
+            if now.duration_since(q.queued) < STORE_RETRY_EXPIRY {
+                out.push((q.key, q.value.clone()));
             }
+            return false;
+        });
+        return out;
+    }
+
+    /// Whether the neighbor was marked unresponsive within `STORE_RETRY_WINDOW`.
+    fn recently_unresponsive(&self, ident: &NodeIdentity, now: Instant) -> bool {
+        return self.unresponsive_since.get(ident).map(|t| now.duration_since(*t) < STORE_RETRY_WINDOW).unwrap_or(false);
+    }
+
+    /// Queue a message, replacing any queued for the same neighbor and key. Returns
+    /// the number of older messages dropped to stay within `STORE_RETRY_MAX`.
+    fn queue(
+        &mut self,
+        to: &NodeIdentity,
+        key: &Identity,
+        value: &stored::announcement::Announcement,
+        now: Instant,
+    ) -> usize {
+        self.queue.retain(|q| &q.to != to || &q.key != key);
+        self.queue.push_back(QueuedStore {
+            to: *to,
+            key: *key,
+            value: value.clone(),
+            queued: now,
+        });
+        let mut dropped = 0;
+        while self.queue.len() > STORE_RETRY_MAX {
+            self.queue.pop_front();
+            dropped += 1;
         }
+        return dropped;
+    }
 
-        // Periodically save
-        tm.periodic(
-            "Node - persist state",
-            Duration::try_minutes(10).unwrap().to_std().unwrap(),
-            cap_fn!(()(log, dir, db_pool) {
-                if !dir.0.dirty.swap(false, Ordering::Relaxed) {
-                    return;
-                }
-                let db_pool = db_pool.clone();
-                match async {
-                    db_pool.get().await.context("Error getting db connection")?.interact(move |conn| {
-                        db::secret_ensure(conn, &dir.0.own_secret)?;
-                        db::neighbors_clear(conn)?;
-                        for bucket in dir.0.buckets.lock().unwrap().buckets.clone().into_iter() {
-                            for n in bucket {
-                                db::neighbors_insert(conn, &wire::node::NodeState::V1(n))?;
-                            }
-                        }
-                        return Ok(()) as Result<_, loga::Error>;
+    /// Drop expired messages, returning how many were dropped.
+    fn expire(&mut self, now: Instant) -> usize {
+        let before = self.queue.len();
+        self.queue.retain(|q| now.duration_since(q.queued) < STORE_RETRY_EXPIRY);
+        return before - self.queue.len();
+    }
+
+    /// Neighbors with queued messages.
+    fn waiting(&self) -> HashSet<NodeIdentity> {
+        return self.queue.iter().map(|q| q.to).collect();
+    }
+
+    /// Forget neighbors no longer in the routing table.
+    fn retain(&mut self, keep: impl Fn(&NodeIdentity) -> bool) {
+        self.unresponsive_since.retain(|n, _| keep(n));
+        self.queue.retain(|q| keep(&q.to));
+    }
+}
+
+#[cfg(test)]
+mod test_store_retries {
+    use {
+        super::*,
+        crate::interface::config::identity::LocalIdentitySecret,
+    };
+
+    // Content isn't checked
+    fn announcement() -> stored::announcement::Announcement {
+        return stored::announcement::Announcement::V1(stored::announcement::v1::Announcement {
+            message: Blob::new(0),
+            signature: Blob::new(0),
+            _p: std::marker::PhantomData,
+        });
+    }
+
+    #[test]
+    fn test_flush() {
+        let (node, _) = node_identity::NodeIdentity::new();
+        let (other, _) = node_identity::NodeIdentity::new();
+        let (ident, _) = LocalIdentitySecret::new();
+        let value = announcement();
+        let now = Instant::now();
+        let mut retries = StoreRetries::default();
+        assert!(!retries.recently_unresponsive(&node, now));
+        retries.mark(&node, true, now);
+        assert!(retries.recently_unresponsive(&node, now));
+        assert!(!retries.recently_unresponsive(&node, now + STORE_RETRY_WINDOW));
+        assert_eq!(retries.queue(&node, &ident, &value, now), 0);
+        assert_eq!(retries.queue(&node, &ident, &value, now), 0);
+        assert_eq!(retries.queue.len(), 1);
+        assert!(retries.mark(&other, false, now).is_empty());
+        assert_eq!(retries.mark(&node, false, now).len(), 1);
+        assert!(retries.queue.is_empty());
+        assert!(!retries.recently_unresponsive(&node, now));
+    }
+
+    #[test]
+    fn test_expire() {
+        let (node, _) = node_identity::NodeIdentity::new();
+        let (ident, _) = LocalIdentitySecret::new();
+        let value = announcement();
+        let now = Instant::now();
+        let mut retries = StoreRetries::default();
+        retries.mark(&node, true, now);
+        retries.queue(&node, &ident, &value, now);
+        assert_eq!(retries.expire(now), 0);
+        assert!(retries.mark(&node, false, now + STORE_RETRY_EXPIRY).is_empty());
+        retries.queue(&node, &ident, &value, now);
+        assert_eq!(retries.expire(now + STORE_RETRY_EXPIRY), 1);
+    }
+}
+
+/// Maximum number of remembered challenge verifications.
+const VERIFIED_PEERS_MAX: usize = 4096;
+
+/// Nodes that recently answered a challenge at an address, so unsolicited contact
+/// from the same node and address within the validity window can skip another
+/// challenge. Contact from any other address is still challenged.
+#[derive(Default)]
+struct VerifiedPeers {
+    verified: HashMap<(NodeIdentity, SocketAddr), Instant>,
+}
+
+impl VerifiedPeers {
+    /// Remember a successful challenge. If full, expired entries are dropped first;
+    /// if still full the verification isn't remembered.
+    fn insert(&mut self, ident: &NodeIdentity, addr: &SocketAddr, now: Instant, validity: std::time::Duration) {
+        let key = (*ident, *addr);
+        if self.verified.len() >= VERIFIED_PEERS_MAX && !self.verified.contains_key(&key) {
+            self.verified.retain(|_, t| now.duration_since(*t) < validity);
+            if self.verified.len() >= VERIFIED_PEERS_MAX {
+                return;
+            }
+        }
+        self.verified.insert(key, now);
+    }
+
+    /// Whether the node answered a challenge at the address within `validity`.
+    fn check(&self, ident: &NodeIdentity, addr: &SocketAddr, now: Instant, validity: std::time::Duration) -> bool {
+        return self
+            .verified
+            .get(&(*ident, *addr))
+            .map(|t| now.duration_since(*t) < validity)
+            .unwrap_or(false);
+    }
+
+    /// Forget all verifications of a node, ex: when it leaves.
+    fn forget(&mut self, ident: &NodeIdentity) {
+        self.verified.retain(|k, _| &k.0 != ident);
+    }
+}
+
+#[cfg(test)]
+mod test_verified_peers {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let (node, _) = node_identity::NodeIdentity::new();
+        let addr = SocketAddr::from(([192, 0, 2, 1], 48390));
+        let other_addr = SocketAddr::from(([192, 0, 2, 2], 48390));
+        let validity = std::time::Duration::from_secs(600);
+        let now = Instant::now();
+        let mut verified = VerifiedPeers::default();
+        assert!(!verified.check(&node, &addr, now, validity));
+        verified.insert(&node, &addr, now, validity);
+        assert!(verified.check(&node, &addr, now, validity));
+
+        // A different address still needs a challenge
+        assert!(!verified.check(&node, &other_addr, now, validity));
+
+        // Expired
+        assert!(!verified.check(&node, &addr, now + validity, validity));
+
+        // Forgotten
+        verified.forget(&node);
+        assert!(!verified.check(&node, &addr, now, validity));
+    }
+}
+
+/// Number of the fastest peers from the proximity index added to a find's first hop.
+const PROXIMITY_SEEDS: usize = 2;
+
+/// The verified peers with the lowest find response times, regardless of distance,
+/// kept sorted fastest first.
+struct ProximityIndex {
+    max: usize,
+    peers: Vec<(std::time::Duration, wire::node::latest::NodeInfo)>,
+}
+
+impl ProximityIndex {
+    fn new(max: usize) -> ProximityIndex {
+        return ProximityIndex {
+            max: max,
+            peers: vec![],
+        };
+    }
+
+    /// Record a peer's current average response time. Peers slower than all the
+    /// indexed peers are dropped when the index is full.
+    fn update(&mut self, node: &wire::node::latest::NodeInfo, rtt: std::time::Duration) {
+        self.peers.retain(|(_, p)| p.ident != node.ident);
+        let i = self.peers.partition_point(|(r, _)| *r <= rtt);
+        if i >= self.max {
+            return;
+        }
+        self.peers.insert(i, (rtt, node.clone()));
+        self.peers.truncate(self.max);
+    }
+
+    fn retain(&mut self, keep: impl Fn(&NodeIdentity) -> bool) {
+        self.peers.retain(|(_, p)| keep(&p.ident));
+    }
+
+    /// Up to `count` of the fastest peers, skipping `exclude`.
+    fn fastest(&self, count: usize, exclude: &[wire::node::latest::NodeInfo]) -> Vec<wire::node::latest::NodeInfo> {
+        return self
+            .peers
+            .iter()
+            .filter(|(_, p)| !exclude.iter().any(|e| e.ident == p.ident))
+            .take(count)
+            .map(|(_, p)| p.clone())
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod test_db {
+    use {
+        super::*,
+        crate::utils::db_util::{
+            check_schema_version,
+            mark_schema_version,
+        },
+    };
+
+    #[test]
+    fn test_reopen() {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        check_schema_version(&conn, DB_SCHEMA_VERSION).unwrap();
+        db::migrate(&mut conn).unwrap();
+        mark_schema_version(&conn, DB_SCHEMA_VERSION).unwrap();
+        let (ident, secret) = NodeIdentity::new();
+        db::secret_ensure(&conn, &secret).unwrap();
+        db::neighbors_insert(&conn, &wire::node::NodeState::V1(wire::node::latest::NodeState {
+            node: wire::node::latest::NodeInfo {
+                ident: ident,
+                address: SerialAddr(SocketAddr::from(([192, 0, 2, 1], 48390))),
+            },
+            unresponsive: false,
+            alt_addresses: vec![],
+            max_datagram: None,
+        })).unwrap();
+
+        // Migrating again on the next start keeps the secret and neighbors
+        check_schema_version(&conn, DB_SCHEMA_VERSION).unwrap();
+        db::migrate(&mut conn).unwrap();
+        assert_eq!(db::secret_get(&conn).unwrap().map(|s| s.get_identity()), Some(ident));
+        assert_eq!(db::neighbors_get(&conn).unwrap().len(), 1);
+
+        // Older versions refuse to open it
+        assert!(check_schema_version(&conn, DB_SCHEMA_VERSION - 1).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_proximity_index {
+    use super::*;
+
+    fn node(i: u8) -> wire::node::latest::NodeInfo {
+        return wire::node::latest::NodeInfo {
+            ident: node_identity::NodeIdentity::new().0,
+            address: SerialAddr(SocketAddr::from(([192, 0, 2, i], 48390))),
+        };
+    }
+
+    #[test]
+    fn test_update() {
+        let ms = std::time::Duration::from_millis;
+        let (a, b, c) = (node(1), node(2), node(3));
+        let mut index = ProximityIndex::new(2);
+        index.update(&a, ms(50));
+        index.update(&b, ms(20));
+        index.update(&c, ms(90));
+        assert_eq!(index.fastest(3, &[]).iter().map(|p| p.ident).collect::<Vec<_>>(), vec![b.ident, a.ident]);
+
+        // Slowing down moves a peer back, and out if others are faster
+        index.update(&b, ms(100));
+        index.update(&c, ms(10));
+        assert_eq!(index.fastest(3, &[]).iter().map(|p| p.ident).collect::<Vec<_>>(), vec![c.ident, a.ident]);
+        assert_eq!(index.fastest(1, &[c.clone()])[0].ident, a.ident);
+    }
+}
+
+struct NextPingTimeout {
+    end: DateTime<Utc>,
+    key: (node_identity::NodeIdentity, usize),
+}
+
+struct NextChallengeTimeout {
+    end: DateTime<Utc>,
+    key: (node_identity::NodeIdentity, usize),
+}
+
+struct Buckets {
+    buckets: [Vec<wire::node::latest::NodeState>; BUCKET_COUNT],
+    addrs: HashMap<SocketAddr, NodeIdentity>,
+    // Nodes that are never replaced, and replace other nodes when their bucket is
+    // full
+    protected: HashSet<NodeIdentity>,
+    // Nodes that are never added (see `NodeConfig::peers_dir`)
+    tombstoned: HashSet<NodeIdentity>,
+    // When each unresponsive node was marked unresponsive (or restored as
+    // unresponsive)
+    unresponsive_since: HashMap<NodeIdentity, Instant>,
+    // Nodes pruned for being unresponsive too long, and until when they're refused,
+    // so stale routing tables elsewhere don't bring them right back
+    pruned: HashMap<NodeIdentity, Instant>,
+}
+
+fn forget_addrs(addrs: &mut HashMap<SocketAddr, NodeIdentity>, state: &wire::node::latest::NodeState) {
+    addrs.remove(&state.node.address.0);
+    for a in &state.alt_addresses {
+        addrs.remove(&a.0);
+    }
+}
+
+/// Inconsistencies fixed by `Buckets::repair`.
+#[derive(Default, Debug)]
+struct BucketRepairs {
+    /// Nodes in a bucket that doesn't match their distance, moved (or dropped if the
+    /// right bucket is full)
+    misplaced_nodes: usize,
+    /// Nodes in the routing table more than once, dropped
+    duplicate_nodes: usize,
+    /// Addresses of more than one node. The later node is dropped if it's its
+    /// primary address, otherwise the alternate address is dropped.
+    conflicting_addrs: usize,
+    /// Address index entries not matching any node's address
+    stale_addrs: usize,
+    /// Node addresses missing from the address index
+    missing_addrs: usize,
+}
+
+impl BucketRepairs {
+    fn total(&self) -> usize {
+        return self.misplaced_nodes + self.duplicate_nodes + self.conflicting_addrs + self.stale_addrs +
+            self.missing_addrs;
+    }
+}
+
+/// Result of `Buckets::add_good_node`.
+struct AddNodeResult {
+    /// The node isn't in the routing table but there's room for it (or it was just
+    /// added)
+    new: bool,
+    /// The routing table changed and should be persisted
+    changed: bool,
+}
+
+impl Buckets {
+    /// Point `addr` at `new_ident`, removing any other node currently at that address.
+    fn store_addr(&mut self, log: &Log, own_coord: &DhtCoord, addr: SocketAddr, new_ident: NodeIdentity) {
+        if let Some(old) = self.addrs.get(&addr).cloned() {
+            if old != new_ident {
+                let (bucket_i, _) = dist(&node_ident_coord(&old), own_coord);
+                let bucket = &mut self.buckets[bucket_i];
+                if let Some(i) = bucket.iter().position(|n| n.node.ident == old) {
+                    log.log_with(
+                        loga::DEBUG,
+                        "Replaced node with same addr",
+                        ea!(addr = addr, old_ident = old, new_ident = new_ident),
+                    );
+                    let removed = bucket.remove(i);
+                    forget_addrs(&mut self.addrs, &removed);
+                }
+            }
+        };
+        self.addrs.insert(addr, new_ident);
+    }
+
+    /// Add a node restored from the database. Returns false (and doesn't add the
+    /// node) if the node's address belongs to another node. Alternate addresses that
+    /// belong to other nodes are dropped.
+    fn restore(&mut self, own_coord: &DhtCoord, mut state: wire::node::latest::NodeState) -> bool {
+        if self.addrs.contains_key(&state.node.address.0) {
+            return false;
+        }
+        let (bucket_i, _) = dist(&node_ident_coord(&state.node.ident), own_coord);
+        if self.buckets[bucket_i].iter().any(|n| n.node.ident == state.node.ident) {
+            return false;
+        }
+        self.addrs.insert(state.node.address.0, state.node.ident);
+        if state.unresponsive {
+            self.unresponsive_since.insert(state.node.ident, Instant::now());
+        }
+        state.alt_addresses.retain(|a| {
+            if a.0.is_ipv4() == state.node.address.0.is_ipv4() || self.addrs.contains_key(&a.0) {
+                return false;
+            }
+            self.addrs.insert(a.0, state.node.ident);
+            return true;
+        });
+        self.buckets[bucket_i].push(state);
+        return true;
+    }
+
+    /// See `Node::add_good_node`.
+    fn add_good_node(
+        &mut self,
+        log: &Log,
+        own_coord: &DhtCoord,
+        id: NodeIdentity,
+        node: Option<wire::node::latest::NodeInfo>,
+    ) -> AddNodeResult {
+        if self.tombstoned.contains(&id) {
+            log.log(loga::DEBUG, "Node is tombstoned, dropping");
+            return AddNodeResult {
+                new: false,
+                changed: false,
+            };
+        }
+        if self.pruned.get(&id).is_some_and(|until| *until > Instant::now()) {
+            log.log(loga::DEBUG, "Node was recently pruned for being unresponsive, dropping");
+            return AddNodeResult {
+                new: false,
+                changed: false,
+            };
+        }
+        let (bucket_i, _) = dist(&node_ident_coord(&id), own_coord);
+        let bucket = &mut self.buckets[bucket_i];
+        let mut last_unresponsive: Option<usize> = None;
+
+        // Updated or already known
+        for (i, bucket_entry) in bucket.iter_mut().enumerate() {
+            if bucket_entry.node.ident == id {
+                let Some(node) = node else {
+                    return AddNodeResult {
+                        new: false,
+                        changed: false,
+                    };
+                };
+
+                // Keep the most recent verified address in the other IP family to fail over to,
+                // unless another node has taken it since
+                let alt_addresses =
+                    [&bucket_entry.node.address]
+                        .into_iter()
+                        .chain(bucket_entry.alt_addresses.iter())
+                        .filter(|a| self.addrs.get(&a.0).map(|o| *o == id).unwrap_or(true))
+                        .find(|a| a.0.is_ipv4() != node.address.0.is_ipv4())
+                        .cloned()
+                        .into_iter()
+                        .collect::<Vec<_>>();
+                let new_state = wire::node::latest::NodeState {
+                    node: node.clone(),
+                    unresponsive: false,
+                    alt_addresses: alt_addresses.clone(),
+                    // The path to a new address needs to be probed again
+                    max_datagram: if bucket_entry.node.address == node.address {
+                        bucket_entry.max_datagram
+                    } else {
+                        None
+                    },
+                };
+                let changed = *bucket_entry != new_state;
+                if bucket_entry.node.address != node.address {
+                    log.log_with(
+                        loga::DEBUG,
+                        "Node moved to new address",
+                        ea!(old_addr = bucket_entry.node.address.0, new_addr = node.address.0),
+                    );
+                }
+                forget_addrs(&mut self.addrs, bucket_entry);
+                *bucket_entry = new_state;
+                self.unresponsive_since.remove(&id);
+                log.log(loga::DEBUG, "Updated existing node");
+                self.store_addr(log, own_coord, node.address.0, node.ident);
+                for a in alt_addresses {
+                    self.store_addr(log, own_coord, a.0, node.ident);
+                }
+                return AddNodeResult {
+                    new: false,
+                    changed: changed,
+                };
+            }
+            if bucket_entry.unresponsive && !self.protected.contains(&bucket_entry.node.ident) {
+                last_unresponsive = Some(i);
+            }
+        }
+
+        // Empty slot
+        if bucket.len() < NEIGHBORHOOD {
+            let Some(node) = node else {
+                return AddNodeResult {
+                    new: true,
+                    changed: false,
+                };
+            };
+            bucket.insert(0, wire::node::latest::NodeState {
+                node: node.clone(),
+                unresponsive: false,
+                alt_addresses: vec![],
+                max_datagram: None,
+            });
+            log.log(loga::DEBUG, "Added node to empty slot");
+            self.store_addr(log, own_coord, node.address.0, node.ident);
+            return AddNodeResult {
+                new: true,
+                changed: true,
+            };
+        }
+
+        // Replacing dead, or any unprotected node if this one is protected
+        let replace = match last_unresponsive {
+            Some(i) => Some(i),
+            None if self.protected.contains(&id) => bucket.iter().rposition(|n| !self.protected.contains(&n.node.ident)),
+            None => None,
+        };
+        if let Some(i) = replace {
+            let Some(node) = node else {
+                return AddNodeResult {
+                    new: true,
+                    changed: false,
+                };
+            };
+            let removed = bucket.remove(i);
+            bucket.push(wire::node::latest::NodeState {
+                node: node.clone(),
+                unresponsive: false,
+                alt_addresses: vec![],
+                max_datagram: None,
+            });
+            forget_addrs(&mut self.addrs, &removed);
+            log.log_with(
+                loga::DEBUG,
+                "Replaced node",
+                ea!(old_ident = removed.node.ident, unresponsive = removed.unresponsive),
+            );
+            self.store_addr(log, own_coord, node.address.0, node.ident);
+            return AddNodeResult {
+                new: true,
+                changed: true,
+            };
+        }
+        log.log(loga::DEBUG, "Nowhere to place, dropping");
+        return AddNodeResult {
+            new: false,
+            changed: false,
+        };
+    }
+
+    /// Replace the tombstoned nodes, removing them from the routing table. Returns
+    /// whether any were removed.
+    fn set_tombstoned(&mut self, own_coord: &DhtCoord, tombstoned: HashSet<NodeIdentity>) -> bool {
+        let mut changed = false;
+        for id in &tombstoned {
+            let (bucket_i, _) = dist(&node_ident_coord(id), own_coord);
+            let bucket = &mut self.buckets[bucket_i];
+            if let Some(i) = bucket.iter().position(|n| &n.node.ident == id) {
+                let removed = bucket.remove(i);
+                forget_addrs(&mut self.addrs, &removed);
+                changed = true;
+            }
+        }
+        self.tombstoned = tombstoned;
+        return changed;
+    }
+
+    /// Set whether a node is unresponsive, returning whether anything changed.
+    fn mark_node_unresponsive(&mut self, key: &NodeIdentity, bucket_i: usize, unresponsive: bool) -> bool {
+        let Some(n) = self.buckets[bucket_i].iter_mut().find(|n| &n.node.ident == key) else {
+            return false;
+        };
+        if n.unresponsive == unresponsive {
+            return false;
+        }
+        n.unresponsive = unresponsive;
+        if unresponsive {
+            self.unresponsive_since.insert(*key, Instant::now());
+        } else {
+            self.unresponsive_since.remove(key);
+        }
+        return true;
+    }
+
+    /// Remove unprotected nodes that have been unresponsive for at least `after`, even
+    /// if no other node is waiting for their slot, and refuse them for `memory`
+    /// afterwards. Returns the number of nodes removed from each bucket that had any
+    /// removed.
+    fn prune_stale(
+        &mut self,
+        now: Instant,
+        after: std::time::Duration,
+        memory: std::time::Duration,
+    ) -> Vec<(usize, usize)> {
+        self.pruned.retain(|_, until| *until > now);
+        let mut out = vec![];
+        for (bucket_i, bucket) in self.buckets.iter_mut().enumerate() {
+            let before = bucket.len();
+            bucket.retain(|n| {
+                if !n.unresponsive || self.protected.contains(&n.node.ident) {
+                    return true;
+                }
+                let Some(since) = self.unresponsive_since.get(&n.node.ident) else {
+                    return true;
+                };
+                if now.duration_since(*since) < after {
+                    return true;
+                }
+                forget_addrs(&mut self.addrs, n);
+                self.pruned.insert(n.node.ident, now + memory);
+                return false;
+            });
+            if bucket.len() < before {
+                out.push((bucket_i, before - bucket.len()));
+            }
+        }
+
+        // Also forget nodes replaced or removed since they became unresponsive
+        let buckets = &self.buckets;
+        self
+            .unresponsive_since
+            .retain(|id, _| buckets.iter().any(|b| b.iter().any(|n| &n.node.ident == id && n.unresponsive)));
+        return out;
+    }
+
+    /// Whether `id` is in the routing table and `addr` isn't one of its addresses.
+    fn moved(&self, own_coord: &DhtCoord, id: &NodeIdentity, addr: &SocketAddr) -> bool {
+        let (bucket_i, _) = dist(&node_ident_coord(id), own_coord);
+        let Some(entry) = self.buckets[bucket_i].iter().find(|n| &n.node.ident == id) else {
+            return false;
+        };
+        return [&entry.node.address].into_iter().chain(entry.alt_addresses.iter()).all(|a| a.0 != *addr);
+    }
+
+    /// The largest datagram confirmed to reach `id`, if it's been probed.
+    fn max_datagram(&self, own_coord: &DhtCoord, id: &NodeIdentity) -> Option<u16> {
+        let (bucket_i, _) = dist(&node_ident_coord(id), own_coord);
+        return self.buckets[bucket_i].iter().find(|n| &n.node.ident == id)?.max_datagram;
+    }
+
+    /// Record the result of MTU probing, returning whether anything changed.
+    fn set_max_datagram(&mut self, own_coord: &DhtCoord, id: &NodeIdentity, size: u16) -> bool {
+        let (bucket_i, _) = dist(&node_ident_coord(id), own_coord);
+        let Some(entry) = self.buckets[bucket_i].iter_mut().find(|n| &n.node.ident == id) else {
+            return false;
+        };
+        if entry.max_datagram == Some(size) {
+            return false;
+        }
+        entry.max_datagram = Some(size);
+        return true;
+    }
+
+    /// See `Node::fail_over`. Returns the node and its new address.
+    fn fail_over(&mut self, own_coord: &DhtCoord, addr: &SocketAddr) -> Option<(NodeIdentity, SocketAddr)> {
+        let ident = *self.addrs.get(addr)?;
+        let (bucket_i, _) = dist(&node_ident_coord(&ident), own_coord);
+        let entry = self.buckets[bucket_i].iter_mut().find(|n| n.node.ident == ident)?;
+        if entry.node.address.0 != *addr || entry.alt_addresses.is_empty() {
+            return None;
+        }
+        let alt = entry.alt_addresses.remove(0);
+        let old = std::mem::replace(&mut entry.node.address, alt);
+        entry.alt_addresses.push(old);
+        return Some((ident, entry.node.address.0));
+    }
+
+    /// Check that each node is in one bucket, the right one, and that the address index
+    /// maps exactly the nodes' addresses, fixing anything that doesn't match. The
+    /// other methods keep these consistent so this should never find anything.
+    fn repair(&mut self, own_coord: &DhtCoord) -> BucketRepairs {
+        let mut repairs = BucketRepairs::default();
+        let mut seen = HashSet::new();
+        let mut misplaced = vec![];
+        for (bucket_i, bucket) in self.buckets.iter_mut().enumerate() {
+            bucket.retain(|n| {
+                if dist(&node_ident_coord(&n.node.ident), own_coord).0 != bucket_i {
+                    repairs.misplaced_nodes += 1;
+                    misplaced.push(n.clone());
+                    return false;
+                }
+                if !seen.insert(n.node.ident) {
+                    repairs.duplicate_nodes += 1;
+                    return false;
+                }
+                return true;
+            });
+        }
+        for n in misplaced {
+            let (bucket_i, _) = dist(&node_ident_coord(&n.node.ident), own_coord);
+            if self.buckets[bucket_i].len() < NEIGHBORHOOD && seen.insert(n.node.ident) {
+                self.buckets[bucket_i].push(n);
+            }
+        }
+
+        // Rebuild the address index from the buckets
+        let mut addrs = HashMap::new();
+        for bucket in &mut self.buckets {
+            bucket.retain_mut(|n| {
+                if addrs.contains_key(&n.node.address.0) {
+                    repairs.conflicting_addrs += 1;
+                    return false;
+                }
+                let ident = n.node.ident;
+                addrs.insert(n.node.address.0, ident);
+                n.alt_addresses.retain(|a| {
+                    if addrs.contains_key(&a.0) {
+                        repairs.conflicting_addrs += 1;
+                        return false;
+                    }
+                    addrs.insert(a.0, ident);
+                    return true;
+                });
+                return true;
+            });
+        }
+        for (addr, ident) in &self.addrs {
+            if addrs.get(addr) != Some(ident) {
+                repairs.stale_addrs += 1;
+            }
+        }
+        for addr in addrs.keys() {
+            if !self.addrs.contains_key(addr) {
+                repairs.missing_addrs += 1;
+            }
+        }
+        self.addrs = addrs;
+        return repairs;
+    }
+}
+
+#[cfg(test)]
+mod test_buckets {
+    use {
+        super::*,
+        proptest::prelude::*,
+        std::net::{
+            IpAddr,
+            Ipv4Addr,
+            Ipv6Addr,
+        },
+    };
+
+    const IDENTS: usize = 32;
+    const ADDRS: usize = 16;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Add {
+            ident: usize,
+            addr: usize,
+            verified: bool,
+        },
+        MarkUnresponsive {
+            ident: usize,
+            unresponsive: bool,
+        },
+        FailOver {
+            addr: usize,
+        },
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        return prop_oneof![
+            (0 .. IDENTS, 0 .. ADDRS, any::<bool>()).prop_map(|(ident, addr, verified)| Op::Add {
+                ident: ident,
+                addr: addr,
+                verified: verified,
+            }),
+            (0 .. IDENTS, any::<bool>()).prop_map(|(ident, unresponsive)| Op::MarkUnresponsive {
+                ident: ident,
+                unresponsive: unresponsive,
+            }),
+            (0 .. ADDRS).prop_map(|addr| Op::FailOver { addr: addr })
+        ];
+    }
+
+    // Half the addresses are IPv4, half IPv6, so nodes pick up alternate addresses
+    fn addr(i: usize) -> SocketAddr {
+        if i.is_multiple_of(2) {
+            return SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, i as u8)), 1000);
+        } else {
+            return SocketAddr::new(IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, i as u16)), 1000);
+        }
+    }
+
+    // Keys are random, so generate them once per run to keep shrinking consistent
+    fn pool() -> &'static (DhtCoord, Vec<NodeIdentity>) {
+        static POOL: std::sync::OnceLock<(DhtCoord, Vec<NodeIdentity>)> = std::sync::OnceLock::new();
+        return POOL.get_or_init(
+            || (node_ident_coord(&NodeIdentity::new().0), (0 .. IDENTS).map(|_| NodeIdentity::new().0).collect()),
+        );
+    }
+
+    fn check_invariants(own_coord: &DhtCoord, buckets: &Buckets) {
+        let mut seen = HashSet::new();
+        let mut addr_count = 0;
+        for (bucket_i, bucket) in buckets.buckets.iter().enumerate() {
+            assert!(bucket.len() <= NEIGHBORHOOD);
+            for n in bucket {
+                assert_eq!(dist(&node_ident_coord(&n.node.ident), own_coord).0, bucket_i);
+                assert!(seen.insert(n.node.ident), "Node in routing table twice");
+                assert!(n.alt_addresses.len() <= 1);
+                for a in &n.alt_addresses {
+                    assert_ne!(a.0.is_ipv4(), n.node.address.0.is_ipv4());
+                }
+                for a in [&n.node.address].into_iter().chain(n.alt_addresses.iter()) {
+                    assert_eq!(buckets.addrs.get(&a.0), Some(&n.node.ident), "Address not mapped to node");
+                    addr_count += 1;
+                }
+            }
+        }
+        assert_eq!(buckets.addrs.len(), addr_count, "Address map has addresses of removed nodes");
+    }
+
+    proptest!{
+        #[test]
+        fn test_bucket_invariants(ops in proptest::collection::vec(op(), 1 .. 300)) {
+            let log = Log::new();
+            let (own_coord, idents) = pool();
+            let mut buckets = Buckets {
+                buckets: array_init::array_init(|_| vec![]),
+                addrs: HashMap::new(),
+                protected: HashSet::new(),
+                tombstoned: HashSet::new(),
+                unresponsive_since: HashMap::new(),
+                pruned: HashMap::new(),
+            };
+            for op in ops {
+                let before = buckets.buckets.clone();
+                let changed;
+                match op {
+                    Op::Add { ident, addr: addr_i, verified } => {
+                        let id = idents[ident];
+                        let (bucket_i, _) = dist(&node_ident_coord(&id), own_coord);
+                        let was_present = buckets.buckets[bucket_i].iter().any(|n| n.node.ident == id);
+                        let res = buckets.add_good_node(&log, own_coord, id, if verified {
+                            Some(wire::node::latest::NodeInfo {
+                                ident: id,
+                                address: SerialAddr(addr(addr_i)),
+                            })
+                        } else {
+                            None
+                        });
+                        changed = res.changed;
+                        if was_present {
+                            assert!(!res.new);
+                        }
+                        if verified && res.new {
+                            let entry = buckets.buckets[bucket_i].iter().find(|n| n.node.ident == id).unwrap();
+                            assert_eq!(entry.node.address.0, addr(addr_i));
+                            assert!(!entry.unresponsive);
+                        }
+                        if !verified {
+                            assert!(!res.changed);
+                        }
+                    },
+                    Op::MarkUnresponsive { ident, unresponsive } => {
+                        let id = idents[ident];
+                        let (bucket_i, _) = dist(&node_ident_coord(&id), own_coord);
+                        changed = buckets.mark_node_unresponsive(&id, bucket_i, unresponsive);
+                    },
+                    Op::FailOver { addr: addr_i } => {
+                        let res = buckets.fail_over(own_coord, &addr(addr_i));
+                        if let Some((id, new_addr)) = &res {
+                            assert_ne!(*new_addr, addr(addr_i));
+                            assert_eq!(buckets.addrs.get(new_addr), Some(id));
+                        }
+                        changed = res.is_some();
+                    },
+                }
+                check_invariants(own_coord, &buckets);
+                assert_eq!(changed, buckets.buckets != before, "Change flag doesn't match routing table change");
+            }
+        }
+    }
+
+    #[test]
+    fn test_replace_dead_only_when_full() {
+        let log = Log::new();
+        let own_coord = node_ident_coord(&NodeIdentity::new().0);
+        let mut buckets = Buckets {
+            buckets: array_init::array_init(|_| vec![]),
+            addrs: HashMap::new(),
+            protected: HashSet::new(),
+            tombstoned: HashSet::new(),
+            unresponsive_since: HashMap::new(),
+            pruned: HashMap::new(),
+        };
+
+        // Fill the farthest bucket
+        let mut added = vec![];
+        let mut i = 0;
+        while added.len() < NEIGHBORHOOD + 1 {
+            let id = NodeIdentity::new().0;
+            if dist(&node_ident_coord(&id), &own_coord).0 != 0 {
+                continue;
+            }
+            let res = buckets.add_good_node(&log, &own_coord, id, Some(wire::node::latest::NodeInfo {
+                ident: id,
+                address: SerialAddr(addr(i)),
+            }));
+            assert_eq!(res.new, added.len() < NEIGHBORHOOD);
+            added.push(id);
+            i += 2;
+        }
+        let dropped = added.pop().unwrap();
+        assert!(!buckets.buckets[0].iter().any(|n| n.node.ident == dropped));
+
+        // Once a node is unresponsive it can be replaced
+        assert!(buckets.mark_node_unresponsive(&added[3], 0, true));
+        let res = buckets.add_good_node(&log, &own_coord, dropped, Some(wire::node::latest::NodeInfo {
+            ident: dropped,
+            address: SerialAddr(addr(i)),
+        }));
+        assert!(res.new && res.changed);
+        assert!(!buckets.buckets[0].iter().any(|n| n.node.ident == added[3]));
+        check_invariants(&own_coord, &buckets);
+    }
+
+    #[test]
+    fn test_protected() {
+        let log = Log::new();
+        let own_coord = node_ident_coord(&NodeIdentity::new().0);
+        let mut buckets = Buckets {
+            buckets: array_init::array_init(|_| vec![]),
+            addrs: HashMap::new(),
+            protected: HashSet::new(),
+            tombstoned: HashSet::new(),
+            unresponsive_since: HashMap::new(),
+            pruned: HashMap::new(),
+        };
+        let mut idents = vec![];
+        while idents.len() < NEIGHBORHOOD + 2 {
+            let id = NodeIdentity::new().0;
+            if dist(&node_ident_coord(&id), &own_coord).0 != 0 {
+                continue;
+            }
+            idents.push(id);
+        }
+        let protected = idents.pop().unwrap();
+        let extra = idents.pop().unwrap();
+        buckets.protected.insert(protected);
+        buckets.protected.insert(idents[0]);
+        for (i, id) in idents.iter().enumerate() {
+            buckets.add_good_node(&log, &own_coord, *id, Some(wire::node::latest::NodeInfo {
+                ident: *id,
+                address: SerialAddr(addr(i * 2)),
+            }));
+        }
+
+        // Unresponsive protected nodes aren't replaced
+        assert!(buckets.mark_node_unresponsive(&idents[0], 0, true));
+        let res = buckets.add_good_node(&log, &own_coord, extra, Some(wire::node::latest::NodeInfo {
+            ident: extra,
+            address: SerialAddr(addr(100)),
+        }));
+        assert!(!res.new && !res.changed);
+        assert!(buckets.buckets[0].iter().any(|n| n.node.ident == idents[0]));
+
+        // Protected nodes replace responsive unprotected nodes in a full bucket
+        let res = buckets.add_good_node(&log, &own_coord, protected, Some(wire::node::latest::NodeInfo {
+            ident: protected,
+            address: SerialAddr(addr(102)),
+        }));
+        assert!(res.new && res.changed);
+        assert!(buckets.buckets[0].iter().any(|n| n.node.ident == protected));
+        assert!(buckets.buckets[0].iter().any(|n| n.node.ident == idents[0]));
+        assert_eq!(buckets.buckets[0].len(), NEIGHBORHOOD);
+        check_invariants(&own_coord, &buckets);
+    }
+
+    #[test]
+    fn test_tombstoned() {
+        let log = Log::new();
+        let own_coord = node_ident_coord(&NodeIdentity::new().0);
+        let mut buckets = Buckets {
+            buckets: array_init::array_init(|_| vec![]),
+            addrs: HashMap::new(),
+            protected: HashSet::new(),
+            tombstoned: HashSet::new(),
+            unresponsive_since: HashMap::new(),
+            pruned: HashMap::new(),
+        };
+        let id = NodeIdentity::new().0;
+        let node = wire::node::latest::NodeInfo {
+            ident: id,
+            address: SerialAddr(addr(0)),
+        };
+        assert!(buckets.add_good_node(&log, &own_coord, id, Some(node.clone())).changed);
+
+        // Removed, and not added back
+        assert!(buckets.set_tombstoned(&own_coord, [id].into_iter().collect()));
+        assert!(buckets.buckets.iter().all(|b| b.is_empty()));
+        assert!(!buckets.add_good_node(&log, &own_coord, id, Some(node.clone())).changed);
+        check_invariants(&own_coord, &buckets);
+
+        // Until the tombstone is removed
+        assert!(!buckets.set_tombstoned(&own_coord, HashSet::new()));
+        assert!(buckets.add_good_node(&log, &own_coord, id, Some(node)).changed);
+        check_invariants(&own_coord, &buckets);
+    }
+
+    #[test]
+    fn test_moved() {
+        let log = Log::new();
+        let own_coord = node_ident_coord(&NodeIdentity::new().0);
+        let mut buckets = Buckets {
+            buckets: array_init::array_init(|_| vec![]),
+            addrs: HashMap::new(),
+            protected: HashSet::new(),
+            tombstoned: HashSet::new(),
+            unresponsive_since: HashMap::new(),
+            pruned: HashMap::new(),
+        };
+        let id = NodeIdentity::new().0;
+        for a in [addr(0), addr(1)] {
+            buckets.add_good_node(&log, &own_coord, id, Some(wire::node::latest::NodeInfo {
+                ident: id,
+                address: SerialAddr(a),
+            }));
+        }
+        assert!(!buckets.moved(&own_coord, &id, &addr(0)));
+        assert!(!buckets.moved(&own_coord, &id, &addr(1)));
+        assert!(!buckets.moved(&own_coord, &NodeIdentity::new().0, &addr(2)));
+
+        // NAT rebinding, new port on the same IPv4 address
+        let rebound = SocketAddr::new(addr(0).ip(), 2000);
+        assert!(buckets.moved(&own_coord, &id, &rebound));
+        let res = buckets.add_good_node(&log, &own_coord, id, Some(wire::node::latest::NodeInfo {
+            ident: id,
+            address: SerialAddr(rebound),
+        }));
+        assert!(!res.new && res.changed);
+        assert!(!buckets.moved(&own_coord, &id, &rebound));
+        assert!(buckets.moved(&own_coord, &id, &addr(0)));
+        assert!(!buckets.moved(&own_coord, &id, &addr(1)));
+        assert_eq!(buckets.addrs.get(&addr(0)), None);
+        check_invariants(&own_coord, &buckets);
+    }
+
+    #[test]
+    fn test_repair() {
+        let log = Log::new();
+        let own_coord = node_ident_coord(&NodeIdentity::new().0);
+        let mut buckets = Buckets {
+            buckets: array_init::array_init(|_| vec![]),
+            addrs: HashMap::new(),
+            protected: HashSet::new(),
+            tombstoned: HashSet::new(),
+            unresponsive_since: HashMap::new(),
+            pruned: HashMap::new(),
+        };
+        let idents = (0 .. 3).map(|_| NodeIdentity::new().0).collect::<Vec<_>>();
+        for (i, id) in idents.iter().enumerate() {
+            buckets.add_good_node(&log, &own_coord, *id, Some(wire::node::latest::NodeInfo {
+                ident: *id,
+                address: SerialAddr(addr(i)),
+            }));
+        }
+        assert_eq!(buckets.repair(&own_coord).total(), 0);
+
+        // Drift: a node's address dropped from the index, a removed node's address left
+        // in it, and a node copied into the wrong bucket
+        buckets.addrs.remove(&addr(0));
+        buckets.addrs.insert(addr(10), NodeIdentity::new().0);
+        let (bucket_i, _) = dist(&node_ident_coord(&idents[1]), &own_coord);
+        let copy = buckets.buckets[bucket_i].iter().find(|n| n.node.ident == idents[1]).unwrap().clone();
+        buckets.buckets[(bucket_i + 1) % BUCKET_COUNT].push(copy);
+        let repairs = buckets.repair(&own_coord);
+        assert_eq!(repairs.missing_addrs, 1);
+        assert_eq!(repairs.stale_addrs, 1);
+        assert_eq!(repairs.misplaced_nodes, 1);
+        check_invariants(&own_coord, &buckets);
+        assert_eq!(buckets.repair(&own_coord).total(), 0);
+    }
+
+    #[test]
+    fn test_max_datagram() {
+        let log = Log::new();
+        let own_coord = node_ident_coord(&NodeIdentity::new().0);
+        let mut buckets = Buckets {
+            buckets: array_init::array_init(|_| vec![]),
+            addrs: HashMap::new(),
+            protected: HashSet::new(),
+            tombstoned: HashSet::new(),
+            unresponsive_since: HashMap::new(),
+            pruned: HashMap::new(),
+        };
+        let id = NodeIdentity::new().0;
+        let node = |a| wire::node::latest::NodeInfo {
+            ident: id,
+            address: SerialAddr(a),
+        };
+        buckets.add_good_node(&log, &own_coord, id, Some(node(addr(0))));
+        assert_eq!(buckets.max_datagram(&own_coord, &id), None);
+        assert!(buckets.set_max_datagram(&own_coord, &id, 512));
+        assert!(!buckets.set_max_datagram(&own_coord, &id, 512));
+        assert!(!buckets.set_max_datagram(&own_coord, &NodeIdentity::new().0, 512));
+
+        // Kept when the node is seen at the same address, reset when it moves
+        buckets.add_good_node(&log, &own_coord, id, Some(node(addr(0))));
+        assert_eq!(buckets.max_datagram(&own_coord, &id), Some(512));
+        buckets.add_good_node(&log, &own_coord, id, Some(node(SocketAddr::new(addr(0).ip(), 2000))));
+        assert_eq!(buckets.max_datagram(&own_coord, &id), None);
+    }
+
+    #[test]
+    fn test_prune_stale() {
+        let log = Log::new();
+        let own_coord = node_ident_coord(&NodeIdentity::new().0);
+        let mut buckets = Buckets {
+            buckets: array_init::array_init(|_| vec![]),
+            addrs: HashMap::new(),
+            protected: HashSet::new(),
+            tombstoned: HashSet::new(),
+            unresponsive_since: HashMap::new(),
+            pruned: HashMap::new(),
+        };
+        let after = std::time::Duration::from_secs(60 * 60);
+        let memory = std::time::Duration::from_secs(10 * 60);
+        let idents = (0 .. 3).map(|_| NodeIdentity::new().0).collect::<Vec<_>>();
+        for (i, id) in idents.iter().enumerate() {
+            buckets.add_good_node(&log, &own_coord, *id, Some(wire::node::latest::NodeInfo {
+                ident: *id,
+                address: SerialAddr(addr(i)),
+            }));
+        }
+        buckets.protected.insert(idents[2]);
+        for id in [idents[0], idents[2]] {
+            let (bucket_i, _) = dist(&node_ident_coord(&id), &own_coord);
+            buckets.mark_node_unresponsive(&id, bucket_i, true);
+        }
+
+        // Not unresponsive long enough yet
+        let now = Instant::now();
+        assert!(buckets.prune_stale(now, after, memory).is_empty());
+
+        // Only the unprotected unresponsive node is removed
+        let pruned = buckets.prune_stale(now + after, after, memory);
+        assert_eq!(pruned.iter().map(|(_, count)| *count).sum::<usize>(), 1);
+        check_invariants(&own_coord, &buckets);
+        assert!(buckets.buckets.iter().flatten().all(|n| n.node.ident != idents[0]));
+        assert!(buckets.buckets.iter().flatten().any(|n| n.node.ident == idents[1]));
+        assert!(buckets.buckets.iter().flatten().any(|n| n.node.ident == idents[2]));
+
+        // Refused while remembered
+        let res = buckets.add_good_node(&log, &own_coord, idents[0], Some(wire::node::latest::NodeInfo {
+            ident: idents[0],
+            address: SerialAddr(addr(0)),
+        }));
+        assert!(!res.new);
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum TrafficPeer {
+    Node(NodeIdentity),
+    // Addresses not in the routing table
+    Addr(SocketAddr),
+    Other,
+}
+
+impl std::fmt::Display for TrafficPeer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrafficPeer::Node(i) => write!(f, "{}", i),
+            TrafficPeer::Addr(a) => write!(f, "{}", a),
+            TrafficPeer::Other => write!(f, "other"),
+        }
+    }
+}
+
+struct TrafficPeriod {
+    start: DateTime<Utc>,
+    peers: HashMap<TrafficPeer, HashMap<&'static str, TrafficCounts>>,
+}
+
+fn message_type(m: &wire::node::Protocol) -> &'static str {
+    match m {
+        wire::node::Protocol::V1(m) => match m {
+            wire::node::v1::Message::FindRequest(_) => "find_request",
+            wire::node::v1::Message::FindResponse(_) => "find_response",
+            wire::node::v1::Message::Store(_) => "store",
+            wire::node::v1::Message::Ping => "ping",
+            wire::node::v1::Message::Pung(_) => "pung",
+            wire::node::v1::Message::Challenge(_) => "challenge",
+            wire::node::v1::Message::ChallengeResponse(_) => "challenge_response",
+            wire::node::v1::Message::PeerExchange(_) => "peer_exchange",
+            wire::node::v1::Message::StoreDeclined(_) => "store_declined",
+            wire::node::v1::Message::Capabilities(_) => "capabilities",
+            wire::node::v1::Message::StoreResponse(_) => "store_response",
+            wire::node::v1::Message::Versions(_) => "versions",
+            wire::node::v1::Message::Goodbye(_) => "goodbye",
+            wire::node::v1::Message::Error(_) => "error",
+        },
+        wire::node::Protocol::V2(m) => latest_message_type(m),
+    }
+}
+
+fn latest_message_type(m: &wire::node::latest::Message) -> &'static str {
+    match m {
+        wire::node::latest::Message::FindRequest(_) => "find_request",
+        wire::node::latest::Message::FindResponse(_) => "find_response",
+        wire::node::latest::Message::Store(_) => "store",
+        wire::node::latest::Message::Ping => "ping",
+        wire::node::latest::Message::Pung(_) => "pung",
+        wire::node::latest::Message::Challenge(_) => "challenge",
+        wire::node::latest::Message::ChallengeResponse(_) => "challenge_response",
+        wire::node::latest::Message::PeerExchange(_) => "peer_exchange",
+        wire::node::latest::Message::StoreDeclined(_) => "store_declined",
+        wire::node::latest::Message::Capabilities(_) => "capabilities",
+        wire::node::latest::Message::StoreResponse(_) => "store_response",
+        wire::node::latest::Message::Versions(_) => "versions",
+        wire::node::latest::Message::Goodbye(_) => "goodbye",
+        wire::node::latest::Message::Error(_) => "error",
+        wire::node::latest::Message::MtuProbe(_) => "mtu_probe",
+        wire::node::latest::Message::MtuProbeAck(_) => "mtu_probe_ack",
+        wire::node::latest::Message::FindValueOmitted(_) => "find_value_omitted",
+        wire::node::latest::Message::FindValueRequest(_) => "find_value_request",
+        wire::node::latest::Message::FindValueResponse(_) => "find_value_response",
+        wire::node::latest::Message::PingNonce(_) => "ping_nonce",
+        wire::node::latest::Message::SignedPung(_) => "signed_pung",
+        wire::node::latest::Message::Transports(_) => "transports",
+        wire::node::latest::Message::TransportHints(_) => "transport_hints",
+    }
+}
+
+/// The node's UDP socket, a socket on a simulated network, or another transport.
+enum NodeSocket {
+    Udp(Arc<UdpSocket>),
+    #[cfg(feature = "sim")]
+    Sim(sim::SimSocket),
+    Transport(Arc<dyn transport::NodeTransport>),
+}
+
+impl NodeSocket {
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+        match self {
+            NodeSocket::Udp(s) => return s.send_to(buf, addr).await,
+            #[cfg(feature = "sim")]
+            NodeSocket::Sim(s) => return s.send_to(buf, addr).await,
+            NodeSocket::Transport(t) => return t.send_to(buf, addr).await,
+        }
+    }
+
+    async fn send_to_many(&self, addr: &SocketAddr, packets: &[impl AsRef<[u8]>]) -> std::io::Result<()> {
+        match self {
+            NodeSocket::Udp(s) => return send_to_many(s, addr, packets).await,
+            #[cfg(feature = "sim")]
+            NodeSocket::Sim(s) => {
+                for p in packets {
+                    s.send_to(p.as_ref(), *addr).await?;
+                }
+                return Ok(());
+            },
+            NodeSocket::Transport(t) => {
+                for p in packets {
+                    t.send_to(p.as_ref(), *addr).await?;
+                }
+                return Ok(());
+            },
+        }
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> std::io::Result<(usize, SocketAddr)> {
+        match self {
+            NodeSocket::Udp(s) => return s.recv_from(buf).await,
+            #[cfg(feature = "sim")]
+            NodeSocket::Sim(s) => return s.recv_from(buf).await,
+            NodeSocket::Transport(t) => return t.recv_from(buf).await,
+        }
+    }
+
+    /// Whether peers see a relay's address rather than the node's own.
+    fn relayed(&self) -> bool {
+        match self {
+            NodeSocket::Udp(_) => return false,
+            #[cfg(feature = "sim")]
+            NodeSocket::Sim(_) => return false,
+            NodeSocket::Transport(t) => return t.relayed(),
+        }
+    }
+}
+
+/// An ephemeral socket that requests (finds and challenges) are sent from instead
+/// of the listening socket, so spoofed responses also need to guess its port.
+/// Replaced periodically.
+#[derive(Clone)]
+struct RequestSocket {
+    // Starts at 1 and increases each time the socket is replaced; 0 means the
+    // listening socket
+    generation: usize,
+    socket: Arc<UdpSocket>,
+}
+
+async fn bind_request_socket(ip: IpAddr, generation: usize) -> Result<RequestSocket, loga::Error> {
+    let socket =
+        UdpSocket::bind(
+            SocketAddr::new(ip, 0),
+        ).await.context_with("Failed to open node request UDP socket", ea!(ip = ip))?;
+    return Ok(RequestSocket {
+        generation: generation,
+        socket: Arc::new(socket),
+    });
+}
+
+struct ReceivedPacket {
+    data: Vec<u8>,
+    full: bool,
+    addr: SocketAddr,
+    socket: usize,
+}
+
+/// Workers handling received packets concurrently. Packets from an address always
+/// go to the same worker, so they're handled in the order received (ex: a challenge
+/// response isn't handled before the find response that preceded it).
+#[derive(Clone)]
+pub struct PacketWorkers(Arc<Vec<mpsc::Sender<ReceivedPacket>>>);
+
+impl PacketWorkers {
+    fn new(log: &Log, tm: &TaskManager, dir: &Node, count: usize) -> PacketWorkers {
+        let mut senders = vec![];
+        for i in 0 .. count.max(1) {
+            let (send, recv) = mpsc::channel::<ReceivedPacket>(PACKET_QUEUE);
+            senders.push(send);
+            tm.tracked_stream(format!("Node - packet worker {}", i), ReceiverStream::new(recv), cap_fn!((p)(log, dir) {
+                dir.handle_packet(&log, &p.data, p.full, &p.addr, p.socket).await;
+            }));
+        }
+        return PacketWorkers(Arc::new(senders));
+    }
+
+    /// Queue a packet for its worker, waiting if the worker is busy.
+    async fn dispatch(&self, packet: ReceivedPacket) {
+        let i = packet_worker(&packet.addr, self.0.len());
+        _ = self.0[i].send(packet).await;
+    }
+}
+
+/// Which of `count` workers handles packets from an address.
+fn packet_worker(addr: &SocketAddr, count: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    addr.hash(&mut hasher);
+    return (hasher.finish() % count as u64) as usize;
+}
+
+/// Whether a message is a response to a request, and so may be received on a
+/// request socket.
+fn is_response(m: &wire::node::Protocol) -> bool {
+    match m {
+        wire::node::Protocol::V1(m) => return matches!(
+            m,
+            wire::node::v1::Message::FindResponse(_) |
+            wire::node::v1::Message::ChallengeResponse(_) |
+            wire::node::v1::Message::Versions(_) |
+            wire::node::v1::Message::Capabilities(_) |
+            wire::node::v1::Message::Error(_)
+        ),
+        wire::node::Protocol::V2(m) => return matches!(
+            m,
+            wire::node::latest::Message::FindResponse(_) |
+            wire::node::latest::Message::ChallengeResponse(_) |
+            wire::node::latest::Message::Versions(_) |
+            wire::node::latest::Message::Capabilities(_) |
+            wire::node::latest::Message::Error(_) |
+            wire::node::latest::Message::MtuProbeAck(_) |
+            wire::node::latest::Message::FindValueOmitted(_) |
+            wire::node::latest::Message::FindValueResponse(_) |
+            wire::node::latest::Message::Transports(_) |
+            wire::node::latest::Message::TransportHints(_)
+        ),
+    }
+}
+
+/// A store acknowledgement for an in-progress put: sender, sender address, and
+/// stored or the error the sender returned.
+type PutAck = (NodeIdentity, SocketAddr, Result<bool, ErrorCode>);
+
+struct NodeInner {
+    log: Log,
+    own_ident: node_identity::NodeIdentity,
+    own_coord: DhtCoord,
+    own_secret: node_identity::NodeSecret,
+    buckets: Mutex<Buckets>,
+    // Nodes to keep in the routing table, with their configured addresses: the
+    // configured protected nodes plus pinned nodes from the peers directory
+    protected: Mutex<Vec<wire::node::latest::NodeInfo>>,
+    configured_protected: Vec<wire::node::latest::NodeInfo>,
+    peers_dir: Option<PathBuf>,
+    store: Mutex<HashMap<Identity, ValueState>>,
+    max_store: usize,
+    store_evictions: AtomicUsize,
+    announcement_rejections: AtomicUsize,
+    // Store messages for recently unresponsive neighbors
+    store_retries: Mutex<StoreRetries>,
+    store_retries_sent: AtomicUsize,
+    store_retries_dropped: AtomicUsize,
+    // Decline store requests from other nodes
+    no_store: bool,
+    // Extra closer nodes (beyond the neighborhood) tolerated before declining to store
+    // a value
+    store_tolerance: usize,
+    // Neighbors that declined or advertised declining store requests
+    no_store_peers: Mutex<HashSet<NodeIdentity>>,
+    // Protocol versions advertised by neighbors, never reduced (see
+    // `merge_peer_versions`)
+    peer_versions: Mutex<HashMap<NodeIdentity, Vec<VerInt>>>,
+    // If enabled, QUIC sharing the listening socket
+    #[cfg(feature = "quic")]
+    quic: Option<Arc<quic::QuicTransport>>,
+    // Neighbors that advertised accepting QUIC
+    quic_peers: Mutex<HashSet<NodeIdentity>>,
+    quic_fallbacks: AtomicUsize,
+    // Neighbors that advertised being reached through a relay
+    relayed_peers: Mutex<HashSet<NodeIdentity>>,
+    // MTU probes in progress: when the probes were sent and the largest size
+    // acknowledged so far
+    mtu_probes: Mutex<HashMap<NodeIdentity, (Instant, u16)>>,
+    // Moving average of how long neighbors take to answer find requests, with
+    // timeouts counting as `req_timeout`
+    peer_rtts: Mutex<HashMap<NodeIdentity, std::time::Duration>>,
+    // Fastest neighbors, if enabled, to seed finds for goals far from this node
+    proximity: Option<Mutex<ProximityIndex>>,
+    proximity_seeded: AtomicUsize,
+    // In-progress puts waiting for store acknowledgements
+    put_acks: Mutex<HashMap<Identity, tokio::sync::mpsc::UnboundedSender<PutAck>>>,
+    dirty: AtomicBool,
+    socket: NodeSocket,
+    // If enabled, where finds and challenges are sent from
+    request_socket: Mutex<Option<RequestSocket>>,
+    next_req_id: AtomicUsize,
+    find_timeouts: UnboundedSender<NextFindTimeout>,
+    find_states: Mutex<HashMap<FindGoal, FindState>>,
+    ping_states: Mutex<HashMap<node_identity::NodeIdentity, PingState>>,
+    challenge_timeouts: UnboundedSender<NextChallengeTimeout>,
+    // Quarantine - nodes only enter the routing table after responding to a
+    // challenge from their claimed address
+    challenge_states: Mutex<HashMap<node_identity::NodeIdentity, ChallengeState>>,
+    quarantine_rejections: AtomicUsize,
+    stale_rejections: AtomicUsize,
+    quarantine_evictions: AtomicUsize,
+    // Recent successful challenges, if enabled, with how long they're valid
+    verified_peers: Mutex<VerifiedPeers>,
+    verified_peer_window: Option<std::time::Duration>,
+    challenges_skipped: AtomicUsize,
+    find_evictions: AtomicUsize,
+    ping_evictions: AtomicUsize,
+    ping_rejections: AtomicUsize,
+    // Most recently completed finds
+    find_samples: Mutex<VecDeque<FindSample>>,
+    routing_repairs: AtomicUsize,
+    prune_unresponsive_after: std::time::Duration,
+    pruned_peer_memory: std::time::Duration,
+    peers_pruned: AtomicUsize,
+    clock_skew_tolerance: Duration,
+    clock_skew: Mutex<clock_skew::ClockSkew>,
+    last_churn: Mutex<Option<ChurnSummary>>,
+    last_rebalance: Mutex<Option<DateTime<Utc>>>,
+    // Notified after large routing table changes, for re-announcing
+    rebalances: broadcast::Sender<()>,
+    db_pool: Pool,
+    // Traffic since the last rollup
+    traffic: Mutex<TrafficPeriod>,
+}
+
+#[derive(Clone)]
+pub struct Node(Arc<NodeInner>);
+
+/// Outcome of `Node::put`.
+pub struct PutResult {
+    /// The value found in the network before storing, if any. If it's at least as
+    /// new as the value being put, nothing is stored.
+    pub found: Option<stored::announcement::Announcement>,
+    /// How many of the closest nodes the value was sent to, including this node.
+    pub sent: usize,
+    /// How many of those nodes acknowledged storing the value.
+    pub accepted: usize,
+    /// How many of those nodes rejected the value with an error, by error code.
+    /// Nodes that neither acknowledged nor rejected the value may not have received
+    /// it.
+    pub errors: HashMap<ErrorCode, usize>,
+}
+
+#[derive(Clone, Debug)]
+struct OutstandingNodeEntry {
+    rank: FindRank,
+    bucket_i: usize,
+    challenge: Blob,
+    node: wire::node::latest::NodeInfo,
+    // Requests between this node and the find's origin, including this one
+    hops: usize,
+    // Generation of the socket the request was sent from; the response must arrive
+    // on the same socket
+    request_socket: usize,
+    sent: Instant,
+    // The node said its response leaves out the value (`FindValueOmitted`)
+    value_omitted: bool,
+    // Waiting for a `FindValueResponse` rather than a find response
+    value_fetch: bool,
+}
+
+#[derive(Clone)]
+enum NearestNodeEntryNode {
+    Self_,
+    Node(wire::node::latest::NodeInfo),
+}
+
+#[derive(Clone)]
+struct NearestNodeEntry {
+    dist: DhtCoord,
+    node: NearestNodeEntryNode,
+}
+
+struct FindState {
+    req_id: usize,
+    goal: FindGoal,
+    updated: DateTime<Utc>,
+    nearest: Vec<NearestNodeEntry>,
+    outstanding: Vec<OutstandingNodeEntry>,
+    seen: HashSet<node_identity::NodeIdentity>,
+    // For storing value, or retrieving value. Only used for identity searches (None
+    // otherwise).
+    value: Option<stored::announcement::Announcement>,
+    // Number of responding nodes (including self) that had a valid value. Only used
+    // for identity searches.
+    holders: usize,
+    // Most hops to any node that responded
+    hops: usize,
+    started: Instant,
+    // IDs of the API requests waiting on the find, to correlate logs
+    request_ids: Vec<String>,
+    futures: Vec<ManualFutureCompleter<FindResult>>,
+}
+
+struct FindResult {
+    nearest: Vec<NearestNodeEntry>,
+    value: Option<stored::announcement::Announcement>,
+    holders: usize,
+    hops: usize,
+}
+
+/// Keep a value received for an identity find if it's valid and newer than the
+/// value found so far. Returns false if the value was rejected as invalid (bad
+/// signature or malformed content).
+fn accept_find_value(log: &Log, state: &mut FindState, value: stored::announcement::Announcement) -> bool {
+    let FindGoal::Identity(goal_identity) = state.goal else {
+        return true;
+    };
+    let found_published;
+    match &value {
+        stored::announcement::Announcement::V1(found) => {
+            let Ok(content) = found.verify(&goal_identity) else {
+                log.log(loga::DEBUG, "Got value with bad signature or malformed content");
+                return false;
+            };
+            found_published = content.announced;
+            state.holders += 1;
+        },
+    }
+    if let Some(state_value) = &state.value {
+        // Values are verified before being kept, but replace the value if it somehow
+        // can't be parsed
+        if let Ok(have_value) = state_value.parse() {
+            let have_published = have_value.announced;
+            if have_published >= found_published {
+                log.log_with(
+                    loga::DEBUG,
+                    "Received value older than one we already have",
+                    ea!(
+                        have_published = have_published.to_rfc3339(),
+                        found_published = found_published.to_rfc3339()
+                    ),
+                );
+                return true;
+            }
+        }
+    }
+    log.log_with(
+        loga::DEBUG,
+        "Found better value for find, replacing",
+        ea!(old = state.value.dbg_str(), new = state.value.dbg_str(), goal = state.goal.dbg_str()),
+    );
+    state.value = Some(value);
+    return true;
+}
+
+#[cfg(test)]
+mod test_accept_find_value {
+    use {
+        super::*,
+        crate::{
+            interface::config::identity::LocalIdentitySecret,
+            utils::{
+                blob::ToBlob,
+                identity_secret::IdentitySigner,
+            },
+        },
+    };
+
+    fn find_state(identity: Identity) -> FindState {
+        return FindState {
+            req_id: 0,
+            goal: FindGoal::Identity(identity),
+            updated: Utc::now(),
+            nearest: vec![],
+            outstanding: vec![],
+            seen: HashSet::new(),
+            value: None,
+            holders: 0,
+            hops: 0,
+            started: Instant::now(),
+            request_ids: vec![],
+            futures: vec![],
+        };
+    }
+
+    /// Correctly signed, but the signed message isn't an announcement.
+    fn corrupt(secret: &mut LocalIdentitySecret) -> stored::announcement::Announcement {
+        let message = vec![0xffu8; 7].blob();
+        let (_, signature) = IdentitySigner::sign(secret, &message).unwrap();
+        return stored::announcement::Announcement::V1(stored::announcement::v1::BincodeSignature {
+            message: message,
+            signature: signature,
+            _p: Default::default(),
+        });
+    }
+
+    #[test]
+    fn test_corrupt_rejected() {
+        let (identity, mut secret) = LocalIdentitySecret::new();
+        let value = corrupt(&mut secret);
+        assert!(value.parse().is_err());
+        let mut state = find_state(identity);
+        assert!(!accept_find_value(&Log::new(), &mut state, value));
+        assert!(state.value.is_none());
+        assert_eq!(state.holders, 0);
+    }
+
+    #[test]
+    fn test_corrupt_replaced() {
+        let (identity, mut secret) = LocalIdentitySecret::new();
+        let mut state = find_state(identity);
+        state.value = Some(corrupt(&mut secret));
+        let (_, valid) =
+            stored::announcement::v1::Announcement::sign(&mut secret, stored::announcement::v1::AnnouncementContent {
+                publishers: vec![],
+                announced: Utc::now(),
+            }).unwrap();
+        let valid = stored::announcement::Announcement::V1(valid);
+        assert!(accept_find_value(&Log::new(), &mut state, valid.clone()));
+        assert_eq!(state.value, Some(valid));
+    }
+}
+
+struct PingState {
+    req_id: usize,
+    bucket_i: usize,
+    // Already retried at an alternate address this round
+    failed_over: bool,
+    // Sent to v2 neighbors, which must echo it in a signed pung. V1 neighbors get a
+    // plain ping.
+    nonce: Option<Blob>,
+}
+
+struct ChallengeState {
+    req_id: usize,
+    challenge: Blob,
+    node: wire::node::latest::NodeInfo,
+    // Generation of the socket the challenge was sent from; the response must arrive
+    // on the same socket
+    request_socket: usize,
+    // Learned from a known node or config rather than an unsolicited message; these
+    // are evicted last
+    solicited: bool,
+}
+
+// Challenges of other lengths aren't answered, so signatures can't be requested
+// for arbitrary data
+const CHALLENGE_LEN: usize = 32;
+
+/// What a challenge response signed, depending on the protocol version it was sent
+/// with.
+enum ChallengeProof {
+    // The raw challenge
+    V1(Blob),
+    // The challenge and a stamp
+    V2(wire::node::latest::BincodeSignature<wire::node::latest::ChallengeResponseContent, NodeIdentity>),
+}
+
+fn generate_challenge() -> Blob {
+    let mut out = Blob::new(CHALLENGE_LEN);
+    rand::thread_rng().fill_bytes(out.as_mut());
+    return out;
+}
+
+/// Routing table changes between two consecutive snapshots.
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+#[serde(rename_all = "snake_case")]
+pub struct ChurnSummary {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    /// Neighbors in the routing table now that weren't at the start
+    pub joined: usize,
+    /// Neighbors in the routing table at the start that aren't now
+    pub left: usize,
+    /// Neighbors in both snapshots that switched between responsive and unresponsive
+    pub flapped: usize,
+    /// Neighbors in the routing table now
+    pub total: usize,
+}
+
+/// How neighbors are spread across the routing table buckets. Bucket `i` holds
+/// neighbors whose coordinates share the first `i` bits with this node's.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct BucketBalance {
+    /// Number of buckets holding 0, 1, 2, ... neighbors, up to a full bucket
+    pub occupancy: Vec<usize>,
+    /// The occupied bucket closest to this node
+    pub nearest_bucket: Option<usize>,
+    /// Empty buckets farther than `nearest_bucket`. With a healthy routing table these
+    /// should all have neighbors.
+    pub empty_near_buckets: usize,
+    /// Network size estimated from the first bucket that isn't full. Buckets that
+    /// aren't full should hold every node at that distance, and each bucket covers
+    /// half the keyspace of the previous.
+    pub estimated_network_size: Option<u64>,
+}
+
+fn bucket_balance(bucket_lens: &[usize]) -> BucketBalance {
+    let mut occupancy = vec![0; NEIGHBORHOOD + 1];
+    for len in bucket_lens {
+        occupancy[(*len).min(NEIGHBORHOOD)] += 1;
+    }
+    let nearest_bucket = bucket_lens.iter().rposition(|l| *l > 0);
+    let empty_near_buckets = match nearest_bucket {
+        Some(nearest) => bucket_lens[..nearest].iter().filter(|l| **l == 0).count(),
+        None => 0,
+    };
+    let estimated_network_size = match (nearest_bucket, bucket_lens.iter().position(|l| *l < NEIGHBORHOOD)) {
+        (Some(_), Some(first_partial)) => {
+            // Including self
+            let nearer = 1 + bucket_lens[first_partial..].iter().sum::<usize>();
+            Some((nearer as f64 * 2f64.powi(first_partial as i32)) as u64)
+        },
+        _ => None,
+    };
+    return BucketBalance {
+        occupancy: occupancy,
+        nearest_bucket: nearest_bucket,
+        empty_near_buckets: empty_near_buckets,
+        estimated_network_size: estimated_network_size,
+    };
+}
+
+#[cfg(test)]
+mod test_bucket_balance {
+    use super::*;
+
+    #[test]
+    fn test_empty() {
+        let balance = bucket_balance(&[0; BUCKET_COUNT]);
+        assert_eq!(balance.occupancy[0], BUCKET_COUNT);
+        assert_eq!(balance.nearest_bucket, None);
+        assert_eq!(balance.empty_near_buckets, 0);
+        assert_eq!(balance.estimated_network_size, None);
+    }
+
+    #[test]
+    fn test_estimate() {
+        let mut lens = [0; BUCKET_COUNT];
+        lens[0] = NEIGHBORHOOD;
+        lens[1] = NEIGHBORHOOD;
+        lens[2] = 3;
+        lens[4] = 1;
+        let balance = bucket_balance(&lens);
+        assert_eq!(balance.occupancy[NEIGHBORHOOD], 2);
+        assert_eq!(balance.nearest_bucket, Some(4));
+        assert_eq!(balance.empty_near_buckets, 1);
+        assert_eq!(balance.estimated_network_size, Some(20));
+    }
+}
+
+// Completed finds kept for `HealthDetail::finds`
+const FIND_STATS_WINDOW: usize = 1024;
+
+// Upper bounds (milliseconds) of the find latency histogram buckets. A last bucket
+// holds slower finds.
+const FIND_LATENCY_BOUNDS_MS: [u64; 9] = [10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum FindOutcome {
+    /// Every queried node answered, and there were no closer nodes left to ask
+    Converged,
+    /// Some queried nodes didn't answer in time
+    TimedOut,
+    /// Completed early because too many finds were in progress
+    Evicted,
+}
+
+struct FindSample {
+    latency: std::time::Duration,
+    hops: usize,
+    outcome: FindOutcome,
+    // Whether a value was found, for identity finds
+    value_found: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct FindLatencyBucket {
+    /// Upper bound of the bucket's latencies in milliseconds, null for the last bucket
+    pub le_ms: Option<u64>,
+    pub count: usize,
+}
+
+/// Statistics for the most recently completed finds (up to 1024), including finds
+/// made by the node itself (ex: refreshing buckets, storing announcements).
+#[derive(Serialize, Deserialize, JsonSchema, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub struct FindStats {
+    pub samples: usize,
+    pub latency_p50_ms: Option<u64>,
+    pub latency_p95_ms: Option<u64>,
+    pub latency_histogram: Vec<FindLatencyBucket>,
+    /// Most hops to any node that answered
+    pub hops_p50: Option<usize>,
+    pub hops_p95: Option<usize>,
+    /// Share of finds where every queried node answered
+    pub converged_ratio: Option<f64>,
+    /// Share of finds completed after some queried nodes didn't answer in time
+    pub timed_out_ratio: Option<f64>,
+    /// Share of finds completed early because too many were in progress
+    pub evicted_ratio: Option<f64>,
+    /// Share of identity finds that found an announcement
+    pub value_found_ratio: Option<f64>,
+}
+
+fn percentile<T: Copy>(sorted: &[T], p: f64) -> Option<T> {
+    if sorted.is_empty() {
+        return None;
+    }
+    return Some(sorted[((sorted.len() - 1) as f64 * p).round() as usize]);
+}
+
+fn ratio(count: usize, total: usize) -> Option<f64> {
+    if total == 0 {
+        return None;
+    }
+    return Some(count as f64 / total as f64);
+}
+
+fn find_stats(samples: &VecDeque<FindSample>) -> FindStats {
+    let mut latencies = samples.iter().map(|s| s.latency.as_millis() as u64).collect::<Vec<_>>();
+    latencies.sort();
+    let mut hops = samples.iter().map(|s| s.hops).collect::<Vec<_>>();
+    hops.sort();
+    let mut histogram =
+        FIND_LATENCY_BOUNDS_MS
+            .iter()
+            .map(|b| Some(*b))
+            .chain([None])
+            .map(|b| FindLatencyBucket {
+                le_ms: b,
+                count: 0,
+            })
+            .collect::<Vec<_>>();
+    for l in &latencies {
+        let i = FIND_LATENCY_BOUNDS_MS.iter().position(|b| l <= b).unwrap_or(FIND_LATENCY_BOUNDS_MS.len());
+        histogram[i].count += 1;
+    }
+    let outcome_ratio = |o: FindOutcome| ratio(samples.iter().filter(|s| s.outcome == o).count(), samples.len());
+    return FindStats {
+        samples: samples.len(),
+        latency_p50_ms: percentile(&latencies, 0.5),
+        latency_p95_ms: percentile(&latencies, 0.95),
+        latency_histogram: histogram,
+        hops_p50: percentile(&hops, 0.5),
+        hops_p95: percentile(&hops, 0.95),
+        converged_ratio: outcome_ratio(FindOutcome::Converged),
+        timed_out_ratio: outcome_ratio(FindOutcome::TimedOut),
+        evicted_ratio: outcome_ratio(FindOutcome::Evicted),
+        value_found_ratio: ratio(
+            samples.iter().filter(|s| s.value_found == Some(true)).count(),
+            samples.iter().filter(|s| s.value_found.is_some()).count(),
+        ),
+    };
+}
+
+#[cfg(test)]
+mod test_find_stats {
+    use super::*;
+
+    fn sample(ms: u64, hops: usize, outcome: FindOutcome, value_found: Option<bool>) -> FindSample {
+        return FindSample {
+            latency: std::time::Duration::from_millis(ms),
+            hops: hops,
+            outcome: outcome,
+            value_found: value_found,
+        };
+    }
+
+    #[test]
+    fn test_empty() {
+        let stats = find_stats(&VecDeque::new());
+        assert_eq!(stats.samples, 0);
+        assert_eq!(stats.latency_p50_ms, None);
+        assert_eq!(stats.converged_ratio, None);
+        assert!(stats.latency_histogram.iter().all(|b| b.count == 0));
+    }
+
+    #[test]
+    fn test_stats() {
+        let samples = [
+            sample(5, 1, FindOutcome::Converged, None),
+            sample(40, 2, FindOutcome::Converged, Some(true)),
+            sample(60, 2, FindOutcome::Converged, Some(false)),
+            sample(9000, 3, FindOutcome::TimedOut, Some(true)),
+        ].into_iter().collect::<VecDeque<_>>();
+        let stats = find_stats(&samples);
+        assert_eq!(stats.samples, 4);
+        assert_eq!(stats.latency_p50_ms, Some(60));
+        assert_eq!(stats.latency_p95_ms, Some(9000));
+        assert_eq!(stats.latency_histogram[0].count, 1);
+        assert_eq!(stats.latency_histogram[2].count, 1);
+        assert_eq!(stats.latency_histogram[3].count, 1);
+        assert_eq!(stats.latency_histogram.last().unwrap().count, 1);
+        assert_eq!(stats.hops_p50, Some(2));
+        assert_eq!(stats.converged_ratio, Some(0.75));
+        assert_eq!(stats.timed_out_ratio, Some(0.25));
+        assert_eq!(stats.evicted_ratio, Some(0.));
+        assert!((stats.value_found_ratio.unwrap() - 2. / 3.).abs() < 1e-9);
+    }
+}
+
+/// Whether the routing table changed enough between two snapshots that stored
+/// values may no longer be held by the nodes closest to them.
+fn rebalance_needed(old: &HashMap<NodeIdentity, bool>, new: &HashMap<NodeIdentity, bool>) -> bool {
+    let mut changed = old.keys().filter(|i| !new.contains_key(i)).count();
+    for (ident, unresponsive) in new {
+        if old.get(ident) != Some(unresponsive) {
+            changed += 1;
+        }
+    }
+    return changed >= REBALANCE_CHANGE_MIN && changed as f64 >= old.len() as f64 * REBALANCE_CHANGE_FRACTION;
+}
+
+#[cfg(test)]
+mod test_rebalance {
+    use super::*;
+
+    fn table(n: usize) -> HashMap<NodeIdentity, bool> {
+        return (0 .. n).map(|_| (NodeIdentity::new().0, false)).collect();
+    }
+
+    #[test]
+    fn test_unchanged() {
+        let old = table(64);
+        assert!(!rebalance_needed(&old, &old));
+    }
+
+    #[test]
+    fn test_mass_loss() {
+        let old = table(64);
+        let new = old.iter().skip(32).map(|(k, v)| (*k, *v)).collect();
+        assert!(rebalance_needed(&old, &new));
+    }
+
+    #[test]
+    fn test_unresponsive() {
+        let old = table(64);
+        let new = old.iter().enumerate().map(|(i, (k, v))| (*k, *v || i < 16)).collect();
+        assert!(rebalance_needed(&old, &new));
+    }
+
+    #[test]
+    fn test_small_change() {
+        let old = table(64);
+        let new = old.iter().skip(4).map(|(k, v)| (*k, *v)).collect();
+        assert!(!rebalance_needed(&old, &new));
+    }
+
+    #[test]
+    fn test_small_table() {
+        let old = table(6);
+        assert!(!rebalance_needed(&old, &HashMap::new()));
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct HealthDetail {
+    pub responsive_neighbors: usize,
+    pub unresponsive_neighbors: usize,
+    pub active_finds: usize,
+    pub active_pings: usize,
+    /// Unverified nodes waiting to complete a challenge before being added to the
+    /// routing table
+    pub quarantined_nodes: usize,
+    /// Challenge responses rejected due to a bad signature or a source address not
+    /// matching the claimed address
+    pub quarantine_rejections: usize,
+    /// Find and challenge responses rejected because their stamp was outside the
+    /// accepted window, or they were unstamped v1 responses from a node that speaks v2
+    /// (possibly replayed)
+    pub stale_rejections: usize,
+    /// Quarantined nodes evicted or not quarantined because the quarantine was full
+    pub quarantine_evictions: usize,
+    /// Nodes added without a challenge because they answered one at the same address
+    /// recently (see `verified_peer_window`)
+    pub challenges_skipped: usize,
+    /// Finds whose first hop included the fastest neighbors because the goal is far
+    /// from this node (see `proximity_index`)
+    pub proximity_seeded_finds: usize,
+    /// Finds completed early because too many finds were in progress
+    pub find_evictions: usize,
+    /// Pings abandoned because too many pings were in progress
+    pub ping_evictions: usize,
+    /// Pungs ignored because they didn't match the ping's nonce or weren't signed by
+    /// the pinged node
+    pub ping_rejections: usize,
+    /// Inconsistencies between the routing table and its address index found and
+    /// fixed by the periodic routing table check. Non-zero means there's a bug.
+    pub routing_repairs: usize,
+    /// Neighbors removed for being unresponsive too long (see
+    /// `prune_unresponsive_after`)
+    pub peers_pruned: usize,
+    /// Removed neighbors currently refused if they're seen again (see
+    /// `pruned_peer_memory`)
+    pub recently_pruned: usize,
+    /// How far other nodes' clocks are estimated to be ahead of this node's clock
+    /// (negative if behind), in milliseconds, from the stamps on their messages. Not
+    /// set until enough neighbors have been heard from.
+    pub clock_skew_ms: Option<i64>,
+    /// Whether `clock_skew_ms` is more than half of `clock_skew_tolerance`. Messages
+    /// and announcements from other nodes are likely being rejected, check the
+    /// system clock (ex: NTP).
+    pub clock_skew_warning: bool,
+    /// Announcements currently stored for other nodes
+    pub stored_announcements: usize,
+    /// Stored announcements dropped because the store was full
+    pub store_evictions: usize,
+    /// Announcements rejected because their signature was bad or their content
+    /// couldn't be parsed, from store requests, find responses, or local publishers
+    pub announcement_rejections: usize,
+    /// Store messages queued for close neighbors that stopped responding recently,
+    /// to resend if they respond again
+    pub queued_store_retries: usize,
+    /// Queued store messages resent after the neighbor responded again
+    pub store_retries_sent: usize,
+    /// Queued store messages dropped because they expired or the queue was full
+    pub store_retries_dropped: usize,
+    /// Whether this node declines store requests from other nodes
+    pub no_store: bool,
+    /// Neighbors known to decline store requests
+    pub no_store_neighbors: usize,
+    /// Neighbors messages are sent to over QUIC, if QUIC is enabled
+    pub quic_neighbors: usize,
+    /// Messages sent as datagrams after sending over QUIC failed
+    pub quic_fallbacks: usize,
+    /// Whether this node is reached through a relay
+    pub relayed: bool,
+    /// Neighbors that advertised being reached through a relay
+    pub relayed_neighbors: usize,
+    /// Neighbors by the highest protocol version they support. Neighbors that haven't
+    /// advertised versions are counted as v1.
+    pub neighbor_versions: BTreeMap<VerInt, usize>,
+    /// Routing table changes in the most recent snapshot interval, if snapshots are
+    /// enabled
+    pub last_churn: Option<ChurnSummary>,
+    /// When stored values were last re-replicated after a large routing table change
+    pub last_rebalance: Option<DateTime<Utc>>,
+    pub bucket_balance: BucketBalance,
+    pub finds: FindStats,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CensusReplication {
+    pub identity: Identity,
+    /// Number of nodes found near the identity that hold the announcement
+    pub holders: usize,
+    /// Number of nodes found near the identity
+    pub nearest: usize,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct Census {
+    pub samples: usize,
+    /// Estimated from the distance to the nearest nodes at random coordinates
+    pub estimated_network_size: u64,
+    pub routing_table_nodes: usize,
+    pub nonempty_buckets: usize,
+    /// Average nodes per non-empty bucket in the routing table
+    pub average_bucket_fill: f64,
+    pub replication: Vec<CensusReplication>,
+}
+
+/// Message and byte counts in each direction.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub struct TrafficCounts {
+    pub sent_messages: u64,
+    pub sent_bytes: u64,
+    pub received_messages: u64,
+    pub received_bytes: u64,
+}
+
+impl TrafficCounts {
+    fn add(&mut self, other: &TrafficCounts) {
+        self.sent_messages += other.sent_messages;
+        self.sent_bytes += other.sent_bytes;
+        self.received_messages += other.received_messages;
+        self.received_bytes += other.received_bytes;
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PeerTraffic {
+    /// The peer's node identity if it was in the routing table, otherwise its
+    /// address. Traffic with peers beyond the tracking limit is counted as `other`.
+    pub peer: String,
+    pub total: TrafficCounts,
+    /// Counts by message type. Packets that couldn't be parsed are counted as
+    /// `invalid`.
+    pub messages: BTreeMap<String, TrafficCounts>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct TrafficReport {
+    /// Start of the earliest rollup period included
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub total: TrafficCounts,
+    /// Peers by total bytes sent and received, highest first
+    pub peers: Vec<PeerTraffic>,
+}
+
+impl Node {
+    /// Creates and starts a new node within the task manager. Waits until the socket
+    /// is open. Bootstrapping is asynchronous; you should wait until a sufficient
+    /// number of peers are found before doing anything automatically.
+    ///
+    /// * `bootstrap`: Nodes to connect to to join network. Ignored if restoring persisted
+    ///   data. Ignores own id if present.
+    ///
+    /// * `protected`: Nodes that are never replaced in the routing table, and are
+    ///   contacted again whenever they're unresponsive or missing from it.
+    ///
+    /// * `peers_dir`: A directory of declared peers to reconcile the routing table
+    ///   with at startup and periodically. Pinned peers are treated like `protected`
+    ///   nodes, and tombstoned peers are removed and not added again.
+    ///
+    /// * `cache_dir`: Save state to this file before shutting down to make next startup
+    ///   faster
+    ///
+    /// * `secret_storage`: How to protect the node secret, which is saved in `cache_dir`.
+    ///
+    /// * `churn_interval`: If set, snapshot the routing table at this interval and log
+    ///   the neighbors that joined, left, or flapped since the previous snapshot.
+    ///
+    /// * `max_store`: The maximum number of announcements to store on behalf of other
+    ///   nodes. When full, the least recently received announcements are dropped.
+    ///   Defaults to 65536.
+    ///
+    /// * `no_store`: Run as a client node - decline storing values for other nodes.
+    ///   The node still participates in lookups and stores its own values in the
+    ///   network.
+    ///
+    /// * `store_tolerance`: Reject store requests for values when this node knows of
+    ///   more than the neighborhood size plus this many nodes closer to the value.
+    ///   Defaults to the neighborhood size (8).
+    ///
+    /// * `request_socket_rotate`: If set, send finds and challenges from a separate
+    ///   socket on a random port, replaced at this interval. Responses are only
+    ///   accepted on the socket the request was sent from.
+    ///
+    /// * `packet_workers`: How many received packets to handle concurrently. Packets
+    ///   from the same address are handled in order. Defaults to the number of CPUs.
+    ///
+    /// * `verified_peer_window`: If set, nodes that answered a challenge are added
+    ///   without another challenge when they contact this node again from the same
+    ///   address within this time.
+    ///
+    /// * `proximity_index`: If set, keep an index of this many neighbors that answer
+    ///   finds fastest, regardless of distance, and also send the first hop of finds
+    ///   for goals this node isn't near to the fastest of them.
+    ///
+    /// * `prune_unresponsive_after`: Remove unprotected neighbors that have been
+    ///   unresponsive this long, even with no replacement. Defaults to one day.
+    ///
+    /// * `pruned_peer_memory`: Refuse to re-add removed neighbors for this long.
+    ///   Defaults to one hour.
+    ///
+    /// * `clock_skew_tolerance`: Reject message stamps further than this from the
+    ///   local time, and announcements dated further than this in the future.
+    ///   Defaults to one minute.
+    ///
+    /// * `quic`: Experimental. Also accept QUIC on the node's port, and send messages
+    ///   over QUIC to neighbors that advertise accepting it. Requires the `quic`
+    ///   feature.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        log: &Log,
+        tm: &TaskManager,
+        bind_addr: StrSocketAddr,
+        bootstrap: &[wire::node::latest::NodeInfo],
+        protected: &[wire::node::latest::NodeInfo],
+        peers_dir: Option<PathBuf>,
+        cache_dir: &Path,
+        secret_storage: &NodeSecretStorage,
+        churn_interval: Option<Duration>,
+        max_store: Option<usize>,
+        no_store: bool,
+        store_tolerance: Option<usize>,
+        request_socket_rotate: Option<Duration>,
+        packet_workers: Option<usize>,
+        verified_peer_window: Option<Duration>,
+        proximity_index: Option<usize>,
+        prune_unresponsive_after: Option<Duration>,
+        pruned_peer_memory: Option<Duration>,
+        clock_skew_tolerance: Option<Duration>,
+        quic: bool,
+    ) -> Result<Node, loga::Error> {
+        let sock = {
+            let log = log.fork(ea!(addr = bind_addr));
+            UdpSocket::bind(bind_addr.resolve()?).await.stack_context(&log, "Failed to open node UDP port")?
+        };
+        let request_socket = match request_socket_rotate {
+            Some(rotate) => Some(
+                (sock.local_addr().stack_context(log, "Error getting node socket address")?.ip(), rotate),
+            ),
+            None => None,
+        };
+        return Node::new_with_socket(
+            log,
+            tm,
+            NodeSocket::Udp(Arc::new(sock)),
+            bootstrap,
+            protected,
+            peers_dir,
+            cache_dir,
+            secret_storage,
+            churn_interval,
+            max_store,
+            no_store,
+            store_tolerance,
+            request_socket,
+            packet_workers.unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)),
+            verified_peer_window,
+            proximity_index,
+            prune_unresponsive_after,
+            pruned_peer_memory,
+            clock_skew_tolerance,
+            quic,
+        ).await;
+    }
+
+    /// Like `new`, but sending and receiving datagrams with another transport (ex:
+    /// `transport::RelayTransport`) instead of binding a UDP socket. Peers see
+    /// whatever addresses the transport sends from. Request socket rotation and QUIC
+    /// need a UDP socket so they aren't available.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new_with_transport(
+        log: &Log,
+        tm: &TaskManager,
+        transport: Arc<dyn transport::NodeTransport>,
+        bootstrap: &[wire::node::latest::NodeInfo],
+        protected: &[wire::node::latest::NodeInfo],
+        peers_dir: Option<PathBuf>,
+        cache_dir: &Path,
+        secret_storage: &NodeSecretStorage,
+        churn_interval: Option<Duration>,
+        max_store: Option<usize>,
+        no_store: bool,
+        store_tolerance: Option<usize>,
+        packet_workers: Option<usize>,
+        verified_peer_window: Option<Duration>,
+        proximity_index: Option<usize>,
+        prune_unresponsive_after: Option<Duration>,
+        pruned_peer_memory: Option<Duration>,
+        clock_skew_tolerance: Option<Duration>,
+    ) -> Result<Node, loga::Error> {
+        return Node::new_with_socket(
+            log,
+            tm,
+            NodeSocket::Transport(transport),
+            bootstrap,
+            protected,
+            peers_dir,
+            cache_dir,
+            secret_storage,
+            churn_interval,
+            max_store,
+            no_store,
+            store_tolerance,
+            None,
+            packet_workers.unwrap_or_else(|| std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)),
+            verified_peer_window,
+            proximity_index,
+            prune_unresponsive_after,
+            pruned_peer_memory,
+            clock_skew_tolerance,
+            false,
+        ).await;
+    }
+
+    /// Like `new`, but using a socket on a simulated network.
+    #[cfg(feature = "sim")]
+    pub async fn new_sim(
+        log: &Log,
+        tm: &TaskManager,
+        socket: sim::SimSocket,
+        bootstrap: &[wire::node::latest::NodeInfo],
+        cache_dir: &Path,
+    ) -> Result<Node, loga::Error> {
+        return Node::new_with_socket(
+            log,
+            tm,
+            NodeSocket::Sim(socket),
+            bootstrap,
+            &[],
+            None,
+            cache_dir,
+            &NodeSecretStorage::Plaintext,
+            None,
+            None,
+            false,
+            None,
+            None,
+            1,
+            None,
+            None,
+            None,
+            None,
+            None,
+            false,
+        ).await;
+    }
+
+    /// Like `new`, but on a random loopback port with defaults and a fresh cache dir,
+    /// for tests. Returns the cache dir too, to remove after.
+    #[cfg(test)]
+    async fn new_test(tm: &TaskManager, name: &str) -> (Node, PathBuf) {
+        let cache_dir = std::env::temp_dir().join(format!("spagh-test-node-{}-{}", name, std::process::id()));
+        _ = std::fs::remove_dir_all(&cache_dir);
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        let node =
+            Node::new(
+                &Log::new(),
+                tm,
+                StrSocketAddr::new("127.0.0.1:0"),
+                &[],
+                &[],
+                None,
+                &cache_dir,
+                &NodeSecretStorage::Plaintext,
+                None,
+                None,
+                false,
+                None,
+                None,
+                Some(1),
+                None,
+                None,
+                None,
+                None,
+                None,
+                false,
+            )
+                .await
+                .unwrap();
+        return (node, cache_dir);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn new_with_socket(
+        log: &Log,
+        tm: &TaskManager,
+        sock: NodeSocket,
+        bootstrap: &[wire::node::latest::NodeInfo],
+        protected: &[wire::node::latest::NodeInfo],
+        peers_dir: Option<PathBuf>,
+        cache_dir: &Path,
+        secret_storage: &NodeSecretStorage,
+        churn_interval: Option<Duration>,
+        max_store: Option<usize>,
+        no_store: bool,
+        store_tolerance: Option<usize>,
+        request_socket: Option<(IpAddr, Duration)>,
+        packet_workers: usize,
+        verified_peer_window: Option<Duration>,
+        proximity_index: Option<usize>,
+        prune_unresponsive_after: Option<Duration>,
+        pruned_peer_memory: Option<Duration>,
+        clock_skew_tolerance: Option<Duration>,
+        quic: bool,
+    ) -> Result<Node, loga::Error> {
+        #[cfg(feature = "quic")]
+        let quic = match (&sock, quic) {
+            (NodeSocket::Udp(s), true) => Some(
+                Arc::new(
+                    quic::QuicTransport::new(s.clone()).await.stack_context(log, "Error setting up QUIC transport")?,
+                ),
+            ),
+            _ => None,
+        };
+        #[cfg(not(feature = "quic"))]
+        if quic {
+            return Err(log.err("QUIC is enabled but this build doesn't include QUIC support (the `quic` feature)"));
+        }
+        let mut do_bootstrap = false;
+        let mut initial_buckets = Buckets {
+            buckets: array_init::array_init(|_| vec![]),
+            addrs: HashMap::new(),
+            protected: protected.iter().map(|n| n.ident).collect(),
+            tombstoned: HashSet::new(),
+            unresponsive_since: HashMap::new(),
+            pruned: HashMap::new(),
+        };
+        let db_pool =
+            setup_db(&cache_dir.join("node.sqlite3"), DB_SCHEMA_VERSION, db::migrate)
+                .await
+                .stack_context(log, "Error initializing database")?;
+        let db = db_pool.get().await.stack_context(log, "Error getting database connection")?;
+        let own_secret = secret_storage::load_node_secret(log, &db, secret_storage).await?;
+        let own_ident = own_secret.get_identity();
+        let own_coord = node_ident_coord(&own_ident);
+        {
+            let mut no_neighbors = true;
+            for e in db
+                .interact(|conn| db::neighbors_get(&conn))
+                .await
+                .stack_context(log, "Error interacting with database")?
+                .stack_context(log, "Error retrieving old neighbors")? {
+                let state = match e {
+                    wire::node::NodeState::V1(s) => s,
+                };
+                let ident = state.node.ident;
+                let addr = state.node.address.clone();
+                if !initial_buckets.restore(&own_coord, state) {
+                    log.log_with(
+                        loga::WARN,
+                        "Duplicate neighbor or neighbor address in database, skipping",
+                        ea!(
+                            addr = redact_addr(&addr.0),
+                            ident = redact_id(ident),
+                            other_ident = initial_buckets.addrs.get(&addr.0).map(redact_id).dbg_str()
+                        ),
+                    );
+                    continue;
+                }
+                log.log_with(loga::DEBUG, "Restoring neighbor", ea!(ident = ident, addr = addr));
+                no_neighbors = false;
+            }
+            if no_neighbors {
+                do_bootstrap = true;
+            }
+        }
+        log.log_with(loga::INFO, "Starting", ea!(own_node_ident = redact_id(own_ident)));
+        let (find_timeout_write, find_timeout_recv) = unbounded::<NextFindTimeout>();
+        let (ping_timeout_write, ping_timeout_recv) = unbounded::<NextPingTimeout>();
+        let (challenge_timeout_write, challenge_timeout_recv) = unbounded::<NextChallengeTimeout>();
+        let dir = Node(Arc::new(NodeInner {
+            log: log.clone(),
+            own_ident: node_identity::NodeIdentity::V1(match own_ident {
+                node_identity::NodeIdentity::V1(i) => i,
+            }),
+            own_secret: node_identity::NodeSecret::V1(match own_secret {
+                node_identity::NodeSecret::V1(s) => s,
+            }),
+            own_coord: own_coord,
+            buckets: Mutex::new(initial_buckets),
+            protected: Mutex::new(protected.iter().filter(|n| n.ident != own_ident).cloned().collect()),
+            configured_protected: protected.iter().filter(|n| n.ident != own_ident).cloned().collect(),
+            peers_dir: peers_dir,
+            dirty: AtomicBool::new(do_bootstrap),
+            store: Mutex::new(HashMap::new()),
+            max_store: max_store.unwrap_or(65536),
+            store_evictions: AtomicUsize::new(0),
+            announcement_rejections: AtomicUsize::new(0),
+            store_retries: Mutex::new(StoreRetries::default()),
+            store_retries_sent: AtomicUsize::new(0),
+            store_retries_dropped: AtomicUsize::new(0),
+            no_store: no_store,
+            store_tolerance: store_tolerance.unwrap_or(NEIGHBORHOOD),
+            no_store_peers: Mutex::new(HashSet::new()),
+            peer_versions: Mutex::new(HashMap::new()),
+            #[cfg(feature = "quic")]
+            quic: quic,
+            quic_peers: Mutex::new(HashSet::new()),
+            quic_fallbacks: AtomicUsize::new(0),
+            relayed_peers: Mutex::new(HashSet::new()),
+            mtu_probes: Mutex::new(HashMap::new()),
+            peer_rtts: Mutex::new(HashMap::new()),
+            proximity: proximity_index.map(|max| Mutex::new(ProximityIndex::new(max.max(1)))),
+            proximity_seeded: AtomicUsize::new(0),
+            put_acks: Mutex::new(HashMap::new()),
+            socket: sock,
+            request_socket: Mutex::new(None),
+            next_req_id: AtomicUsize::new(0),
+            find_timeouts: find_timeout_write,
+            find_states: Mutex::new(HashMap::new()),
+            ping_states: Mutex::new(HashMap::new()),
+            challenge_timeouts: challenge_timeout_write,
+            challenge_states: Mutex::new(HashMap::new()),
+            quarantine_rejections: AtomicUsize::new(0),
+            stale_rejections: AtomicUsize::new(0),
+            quarantine_evictions: AtomicUsize::new(0),
+            verified_peers: Mutex::new(VerifiedPeers::default()),
+            verified_peer_window: verified_peer_window.map(|w| w.to_std().unwrap_or_default()),
+            challenges_skipped: AtomicUsize::new(0),
+            find_evictions: AtomicUsize::new(0),
+            ping_evictions: AtomicUsize::new(0),
+            ping_rejections: AtomicUsize::new(0),
+            find_samples: Mutex::new(VecDeque::new()),
+            routing_repairs: AtomicUsize::new(0),
+            prune_unresponsive_after: prune_unresponsive_after
+                .unwrap_or_else(|| Duration::try_days(1).unwrap())
+                .to_std()
+                .unwrap_or_default(),
+            pruned_peer_memory: pruned_peer_memory
+                .unwrap_or_else(|| Duration::try_hours(1).unwrap())
+                .to_std()
+                .unwrap_or_default(),
+            peers_pruned: AtomicUsize::new(0),
+            clock_skew_tolerance: clock_skew_tolerance.unwrap_or_else(default_clock_skew_tolerance),
+            clock_skew: Mutex::new(clock_skew::ClockSkew::default()),
+            last_churn: Mutex::new(None),
+            last_rebalance: Mutex::new(None),
+            rebalances: broadcast::channel(1).0,
+            db_pool: db_pool.clone(),
+            traffic: Mutex::new(TrafficPeriod {
+                start: Utc::now(),
+                peers: HashMap::new(),
+            }),
+        }));
+        if dir.0.peers_dir.is_some() {
+            dir.reconcile_peers().await.stack_context(log, "Error reconciling routing table with peers directory")?;
+        }
+        if do_bootstrap {
+            // Bootstrap nodes are quarantined like any other node, they're added once they
+            // respond to the challenge
+            log.log_with(loga::DEBUG, "No neighbors, bootstrapping", ea!(count = bootstrap.len()));
+            for b in bootstrap {
+                if b.ident == dir.0.own_ident {
+                    continue;
+                }
+                dir.start_challenge(b.ident, &b.address.0, true).await;
+            }
+        }
+
+        // Protected nodes
+        if !dir.0.protected.lock().unwrap().is_empty() || dir.0.peers_dir.is_some() {
+            dir.retry_protected().await;
+            tm.tracked_periodic(
+                "Node - protected node retry",
+                protected_retry_interval().to_std().unwrap(),
+                cap_fn!(()(dir) {
+                    dir.retry_protected().await;
+                }),
+            );
+        }
+        if dir.0.peers_dir.is_some() {
+            tm.tracked_periodic(
+                "Node - peers directory reconcile",
+                peers_reconcile_interval().to_std().unwrap(),
+                cap_fn!(()(log, dir) {
+                    match dir.reconcile_peers().await {
+                        Ok(_) => dir.retry_protected().await,
+                        Err(e) => log_warn_err(&log, e.context("Error reconciling routing table with peers directory")),
+                    }
+                }),
+            );
+        }
+
+        // Periodically save
+        tm.tracked_periodic(
+            "Node - persist state",
+            Duration::try_minutes(10).unwrap().to_std().unwrap(),
+            cap_fn!(()(log, dir, db_pool) {
+                if !dir.0.dirty.swap(false, Ordering::Relaxed) {
+                    return;
+                }
+                let db_pool = db_pool.clone();
+                match async {
+                    db_pool.get().await.context("Error getting db connection")?.interact(move |conn| {
+                        db::neighbors_clear(conn)?;
+                        for bucket in dir.0.buckets.lock().unwrap().buckets.clone().into_iter() {
+                            for n in bucket {
+                                db::neighbors_insert(conn, &wire::node::NodeState::V1(n))?;
+                            }
+                        }
+                        return Ok(()) as Result<_, loga::Error>;
                     }).await??;
                     return Ok(()) as Result<_, loga::Error>;
                 }.await {
                     Ok(_) => { },
-                    Err(e) => log.log_err(loga::WARN, e.context("Failed to persist state")),
+                    Err(e) => log_warn_err(&log, e.context("Failed to persist state")),
+                }
+            }),
+        );
+
+        // Routing table consistency - debug builds fail loudly, release builds fix and
+        // log the inconsistency
+        tm.tracked_periodic(
+            "Node - routing table check",
+            routing_check_interval().to_std().unwrap(),
+            cap_fn!(()(log, dir) {
+                let repairs = dir.0.buckets.lock().unwrap().repair(&dir.0.own_coord);
+                debug_assert_eq!(repairs.total(), 0, "Routing table inconsistent: {:?}", repairs);
+                if repairs.total() == 0 {
+                    return;
+                }
+                dir.0.routing_repairs.fetch_add(repairs.total(), Ordering::Relaxed);
+                dir.0.dirty.store(true, Ordering::Relaxed);
+                log.log_with(
+                    loga::WARN,
+                    "Repaired inconsistent routing table",
+                    ea!(
+                        misplaced_nodes = repairs.misplaced_nodes,
+                        duplicate_nodes = repairs.duplicate_nodes,
+                        conflicting_addrs = repairs.conflicting_addrs,
+                        stale_addrs = repairs.stale_addrs,
+                        missing_addrs = repairs.missing_addrs
+                    ),
+                );
+            }),
+        );
+
+        // Remove neighbors that stopped responding long ago, even if nothing is
+        // waiting to replace them
+        tm.tracked_periodic(
+            "Node - prune stale neighbors",
+            prune_check_interval().to_std().unwrap(),
+            cap_fn!(()(log, dir) {
+                let pruned =
+                    dir
+                        .0
+                        .buckets
+                        .lock()
+                        .unwrap()
+                        .prune_stale(Instant::now(), dir.0.prune_unresponsive_after, dir.0.pruned_peer_memory);
+                let total = pruned.iter().map(|(_, count)| *count).sum::<usize>();
+                if total == 0 {
+                    return;
+                }
+                dir.0.peers_pruned.fetch_add(total, Ordering::Relaxed);
+                dir.0.dirty.store(true, Ordering::Relaxed);
+                log.log_with(
+                    loga::INFO,
+                    "Removed long-unresponsive neighbors",
+                    ea!(
+                        count = total,
+                        buckets = pruned.iter().map(|(b, _)| b.to_string()).collect::<Vec<_>>().join(", ")
+                    ),
+                );
+            }),
+        );
+
+        // Warn if this node's clock seems far from other nodes'
+        tm.tracked_periodic(
+            "Node - clock skew check",
+            clock_skew_check_interval().to_std().unwrap(),
+            cap_fn!(()(log, dir) {
+                let Some(skew) = dir.0.clock_skew.lock().unwrap().estimate(Instant::now()) else {
+                    return;
+                };
+                if clock_skew::skew_exceeds(skew, dir.0.clock_skew_tolerance) {
+                    log_warn_err(
+                        &log,
+                        loga::err_with(
+                            "Local clock seems far from other nodes' clocks, other nodes' messages may be rejected",
+                            ea!(
+                                skew_ms = skew.num_milliseconds(),
+                                tolerance_ms = dir.0.clock_skew_tolerance.num_milliseconds()
+                            ),
+                        ),
+                    );
                 }
             }),
         );
 
+        // Traffic rollups, also saved at shutdown
+        tm.tracked_periodic(
+            "Node - traffic rollup",
+            traffic_rollup_interval().to_std().unwrap(),
+            cap_fn!(()(log, dir) {
+                if let Err(e) = dir.roll_up_traffic().await {
+                    log_warn_err(&log, e.context("Failed to save traffic counts"));
+                }
+            }),
+        );
+        tm.tracked_task("Node - traffic rollup at shutdown", {
+            let log = log.clone();
+            let dir = dir.clone();
+            let tm = tm.clone();
+            async move {
+                tm.until_terminate().await;
+                if let Err(e) = dir.roll_up_traffic().await {
+                    log_warn_err(&log, e.context("Failed to save traffic counts"));
+                }
+            }
+        });
+
+        // Tell neighbors we're leaving
+        tm.tracked_task("Node - goodbye at shutdown", {
+            let dir = dir.clone();
+            let tm = tm.clone();
+            async move {
+                tm.until_terminate().await;
+                dir.send_goodbye().await;
+            }
+        });
+
         // Find timeouts
-        tm.stream("Node - finish timed requests", find_timeout_recv, cap_fn!((e)(dir) {
+        tm.tracked_stream("Node - finish timed requests", find_timeout_recv, cap_fn!((e)(dir) {
             let deadline = e.updated + req_timeout();
             tokio::time::sleep_until(deadline.to_instant()).await;
             let state = {
@@ -479,13 +3280,14 @@ impl Node {
                 state_entry.remove()
             };
             for o in &state.outstanding {
+                dir.record_rtt(&o.node, req_timeout().to_std().unwrap());
                 dir.mark_node_unresponsive(o.node.ident, o.bucket_i, true);
             }
-            dir.complete_state(state).await;
+            dir.complete_state(state, FindOutcome::TimedOut).await;
         }));
 
         // Stored data expiry
-        tm.periodic(
+        tm.tracked_periodic(
             "Node - re-propagate/expire stored data",
             Duration::try_hours(1).unwrap().to_std().unwrap(),
             cap_fn!(()(dir) {
@@ -496,11 +3298,25 @@ impl Node {
                     }
                     return true;
                 });
+
+                // Forget capabilities, versions, and response times of nodes no longer in the
+                // routing table
+                let neighbors = dir.routing_snapshot();
+                dir.0.no_store_peers.lock().unwrap().retain(|n| neighbors.contains_key(n));
+                dir.0.peer_versions.lock().unwrap().retain(|n, _| neighbors.contains_key(n));
+                dir.0.quic_peers.lock().unwrap().retain(|n| neighbors.contains_key(n));
+                dir.0.relayed_peers.lock().unwrap().retain(|n| neighbors.contains_key(n));
+                dir.0.peer_rtts.lock().unwrap().retain(|n, _| neighbors.contains_key(n));
+                if let Some(proximity) = &dir.0.proximity {
+                    proximity.lock().unwrap().retain(|n| neighbors.get(n) == Some(&false));
+                }
+                dir.0.mtu_probes.lock().unwrap().retain(|n, _| neighbors.contains_key(n));
+                dir.0.store_retries.lock().unwrap().retain(|n| neighbors.contains_key(n));
             }),
         );
 
         // Pings
-        tm.periodic(
+        tm.tracked_periodic(
             "Node - neighbor aliveness",
             Duration::try_minutes(10).unwrap().to_std().unwrap(),
             cap_fn!(()(dir, ping_timeout_write) {
@@ -512,26 +3328,116 @@ impl Node {
                             } else {
                                 continue;
                             };
-                        let req_id = dir.0.next_req_id.fetch_add(1, Ordering::Relaxed);
-                        match dir.0.ping_states.lock().unwrap().entry(id.clone()) {
-                            Entry::Occupied(_) => continue,
-                            Entry::Vacant(e) => e.insert(PingState {
-                                req_id: req_id,
-                                bucket_i: leading_zeros,
-                            }),
-                        };
-                        dir.send(&addr.0, wire::node::Protocol::V1(wire::node::latest::Message::Ping)).await;
-                        ping_timeout_write.unbounded_send(NextPingTimeout {
-                            end: Utc::now() + req_timeout(),
-                            key: (id, req_id),
-                        }).unwrap();
+                        dir.start_ping(&ping_timeout_write, id, &addr.0, leading_zeros).await;
                     }
                 }
             }),
         );
 
+        // Store retries - check whether neighbors with queued store messages are back
+        tm.tracked_periodic("Node - store retries", STORE_RETRY_PING_INTERVAL, cap_fn!(()(dir, ping_timeout_write) {
+            let waiting = {
+                let mut retries = dir.0.store_retries.lock().unwrap();
+                let expired = retries.expire(Instant::now());
+                dir.0.store_retries_dropped.fetch_add(expired, Ordering::Relaxed);
+                retries.waiting()
+            };
+            for id in waiting {
+                let (bucket_i, _) = dist(&node_ident_coord(&id), &dir.0.own_coord);
+                let Some(addr) =
+                    dir.0.buckets.lock().unwrap().buckets[bucket_i]
+                        .iter()
+                        .find(|n| n.node.ident == id)
+                        .map(|n| n.node.address.0) else {
+                        continue;
+                    };
+                dir.start_ping(&ping_timeout_write, id, &addr, bucket_i).await;
+            }
+        }));
+
+        // Peer exchange
+        tm.tracked_periodic(
+            "Node - peer exchange",
+            Duration::try_minutes(10).unwrap().to_std().unwrap(),
+            cap_fn!(()(dir) {
+                for peer in dir.get_closest_peers(dir.0.own_coord, NEIGHBORHOOD) {
+                    dir.send_peer_exchange(&peer).await;
+                }
+            }),
+        );
+
+        // Routing table churn
+        if let Some(churn_interval) = churn_interval {
+            let log = log.fork(ea!(subsys = "churn"));
+            let previous = Arc::new(Mutex::new((Utc::now(), dir.routing_snapshot())));
+            tm.tracked_periodic(
+                "Node - routing table churn",
+                churn_interval.to_std().stack_context(&log, "Churn snapshot interval out of range")?,
+                cap_fn!(()(log, dir, previous) {
+                    let now = Utc::now();
+                    let snapshot = dir.routing_snapshot();
+                    let (start, old) = std::mem::replace(&mut *previous.lock().unwrap(), (now, snapshot.clone()));
+                    let mut joined = vec![];
+                    let mut flapped = vec![];
+                    for (ident, unresponsive) in &snapshot {
+                        match old.get(ident) {
+                            Some(old_unresponsive) => {
+                                if old_unresponsive != unresponsive {
+                                    flapped.push(*ident);
+                                }
+                            },
+                            None => {
+                                joined.push(*ident);
+                            },
+                        }
+                    }
+                    let left = old.keys().filter(|i| !snapshot.contains_key(i)).cloned().collect::<Vec<_>>();
+                    let summary = ChurnSummary {
+                        start: start,
+                        end: now,
+                        joined: joined.len(),
+                        left: left.len(),
+                        flapped: flapped.len(),
+                        total: snapshot.len(),
+                    };
+                    log.log_with(
+                        loga::INFO,
+                        "Routing table churn",
+                        ea!(joined = summary.joined, left = summary.left, flapped = summary.flapped, total = summary.total),
+                    );
+                    log.log_with(
+                        loga::DEBUG,
+                        "Routing table churn detail",
+                        ea!(joined = joined.dbg_str(), left = left.dbg_str(), flapped = flapped.dbg_str()),
+                    );
+                    *dir.0.last_churn.lock().unwrap() = Some(summary);
+                }),
+            );
+        }
+
+        // Rebalancing - after large routing table changes (ex: a partition healing)
+        // stored values may not be on the nodes closest to them anymore
+        tm.tracked_periodic("Node - rebalance check", rebalance_check_interval().to_std().unwrap(), {
+            let previous = Arc::new(Mutex::new(dir.routing_snapshot()));
+            cap_fn!(()(log, dir, previous) {
+                let snapshot = dir.routing_snapshot();
+                let old = std::mem::replace(&mut *previous.lock().unwrap(), snapshot.clone());
+                if !rebalance_needed(&old, &snapshot) {
+                    return;
+                }
+                log.log_with(
+                    loga::INFO,
+                    "Large routing table change, re-replicating stored values",
+                    ea!(before = old.len(), after = snapshot.len()),
+                );
+                *dir.0.last_rebalance.lock().unwrap() = Some(Utc::now());
+                _ = dir.0.rebalances.send(());
+                dir.replicate_store().await;
+            })
+        });
+
         // Ping timeouts
-        tm.stream("Node - ping timeouts", ping_timeout_recv, cap_fn!((e)(dir) {
+        tm.tracked_stream("Node - ping timeouts", ping_timeout_recv, cap_fn!((e)(dir, ping_timeout_write) {
             tokio::time::sleep_until(e.end.to_instant()).await;
             let state = {
                 let mut borrowed_states = dir.0.ping_states.lock().unwrap();
@@ -546,14 +3452,39 @@ impl Node {
                 }
                 state_entry.remove()
             };
+
+            // Retry once at the node's address in the other IP family, if it has one
+            if !state.failed_over {
+                let addr =
+                    dir.0.buckets.lock().unwrap().buckets[state.bucket_i]
+                        .iter()
+                        .find(|n| n.node.ident == e.key.0)
+                        .map(|n| n.node.address.0);
+                if let Some(alt) = addr.and_then(|addr| dir.fail_over(&addr)) {
+                    let req_id = dir.0.next_req_id.fetch_add(1, Ordering::Relaxed);
+                    let nonce = dir.ping_nonce(&alt);
+                    dir.0.ping_states.lock().unwrap().insert(e.key.0, PingState {
+                        req_id: req_id,
+                        bucket_i: state.bucket_i,
+                        failed_over: true,
+                        nonce: nonce.clone(),
+                    });
+                    dir.send_ping(&alt, nonce).await;
+                    ping_timeout_write.unbounded_send(NextPingTimeout {
+                        end: Utc::now() + req_timeout(),
+                        key: (e.key.0, req_id),
+                    }).unwrap();
+                    return;
+                }
+            }
             dir.mark_node_unresponsive(e.key.0, state.bucket_i, true);
         }));
 
         // Challenge timeouts
-        tm.stream("Node - challenge timeouts", challenge_timeout_recv, cap_fn!((e)(dir) {
+        tm.tracked_stream("Node - challenge timeouts", challenge_timeout_recv, cap_fn!((e)(dir) {
             tokio::time::sleep_until(e.end.to_instant()).await;
             let mut borrowed_states = dir.0.challenge_states.lock().unwrap();
-            let mut state_entry = match borrowed_states.entry(e.key.0.clone()) {
+            let mut state_entry = match borrowed_states.entry(e.key.0) {
                 Entry::Occupied(s) => s,
                 Entry::Vacant(_) => return,
             };
@@ -566,12 +3497,25 @@ impl Node {
         }));
 
         // Listen loop
-        tm.task("Node - socket", {
+        let workers = PacketWorkers::new(&log.fork(ea!(subsys = "listen")), tm, &dir, packet_workers);
+        #[cfg(feature = "quic")]
+        if let Some(quic) = &dir.0.quic {
+            quic.serve(log, tm, workers.clone());
+        }
+        tm.tracked_task("Node - socket", {
             let log = log.fork(ea!(subsys = "listen"));
             let dir = dir.clone();
             let tm = tm.clone();
+            let workers = workers.clone();
             async move {
-                let mut buf = [0u8; 1024];
+                #[cfg(feature = "quic")]
+                let mut buf = vec![0u8; if dir.0.quic.is_some() {
+                    quic::DATAGRAM_MAX
+                } else {
+                    DATAGRAM_MAX
+                }];
+                #[cfg(not(feature = "quic"))]
+                let mut buf = vec![0u8; DATAGRAM_MAX];
                 loop {
                     let packet = select!{
                         _ = tm.until_terminate() => {
@@ -581,34 +3525,108 @@ impl Node {
                     };
                     match packet {
                         Ok((len, addr)) => {
-                            match match wire::node::Protocol::from_bytes(&buf[..len]) {
-                                Ok(ver) => match dir.handle(ver, &addr).await {
-                                    Ok(()) => Ok(()),
-                                    Err(e) => Err(e),
-                                },
-                                Err(e) => Err(e.context("Failed to bincode deserialize packet")),
-                            } {
-                                Ok(()) => { },
-                                Err(e) => {
-                                    log.log_err(
-                                        loga::DEBUG,
-                                        e.context_with("Received invalid directory message", ea!(addr = addr)),
-                                    );
-                                },
+                            #[cfg(feature = "quic")]
+                            if let Some(quic) = &dir.0.quic {
+                                if quic::is_quic(&buf[..len]) {
+                                    quic.deliver(buf[..len].to_vec(), addr);
+                                    continue;
+                                }
                             }
+                            workers.dispatch(ReceivedPacket {
+                                data: buf[..len].to_vec(),
+                                full: len == buf.len(),
+                                addr: addr,
+                                socket: 0,
+                            }).await;
                         },
                         Err(e) => {
-                            log.log_err(loga::WARN, e.context("Error receiving packet"));
+                            log_warn_err(&log, e.context("Error receiving packet"));
                         },
                     };
                 }
             }
         });
+
+        // Request socket, rotation and listen loop
+        if let Some((bind_ip, rotate)) = request_socket {
+            let current = bind_request_socket(bind_ip, 1).await.stack_context(log, "Error setting up request socket")?;
+            *dir.0.request_socket.lock().unwrap() = Some(current.clone());
+            tm.tracked_task("Node - request socket", {
+                let log = log.fork(ea!(subsys = "request_socket"));
+                let dir = dir.clone();
+                let tm = tm.clone();
+                let workers = workers.clone();
+                let rotate = rotate.to_std().unwrap();
+                async move {
+                    let mut current = current;
+
+                    // Responses to requests sent just before rotating still arrive on the previous
+                    // socket
+                    let mut previous: Option<RequestSocket> = None;
+                    let mut rotate_at = Instant::now() + rotate;
+                    let mut buf = [0u8; DATAGRAM_MAX];
+                    let mut previous_buf = [0u8; DATAGRAM_MAX];
+                    loop {
+                        let (generation, packet) = select!{
+                            _ = tm.until_terminate() => {
+                                return;
+                            }
+                            _ = sleep_until(rotate_at) => {
+                                rotate_at = Instant::now() + rotate;
+                                match bind_request_socket(bind_ip, current.generation + 1).await {
+                                    Ok(next) => {
+                                        log.log_with(
+                                            loga::DEBUG,
+                                            "Rotated request socket",
+                                            ea!(generation = next.generation),
+                                        );
+                                        *dir.0.request_socket.lock().unwrap() = Some(next.clone());
+                                        previous = Some(std::mem::replace(&mut current, next));
+                                    },
+                                    Err(e) => {
+                                        log_warn_err(&log, e.context("Error rotating request socket, keeping current"));
+                                    },
+                                }
+                                continue;
+                            }
+                            p = current.socket.recv_from(&mut buf) => (current.generation, p),
+                            p = async {
+                                match &previous {
+                                    Some(s) => return s.socket.recv_from(&mut previous_buf).await,
+                                    None => return std::future::pending().await,
+                                }
+                            } => (
+                                previous.as_ref().unwrap().generation,
+                                p,
+                            ),
+                        };
+                        let buf = if generation == current.generation {
+                            &buf
+                        } else {
+                            &previous_buf
+                        };
+                        match packet {
+                            Ok((len, addr)) => {
+                                workers.dispatch(ReceivedPacket {
+                                    data: buf[..len].to_vec(),
+                                    full: false,
+                                    addr: addr,
+                                    socket: generation,
+                                }).await;
+                            },
+                            Err(e) => {
+                                log_warn_err(&log, e.context("Error receiving packet on request socket"));
+                            },
+                        };
+                    }
+                }
+            });
+        }
         dir.start_find(FindGoal::Coord(node_ident_coord(&dir.0.own_ident)), None).await;
 
         // If running in a container or at boot, packets may be lost immediately after
         // getting an ip address so do it again in a minute.
-        tm.task("Node - retry startup find once", {
+        tm.tracked_task("Node - retry startup find once", {
             let dir = dir.clone();
             let tm = tm.clone();
             async move {
@@ -625,67 +3643,401 @@ impl Node {
         return Ok(dir);
     }
 
+    /// Number of responsive neighbors reached over IPv4 and IPv6, respectively.
+    pub fn responsive_neighbor_families(&self) -> (usize, usize) {
+        let mut ipv4 = 0;
+        let mut ipv6 = 0;
+        for bucket in &self.0.buckets.lock().unwrap().buckets {
+            for n in bucket {
+                if n.unresponsive {
+                    continue;
+                }
+                match n.node.address.0.ip().to_canonical() {
+                    IpAddr::V4(_) => ipv4 += 1,
+                    IpAddr::V6(_) => ipv6 += 1,
+                }
+            }
+        }
+        return (ipv4, ipv6);
+    }
+
     pub fn health_detail(&self) -> HealthDetail {
         let mut responsive = 0;
         let mut unresponsive = 0;
-        for bucket in self.0.buckets.lock().unwrap().buckets.clone().into_iter() {
+        let mut neighbor_versions = BTreeMap::new();
+        let peer_versions = self.0.peer_versions.lock().unwrap().clone();
+        let buckets = self.0.buckets.lock().unwrap().buckets.clone();
+        let bucket_lens = buckets.iter().map(|b| b.len()).collect::<Vec<_>>();
+        let clock_skew = self.0.clock_skew.lock().unwrap().estimate(Instant::now());
+        for bucket in buckets.into_iter() {
             for n in bucket {
                 if n.unresponsive {
                     unresponsive += 1;
                 } else {
                     responsive += 1;
                 }
+                let version =
+                    peer_versions.get(&n.node.ident).and_then(|v| v.iter().max().cloned()).unwrap_or(1);
+                *neighbor_versions.entry(version).or_insert(0) += 1;
             }
         }
         return HealthDetail {
             responsive_neighbors: responsive,
             unresponsive_neighbors: unresponsive,
-            active_challenges: self.0.challenge_states.lock().unwrap().len(),
             active_finds: self.0.find_states.lock().unwrap().len(),
             active_pings: self.0.ping_states.lock().unwrap().len(),
+            quarantined_nodes: self.0.challenge_states.lock().unwrap().len(),
+            quarantine_rejections: self.0.quarantine_rejections.load(Ordering::Relaxed),
+            stale_rejections: self.0.stale_rejections.load(Ordering::Relaxed),
+            quarantine_evictions: self.0.quarantine_evictions.load(Ordering::Relaxed),
+            challenges_skipped: self.0.challenges_skipped.load(Ordering::Relaxed),
+            proximity_seeded_finds: self.0.proximity_seeded.load(Ordering::Relaxed),
+            find_evictions: self.0.find_evictions.load(Ordering::Relaxed),
+            ping_evictions: self.0.ping_evictions.load(Ordering::Relaxed),
+            ping_rejections: self.0.ping_rejections.load(Ordering::Relaxed),
+            routing_repairs: self.0.routing_repairs.load(Ordering::Relaxed),
+            peers_pruned: self.0.peers_pruned.load(Ordering::Relaxed),
+            recently_pruned: {
+                let now = Instant::now();
+                self.0.buckets.lock().unwrap().pruned.values().filter(|until| **until > now).count()
+            },
+            clock_skew_ms: clock_skew.map(|s| s.num_milliseconds()),
+            clock_skew_warning: clock_skew.is_some_and(
+                |s| clock_skew::skew_exceeds(s, self.0.clock_skew_tolerance),
+            ),
+            stored_announcements: self.0.store.lock().unwrap().len(),
+            store_evictions: self.0.store_evictions.load(Ordering::Relaxed),
+            announcement_rejections: self.0.announcement_rejections.load(Ordering::Relaxed),
+            queued_store_retries: self.0.store_retries.lock().unwrap().queue.len(),
+            store_retries_sent: self.0.store_retries_sent.load(Ordering::Relaxed),
+            store_retries_dropped: self.0.store_retries_dropped.load(Ordering::Relaxed),
+            no_store: self.0.no_store,
+            no_store_neighbors: self.0.no_store_peers.lock().unwrap().len(),
+            quic_neighbors: self.0.quic_peers.lock().unwrap().len(),
+            quic_fallbacks: self.0.quic_fallbacks.load(Ordering::Relaxed),
+            relayed: self.0.socket.relayed(),
+            relayed_neighbors: self.0.relayed_peers.lock().unwrap().len(),
+            neighbor_versions: neighbor_versions,
+            last_churn: self.0.last_churn.lock().unwrap().clone(),
+            last_rebalance: *self.0.last_rebalance.lock().unwrap(),
+            bucket_balance: bucket_balance(&bucket_lens),
+            finds: find_stats(&self.0.find_samples.lock().unwrap()),
+        };
+    }
+
+    /// Traffic counts per peer from rollups ending after `since`, plus traffic since
+    /// the last rollup.
+    pub async fn traffic(&self, since: DateTime<Utc>) -> Result<TrafficReport, loga::Error> {
+        let rows =
+            self
+                .0
+                .db_pool
+                .get()
+                .await
+                .context("Error getting db connection")?
+                .interact(move |conn| db::traffic_get_since(conn, since))
+                .await??;
+        let end = Utc::now();
+        let mut start = end;
+        let mut peers = HashMap::<String, BTreeMap<String, TrafficCounts>>::new();
+        for row in rows {
+            start = start.min(row.start);
+            peers.entry(row.peer).or_default().entry(row.message).or_default().add(&TrafficCounts {
+                sent_messages: row.sent_messages as u64,
+                sent_bytes: row.sent_bytes as u64,
+                received_messages: row.received_messages as u64,
+                received_bytes: row.received_bytes as u64,
+            });
+        }
+        {
+            let traffic = self.0.traffic.lock().unwrap();
+            start = start.min(traffic.start);
+            for (peer, messages) in &traffic.peers {
+                let peer_messages = peers.entry(peer.to_string()).or_default();
+                for (message, counts) in messages {
+                    peer_messages.entry(message.to_string()).or_default().add(counts);
+                }
+            }
+        }
+        let mut total = TrafficCounts::default();
+        let mut out = vec![];
+        for (peer, messages) in peers {
+            let mut peer_total = TrafficCounts::default();
+            for counts in messages.values() {
+                peer_total.add(counts);
+            }
+            total.add(&peer_total);
+            out.push(PeerTraffic {
+                peer: peer,
+                total: peer_total,
+                messages: messages,
+            });
+        }
+        out.sort_by_key(|p| std::cmp::Reverse(p.total.sent_bytes + p.total.received_bytes));
+        return Ok(TrafficReport {
+            start: start,
+            end: end,
+            total: total,
+            peers: out,
+        });
+    }
+
+    /// Save the traffic counts since the last rollup and start a new period.
+    async fn roll_up_traffic(&self) -> Result<(), loga::Error> {
+        let end = Utc::now();
+        let period = std::mem::replace(&mut *self.0.traffic.lock().unwrap(), TrafficPeriod {
+            start: end,
+            peers: HashMap::new(),
+        });
+        self.0.db_pool.get().await.context("Error getting db connection")?.interact(move |conn| {
+            for (peer, messages) in period.peers {
+                let peer = peer.to_string();
+                for (message, counts) in messages {
+                    db::traffic_insert(
+                        conn,
+                        period.start,
+                        end,
+                        &peer,
+                        message,
+                        counts.sent_messages as i64,
+                        counts.sent_bytes as i64,
+                        counts.received_messages as i64,
+                        counts.received_bytes as i64,
+                    )?;
+                }
+            }
+            db::traffic_expire(conn, end - traffic_retention())?;
+            return Ok(()) as Result<_, loga::Error>;
+        }).await??;
+        return Ok(());
+    }
+
+    fn count_traffic(&self, addr: &SocketAddr, message: &'static str, bytes: usize, sent: bool) {
+        let peer = if privacy_mode() {
+            TrafficPeer::Other
+        } else {
+            match self.0.buckets.lock().unwrap().addrs.get(addr) {
+                Some(ident) => TrafficPeer::Node(*ident),
+                None => TrafficPeer::Addr(*addr),
+            }
+        };
+        let mut traffic = self.0.traffic.lock().unwrap();
+        let peer = if traffic.peers.len() >= TRAFFIC_PEERS_MAX && !traffic.peers.contains_key(&peer) {
+            TrafficPeer::Other
+        } else {
+            peer
+        };
+        let counts = traffic.peers.entry(peer).or_default().entry(message).or_default();
+        if sent {
+            counts.sent_messages += 1;
+            counts.sent_bytes += bytes as u64;
+        } else {
+            counts.received_messages += 1;
+            counts.received_bytes += bytes as u64;
+        }
+    }
+
+    /// Neighbors in the routing table and whether each is unresponsive.
+    /// Notifications of large routing table changes. Publishers should re-announce
+    /// their values when notified.
+    pub fn subscribe_rebalance(&self) -> broadcast::Receiver<()> {
+        return self.0.rebalances.subscribe();
+    }
+
+    /// Send each stored value to the nodes currently closest to its key.
+    async fn replicate_store(&self) {
+        let store =
+            self
+                .0
+                .store
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| (*k, v.value.clone()))
+                .collect::<Vec<_>>();
+        futures::stream::iter(store).for_each_concurrent(PARALLEL, |(key, value)| async move {
+            let (f, c) = ManualFuture::new();
+            self.start_find(FindGoal::Coord(ident_coord(&key)), Some(c)).await;
+            let res = f.await;
+            self.queue_store_retries(&res.nearest, &key, &value);
+            for nearest in res.nearest {
+                let NearestNodeEntryNode::Node(node) = nearest.node else {
+                    continue;
+                };
+                if self.0.no_store_peers.lock().unwrap().contains(&node.ident) {
+                    continue;
+                }
+                self
+                    .send(
+                        &node.address.0,
+                        wire::node::latest::Message::Store(wire::node::latest::StoreRequest {
+                            key: key,
+                            value: value.clone(),
+                        }),
+                    )
+                    .await;
+            }
+        }).await;
+    }
+
+    fn routing_snapshot(&self) -> HashMap<NodeIdentity, bool> {
+        let mut out = HashMap::new();
+        for bucket in &self.0.buckets.lock().unwrap().buckets {
+            for n in bucket {
+                out.insert(n.node.ident, n.unresponsive);
+            }
+        }
+        return out;
+    }
+
+    /// Estimate network size by looking up `samples` random coordinates, and check
+    /// how many nodes hold announcements for `identities`.
+    pub async fn census(&self, samples: usize, identities: Vec<Identity>) -> Census {
+        let mut size_estimates = vec![];
+        for _ in 0 .. samples {
+            let mut coord = DhtCoord::default();
+            rand::thread_rng().fill_bytes(coord.0.as_mut_slice());
+            let (f, c) = ManualFuture::new();
+            self.start_find(FindGoal::Coord(coord), Some(c)).await;
+            let res = f.await;
+            let Some(farthest) = res.nearest.last() else {
+                continue;
+            };
+
+            // With N evenly distributed nodes, the k-th nearest node is expected at about
+            // k/N of the keyspace
+            let frac =
+                u64::from_be_bytes(farthest.dist.0[..8].try_into().unwrap()) as f64 / (u64::MAX as f64 + 1.);
+            if frac <= 0. {
+                continue;
+            }
+            size_estimates.push(res.nearest.len() as f64 / frac);
+        }
+        let estimated_network_size = if size_estimates.is_empty() {
+            0
+        } else {
+            (size_estimates.iter().sum::<f64>() / size_estimates.len() as f64).round() as u64
+        };
+        let mut routing_table_nodes = 0;
+        let mut nonempty_buckets = 0;
+        for bucket in &self.0.buckets.lock().unwrap().buckets {
+            if bucket.is_empty() {
+                continue;
+            }
+            nonempty_buckets += 1;
+            routing_table_nodes += bucket.len();
+        }
+        let mut replication = vec![];
+        for identity in identities {
+            let (f, c) = ManualFuture::new();
+            self.start_find(FindGoal::Identity(identity), Some(c)).await;
+            let res = f.await;
+            replication.push(CensusReplication {
+                identity: identity,
+                holders: res.holders,
+                nearest: res.nearest.len(),
+            });
+        }
+        return Census {
+            samples: samples,
+            estimated_network_size: estimated_network_size,
+            routing_table_nodes: routing_table_nodes,
+            nonempty_buckets: nonempty_buckets,
+            average_bucket_fill: if nonempty_buckets == 0 {
+                0.
+            } else {
+                routing_table_nodes as f64 / nonempty_buckets as f64
+            },
+            replication: replication,
         };
     }
 
+    /// Look up this node's own id in the network, returning how many other nodes
+    /// responded. Zero means the node can't reach the network.
+    pub async fn probe_lookup(&self) -> usize {
+        let (f, c) = ManualFuture::new();
+        self.start_find(FindGoal::Coord(node_ident_coord(&self.0.own_ident)), Some(c)).await;
+        let res = f.await;
+        return res.nearest.iter().filter(|n| !matches!(n.node, NearestNodeEntryNode::Self_)).count();
+    }
+
     /// Identity of node
     pub fn node_identity(&self) -> node_identity::NodeIdentity {
-        return self.0.own_ident.clone();
+        return self.0.own_ident;
     }
 
     /// Look up a value in the network
     pub async fn get(&self, key: Identity) -> Option<stored::announcement::Announcement> {
+        return self.get_with_hops(key).await.0;
+    }
+
+    /// Look up a value in the network, also returning the most hops (requests chained
+    /// from this node) to any node that responded during the lookup.
+    pub async fn get_with_hops(&self, key: Identity) -> (Option<stored::announcement::Announcement>, usize) {
+        let (f, c) = ManualFuture::new();
+        self.start_find(FindGoal::Identity(key), Some(c)).await;
+        let res = f.await;
+        return (res.value, res.hops);
+    }
+
+    /// Look up a value in the network, also returning how many of the nodes found near
+    /// the identity hold it.
+    pub async fn get_with_replication(
+        &self,
+        key: Identity,
+    ) -> (Option<stored::announcement::Announcement>, CensusReplication) {
         let (f, c) = ManualFuture::new();
         self.start_find(FindGoal::Identity(key), Some(c)).await;
-        return f.await.value;
+        let res = f.await;
+        return (res.value, CensusReplication {
+            identity: key,
+            holders: res.holders,
+            nearest: res.nearest.len(),
+        });
     }
 
     /// Store a value in the network. `value` message must be `ValueBody::to_bytes()`
     /// and `signature` is the signature of those bytes using the corresponding
     /// `IdentitySecret`
-    pub async fn put(
-        &self,
-        key: Identity,
-        value: stored::announcement::Announcement,
-    ) -> Option<stored::announcement::Announcement> {
+    ///
+    /// Waits briefly for the closest nodes to acknowledge storing the value.
+    pub async fn put(&self, key: Identity, value: stored::announcement::Announcement) -> PutResult {
+        let new_announced = match &value {
+            stored::announcement::Announcement::V1(a) => a.verify(&key).map(|a| a.announced),
+        };
+        let Ok(new_announced) = new_announced else {
+            self.0.announcement_rejections.fetch_add(1, Ordering::Relaxed);
+            self
+                .0
+                .log
+                .log_with(
+                    loga::WARN,
+                    "Not storing announcement with bad signature or malformed content",
+                    ea!(identity = redact_id(key)),
+                );
+            return PutResult {
+                found: None,
+                sent: 0,
+                accepted: 0,
+                errors: HashMap::new(),
+            };
+        };
         let (f, c) = ManualFuture::new();
         self.start_find(FindGoal::Identity(key), Some(c)).await;
         let res = f.await;
+        let mut sent = 0;
+        let mut accepted = 0;
+        let mut errors = HashMap::new();
+        let mut pending = HashMap::new();
+        let (ack_write, mut ack_read) = tokio::sync::mpsc::unbounded_channel();
         shed!{
             'skip_store _;
-            match &res.value {
-                Some(accepted) => match accepted {
-                    stored::announcement::Announcement::V1(accepted) => {
-                        let new_announced = match &value {
-                            stored::announcement::Announcement::V1(a) => {
-                                a.parse_unwrap().announced
-                            },
-                        };
-                        if accepted.parse_unwrap().announced >= new_announced {
-                            break 'skip_store;
-                        }
-                    },
-                },
-                _ => (),
+            if let Some(Ok(accepted)) = res.value.as_ref().map(|v| v.parse()) {
+                if accepted.announced >= new_announced {
+                    break 'skip_store;
+                }
             }
+            self.queue_store_retries(&res.nearest, &key, &value);
             for nearest in res.nearest {
                 match nearest.node {
                     NearestNodeEntryNode::Self_ => {
@@ -693,48 +4045,331 @@ impl Node {
                             .0
                             .log
                             .log_with(loga::DEBUG, "Own store request, storing locally", ea!(value = key.dbg_str()));
-                        self.0.store.lock().unwrap().insert(key.clone(), ValueState {
+                        let mut store = self.0.store.lock().unwrap();
+                        store.insert(key, ValueState {
                             value: value.clone(),
                             received: Utc::now(),
                         });
+                        let dropped = trim_store(&mut store, self.0.max_store);
+                        self.0.store_evictions.fetch_add(dropped, Ordering::Relaxed);
+                        sent += 1;
+                        accepted += 1;
                     },
                     NearestNodeEntryNode::Node(node) => {
+                        if self.0.no_store_peers.lock().unwrap().contains(&node.ident) {
+                            continue;
+                        }
+                        if pending.is_empty() {
+                            self.0.put_acks.lock().unwrap().insert(key, ack_write.clone());
+                        }
+                        sent += 1;
+                        pending.insert(node.ident, node.address.0);
                         self
                             .send(
                                 &node.address.0,
-                                wire::node::Protocol::V1(
-                                    wire::node::latest::Message::Store(wire::node::latest::StoreRequest {
-                                        key: key.clone(),
-                                        value: value.clone(),
-                                    }),
-                                ),
+                                wire::node::latest::Message::Store(wire::node::latest::StoreRequest {
+                                    key: key,
+                                    value: value.clone(),
+                                }),
                             )
                             .await;
                     },
                 }
             }
         };
-        return res.value;
+
+        // Wait for acknowledgements
+        if !pending.is_empty() {
+            let deadline = tokio::time::Instant::now() + req_timeout().to_std().unwrap();
+            while !pending.is_empty() {
+                let Ok(Some((sender, addr, stored))) =
+                    tokio::time::timeout_at(deadline, ack_read.recv()).await else {
+                        break;
+                    };
+                if pending.get(&sender) != Some(&addr) {
+                    continue;
+                }
+                pending.remove(&sender);
+                match stored {
+                    Ok(true) => {
+                        accepted += 1;
+                    },
+                    Ok(false) => { },
+                    Err(code) => {
+                        *errors.entry(code).or_insert(0) += 1;
+                    },
+                }
+            }
+            self.0.put_acks.lock().unwrap().remove(&key);
+        }
+        return PutResult {
+            found: res.value,
+            sent: sent,
+            accepted: accepted,
+            errors: errors,
+        };
     }
 
     fn mark_node_unresponsive(&self, key: node_identity::NodeIdentity, bucket_i: usize, unresponsive: bool) {
-        let mut buckets = self.0.buckets.lock().unwrap();
-        let bucket = &mut buckets.buckets[bucket_i];
-        for n in bucket {
-            if n.node.ident == key {
-                n.unresponsive = unresponsive;
+        if self.0.buckets.lock().unwrap().mark_node_unresponsive(&key, bucket_i, unresponsive) {
+            self.0.dirty.store(true, Ordering::Relaxed);
+            if unresponsive {
+                self.0.store_retries.lock().unwrap().mark(&key, true, Instant::now());
+            }
+        }
+    }
+
+    /// Mark a node responsive again, resending any store messages queued while it
+    /// was unresponsive.
+    async fn mark_node_responsive(&self, key: node_identity::NodeIdentity, bucket_i: usize) {
+        self.mark_node_unresponsive(key, bucket_i, false);
+        let resend = self.0.store_retries.lock().unwrap().mark(&key, false, Instant::now());
+        if resend.is_empty() {
+            return;
+        }
+        let Some(addr) =
+            self.0.buckets.lock().unwrap().buckets[bucket_i]
+                .iter()
+                .find(|n| n.node.ident == key)
+                .map(|n| n.node.address.0) else {
+                return;
+            };
+        self
+            .0
+            .log
+            .log_with(
+                loga::DEBUG,
+                "Neighbor responsive again, resending queued stores",
+                ea!(node = key.dbg_str(), count = resend.len()),
+            );
+        self.0.store_retries_sent.fetch_add(resend.len(), Ordering::Relaxed);
+        self
+            .send_batch(
+                &addr,
+                resend
+                    .into_iter()
+                    .map(|(key, value)| wire::node::latest::Message::Store(wire::node::latest::StoreRequest {
+                        key: key,
+                        value: value,
+                    }))
+                    .collect(),
+            )
+            .await;
+    }
+
+    /// Queue store messages for recently unresponsive neighbors that would be among
+    /// the `nearest` nodes to the key, to send if they respond again.
+    fn queue_store_retries(
+        &self,
+        nearest: &[NearestNodeEntry],
+        key: &Identity,
+        value: &stored::announcement::Announcement,
+    ) {
+        let key_coord = ident_coord(key);
+        let farthest = match nearest.len() >= NEIGHBORHOOD {
+            true => nearest.last().map(|e| e.dist),
+            false => None,
+        };
+        let now = Instant::now();
+        let buckets = self.0.buckets.lock().unwrap();
+        let no_store_peers = self.0.no_store_peers.lock().unwrap();
+        let mut retries = self.0.store_retries.lock().unwrap();
+        for bucket in &buckets.buckets {
+            for n in bucket {
+                if !n.unresponsive || no_store_peers.contains(&n.node.ident) ||
+                    !retries.recently_unresponsive(&n.node.ident, now) {
+                    continue;
+                }
+                if let Some(farthest) = farthest {
+                    if dist(&node_ident_coord(&n.node.ident), &key_coord).1 >= farthest {
+                        continue;
+                    }
+                }
+                let dropped = retries.queue(&n.node.ident, key, value, now);
+                self.0.store_retries_dropped.fetch_add(dropped, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Ping a neighbor, marking it unresponsive if it doesn't answer in time.
+    async fn start_ping(
+        &self,
+        ping_timeouts: &UnboundedSender<NextPingTimeout>,
+        id: NodeIdentity,
+        addr: &SocketAddr,
+        bucket_i: usize,
+    ) {
+        let req_id = self.0.next_req_id.fetch_add(1, Ordering::Relaxed);
+        let nonce = self.ping_nonce(addr);
+        {
+            let mut borrowed_states = self.0.ping_states.lock().unwrap();
+            if borrowed_states.len() >= PING_STATES_MAX && !borrowed_states.contains_key(&id) {
+                // Abandon the oldest ping
+                let evict = borrowed_states.iter().min_by_key(|(_, s)| s.req_id).map(|(k, _)| *k).unwrap();
+                borrowed_states.remove(&evict);
+                self.0.ping_evictions.fetch_add(1, Ordering::Relaxed);
+            }
+            match borrowed_states.entry(id) {
+                Entry::Occupied(_) => return,
+                Entry::Vacant(e) => e.insert(PingState {
+                    req_id: req_id,
+                    bucket_i: bucket_i,
+                    failed_over: false,
+                    nonce: nonce.clone(),
+                }),
+            };
+        }
+        self.send_ping(addr, nonce).await;
+        ping_timeouts.unbounded_send(NextPingTimeout {
+            end: Utc::now() + req_timeout(),
+            key: (id, req_id),
+        }).unwrap();
+    }
+
+    /// A nonce for pinging `addr` if the node there supports signed pungs.
+    fn ping_nonce(&self, addr: &SocketAddr) -> Option<Blob> {
+        if self.send_version(addr) < 2 {
+            return None;
+        }
+        return Some(generate_challenge());
+    }
+
+    async fn send_ping(&self, addr: &SocketAddr, nonce: Option<Blob>) {
+        match nonce {
+            Some(nonce) => {
+                self
+                    .send_protocol(addr, wire::node::Protocol::V2(wire::node::latest::Message::PingNonce(nonce)))
+                    .await;
+            },
+            None => {
+                self.send(addr, wire::node::latest::Message::Ping).await;
+            },
+        }
+    }
+
+    /// Mark a neighbor responsive if the pung answers an outstanding ping. Pings with
+    /// a nonce need a signed pung echoing it (`signed_nonce`).
+    async fn handle_pung(&self, log: &Log, sender: NodeIdentity, signed_nonce: Option<Blob>) {
+        let state = {
+            let mut borrowed_states = self.0.ping_states.lock().unwrap();
+            let Entry::Occupied(state_entry) = borrowed_states.entry(sender) else {
                 return;
+            };
+            match (&state_entry.get().nonce, &signed_nonce) {
+                (None, _) => { },
+                (Some(want), Some(got)) if constant_time_eq(want, got) => { },
+                (Some(_), _) => {
+                    log.log_with(loga::DEBUG, "Pung doesn't match ping nonce", ea!(node = sender.dbg_str()));
+                    self.0.ping_rejections.fetch_add(1, Ordering::Relaxed);
+                    return;
+                },
+            }
+            state_entry.remove()
+        };
+        self.mark_node_responsive(sender, state.bucket_i).await;
+    }
+
+    /// Re-read the peers directory and apply it: pinned peers become protected, and
+    /// tombstoned peers are removed from the routing table. On error the previous
+    /// declarations stay in effect.
+    async fn reconcile_peers(&self) -> Result<(), loga::Error> {
+        let Some(peers_dir) = &self.0.peers_dir else {
+            return Ok(());
+        };
+        let declared = peers_dir::read_peers_dir(peers_dir).await?;
+        let mut protected = self.0.configured_protected.clone();
+        for n in declared.pinned {
+            if n.ident == self.0.own_ident || declared.tombstoned.contains(&n.ident) {
+                continue;
             }
+            protected.retain(|p| p.ident != n.ident);
+            protected.push(n);
+        }
+        protected.retain(|p| !declared.tombstoned.contains(&p.ident));
+        let (protected_count, tombstoned_count) = (protected.len(), declared.tombstoned.len());
+        {
+            let mut buckets = self.0.buckets.lock().unwrap();
+            buckets.protected = protected.iter().map(|n| n.ident).collect();
+            if buckets.set_tombstoned(&self.0.own_coord, declared.tombstoned) {
+                self.0.dirty.store(true, Ordering::Relaxed);
+            }
+        }
+        *self.0.protected.lock().unwrap() = protected;
+        self.0.log.log_with(
+            loga::DEBUG,
+            "Reconciled routing table with peers directory",
+            ea!(protected = protected_count, tombstoned = tombstoned_count),
+        );
+        return Ok(());
+    }
+
+    /// Challenge protected nodes that are missing from the routing table or
+    /// unresponsive, at their configured addresses.
+    async fn retry_protected(&self) {
+        let retry = {
+            let buckets = self.0.buckets.lock().unwrap();
+            self.0.protected.lock().unwrap().iter().filter(|n| {
+                let (bucket_i, _) = dist(&node_ident_coord(&n.ident), &self.0.own_coord);
+                return buckets.buckets[bucket_i]
+                    .iter()
+                    .find(|e| e.node.ident == n.ident)
+                    .map(|e| e.unresponsive)
+                    .unwrap_or(true);
+            }).cloned().collect::<Vec<_>>()
+        };
+        for n in retry {
+            self.start_challenge(n.ident, &n.address.0, true).await;
         }
-        self.0.dirty.store(true, Ordering::Relaxed);
     }
 
-    async fn start_challenge(&self, id: node_identity::NodeIdentity, addr: &SocketAddr) {
+    /// Quarantine a node and challenge it at the claimed address. The node is added
+    /// to the routing table once a valid response arrives from that address.
+    ///
+    /// If the quarantine is full the oldest unsolicited node is evicted. If there are
+    /// none, a solicited node evicts the oldest node and an unsolicited node is
+    /// dropped.
+    async fn start_challenge(&self, id: node_identity::NodeIdentity, addr: &SocketAddr, solicited: bool) {
+        // Unsolicited contact shows the node is up, so a recent verification at the same
+        // address is enough
+        if let Some(window) = self.0.verified_peer_window {
+            if !solicited && self.0.verified_peers.lock().unwrap().check(&id, addr, Instant::now(), window) {
+                self.0.challenges_skipped.fetch_add(1, Ordering::Relaxed);
+                self.add_verified_node(wire::node::latest::NodeInfo {
+                    ident: id,
+                    address: SerialAddr(*addr),
+                }).await;
+                return;
+            }
+        }
+
         // store state by key, with futures
         let timeout = Utc::now() + req_timeout();
+        let request_socket = self.request_socket();
         let (challenge, req_id) = {
             let mut borrowed_states = self.0.challenge_states.lock().unwrap();
-            let (challenge, state) = match borrowed_states.entry(id.clone()) {
+            if borrowed_states.len() >= QUARANTINE_MAX && !borrowed_states.contains_key(&id) {
+                let evict =
+                    borrowed_states
+                        .iter()
+                        .filter(|(_, s)| !s.solicited)
+                        .min_by_key(|(_, s)| s.req_id)
+                        .or_else(|| match solicited {
+                            true => borrowed_states.iter().min_by_key(|(_, s)| s.req_id),
+                            false => None,
+                        })
+                        .map(|(k, _)| *k);
+                self.0.quarantine_evictions.fetch_add(1, Ordering::Relaxed);
+                match evict {
+                    Some(k) => {
+                        borrowed_states.remove(&k);
+                    },
+                    None => {
+                        self.0.log.log_with(loga::DEBUG, "Quarantine full, dropping node", ea!(node = id.dbg_str()));
+                        return;
+                    },
+                }
+            }
+            let (challenge, state) = match borrowed_states.entry(id) {
                 Entry::Occupied(_) => {
                     return;
                 },
@@ -747,12 +4382,14 @@ impl Node {
                             ident: id.clone(),
                             address: SerialAddr(addr.clone()),
                         },
+                        request_socket: request_socket.as_ref().map(|s| s.generation).unwrap_or(0),
+                        solicited: solicited,
                     }))
                 },
             };
             (challenge, state.req_id)
         };
-        self.send(addr, wire::node::Protocol::V1(wire::node::latest::Message::Challenge(challenge))).await;
+        self.send_request(&request_socket, addr, wire::node::latest::Message::Challenge(challenge)).await;
         self.0.challenge_timeouts.unbounded_send(NextChallengeTimeout {
             end: timeout,
             key: (id, req_id),
@@ -767,11 +4404,36 @@ impl Node {
 
         // store state by key, with futures
         let updated = Utc::now();
+        let request_socket = self.request_socket();
+        let request_id = current_request_id();
         let mut defer = vec![];
+        let mut evicted = None;
         let req_id = {
             let mut borrowed_states = self.0.find_states.lock().unwrap();
+            if borrowed_states.len() >= FIND_STATES_MAX && !borrowed_states.contains_key(&goal) {
+                // Evict the oldest find, preferring ones nobody is waiting on
+                let evict =
+                    borrowed_states
+                        .iter()
+                        .min_by_key(|(_, s)| (!s.futures.is_empty(), s.updated))
+                        .map(|(k, _)| *k)
+                        .unwrap();
+                self.0.find_evictions.fetch_add(1, Ordering::Relaxed);
+                evicted = borrowed_states.remove(&evict);
+            }
             let state = match borrowed_states.entry(goal) {
                 Entry::Occupied(mut e) => {
+                    if let Some(request_id) = request_id {
+                        self
+                            .0
+                            .log
+                            .log_with(
+                                loga::DEBUG,
+                                "Joining in-progress find",
+                                ea!(req_id = e.get().req_id, request_id = request_id, goal = goal.dbg_str()),
+                            );
+                        e.get_mut().request_ids.push(request_id);
+                    }
                     if let Some(f) = fut {
                         e.get_mut().futures.push(f);
                     }
@@ -803,7 +4465,11 @@ impl Node {
                                     .log_with(
                                         loga::DEBUG,
                                         "Starting find with initial value",
-                                        ea!(value = v.dbg_str(), goal = goal.dbg_str()),
+                                        ea!(
+                                            value = v.dbg_str(),
+                                            goal = goal.dbg_str(),
+                                            request_id = request_id.clone().unwrap_or_default()
+                                        ),
                                     );
                                 Some(v)
                             },
@@ -811,26 +4477,54 @@ impl Node {
                                 self
                                     .0
                                     .log
-                                    .log_with(loga::DEBUG, "Starting find with no value", ea!(goal = goal.dbg_str()));
+                                    .log_with(
+                                        loga::DEBUG,
+                                        "Starting find with no value",
+                                        ea!(goal = goal.dbg_str(), request_id = request_id.clone().unwrap_or_default()),
+                                    );
                                 None
                             },
                         },
                     },
+                    holders: 0,
+                    hops: 0,
+                    started: Instant::now(),
+                    request_ids: request_id.into_iter().collect(),
                     futures: vec![],
                 }),
             };
+            if state.value.is_some() {
+                state.holders += 1;
+            }
             if let Some(f) = fut {
                 state.futures.push(f);
             }
-            let closest_peers = self.get_closest_peers(goal_coord, PARALLEL);
+            let mut closest_peers = self.get_fast_close_peers(goal_coord);
+            if let Some(proximity) = &self.0.proximity {
+                // Far goals are reached through whichever peers answer quickest, the later
+                // hops converge by distance
+                if !self.in_store_neighborhood(&goal_coord) {
+                    let seeds = proximity.lock().unwrap().fastest(PROXIMITY_SEEDS, &closest_peers);
+                    if !seeds.is_empty() {
+                        self.0.proximity_seeded.fetch_add(1, Ordering::Relaxed);
+                    }
+                    closest_peers.extend(seeds);
+                }
+            }
             for p in closest_peers {
                 let challenge = generate_challenge();
                 let (bucket_i, dist) = dist(&node_ident_coord(&p.ident), &goal_coord);
+                let rtt = self.0.peer_rtts.lock().unwrap().get(&p.ident).cloned();
                 state.outstanding.push(OutstandingNodeEntry {
-                    dist: dist,
+                    rank: find_rank(bucket_i, dist, rtt),
                     bucket_i: bucket_i,
                     challenge: challenge.clone(),
                     node: p.clone(),
+                    hops: 1,
+                    request_socket: request_socket.as_ref().map(|s| s.generation).unwrap_or(0),
+                    sent: Instant::now(),
+                    value_omitted: false,
+                    value_fetch: false,
                 });
 
                 struct Defer {
@@ -843,19 +4537,26 @@ impl Node {
                     addr: p.address.0.clone(),
                 });
             }
+            state.outstanding.sort_by_key(|e| e.rank);
             state.req_id
         };
+        if let Some(evicted) = evicted {
+            self
+                .0
+                .log
+                .log_with(loga::DEBUG, "Too many finds, completing oldest early", ea!(goal = evicted.goal.dbg_str()));
+            self.complete_state(evicted, FindOutcome::Evicted).await;
+        }
         for d in defer {
             self
-                .send(
+                .send_request(
+                    &request_socket,
                     &d.addr,
-                    wire::node::Protocol::V1(
-                        wire::node::latest::Message::FindRequest(wire::node::latest::FindRequest {
-                            challenge: d.challenge,
-                            goal: goal,
-                            sender: self.0.own_ident.clone(),
-                        }),
-                    ),
+                    wire::node::latest::Message::FindRequest(wire::node::latest::FindRequest {
+                        challenge: d.challenge,
+                        goal: goal,
+                        sender: self.0.own_ident,
+                    }),
                 )
                 .await;
         }
@@ -877,7 +4578,22 @@ impl Node {
         };
     }
 
-    async fn complete_state(&self, state: FindState) {
+    async fn complete_state(&self, state: FindState, outcome: FindOutcome) {
+        {
+            let mut samples = self.0.find_samples.lock().unwrap();
+            if samples.len() >= FIND_STATS_WINDOW {
+                samples.pop_front();
+            }
+            samples.push_back(FindSample {
+                latency: state.started.elapsed(),
+                hops: state.hops,
+                outcome: outcome,
+                value_found: match state.goal {
+                    FindGoal::Coord(_) => None,
+                    FindGoal::Identity(_) => Some(state.value.is_some()),
+                },
+            });
+        }
         match &state.value {
             Some(v) => self
                 .0
@@ -885,54 +4601,365 @@ impl Node {
                 .log_with(
                     loga::DEBUG,
                     "Completing state with value",
-                    ea!(value = v.dbg_str(), goal = state.goal.dbg_str()),
+                    ea!(
+                        value = v.dbg_str(),
+                        goal = state.goal.dbg_str(),
+                        req_id = state.req_id,
+                        request_ids = state.request_ids.join(","),
+                        hops = state.hops
+                    ),
                 ),
             None => self
                 .0
                 .log
-                .log_with(loga::DEBUG, "Completing state with no value", ea!(goal = state.goal.dbg_str())),
+                .log_with(
+                    loga::DEBUG,
+                    "Completing state with no value",
+                    ea!(
+                        goal = state.goal.dbg_str(),
+                        req_id = state.req_id,
+                        request_ids = state.request_ids.join(","),
+                        hops = state.hops
+                    ),
+                ),
         }
         for f in state.futures {
             f.complete(FindResult {
                 value: state.value.clone(),
                 nearest: state.nearest.clone(),
+                holders: state.holders,
+                hops: state.hops,
             }).await;
         }
     }
 
-    async fn handle_challenge_resp(&self, resp: wire::node::latest::ChallengeResponse) {
-        let log = self.0.log.fork(ea!(action = "challenge_response", from_node_ident = resp.sender.dbg_str()));
+    async fn handle_challenge_resp(
+        &self,
+        sender: NodeIdentity,
+        proof: ChallengeProof,
+        reply_to: &SocketAddr,
+        socket: usize,
+    ) {
+        let log = self.0.log.fork(ea!(action = "challenge_response", from_node_ident = sender.dbg_str()));
+
+        // Lookup request state
+        let node = {
+            let mut borrowed_states = self.0.challenge_states.lock().unwrap();
+            let state_entry = match borrowed_states.entry(sender) {
+                Entry::Occupied(s) => s,
+                Entry::Vacant(_) => {
+                    // Happens normally if outgoing replaced for a better peer and then the request is
+                    // resolved before resp comes back
+                    return;
+                },
+            };
+            let state = state_entry.get();
+
+            // Confirm sender is legit routable, add to own routing table. The state is left
+            // in place on failure so spoofed responses can't cancel the challenge.
+            if *reply_to != state.node.address.0 {
+                log.log_with(
+                    loga::DEBUG,
+                    "Challenge response source doesn't match claimed address",
+                    ea!(claimed = state.node.address, source = reply_to),
+                );
+                self.0.quarantine_rejections.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            if socket != state.request_socket {
+                log.log_with(
+                    loga::DEBUG,
+                    "Challenge response arrived on a different socket than the challenge was sent from",
+                    ea!(want = state.request_socket, got = socket),
+                );
+                self.0.quarantine_rejections.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            match proof {
+                ChallengeProof::V1(signature) => {
+                    if sender.verify(&state.challenge, &signature).is_err() {
+                        log.log(loga::DEBUG, "Bad sender signature");
+                        self.0.quarantine_rejections.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                },
+                ChallengeProof::V2(content) => {
+                    let Ok(content) = content.verify(&sender) else {
+                        log.log(loga::DEBUG, "Bad sender signature");
+                        self.0.quarantine_rejections.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    };
+                    if !constant_time_eq(&content.challenge, &state.challenge) {
+                        log.log(loga::DEBUG, "Challenge response is for a different challenge");
+                        self.0.quarantine_rejections.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    if !self.fresh_stamp(&log, &sender, content.stamp) {
+                        return;
+                    }
+                },
+            }
+            state_entry.remove().node
+        };
+        if let Some(window) = self.0.verified_peer_window {
+            self.0.verified_peers.lock().unwrap().insert(&sender, reply_to, Instant::now(), window);
+        }
+        self.add_verified_node(node).await;
+    }
+
+    /// Add a node that proved it's reachable at the address.
+    async fn add_verified_node(&self, node: wire::node::latest::NodeInfo) {
+        let had_neighbors =
+            self.0.buckets.lock().unwrap().buckets.iter().any(|b| b.iter().any(|n| !n.unresponsive));
+        if self.add_good_node(node.ident, Some(node.clone())) {
+            if !had_neighbors {
+                // First verified neighbor (ex: bootstrap node), find more
+                self.start_find(FindGoal::Coord(self.0.own_coord), None).await;
+            }
+
+            self.transfer_owned_values(&node).await;
+
+            // Help the new node populate its routing table
+            self.send_peer_exchange(&node).await;
+        }
+    }
+
+    /// Send a signed random sample of responsive nodes from the routing table.
+    async fn send_peer_exchange(&self, to: &wire::node::latest::NodeInfo) {
+        let mut nodes = vec![];
+        {
+            let buckets = self.0.buckets.lock().unwrap();
+            for bucket in &buckets.buckets {
+                for state in bucket {
+                    if state.unresponsive || state.node.ident == to.ident {
+                        continue;
+                    }
+                    nodes.push(state.node.clone());
+                }
+            }
+        }
+        if nodes.is_empty() {
+            return;
+        }
+        nodes.shuffle(&mut thread_rng());
+        nodes.truncate(PEER_EXCHANGE_COUNT);
+        self
+            .send(
+                &to.address.0,
+                wire::node::latest::Message::PeerExchange(wire::node::latest::PeerExchange {
+                    sender: self.0.own_ident,
+                    content: <wire
+                    ::node
+                    ::latest
+                    ::BincodeSignature<wire::node::latest::PeerExchangeContent, NodeIdentity>>::sign(
+                        &self.0.own_secret,
+                        wire::node::latest::PeerExchangeContent {
+                            sender: self.0.own_ident,
+                            nodes: nodes,
+                        },
+                    ),
+                }),
+            )
+            .await;
+    }
+
+    async fn handle_peer_exchange(&self, m: wire::node::latest::PeerExchange, reply_to: &SocketAddr) {
+        let log = self.0.log.fork(ea!(action = "peer_exchange", from_node_ident = m.sender.dbg_str()));
+        let Ok(content) = m.content.verify(&m.sender) else {
+            log.log(loga::DEBUG, "Peer exchange has invalid signature");
+            return;
+        };
+        if content.sender != m.sender {
+            log.log(loga::DEBUG, "Peer exchange signed content sender doesn't match sender");
+            return;
+        }
+
+        // Only accept samples from established neighbors
+        {
+            let (bucket_i, _) = dist(&node_ident_coord(&m.sender), &self.0.own_coord);
+            let buckets = self.0.buckets.lock().unwrap();
+            if !buckets.buckets[bucket_i].iter().any(|n| n.node.ident == m.sender && !n.unresponsive) {
+                log.log(loga::DEBUG, "Peer exchange from unknown node, ignoring");
+                return;
+            }
+        }
+        if self.moved(&m.sender, reply_to) {
+            self.start_challenge(m.sender, reply_to, false).await;
+        }
+
+        // Challenge any nodes that would be new before adding them
+        for n in content.nodes.into_iter().take(PEER_EXCHANGE_COUNT) {
+            if self.add_good_node(n.ident, None) || self.new_addr_family(&n.ident, &n.address.0) {
+                self.start_challenge(n.ident, &n.address.0, true).await;
+            }
+        }
+    }
+
+    /// Send a signed goodbye to all responsive neighbors.
+    async fn send_goodbye(&self) {
+        let mut addrs = vec![];
+        {
+            let buckets = self.0.buckets.lock().unwrap();
+            for bucket in &buckets.buckets {
+                for state in bucket {
+                    if state.unresponsive {
+                        continue;
+                    }
+                    addrs.push(state.node.address.0);
+                }
+            }
+        }
+        self.0.log.log_with(loga::DEBUG, "Sending goodbye to neighbors", ea!(count = addrs.len()));
+        let goodbye = wire::node::latest::Goodbye {
+            sender: self.0.own_ident,
+            content: <wire
+            ::node
+            ::latest
+            ::BincodeSignature<wire::node::latest::GoodbyeContent, NodeIdentity>>::sign(
+                &self.0.own_secret,
+                wire::node::latest::GoodbyeContent {
+                    sender: self.0.own_ident,
+                    stamp: Utc::now(),
+                },
+            ),
+        };
+        for addr in addrs {
+            self.send(&addr, wire::node::latest::Message::Goodbye(goodbye.clone())).await;
+        }
+    }
+
+    /// Mark a neighbor that's shutting down as unresponsive. It'll be marked
+    /// responsive again if it answers a later ping.
+    fn handle_goodbye(&self, m: wire::node::latest::Goodbye) {
+        let log = self.0.log.fork(ea!(action = "goodbye", from_node_ident = m.sender.dbg_str()));
+        let Ok(content) = m.content.verify(&m.sender) else {
+            log.log(loga::DEBUG, "Goodbye has invalid signature");
+            return;
+        };
+        if content.sender != m.sender {
+            log.log(loga::DEBUG, "Goodbye signed content sender doesn't match sender");
+            return;
+        }
+        if !self.stamp_within_tolerance(&m.sender, content.stamp) {
+            log.log_with(loga::DEBUG, "Goodbye is stale, ignoring", ea!(stamp = content.stamp.to_rfc3339()));
+            return;
+        }
+        let (bucket_i, _) = dist(&node_ident_coord(&m.sender), &self.0.own_coord);
+        log.log(loga::DEBUG, "Neighbor is shutting down, marking unresponsive");
+        self.0.ping_states.lock().unwrap().remove(&m.sender);
+        self.0.verified_peers.lock().unwrap().forget(&m.sender);
+        self.mark_node_unresponsive(m.sender, bucket_i, true);
+    }
+
+    /// Replicate stored values to a newly added node. Only values for keys where the
+    /// new node is now among the `NEIGHBORHOOD` closest known nodes (including this
+    /// node) are sent.
+    async fn transfer_owned_values(&self, node: &wire::node::latest::NodeInfo) {
+        if self.0.no_store_peers.lock().unwrap().contains(&node.ident) {
+            return;
+        }
+        let node_coord = node_ident_coord(&node.ident);
+        let mut store = vec![];
+        {
+            let lock = self.0.store.lock().unwrap();
+            store.extend(lock.iter().map(|(k, v)| (*k, v.value.clone())));
+        }
+        let mut messages = vec![];
+        for (k, v) in store.into_iter() {
+            let key_coord = ident_coord(&k);
+            let (_, node_dist) = dist(&node_coord, &key_coord);
+            let mut closer = 0;
+            if dist(&self.0.own_coord, &key_coord).1 < node_dist {
+                closer += 1;
+            }
+            closer += self.get_closest_peers(key_coord, NEIGHBORHOOD).iter().filter(|p| {
+                p.ident != node.ident && dist(&node_ident_coord(&p.ident), &key_coord).1 < node_dist
+            }).count();
+            if closer >= NEIGHBORHOOD {
+                continue;
+            }
+            self
+                .0
+                .log
+                .log_with(
+                    loga::DEBUG,
+                    "Transferring value to new close node",
+                    ea!(key = k.dbg_str(), node = node.ident.dbg_str()),
+                );
+            messages.push(wire::node::latest::Message::Store(wire::node::latest::StoreRequest {
+                key: k,
+                value: v,
+            }));
+        }
+        self.send_batch(&node.address.0, messages).await;
+    }
+
+    /// Whether a stamp on a signed message from `sender` is within
+    /// `clock_skew_tolerance` of the current time. The difference is recorded for
+    /// clock skew estimation either way.
+    fn stamp_within_tolerance(&self, sender: &NodeIdentity, stamp: DateTime<Utc>) -> bool {
+        let offset = stamp - Utc::now();
+        self.0.clock_skew.lock().unwrap().observe(Instant::now(), *sender, offset);
+        return offset.abs() <= self.0.clock_skew_tolerance;
+    }
+
+    /// Whether a stamp on a response is within `clock_skew_tolerance` of the current
+    /// time. Rejections are counted.
+    fn fresh_stamp(&self, log: &Log, sender: &NodeIdentity, stamp: DateTime<Utc>) -> bool {
+        if !self.stamp_within_tolerance(sender, stamp) {
+            log.log_with(loga::DEBUG, "Response is stale, rejecting", ea!(stamp = stamp.to_rfc3339()));
+            self.0.stale_rejections.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        return true;
+    }
+
+    /// V1 responses aren't stamped, so they could be replays. Responses are sent in
+    /// the version of the request, so they're only accepted from addresses this node
+    /// still sends v1 to.
+    fn accept_unstamped(&self, log: &Log, addr: &SocketAddr) -> bool {
+        if self.send_version(addr) >= 2 {
+            log.log(loga::DEBUG, "Unstamped v1 response from a node that speaks v2, rejecting");
+            self.0.stale_rejections.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        return true;
+    }
 
-        // Lookup request state
-        let mut borrowed_states = self.0.challenge_states.lock().unwrap();
-        let state_entry = match borrowed_states.entry(resp.sender.clone()) {
-            Entry::Occupied(s) => s,
-            Entry::Vacant(_) => {
-                // Happens normally if outgoing replaced for a better peer and then the request is
-                // resolved before resp comes back
-                return;
+    /// Bump a find's updated time and queue a timeout check for it.
+    fn touch_find(&self, state: &mut FindState) {
+        state.updated = Utc::now();
+        match self.0.find_timeouts.unbounded_send(NextFindTimeout {
+            updated: state.updated,
+            key: (state.goal, state.req_id),
+        }) {
+            Ok(_) => { },
+            Err(e) => {
+                let e = e.into_send_error();
+                if e.is_disconnected() {
+                    // nop
+                } else {
+                    unreachable!();
+                }
             },
         };
-        let state = state_entry.get();
-
-        // Confirm sender is legit routable, add to own routing table
-        if resp.sender.verify(&state.challenge, &resp.signature).is_err() {
-            log.log(loga::DEBUG, "Bad sender signature");
-            return;
-        }
-        let state = state_entry.remove();
-        self.add_good_node(resp.sender.clone(), Some(state.node));
     }
 
-    async fn handle_find_resp(&self, resp: wire::node::latest::FindResponse) {
-        let Ok(content) = resp.content.verify(&resp.sender) else {
-            self.0.log.log(loga::DEBUG, "Find response has invalid signature");
-            return;
-        };
-        let log: Log = self.0.log.fork(ea!(action = "find_response", from_node_ident = resp.sender.dbg_str()));
+    /// Handle a find response whose signature (and stamp, for v2) has already been
+    /// verified.
+    async fn handle_find_resp(
+        &self,
+        sender: NodeIdentity,
+        content: wire::node::v1::FindResponseContent,
+        reply_to: &SocketAddr,
+        socket: usize,
+    ) {
+        let log: Log = self.0.log.fork(ea!(action = "find_response", from_node_ident = sender.dbg_str()));
         let goal;
+        let request_socket = self.request_socket();
         let mut defer_next_req = vec![];
-        let mut transfer_stored_addr: Option<SocketAddr> = None;
+        let mut transfer_node: Option<wire::node::latest::NodeInfo> = None;
+        let mut value_fetch: Option<(Blob, SocketAddr)> = None;
         let state = {
             // Lookup request state, discard if unsolicited (or obsolete) find response
             let mut borrowed_states = self.0.find_states.lock().unwrap();
@@ -947,8 +4974,22 @@ impl Node {
             goal = state.goal;
             let mut outstanding_entry: Option<OutstandingNodeEntry> = None;
             state.outstanding.retain(|e| {
-                if e.node.ident == resp.sender {
-                    if constant_time_eq(&content.challenge, &e.challenge) {
+                if e.node.ident == sender {
+                    if e.node.address.0 != *reply_to {
+                        // The address may have been claimed by a third party, only accept responses
+                        // from the address the request was sent to
+                        log.log_with(
+                            loga::DEBUG,
+                            "Response source doesn't match requested address",
+                            ea!(want = e.node.address, got = reply_to),
+                        );
+                    } else if e.request_socket != socket {
+                        log.log_with(
+                            loga::DEBUG,
+                            "Response arrived on a different socket than the request was sent from",
+                            ea!(want = e.request_socket, got = socket),
+                        );
+                    } else if constant_time_eq(&content.challenge, &e.challenge) {
                         outstanding_entry = Some(e.clone());
                         return false;
                     } else {
@@ -966,23 +5007,33 @@ impl Node {
                 None => {
                     // 1. May have been dropped because there are better candidates
                     //
-                    // 2. Entry skipped because wrong challenge
+                    // 2. Entry skipped because wrong challenge or source address
                     return;
                 },
             };
+            state.hops = state.hops.max(outstanding_entry.hops);
+            self.record_rtt(&outstanding_entry.node, outstanding_entry.sent.elapsed());
+
+            // The value was left out to fit in a datagram, fetch it separately
+            if outstanding_entry.value_omitted && content.value.is_none() && matches!(goal, FindGoal::Identity(_)) {
+                let challenge = generate_challenge();
+                state.outstanding.push(OutstandingNodeEntry {
+                    challenge: challenge.clone(),
+                    sent: Instant::now(),
+                    value_omitted: false,
+                    value_fetch: true,
+                    ..outstanding_entry.clone()
+                });
+                state.outstanding.sort_by_key(|e| e.rank);
+                value_fetch = Some((challenge, outstanding_entry.node.address.0));
+            }
 
             // Confirm sender is legit routable, possibly add to own routing table
             let (_, sender_dist) = dist(&node_ident_coord(&outstanding_entry.node.ident), &self.0.own_coord);
-            if self.add_good_node(outstanding_entry.node.ident.clone(), Some(outstanding_entry.node.clone())) {
-                if !self
-                    .get_closest_peers(self.0.own_coord, NEIGHBORHOOD)
-                    .iter()
-                    .any(|p| dist(&node_ident_coord(&p.ident), &self.0.own_coord).1 < sender_dist) {
-                    // Incidental work; added sender as a close peer, and sender is the closest peer
-                    // so need to replicate all state to it (i.e. it is one of N closest nodes to all
-                    // data on this node)
-                    transfer_stored_addr = Some(outstanding_entry.node.address.0.clone());
-                }
+            if self.add_good_node(outstanding_entry.node.ident, Some(outstanding_entry.node.clone())) {
+                // Incidental work; added sender as a new peer, replicate any state it's now
+                // responsible for
+                transfer_node = Some(outstanding_entry.node.clone());
             }
 
             // The node responded and is legit, add it to the nearest node set
@@ -1027,6 +5078,8 @@ impl Node {
                 }
                 let candidate_hash = node_ident_coord(&n.ident);
                 let (bucket_i, candidate_dist) = dist(&candidate_hash, &goal_coord);
+                let candidate_rank =
+                    find_rank(bucket_i, candidate_dist, self.0.peer_rtts.lock().unwrap().get(&n.ident).cloned());
 
                 // If nearest list is full and found node is farther away than any current nodes,
                 // drop it
@@ -1034,15 +5087,15 @@ impl Node {
                     continue;
                 }
 
-                // If outstanding list is full and found node is farther away than any current
-                // nodes, drop it
+                // If outstanding list is full and found node ranks below all current nodes
+                // (farther away, or slower at a similar distance), drop it
                 let mut replace_outstanding = false;
                 if state.outstanding.len() == PARALLEL {
-                    if candidate_dist >= state.outstanding.last().unwrap().dist {
+                    if candidate_rank >= state.outstanding.last().unwrap().rank {
                         continue;
                     }
 
-                    // Not farther away, we can pop the farther one off and add the found node below
+                    // Ranks higher, we can pop the lowest one off and add the found node below
                     replace_outstanding = true;
                 }
 
@@ -1063,12 +5116,17 @@ impl Node {
                     state.outstanding.pop();
                 }
                 state.outstanding.push(OutstandingNodeEntry {
-                    dist: candidate_dist,
+                    rank: candidate_rank,
                     challenge: challenge.clone(),
                     node: n.clone(),
                     bucket_i,
+                    hops: outstanding_entry.hops + 1,
+                    request_socket: request_socket.as_ref().map(|s| s.generation).unwrap_or(0),
+                    sent: Instant::now(),
+                    value_omitted: false,
+                    value_fetch: false,
                 });
-                state.outstanding.sort_by_key(|e| e.dist);
+                state.outstanding.sort_by_key(|e| e.rank);
 
                 struct Defer {
                     challenge: Blob,
@@ -1082,47 +5140,10 @@ impl Node {
             }
 
             // Process received value
-            if let (Some(value), FindGoal::Identity(goal_identity)) = (content.value, goal) {
-                shed!{
-                    let found_published;
-                    match &value {
-                        stored::announcement::Announcement::V1(found) => {
-                            let Ok(content) = found.verify(&goal_identity) else {
-                                log.log(loga::DEBUG, "Got value with bad signature");
-                                break;
-                            };
-                            found_published = content.announced;
-                        },
-                    }
-                    match &mut state.value {
-                        Some(state_value) => {
-                            let have_published;
-                            match state_value {
-                                stored::announcement::Announcement::V1(have_value) => {
-                                    have_published = have_value.parse_unwrap().announced;
-                                },
-                            }
-                            if have_published >= found_published {
-                                log.log_with(
-                                    loga::DEBUG,
-                                    "Received value older than one we already have",
-                                    ea!(
-                                        have_published = have_published.to_rfc3339(),
-                                        found_published = found_published.to_rfc3339()
-                                    ),
-                                );
-                                break;
-                            }
-                        },
-                        _ => (),
-                    }
-                    log.log_with(
-                        loga::DEBUG,
-                        "Found better value for find, replacing",
-                        ea!(old = state.value.dbg_str(), new = state.value.dbg_str(), goal = state.goal.dbg_str()),
-                    );
-                    state.value = Some(value);
-                };
+            if let Some(value) = content.value {
+                if !accept_find_value(&log, state, value) {
+                    self.0.announcement_rejections.fetch_add(1, Ordering::Relaxed);
+                }
             }
 
             // If done, cleanup or else update timeouts
@@ -1131,65 +5152,266 @@ impl Node {
                 Some(state_entry.remove())
             } else {
                 // New things to do, bump updated time and re-queue
-                state.updated = Utc::now();
-                match self.0.find_timeouts.unbounded_send(NextFindTimeout {
-                    updated: state.updated,
-                    key: (state.goal.clone(), state.req_id),
-                }) {
-                    Ok(_) => { },
-                    Err(e) => {
-                        let e = e.into_send_error();
-                        if e.is_disconnected() {
-                            // nop
-                        } else if e.is_full() {
-                            unreachable!();
-                        } else {
-                            unreachable!();
-                        }
-                    },
-                };
+                self.touch_find(state);
                 None
             }
         };
 
         // Send deferred messages now that locks are released
-        if let Some(addr) = transfer_stored_addr {
-            let mut store = HashMap::new();
-            {
-                let lock = self.0.store.lock().unwrap();
-                store.extend(lock.iter().map(|(k, v)| (k.clone(), v.value.clone())));
-            }
-            for (k, v) in store.into_iter() {
-                self
-                    .send(
-                        &addr,
-                        wire::node::Protocol::V1(wire::node::latest::Message::Store(wire::node::latest::StoreRequest {
-                            key: k,
-                            value: v,
-                        })),
-                    )
-                    .await;
-            }
+        if let Some(node) = transfer_node {
+            self.transfer_owned_values(&node).await;
+            self.send_peer_exchange(&node).await;
         }
         if let Some(s) = state {
-            self.complete_state(s).await;
+            self.complete_state(s, FindOutcome::Converged).await;
         }
         for d in defer_next_req {
             self
-                .send(
+                .send_request(
+                    &request_socket,
                     &d.addr,
-                    wire::node::Protocol::V1(
-                        wire::node::latest::Message::FindRequest(wire::node::latest::FindRequest {
-                            challenge: d.challenge,
-                            goal: goal,
-                            sender: self.0.own_ident.clone(),
-                        }),
-                    ),
+                    wire::node::latest::Message::FindRequest(wire::node::latest::FindRequest {
+                        challenge: d.challenge,
+                        goal: goal,
+                        sender: self.0.own_ident,
+                    }),
+                )
+                .await;
+        }
+        if let (Some((challenge, addr)), FindGoal::Identity(identity)) = (value_fetch, goal) {
+            self
+                .send_request(
+                    &request_socket,
+                    &addr,
+                    wire::node::latest::Message::FindValueRequest(wire::node::latest::FindValueRequest {
+                        sender: self.0.own_ident,
+                        identity: identity,
+                        challenge: challenge,
+                    }),
                 )
                 .await;
         }
     }
 
+    /// A node will leave the value out of its response to a find request - remember
+    /// to fetch it when the response arrives.
+    fn handle_find_value_omitted(
+        &self,
+        m: wire::node::latest::FindValueOmitted,
+        reply_to: &SocketAddr,
+        socket: usize,
+    ) {
+        let mut borrowed_states = self.0.find_states.lock().unwrap();
+        let Some(state) = borrowed_states.get_mut(&FindGoal::Identity(m.identity)) else {
+            return;
+        };
+        for e in &mut state.outstanding {
+            if e.node.ident == m.sender && e.node.address.0 == *reply_to && e.request_socket == socket &&
+                !e.value_fetch &&
+                constant_time_eq(&m.challenge, &e.challenge) {
+                e.value_omitted = true;
+            }
+        }
+    }
+
+    /// Handle a value fetched after it was left out of a find response.
+    async fn handle_find_value_resp(
+        &self,
+        m: wire::node::latest::FindValueResponse,
+        reply_to: &SocketAddr,
+        socket: usize,
+    ) {
+        let log: Log = self.0.log.fork(ea!(action = "find_value_response", from_node_ident = m.sender.dbg_str()));
+        let state = {
+            let mut borrowed_states = self.0.find_states.lock().unwrap();
+            let Entry::Occupied(mut state_entry) = borrowed_states.entry(FindGoal::Identity(m.identity)) else {
+                log.log(loga::DEBUG, "No request state matching response target");
+                return;
+            };
+            let state = state_entry.get_mut();
+            let Some(i) =
+                state
+                    .outstanding
+                    .iter()
+                    .position(
+                        |e| e.value_fetch && e.node.ident == m.sender && e.node.address.0 == *reply_to &&
+                            e.request_socket == socket &&
+                            constant_time_eq(&m.challenge, &e.challenge),
+                    ) else {
+                    log.log(loga::DEBUG, "Unsolicited value response");
+                    return;
+                };
+            state.outstanding.remove(i);
+            if !accept_find_value(&log, state, m.value) {
+                self.0.announcement_rejections.fetch_add(1, Ordering::Relaxed);
+            }
+            if state.outstanding.is_empty() {
+                Some(state_entry.remove())
+            } else {
+                self.touch_find(state);
+                None
+            }
+        };
+        if let Some(s) = state {
+            self.complete_state(s, FindOutcome::Converged).await;
+        }
+    }
+
+    /// Check which datagram sizes reach a neighbor. The largest acknowledged becomes
+    /// the neighbor's `max_datagram`.
+    async fn start_mtu_probe(&self, ident: &NodeIdentity, addr: &SocketAddr) {
+        self.0.mtu_probes.lock().unwrap().insert(*ident, (Instant::now(), 0));
+        let probe = |size: u16, padding: usize| {
+            return wire::node::Protocol::V2(wire::node::latest::Message::MtuProbe(wire::node::latest::MtuProbe {
+                sender: self.0.own_ident,
+                size: size,
+                padding: Blob::new(padding),
+            }));
+        };
+        for size in MTU_PROBE_SIZES {
+            let overhead = probe(size, 0).to_bytes().len();
+            self.send_protocol(addr, probe(size, (size as usize).saturating_sub(overhead))).await;
+        }
+    }
+
+    /// Record a datagram size that reached a neighbor.
+    fn handle_mtu_probe_ack(&self, m: wire::node::latest::MtuProbeAck, reply_to: &SocketAddr) {
+        if !self.known_sender(&m.sender, reply_to) || !MTU_PROBE_SIZES.contains(&m.size) {
+            return;
+        }
+        {
+            let mut probes = self.0.mtu_probes.lock().unwrap();
+            let Some((started, best)) = probes.get_mut(&m.sender) else {
+                return;
+            };
+            if started.elapsed() > req_timeout().to_std().unwrap() || m.size <= *best {
+                return;
+            }
+            *best = m.size;
+        }
+        if self.0.buckets.lock().unwrap().set_max_datagram(&self.0.own_coord, &m.sender, m.size) {
+            self.0.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// The largest datagram to send to a node.
+    fn max_datagram(&self, ident: &NodeIdentity) -> usize {
+        return self
+            .0
+            .buckets
+            .lock()
+            .unwrap()
+            .max_datagram(&self.0.own_coord, ident)
+            .map(|s| s as usize)
+            .unwrap_or(DATAGRAM_MAX);
+    }
+
+    /// Sign a (v2) find response that fits in `max` bytes, leaving out the furthest
+    /// nodes (down to `FIND_RESPONSE_MIN_NODES`) and then the value if necessary.
+    /// Returns whether the value was left out.
+    fn fit_find_response(
+        &self,
+        challenge: &Blob,
+        goal: FindGoal,
+        nodes: Vec<wire::node::latest::NodeInfo>,
+        value: Option<stored::announcement::Announcement>,
+        max: usize,
+    ) -> (wire::node::Protocol, bool) {
+        let mut count = nodes.len();
+        let mut include_value = true;
+        loop {
+            let resp =
+                wire::node::Protocol::V2(
+                    wire::node::latest::Message::FindResponse(wire::node::latest::FindResponse {
+                        sender: self.0.own_ident,
+                        content: <wire
+                        ::node
+                        ::latest
+                        ::BincodeSignature<wire::node::latest::FindResponseContent, NodeIdentity>>::sign(
+                            &self.0.own_secret,
+                            wire::node::latest::FindResponseContent {
+                                challenge: challenge.clone(),
+                                goal: goal,
+                                sender: self.0.own_ident,
+                                nodes: nodes[..count].to_vec(),
+                                value: if include_value {
+                                    value.clone()
+                                } else {
+                                    None
+                                },
+                                stamp: Utc::now(),
+                            },
+                        ),
+                    }),
+                );
+            let omitted = value.is_some() && !include_value;
+            if resp.to_bytes().len() <= max {
+                return (resp, omitted);
+            }
+            if count > FIND_RESPONSE_MIN_NODES {
+                count -= 1;
+                continue;
+            }
+            if value.is_some() && include_value {
+                include_value = false;
+                count = nodes.len();
+                continue;
+            }
+            return (resp, omitted);
+        }
+    }
+
+    /// Whether this node is plausibly one of the nodes that should store values at
+    /// `coord`: it doesn't know of more responsive nodes closer to the coordinate than
+    /// the neighborhood size plus the configured tolerance. Nodes with sparse routing
+    /// tables accept more.
+    fn in_store_neighborhood(&self, coord: &DhtCoord) -> bool {
+        let buckets = self.0.buckets.lock().unwrap();
+        let closer =
+            count_closer(
+                &self.0.own_coord,
+                coord,
+                buckets
+                    .buckets
+                    .iter()
+                    .flatten()
+                    .filter(|s| !s.unresponsive)
+                    .map(|s| node_ident_coord(&s.node.ident)),
+            );
+        return closer < NEIGHBORHOOD + self.0.store_tolerance;
+    }
+
+    /// Update a neighbor's average find response time.
+    fn record_rtt(&self, node: &wire::node::latest::NodeInfo, sample: std::time::Duration) {
+        let rtt = {
+            let mut rtts = self.0.peer_rtts.lock().unwrap();
+            let rtt = rtts.entry(node.ident).or_insert(sample);
+            *rtt = (*rtt * 3 + sample) / 4;
+            *rtt
+        };
+        if let Some(proximity) = &self.0.proximity {
+            // Only verified nodes, in the routing table at this address
+            if self.0.buckets.lock().unwrap().addrs.get(&node.address.0) != Some(&node.ident) {
+                return;
+            }
+            proximity.lock().unwrap().update(node, rtt);
+        }
+    }
+
+    /// The first peers to send a find to: of the `PARALLEL_CANDIDATES` nearest, the
+    /// `PARALLEL` that have answered finds fastest. A slightly farther peer that
+    /// answers quickly usually makes the lookup faster than a closer slow one, and
+    /// later hops still converge on the goal by distance.
+    fn get_fast_close_peers(&self, goal_coord: DhtCoord) -> Vec<wire::node::latest::NodeInfo> {
+        let mut peers = self.get_closest_peers(goal_coord, PARALLEL_CANDIDATES);
+        let rtts = self.0.peer_rtts.lock().unwrap();
+
+        // Peers with similar times stay in distance order (the sort is stable)
+        peers.sort_by_key(|p| rtt_step(rtts.get(&p.ident).cloned()));
+        peers.truncate(PARALLEL);
+        return peers;
+    }
+
     fn get_closest_peers(&self, goal_coord: DhtCoord, count: usize) -> Vec<wire::node::latest::NodeInfo> {
         let buckets = self.0.buckets.lock().unwrap();
         let (bucket_i, _) = dist(&goal_coord, &self.0.own_coord);
@@ -1233,131 +5455,535 @@ impl Node {
         return nodes;
     }
 
-    async fn handle(&self, m: wire::node::Protocol, reply_to: &SocketAddr) -> Result<(), loga::Error> {
+    /// Parse and handle a received packet. `full` if the packet filled the receive
+    /// buffer (and so was probably truncated). `socket` is the request socket
+    /// generation it was received on, or 0 for the listening socket.
+    async fn handle_packet(&self, log: &Log, packet: &[u8], full: bool, addr: &SocketAddr, socket: usize) {
+        match match wire::node::Protocol::from_bytes(packet) {
+            Ok(ver) => {
+                let message = message_type(&ver);
+                self.count_traffic(addr, message, packet.len(), false);
+                if socket != 0 && !is_response(&ver) {
+                    // Only responses are expected here, and answering challenges would have peers
+                    // add this node at the short-lived request socket address
+                    Err(loga::err_with("Ignoring non-response message on request socket", ea!(message = message)))
+                } else {
+                    self.handle(ver, addr, socket).await
+                }
+            },
+            Err(e) => {
+                self.count_traffic(addr, "invalid", packet.len(), false);
+                if full && socket == 0 {
+                    // Probably truncated
+                    self.send_error(addr, ErrorRequest::Unknown, ErrorCode::TooLarge).await;
+                }
+                Err(e.context("Failed to bincode deserialize packet"))
+            },
+        } {
+            Ok(()) => { },
+            Err(e) => {
+                log.log_err(loga::DEBUG, e.context_with("Received invalid directory message", ea!(addr = addr)));
+            },
+        }
+    }
+
+    async fn handle(
+        &self,
+        m: wire::node::Protocol,
+        reply_to: &SocketAddr,
+        socket: usize,
+    ) -> Result<(), loga::Error> {
         let log = self.0.log.fork(ea!(from_addr = reply_to, message = m.dbg_str()));
         log.log(loga::DEBUG, "Received");
+        let version: VerInt = match &m {
+            wire::node::Protocol::V1(_) => 1,
+            wire::node::Protocol::V2(_) => 2,
+        };
         match m {
-            wire::node::Protocol::V1(v1) => match v1 {
+            wire::node::Protocol::V1(wire::node::v1::Message::FindResponse(m)) => {
+                if !self.accept_unstamped(&log, reply_to) {
+                    return Ok(());
+                }
+                let Ok(content) = m.content.verify(&m.sender) else {
+                    log.log(loga::DEBUG, "Find response has invalid signature");
+                    return Ok(());
+                };
+                self.handle_find_resp(m.sender, content, reply_to, socket).await;
+                return Ok(());
+            },
+            wire::node::Protocol::V1(wire::node::v1::Message::ChallengeResponse(m)) => {
+                if !self.accept_unstamped(&log, reply_to) {
+                    return Ok(());
+                }
+                self.handle_challenge_resp(m.sender, ChallengeProof::V1(m.signature), reply_to, socket).await;
+                return Ok(());
+            },
+            m => match match m {
+                wire::node::Protocol::V1(m) => wire::node::latest::Message::from_v1(
+                    m,
+                ).map_err(|m| loga::err_with("Unconvertible v1 message", ea!(message = m.dbg_str())))?,
+                wire::node::Protocol::V2(m) => m,
+            } {
                 wire::node::latest::Message::FindRequest(m) => {
-                    let body = wire::node::latest::FindResponseContent {
-                        challenge: m.challenge,
-                        goal: m.goal,
-                        sender: self.0.own_ident.clone(),
-                        nodes: self.get_closest_peers(match m.goal {
-                            FindGoal::Coord(c) => c,
-                            FindGoal::Identity(i) => ident_coord(&i),
-                        }, NEIGHBORHOOD),
-                        value: shed!{
-                            let FindGoal::Identity(ident) = m.goal else {
-                                break None;
+                    let nodes = self.get_closest_peers(match m.goal {
+                        FindGoal::Coord(c) => c,
+                        FindGoal::Identity(i) => ident_coord(&i),
+                    }, NEIGHBORHOOD);
+                    let value = shed!{
+                        let FindGoal::Identity(ident) = m.goal else {
+                            break None;
+                        };
+                        break self.0.store.lock().unwrap().get(&ident).map(|v| v.value.clone());
+                    };
+
+                    // Reply with the version of the request, since the requester may not know this
+                    // node's versions yet
+                    let resp = match version {
+                        1 => wire::node::Protocol::V1(
+                            wire::node::v1::Message::FindResponse(wire::node::v1::FindResponse {
+                                sender: self.0.own_ident,
+                                content: <wire
+                                ::node
+                                ::v1
+                                ::BincodeSignature<wire::node::v1::FindResponseContent, NodeIdentity>>::sign(
+                                    &self.0.own_secret,
+                                    wire::node::v1::FindResponseContent {
+                                        challenge: m.challenge,
+                                        goal: m.goal,
+                                        sender: self.0.own_ident,
+                                        nodes: nodes,
+                                        value: value,
+                                    },
+                                ),
+                            }),
+                        ),
+                        _ => {
+                            let (resp, omitted) =
+                                self.fit_find_response(&m.challenge, m.goal, nodes, value, self.max_datagram(&m.sender));
+                            if let (true, FindGoal::Identity(identity)) = (omitted, m.goal) {
+                                log.log(loga::DEBUG, "Leaving value out of find response to fit in datagram");
+                                self
+                                    .send_protocol(
+                                        reply_to,
+                                        wire::node::Protocol::V2(
+                                            wire::node::latest::Message::FindValueOmitted(
+                                                wire::node::latest::FindValueOmitted {
+                                                    sender: self.0.own_ident,
+                                                    identity: identity,
+                                                    challenge: m.challenge.clone(),
+                                                },
+                                            ),
+                                        ),
+                                    )
+                                    .await;
+                            }
+                            resp
+                        },
+                    };
+                    self.send_protocol(reply_to, resp).await;
+                    if self.add_good_node(m.sender, None) || self.moved(&m.sender, reply_to) {
+                        self.start_challenge(m.sender, reply_to, false).await;
+                    }
+                },
+                wire::node::latest::Message::FindResponse(m) => {
+                    let Ok(content) = m.content.verify(&m.sender) else {
+                        log.log(loga::DEBUG, "Find response has invalid signature");
+                        return Ok(());
+                    };
+                    if !self.fresh_stamp(&log, &m.sender, content.stamp) {
+                        return Ok(());
+                    }
+                    self.handle_find_resp(m.sender, wire::node::v1::FindResponseContent {
+                        goal: content.goal,
+                        challenge: content.challenge,
+                        sender: content.sender,
+                        nodes: content.nodes,
+                        value: content.value,
+                    }, reply_to, socket).await;
+                },
+                wire::node::latest::Message::Store(m) => {
+                    if self.0.no_store {
+                        log.log_with(loga::DEBUG, "Declining store request", ea!(value = m.key.dbg_str()));
+                        self
+                            .send(
+                                reply_to,
+                                wire::node::latest::Message::StoreDeclined(wire::node::latest::StoreDeclined {
+                                    sender: self.0.own_ident,
+                                    key: m.key,
+                                }),
+                            )
+                            .await;
+                        return Ok(());
+                    }
+                    log.log_with(loga::DEBUG, "Storing", ea!(value = m.key.dbg_str()));
+                    let new_announced;
+                    match &m.value {
+                        stored::announcement::Announcement::V1(value) => {
+                            let Ok(new_content) = value.verify(&m.key) else {
+                                self.0.announcement_rejections.fetch_add(1, Ordering::Relaxed);
+                                self.send_error(reply_to, ErrorRequest::Store(m.key), ErrorCode::BadSignature).await;
+                                return Err(log.err("Store request failed signature validation"));
                             };
-                            break self.0.store.lock().unwrap().get(&ident).map(|v| v.value.clone());
+                            new_announced = new_content.announced;
                         },
+                    }
+                    if new_announced > Utc::now() + self.0.clock_skew_tolerance {
+                        self.send_error(reply_to, ErrorRequest::Store(m.key), ErrorCode::NotStored).await;
+                        return Err(log.err("Store request published date too far in the future"));
+                    }
+                    if !self.in_store_neighborhood(&ident_coord(&m.key)) {
+                        self.send_error(reply_to, ErrorRequest::Store(m.key), ErrorCode::NotNearest).await;
+                        return Err(log.err("Store request for value outside this node's neighborhood"));
+                    }
+                    let (accepted, dropped) = {
+                        let accepted;
+                        let mut store = self.0.store.lock().unwrap();
+                        match store.entry(m.key) {
+                            Entry::Occupied(mut e) => {
+                                let existing_value = &e.get().value;
+
+                                // Replace stored values that can't be parsed
+                                let existing_published = match existing_value.parse() {
+                                    Ok(v) => v.announced,
+                                    Err(_) => {
+                                        self.0.announcement_rejections.fetch_add(1, Ordering::Relaxed);
+                                        DateTime::<Utc>::MIN_UTC
+                                    },
+                                };
+                                if new_announced >= existing_published || existing_value == &m.value {
+                                    e.insert(ValueState {
+                                        value: m.value,
+                                        received: Utc::now(),
+                                    });
+                                    accepted = true;
+                                } else {
+                                    accepted = false;
+                                }
+                            },
+                            Entry::Vacant(e) => {
+                                e.insert(ValueState {
+                                    value: m.value,
+                                    received: Utc::now(),
+                                });
+                                accepted = true;
+                            },
+                        };
+                        let dropped = trim_store(&mut store, self.0.max_store);
+                        (accepted, dropped)
+                    };
+                    if dropped > 0 {
+                        log.log_with(loga::DEBUG, "Store full, dropped oldest values", ea!(count = dropped));
+                        self.0.store_evictions.fetch_add(dropped, Ordering::Relaxed);
+                    }
+                    self
+                        .send(
+                            reply_to,
+                            wire::node::latest::Message::StoreResponse(wire::node::latest::StoreResponse {
+                                sender: self.0.own_ident,
+                                key: m.key,
+                                accepted: accepted,
+                            }),
+                        )
+                        .await;
+                },
+                wire::node::latest::Message::Ping => {
+                    self
+                        .send(
+                            reply_to,
+                            wire::node::latest::Message::Pung(self.0.own_ident),
+                        )
+                        .await;
+                },
+                wire::node::latest::Message::Pung(k) => {
+                    self.handle_pung(&log, k, None).await;
+                },
+                wire::node::latest::Message::Challenge(challenge) => {
+                    if challenge.len() != CHALLENGE_LEN {
+                        return Err(log.err_with("Challenge has wrong length", ea!(length = challenge.len())));
+                    }
+                    let resp = match version {
+                        1 => wire::node::Protocol::V1(
+                            wire::node::v1::Message::ChallengeResponse(wire::node::v1::ChallengeResponse {
+                                sender: self.0.own_ident,
+                                signature: self.0.own_secret.sign(&challenge),
+                            }),
+                        ),
+                        _ => wire::node::Protocol::V2(
+                            wire::node::latest::Message::ChallengeResponse(wire::node::latest::ChallengeResponse {
+                                sender: self.0.own_ident,
+                                content: <wire
+                                ::node
+                                ::latest
+                                ::BincodeSignature<wire::node::latest::ChallengeResponseContent, NodeIdentity>>::sign(
+                                    &self.0.own_secret,
+                                    wire::node::latest::ChallengeResponseContent {
+                                        challenge: challenge,
+                                        stamp: Utc::now(),
+                                    },
+                                ),
+                            }),
+                        ),
                     };
+                    self.send_protocol(reply_to, resp).await;
+                    self
+                        .send(
+                            reply_to,
+                            wire::node::latest::Message::Versions(wire::node::latest::Versions {
+                                sender: self.0.own_ident,
+                                versions: wire::node::Protocol::VERSIONS.to_vec(),
+                            }),
+                        )
+                        .await;
+                    if self.0.no_store {
+                        self
+                            .send(
+                                reply_to,
+                                wire::node::latest::Message::Capabilities(wire::node::latest::Capabilities {
+                                    sender: self.0.own_ident,
+                                    store: false,
+                                }),
+                            )
+                            .await;
+                    }
+                    if version >= 2 && self.quic_enabled() {
+                        self
+                            .send_protocol(
+                                reply_to,
+                                wire::node::Protocol::V2(
+                                    wire::node::latest::Message::Transports(wire::node::latest::Transports {
+                                        sender: self.0.own_ident,
+                                        quic: true,
+                                    }),
+                                ),
+                            )
+                            .await;
+                    }
+                    if version >= 2 && self.0.socket.relayed() {
+                        self
+                            .send_protocol(
+                                reply_to,
+                                wire::node::Protocol::V2(
+                                    wire::node::latest::Message::TransportHints(wire::node::latest::TransportHints {
+                                        sender: self.0.own_ident,
+                                        relayed: true,
+                                    }),
+                                ),
+                            )
+                            .await;
+                    }
+                },
+                wire::node::latest::Message::ChallengeResponse(resp) => {
+                    self.handle_challenge_resp(resp.sender, ChallengeProof::V2(resp.content), reply_to, socket).await;
+                },
+                wire::node::latest::Message::PeerExchange(m) => {
+                    self.handle_peer_exchange(m, reply_to).await;
+                },
+                wire::node::latest::Message::Goodbye(m) => {
+                    self.handle_goodbye(m);
+                },
+                wire::node::latest::Message::StoreDeclined(m) => {
+                    self.set_peer_stores(&m.sender, reply_to, false);
+                    if let Some(acks) = self.0.put_acks.lock().unwrap().get(&m.key) {
+                        _ = acks.send((m.sender, *reply_to, Ok(false)));
+                    }
+                },
+                wire::node::latest::Message::StoreResponse(m) => {
+                    if let Some(acks) = self.0.put_acks.lock().unwrap().get(&m.key) {
+                        _ = acks.send((m.sender, *reply_to, Ok(m.accepted)));
+                    }
+                },
+                wire::node::latest::Message::Capabilities(m) => {
+                    self.set_peer_stores(&m.sender, reply_to, m.store);
+                },
+                wire::node::latest::Message::Versions(m) => {
+                    if self.known_sender(&m.sender, reply_to) {
+                        let probe = m.versions.contains(&2);
+                        merge_peer_versions(
+                            self.0.peer_versions.lock().unwrap().entry(m.sender).or_default(),
+                            m.versions,
+                        );
+                        if probe {
+                            self.start_mtu_probe(&m.sender, reply_to).await;
+                        }
+                    }
+                },
+                wire::node::latest::Message::Transports(m) => {
+                    if self.known_sender(&m.sender, reply_to) {
+                        let mut quic_peers = self.0.quic_peers.lock().unwrap();
+                        if m.quic && self.quic_enabled() {
+                            quic_peers.insert(m.sender);
+                        } else {
+                            quic_peers.remove(&m.sender);
+                        }
+                    }
+                },
+                wire::node::latest::Message::TransportHints(m) => {
+                    if self.known_sender(&m.sender, reply_to) {
+                        let mut relayed_peers = self.0.relayed_peers.lock().unwrap();
+                        if m.relayed {
+                            relayed_peers.insert(m.sender);
+                        } else {
+                            relayed_peers.remove(&m.sender);
+                        }
+                    }
+                },
+                wire::node::latest::Message::MtuProbe(m) => {
                     self
-                        .send(
+                        .send_protocol(
                             reply_to,
-                            wire::node::Protocol::V1(
-                                wire::node::latest::Message::FindResponse(wire::node::latest::FindResponse {
-                                    sender: self.0.own_ident.clone(),
-                                    content: <wire
-                                    ::node
-                                    ::latest
-                                    ::BincodeSignature<wire::node::latest::FindResponseContent, NodeIdentity>>::sign(
-                                        &self.0.own_secret,
-                                        body,
-                                    ),
+                            wire::node::Protocol::V2(
+                                wire::node::latest::Message::MtuProbeAck(wire::node::latest::MtuProbeAck {
+                                    sender: self.0.own_ident,
+                                    size: m.size,
                                 }),
                             ),
                         )
                         .await;
-                    if self.add_good_node(m.sender.clone(), None) {
-                        self.start_challenge(m.sender, reply_to).await;
-                    }
                 },
-                wire::node::latest::Message::FindResponse(m) => {
-                    self.handle_find_resp(m).await;
+                wire::node::latest::Message::MtuProbeAck(m) => {
+                    self.handle_mtu_probe_ack(m, reply_to);
                 },
-                wire::node::latest::Message::Store(m) => {
-                    log.log_with(loga::DEBUG, "Storing", ea!(value = m.key.dbg_str()));
-                    let new_announced;
-                    match &m.value {
-                        stored::announcement::Announcement::V1(value) => {
-                            let Ok(new_content) = value.verify(&m.key) else {
-                                return Err(log.err("Store request failed signature validation"));
-                            };
-                            new_announced = new_content.announced;
-                        },
-                    }
-                    if new_announced > Utc::now() + Duration::try_minutes(1).unwrap() {
-                        return Err(log.err("Store request published date too far in the future"));
-                    }
-                    match self.0.store.lock().unwrap().entry(m.key) {
-                        Entry::Occupied(mut e) => {
-                            let existing_value = &e.get().value;
-                            let existing_published;
-                            match existing_value {
-                                stored::announcement::Announcement::V1(have_value) => {
-                                    existing_published = have_value.parse_unwrap().announced;
-                                },
-                            }
-                            if new_announced >= existing_published {
-                                e.insert(ValueState {
-                                    value: m.value,
-                                    received: Utc::now(),
-                                });
-                            } else if existing_value == &m.value {
-                                e.insert(ValueState {
-                                    value: m.value,
-                                    received: Utc::now(),
-                                });
-                            }
-                        },
-                        Entry::Vacant(e) => {
-                            e.insert(ValueState {
-                                value: m.value,
-                                received: Utc::now(),
-                            });
-                        },
-                    };
+                wire::node::latest::Message::FindValueOmitted(m) => {
+                    self.handle_find_value_omitted(m, reply_to, socket);
                 },
-                wire::node::latest::Message::Ping => {
+                wire::node::latest::Message::FindValueRequest(m) => {
+                    let Some(value) = self.0.store.lock().unwrap().get(&m.identity).map(|v| v.value.clone()) else {
+                        return Ok(());
+                    };
                     self
-                        .send(
+                        .send_protocol(
                             reply_to,
-                            wire::node::Protocol::V1(wire::node::latest::Message::Pung(self.0.own_ident.clone())),
+                            wire::node::Protocol::V2(
+                                wire::node::latest::Message::FindValueResponse(wire::node::latest::FindValueResponse {
+                                    sender: self.0.own_ident,
+                                    identity: m.identity,
+                                    challenge: m.challenge,
+                                    value: value,
+                                }),
+                            ),
                         )
                         .await;
                 },
-                wire::node::latest::Message::Pung(k) => {
-                    let state = match self.0.ping_states.lock().unwrap().entry(k.clone()) {
-                        Entry::Occupied(s) => s.remove(),
-                        Entry::Vacant(_) => return Ok(()),
-                    };
-                    self.mark_node_unresponsive(k, state.bucket_i, false);
+                wire::node::latest::Message::FindValueResponse(m) => {
+                    self.handle_find_value_resp(m, reply_to, socket).await;
                 },
-                wire::node::latest::Message::Challenge(challenge) => {
+                wire::node::latest::Message::PingNonce(nonce) => {
+                    if nonce.len() != CHALLENGE_LEN {
+                        return Err(log.err_with("Ping nonce has wrong length", ea!(length = nonce.len())));
+                    }
                     self
-                        .send(
+                        .send_protocol(
                             reply_to,
-                            wire::node::Protocol::V1(
-                                wire::node::latest::Message::ChallengeResponse(wire::node::latest::ChallengeResponse {
-                                    sender: self.0.own_ident.clone(),
-                                    signature: self.0.own_secret.sign(&challenge),
+                            wire::node::Protocol::V2(
+                                wire::node::latest::Message::SignedPung(wire::node::latest::SignedPung {
+                                    sender: self.0.own_ident,
+                                    content: <wire
+                                    ::node
+                                    ::latest
+                                    ::BincodeSignature<wire::node::latest::PungContent, NodeIdentity>>::sign(
+                                        &self.0.own_secret,
+                                        wire::node::latest::PungContent { nonce: nonce },
+                                    ),
                                 }),
                             ),
                         )
                         .await;
                 },
-                wire::node::latest::Message::ChallengeResponse(resp) => {
-                    self.handle_challenge_resp(resp).await;
+                wire::node::latest::Message::SignedPung(m) => {
+                    let Ok(content) = m.content.verify(&m.sender) else {
+                        log.log(loga::DEBUG, "Bad pung signature");
+                        self.0.ping_rejections.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    };
+                    self.handle_pung(&log, m.sender, Some(content.nonce)).await;
+                },
+                wire::node::latest::Message::Error(m) => {
+                    log.log_with(loga::DEBUG, "Request rejected", ea!(code = m.code.dbg_str()));
+                    match m.request {
+                        ErrorRequest::Store(key) => {
+                            if let Some(acks) = self.0.put_acks.lock().unwrap().get(&key) {
+                                _ = acks.send((m.sender, *reply_to, Err(m.code)));
+                            }
+                        },
+                        ErrorRequest::Find(goal) => {
+                            self.handle_find_error(goal, &m.sender, reply_to, socket).await;
+                        },
+                        ErrorRequest::Unknown => { },
+                    }
                 },
             },
         };
         Ok(())
     }
 
-    /// Add a node, or check if adding a node would be new (returns whether id is new)
+    /// Tell the sender of a request that it was rejected.
+    async fn send_error(&self, addr: &SocketAddr, request: ErrorRequest, code: ErrorCode) {
+        self
+            .send(addr, wire::node::latest::Message::Error(wire::node::latest::ErrorResponse {
+                sender: self.0.own_ident,
+                request: request,
+                code: code,
+            }))
+            .await;
+    }
+
+    /// A node rejected a find request - stop waiting for it rather than waiting for
+    /// the request to time out.
+    async fn handle_find_error(&self, goal: FindGoal, sender: &NodeIdentity, reply_to: &SocketAddr, socket: usize) {
+        let state = {
+            let mut borrowed_states = self.0.find_states.lock().unwrap();
+            let Entry::Occupied(mut state_entry) = borrowed_states.entry(goal) else {
+                return;
+            };
+            let state = state_entry.get_mut();
+            let before = state.outstanding.len();
+
+            // Only accept errors from the address the request was sent to (and on the socket
+            // it was sent from), like responses
+            state
+                .outstanding
+                .retain(|e| e.node.ident != *sender || e.node.address.0 != *reply_to || e.request_socket != socket);
+            if state.outstanding.len() == before || !state.outstanding.is_empty() {
+                return;
+            }
+            state_entry.remove()
+        };
+        self.complete_state(state, FindOutcome::Converged).await;
+    }
+
+    /// Capability and version messages aren't signed, so they're only trusted if they
+    /// come from the address in the routing table for the sender.
+    fn known_sender(&self, sender: &NodeIdentity, addr: &SocketAddr) -> bool {
+        if self.0.buckets.lock().unwrap().addrs.get(addr) != Some(sender) {
+            self
+                .0
+                .log
+                .log_with(
+                    loga::DEBUG,
+                    "Ignoring unsigned message from unknown node or address",
+                    ea!(node = sender.dbg_str(), addr = addr),
+                );
+            return false;
+        }
+        return true;
+    }
+
+    /// Record whether a neighbor accepts store requests.
+    fn set_peer_stores(&self, sender: &NodeIdentity, addr: &SocketAddr, stores: bool) {
+        if !self.known_sender(sender, addr) {
+            return;
+        }
+        let mut peers = self.0.no_store_peers.lock().unwrap();
+        if stores {
+            peers.remove(sender);
+        } else {
+            peers.insert(*sender);
+        }
+    }
+
+    /// Add a node, or check if adding a node would be new (returns whether id is new).
+    /// Only pass `node` once the node has proven it's reachable at the address (by
+    /// responding to a challenge from that address), otherwise use `start_challenge`.
     fn add_good_node(&self, id: node_identity::NodeIdentity, node: Option<wire::node::latest::NodeInfo>) -> bool {
         let log = self.0.log.fork(ea!(activity = "add_good_node", node = id.dbg_str()));
         let log = &log;
@@ -1365,106 +5991,537 @@ impl Node {
             log.log(loga::DEBUG, "Own node id, ignoring");
             return false;
         }
-        let (bucket_i, _) = dist(&node_ident_coord(&id), &self.0.own_coord);
-        let mut buckets = self.0.buckets.lock().unwrap();
-        let buckets = &mut *buckets;
-
-        fn store_addr(
-            log: &Log,
-            buckets: &mut Buckets,
-            own_coord: &DhtCoord,
-            addr: SocketAddr,
-            new_ident: NodeIdentity,
-        ) {
-            if let Some(old) = buckets.addrs.get(&addr) {
-                let (bucket_i, _) = dist(&node_ident_coord(old), own_coord);
-                let bucket = &mut buckets.buckets[bucket_i];
-                for i in 0 .. bucket.len() {
-                    let n = &mut bucket[i];
-                    if &n.node.ident == old {
-                        log.log_with(
-                            loga::DEBUG,
-                            "Replaced node with same addr",
-                            ea!(addr = addr, old_ident = old, new_ident = new_ident),
-                        );
-                        bucket.remove(i);
-                        break;
-                    }
-                }
-            };
-            buckets.addrs.insert(addr, new_ident);
+        let res = self.0.buckets.lock().unwrap().add_good_node(log, &self.0.own_coord, id, node);
+        if res.changed {
+            self.0.dirty.store(true, Ordering::Relaxed);
         }
+        return res.new;
+    }
 
-        let new_node = 'logic : loop {
-            let bucket = &mut buckets.buckets[bucket_i];
-            let mut last_unresponsive: Option<usize> = None;
+    /// The highest protocol version supported by both this node and the node at
+    /// `addr`. Unknown nodes and nodes that haven't advertised versions are assumed
+    /// to only support v1.
+    fn send_version(&self, addr: &SocketAddr) -> VerInt {
+        let Some(ident) = self.0.buckets.lock().unwrap().addrs.get(addr).cloned() else {
+            return 1;
+        };
+        let Some(versions) = self.0.peer_versions.lock().unwrap().get(&ident).cloned() else {
+            return 1;
+        };
+        return versions
+            .into_iter()
+            .filter(|v| wire::node::Protocol::VERSIONS.contains(v))
+            .max()
+            .unwrap_or(1);
+    }
 
-            // Updated or already known
-            for i in 0 .. bucket.len() {
-                let bucket_entry = &mut bucket[i];
-                if bucket_entry.node.ident == id {
-                    if let Some(node) = node {
-                        if bucket_entry.unresponsive {
-                            bucket_entry.unresponsive = false;
-                        }
-                        buckets.addrs.remove(&bucket_entry.node.address.0);
-                        let new_state = wire::node::latest::NodeState {
-                            node: node.clone(),
-                            unresponsive: false,
-                        };
-                        let changed = *bucket_entry == new_state;
-                        *bucket_entry = new_state;
-                        if changed {
-                            self.0.dirty.store(true, Ordering::Relaxed);
-                        }
-                        log.log(loga::DEBUG, "Updated existing node");
-                        store_addr(log, buckets, &self.0.own_coord, node.address.0, node.ident);
-                    }
-                    break 'logic false;
-                }
-                if bucket_entry.unresponsive {
-                    last_unresponsive = Some(i);
-                }
-            }
+    /// If `addr` is a neighbor's current address and the neighbor has an address in
+    /// the other IP family, switch to that and return it.
+    fn fail_over(&self, addr: &SocketAddr) -> Option<SocketAddr> {
+        let (ident, alt) = self.0.buckets.lock().unwrap().fail_over(&self.0.own_coord, addr)?;
+        self.0.dirty.store(true, Ordering::Relaxed);
+        self
+            .0
+            .log
+            .log_with(
+                loga::DEBUG,
+                "Failing over to alternate address",
+                ea!(node = ident.dbg_str(), from = addr, to = alt),
+            );
+        return Some(alt);
+    }
 
-            // Empty slot
-            if bucket.len() < NEIGHBORHOOD {
-                if let Some(node) = node {
-                    bucket.insert(0, wire::node::latest::NodeState {
-                        node: node.clone(),
-                        unresponsive: false,
-                    });
-                    self.0.dirty.store(true, Ordering::Relaxed);
-                    log.log(loga::DEBUG, "Added node to empty slot");
-                    store_addr(log, buckets, &self.0.own_coord, node.address.0, node.ident);
+    /// Whether `id` is in the routing table but `addr` isn't one of its addresses: a
+    /// new IP family, or the node moved (ex: its NAT mapping changed). The node is
+    /// only moved once it responds to a challenge at the new address (see
+    /// `Buckets::add_good_node`), so spoofed senders can't redirect it.
+    fn moved(&self, id: &NodeIdentity, addr: &SocketAddr) -> bool {
+        return self.0.buckets.lock().unwrap().moved(&self.0.own_coord, id, addr);
+    }
+
+    /// Whether `id` is in the routing table but has no address in the IP family of
+    /// `addr`.
+    fn new_addr_family(&self, id: &NodeIdentity, addr: &SocketAddr) -> bool {
+        let (bucket_i, _) = dist(&node_ident_coord(id), &self.0.own_coord);
+        let buckets = self.0.buckets.lock().unwrap();
+        let Some(entry) = buckets.buckets[bucket_i].iter().find(|n| &n.node.ident == id) else {
+            return false;
+        };
+        return [&entry.node.address]
+            .into_iter()
+            .chain(entry.alt_addresses.iter())
+            .all(|a| a.0.is_ipv4() != addr.is_ipv4());
+    }
+
+    /// Wrap a message in the highest protocol version supported by `addr`.
+    fn versioned(&self, addr: &SocketAddr, message: wire::node::latest::Message) -> wire::node::Protocol {
+        match self.send_version(addr) {
+            1 => match message.to_v1() {
+                Ok(m) => return wire::node::Protocol::V1(m),
+                // Responses that differ between versions are sent with `send_protocol` in the
+                // version of the request
+                Err(m) => return wire::node::Protocol::V2(*m),
+            },
+            _ => return wire::node::Protocol::V2(message),
+        }
+    }
+
+    /// Serialize a message for sending to `addr`, counting it as sent traffic.
+    fn encode(&self, addr: &SocketAddr, data: wire::node::Protocol) -> Vec<u8> {
+        let bytes = data.to_bytes();
+        self.count_traffic(addr, message_type(&data), bytes.len(), true);
+        self.0.log.log_with(loga::DEBUG, "Sending", ea!(to_addr = addr, message = data.dbg_str()));
+        return bytes;
+    }
+
+    /// Send many messages to one node, in paced batches.
+    async fn send_batch(&self, addr: &SocketAddr, messages: Vec<wire::node::latest::Message>) {
+        let packets = messages.into_iter().map(|m| self.encode(addr, self.versioned(addr, m))).collect::<Vec<_>>();
+        let mut addr = *addr;
+        for (i, batch) in packets.chunks(REPLICATION_BATCH).enumerate() {
+            if i > 0 {
+                sleep(REPLICATION_BATCH_INTERVAL).await;
+            }
+            if let Err(e) = self.0.socket.send_to_many(&addr, batch).await {
+                self.0.log.log_with(loga::DEBUG, "Error sending batch", ea!(to_addr = addr, err = e));
+                let Some(alt) = self.fail_over(&addr) else {
+                    return;
+                };
+                addr = alt;
+                if let Err(e) = self.0.socket.send_to_many(&addr, batch).await {
+                    self.0.log.log_with(loga::DEBUG, "Error sending batch", ea!(to_addr = addr, err = e));
+                    return;
                 }
-                break true;
             }
+        }
+    }
 
-            // Replacing dead
-            if let Some(i) = last_unresponsive {
-                if let Some(node) = node {
-                    buckets.addrs.remove(&bucket[i].node.address.0);
-                    bucket.remove(i);
-                    bucket.push(wire::node::latest::NodeState {
-                        node: node.clone(),
-                        unresponsive: false,
-                    });
-                    self.0.dirty.store(true, Ordering::Relaxed);
-                    log.log(loga::DEBUG, "Replaced dead node");
-                    store_addr(log, buckets, &self.0.own_coord, node.address.0, node.ident);
+    /// The socket to send requests from, if request sockets are enabled.
+    fn request_socket(&self) -> Option<RequestSocket> {
+        return self.0.request_socket.lock().unwrap().clone();
+    }
+
+    /// Send a request from the request socket, or from the listening socket if
+    /// `socket` is `None`.
+    async fn send_request(
+        &self,
+        socket: &Option<RequestSocket>,
+        addr: &SocketAddr,
+        message: wire::node::latest::Message,
+    ) {
+        let Some(socket) = socket else {
+            self.send(addr, message).await;
+            return;
+        };
+        let bytes = self.encode(addr, self.versioned(addr, message));
+        if let Err(e) = socket.socket.send_to(&bytes, *addr).await {
+            self.0.log.log_with(loga::DEBUG, "Error sending request", ea!(to_addr = addr, err = e));
+        }
+    }
+
+    async fn send(&self, addr: &SocketAddr, message: wire::node::latest::Message) {
+        self.send_protocol(addr, self.versioned(addr, message)).await;
+    }
+
+    fn quic_enabled(&self) -> bool {
+        #[cfg(feature = "quic")]
+        {
+            return self.0.quic.is_some();
+        }
+        #[cfg(not(feature = "quic"))]
+        {
+            return false;
+        }
+    }
+
+    /// If the node at `addr` accepts QUIC, send the message over QUIC. If that fails
+    /// the node is sent datagrams from then on. Returns whether the message was sent.
+    #[cfg(feature = "quic")]
+    async fn send_quic(&self, addr: &SocketAddr, bytes: &[u8]) -> bool {
+        let Some(quic) = &self.0.quic else {
+            return false;
+        };
+        let Some(ident) = self.0.buckets.lock().unwrap().addrs.get(addr).cloned() else {
+            return false;
+        };
+        if !self.0.quic_peers.lock().unwrap().contains(&ident) {
+            return false;
+        }
+        match quic.send(*addr, bytes).await {
+            Ok(()) => return true,
+            Err(e) => {
+                self
+                    .0
+                    .log
+                    .log_err(
+                        loga::DEBUG,
+                        e.context_with("Error sending over QUIC, falling back to datagrams", ea!(to_addr = addr)),
+                    );
+                self.0.quic_peers.lock().unwrap().remove(&ident);
+                self.0.quic_fallbacks.fetch_add(1, Ordering::Relaxed);
+                return false;
+            },
+        }
+    }
+
+    /// Send a message with a specific protocol version.
+    async fn send_protocol(&self, addr: &SocketAddr, data: wire::node::Protocol) {
+        let bytes = self.encode(addr, data);
+        #[cfg(feature = "quic")]
+        if self.send_quic(addr, &bytes).await {
+            return;
+        }
+        if let Err(e) = self.0.socket.send_to(&bytes, *addr).await {
+            self.0.log.log_with(loga::DEBUG, "Error sending", ea!(to_addr = addr, err = e));
+            if let Some(alt) = self.fail_over(addr) {
+                if let Err(e) = self.0.socket.send_to(&bytes, alt).await {
+                    self.0.log.log_with(loga::DEBUG, "Error sending", ea!(to_addr = alt, err = e));
                 }
-                break 'logic true;
             }
-            log.log(loga::DEBUG, "Nowhere to place, dropping");
-            break false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_stamped_responses {
+    use {
+        super::Node,
+        crate::{
+            interface::{
+                stored::{
+                    node_identity::{
+                        NodeIdentity,
+                        NodeSecret,
+                        NodeSecretMethods,
+                    },
+                    shared::SerialAddr,
+                },
+                wire::{
+                    self,
+                    node::latest::{
+                        BincodeSignature,
+                        ChallengeResponseContent,
+                        FindResponseContent,
+                    },
+                },
+            },
+            utils::{
+                blob::Blob,
+                signed::NodeIdentSignatureMethods,
+            },
+        },
+        chrono::{
+            Duration,
+            Utc,
+        },
+        std::{
+            net::SocketAddr,
+            str::FromStr,
+            sync::atomic::Ordering,
+        },
+        taskmanager::TaskManager,
+    };
+
+    pub(super) fn challenge_response(
+        secret: &NodeSecret,
+        challenge: &Blob,
+        offset: Duration,
+    ) -> wire::node::Protocol {
+        return wire::node::Protocol::V2(
+            wire::node::latest::Message::ChallengeResponse(wire::node::latest::ChallengeResponse {
+                sender: secret.get_identity(),
+                content: BincodeSignature::sign(secret, ChallengeResponseContent {
+                    challenge: challenge.clone(),
+                    stamp: Utc::now() + offset,
+                }),
+            }),
+        );
+    }
+
+    pub(super) fn pending_challenge(node: &Node, ident: &NodeIdentity) -> Option<Blob> {
+        return node.0.challenge_states.lock().unwrap().get(ident).map(|s| s.challenge.clone());
+    }
+
+    #[tokio::test]
+    async fn test_challenge_stamp() {
+        let tm = TaskManager::new();
+        let (node, cache_dir) = Node::new_test(&tm, "challenge-stamp").await;
+        let (ident, secret) = NodeIdentity::new();
+        let addr = SocketAddr::from_str("127.0.0.1:9").unwrap();
+        node.start_challenge(ident, &addr, true).await;
+        let challenge = pending_challenge(&node, &ident).unwrap();
+
+        // Stale and future stamps are rejected, leaving the challenge pending
+        node.handle(challenge_response(&secret, &challenge, Duration::minutes(-2)), &addr, 0).await.unwrap();
+        node.handle(challenge_response(&secret, &challenge, Duration::minutes(2)), &addr, 0).await.unwrap();
+        assert_eq!(node.0.stale_rejections.load(Ordering::Relaxed), 2);
+        assert_eq!(pending_challenge(&node, &ident), Some(challenge.clone()));
+
+        // A response to an earlier challenge is rejected
+        node.handle(challenge_response(&secret, &Blob::new(32), Duration::zero()), &addr, 0).await.unwrap();
+        assert_eq!(node.0.quarantine_rejections.load(Ordering::Relaxed), 1);
+        assert_eq!(pending_challenge(&node, &ident), Some(challenge.clone()));
+
+        // A fresh response is accepted
+        node.handle(challenge_response(&secret, &challenge, Duration::zero()), &addr, 0).await.unwrap();
+        assert_eq!(pending_challenge(&node, &ident), None);
+        assert_eq!(node.0.buckets.lock().unwrap().addrs.get(&addr), Some(&ident));
+        tm.terminate();
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_find_stamp() {
+        let tm = TaskManager::new();
+        let (node, cache_dir) = Node::new_test(&tm, "find-stamp").await;
+        let (ident, secret) = NodeIdentity::new();
+        let addr = SocketAddr::from_str("127.0.0.1:9").unwrap();
+        for (offset, rejections) in [(Duration::minutes(-2), 1), (Duration::minutes(2), 2), (Duration::zero(), 2)] {
+            node.handle(wire::node::Protocol::V2(
+                wire::node::latest::Message::FindResponse(wire::node::latest::FindResponse {
+                    sender: ident,
+                    content: BincodeSignature::sign(&secret, FindResponseContent {
+                        goal: wire::node::latest::FindGoal::Coord(node.0.own_coord),
+                        challenge: Blob::new(32),
+                        sender: ident,
+                        nodes: vec![],
+                        value: None,
+                        stamp: Utc::now() + offset,
+                    }),
+                }),
+            ), &addr, 0).await.unwrap();
+            assert_eq!(node.0.stale_rejections.load(Ordering::Relaxed), rejections);
+        }
+        tm.terminate();
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unstamped_from_v2() {
+        let tm = TaskManager::new();
+        let (node, cache_dir) = Node::new_test(&tm, "unstamped").await;
+        let (ident, secret) = NodeIdentity::new();
+        let addr = SocketAddr::from_str("127.0.0.1:9").unwrap();
+        node.start_challenge(ident, &addr, true).await;
+        let challenge = pending_challenge(&node, &ident).unwrap();
+        let v1_response = || wire::node::Protocol::V1(
+            wire::node::v1::Message::ChallengeResponse(wire::node::v1::ChallengeResponse {
+                sender: ident,
+                signature: secret.sign(&challenge),
+            }),
+        );
+
+        // Once the address is known to speak v2, v1 responses from it could be replays
+        node.add_good_node(ident, Some(wire::node::latest::NodeInfo {
+            ident: ident,
+            address: SerialAddr(addr),
+        }));
+        node.0.peer_versions.lock().unwrap().insert(ident, vec![1, 2]);
+        node.handle(v1_response(), &addr, 0).await.unwrap();
+        assert_eq!(node.0.stale_rejections.load(Ordering::Relaxed), 1);
+        assert_eq!(pending_challenge(&node, &ident), Some(challenge.clone()));
+
+        // Accepted from v1 nodes
+        node.0.peer_versions.lock().unwrap().insert(ident, vec![1]);
+        node.handle(v1_response(), &addr, 0).await.unwrap();
+        assert_eq!(node.0.stale_rejections.load(Ordering::Relaxed), 1);
+        assert_eq!(pending_challenge(&node, &ident), None);
+        tm.terminate();
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_pung {
+    use {
+        super::{
+            dist,
+            node_ident_coord,
+            Node,
+        },
+        crate::{
+            interface::{
+                stored::{
+                    node_identity::{
+                        NodeIdentity,
+                        NodeSecret,
+                    },
+                    shared::SerialAddr,
+                },
+                wire::{
+                    self,
+                    node::latest::{
+                        BincodeSignature,
+                        PungContent,
+                    },
+                },
+            },
+            utils::{
+                blob::Blob,
+                signed::NodeIdentSignatureMethods,
+            },
+        },
+        futures::channel::mpsc::unbounded,
+        std::{
+            net::SocketAddr,
+            str::FromStr,
+            sync::atomic::Ordering,
+        },
+        taskmanager::TaskManager,
+    };
+
+    fn signed_pung(sender: NodeIdentity, secret: &NodeSecret, nonce: &Blob) -> wire::node::Protocol {
+        return wire::node::Protocol::V2(wire::node::latest::Message::SignedPung(wire::node::latest::SignedPung {
+            sender: sender,
+            content: BincodeSignature::sign(secret, PungContent { nonce: nonce.clone() }),
+        }));
+    }
+
+    #[tokio::test]
+    async fn test_nonce() {
+        let tm = TaskManager::new();
+        let (node, cache_dir) = Node::new_test(&tm, "pung").await;
+        let (ping_timeouts, _ping_timeouts_recv) = unbounded();
+        let (ident, secret) = NodeIdentity::new();
+        let addr = SocketAddr::from_str("127.0.0.1:9").unwrap();
+        let bucket_i = dist(&node_ident_coord(&ident), &node.0.own_coord).0;
+        node.add_good_node(ident, Some(wire::node::latest::NodeInfo {
+            ident: ident,
+            address: SerialAddr(addr),
+        }));
+        node.0.peer_versions.lock().unwrap().insert(ident, vec![1, 2]);
+        node.mark_node_unresponsive(ident, bucket_i, true);
+        let unresponsive = || node.0.buckets.lock().unwrap().buckets[bucket_i][0].unresponsive;
+        let pending_nonce = || node.0.ping_states.lock().unwrap().get(&ident).and_then(|s| s.nonce.clone());
+
+        // Nonce from a ping that timed out
+        node.start_ping(&ping_timeouts, ident, &addr, bucket_i).await;
+        let stale_nonce = pending_nonce().unwrap();
+        node.0.ping_states.lock().unwrap().remove(&ident);
+        node.start_ping(&ping_timeouts, ident, &addr, bucket_i).await;
+        let nonce = pending_nonce().unwrap();
+        node.handle(signed_pung(ident, &secret, &stale_nonce), &addr, 0).await.unwrap();
+
+        // Wrong nonce
+        node.handle(signed_pung(ident, &secret, &Blob::new(nonce.len())), &addr, 0).await.unwrap();
+
+        // Unsigned
+        node.handle(wire::node::Protocol::V2(wire::node::latest::Message::Pung(ident)), &addr, 0).await.unwrap();
+
+        // Signed by someone else
+        let (_, other_secret) = NodeIdentity::new();
+        node.handle(signed_pung(ident, &other_secret, &nonce), &addr, 0).await.unwrap();
+        assert_eq!(node.0.ping_rejections.load(Ordering::Relaxed), 4);
+        assert!(unresponsive());
+        assert_eq!(pending_nonce(), Some(nonce.clone()));
+
+        // Right nonce
+        node.handle(signed_pung(ident, &secret, &nonce), &addr, 0).await.unwrap();
+        assert!(!unresponsive());
+        assert_eq!(pending_nonce(), None);
+        tm.terminate();
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod test_quarantine {
+    use {
+        super::{
+            node_ident_coord,
+            req_timeout,
+            test_stamped_responses::{
+                challenge_response,
+                pending_challenge,
+            },
+            Node,
+            NEIGHBORHOOD,
+        },
+        crate::{
+            interface::{
+                stored::node_identity::NodeIdentity,
+                wire::{
+                    self,
+                    node::latest::FindGoal,
+                },
+            },
+            utils::blob::Blob,
+        },
+        chrono::Duration,
+        std::{
+            net::SocketAddr,
+            str::FromStr,
+        },
+        taskmanager::TaskManager,
+    };
+
+    /// Whether `ident` would be given out in find responses.
+    fn findable(node: &Node, ident: &NodeIdentity) -> bool {
+        return node.get_closest_peers(node_ident_coord(ident), NEIGHBORHOOD).iter().any(|n| n.ident == *ident);
+    }
+
+    /// Have `ident` contact `node` with a find request, as an unknown node would.
+    async fn contact(node: &Node, ident: NodeIdentity, addr: &SocketAddr) {
+        let request = wire::node::latest::FindRequest {
+            sender: ident,
+            challenge: Blob::new(32),
+            goal: FindGoal::Coord(node.0.own_coord),
         };
-        return new_node;
+        node
+            .handle(wire::node::Protocol::V2(wire::node::latest::Message::FindRequest(request)), addr, 0)
+            .await
+            .unwrap();
     }
 
-    async fn send(&self, addr: &SocketAddr, data: wire::node::Protocol) {
-        let bytes = data.to_bytes();
-        self.0.log.log_with(loga::DEBUG, "Sending", ea!(to_addr = addr, message = data.dbg_str()));
-        self.0.socket.send_to(&bytes, addr).await.unwrap();
+    #[tokio::test]
+    async fn test_verified() {
+        let tm = TaskManager::new();
+        let (node, cache_dir) = Node::new_test(&tm, "quarantine-verified").await;
+        let (ident, secret) = NodeIdentity::new();
+        let addr = SocketAddr::from_str("127.0.0.1:9").unwrap();
+
+        // Quarantined on contact, not routable yet
+        contact(&node, ident, &addr).await;
+        let challenge = pending_challenge(&node, &ident).unwrap();
+        assert_eq!(node.health_detail().quarantined_nodes, 1);
+        assert!(!node.0.buckets.lock().unwrap().addrs.contains_key(&addr));
+        assert!(!findable(&node, &ident));
+
+        // Responses from another address don't release it
+        node
+            .handle(
+                challenge_response(&secret, &challenge, Duration::zero()),
+                &SocketAddr::from_str("127.0.0.1:10").unwrap(),
+                0,
+            )
+            .await
+            .unwrap();
+        assert_eq!(node.health_detail().quarantined_nodes, 1);
+        assert!(!findable(&node, &ident));
+
+        // Released once verified
+        node.handle(challenge_response(&secret, &challenge, Duration::zero()), &addr, 0).await.unwrap();
+        assert_eq!(node.health_detail().quarantined_nodes, 0);
+        assert!(findable(&node, &ident));
+        tm.terminate();
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let tm = TaskManager::new();
+        let (node, cache_dir) = Node::new_test(&tm, "quarantine-timeout").await;
+        let (ident, _) = NodeIdentity::new();
+        let addr = SocketAddr::from_str("127.0.0.1:9").unwrap();
+        contact(&node, ident, &addr).await;
+        assert_eq!(node.health_detail().quarantined_nodes, 1);
+
+        // Dropped without being added if the challenge isn't answered
+        tokio::time::sleep((req_timeout() + Duration::milliseconds(500)).to_std().unwrap()).await;
+        assert_eq!(node.health_detail().quarantined_nodes, 0);
+        assert!(!node.0.buckets.lock().unwrap().addrs.contains_key(&addr));
+        assert!(!findable(&node, &ident));
+        tm.terminate();
+        std::fs::remove_dir_all(&cache_dir).unwrap();
     }
 }
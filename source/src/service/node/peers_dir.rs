@@ -0,0 +1,133 @@
+//! Reading the declarative peers directory (`NodeConfig::peers_dir`), which the
+//! node reconciles its routing table against.
+use {
+    crate::interface::{
+        config::node::node_config::DeclaredPeer,
+        stored::{
+            node_identity::NodeIdentity,
+            shared::SerialAddr,
+        },
+        wire,
+    },
+    loga::{
+        ea,
+        ErrContext,
+        ResultContext,
+    },
+    std::{
+        collections::{
+            HashMap,
+            HashSet,
+        },
+        path::Path,
+    },
+    tokio::fs::{
+        read,
+        read_dir,
+    },
+};
+
+#[derive(Default, Debug)]
+pub struct DeclaredPeers {
+    pub pinned: Vec<wire::node::latest::NodeInfo>,
+    pub tombstoned: HashSet<NodeIdentity>,
+}
+
+/// Combine the declarations from each file (file name, declaration). Declaring the
+/// same peer in multiple files is an error.
+pub fn collect_declared_peers(files: Vec<(String, DeclaredPeer)>) -> Result<DeclaredPeers, loga::Error> {
+    let mut seen = HashMap::new();
+    let mut out = DeclaredPeers::default();
+    for (name, peer) in files {
+        let ident = match &peer {
+            DeclaredPeer::Pinned { ident, .. } => *ident,
+            DeclaredPeer::Tombstone { ident } => *ident,
+        };
+        if let Some(other) = seen.insert(ident, name.clone()) {
+            return Err(
+                loga::err_with(
+                    "Peer declared in multiple files",
+                    ea!(ident = ident, file = name, other_file = other),
+                ),
+            );
+        }
+        match peer {
+            DeclaredPeer::Pinned { addr, ident } => {
+                out.pinned.push(wire::node::latest::NodeInfo {
+                    ident: ident,
+                    address: SerialAddr(
+                        addr.resolve().context_with("Error resolving declared peer address", ea!(file = name))?,
+                    ),
+                });
+            },
+            DeclaredPeer::Tombstone { ident } => {
+                out.tombstoned.insert(ident);
+            },
+        }
+    }
+    return Ok(out);
+}
+
+/// Read all `*.json` files in the directory. A missing directory has no
+/// declarations.
+pub async fn read_peers_dir(dir: &Path) -> Result<DeclaredPeers, loga::Error> {
+    let mut entries = match read_dir(dir).await {
+        Ok(e) => e,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(DeclaredPeers::default()),
+        Err(e) => return Err(e.context_with("Error listing peers directory", ea!(dir = dir.to_string_lossy()))),
+    };
+    let mut files = vec![];
+    while let Some(entry) = entries.next_entry().await.context("Error listing peers directory")? {
+        let Some(name) = entry.file_name().to_str().map(|n| n.to_string()) else {
+            continue;
+        };
+        if name.starts_with(".") || !name.ends_with(".json") {
+            continue;
+        }
+        let path = entry.path();
+        let peer =
+            serde_json::from_slice::<DeclaredPeer>(
+                &read(&path).await.context_with("Error reading declared peer", ea!(file = name))?,
+            ).context_with("Error parsing declared peer", ea!(file = name))?;
+        files.push((name, peer));
+    }
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    return collect_declared_peers(files);
+}
+
+#[cfg(test)]
+mod test_peers_dir {
+    use {
+        super::collect_declared_peers,
+        crate::interface::{
+            config::{
+                node::node_config::DeclaredPeer,
+                shared::StrSocketAddr,
+            },
+            stored::node_identity::NodeIdentity,
+        },
+    };
+
+    #[test]
+    fn test_collect() {
+        let a = NodeIdentity::new().0;
+        let b = NodeIdentity::new().0;
+        let peers = collect_declared_peers(vec![("a.json".to_string(), DeclaredPeer::Pinned {
+            addr: StrSocketAddr::new("192.0.2.1:48390"),
+            ident: a,
+        }), ("b.json".to_string(), DeclaredPeer::Tombstone { ident: b })]).unwrap();
+        assert_eq!(peers.pinned.len(), 1);
+        assert_eq!(peers.pinned[0].ident, a);
+        assert!(peers.tombstoned.contains(&b));
+
+        // Each peer can only be declared once
+        assert!(
+            collect_declared_peers(
+                vec![
+                    ("a.json".to_string(), DeclaredPeer::Tombstone { ident: a }),
+                    ("c.json".to_string(), DeclaredPeer::Tombstone { ident: a })
+                ],
+            ).is_err()
+        );
+    }
+}
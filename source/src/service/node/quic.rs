@@ -0,0 +1,391 @@
+//! Experimental QUIC transport for messages between nodes (`NodeConfig::quic`).
+//!
+//! QUIC shares the node's UDP port with the datagram protocol. Datagram messages
+//! start with a little-endian protocol version (`0x01`, `0x02`) while QUIC packets
+//! always have the fixed bit (`0x40`) set in the first byte, so received packets
+//! can be told apart by that byte.
+//!
+//! Each message is sent on its own unidirectional stream of a connection per peer
+//! address. The TLS certificate is self-signed and not checked: senders are
+//! authenticated by the signatures and challenges of the node protocol like with
+//! datagrams, QUIC only adds congestion control and larger messages.
+use {
+    super::{
+        PacketWorkers,
+        ReceivedPacket,
+    },
+    crate::utils::{
+        task_status::TrackedTasks,
+        tls_util::create_leaf_cert_der_local,
+    },
+    chrono::{
+        DateTime,
+        NaiveDate,
+        NaiveDateTime,
+    },
+    loga::{
+        ea,
+        ErrContext,
+        Log,
+        ResultContext,
+    },
+    p256::pkcs8::EncodePrivateKey,
+    quinn::{
+        rustls::{
+            self,
+            pki_types::{
+                CertificateDer,
+                PrivateKeyDer,
+                PrivatePkcs8KeyDer,
+                ServerName,
+                UnixTime,
+            },
+        },
+        udp::{
+            RecvMeta,
+            Transmit,
+        },
+        AsyncUdpSocket,
+        UdpPoller,
+    },
+    std::{
+        collections::HashMap,
+        fmt::Debug,
+        io::IoSliceMut,
+        net::SocketAddr,
+        pin::Pin,
+        sync::{
+            Arc,
+            Mutex,
+        },
+        task::{
+            Context,
+            Poll,
+        },
+        time::Duration,
+    },
+    taskmanager::TaskManager,
+    tokio::{
+        net::UdpSocket,
+        select,
+        spawn,
+        sync::mpsc,
+        time::timeout,
+    },
+};
+
+// Server name sent when connecting; certificates aren't checked so it's only a
+// placeholder
+const SERVER_NAME: &str = "node.spagh";
+// Largest message accepted on a stream
+const MESSAGE_MAX: usize = 64 * 1024;
+// Received QUIC packets queued before more are dropped
+const RECV_QUEUE: usize = 1024;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Receive buffer size for the shared socket. QUIC requires datagrams of at least
+/// 1200 bytes, more than the datagram protocol's limit.
+pub const DATAGRAM_MAX: usize = 1500;
+
+/// Whether a packet received on the shared socket is QUIC rather than a datagram
+/// protocol message.
+pub fn is_quic(packet: &[u8]) -> bool {
+    return packet.first().is_some_and(|b| b & 0x40 != 0);
+}
+
+/// The node's listening socket as seen by quinn: sends go straight to the socket,
+/// while received packets are passed in by the node's listen loop.
+struct SharedSocket {
+    socket: Arc<UdpSocket>,
+    recv: Mutex<mpsc::Receiver<(Vec<u8>, SocketAddr)>>,
+}
+
+impl Debug for SharedSocket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return f.debug_struct("SharedSocket").field("socket", &self.socket).finish();
+    }
+}
+
+#[derive(Debug)]
+struct SharedSocketPoller(Arc<UdpSocket>);
+
+impl UdpPoller for SharedSocketPoller {
+    fn poll_writable(self: Pin<&mut Self>, cx: &mut Context) -> Poll<std::io::Result<()>> {
+        return self.0.poll_send_ready(cx);
+    }
+}
+
+impl AsyncUdpSocket for SharedSocket {
+    fn create_io_poller(self: Arc<Self>) -> Pin<Box<dyn UdpPoller>> {
+        return Box::pin(SharedSocketPoller(self.socket.clone()));
+    }
+
+    fn try_send(&self, transmit: &Transmit) -> std::io::Result<()> {
+        self.socket.try_send_to(transmit.contents, transmit.destination)?;
+        return Ok(());
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<std::io::Result<usize>> {
+        let Some((data, addr)) = std::task::ready!(self.recv.lock().unwrap().poll_recv(cx)) else {
+            return Poll::Ready(Err(std::io::ErrorKind::BrokenPipe.into()));
+        };
+        let len = data.len().min(bufs[0].len());
+        bufs[0][..len].copy_from_slice(&data[..len]);
+        meta[0].addr = addr;
+        meta[0].len = len;
+        meta[0].stride = len;
+        meta[0].ecn = None;
+        meta[0].dst_ip = None;
+        return Poll::Ready(Ok(1));
+    }
+
+    fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        return self.socket.local_addr();
+    }
+}
+
+/// Accepts any certificate, but still checks that the handshake was signed by it.
+#[derive(Debug)]
+struct AnyCertVerifier(Arc<rustls::crypto::CryptoProvider>);
+
+impl rustls::client::danger::ServerCertVerifier for AnyCertVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        return Ok(rustls::client::danger::ServerCertVerified::assertion());
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        return rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms);
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        return rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms);
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        return self.0.signature_verification_algorithms.supported_schemes();
+    }
+}
+
+pub struct QuicTransport {
+    endpoint: quinn::Endpoint,
+    recv: mpsc::Sender<(Vec<u8>, SocketAddr)>,
+    // Open connections by peer address, both initiated and accepted
+    conns: Mutex<HashMap<SocketAddr, quinn::Connection>>,
+}
+
+impl QuicTransport {
+    pub async fn new(socket: Arc<UdpSocket>) -> Result<QuicTransport, loga::Error> {
+        let priv_key = p256::ecdsa::SigningKey::random(&mut rand::thread_rng());
+        let cert_der =
+            create_leaf_cert_der_local(
+                priv_key.clone(),
+                SERVER_NAME,
+                DateTime::UNIX_EPOCH,
+                NaiveDateTime::from(NaiveDate::from_ymd_opt(9999, 12, 31).unwrap()).and_utc(),
+                None,
+                SERVER_NAME,
+            ).await?;
+        let mut transport = quinn::TransportConfig::default();
+        transport.max_idle_timeout(Some(IDLE_TIMEOUT.try_into().unwrap()));
+        let transport = Arc::new(transport);
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut server_config =
+            quinn::ServerConfig::with_crypto(
+                Arc::new(
+                    quinn::crypto::rustls::QuicServerConfig::try_from(
+                        rustls::ServerConfig::builder_with_provider(provider.clone())
+                            .with_protocol_versions(&[&rustls::version::TLS13])
+                            .context("Error setting QUIC server TLS versions")?
+                            .with_no_client_auth()
+                            .with_single_cert(
+                                vec![CertificateDer::from(cert_der.to_vec())],
+                                PrivateKeyDer::from(
+                                    PrivatePkcs8KeyDer::from(priv_key.to_pkcs8_der().unwrap().as_bytes().to_vec()),
+                                ),
+                            )
+                            .context("Error setting QUIC server certificate")?,
+                    ).context("Error building QUIC server TLS config")?,
+                ),
+            );
+        server_config.transport_config(transport.clone());
+        let mut client_config =
+            quinn::ClientConfig::new(
+                Arc::new(
+                    quinn::crypto::rustls::QuicClientConfig::try_from(
+                        rustls::ClientConfig::builder_with_provider(provider.clone())
+                            .with_protocol_versions(&[&rustls::version::TLS13])
+                            .context("Error setting QUIC client TLS versions")?
+                            .dangerous()
+                            .with_custom_certificate_verifier(Arc::new(AnyCertVerifier(provider)))
+                            .with_no_client_auth(),
+                    ).context("Error building QUIC client TLS config")?,
+                ),
+            );
+        client_config.transport_config(transport);
+        let (recv_send, recv_recv) = mpsc::channel(RECV_QUEUE);
+        let mut endpoint =
+            quinn::Endpoint::new_with_abstract_socket(
+                quinn::EndpointConfig::default(),
+                Some(server_config),
+                Arc::new(SharedSocket {
+                    socket: socket,
+                    recv: Mutex::new(recv_recv),
+                }),
+                Arc::new(quinn::TokioRuntime),
+            ).context("Error creating QUIC endpoint")?;
+        endpoint.set_default_client_config(client_config);
+        return Ok(QuicTransport {
+            endpoint: endpoint,
+            recv: recv_send,
+            conns: Mutex::new(HashMap::new()),
+        });
+    }
+
+    /// Pass in a QUIC packet received on the shared socket. Dropped if too many are
+    /// waiting, like a full socket buffer.
+    pub fn deliver(&self, packet: Vec<u8>, addr: SocketAddr) {
+        _ = self.recv.try_send((packet, addr));
+    }
+
+    fn conn(&self, addr: &SocketAddr) -> Option<quinn::Connection> {
+        return self.conns.lock().unwrap().get(addr).filter(|c| c.close_reason().is_none()).cloned();
+    }
+
+    fn add_conn(&self, conn: quinn::Connection) {
+        let mut conns = self.conns.lock().unwrap();
+        conns.retain(|_, c| c.close_reason().is_none());
+        conns.insert(conn.remote_address(), conn);
+    }
+
+    /// Send one message to the node at `addr`, connecting if there's no open
+    /// connection.
+    pub async fn send(&self, addr: SocketAddr, message: &[u8]) -> Result<(), loga::Error> {
+        let conn = match self.conn(&addr) {
+            Some(c) => c,
+            None => {
+                let conn =
+                    timeout(
+                        CONNECT_TIMEOUT,
+                        self.endpoint.connect(addr, SERVER_NAME).context("Error starting QUIC connection")?,
+                    )
+                        .await
+                        .context("Timed out establishing QUIC connection")?
+                        .context("Error establishing QUIC connection")?;
+                self.add_conn(conn.clone());
+                conn
+            },
+        };
+        let mut stream = conn.open_uni().await.context("Error opening QUIC stream")?;
+        stream.write_all(message).await.context("Error writing message to QUIC stream")?;
+        stream.finish().context("Error finishing QUIC stream")?;
+        return Ok(());
+    }
+
+    /// Accept connections from other nodes, handling each message received like a
+    /// datagram from the connection's address.
+    pub fn serve(self: &Arc<Self>, log: &Log, tm: &TaskManager, workers: PacketWorkers) {
+        tm.tracked_task("Node - QUIC", {
+            let log = log.fork(ea!(subsys = "quic"));
+            let tm = tm.clone();
+            let transport = self.clone();
+            async move {
+                loop {
+                    let incoming = select!{
+                        _ = tm.until_terminate() => {
+                            transport.endpoint.close(quinn::VarInt::from_u32(0), b"");
+                            return;
+                        }
+                        i = transport.endpoint.accept() => match i {
+                            Some(i) => i,
+                            None => return,
+                        },
+                    };
+                    let log = log.clone();
+                    let transport = transport.clone();
+                    let workers = workers.clone();
+                    spawn(async move {
+                        let conn = match incoming.await {
+                            Ok(c) => c,
+                            Err(e) => {
+                                log.log_err(loga::DEBUG, e.context("Error accepting QUIC connection"));
+                                return;
+                            },
+                        };
+                        let addr = conn.remote_address();
+                        transport.add_conn(conn.clone());
+
+                        // Streams are read one at a time so messages are handled in the order sent
+                        loop {
+                            let mut stream = match conn.accept_uni().await {
+                                Ok(s) => s,
+                                Err(_) => return,
+                            };
+                            let data = match stream.read_to_end(MESSAGE_MAX).await {
+                                Ok(d) => d,
+                                Err(e) => {
+                                    log.log_err(
+                                        loga::DEBUG,
+                                        e.context_with("Error reading QUIC message", ea!(addr = addr)),
+                                    );
+                                    continue;
+                                },
+                            };
+                            workers.dispatch(ReceivedPacket {
+                                data: data,
+                                full: false,
+                                addr: addr,
+                                socket: 0,
+                            }).await;
+                        }
+                    });
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_quic {
+    use {
+        super::is_quic,
+        crate::interface::wire,
+    };
+
+    #[test]
+    fn test_is_quic() {
+        for p in [
+            wire::node::Protocol::V1(wire::node::v1::Message::Ping),
+            wire::node::Protocol::V2(wire::node::v2::Message::Ping),
+        ] {
+            assert!(!is_quic(&p.to_bytes()));
+        }
+
+        // Long header initial, short header
+        assert!(is_quic(&[0xc3, 0, 0, 0, 1]));
+        assert!(is_quic(&[0x41, 0]));
+        assert!(!is_quic(&[]));
+    }
+}
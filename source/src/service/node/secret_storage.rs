@@ -0,0 +1,329 @@
+//! Keeping the node secret encrypted at rest. The secret is encrypted with a random
+//! key, which is kept in the OS keyring or protected by external commands (see
+//! `NodeSecretStorage`). With a command only the encrypted secret is written to the
+//! node database; with the keyring the unencrypted copy is kept too, to fall back to
+//! when the keyring isn't available.
+use {
+    super::db,
+    crate::{
+        interface::{
+            config::node::node_config::NodeSecretStorage,
+            stored::node_identity::{
+                self,
+                NodeIdentity,
+                NodeSecret,
+                SealedNodeSecret,
+            },
+        },
+        utils::blob::{
+            Blob,
+            ToBlob,
+        },
+    },
+    chacha20poly1305::{
+        aead::{
+            Aead,
+            KeyInit,
+        },
+        XChaCha20Poly1305,
+        XNonce,
+    },
+    loga::{
+        ea,
+        Log,
+        ResultContext,
+    },
+    rand::{
+        rngs::OsRng,
+        RngCore,
+    },
+    std::process::Stdio,
+    tokio::{
+        io::AsyncWriteExt,
+        process::Command,
+        task::spawn_blocking,
+    },
+};
+
+const KEYRING_SERVICE: &str = "spaghettinuum-node";
+
+fn keyring_entry(user: &str) -> Result<keyring::Entry, loga::Error> {
+    return keyring::Entry::new(KEYRING_SERVICE, user).context_with("Error opening keyring entry", ea!(user = user));
+}
+
+/// Run a seal/unseal command, passing `input` on stdin and returning stdout.
+async fn run_command(command: &[String], input: &[u8]) -> Result<Vec<u8>, loga::Error> {
+    let Some((program, args)) = command.split_first() else {
+        return Err(loga::err("Secret storage command is empty"));
+    };
+    let log = Log::new().fork(ea!(command = program));
+    let mut child =
+        Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .stack_context(&log, "Error starting secret storage command")?;
+    {
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(input).await.stack_context(&log, "Error writing to secret storage command")?;
+    }
+    let output = child.wait_with_output().await.stack_context(&log, "Error waiting for secret storage command")?;
+    if !output.status.success() {
+        return Err(loga::err_with("Secret storage command failed", ea!(command = program, status = output.status)));
+    }
+    return Ok(output.stdout);
+}
+
+/// Encrypt the secret with a new key, storing the key as `storage` says.
+async fn seal(storage: &NodeSecretStorage, secret: &NodeSecret) -> Result<SealedNodeSecret, loga::Error> {
+    let mut key = chacha20poly1305::Key::default();
+    OsRng.fill_bytes(&mut key);
+    let key_ref = match storage {
+        NodeSecretStorage::Keyring => {
+            let mut user = Blob::new(16);
+            OsRng.fill_bytes(&mut user);
+            let user = format!("node-secret-{}", zbase32::encode_full_bytes(&user));
+            let encoded = zbase32::encode_full_bytes(&key);
+            spawn_blocking({
+                let user = user.clone();
+                move || {
+                    return keyring_entry(&user)?.set_password(&encoded).context("Error storing key in keyring");
+                }
+            }).await.context("Keyring task failed")??;
+            node_identity::v1::SealedNodeSecretKey::Keyring(user)
+        },
+        NodeSecretStorage::Command { seal, .. } => {
+            node_identity::v1::SealedNodeSecretKey::Command(run_command(seal, &key).await?.blob())
+        },
+        NodeSecretStorage::Plaintext => unreachable!(),
+    };
+    let mut nonce = Blob::new(24);
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext =
+        XChaCha20Poly1305::new(&key)
+            .encrypt(XNonce::from_slice(&nonce), secret.to_bytes().as_ref())
+            .map_err(|_| loga::err("Error encrypting node secret"))?
+            .blob();
+    return Ok(SealedNodeSecret::V1(node_identity::v1::SealedNodeSecret {
+        key: key_ref,
+        nonce: nonce,
+        ciphertext: ciphertext,
+    }));
+}
+
+async fn unseal(storage: &NodeSecretStorage, sealed: &SealedNodeSecret) -> Result<NodeSecret, loga::Error> {
+    match sealed {
+        SealedNodeSecret::V1(sealed) => {
+            let key = match &sealed.key {
+                node_identity::v1::SealedNodeSecretKey::Keyring(user) => {
+                    let user = user.clone();
+                    let encoded = spawn_blocking(move || {
+                        return keyring_entry(&user)?.get_password().context("Error reading key from keyring");
+                    }).await.context("Keyring task failed")??;
+                    zbase32::decode_full_bytes_str(
+                        &encoded,
+                    ).map_err(|e| loga::err_with("Key in keyring isn't valid zbase32", ea!(err = e)))?
+                },
+                node_identity::v1::SealedNodeSecretKey::Command(sealed_key) => {
+                    let NodeSecretStorage::Command { unseal, .. } = storage else {
+                        return Err(loga::err("Node secret was sealed with a command but no command is configured"));
+                    };
+                    run_command(unseal, sealed_key).await?
+                },
+            };
+            if key.len() != 32 {
+                return Err(loga::err_with("Node secret key has wrong length", ea!(len = key.len())));
+            }
+            if sealed.nonce.len() != 24 {
+                return Err(loga::err_with("Sealed node secret nonce has wrong length", ea!(len = sealed.nonce.len())));
+            }
+            let plaintext =
+                XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&key))
+                    .decrypt(XNonce::from_slice(&sealed.nonce), sealed.ciphertext.as_ref())
+                    .map_err(|_| loga::err("Error decrypting node secret, key may be incorrect"))?;
+            return NodeSecret::from_bytes(&plaintext);
+        },
+    }
+}
+
+fn sealed_key_matches(storage: &NodeSecretStorage, sealed: &SealedNodeSecret) -> bool {
+    match sealed {
+        SealedNodeSecret::V1(sealed) => match (storage, &sealed.key) {
+            (NodeSecretStorage::Keyring, node_identity::v1::SealedNodeSecretKey::Keyring(_)) => return true,
+            (NodeSecretStorage::Command { .. }, node_identity::v1::SealedNodeSecretKey::Command(_)) => return true,
+            _ => return false,
+        },
+    }
+}
+
+/// Load the node secret (generating one if there's none yet), and make sure it's
+/// stored the way `storage` says. A secret stored another way (ex: plaintext from
+/// before this was configured) is moved.
+pub async fn load_node_secret(
+    log: &Log,
+    db: &deadpool_sqlite::Object,
+    storage: &NodeSecretStorage,
+) -> Result<NodeSecret, loga::Error> {
+    let (plain, sealed) =
+        db
+            .interact(|conn| {
+                return Ok(
+                    (db::secret_get(conn)?, db::sealed_secret_get(conn)?),
+                ) as Result<(Option<NodeSecret>, Option<SealedNodeSecret>), loga::Error>;
+            })
+            .await
+            .stack_context(log, "Error interacting with database")?
+            .stack_context(log, "Error retrieving secret")?;
+    let secret;
+    match (&sealed, &plain) {
+        (Some(sealed), _) => match unseal(storage, sealed).await {
+            Ok(s) => {
+                secret = s;
+            },
+            Err(e) => {
+                let Some(plain) = &plain else {
+                    return Err(e).stack_context(
+                        log,
+                        "Error unsealing node secret, if the key is lost delete the node database to reset",
+                    );
+                };
+                log.log_err(
+                    loga::WARN,
+                    e.context("Error unsealing node secret, using the unencrypted copy in the node database"),
+                );
+                secret = plain.clone();
+            },
+        },
+        (None, Some(plain)) => {
+            secret = plain.clone();
+        },
+        (None, None) => {
+            (_, secret) = NodeIdentity::new();
+        },
+    }
+
+    // Move the secret if necessary
+    let old_keyring_user = match &sealed {
+        Some(SealedNodeSecret::V1(node_identity::v1::SealedNodeSecret {
+            key: node_identity::v1::SealedNodeSecretKey::Keyring(user),
+            ..
+        })) => Some(user.clone()),
+        _ => None,
+    };
+    match storage {
+        NodeSecretStorage::Plaintext => {
+            if plain.is_some() && sealed.is_none() {
+                return Ok(secret);
+            }
+            db.interact({
+                let secret = secret.clone();
+                move |conn| {
+                    db::secret_ensure(conn, &secret)?;
+                    db::sealed_secret_delete(conn)?;
+                    return Ok(()) as Result<_, loga::Error>;
+                }
+            })
+                .await
+                .stack_context(log, "Error interacting with database")?
+                .stack_context(log, "Error storing plaintext node secret")?;
+            if sealed.is_some() {
+                log.log(loga::INFO, "Moved node secret to plaintext storage");
+            }
+        },
+        NodeSecretStorage::Keyring => {
+            if plain.is_some() && sealed.as_ref().map(|s| sealed_key_matches(storage, s)).unwrap_or(false) {
+                return Ok(secret);
+            }
+            let new_sealed = match seal(storage, &secret).await {
+                Ok(s) => Some(s),
+                Err(e) => {
+                    log.log_err(
+                        loga::WARN,
+                        e.context("Error storing node secret key in keyring, keeping only the unencrypted copy"),
+                    );
+                    None
+                },
+            };
+            let sealed_now = new_sealed.is_some();
+            db.interact({
+                let secret = secret.clone();
+                move |conn| {
+                    db::secret_ensure(conn, &secret)?;
+                    if let Some(new_sealed) = new_sealed {
+                        db::sealed_secret_ensure(conn, &new_sealed)?;
+                    }
+                    return Ok(()) as Result<_, loga::Error>;
+                }
+            })
+                .await
+                .stack_context(log, "Error interacting with database")?
+                .stack_context(log, "Error storing node secret")?;
+            if !sealed_now {
+                return Ok(secret);
+            }
+            if plain.is_some() || sealed.is_some() {
+                log.log(loga::INFO, "Sealed node secret with newly configured storage");
+            }
+        },
+        NodeSecretStorage::Command { .. } => {
+            if plain.is_none() && sealed.as_ref().map(|s| sealed_key_matches(storage, s)).unwrap_or(false) {
+                return Ok(secret);
+            }
+            let new_sealed =
+                seal(storage, &secret)
+                    .await
+                    .stack_context(log, "Error sealing node secret; see `node.secret_storage` for other options")?;
+            db.interact(move |conn| {
+                db::sealed_secret_ensure(conn, &new_sealed)?;
+                db::secret_delete(conn)?;
+                return Ok(()) as Result<_, loga::Error>;
+            })
+                .await
+                .stack_context(log, "Error interacting with database")?
+                .stack_context(log, "Error storing sealed node secret")?;
+            if plain.is_some() || sealed.is_some() {
+                log.log(loga::INFO, "Sealed node secret with newly configured storage");
+            }
+        },
+    }
+    if let Some(user) = old_keyring_user {
+        if let Err(e) = spawn_blocking(move || {
+            return keyring_entry(&user)?.delete_password().context("Error deleting old key from keyring");
+        }).await.context("Keyring task failed").and_then(|r| r) {
+            log.log_err(loga::WARN, e);
+        }
+    }
+    return Ok(secret);
+}
+
+#[cfg(test)]
+mod test_secret_storage {
+    use {
+        super::{
+            seal,
+            unseal,
+        },
+        crate::interface::{
+            config::node::node_config::NodeSecretStorage,
+            stored::node_identity::{
+                NodeIdentity,
+                NodeSecret,
+            },
+        },
+    };
+
+    #[tokio::test]
+    async fn test_command_roundtrip() {
+        let (ident, secret) = NodeIdentity::new();
+        let storage = NodeSecretStorage::Command {
+            seal: vec!["cat".to_string()],
+            unseal: vec!["cat".to_string()],
+        };
+        let sealed = seal(&storage, &secret).await.unwrap();
+        let unsealed: NodeSecret = unseal(&storage, &sealed).await.unwrap();
+        assert_eq!(unsealed.get_identity(), ident);
+        assert!(unseal(&NodeSecretStorage::Keyring, &sealed).await.is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! An in-process network for running many nodes at once (see `spagh-sim`).
+//! Datagrams are delivered over channels, with configurable loss and latency.
+use {
+    rand::{
+        thread_rng,
+        Rng,
+    },
+    serde::Serialize,
+    std::{
+        collections::HashMap,
+        io,
+        net::SocketAddr,
+        sync::{
+            atomic::{
+                AtomicU64,
+                Ordering,
+            },
+            Arc,
+            Mutex,
+        },
+        time::Duration,
+    },
+    tokio::{
+        spawn,
+        sync::mpsc::{
+            unbounded_channel,
+            UnboundedReceiver,
+            UnboundedSender,
+        },
+        time::sleep,
+    },
+};
+
+#[derive(Clone, Debug)]
+pub struct SimNetworkConfig {
+    /// Fraction of datagrams to drop, 0 to 1
+    pub loss: f64,
+    /// Each datagram is delayed a random duration between `latency_min` and
+    /// `latency_max`
+    pub latency_min: Duration,
+    pub latency_max: Duration,
+}
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct SimNetworkStats {
+    pub sent: u64,
+    /// Datagrams lost, including those sent to addresses with no socket
+    pub dropped: u64,
+}
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+struct SimNetwork_ {
+    config: SimNetworkConfig,
+    sockets: Mutex<HashMap<SocketAddr, UnboundedSender<Datagram>>>,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+}
+
+#[derive(Clone)]
+pub struct SimNetwork(Arc<SimNetwork_>);
+
+impl SimNetwork {
+    pub fn new(config: SimNetworkConfig) -> SimNetwork {
+        return SimNetwork(Arc::new(SimNetwork_ {
+            config: config,
+            sockets: Mutex::new(HashMap::new()),
+            sent: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }));
+    }
+
+    /// Create a socket receiving datagrams sent to `addr`. Replaces any existing
+    /// socket at the address.
+    pub fn bind(&self, addr: SocketAddr) -> SimSocket {
+        let (write, read) = unbounded_channel();
+        self.0.sockets.lock().unwrap().insert(addr, write);
+        return SimSocket {
+            net: self.clone(),
+            addr: addr,
+            recv: tokio::sync::Mutex::new(read),
+        };
+    }
+
+    pub fn stats(&self) -> SimNetworkStats {
+        return SimNetworkStats {
+            sent: self.0.sent.load(Ordering::Relaxed),
+            dropped: self.0.dropped.load(Ordering::Relaxed),
+        };
+    }
+
+    fn send(&self, from: SocketAddr, to: SocketAddr, data: Vec<u8>) {
+        self.0.sent.fetch_add(1, Ordering::Relaxed);
+        let config = &self.0.config;
+        let mut rng = thread_rng();
+        if rng.gen::<f64>() < config.loss {
+            self.0.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let Some(dest) = self.0.sockets.lock().unwrap().get(&to).cloned() else {
+            self.0.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        let latency = if config.latency_max > config.latency_min {
+            rng.gen_range(config.latency_min ..= config.latency_max)
+        } else {
+            config.latency_min
+        };
+        spawn(async move {
+            sleep(latency).await;
+            _ = dest.send((data, from));
+        });
+    }
+}
+
+/// A socket on a `SimNetwork`, with the same send and receive behavior as a UDP
+/// socket.
+pub struct SimSocket {
+    net: SimNetwork,
+    addr: SocketAddr,
+    recv: tokio::sync::Mutex<UnboundedReceiver<Datagram>>,
+}
+
+impl SimSocket {
+    pub fn local_addr(&self) -> SocketAddr {
+        return self.addr;
+    }
+
+    pub async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.net.send(self.addr, addr, buf.to_vec());
+        return Ok(buf.len());
+    }
+
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let Some((data, from)) = self.recv.lock().await.recv().await else {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "Simulated network closed"));
+        };
+
+        // Like UDP, datagrams larger than the buffer are truncated
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        return Ok((len, from));
+    }
+}
+
+#[cfg(test)]
+mod test_sim {
+    use {
+        super::{
+            SimNetwork,
+            SimNetworkConfig,
+        },
+        std::time::Duration,
+    };
+
+    #[tokio::test]
+    async fn test_deliver() {
+        let net = SimNetwork::new(SimNetworkConfig {
+            loss: 0.,
+            latency_min: Duration::ZERO,
+            latency_max: Duration::from_millis(5),
+        });
+        let a = net.bind("10.0.0.1:1".parse().unwrap());
+        let b = net.bind("10.0.0.2:1".parse().unwrap());
+        a.send_to(b"hello", b.local_addr()).await.unwrap();
+        let mut buf = [0u8; 3];
+        let (len, from) = b.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"hel");
+        assert_eq!(from, a.local_addr());
+    }
+
+    #[tokio::test]
+    async fn test_loss() {
+        let net = SimNetwork::new(SimNetworkConfig {
+            loss: 1.,
+            latency_min: Duration::ZERO,
+            latency_max: Duration::ZERO,
+        });
+        let a = net.bind("10.0.0.1:1".parse().unwrap());
+        a.send_to(b"hello", "10.0.0.2:1".parse().unwrap()).await.unwrap();
+        a.send_to(b"hello", a.local_addr()).await.unwrap();
+        let stats = net.stats();
+        assert_eq!(stats.sent, 2);
+        assert_eq!(stats.dropped, 2);
+    }
+}
@@ -0,0 +1,525 @@
+//! Transports other than the node's own UDP socket, for nodes that can't or
+//! shouldn't expose their IP address directly.
+//!
+//! The node only needs to send and receive datagrams tagged with a peer address,
+//! and treats those addresses as opaque: they're whatever the transport uses to
+//! reach peers, and what peers see as the node's address. With the relay transport
+//! here, peers see the relay's UDP address and the node's traffic to the relay can
+//! go through a SOCKS5 proxy (ex: Tor).
+//!
+//! The relay protocol is a TCP stream of frames. The client first sends an
+//! authentication token (a `u16` length then the token bytes), then both sides send
+//! datagrams as: address family (`4` or `6`), IP address, port (`u16`), payload
+//! length (`u16`), payload. Frames from the client are sent from the relay's UDP
+//! socket to the frame address, and datagrams the relay receives are forwarded to
+//! the client with the frame address set to the sender. Numbers are big endian.
+//!
+//! The stream isn't encrypted, so the token and the relayed datagrams are visible
+//! to anything on the path between the node and the relay. The link must be
+//! private: a Tor onion service, a VPN, or a loopback/LAN address.
+use {
+    crate::{
+        ta_res,
+        utils::task_status::TrackedTasks,
+    },
+    async_trait::async_trait,
+    constant_time_eq::constant_time_eq,
+    loga::{
+        ea,
+        ErrContext,
+        Log,
+        ResultContext,
+    },
+    std::{
+        io,
+        net::{
+            IpAddr,
+            Ipv4Addr,
+            Ipv6Addr,
+            SocketAddr,
+        },
+        sync::{
+            atomic::{
+                AtomicU64,
+                Ordering,
+            },
+            Arc,
+        },
+        time::Duration,
+    },
+    taskmanager::TaskManager,
+    tokio::{
+        io::{
+            AsyncRead,
+            AsyncReadExt,
+            AsyncWrite,
+            AsyncWriteExt,
+        },
+        net::{
+            tcp::OwnedWriteHalf,
+            TcpListener,
+            TcpStream,
+            UdpSocket,
+        },
+        select,
+        sync::{
+            mpsc::{
+                channel,
+                error::TrySendError,
+                Receiver,
+                Sender,
+            },
+            Mutex,
+        },
+        time::{
+            sleep,
+            timeout,
+        },
+    },
+};
+
+/// Datagrams waiting for the node (in the node's receive queue, or the relay's
+/// queue for the connection) beyond this many are dropped, like a full socket
+/// buffer.
+pub const RELAY_QUEUE: usize = 1024;
+
+fn relay_reconnect_delay() -> Duration {
+    return Duration::from_secs(5);
+}
+
+fn relay_handshake_timeout() -> Duration {
+    return Duration::from_secs(30);
+}
+
+/// A way for the node to send and receive datagrams.
+#[async_trait]
+pub trait NodeTransport: Send + Sync {
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+    /// Receive a datagram, truncated to the size of `buf`.
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+
+    /// Whether peers see a relay's address rather than the node's own. This is
+    /// advertised to peers.
+    fn relayed(&self) -> bool;
+}
+
+fn encode_frame(addr: SocketAddr, payload: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let len =
+        u16::try_from(
+            payload.len(),
+        ).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Datagram too large for relay frame"))?;
+    let mut out = Vec::with_capacity(1 + 16 + 2 + 2 + payload.len());
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            out.push(4);
+            out.extend_from_slice(&ip.octets());
+        },
+        IpAddr::V6(ip) => {
+            out.push(6);
+            out.extend_from_slice(&ip.octets());
+        },
+    }
+    out.extend_from_slice(&addr.port().to_be_bytes());
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(payload);
+    return Ok(out);
+}
+
+async fn read_frame(r: &mut (impl AsyncRead + Unpin)) -> io::Result<(SocketAddr, Vec<u8>)> {
+    let ip = match r.read_u8().await? {
+        4 => {
+            let mut ip = [0u8; 4];
+            r.read_exact(&mut ip).await?;
+            IpAddr::V4(Ipv4Addr::from(ip))
+        },
+        6 => {
+            let mut ip = [0u8; 16];
+            r.read_exact(&mut ip).await?;
+            IpAddr::V6(Ipv6Addr::from(ip))
+        },
+        f => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Unknown relay frame address family {}", f)));
+        },
+    };
+    let port = r.read_u16().await?;
+    let len = r.read_u16().await?;
+    let mut payload = vec![0u8; len as usize];
+    r.read_exact(&mut payload).await?;
+    return Ok((SocketAddr::new(ip, port), payload));
+}
+
+async fn write_token(w: &mut (impl AsyncWrite + Unpin), token: &[u8]) -> io::Result<()> {
+    let len =
+        u16::try_from(token.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Relay token too long"))?;
+    w.write_all(&len.to_be_bytes()).await?;
+    w.write_all(token).await?;
+    return Ok(());
+}
+
+async fn read_token(r: &mut (impl AsyncRead + Unpin)) -> io::Result<Vec<u8>> {
+    let len = r.read_u16().await?;
+    let mut token = vec![0u8; len as usize];
+    r.read_exact(&mut token).await?;
+    return Ok(token);
+}
+
+/// Split `host:port` (with the host in brackets if it's an IPv6 address).
+fn split_host_port(addr: &str) -> Result<(String, u16), loga::Error> {
+    let Some((host, port)) = addr.rsplit_once(':') else {
+        return Err(loga::err_with("Relay address is missing a port", ea!(addr = addr)));
+    };
+    let port = port.parse::<u16>().context_with("Invalid port in relay address", ea!(addr = addr))?;
+    let host = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+    if host.is_empty() {
+        return Err(loga::err_with("Relay address is missing a host", ea!(addr = addr)));
+    }
+    return Ok((host.to_string(), port));
+}
+
+/// Open a TCP connection to `host:port` through a SOCKS5 proxy, without
+/// authentication. Host names are resolved by the proxy, so `.onion` addresses work
+/// with Tor.
+async fn socks5_connect(proxy: SocketAddr, host: &str, port: u16) -> Result<TcpStream, loga::Error> {
+    let mut conn = TcpStream::connect(proxy).await.context("Error connecting to SOCKS5 proxy")?;
+    conn.write_all(&[5, 1, 0]).await.context("Error sending SOCKS5 greeting")?;
+    let mut greeting = [0u8; 2];
+    conn.read_exact(&mut greeting).await.context("Error reading SOCKS5 greeting reply")?;
+    if greeting != [5, 0] {
+        return Err(
+            loga::err_with("SOCKS5 proxy refused connecting without authentication", ea!(reply = greeting[1])),
+        );
+    }
+    let mut req = vec![5, 1, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            req.push(1);
+            req.extend_from_slice(&ip.octets());
+        },
+        Ok(IpAddr::V6(ip)) => {
+            req.push(4);
+            req.extend_from_slice(&ip.octets());
+        },
+        Err(_) => {
+            let Ok(len) = u8::try_from(host.len()) else {
+                return Err(loga::err_with("Host name too long for SOCKS5", ea!(host = host)));
+            };
+            req.push(3);
+            req.push(len);
+            req.extend_from_slice(host.as_bytes());
+        },
+    }
+    req.extend_from_slice(&port.to_be_bytes());
+    conn.write_all(&req).await.context("Error sending SOCKS5 connect request")?;
+    let mut reply = [0u8; 4];
+    conn.read_exact(&mut reply).await.context("Error reading SOCKS5 connect reply")?;
+    if reply[1] != 0 {
+        return Err(loga::err_with("SOCKS5 proxy failed to connect", ea!(reply = reply[1])));
+    }
+    let bound_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => conn.read_u8().await.context("Error reading SOCKS5 connect reply")? as usize,
+        a => return Err(loga::err_with("Unknown address type in SOCKS5 connect reply", ea!(atyp = a))),
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    conn.read_exact(&mut bound).await.context("Error reading SOCKS5 connect reply")?;
+    return Ok(conn);
+}
+
+struct RelayTransportInner {
+    write: Mutex<Option<OwnedWriteHalf>>,
+    recv: Mutex<Receiver<(SocketAddr, Vec<u8>)>>,
+}
+
+/// Sends and receives the node's datagrams through a relay (see
+/// `start_relay_server`), reconnecting whenever the connection drops. Datagrams
+/// sent while disconnected fail.
+#[derive(Clone)]
+pub struct RelayTransport(Arc<RelayTransportInner>);
+
+impl RelayTransport {
+    /// * `relay_addr`: The relay's TCP address, as `host:port`.
+    ///
+    /// * `socks5_proxy`: Connect to the relay through this SOCKS5 proxy.
+    ///
+    /// * `token`: Must match the relay's token.
+    pub fn new(
+        log: &Log,
+        tm: &TaskManager,
+        relay_addr: &str,
+        socks5_proxy: Option<SocketAddr>,
+        token: String,
+    ) -> Result<RelayTransport, loga::Error> {
+        let (host, port) = split_host_port(relay_addr)?;
+        let (recv_tx, recv_rx) = channel(RELAY_QUEUE);
+        let out = RelayTransport(Arc::new(RelayTransportInner {
+            write: Mutex::new(None),
+            recv: Mutex::new(recv_rx),
+        }));
+        tm.tracked_task("Node - relay connection", {
+            let log = log.fork(ea!(subsys = "relay", relay = relay_addr));
+            let tm = tm.clone();
+            let out = out.clone();
+            async move {
+                loop {
+                    let res = select!{
+                        _ = tm.until_terminate() => {
+                            return;
+                        }
+                        r = out.run_connection(&log, &host, port, socks5_proxy, &token, &recv_tx) => r,
+                    };
+                    *out.0.write.lock().await = None;
+                    if let Err(e) = res {
+                        log.log_err(loga::WARN, e.context("Relay connection failed, reconnecting"));
+                    }
+                    select!{
+                        _ = tm.until_terminate() => {
+                            return;
+                        }
+                        _ = sleep(relay_reconnect_delay()) => {
+                        }
+                    }
+                }
+            }
+        });
+        return Ok(out);
+    }
+
+    async fn run_connection(
+        &self,
+        log: &Log,
+        host: &str,
+        port: u16,
+        socks5_proxy: Option<SocketAddr>,
+        token: &str,
+        recv_tx: &Sender<(SocketAddr, Vec<u8>)>,
+    ) -> Result<(), loga::Error> {
+        let conn = timeout(relay_handshake_timeout(), async {
+            ta_res!(TcpStream);
+            let mut conn = match socks5_proxy {
+                Some(proxy) => socks5_connect(proxy, host, port).await?,
+                None => TcpStream::connect((host, port)).await.context("Error connecting to relay")?,
+            };
+            write_token(&mut conn, token.as_bytes()).await.context("Error sending relay token")?;
+            return Ok(conn);
+        }).await.map_err(|_| loga::err("Timed out connecting to relay"))??;
+        _ = conn.set_nodelay(true);
+        let (mut read, write) = conn.into_split();
+        *self.0.write.lock().await = Some(write);
+        log.log(loga::INFO, "Connected to relay");
+        loop {
+            let (addr, payload) = read_frame(&mut read).await.context("Error reading from relay")?;
+            _ = recv_tx.try_send((addr, payload));
+        }
+    }
+}
+
+#[async_trait]
+impl NodeTransport for RelayTransport {
+    async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let frame = encode_frame(addr, buf)?;
+        let mut write = self.0.write.lock().await;
+        let Some(w) = write.as_mut() else {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "Not connected to relay"));
+        };
+        w.write_all(&frame).await?;
+        return Ok(buf.len());
+    }
+
+    async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let Some((addr, payload)) = self.0.recv.lock().await.recv().await else {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "Relay connection task stopped"));
+        };
+        let len = payload.len().min(buf.len());
+        buf[..len].copy_from_slice(&payload[..len]);
+        return Ok((len, addr));
+    }
+
+    fn relayed(&self) -> bool {
+        return true;
+    }
+}
+
+/// Relay datagrams between a UDP socket and one node connected over TCP (see
+/// `RelayTransport`). A newly authenticated connection replaces the current one,
+/// so a node that reconnects doesn't wait for the old connection to time out.
+///
+/// Datagrams for the node are queued for a writer task per connection, and dropped
+/// when the queue is full (`RELAY_QUEUE`) so a slow connection doesn't block
+/// receiving.
+pub async fn start_relay_server(
+    log: &Log,
+    tm: &TaskManager,
+    udp_bind_addr: SocketAddr,
+    tcp_bind_addr: SocketAddr,
+    token: String,
+) -> Result<(), loga::Error> {
+    let log = log.fork(ea!(sys = "relay_server", udp = udp_bind_addr, tcp = tcp_bind_addr));
+    let udp = Arc::new(UdpSocket::bind(udp_bind_addr).await.stack_context(&log, "Error binding relay UDP socket")?);
+    let listener = TcpListener::bind(tcp_bind_addr).await.stack_context(&log, "Error binding relay TCP listener")?;
+
+    // The connected node's outgoing frame queue, with a generation so a finished
+    // connection doesn't clear a newer one
+    let client = Arc::new(std::sync::Mutex::new(None as Option<(u64, Sender<Vec<u8>>)>));
+    let generation = Arc::new(AtomicU64::new(0));
+    tm.tracked_task("Relay - UDP", {
+        let log = log.clone();
+        let tm = tm.clone();
+        let udp = udp.clone();
+        let client = client.clone();
+        async move {
+            let mut buf = vec![0u8; u16::MAX as usize];
+            loop {
+                let received = select!{
+                    _ = tm.until_terminate() => {
+                        return;
+                    }
+                    r = udp.recv_from(&mut buf) => r,
+                };
+                let (len, addr) = match received {
+                    Ok(r) => r,
+                    Err(e) => {
+                        log.log_err(loga::DEBUG, e.context("Error receiving datagram"));
+                        continue;
+                    },
+                };
+                let Ok(frame) = encode_frame(addr, &buf[..len]) else {
+                    continue;
+                };
+                let Some((generation, queue)) = client.lock().unwrap().clone() else {
+                    continue;
+                };
+                match queue.try_send(frame) {
+                    Ok(_) => { },
+                    Err(TrySendError::Full(_)) => { },
+                    Err(TrySendError::Closed(_)) => {
+                        // The writer stopped after an error
+                        let mut client = client.lock().unwrap();
+                        if client.as_ref().is_some_and(|(g, _)| *g == generation) {
+                            *client = None;
+                        }
+                    },
+                }
+            }
+        }
+    });
+    tm.tracked_task("Relay - TCP", {
+        let log = log.clone();
+        let tm = tm.clone();
+        async move {
+            loop {
+                let accepted = select!{
+                    _ = tm.until_terminate() => {
+                        return;
+                    }
+                    r = listener.accept() => r,
+                };
+                let (mut conn, peer) = match accepted {
+                    Ok(r) => r,
+                    Err(e) => {
+                        log.log_err(loga::DEBUG, e.context("Error accepting relay connection"));
+                        continue;
+                    },
+                };
+                let log = log.fork(ea!(peer = peer));
+                let tm = tm.clone();
+                let udp = udp.clone();
+                let client = client.clone();
+                let token = token.clone();
+                let generation = generation.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(async move {
+                    match timeout(relay_handshake_timeout(), read_token(&mut conn)).await {
+                        Ok(Ok(got)) if constant_time_eq(&got, token.as_bytes()) => { },
+                        Ok(Ok(_)) => {
+                            log.log(loga::WARN, "Rejected relay connection with wrong token");
+                            return;
+                        },
+                        Ok(Err(e)) => {
+                            log.log_err(loga::DEBUG, e.context("Error reading relay token"));
+                            return;
+                        },
+                        Err(_) => {
+                            log.log(loga::DEBUG, "Timed out waiting for relay token");
+                            return;
+                        },
+                    }
+                    _ = conn.set_nodelay(true);
+                    let (mut read, mut write) = conn.into_split();
+                    let (queue_tx, mut queue_rx) = channel::<Vec<u8>>(RELAY_QUEUE);
+                    tokio::spawn({
+                        let log = log.clone();
+                        async move {
+                            // Ends when the connection is replaced or closed, dropping the queue
+                            while let Some(frame) = queue_rx.recv().await {
+                                if let Err(e) = write.write_all(&frame).await {
+                                    log.log_err(loga::DEBUG, e.context("Error forwarding datagram to relay client"));
+                                    return;
+                                }
+                            }
+                        }
+                    });
+                    *client.lock().unwrap() = Some((generation, queue_tx));
+                    log.log(loga::INFO, "Relay client connected");
+                    loop {
+                        let frame = select!{
+                            _ = tm.until_terminate() => {
+                                break;
+                            }
+                            f = read_frame(&mut read) => f,
+                        };
+                        let (addr, payload) = match frame {
+                            Ok(f) => f,
+                            Err(e) => {
+                                log.log_err(loga::INFO, e.context("Relay client disconnected"));
+                                break;
+                            },
+                        };
+                        if let Err(e) = udp.send_to(&payload, addr).await {
+                            log.log_err(loga::DEBUG, e.context_with("Error relaying datagram", ea!(to = addr)));
+                        }
+                    }
+                    let mut client = client.lock().unwrap();
+                    if client.as_ref().is_some_and(|(g, _)| *g == generation) {
+                        *client = None;
+                    }
+                });
+            }
+        }
+    });
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test_transport {
+    use {
+        super::{
+            encode_frame,
+            read_frame,
+            split_host_port,
+        },
+        std::net::SocketAddr,
+    };
+
+    #[tokio::test]
+    async fn test_frame_roundtrip() {
+        let mut stream = vec![];
+        let a = "192.0.2.1:48390".parse::<SocketAddr>().unwrap();
+        let b = "[2001:db8::1]:1234".parse::<SocketAddr>().unwrap();
+        stream.extend(encode_frame(a, b"hello").unwrap());
+        stream.extend(encode_frame(b, b"").unwrap());
+        let mut r = stream.as_slice();
+        assert_eq!(read_frame(&mut r).await.unwrap(), (a, b"hello".to_vec()));
+        assert_eq!(read_frame(&mut r).await.unwrap(), (b, vec![]));
+        assert!(read_frame(&mut r).await.is_err());
+        assert!(encode_frame(a, &vec![0u8; 70000]).is_err());
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("relay.example:4000").unwrap(), ("relay.example".to_string(), 4000));
+        assert_eq!(split_host_port("[2001:db8::1]:4000").unwrap(), ("2001:db8::1".to_string(), 4000));
+        assert!(split_host_port("relay.example").is_err());
+        assert!(split_host_port(":4000").is_err());
+    }
+}
@@ -2,41 +2,112 @@ use {
     crate::{
         cap_fn,
         interface::{
+            config::node::publisher_config::{
+                PublishPolicyConfig,
+                PublisherRetentionConfig,
+                PublisherWatchdogConfig,
+            },
             stored::{
                 self,
                 announcement::Announcement,
                 identity::Identity,
-                record::record_utils::{
-                    join_record_key,
-                    RecordKey,
+                record::{
+                    addr_pref_record::latest::IpFamily,
+                    alias_record::{
+                        build_alias_key,
+                        Alias,
+                        ALIAS_MAX_HOPS,
+                    },
+                    key_alias_record::{
+                        build_key_alias_key,
+                        KeyAlias,
+                        KEY_ALIAS_MAX_HOPS,
+                        KEY_SUFFIX_KEY_ALIAS,
+                    },
+                    record_utils::{
+                        join_record_key,
+                        normalize_record_key,
+                        split_record_key,
+                        RecordKey,
+                    },
                 },
             },
             wire::{
                 self,
-                api::admin::v1::{
-                    AdminAllowIdentityBody,
-                    AdminIdentity,
+                api::{
+                    admin::v1::{
+                        AdminAllowIdentityBody,
+                        AdminAuditEntry,
+                        AdminAuditEvent,
+                        AdminIdentity,
+                        AdminIdentityUsage,
+                        AdminMaintenance,
+                        AdminReverseMatch,
+                        AdminUsageReport,
+                        PublisherWatchdogAlert,
+                        PublisherWatchdogStatus,
+                    },
+                    error::latest::ErrorCode,
+                },
+            },
+        },
+        service::{
+            node::Node,
+            publisher::{
+                reverse::{
+                    reverse_matches,
+                    ReverseQuery,
+                },
+                storage::{
+                    db_key,
+                    PublisherStorage,
+                    StoredValue,
+                },
+                watchdog::{
+                    check_announcement,
+                    Watchdog,
                 },
             },
         },
-        service::node::Node,
         ta_res,
         ta_vis_res,
         utils::{
+            admin_auth::AdminTokens,
+            api_error::{
+                response_bad_request,
+                response_bad_signature,
+                response_error,
+                response_expired,
+                response_internal,
+                response_not_found,
+                response_unauthorized,
+                response_unavailable,
+            },
             blob::{
                 Blob,
                 ToBlob,
             },
             db_util::{
-                setup_db,
-                DbTx,
+                ListFilter,
+                PAGE_MAX,
+            },
+            graceful::{
+                handle_https_conn,
+                serve_draining,
             },
             identity_secret::IdentitySigner,
+            jsonrpc,
+            privacy::redact_id,
             publish_util,
+            recent_errors::log_warn_err,
+            request_id::request_trace_headers,
             signed::IdentSignatureMethods,
+            task_status::TrackedTasks,
             tls_util::{
                 cert_der_hash,
+                cert_der_sign,
                 create_leaf_cert_der_local,
+                ClientCertIdentity,
             },
             ResultVisErr,
             VisErr,
@@ -50,31 +121,35 @@ use {
         NaiveDateTime,
         Utc,
     },
-    deadpool_sqlite::Pool,
     flowcontrol::shed,
-    good_ormning_runtime::GoodError,
     http::{
+        header::{
+            CONTENT_TYPE,
+            ETAG,
+            IF_NONE_MATCH,
+        },
+        HeaderValue,
         Method,
         Response,
+        StatusCode,
+        Uri,
     },
     http_body_util::BodyExt,
-    htwrap::htserve::{
-        self,
-        responses::{
-            response_200,
-            response_200_json,
-            response_400,
-            response_401,
-            response_404,
-            response_503,
-        },
-        auth::{
-            check_auth_token_hash,
-            AuthTokenHash,
+    htwrap::{
+        htreq,
+        htserve::{
+            self,
+            responses::{
+                body_full,
+                response_200,
+                response_200_json,
+            },
         },
     },
+    hyper::body::Body,
     loga::{
         ea,
+        DebugDisplay,
         Log,
         ResultContext,
     },
@@ -84,23 +159,241 @@ use {
         PrivateKeyDer,
         PrivatePkcs8KeyDer,
     },
-    serde::Deserialize,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    sha2::{
+        Digest,
+        Sha256,
+    },
     std::{
-        collections::HashMap,
-        net::SocketAddr,
-        path::Path,
+        collections::{
+            hash_map::Entry,
+            HashMap,
+            HashSet,
+        },
+        net::{
+            IpAddr,
+            SocketAddr,
+        },
         str::FromStr,
         sync::{
+            atomic::{
+                AtomicU64,
+                Ordering,
+            },
             Arc,
             Mutex,
             RwLock,
         },
     },
     taskmanager::TaskManager,
+    tokio::{
+        net::TcpStream,
+        select,
+        sync::broadcast,
+        time::{
+            timeout,
+            timeout_at,
+            Instant,
+        },
+    },
 };
 
+/// The `ETag` for a resolve response with values as of `now`: a hash of each key's
+/// TTL, data, and publish time. Responses with the same tag only differ in
+/// expiration times.
+pub fn resolve_etag(now: DateTime<Utc>, values: &HashMap<RecordKey, wire::resolve::latest::ResolveValue>) -> String {
+    let mut keys = values.keys().collect::<Vec<_>>();
+    keys.sort();
+    let mut hash = Sha256::new();
+    for k in keys {
+        let v = &values[k];
+        hash.update(serde_json::to_vec(&(k, (v.expires - now).num_seconds(), &v.data, &v.published)).unwrap());
+        hash.update([0u8]);
+    }
+    return format!("\"{}\"", zbase32::encode_full_bytes(&hash.finalize()));
+}
+
+/// Whether an `If-None-Match` header value includes the tag.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    return if_none_match.split(',').any(|t| t.trim() == etag || t.trim() == "*");
+}
+
+#[cfg(test)]
+mod test_resolve_etag {
+    use {
+        super::{
+            etag_matches,
+            resolve_etag,
+        },
+        crate::interface::wire,
+        chrono::{
+            Duration,
+            Utc,
+        },
+        serde_json::json,
+        std::collections::HashMap,
+    };
+
+    #[test]
+    fn test_etag() {
+        let value = |now, ttl, data| wire::resolve::latest::ResolveValue {
+            expires: now + Duration::try_minutes(ttl).unwrap(),
+            data: Some(data),
+            published: None,
+            provenance: None,
+            external: None,
+        };
+        let key = vec!["dns/a".to_string()];
+        let t1 = Utc::now();
+        let t2 = t1 + Duration::try_minutes(3).unwrap();
+        let a = resolve_etag(t1, &HashMap::from([(key.clone(), value(t1, 60, json!("x")))]));
+
+        // Only the expiration time changed
+        assert_eq!(a, resolve_etag(t2, &HashMap::from([(key.clone(), value(t2, 60, json!("x")))])));
+        assert_ne!(a, resolve_etag(t2, &HashMap::from([(key.clone(), value(t2, 30, json!("x")))])));
+        assert_ne!(a, resolve_etag(t2, &HashMap::from([(key.clone(), value(t2, 60, json!("y")))])));
+        assert!(etag_matches(&format!("\"other\", {}", a), &a));
+        assert!(!etag_matches("\"other\"", &a));
+    }
+}
+
+/// How long a watch request waits for changes before returning unchanged.
+const WATCH_WAIT: std::time::Duration = std::time::Duration::from_secs(60);
+
 pub mod db;
 pub mod admin_db;
+pub mod reverse;
+pub mod storage;
+pub mod watchdog;
+
+fn usage_rollup_interval() -> Duration {
+    return Duration::try_hours(1).unwrap();
+}
+
+// Usage rollups older than this are deleted
+pub fn usage_retention() -> Duration {
+    return Duration::try_days(400).unwrap();
+}
+
+/// Whether the value is a tombstone for a cleared key that's been served for at
+/// least the tombstone TTL, so it can be deleted.
+fn tombstone_expired(value: &StoredValue, now: DateTime<Utc>, tombstone_ttl: Duration) -> bool {
+    let stored::record::RecordValue::V1(v) = &value.value;
+    if v.data.is_some() {
+        return false;
+    }
+    let Some(published) = value.published else {
+        return false;
+    };
+    return now - published >= tombstone_ttl;
+}
+
+#[cfg(test)]
+mod test_tombstone {
+    use {
+        super::tombstone_expired,
+        crate::{
+            interface::stored,
+            service::publisher::storage::StoredValue,
+        },
+        chrono::{
+            Duration,
+            Utc,
+        },
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_tombstone_expired() {
+        let now = Utc::now();
+        let ttl = Duration::try_minutes(10).unwrap();
+        let value = |data, published| StoredValue {
+            value: stored::record::RecordValue::V1(stored::record::v1::RecordValue {
+                ttl: 10,
+                data: data,
+            }),
+            published: published,
+        };
+        assert!(tombstone_expired(&value(None, Some(now - Duration::try_minutes(11).unwrap())), now, ttl));
+        assert!(!tombstone_expired(&value(None, Some(now - Duration::try_minutes(9).unwrap())), now, ttl));
+
+        // Values with data, and removals from before publish times were stored, are kept
+        assert!(!tombstone_expired(&value(Some(json!("x")), Some(now - Duration::try_days(1).unwrap())), now, ttl));
+        assert!(!tombstone_expired(&value(None, None), now, ttl));
+    }
+}
+
+#[derive(Default)]
+struct UsageCounts {
+    resolves: u64,
+    bytes: u64,
+}
+
+/// Publisher activity counts since startup.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PublisherStats {
+    /// Accepted publish requests (record set changes)
+    pub publishes: u64,
+    /// Announcements stored (not superseded by newer announcements in the network)
+    pub announces: u64,
+    /// Resolve requests from other nodes served
+    pub resolves: u64,
+}
+
+/// How the publisher is serving one identity.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PublisherIdentityStatus {
+    /// In the admin allowed identities list
+    pub allowed: bool,
+    /// When the announcement stored here was made. Null if the identity isn't hosted
+    /// here.
+    pub announced: Option<DateTime<Utc>>,
+    /// Whether this publisher is listed in the stored announcement
+    pub listed: bool,
+    /// Marked dormant by the retention policy, so values aren't served
+    pub dormant: bool,
+    /// Retry-after seconds, if the publisher is in maintenance mode
+    pub maintenance: Option<u32>,
+    /// Keys with published values
+    pub keys: Vec<RecordKey>,
+}
+
+#[derive(Default)]
+struct PublisherCounters {
+    publishes: AtomicU64,
+    announces: AtomicU64,
+    resolves: AtomicU64,
+}
+
+// Usage since the last rollup
+struct UsagePeriod {
+    start: DateTime<Utc>,
+    identities: HashMap<Identity, UsageCounts>,
+}
+
+struct PublishPolicy {
+    url: Uri,
+    fail_open: bool,
+    timeout: std::time::Duration,
+}
+
+struct RetentionPolicy {
+    dormant_after: Duration,
+    stop_serving: bool,
+    purge_after: Option<Duration>,
+}
+
+// What a retention sweep did to an identity
+enum RetentionAction {
+    MarkedDormant,
+    Purge,
+}
 
 pub struct SingleCertResolver(pub Arc<RwLock<Arc<rustls::sign::CertifiedKey>>>);
 
@@ -122,40 +415,110 @@ pub struct Publisher {
     log: Log,
     node: Node,
     cert_pub_hash: Blob,
-    advertise_addr: SocketAddr,
-    db_pool: Pool,
+    certs: stored::publisher::latest::Certs,
+    // Not empty
+    advertise_addrs: Vec<SocketAddr>,
+    storage: Arc<dyn PublisherStorage>,
+    max_db_size: Option<u64>,
+    // In-memory read counts for published keys, None if disabled
+    read_stats: Option<Mutex<HashMap<Identity, HashMap<RecordKey, wire::api::publish::latest::KeyReadStats>>>>,
+    // Retry-after seconds sent to resolvers while in maintenance mode
+    maintenance: Mutex<Option<u32>>,
+    // Identities whose record sets changed, for watch requests
+    changes: broadcast::Sender<Identity>,
+    usage: Mutex<UsagePeriod>,
+    counters: PublisherCounters,
+    publish_policy: Option<PublishPolicy>,
+    retention: Option<RetentionPolicy>,
+    // If set, only resolvers with these identities can get values
+    authorized_resolvers: Option<HashSet<Identity>>,
+    watchdog: Option<Watchdog>,
+    // If set, cleared keys are served as removed for this many minutes
+    tombstone_ttl: Option<u32>,
 }
 
 impl Publisher {
     /// Launch a new dynamic publisher in task manager.
     ///
-    /// * `advertise_addr`: The address to use in announcements to the network. This should
-    ///   be the internet-routable address of this instance (your public ip, plus the port
-    ///   you're forwarding to the host)
+    /// * `advertise_addrs`: The addresses to use in announcements to the network. These
+    ///   should be the internet-routable addresses of this instance (your public ips,
+    ///   plus the ports you're forwarding to the host), ex: one IPv4 and one IPv6.
+    ///   The first is preferred by older resolvers.
+    ///
+    /// * `storage`: Where to keep announcements, published values, and other state
+    ///
+    /// * `read_stats`: Track per-key read counts, for identity owners to query
+    ///
+    /// * `max_db_size`: Reject publishes that set values once the storage is larger
+    ///   than this (bytes). Clearing values is always allowed.
+    ///
+    /// * `publish_policy`: An external endpoint to check publish requests with before
+    ///   accepting them
+    ///
+    /// * `retention`: Mark identities without recent activity dormant, optionally
+    ///   stop serving and later purge them
+    ///
+    /// * `authorized_resolvers`: Only answer resolve requests signed by these resolver
+    ///   identities
+    ///
+    /// * `watchdog`: Periodically check that the network's announcements for hosted
+    ///   identities list this publisher
+    ///
+    /// * `tombstone_ttl`: Serve cleared keys as removed (with a publish time) for this
+    ///   many minutes before deleting them
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         log: &Log,
         tm: &TaskManager,
         node: Node,
         bind_addr: SocketAddr,
-        advertise_addr: SocketAddr,
-        persistent_dir: &Path,
+        advertise_addrs: Vec<SocketAddr>,
+        storage: Arc<dyn PublisherStorage>,
+        read_stats: bool,
+        max_db_size: Option<u64>,
+        publish_policy: Option<&PublishPolicyConfig>,
+        retention: Option<&PublisherRetentionConfig>,
+        authorized_resolvers: Option<Vec<Identity>>,
+        watchdog: Option<&PublisherWatchdogConfig>,
+        tombstone_ttl: Option<u32>,
+        shutdown_grace: std::time::Duration,
     ) -> Result<Arc<Publisher>, loga::Error> {
-        let db_pool =
-            setup_db(&persistent_dir.join("publisher.sqlite3"), db::migrate)
-                .await
-                .stack_context(log, "Error initializing database")?;
-
-        // Prepare publisher certs for publisher-resolver communication
-        let certs = {
-            match db_pool
-                .tx(|conn| Ok(db::get_certs(conn)?))
-                .await
-                .stack_context(log, "Error looking up certs")? {
-                Some(c) => match c {
-                    stored::publisher::Certs::V1(v1) => {
-                        v1
-                    },
+        if tombstone_ttl.is_some_and(|t| t > i32::MAX as u32) {
+            return Err(log.err("Tombstone TTL out of range"));
+        }
+        let publish_policy = match publish_policy {
+            Some(c) => Some(PublishPolicy {
+                url: Uri::from_str(&c.url).stack_context_with(log, "Invalid publish policy url", ea!(url = c.url))?,
+                fail_open: c.fail_open,
+                timeout: std::time::Duration::from_secs(c.timeout_secs.unwrap_or(5)),
+            }),
+            None => None,
+        };
+        let retention = match retention {
+            Some(c) => Some(RetentionPolicy {
+                dormant_after: Duration::try_days(
+                    c.dormant_after_days as i64,
+                ).context("Retention dormant period out of range")?,
+                stop_serving: c.stop_serving_dormant,
+                purge_after: match c.purge_after_days {
+                    Some(d) => Some(Duration::try_days(d as i64).context("Retention purge period out of range")?),
+                    None => None,
                 },
+            }),
+            None => None,
+        };
+        let watchdog = match watchdog {
+            Some(c) => Some(Watchdog::new(c).stack_context(log, "Invalid watchdog config")?),
+            None => None,
+        };
+        if advertise_addrs.is_empty() {
+            return Err(log.err("Publisher needs at least one advertised address"));
+        }
+        // Prepare publisher certs for publisher-resolver communication. Replicas sharing
+        // storage use whichever certs were stored first.
+        let certs = {
+            let certs = match storage.get_certs().await.stack_context(log, "Error looking up certs")? {
+                Some(c) => c,
                 None => {
                     let priv_key = p256::ecdsa::SigningKey::random(&mut rand::thread_rng());
                     let pub_cert_der =
@@ -167,27 +530,50 @@ impl Publisher {
                             None,
                             "unused",
                         ).await?;
-                    let certs = stored::publisher::latest::Certs {
-                        pub_der: pub_cert_der,
-                        priv_der: priv_key.to_pkcs8_der().unwrap().as_bytes().blob(),
-                    };
-                    db_pool.tx({
-                        let certs = certs.clone();
-                        move |conn| Ok(db::ensure_certs(conn, &stored::publisher::Certs::V1(certs.clone()))?)
-                    }).await.stack_context(log, "Error persisting generated certs")?;
-                    certs
+                    storage
+                        .ensure_certs(stored::publisher::Certs::V1(stored::publisher::latest::Certs {
+                            pub_der: pub_cert_der,
+                            priv_der: priv_key.to_pkcs8_der().unwrap().as_bytes().blob(),
+                        }))
+                        .await
+                        .stack_context(log, "Error persisting generated certs")?
                 },
+            };
+            match certs {
+                stored::publisher::Certs::V1(v1) => v1,
             }
         };
         let publisher = Arc::new(Publisher {
             node: node.clone(),
             log: log.clone(),
             cert_pub_hash: cert_der_hash(&certs.pub_der).unwrap(),
-            advertise_addr: advertise_addr,
-            db_pool: db_pool,
+            certs: certs,
+            advertise_addrs: advertise_addrs,
+            storage: storage,
+            max_db_size: max_db_size,
+            read_stats: if read_stats {
+                Some(Mutex::new(HashMap::new()))
+            } else {
+                None
+            },
+            maintenance: Mutex::new(None),
+            changes: broadcast::channel(1024).0,
+            usage: Mutex::new(UsagePeriod {
+                start: Utc::now(),
+                identities: HashMap::new(),
+            }),
+            counters: PublisherCounters::default(),
+            publish_policy: publish_policy,
+            retention: retention,
+            authorized_resolvers: authorized_resolvers.map(|r| r.into_iter().collect()),
+            watchdog: watchdog,
+            tombstone_ttl: tombstone_ttl,
         });
-        tm.stream(
-            "Publisher - network server",
+        serve_draining(
+            log,
+            tm,
+            "Publisher - network server".to_string(),
+            shutdown_grace,
             tokio_stream::wrappers::TcpListenerStream::new(
                 tokio::net::TcpListener::bind(bind_addr).await.stack_context(&log, "Error binding to address")?,
             ),
@@ -200,7 +586,28 @@ impl Publisher {
                         htwrap::handler!((publisher: Arc < Publisher >, log: Log)(r -> htserve:: responses:: Body) {
                             match async {
                                 ta_vis_res!(Response < htserve:: responses:: Body >);
-                                log.log_with(loga::DEBUG, "Recieved request", ea!(path = r.head.uri));
+                                // Resolvers forward the ID and trace context of the API request they're
+                                // handling
+                                let (request_id, traceparent) = request_trace_headers(&r.head.headers);
+                                log.log_with(
+                                    loga::DEBUG,
+                                    "Recieved request",
+                                    ea!(
+                                        path = r.head.uri,
+                                        request_id = request_id.clone().unwrap_or_default(),
+                                        traceparent = traceparent.clone().unwrap_or_default()
+                                    ),
+                                );
+                                if let Some(retry_after) = publisher.maintenance() {
+                                    return Ok(response_unavailable(retry_after));
+                                }
+                                let if_none_match =
+                                    r
+                                        .head
+                                        .headers
+                                        .get(IF_NONE_MATCH)
+                                        .and_then(|h| h.to_str().ok())
+                                        .map(|h| h.to_string());
                                 let req_body =
                                     serde_json::from_slice::<wire::resolve::ResolveRequest>(
                                         &r
@@ -213,31 +620,63 @@ impl Publisher {
                                     )
                                         .context("Request doesn't match schema")
                                         .err_external()?;
-                                match req_body {
+                                let req_body = match req_body {
                                     wire::resolve::ResolveRequest::V1(req_body) => {
-                                        let values =
-                                            publisher
-                                                .get_values(&req_body.ident, req_body.keys)
-                                                .await
-                                                .err_internal()?;
-                                        return Ok(
-                                            response_200_json(
-                                                values
-                                                    .into_iter()
-                                                    .map(|(k, v)| (k, v))
-                                                    .collect::<wire::resolve::v1::ResolveResp>(),
-                                            ),
-                                        );
+                                        if publisher.authorized_resolvers.is_some() {
+                                            return Ok(response_unauthorized());
+                                        }
+                                        req_body
+                                    },
+                                    wire::resolve::ResolveRequest::V1Signed(req_body) => {
+                                        let Ok(content) = req_body.content.verify(&req_body.resolver) else {
+                                            return Ok(response_bad_signature());
+                                        };
+                                        if (Utc::now() - content.requested).abs() > Duration::try_minutes(5).unwrap() {
+                                            return Ok(response_expired());
+                                        }
+                                        if let Some(authorized) = &publisher.authorized_resolvers {
+                                            if !authorized.contains(&req_body.resolver) {
+                                                return Ok(response_unauthorized());
+                                            }
+                                        }
+                                        content.request
                                     },
+                                };
+                                let (etag, values) =
+                                    publisher
+                                        .get_values_tagged(&req_body.ident, req_body.keys)
+                                        .await
+                                        .err_internal()?;
+                                if if_none_match.is_some_and(|h| etag_matches(&h, &etag)) {
+                                    publisher.count_usage(&req_body.ident, 0);
+                                    return Ok(
+                                        Response::builder()
+                                            .status(StatusCode::NOT_MODIFIED)
+                                            .header(ETAG, etag)
+                                            .body(body_full(vec![]))
+                                            .unwrap(),
+                                    );
                                 }
+                                let mut resp =
+                                    response_200_json(
+                                        values
+                                            .into_iter()
+                                            .collect::<wire::resolve::v1::ResolveResp>(),
+                                    );
+                                resp.headers_mut().insert(ETAG, HeaderValue::from_str(&etag).unwrap());
+                                publisher.count_usage(
+                                    &req_body.ident,
+                                    resp.body().size_hint().exact().unwrap_or_default(),
+                                );
+                                return Ok(resp);
                             }.await {
                                 Ok(r) => return r,
                                 Err(VisErr::Internal(e)) => {
-                                    log.log_err(loga::WARN, e.context("Error processing request"));
-                                    return response_503();
+                                    log_warn_err(log, e.context("Error processing request"));
+                                    return response_internal();
                                 },
                                 Err(VisErr::External(e)) => {
-                                    return response_400(e);
+                                    return response_bad_request(e);
                                 },
                             }
                         }),
@@ -248,21 +687,23 @@ impl Publisher {
                         rustls::ServerConfig::builder()
                             .with_no_client_auth()
                             .with_single_cert(
-                                vec![CertificateDer::from(certs.pub_der.clone().to_vec())],
-                                PrivateKeyDer::from(PrivatePkcs8KeyDer::from(certs.priv_der.clone().to_vec())),
+                                vec![CertificateDer::from(publisher.certs.pub_der.clone().to_vec())],
+                                PrivateKeyDer::from(
+                                    PrivatePkcs8KeyDer::from(publisher.certs.priv_der.clone().to_vec()),
+                                ),
                             )
                             .context("Error setting up tls listener")?;
                     server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
                     tokio_rustls::TlsAcceptor::from(Arc::new(server_config))
                 };
-                move |stream| {
+                move |stream, shutdown| {
                     let log = log.clone();
                     let tls_acceptor = tls_acceptor.clone();
                     let handler = handler.clone();
                     async move {
                         match async {
                             ta_res!(());
-                            htserve::handler::root_handle_https(&log, tls_acceptor, handler, stream?).await?;
+                            handle_https_conn(tls_acceptor, handler, stream?, shutdown).await?;
                             return Ok(());
                         }.await {
                             Ok(_) => { },
@@ -274,268 +715,1199 @@ impl Publisher {
                 }
             },
         );
-        tm.periodic("Publisher - periodic announce", Duration::try_hours(1).unwrap().to_std().unwrap(), {
+        tm.tracked_periodic("Publisher - periodic announce", Duration::try_hours(1).unwrap().to_std().unwrap(), {
             let log = log.fork(ea!(subsys = "periodic_announce"));
-            cap_fn!(()(log, publisher, node) {
-                match async {
-                    ta_res!(());
-                    let mut after = None;
-                    loop {
-                        let announce_pairs = publisher.list_announcements(after.as_ref()).await?;
-                        after = match announce_pairs.last() {
-                            Some(p) => Some(p.0.clone()),
-                            None => {
-                                break;
-                            },
-                        };
-                        for (identity, local_announcement) in announce_pairs {
-                            log.log_with(loga::DEBUG, "Sending announcement", ea!(identity = identity));
-                            let remote_announcement = node.put(identity.clone(), local_announcement.clone()).await;
-
-                            // Check to see if discovered a newer remote announcement - some other publisher
-                            // node has surplanted this one (and delete our own announcement).
-                            shed!{
-                                let Some(remote_announcement) = remote_announcement else {
-                                    break;
-                                };
-                                let local_announced = match &local_announcement {
-                                    Announcement::V1(a) => a.parse_unwrap().announced,
-                                };
-                                let remote_announced = match remote_announcement {
-                                    Announcement::V1(remote_announcement) => {
-                                        remote_announcement.parse_unwrap().announced
-                                    },
-                                };
-                                if remote_announced <= local_announced {
-                                    break;
+            cap_fn!(()(log, publisher) {
+                if let Err(e) = publisher.announce_all(&log).await {
+                    log_warn_err(&log, e.context("Error while re-announcing publishers"));
+                }
+            })
+        });
+
+        // Re-announce immediately when the DHT neighborhood shifts, since announcements
+        // may have been lost with the nodes that stored them
+        tm.tracked_task("Publisher - announce after rebalance", {
+            let log = log.fork(ea!(subsys = "rebalance_announce"));
+            let publisher = publisher.clone();
+            let mut rebalances = node.subscribe_rebalance();
+            let tm = tm.clone();
+            async move {
+                loop {
+                    select!{
+                        r = rebalances.recv() => match r {
+                            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                                log.log(loga::INFO, "Routing table rebalanced, re-announcing");
+                                if let Err(e) = publisher.announce_all(&log).await {
+                                    log_warn_err(&log, e.context("Error while re-announcing publishers"));
                                 }
+                            },
+                            Err(broadcast::error::RecvError::Closed) => break,
+                        },
+                        _ = tm.until_terminate() => break,
+                    }
+                }
+            }
+        });
 
-                                // Newer announcement elsewhere, delete this announcement to save some network
-                                // effort
-                                publisher.db_pool.tx({
-                                    let identity = identity.clone();
-                                    let log = log.clone();
-                                    move |db| {
-                                        // Check once more if local announcement is older, in case it was updated while
-                                        // doing the node.put
-                                        let Some(local_announcement) = db::announcements_get(db, &identity)? else {
-                                            return Ok(());
-                                        };
-                                        let local_announced = match &local_announcement {
-                                            Announcement::V1(a) => a.parse_unwrap().announced,
-                                        };
-                                        if remote_announced <= local_announced {
-                                            return Ok(());
-                                        }
+        // Dormant identity retention
+        if publisher.retention.is_some() {
+            tm.tracked_periodic("Publisher - retention", Duration::try_hours(1).unwrap().to_std().unwrap(), {
+                let log = log.fork(ea!(subsys = "retention"));
+                cap_fn!(()(log, publisher) {
+                    if let Err(e) = publisher.sweep_retention(&log).await {
+                        log_warn_err(&log, e.context("Error applying identity retention policy"));
+                    }
+                })
+            });
+        }
 
-                                        // Discovered announcement actually newer - delete obsolete local announcement
-                                        log.log_with(
-                                            loga::DEBUG,
-                                            "Received more up to date announcement from network, discarding obsolete local announcement",
-                                            ea!(
-                                                identity = identity,
-                                                remote_announced = remote_announced,
-                                                local_announced = local_announced
-                                            ),
-                                        );
-                                        db::announcements_delete(db, &identity)?;
-                                        db::values_delete_all(db, &identity)?;
-                                        return Ok(());
-                                    }
-                                }).await.log(&log, loga::WARN, "Error deleting obsolete announcement");
-                            };
-                        }
+        // Delete tombstones once they've been served long enough
+        if publisher.tombstone_ttl.is_some() {
+            tm.tracked_periodic("Publisher - tombstones", Duration::try_minutes(5).unwrap().to_std().unwrap(), {
+                let log = log.fork(ea!(subsys = "tombstones"));
+                cap_fn!(()(log, publisher) {
+                    if let Err(e) = publisher.sweep_tombstones().await {
+                        log_warn_err(&log, e.context("Error deleting expired tombstones"));
                     }
-                    return Ok(());
-                }.await {
-                    Ok(_) => { },
-                    Err(e) => {
-                        log.log_err(loga::WARN, e.context("Error while re-announcing publishers"));
-                    },
+                })
+            });
+        }
+
+        // Check the network's announcements for hosted identities
+        if let Some(watchdog) = &publisher.watchdog {
+            tm.tracked_periodic("Publisher - watchdog", watchdog.interval, {
+                let log = log.fork(ea!(subsys = "watchdog"));
+                cap_fn!(()(log, publisher) {
+                    if let Err(e) = publisher.run_watchdog(&log).await {
+                        log_warn_err(&log, e.context("Error checking network announcements"));
+                    }
+                })
+            });
+        }
+
+        // Usage rollups, also saved at shutdown
+        tm.tracked_periodic(
+            "Publisher - usage rollup",
+            usage_rollup_interval().to_std().unwrap(),
+            cap_fn!(()(log, publisher) {
+                if let Err(e) = publisher.roll_up_usage().await {
+                    log_warn_err(&log, e.context("Failed to save usage counts"));
                 }
-            })
+            }),
+        );
+        tm.tracked_task("Publisher - usage rollup at shutdown", {
+            let log = log.clone();
+            let publisher = publisher.clone();
+            let tm = tm.clone();
+            async move {
+                tm.until_terminate().await;
+                if let Err(e) = publisher.roll_up_usage().await {
+                    log_warn_err(&log, e.context("Failed to save usage counts"));
+                }
+            }
         });
         return Ok(publisher);
     }
 
-    pub fn pub_cert_hash(&self) -> Blob {
-        return self.cert_pub_hash.clone();
-    }
+    /// Send all local announcements to the network. Announcements superseded by a
+    /// newer one elsewhere are deleted along with their values.
+    async fn announce_all(&self, log: &Log) -> Result<(), loga::Error> {
+        let mut after = None;
+        loop {
+            let announce_pairs = self.list_announcements(&ListFilter {
+                after: after.take(),
+                ..Default::default()
+            }).await?;
+            after = match announce_pairs.last() {
+                Some(p) => Some(p.0.to_string()),
+                None => {
+                    break;
+                },
+            };
+            for (identity, local_announcement) in announce_pairs {
+                if self.retention.as_ref().is_some_and(|r| r.stop_serving) && self.is_dormant(&identity).await? {
+                    log.log_with(loga::DEBUG, "Skipping announcement for dormant identity", ea!(identity = identity));
+                    continue;
+                }
+                log.log_with(loga::DEBUG, "Sending announcement", ea!(identity = identity));
+                let put = self.node.put(identity, local_announcement.clone()).await;
+                log.log_with(
+                    loga::DEBUG,
+                    "Sent announcement",
+                    ea!(identity = identity, sent = put.sent, accepted = put.accepted),
+                );
+                if put.sent > 0 && put.accepted == 0 {
+                    log.log_with(
+                        loga::WARN,
+                        "No nodes acknowledged storing announcement",
+                        ea!(identity = redact_id(identity), sent = put.sent, errors = put.errors.dbg_str()),
+                    );
+                }
+                let remote_announcement = put.found;
 
-    pub async fn announce(
-        &self,
-        identity: &Identity,
-        announcement: stored::announcement::Announcement,
-    ) -> Result<(), loga::Error> {
-        let remote_announcement = self.node.put(identity.clone(), announcement.clone()).await;
-        match remote_announcement {
-            Some(remote_announcement) => {
-                let new_published = match &announcement {
-                    Announcement::V1(a) => a.parse_unwrap().announced,
-                };
-                let remote_published = match remote_announcement {
-                    Announcement::V1(a) => a.parse_unwrap().announced,
+                // Check to see if discovered a newer remote announcement - some other publisher
+                // node has surplanted this one (and delete our own announcement).
+                shed!{
+                    let Some(remote_announcement) = remote_announcement else {
+                        break;
+                    };
+                    let local_announced = match local_announcement.parse() {
+                        Ok(a) => a.announced,
+                        Err(e) => {
+                            log.log_err(
+                                loga::WARN,
+                                e.context_with("Stored announcement is malformed", ea!(identity = redact_id(identity))),
+                            );
+                            break;
+                        },
+                    };
+                    let Ok(remote_announced) = remote_announcement.parse().map(|a| a.announced) else {
+                        break;
+                    };
+                    if remote_announced <= local_announced {
+                        break;
+                    }
+
+                    // Newer announcement elsewhere, delete this announcement to save some network
+                    // effort. The storage checks once more if local announcement is older, in case it
+                    // was updated while doing the node.put
+                    match self.storage.clear_superseded(&identity, remote_announced).await {
+                        Ok(Some(local_announced)) => {
+                            log.log_with(
+                                loga::DEBUG,
+                                "Received more up to date announcement from network, discarded obsolete local announcement",
+                                ea!(
+                                    identity = identity,
+                                    remote_announced = remote_announced,
+                                    local_announced = local_announced
+                                ),
+                            );
+                            let problem =
+                                check_announcement(
+                                    Some(&remote_announcement),
+                                    &self.advertise_addrs,
+                                    &self.cert_pub_hash,
+                                );
+                            if let (Some(watchdog), Some(problem)) = (&self.watchdog, problem) {
+                                if let Some(alert) = watchdog.alert(Utc::now(), &identity, problem) {
+                                    self.send_watchdog_alert(log, watchdog, &alert).await;
+                                }
+                            }
+                        },
+                        Ok(None) => { },
+                        Err(e) => {
+                            log.log_err(loga::WARN, e.context("Error deleting obsolete announcement"));
+                        },
+                    }
                 };
-                if remote_published > new_published {
-                    // A newer announcement was found elsewhere in the network; just drop the outdated
-                    // announcement we're trying to publish here
-                    return Ok(());
+            }
+        }
+        return Ok(());
+    }
+
+    /// Look up each hosted identity's announcement in the network and alert if it's
+    /// missing or doesn't list this publisher.
+    async fn run_watchdog(&self, log: &Log) -> Result<(), loga::Error> {
+        let Some(watchdog) = &self.watchdog else {
+            return Ok(());
+        };
+        let mut checked = vec![];
+        let mut problems = HashMap::new();
+        let mut filter = ListFilter::default();
+        loop {
+            let page = self.list_announcements(&filter).await?;
+            let Some((last, _)) = page.last() else {
+                break;
+            };
+            filter.after = Some(last.to_string());
+            for (identity, _) in page {
+                if self.retention.as_ref().is_some_and(|r| r.stop_serving) && self.is_dormant(&identity).await? {
+                    continue;
                 }
-            },
-            None => (),
+                let found = self.node.get(identity).await;
+                if let Some(problem) = check_announcement(found.as_ref(), &self.advertise_addrs, &self.cert_pub_hash) {
+                    problems.insert(identity, problem);
+                }
+                checked.push(identity);
+            }
+        }
+        for alert in watchdog.update(Utc::now(), &checked, problems) {
+            self.send_watchdog_alert(log, watchdog, &alert).await;
         }
-        self.db_pool.tx({
-            let identity = identity.clone();
-            let announcement = announcement.clone();
-            move |db| Ok(db::announcements_set(db, &identity, &announcement)?)
-        }).await?;
-        return Ok(())
+        return Ok(());
     }
 
-    pub async fn clear_identity(&self, identity: &Identity) -> Result<(), loga::Error> {
-        self.db_pool.tx({
-            let identity = identity.clone();
-            move |db| {
-                db::announcements_delete(db, &identity)?;
-                db::values_delete_all(db, &identity)?;
-                return Ok(());
+    async fn send_watchdog_alert(&self, log: &Log, watchdog: &Watchdog, alert: &PublisherWatchdogAlert) {
+        log.log_with(
+            loga::WARN,
+            "Network announcement for hosted identity doesn't match this publisher",
+            ea!(identity = redact_id(alert.identity), problem = alert.problem.dbg_str()),
+        );
+        if let Err(e) = watchdog.notify(log, alert).await {
+            log_warn_err(log, e.context("Error sending watchdog alert to webhook"));
+        }
+    }
+
+    /// Mark announced identities without activity in the retention period dormant, and
+    /// purge identities that have been dormant longer than the purge period. Both are
+    /// recorded in the audit log.
+    async fn sweep_retention(&self, log: &Log) -> Result<(), loga::Error> {
+        let Some(retention) = &self.retention else {
+            return Ok(());
+        };
+        let now = Utc::now();
+        let mut filter = ListFilter::default();
+        loop {
+            let page = self.list_announcements(&filter).await?;
+            let Some((last, _)) = page.last() else {
+                break;
+            };
+            filter.after = Some(last.to_string());
+            for (identity, announcement) in page {
+                let announced = match announcement.parse() {
+                    Ok(a) => a.announced,
+                    Err(e) => {
+                        log.log_err(
+                            loga::WARN,
+                            e.context_with(
+                                "Stored announcement is malformed, skipping",
+                                ea!(identity = redact_id(identity)),
+                            ),
+                        );
+                        continue;
+                    },
+                };
+                let action = shed!{
+                    'done _;
+                    if let Some(since) = self.storage.dormant_since(&identity).await? {
+                        if retention.purge_after.is_some_and(|p| now - since >= p) {
+                            break 'done Some(RetentionAction::Purge);
+                        }
+                        break 'done None;
+                    }
+
+                    // Identities active before activity was tracked only have the announcement time
+                    let last_active = match self.storage.last_active(&identity).await? {
+                        Some(a) => a.max(announced),
+                        None => announced,
+                    };
+                    if now - last_active < retention.dormant_after {
+                        break 'done None;
+                    }
+                    self.storage.mark_dormant(&identity, now).await?;
+                    break 'done Some(RetentionAction::MarkedDormant);
+                };
+                match action {
+                    None => { },
+                    Some(RetentionAction::MarkedDormant) => {
+                        log.log_with(loga::INFO, "Identity is now dormant", ea!(identity = redact_id(identity)));
+                    },
+                    Some(RetentionAction::Purge) => {
+                        self.clear_identity(&identity).await?;
+                        self.storage.audit_insert(now, &identity, AdminAuditEvent::Purged).await?;
+                        log.log_with(loga::INFO, "Purged dormant identity", ea!(identity = redact_id(identity)));
+                    },
+                }
             }
-        }).await?;
+        }
         return Ok(());
     }
 
-    pub async fn list_announcements(
-        &self,
-        after: Option<&Identity>,
-    ) -> Result<Vec<(Identity, Announcement)>, loga::Error> {
-        let after = after.cloned();
-        return Ok(match after {
-            None => {
-                let res =
+    /// Delete tombstones of hosted identities that have been served for the tombstone
+    /// TTL.
+    async fn sweep_tombstones(&self) -> Result<(), loga::Error> {
+        let Some(tombstone_ttl) = self.tombstone_ttl else {
+            return Ok(());
+        };
+        let tombstone_ttl = Duration::try_minutes(tombstone_ttl as i64).unwrap();
+        let now = Utc::now();
+        let mut filter = ListFilter::default();
+        loop {
+            let page = self.list_announcements(&filter).await?;
+            let Some((last, _)) = page.last() else {
+                break;
+            };
+            filter.after = Some(last.to_string());
+            for (identity, _) in page {
+                let removed =
                     self
-                        .db_pool
-                        .tx(move |db| Ok(db::announcements_list_start(db)?))
+                        .storage
+                        .record_set(&identity)
                         .await?
+                        .1
                         .into_iter()
-                        .map(|p| (p.identity, p.value))
-                        .collect();
-                res
-            },
-            Some(a) => {
-                self
-                    .db_pool
-                    .tx(move |db| Ok(db::announcements_list_after(db, &a)?))
-                    .await?
-                    .into_iter()
-                    .map(|p| (p.identity, p.value))
-                    .collect()
-            },
-        });
-    }
-
-    pub async fn modify_values(
-        &self,
-        identity: &Identity,
-        args: publish_util::PublishArgs,
-    ) -> Result<(), loga::Error> {
-        self.db_pool.tx({
-            let identity = identity.clone();
-            move |db| {
-                if let Some(missing_ttl) = args.missing_ttl {
-                    db::ident_set(db, &identity, missing_ttl as i64)?;
+                        .filter(|(_, v)| match v {
+                            stored::record::RecordValue::V1(v) => v.data.is_none(),
+                        })
+                        .map(|(k, _)| k)
+                        .collect::<Vec<_>>();
+                if removed.is_empty() {
+                    continue;
                 }
-                if args.clear_all {
-                    db::values_delete_all(db, &identity)?;
-                }
-                for k in args.clear {
-                    db::values_delete(db, &identity, &join_record_key(&k))?;
-                }
-                for (k, v) in args.set {
-                    db::values_set(db, &identity, &join_record_key(&k), &v)?;
+                let expired =
+                    self
+                        .storage
+                        .get_values(&identity, removed)
+                        .await?
+                        .into_iter()
+                        .filter(|(_, v)| tombstone_expired(v, now, tombstone_ttl))
+                        .map(|(k, _)| split_record_key(&k))
+                        .collect::<HashSet<_>>();
+                if expired.is_empty() {
+                    continue;
                 }
-                return Ok(());
+                self.storage.modify_values(&identity, publish_util::PublishArgs {
+                    clear: expired,
+                    ..Default::default()
+                }, now, now).await?;
+                _ = self.changes.send(identity);
             }
-        }).await?;
+        }
         return Ok(());
     }
 
-    pub async fn get_values(
-        &self,
-        identity: &Identity,
-        keys: Vec<RecordKey>,
-    ) -> Result<HashMap<RecordKey, wire::resolve::latest::ResolveValue>, loga::Error> {
-        let identity = identity.clone();
-        return Ok(self.db_pool.tx(move |db| {
-            let mut out = HashMap::new();
-            let missing_ttl = db::ident_get(db, &identity)?.unwrap_or_else(|| 0);
-            let now = Utc::now();
-            for k in keys {
-                let expires;
-                let data;
-                match db::values_get(db, &identity, &join_record_key(&k))? {
-                    Some(v) => match v {
-                        stored::record::RecordValue::V1(v) => {
-                            expires = now + Duration::try_minutes(v.ttl as i64).context("TTL out of range")?;
-                            data = v.data;
-                        },
-                    },
-                    None => {
-                        expires =
-                            now + Duration::try_minutes(missing_ttl as i64).context("Missing-TTL out of range")?;
-                        data = None;
-                    },
-                }
-                out.insert(k.clone(), wire::resolve::v1::ResolveValue {
-                    expires: expires,
-                    data: data,
-                });
-            }
-            return Ok(out);
-        }).await?);
+    /// Whether the publisher has the identity's announcement and values, and whether
+    /// it's serving them.
+    pub async fn identity_status(&self, identity: &Identity) -> Result<PublisherIdentityStatus, loga::Error> {
+        let announcement = match self.storage.get_announcement(identity).await? {
+            Some(a) => Some(
+                a.parse().context_with("Stored announcement is malformed", ea!(identity = identity))?,
+            ),
+            None => None,
+        };
+        return Ok(PublisherIdentityStatus {
+            allowed: self.storage.is_identity_allowed(identity).await?,
+            announced: announcement.as_ref().map(|a| a.announced),
+            listed: announcement
+                .as_ref()
+                .is_some_and(
+                    |a| a
+                        .publishers
+                        .iter()
+                        .any(|p| p.cert_hash == self.cert_pub_hash || self.advertise_addrs.contains(&p.addr.0)),
+                ),
+            dormant: self.is_dormant(identity).await?,
+            maintenance: self.maintenance(),
+            keys: self.list_values(identity).await?.into_iter().map(|(k, _)| k).collect(),
+        });
+    }
+
+    /// Whether the identity was marked dormant by the retention policy.
+    pub async fn is_dormant(&self, identity: &Identity) -> Result<bool, loga::Error> {
+        return Ok(self.storage.dormant_since(identity).await?.is_some());
     }
 
-    pub async fn list_value_keys(
+    /// Retention events in a time range, oldest first.
+    pub async fn audit_log(
         &self,
-        identity: &Identity,
-        after: Option<String>,
-    ) -> Result<Vec<String>, loga::Error> {
-        let identity = identity.clone();
-        return Ok(self.db_pool.tx(move |conn| match after {
-            Some(v) => {
-                return Ok(db::values_keys_list_after(conn, &identity, &v)?);
-            },
-            None => {
-                return Ok(db::values_keys_list_start(conn, &identity)?);
+        since: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Result<Vec<AdminAuditEntry>, loga::Error> {
+        return self.storage.audit_log(since, until).await;
+    }
+
+    /// The state of the watchdog.
+    pub fn watchdog_status(&self) -> PublisherWatchdogStatus {
+        match &self.watchdog {
+            Some(w) => return w.status(),
+            None => return PublisherWatchdogStatus {
+                enabled: false,
+                last_check: None,
+                alerts: vec![],
             },
-        }).await?);
+        }
     }
-}
 
-#[async_trait]
-impl crate::publishing::Publisher for Publisher {
-    async fn publish(
-        &self,
-        _log: &Log,
-        identity_signer: &Arc<Mutex<dyn IdentitySigner>>,
-        content: publish_util::PublishArgs,
-    ) -> Result<(), loga::Error> {
-        let identity = identity_signer.lock().unwrap().identity()?;
-        self.modify_values(&identity, content).await?;
-        return Ok(());
+    /// Remove all watchdog alerts.
+    pub fn clear_watchdog_alerts(&self) {
+        if let Some(w) = &self.watchdog {
+            w.clear();
+        }
     }
-}
 
-#[async_trait]
-pub trait PublisherAuthorizer: Sync + Send {
-    async fn is_identity_allowed(&self, identity: &Identity) -> Result<bool, loga::Error>;
-}
+    pub fn pub_cert_hash(&self) -> Blob {
+        return self.cert_pub_hash.clone();
+    }
 
-pub const API_ROUTE_PUBLISH: &str = "publish";
+    pub fn info(&self) -> wire::api::publish::v1::InfoResponse {
+        return wire::api::publish::v1::InfoResponse {
+            advertise_addr: self.advertise_addrs[0],
+            advertise_addrs: self.advertise_addrs.clone(),
+            cert_pub_hash: self.cert_pub_hash.clone(),
+        };
+    }
 
+    /// Start (with the number of seconds resolvers should wait before retrying) or
+    /// stop (`None`) maintenance mode. While in maintenance mode, resolve requests
+    /// from other nodes get a `503` with a `Retry-After` header. Lookups from this
+    /// node's own resolver are still answered.
+    pub fn set_maintenance(&self, retry_after: Option<u32>) {
+        let retry_after = retry_after.map(|r| r.min(wire::resolve::MAX_RETRY_AFTER_SECS));
+        *self.maintenance.lock().unwrap() = retry_after;
+        self.log.log_with(loga::INFO, "Changed maintenance mode", ea!(retry_after = retry_after.dbg_str()));
+    }
+
+    /// The retry-after seconds if in maintenance mode.
+    pub fn maintenance(&self) -> Option<u32> {
+        return *self.maintenance.lock().unwrap();
+    }
+
+    pub async fn announce(
+        &self,
+        identity: &Identity,
+        announcement: stored::announcement::Announcement,
+    ) -> Result<wire::api::publish::latest::AnnounceResponse, loga::Error> {
+        let put = self.node.put(*identity, announcement.clone()).await;
+        if let Some(remote_announcement) = put.found {
+            let new_published =
+                announcement.parse().context("Announcement is malformed")?.announced;
+            let remote_published = remote_announcement.parse().map(|a| a.announced);
+            if remote_published.is_ok_and(|r| r > new_published) {
+                // A newer announcement was found elsewhere in the network; just drop the outdated
+                // announcement we're trying to publish here
+                return Ok(wire::api::publish::latest::AnnounceResponse {
+                    sent: put.sent,
+                    accepted: put.accepted,
+                    superseded: true,
+                });
+            }
+        }
+        self.storage.set_announcement(identity, &announcement, Utc::now()).await?;
+        self.counters.announces.fetch_add(1, Ordering::Relaxed);
+        return Ok(wire::api::publish::latest::AnnounceResponse {
+            sent: put.sent,
+            accepted: put.accepted,
+            superseded: false,
+        });
+    }
+
+    /// Send the stored announcement for a hosted identity to the network now, outside
+    /// the regular announce schedule (ex: after suspected DHT data loss). Returns
+    /// `None` if the identity has no announcement here. A newer announcement found in
+    /// the network is reported as `superseded`; the local one is cleaned up by the
+    /// next scheduled announce.
+    pub async fn reannounce(
+        &self,
+        identity: &Identity,
+    ) -> Result<Option<wire::api::publish::latest::AnnounceResponse>, loga::Error> {
+        let Some(announcement) = self.storage.get_announcement(identity).await? else {
+            return Ok(None);
+        };
+        let put = self.node.put(*identity, announcement.clone()).await;
+        self
+            .log
+            .log_with(
+                loga::INFO,
+                "Reannounced identity on request",
+                ea!(identity = identity, sent = put.sent, accepted = put.accepted, errors = put.errors.dbg_str()),
+            );
+        let mut superseded = false;
+        if let Some(remote_announcement) = put.found {
+            let local_announced = announcement.parse().map(|a| a.announced);
+            let remote_announced = remote_announcement.parse().map(|a| a.announced);
+            if let (Ok(local_announced), Ok(remote_announced)) = (local_announced, remote_announced) {
+                superseded = remote_announced > local_announced;
+            }
+        }
+        return Ok(Some(wire::api::publish::latest::AnnounceResponse {
+            sent: put.sent,
+            accepted: put.accepted,
+            superseded: superseded,
+        }));
+    }
+
+    pub async fn clear_identity(&self, identity: &Identity) -> Result<(), loga::Error> {
+        self.storage.clear_identity(identity).await?;
+        if let Some(read_stats) = &self.read_stats {
+            read_stats.lock().unwrap().remove(identity);
+        }
+        _ = self.changes.send(*identity);
+        return Ok(());
+    }
+
+    pub async fn list_announcements(&self, filter: &ListFilter) -> Result<Vec<(Identity, Announcement)>, loga::Error> {
+        return self.storage.list_announcements(filter).await;
+    }
+
+    /// Like `list_announcements` but only announcements published in a time range.
+    /// Pages are only short at the end.
+    pub async fn list_announcements_between(
+        &self,
+        filter: &ListFilter,
+        since: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<(Identity, Announcement)>, loga::Error> {
+        let limit = filter.limit();
+        let mut filter = filter.clone();
+        let mut out = vec![];
+        while out.len() < limit {
+            let page = self.list_announcements(&filter).await?;
+            let Some((last, _)) = page.last() else {
+                break;
+            };
+            filter.after = Some(last.to_string());
+            for (identity, announcement) in page {
+                let Ok(announced) = announcement.parse().map(|a| a.announced) else {
+                    continue;
+                };
+                if since.is_some_and(|t| announced < t) || until.is_some_and(|t| announced >= t) {
+                    continue;
+                }
+                out.push((identity, announcement));
+                if out.len() >= limit {
+                    break;
+                }
+            }
+        }
+        return Ok(out);
+    }
+
+    /// List all identities with announcements, across all pages.
+    pub async fn list_announced_identities(&self) -> Result<Vec<Identity>, loga::Error> {
+        let mut out = vec![];
+        let mut filter = ListFilter::default();
+        loop {
+            let page = self.list_announcements(&filter).await?;
+            let Some((last, _)) = page.last() else {
+                break;
+            };
+            filter.after = Some(last.to_string());
+            out.extend(page.into_iter().map(|(i, _)| i));
+        }
+        return Ok(out);
+    }
+
+    /// Get the current version of the record set published for an identity.
+    pub async fn values_version(&self, identity: &Identity) -> Result<String, loga::Error> {
+        return Ok(self.storage.record_set(identity).await?.0);
+    }
+
+    /// Check a publish request with the publish policy endpoint, if configured.
+    /// Returns the reason if the request should be rejected.
+    pub async fn check_publish_policy(
+        &self,
+        identity: &Identity,
+        content: &wire::api::publish::v1::PublishRequestContent,
+    ) -> Option<String> {
+        let Some(policy) = &self.publish_policy else {
+            return None;
+        };
+        let log = self.log.fork(ea!(url = policy.url, identity = identity));
+        let res = timeout(policy.timeout, async {
+            ta_res!(wire::api::publish::v1::PublishPolicyResponse);
+            let body =
+                htreq::post(
+                    &log,
+                    &mut htreq::connect(&policy.url).await.context("Error connecting to publish policy endpoint")?,
+                    &policy.url,
+                    &HashMap::new(),
+                    serde_json::to_vec(&wire::api::publish::v1::PublishPolicyRequest {
+                        identity: *identity,
+                        content: content.clone(),
+                    }).unwrap(),
+                    64 * 1024,
+                ).await?;
+            return serde_json::from_slice::<wire::api::publish::v1::PublishPolicyResponse>(
+                &body,
+            ).context("Error parsing publish policy response as json");
+        }).await;
+        let e = match res {
+            Ok(Ok(resp)) => {
+                if resp.allow {
+                    return None;
+                }
+                return Some(resp.reason.unwrap_or_else(|| "Rejected by publish policy".to_string()));
+            },
+            Ok(Err(e)) => e,
+            Err(_) => loga::err("Timed out waiting for publish policy endpoint"),
+        };
+        if policy.fail_open {
+            log_warn_err(&log, e.context("Error checking publish policy, accepting publish"));
+            return None;
+        } else {
+            log_warn_err(&log, e.context("Error checking publish policy, rejecting publish"));
+            return Some("Publish policy check failed".to_string());
+        }
+    }
+
+    pub async fn modify_values(
+        &self,
+        identity: &Identity,
+        mut args: publish_util::PublishArgs,
+    ) -> Result<ModifyValuesResult, loga::Error> {
+        args.set = match publish_util::canonicalize_records(std::mem::take(&mut args.set)) {
+            Ok(s) => s,
+            Err(problems) => return Ok(ModifyValuesResult::InvalidRecords(problems)),
+        };
+        if let Some(max_db_size) = self.max_db_size {
+            if !args.set.is_empty() && self.storage.size().await? > max_db_size {
+                return Ok(ModifyValuesResult::StorageFull);
+            }
+        }
+        // Future times (from clients with bad clocks) would win resolver merges until
+        // they pass
+        let now = Utc::now();
+        let published = args.published.map(|p| p.min(now)).unwrap_or(now);
+
+        // Replace cleared values with tombstones (no data) so caches that already have
+        // the values see a newer removal instead of falling back to stale replicas
+        if let Some(tombstone_ttl) = self.tombstone_ttl {
+            let cleared = if args.clear_all {
+                self.storage.record_set(identity).await?.1.into_iter().map(|(k, _)| k).collect::<Vec<_>>()
+            } else {
+                args.clear.iter().filter(|k| !args.set.contains_key(*k)).map(db_key).collect::<Vec<_>>()
+            };
+            if !cleared.is_empty() {
+                let tombstones =
+                    self
+                        .storage
+                        .get_values(identity, cleared)
+                        .await?
+                        .into_iter()
+                        .filter(|(_, v)| match &v.value {
+                            stored::record::RecordValue::V1(v) => v.data.is_some(),
+                        })
+                        .map(|(k, _)| split_record_key(&k))
+                        .filter(|k| !args.set.contains_key(k))
+                        .collect::<Vec<_>>();
+                for k in tombstones {
+                    args.set.insert(k, stored::record::RecordValue::V1(stored::record::v1::RecordValue {
+                        ttl: tombstone_ttl as i32,
+                        data: None,
+                    }));
+                }
+            }
+        }
+        let res = self.storage.modify_values(identity, args, published, now).await?;
+        if let ModifyValuesResult::Applied(_) = &res {
+            self.counters.publishes.fetch_add(1, Ordering::Relaxed);
+            _ = self.changes.send(*identity);
+        }
+        return Ok(res);
+    }
+
+    /// All values published for an identity.
+    pub async fn list_values(
+        &self,
+        identity: &Identity,
+    ) -> Result<Vec<(RecordKey, stored::record::RecordValue)>, loga::Error> {
+        return Ok(
+            self
+                .storage
+                .record_set(identity)
+                .await?
+                .1
+                .into_iter()
+                .map(|(k, v)| (split_record_key(&k), v))
+                .collect(),
+        );
+    }
+
+    /// Hosted identities and keys with records publishing the queried address or
+    /// certificate. Only values the publisher serves are searched (not dormant
+    /// identities it stopped serving).
+    pub async fn reverse_lookup(&self, query: &ReverseQuery) -> Result<Vec<AdminReverseMatch>, loga::Error> {
+        let mut out = vec![];
+        let mut filter = ListFilter::default();
+        loop {
+            let page = self.list_announcements(&filter).await?;
+            let Some((last, _)) = page.last() else {
+                break;
+            };
+            filter.after = Some(last.to_string());
+            for (identity, _) in page {
+                if self.retention.as_ref().is_some_and(|r| r.stop_serving) && self.is_dormant(&identity).await? {
+                    continue;
+                }
+                for (key, value) in self.list_values(&identity).await? {
+                    let stored::record::RecordValue::V1(value) = value;
+                    let Some(data) = &value.data else {
+                        continue;
+                    };
+                    if reverse_matches(query, &key, data) {
+                        out.push(AdminReverseMatch {
+                            identity: identity,
+                            key: key,
+                        });
+                    }
+                }
+            }
+        }
+        return Ok(out);
+    }
+
+    /// The identity's current record set, signed with the publisher cert key so third
+    /// parties can check what the publisher serves.
+    pub async fn export(&self, identity: &Identity) -> Result<wire::api::publish::latest::SignedExport, loga::Error> {
+        let (version, values) = self.storage.record_set(identity).await?;
+        let content = serde_json::to_string(&wire::api::publish::latest::ExportContent {
+            identity: *identity,
+            version: version,
+            exported: Utc::now(),
+            values: values.into_iter().map(|(k, v)| (split_record_key(&k), v)).collect(),
+        }).unwrap();
+        let signature = cert_der_sign(&self.certs.priv_der, content.as_bytes())?;
+        return Ok(wire::api::publish::latest::SignedExport {
+            cert_pub_der: self.certs.pub_der.clone(),
+            content: content,
+            signature: signature,
+        });
+    }
+
+    /// Wait until the identity's record set version differs from `version`, up to
+    /// `WATCH_WAIT`. Returns immediately if `version` is `None`.
+    pub async fn watch(
+        &self,
+        identity: &Identity,
+        version: Option<&str>,
+    ) -> Result<wire::api::publish::latest::WatchResponse, loga::Error> {
+        // Subscribe before checking the version so no change is missed
+        let mut changes = self.changes.subscribe();
+        let deadline = Instant::now() + WATCH_WAIT;
+        loop {
+            let current = self.values_version(identity).await?;
+            if version != Some(current.as_str()) {
+                return Ok(wire::api::publish::latest::WatchResponse {
+                    values: self.list_values(identity).await?,
+                    version: current,
+                    changed: true,
+                });
+            }
+            loop {
+                match timeout_at(deadline, changes.recv()).await {
+                    Ok(Ok(changed)) => {
+                        if &changed == identity {
+                            break;
+                        }
+                    },
+                    Ok(Err(broadcast::error::RecvError::Lagged(_))) => {
+                        break;
+                    },
+                    Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => {
+                        return Ok(wire::api::publish::latest::WatchResponse {
+                            version: current,
+                            changed: false,
+                            values: vec![],
+                        });
+                    },
+                }
+            }
+        }
+    }
+
+    /// Values for an identity this publisher has an announcement for, for a resolver in
+    /// the same node. `None` if the publisher doesn't host the identity.
+    pub async fn get_local_values(
+        &self,
+        identity: &Identity,
+        keys: Vec<RecordKey>,
+    ) -> Result<Option<HashMap<RecordKey, wire::resolve::latest::ResolveValue>>, loga::Error> {
+        if self.storage.get_announcement(identity).await?.is_none() {
+            return Ok(None);
+        }
+        return Ok(Some(self.get_values(identity, keys).await?));
+    }
+
+    pub async fn get_values(
+        &self,
+        identity: &Identity,
+        keys: Vec<RecordKey>,
+    ) -> Result<HashMap<RecordKey, wire::resolve::latest::ResolveValue>, loga::Error> {
+        return self.get_values_at(identity, keys, Utc::now()).await;
+    }
+
+    /// Like `get_values`, also returning the response `ETag` (see `resolve_etag`).
+    pub async fn get_values_tagged(
+        &self,
+        identity: &Identity,
+        keys: Vec<RecordKey>,
+    ) -> Result<(String, HashMap<RecordKey, wire::resolve::latest::ResolveValue>), loga::Error> {
+        let now = Utc::now();
+        let values = self.get_values_at(identity, keys, now).await?;
+        return Ok((resolve_etag(now, &values), values));
+    }
+
+    /// The stored key whose value is served for `key`, following key aliases in the
+    /// identity's records. `None` if the aliases loop or chain too far.
+    async fn follow_key_aliases(&self, identity: &Identity, key: &RecordKey) -> Result<Option<String>, loga::Error> {
+        let key = normalize_record_key(key);
+        let Some((suffix, head)) = key.split_last() else {
+            return Ok(Some(db_key(&key)));
+        };
+        if suffix == KEY_SUFFIX_KEY_ALIAS {
+            return Ok(Some(db_key(&key)));
+        }
+        let mut head = head.to_vec();
+        let mut seen = HashSet::new();
+        seen.insert(head.clone());
+        loop {
+            let alias_db_key = db_key(&build_key_alias_key(head.clone()));
+            let Some(alias) =
+                self.storage.get_values(identity, vec![alias_db_key.clone()]).await?.remove(&alias_db_key) else {
+                    break;
+                };
+            let stored::record::RecordValue::V1(alias) = alias.value;
+            let Some(alias) = alias.data.and_then(|d| serde_json::from_value::<KeyAlias>(d).ok()) else {
+                break;
+            };
+            let target = normalize_record_key(alias.target());
+            if seen.contains(&target) || seen.len() > KEY_ALIAS_MAX_HOPS {
+                return Ok(None);
+            }
+            seen.insert(target.clone());
+            head = target;
+        }
+        head.push(suffix.clone());
+        return Ok(Some(db_key(&head)));
+    }
+
+    /// Values as of `now`, which expirations are relative to.
+    async fn get_values_at(
+        &self,
+        identity: &Identity,
+        keys: Vec<RecordKey>,
+        now: DateTime<Utc>,
+    ) -> Result<HashMap<RecordKey, wire::resolve::latest::ResolveValue>, loga::Error> {
+        let stop_serving_dormant = self.retention.as_ref().is_some_and(|r| r.stop_serving);
+        let mut out = HashMap::new();
+
+        // Follow aliases to identities on this publisher
+        let alias_key = build_alias_key();
+        let alias_db_key = join_record_key(&alias_key);
+        let mut source = *identity;
+        let mut seen = HashSet::new();
+        seen.insert(source);
+        while seen.len() <= ALIAS_MAX_HOPS {
+            let Some(alias) =
+                self.storage.get_values(&source, vec![alias_db_key.clone()]).await?.remove(&alias_db_key) else {
+                    break;
+                };
+            let stored::record::RecordValue::V1(alias) = alias.value;
+            let Some(alias) = alias.data.and_then(|d| serde_json::from_value::<Alias>(d).ok()) else {
+                break;
+            };
+            let target = alias.target();
+            if seen.contains(&target) || self.storage.get_announcement(&target).await?.is_none() {
+                break;
+            }
+            seen.insert(target);
+            source = target;
+        }
+        let missing_ttl = self.storage.missing_ttl(&source).await?.unwrap_or(0);
+
+        // Dormant identities are answered as if nothing was published
+        let dormant =
+            stop_serving_dormant &&
+                (self.is_dormant(identity).await? || (&source != identity && self.is_dormant(&source).await?));
+
+        // Alias keys are answered from the queried identity, other keys from the alias
+        // target (following key aliases there)
+        let mut identity_keys = vec![];
+        let mut source_keys = vec![];
+        let mut source_db_keys = HashMap::new();
+        for k in &keys {
+            if normalize_record_key(k) == alias_key {
+                identity_keys.push(alias_db_key.clone());
+            } else if let Some(source_key) = self.follow_key_aliases(&source, k).await? {
+                source_keys.push(source_key.clone());
+                source_db_keys.insert(k.clone(), source_key);
+            }
+        }
+        let identity_values;
+        let source_values;
+        if dormant {
+            identity_values = HashMap::new();
+            source_values = HashMap::new();
+        } else {
+            identity_values = self.storage.get_values(identity, identity_keys).await?;
+            source_values = self.storage.get_values(&source, source_keys).await?;
+        }
+        for k in keys {
+            let expires;
+            let data;
+            let published;
+            let value = if normalize_record_key(&k) == alias_key {
+                identity_values.get(&alias_db_key)
+            } else {
+                source_db_keys.get(&k).and_then(|k| source_values.get(k))
+            };
+            match value {
+                Some(v) => match &v.value {
+                    stored::record::RecordValue::V1(value) => {
+                        expires = now + Duration::try_minutes(value.ttl as i64).context("TTL out of range")?;
+                        data = value.data.clone();
+                        published = v.published;
+                    },
+                },
+                None => {
+                    expires = now + Duration::try_minutes(missing_ttl as i64).context("Missing-TTL out of range")?;
+                    data = None;
+                    published = None;
+                },
+            }
+            out.insert(k.clone(), wire::resolve::v1::ResolveValue {
+                expires: expires,
+                data: data,
+                published: published,
+                provenance: None,
+                external: None,
+            });
+        }
+
+        // Only count keys with values, so stats can't be inflated with arbitrary keys
+        if let Some(read_stats) = &self.read_stats {
+            let read_keys = out.iter().filter(|(_, v)| v.data.is_some()).map(|(k, _)| k).collect::<Vec<_>>();
+            if !read_keys.is_empty() {
+                let now = Utc::now();
+                let mut read_stats = read_stats.lock().unwrap();
+                let identity_stats = read_stats.entry(*identity).or_default();
+                for k in read_keys {
+                    match identity_stats.entry(k.clone()) {
+                        Entry::Occupied(mut e) => {
+                            let e = e.get_mut();
+                            e.reads += 1;
+                            e.last_read = now;
+                        },
+                        Entry::Vacant(e) => {
+                            e.insert(wire::api::publish::latest::KeyReadStats {
+                                key: k.clone(),
+                                reads: 1,
+                                last_read: now,
+                            });
+                        },
+                    }
+                }
+            }
+        }
+        return Ok(out);
+    }
+
+    /// Read counts for keys published for an identity, ordered by key. Returns None if
+    /// read stats are disabled.
+    pub fn read_stats(&self, identity: &Identity) -> Option<Vec<wire::api::publish::latest::KeyReadStats>> {
+        let read_stats = self.read_stats.as_ref()?.lock().unwrap();
+        let mut out = read_stats.get(identity).map(|s| s.values().cloned().collect::<Vec<_>>()).unwrap_or_default();
+        out.sort_by(|a, b| a.key.cmp(&b.key));
+        return Some(out);
+    }
+
+    fn count_usage(&self, identity: &Identity, bytes: u64) {
+        self.counters.resolves.fetch_add(1, Ordering::Relaxed);
+        let mut usage = self.usage.lock().unwrap();
+        let counts = usage.identities.entry(*identity).or_default();
+        counts.resolves += 1;
+        counts.bytes += bytes;
+    }
+
+    pub fn stats(&self) -> PublisherStats {
+        return PublisherStats {
+            publishes: self.counters.publishes.load(Ordering::Relaxed),
+            announces: self.counters.announces.load(Ordering::Relaxed),
+            resolves: self.counters.resolves.load(Ordering::Relaxed),
+        };
+    }
+
+    /// Save the usage counts since the last rollup and start a new period.
+    async fn roll_up_usage(&self) -> Result<(), loga::Error> {
+        let end = Utc::now();
+        let period = std::mem::replace(&mut *self.usage.lock().unwrap(), UsagePeriod {
+            start: end,
+            identities: HashMap::new(),
+        });
+        self
+            .storage
+            .save_usage(
+                period.start,
+                end,
+                period.identities.into_iter().map(|(identity, c)| (identity, c.resolves, c.bytes)).collect(),
+                end - usage_retention(),
+            )
+            .await?;
+        return Ok(());
+    }
+
+    /// Per-identity usage from rollups overlapping `since` to `until`, plus usage
+    /// since the last rollup if the period is still open. Includes all announced
+    /// identities, even without resolves.
+    pub async fn usage(&self, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<AdminUsageReport, loga::Error> {
+        let rows = self.storage.usage_between(since, until).await?;
+        let end = until.min(Utc::now());
+        let mut start = end;
+        let mut counts = HashMap::<Identity, UsageCounts>::new();
+        for row in rows {
+            start = start.min(row.start);
+            let c = counts.entry(row.identity).or_default();
+            c.resolves += row.resolves;
+            c.bytes += row.bytes;
+        }
+        {
+            let usage = self.usage.lock().unwrap();
+            if usage.start < until {
+                start = start.min(usage.start);
+                for (identity, period_counts) in &usage.identities {
+                    let c = counts.entry(*identity).or_default();
+                    c.resolves += period_counts.resolves;
+                    c.bytes += period_counts.bytes;
+                }
+            }
+        }
+        let mut announced = HashMap::new();
+        let mut filter = ListFilter::default();
+        loop {
+            let page = self.list_announcements(&filter).await?;
+            let Some((last, _)) = page.last() else {
+                break;
+            };
+            filter.after = Some(last.to_string());
+            for (identity, announcement) in page {
+                if let Ok(a) = announcement.parse() {
+                    announced.insert(identity, a.announced);
+                }
+            }
+        }
+        let mut identities = counts.keys().chain(announced.keys()).cloned().collect::<Vec<_>>();
+        identities.sort_by_key(|i| i.to_string());
+        identities.dedup();
+        let mut out = vec![];
+        for identity in identities {
+            let mut records = 0;
+            let mut filter = ListFilter {
+                limit: Some(PAGE_MAX),
+                ..Default::default()
+            };
+            loop {
+                let page = self.list_value_keys(&identity, &filter).await?;
+                let Some(last) = page.last() else {
+                    break;
+                };
+                records += page.len() as u64;
+                filter.after = Some(last.clone());
+            }
+            let c = counts.remove(&identity).unwrap_or_default();
+            out.push(AdminIdentityUsage {
+                announced: announced.get(&identity).cloned(),
+                identity: identity,
+                records: records,
+                resolves: c.resolves,
+                bytes: c.bytes,
+            });
+        }
+        return Ok(AdminUsageReport {
+            start: start,
+            end: end,
+            identities: out,
+        });
+    }
+
+    pub async fn list_value_keys(&self, identity: &Identity, filter: &ListFilter) -> Result<Vec<String>, loga::Error> {
+        return self.storage.list_value_keys(identity, filter).await;
+    }
+}
+
+#[async_trait]
+impl crate::publishing::Publisher for Publisher {
+    async fn publish(
+        &self,
+        _log: &Log,
+        identity_signer: &Arc<Mutex<dyn IdentitySigner>>,
+        content: publish_util::PublishArgs,
+    ) -> Result<(), loga::Error> {
+        let identity = identity_signer.lock().unwrap().identity()?;
+        match self.modify_values(&identity, content).await? {
+            ModifyValuesResult::Applied(_) => { },
+            ModifyValuesResult::VersionMismatch(current) => {
+                return Err(loga::err_with("Record set version doesn't match", ea!(current_version = current)));
+            },
+            ModifyValuesResult::StorageFull => {
+                return Err(loga::err("Publisher database is full"));
+            },
+            ModifyValuesResult::InvalidRecords(problems) => {
+                return Err(loga::err_with("Records rejected", ea!(problems = problems.dbg_str())));
+            },
+        }
+        return Ok(());
+    }
+}
+
+/// Outcome of `modify_values`.
+pub enum ModifyValuesResult {
+    /// The changes were applied; this is the new record set version.
+    Applied(String),
+    /// The `if_version` precondition failed and nothing was changed; this is the
+    /// current record set version.
+    VersionMismatch(String),
+    /// The database is over its size limit and nothing was changed.
+    StorageFull,
+    /// Some records to set are too large and nothing was changed.
+    InvalidRecords(Vec<wire::api::publish::v1::RecordProblem>),
+}
+
+/// Check which address families the host is reachable on, returning the family
+/// clients should try first if exactly one of the two published families works.
+///
+/// A family works if the publisher accepts connections on at least one of the
+/// host's global addresses in that family and the node has responsive neighbors
+/// over it (unless the node has no responsive neighbors at all yet, in which case
+/// only the connection is checked).
+pub async fn test_addr_families(log: &Log, node: &Node, ips: &[IpAddr], port: u16) -> Option<IpFamily> {
+    let (neighbors_v4, neighbors_v6) = node.responsive_neighbor_families();
+    let mut works_v4 = None;
+    let mut works_v6 = None;
+    for ip in ips {
+        let (works, neighbors) = match ip {
+            IpAddr::V4(_) => (&mut works_v4, neighbors_v4),
+            IpAddr::V6(_) => (&mut works_v6, neighbors_v6),
+        };
+        let addr = SocketAddr::new(*ip, port);
+        let connects = match timeout(std::time::Duration::from_secs(5), TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => true,
+            Ok(Err(e)) => {
+                log.log_with(
+                    loga::DEBUG,
+                    "Address reachability self-test failed to connect",
+                    ea!(addr = addr, err = e),
+                );
+                false
+            },
+            Err(_) => {
+                log.log_with(loga::DEBUG, "Address reachability self-test timed out connecting", ea!(addr = addr));
+                false
+            },
+        };
+        let ok = connects && (neighbors > 0 || neighbors_v4 + neighbors_v6 == 0);
+        *works = Some(works.unwrap_or(false) || ok);
+    }
+    log.log_with(
+        loga::DEBUG,
+        "Address reachability self-test results",
+        ea!(
+            ipv4 = works_v4.dbg_str(),
+            ipv6 = works_v6.dbg_str(),
+            ipv4_neighbors = neighbors_v4,
+            ipv6_neighbors = neighbors_v6
+        ),
+    );
+    match (works_v4, works_v6) {
+        (Some(true), Some(false)) => return Some(IpFamily::V4),
+        (Some(false), Some(true)) => return Some(IpFamily::V6),
+        _ => return None,
+    }
+}
+
+#[async_trait]
+pub trait PublisherAuthorizer: Sync + Send {
+    async fn is_identity_allowed(&self, identity: &Identity) -> Result<bool, loga::Error>;
+}
+
+pub const API_ROUTE_PUBLISH: &str = "publish";
+
+/// Build the publisher HTTP endpoints. If `jsonrpc` is set, the same operations are
+/// also available as JSON-RPC methods at `/jsonrpc`.
 pub async fn build_api_endpoints_with_authorizer(
     log: &Log,
     publisher: &Arc<Publisher>,
     authorizer: Arc<dyn PublisherAuthorizer>,
+    jsonrpc: bool,
 ) -> Result<htserve::handler::PathRouter<htserve::responses::Body>, loga::Error> {
     struct State {
         log: Log,
@@ -548,6 +1920,110 @@ pub async fn build_api_endpoints_with_authorizer(
         publisher: publisher.clone(),
         authorizer: authorizer,
     });
+
+    enum PublishContentError {
+        BadSignature,
+        Expired,
+    }
+
+    /// Get the content of a publish request, checking the signature unless the
+    /// connection was authenticated with a TLS client cert for the identity.
+    fn publish_content(
+        req: &wire::api::publish::v1::PublishRequest,
+        client_identity: Option<&Identity>,
+    ) -> Result<wire::api::publish::v1::PublishRequestContent, PublishContentError> {
+        if client_identity == Some(&req.identity) {
+            let body =
+                serde_json::from_str::<wire::api::publish::v1::PublishRequestContent>(
+                    &req.content.message,
+                ).map_err(|_| PublishContentError::BadSignature)?;
+
+            // Without a signature, a recent request time is what keeps captured requests
+            // from being replayed later
+            let Some(published) = body.published else {
+                return Err(PublishContentError::Expired);
+            };
+            if (Utc::now() - published).abs() > Duration::try_minutes(5).unwrap() {
+                return Err(PublishContentError::Expired);
+            }
+            return Ok(body);
+        }
+        return req.content.verify(&req.identity).map_err(|_| PublishContentError::BadSignature);
+    }
+
+    /// Apply one request of a publish batch, with the same checks as `publish`.
+    async fn publish_batch_item(
+        state: &State,
+        client_identity: Option<&Identity>,
+        req: wire::api::publish::v1::PublishRequest,
+    ) -> wire::api::publish::v1::PublishBatchOutcome {
+        let body = match publish_content(&req, client_identity) {
+            Ok(b) => b,
+            Err(PublishContentError::BadSignature) => {
+                return wire::api::publish::v1::PublishBatchOutcome::BadSignature;
+            },
+            Err(PublishContentError::Expired) => {
+                return wire::api::publish::v1::PublishBatchOutcome::Expired;
+            },
+        };
+        match async {
+            ta_res!(wire:: api:: publish:: v1:: PublishBatchOutcome);
+            if !state.authorizer.is_identity_allowed(&req.identity).await? {
+                return Ok(wire::api::publish::v1::PublishBatchOutcome::Unauthorized);
+            }
+            if let Some(reason) = state.publisher.check_publish_policy(&req.identity, &body).await {
+                return Ok(wire::api::publish::v1::PublishBatchOutcome::Rejected { reason: reason });
+            }
+            match state.publisher.modify_values(&req.identity, publish_util::PublishArgs {
+                missing_ttl: body.missing_ttl,
+                clear_all: body.clear_all,
+                clear: body.clear,
+                set: body.set.into_iter().collect(),
+                if_version: body.if_version,
+                published: body.published,
+                ..Default::default()
+            }).await? {
+                ModifyValuesResult::Applied(version) => {
+                    return Ok(wire::api::publish::v1::PublishBatchOutcome::Applied { version: version });
+                },
+                ModifyValuesResult::VersionMismatch(current) => {
+                    return Ok(wire::api::publish::v1::PublishBatchOutcome::VersionMismatch { current: current });
+                },
+                ModifyValuesResult::StorageFull => {
+                    return Ok(wire::api::publish::v1::PublishBatchOutcome::StorageFull);
+                },
+                ModifyValuesResult::InvalidRecords(problems) => {
+                    return Ok(wire::api::publish::v1::PublishBatchOutcome::InvalidRecords { problems: problems });
+                },
+            }
+        }.await {
+            Ok(o) => return o,
+            Err(e) => {
+                log_warn_err(
+                    &state.log,
+                    e.context_with("Error publishing key values in batch", ea!(identity = req.identity)),
+                );
+                return wire::api::publish::v1::PublishBatchOutcome::Internal;
+            },
+        }
+    }
+
+    /// Apply the requests of a publish batch in order.
+    async fn publish_batch(
+        state: &State,
+        client_identity: Option<&Identity>,
+        req: wire::api::publish::v1::PublishBatchRequest,
+    ) -> wire::api::publish::v1::PublishBatchResponse {
+        let mut results = vec![];
+        for req in req.requests {
+            let identity = req.identity;
+            results.push(wire::api::publish::v1::PublishBatchResult {
+                identity: identity,
+                outcome: publish_batch_item(state, client_identity, req).await,
+            });
+        }
+        return wire::api::publish::v1::PublishBatchResponse { results: results };
+    }
     let mut routes = htserve::handler::PathRouter::default();
     routes.insert("/v1", {
         let mut routes = htserve::handler::PathRouter::default();
@@ -564,32 +2040,33 @@ pub async fn build_api_endpoints_with_authorizer(
                         ) {
                             Ok(r) => r,
                             Err(e) => {
-                                return Ok(response_400(format!("Invalid json: {}", e))) as Result<_, loga::Error>;
+                                return Ok(
+                                    response_bad_request(format!("Invalid json: {}", e)),
+                                ) as Result<_, loga::Error>;
                             },
                         };
                     match &req.announcement {
                         Announcement::V1(a) => {
                             let Ok(_) = a.verify(&req.identity) else {
-                                return Ok(response_400("Couldn't verify payload"));
+                                return Ok(response_bad_signature());
                             };
                         },
                     };
 
                     // Auth
                     if !state.authorizer.is_identity_allowed(&req.identity).await? {
-                        return Ok(response_401());
+                        return Ok(response_unauthorized());
                     }
 
                     // Publish it
-                    state.publisher.announce(&req.identity, req.announcement).await?;
-                    return Ok(response_200());
+                    return Ok(response_200_json(state.publisher.announce(&req.identity, req.announcement).await?));
                 }.await {
                     Ok(r) => {
                         return r;
                     },
                     Err(e) => {
-                        state.log.log_err(loga::WARN, e.context("Error publishing key values"));
-                        return response_503();
+                        log_warn_err(&state.log, e.context("Error publishing key values"));
+                        return response_internal();
                     },
                 }
             }))
@@ -607,33 +2084,313 @@ pub async fn build_api_endpoints_with_authorizer(
                         ) {
                             Ok(r) => r,
                             Err(e) => {
-                                return Ok(response_400(format!("Invalid json: {}", e))) as Result<_, loga::Error>;
+                                return Ok(
+                                    response_bad_request(format!("Invalid json: {}", e)),
+                                ) as Result<_, loga::Error>;
                             },
                         };
                     let Ok(_) = req.challenge.verify(&req.identity) else {
-                        return Ok(response_400("Couldn't verify payload"));
+                        return Ok(response_bad_signature());
                     };
 
                     // Auth
                     if !state.authorizer.is_identity_allowed(&req.identity).await? {
-                        return Ok(response_401());
+                        return Ok(response_unauthorized());
+                    }
+
+                    // Respond
+                    state.publisher.clear_identity(&req.identity).await?;
+                    return Ok(response_200());
+                }.await {
+                    Ok(r) => {
+                        return r;
+                    },
+                    Err(e) => {
+                        log_warn_err(&state.log, e.context("Error unpublishing key values"));
+                        return response_internal();
+                    },
+                }
+            }))
+        }).unwrap();
+        routes.insert("/publish", {
+            let state = state.clone();
+            Box::new(htwrap::handler!((state: Arc < State >)(r -> htserve:: responses:: Body) {
+                match async {
+                    ta_res!(Response < htserve:: responses:: Body >);
+
+                    // Params
+                    let req =
+                        match serde_json::from_slice::<wire::api::publish::v1::PublishRequest>(
+                            &r.body.collect().await?.to_bytes(),
+                        ) {
+                            Ok(r) => r,
+                            Err(e) => {
+                                return Ok(
+                                    response_bad_request(format!("Invalid json: {}", e)),
+                                ) as Result<_, loga::Error>;
+                            },
+                        };
+                    let client_identity = r.head.extensions.get::<ClientCertIdentity>().map(|i| &i.0);
+                    let body = match publish_content(&req, client_identity) {
+                        Ok(b) => b,
+                        Err(PublishContentError::BadSignature) => {
+                            return Ok(response_bad_signature());
+                        },
+                        Err(PublishContentError::Expired) => {
+                            return Ok(response_expired());
+                        },
+                    };
+
+                    // Auth
+                    if !state.authorizer.is_identity_allowed(&req.identity).await? {
+                        return Ok(response_unauthorized());
+                    }
+
+                    // Policy
+                    if let Some(reason) = state.publisher.check_publish_policy(&req.identity, &body).await {
+                        return Ok(response_error(ErrorCode::Rejected, reason));
+                    }
+
+                    // Publish it
+                    match state.publisher.modify_values(&req.identity, publish_util::PublishArgs {
+                        missing_ttl: body.missing_ttl,
+                        clear_all: body.clear_all,
+                        clear: body.clear,
+                        set: body.set.into_iter().collect(),
+                        if_version: body.if_version,
+                        published: body.published,
+                        ..Default::default()
+                    }).await? {
+                        ModifyValuesResult::Applied(version) => {
+                            return Ok(
+                                response_200_json(wire::api::publish::v1::RecordSetVersion { version: version }),
+                            );
+                        },
+                        ModifyValuesResult::VersionMismatch(current) => {
+                            let mut resp =
+                                response_200_json(wire::api::publish::v1::RecordSetVersion { version: current });
+                            *resp.status_mut() = StatusCode::CONFLICT;
+                            return Ok(resp);
+                        },
+                        ModifyValuesResult::StorageFull => {
+                            return Ok(response_error(ErrorCode::StorageFull, "Publisher storage is full"));
+                        },
+                        ModifyValuesResult::InvalidRecords(problems) => {
+                            let mut resp =
+                                response_200_json(wire::api::publish::v1::RejectedRecords { problems: problems });
+                            *resp.status_mut() = StatusCode::BAD_REQUEST;
+                            return Ok(resp);
+                        },
+                    }
+                }.await {
+                    Ok(r) => {
+                        return r;
+                    },
+                    Err(e) => {
+                        log_warn_err(&state.log, e.context("Error publishing key values"));
+                        return response_internal();
+                    },
+                }
+            }))
+        }).unwrap();
+        routes.insert("/publish_batch", {
+            let state = state.clone();
+            Box::new(htwrap::handler!((state: Arc < State >)(r -> htserve:: responses:: Body) {
+                match async {
+                    ta_res!(Response < htserve:: responses:: Body >);
+
+                    // Params
+                    let req =
+                        match serde_json::from_slice::<wire::api::publish::v1::PublishBatchRequest>(
+                            &r.body.collect().await?.to_bytes(),
+                        ) {
+                            Ok(r) => r,
+                            Err(e) => {
+                                return Ok(
+                                    response_bad_request(format!("Invalid json: {}", e)),
+                                ) as Result<_, loga::Error>;
+                            },
+                        };
+                    if req.requests.len() > wire::api::publish::v1::PUBLISH_BATCH_MAX {
+                        return Ok(
+                            response_bad_request(
+                                format!(
+                                    "Too many requests in batch, max {}",
+                                    wire::api::publish::v1::PUBLISH_BATCH_MAX
+                                ),
+                            ),
+                        );
+                    }
+
+                    // Publish them
+                    let client_identity = r.head.extensions.get::<ClientCertIdentity>().map(|i| &i.0);
+                    return Ok(response_200_json(publish_batch(state, client_identity, req).await));
+                }.await {
+                    Ok(r) => {
+                        return r;
+                    },
+                    Err(e) => {
+                        log_warn_err(&state.log, e.context("Error publishing key values in batch"));
+                        return response_internal();
+                    },
+                }
+            }))
+        }).unwrap();
+        routes.insert("/version", {
+            let state = state.clone();
+            Box::new(htwrap::handler!((state: Arc < State >)(r -> htserve:: responses:: Body) {
+                match async {
+                    ta_vis_res!(Response < htserve:: responses:: Body >);
+                    let Some(identity) = r.subpath.strip_prefix("/") else {
+                        return Ok(response_bad_request("Missing identity in path"));
+                    };
+                    let identity = Identity::from_str(identity).err_external()?;
+                    return Ok(
+                        response_200_json(
+                            wire::api::publish::v1::RecordSetVersion {
+                                version: state.publisher.values_version(&identity).await.err_internal()?,
+                            },
+                        ),
+                    );
+                }.await {
+                    Ok(r) => {
+                        return r;
+                    },
+                    Err(VisErr::External(e)) => {
+                        return response_bad_request(e);
+                    },
+                    Err(VisErr::Internal(e)) => {
+                        log_warn_err(&state.log, e.context("Error getting record set version"));
+                        return response_internal();
+                    },
+                }
+            }))
+        }).unwrap();
+        routes.insert("/announcement", {
+            let state = state.clone();
+            Box::new(htwrap::handler!((state: Arc < State >)(r -> htserve:: responses:: Body) {
+                match async {
+                    ta_vis_res!(Response < htserve:: responses:: Body >);
+                    let Some(identity) = r.subpath.strip_prefix("/") else {
+                        return Ok(response_bad_request("Missing identity in path"));
+                    };
+                    let identity = Identity::from_str(identity).err_external()?;
+
+                    // Announcements are public (they're in the DHT), this lets clients check a
+                    // publisher without going through the DHT
+                    return Ok(
+                        response_200_json(
+                            wire::api::publish::v1::AnnouncementResponse {
+                                announcement: state.publisher.storage.get_announcement(&identity).await.err_internal()?,
+                            },
+                        ),
+                    );
+                }.await {
+                    Ok(r) => {
+                        return r;
+                    },
+                    Err(VisErr::External(e)) => {
+                        return response_bad_request(e);
+                    },
+                    Err(VisErr::Internal(e)) => {
+                        log_warn_err(&state.log, e.context("Error getting announcement"));
+                        return response_internal();
+                    },
+                }
+            }))
+        }).unwrap();
+        routes.insert("/export", {
+            let state = state.clone();
+            Box::new(htwrap::handler!((state: Arc < State >)(r -> htserve:: responses:: Body) {
+                match async {
+                    ta_vis_res!(Response < htserve:: responses:: Body >);
+                    let Some(identity) = r.subpath.strip_prefix("/") else {
+                        return Ok(response_bad_request("Missing identity in path"));
+                    };
+                    let identity = Identity::from_str(identity).err_external()?;
+
+                    // Values are only for authorized resolvers, which must sign the request
+                    if let Some(authorized) = &state.publisher.authorized_resolvers {
+                        let body = r.body.collect().await.context("Error reading body").err_external()?.to_bytes();
+                        if body.is_empty() {
+                            return Ok(response_unauthorized());
+                        }
+                        let req =
+                            serde_json::from_slice::<wire::api::publish::latest::ExportRequest>(
+                                &body,
+                            ).context("Request doesn't match schema").err_external()?;
+                        let Ok(content) = req.content.verify(&req.resolver) else {
+                            return Ok(response_bad_signature());
+                        };
+                        if (Utc::now() - content.requested).abs() > Duration::try_minutes(5).unwrap() {
+                            return Ok(response_expired());
+                        }
+                        if content.identity != identity || !authorized.contains(&req.resolver) {
+                            return Ok(response_unauthorized());
+                        }
+                    }
+                    return Ok(response_200_json(state.publisher.export(&identity).await.err_internal()?));
+                }.await {
+                    Ok(r) => {
+                        return r;
+                    },
+                    Err(VisErr::External(e)) => {
+                        return response_bad_request(e);
+                    },
+                    Err(VisErr::Internal(e)) => {
+                        log_warn_err(&state.log, e.context("Error exporting record set"));
+                        return response_internal();
+                    },
+                }
+            }))
+        }).unwrap();
+        routes.insert("/read_stats", {
+            let state = state.clone();
+            Box::new(htwrap::handler!((state: Arc < State >)(r -> htserve:: responses:: Body) {
+                match async {
+                    ta_res!(Response < htserve:: responses:: Body >);
+
+                    // Params
+                    let req =
+                        match serde_json::from_slice::<wire::api::publish::v1::ReadStatsRequest>(
+                            &r.body.collect().await?.to_bytes(),
+                        ) {
+                            Ok(r) => r,
+                            Err(e) => {
+                                return Ok(
+                                    response_bad_request(format!("Invalid json: {}", e)),
+                                ) as Result<_, loga::Error>;
+                            },
+                        };
+                    let Ok(body) = req.content.verify(&req.identity) else {
+                        return Ok(response_bad_signature());
+                    };
+                    if (Utc::now() - body.requested).abs() > Duration::try_minutes(5).unwrap() {
+                        return Ok(response_expired());
+                    }
+
+                    // Auth
+                    if !state.authorizer.is_identity_allowed(&req.identity).await? {
+                        return Ok(response_unauthorized());
                     }
 
                     // Respond
-                    state.publisher.clear_identity(&req.identity).await?;
-                    return Ok(response_200());
+                    let Some(stats) = state.publisher.read_stats(&req.identity) else {
+                        return Ok(response_not_found());
+                    };
+                    return Ok(response_200_json(stats));
                 }.await {
                     Ok(r) => {
                         return r;
                     },
                     Err(e) => {
-                        state.log.log_err(loga::WARN, e.context("Error unpublishing key values"));
-                        return response_503();
+                        log_warn_err(&state.log, e.context("Error getting read stats"));
+                        return response_internal();
                     },
                 }
             }))
         }).unwrap();
-        routes.insert("/publish", {
+        routes.insert("/watch", {
             let state = state.clone();
             Box::new(htwrap::handler!((state: Arc < State >)(r -> htserve:: responses:: Body) {
                 match async {
@@ -641,38 +2398,39 @@ pub async fn build_api_endpoints_with_authorizer(
 
                     // Params
                     let req =
-                        match serde_json::from_slice::<wire::api::publish::v1::PublishRequest>(
+                        match serde_json::from_slice::<wire::api::publish::v1::WatchRequest>(
                             &r.body.collect().await?.to_bytes(),
                         ) {
                             Ok(r) => r,
                             Err(e) => {
-                                return Ok(response_400(format!("Invalid json: {}", e))) as Result<_, loga::Error>;
+                                return Ok(
+                                    response_bad_request(format!("Invalid json: {}", e)),
+                                ) as Result<_, loga::Error>;
                             },
                         };
                     let Ok(body) = req.content.verify(&req.identity) else {
-                        return Ok(response_400("Couldn't verify payload"));
+                        return Ok(response_bad_signature());
                     };
+                    if (Utc::now() - body.requested).abs() > Duration::try_minutes(5).unwrap() {
+                        return Ok(response_expired());
+                    }
 
                     // Auth
                     if !state.authorizer.is_identity_allowed(&req.identity).await? {
-                        return Ok(response_401());
+                        return Ok(response_unauthorized());
                     }
 
-                    // Publish it
-                    state.publisher.modify_values(&req.identity, publish_util::PublishArgs {
-                        missing_ttl: body.missing_ttl,
-                        clear_all: body.clear_all,
-                        clear: body.clear,
-                        set: body.set.into_iter().collect(),
-                    }).await?;
-                    return Ok(response_200());
+                    // Respond
+                    return Ok(
+                        response_200_json(state.publisher.watch(&req.identity, body.version.as_deref()).await?),
+                    );
                 }.await {
                     Ok(r) => {
                         return r;
                     },
                     Err(e) => {
-                        state.log.log_err(loga::WARN, e.context("Error publishing key values"));
-                        return response_503();
+                        log_warn_err(&state.log, e.context("Error watching record set"));
+                        return response_internal();
                     },
                 }
             }))
@@ -680,93 +2438,211 @@ pub async fn build_api_endpoints_with_authorizer(
         routes.insert("/info", {
             let state = state.clone();
             Box::new(htwrap::handler!((state: Arc < State >)(_r -> htserve:: responses:: Body) {
-                return response_200_json(wire::api::publish::v1::InfoResponse {
-                    advertise_addr: state.publisher.advertise_addr,
-                    cert_pub_hash: state.publisher.cert_pub_hash.clone(),
-                });
+                return response_200_json(state.publisher.info());
             }))
         }).unwrap();
         Box::new(routes)
     }).unwrap();
+    if jsonrpc {
+        routes.insert("/jsonrpc", {
+            let state = state.clone();
+            Box::new(htwrap::handler!((state: Arc < State >)(r -> htserve:: responses:: Body) {
+                let body = match r.body.collect().await {
+                    Ok(b) => b.to_bytes(),
+                    Err(e) => {
+                        return response_bad_request(format!("Error reading body: {}", e));
+                    },
+                };
+                let client_identity = r.head.extensions.get::<ClientCertIdentity>().map(|i| &i.0);
+                return jsonrpc::handle(&state.log, &body, |method, params| async move {
+                    async fn authorize(state: &State, identity: &Identity) -> Result<(), jsonrpc::RpcError> {
+                        if !state.authorizer.is_identity_allowed(identity).await? {
+                            return Err(
+                                jsonrpc::app_err(jsonrpc::ERR_UNAUTHORIZED, "Identity isn't allowed to publish here"),
+                            );
+                        }
+                        return Ok(());
+                    }
+
+                    match method.as_str() {
+                        "announce" => {
+                            let req = jsonrpc::params::<wire::api::publish::v1::AnnounceRequest>(params)?;
+                            match &req.announcement {
+                                Announcement::V1(a) => {
+                                    if a.verify(&req.identity).is_err() {
+                                        return Err(jsonrpc::invalid_params("Couldn't verify payload"));
+                                    }
+                                },
+                            };
+                            authorize(state, &req.identity).await?;
+                            return jsonrpc::result(state.publisher.announce(&req.identity, req.announcement).await?);
+                        },
+                        "clear_identity" => {
+                            let req = jsonrpc::params::<wire::api::publish::v1::DeleteAnnouncementRequest>(params)?;
+                            if req.challenge.verify(&req.identity).is_err() {
+                                return Err(jsonrpc::invalid_params("Couldn't verify payload"));
+                            }
+                            authorize(state, &req.identity).await?;
+                            state.publisher.clear_identity(&req.identity).await?;
+                            return jsonrpc::result(());
+                        },
+                        "publish" => {
+                            let req = jsonrpc::params::<wire::api::publish::v1::PublishRequest>(params)?;
+                            let body = match publish_content(&req, client_identity) {
+                                Ok(b) => b,
+                                Err(PublishContentError::BadSignature) => {
+                                    return Err(jsonrpc::invalid_params("Couldn't verify payload"));
+                                },
+                                Err(PublishContentError::Expired) => {
+                                    return Err(jsonrpc::invalid_params("Request time too far from current time"));
+                                },
+                            };
+                            authorize(state, &req.identity).await?;
+                            if let Some(reason) = state.publisher.check_publish_policy(&req.identity, &body).await {
+                                return Err(jsonrpc::app_err(jsonrpc::ERR_REJECTED, reason));
+                            }
+                            match state.publisher.modify_values(&req.identity, publish_util::PublishArgs {
+                                missing_ttl: body.missing_ttl,
+                                clear_all: body.clear_all,
+                                clear: body.clear,
+                                set: body.set.into_iter().collect(),
+                                if_version: body.if_version,
+                                published: body.published,
+                                ..Default::default()
+                            }).await? {
+                                ModifyValuesResult::Applied(version) => {
+                                    return jsonrpc::result(
+                                        wire::api::publish::v1::RecordSetVersion { version: version },
+                                    );
+                                },
+                                ModifyValuesResult::VersionMismatch(current) => {
+                                    return Err(jsonrpc::RpcError::App {
+                                        code: jsonrpc::ERR_VERSION_MISMATCH,
+                                        message: "`if_version` didn't match the current version".to_string(),
+                                        data: Some(
+                                            serde_json::to_value(
+                                                wire::api::publish::v1::RecordSetVersion { version: current },
+                                            ).unwrap(),
+                                        ),
+                                    });
+                                },
+                                ModifyValuesResult::StorageFull => {
+                                    return Err(
+                                        jsonrpc::app_err(jsonrpc::ERR_STORAGE_FULL, "Publisher storage is full"),
+                                    );
+                                },
+                                ModifyValuesResult::InvalidRecords(problems) => {
+                                    return Err(jsonrpc::RpcError::App {
+                                        code: jsonrpc::ERR_INVALID_PARAMS,
+                                        message: "Records rejected".to_string(),
+                                        data: Some(
+                                            serde_json::to_value(
+                                                wire::api::publish::v1::RejectedRecords { problems: problems },
+                                            ).unwrap(),
+                                        ),
+                                    });
+                                },
+                            }
+                        },
+                        "publish_batch" => {
+                            let req = jsonrpc::params::<wire::api::publish::v1::PublishBatchRequest>(params)?;
+                            if req.requests.len() > wire::api::publish::v1::PUBLISH_BATCH_MAX {
+                                return Err(jsonrpc::invalid_params("Too many requests in batch"));
+                            }
+                            return jsonrpc::result(publish_batch(state, client_identity, req).await);
+                        },
+                        "version" => {
+                            #[derive(Deserialize)]
+                            struct Params {
+                                identity: Identity,
+                            }
+
+                            let req = jsonrpc::params::<Params>(params)?;
+                            return jsonrpc::result(wire::api::publish::v1::RecordSetVersion {
+                                version: state.publisher.values_version(&req.identity).await?,
+                            });
+                        },
+                        "read_stats" => {
+                            let req = jsonrpc::params::<wire::api::publish::v1::ReadStatsRequest>(params)?;
+                            let Ok(body) = req.content.verify(&req.identity) else {
+                                return Err(jsonrpc::invalid_params("Couldn't verify payload"));
+                            };
+                            if (Utc::now() - body.requested).abs() > Duration::try_minutes(5).unwrap() {
+                                return Err(jsonrpc::invalid_params("Request time too far from current time"));
+                            }
+                            authorize(state, &req.identity).await?;
+                            let Some(stats) = state.publisher.read_stats(&req.identity) else {
+                                return Err(
+                                    jsonrpc::app_err(
+                                        jsonrpc::ERR_NOT_FOUND,
+                                        "The publisher doesn't track read statistics",
+                                    ),
+                                );
+                            };
+                            return jsonrpc::result(stats);
+                        },
+                        "watch" => {
+                            let req = jsonrpc::params::<wire::api::publish::v1::WatchRequest>(params)?;
+                            let Ok(body) = req.content.verify(&req.identity) else {
+                                return Err(jsonrpc::invalid_params("Couldn't verify payload"));
+                            };
+                            if (Utc::now() - body.requested).abs() > Duration::try_minutes(5).unwrap() {
+                                return Err(jsonrpc::invalid_params("Request time too far from current time"));
+                            }
+                            authorize(state, &req.identity).await?;
+                            return jsonrpc::result(
+                                state.publisher.watch(&req.identity, body.version.as_deref()).await?,
+                            );
+                        },
+                        "info" => {
+                            return jsonrpc::result(state.publisher.info());
+                        },
+                        _ => return Err(jsonrpc::RpcError::MethodNotFound),
+                    }
+                }).await;
+            }))
+        }).unwrap();
+    }
     return Ok(routes);
 }
 
 pub async fn build_api_endpoints(
     log: &Log,
     publisher: &Arc<Publisher>,
-    admin_token: &AuthTokenHash,
-    persist_dir: &Path,
+    admin_token: &AdminTokens,
+    jsonrpc: bool,
 ) -> Result<htserve::handler::PathRouter<htserve::responses::Body>, loga::Error> {
-    let db_pool =
-        setup_db(&persist_dir.join("publisher_admin.sqlite3"), admin_db::migrate)
-            .await
-            .context("Error initializing database")?;
-
     struct State {
         log: Log,
-        db_pool: Pool,
         publisher: Arc<Publisher>,
     }
 
     impl State {
         async fn allow_identity(&self, identity: &Identity, group: String) -> Result<(), loga::Error> {
-            let identity = identity.clone();
-            self.db_pool.get().await?.interact(move |db| {
-                admin_db::allow_identity(db, &identity, &group)?;
-                return Ok(()) as Result<(), GoodError>;
-            }).await??;
-            return Ok(());
+            return self.publisher.storage.allow_identity(identity, group).await;
         }
 
         async fn disallow_identity(&self, identity: &Identity) -> Result<(), loga::Error> {
-            let identity = identity.clone();
-            self.db_pool.get().await?.interact(move |db| {
-                admin_db::disallow_identity(db, &identity)?;
-                return Ok(()) as Result<(), GoodError>;
-            }).await??;
-            return Ok(());
+            return self.publisher.storage.disallow_identity(identity).await;
         }
 
-        async fn list_allowed_identities(
-            &self,
-            after: Option<&Identity>,
-        ) -> Result<Vec<AdminIdentity>, loga::Error> {
-            let after = after.cloned();
-            return Ok(match after {
-                None => {
-                    self.db_pool.get().await?.interact(|db| admin_db::list_allowed_identities_start(db)).await??
-                },
-                Some(a) => {
-                    self
-                        .db_pool
-                        .get()
-                        .await?
-                        .interact(move |db| admin_db::list_allowed_identities_after(db, &a))
-                        .await??
-                },
-            }.into_iter().map(|r| AdminIdentity {
-                identity: r.identity,
-                group: r.group,
-            }).collect());
+        async fn list_allowed_identities(&self, filter: &ListFilter) -> Result<Vec<AdminIdentity>, loga::Error> {
+            return self.publisher.storage.list_allowed_identities(filter).await;
         }
     }
 
     #[async_trait]
     impl PublisherAuthorizer for State {
         async fn is_identity_allowed(&self, identity: &Identity) -> Result<bool, loga::Error> {
-            let identity = identity.clone();
-            return Ok(self.db_pool.get().await?.interact(move |db| {
-                return Ok(admin_db::is_identity_allowed(db, &identity)?.is_some()) as Result<bool, GoodError>;
-            }).await??);
+            return self.publisher.storage.is_identity_allowed(identity).await;
         }
     }
 
     let state = Arc::new(State {
         log: log.clone(),
-        db_pool: db_pool,
         publisher: publisher.clone(),
     });
-    let mut routes = build_api_endpoints_with_authorizer(log, publisher, state.clone()).await?;
+    let mut routes = build_api_endpoints_with_authorizer(log, publisher, state.clone(), jsonrpc).await?;
     let admin_token = admin_token.clone();
     routes.insert("/admin", {
         let mut routes = htserve::handler::PathRouter::default();
@@ -774,43 +2650,27 @@ pub async fn build_api_endpoints(
             let state = state.clone();
             let admin_token = admin_token.clone();
             Box::new(
-                htwrap::handler!((state: Arc < State >, admin_token: AuthTokenHash)(r -> htserve:: responses:: Body) {
+                htwrap::handler!((state: Arc < State >, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
                     match async {
                         ta_vis_res!(Response < htserve:: responses:: Body >);
-                        if !check_auth_token_hash(
-                            &admin_token,
-                            &htserve::auth::get_auth_token(&r.head.headers).err_external()?,
-                        ) {
-                            return Ok(response_401());
+                        if !admin_token.check(&r.head.headers).err_external()? {
+                            return Ok(response_unauthorized());
                         }
                         match r.head.method {
                             Method::GET => {
-                                #[derive(Debug, Deserialize)]
-                                struct Params {
-                                    after: Option<String>,
-                                }
-
-                                let query = match serde_urlencoded::from_str::<Params>(&r.query) {
+                                let query = match serde_urlencoded::from_str::<ListFilter>(r.query) {
                                     Ok(q) => q,
                                     Err(e) => {
-                                        return Ok(response_400(format!("Invalid query parameters: {}", e)));
-                                    },
-                                };
-                                let after = match &query.after {
-                                    Some(i) => {
-                                        Some(Identity::from_str(i).err_external()?)
+                                        return Ok(response_bad_request(format!("Invalid query parameters: {}", e)));
                                     },
-                                    None => None,
                                 };
                                 return Ok(
-                                    response_200_json(
-                                        state.list_allowed_identities(after.as_ref()).await.err_internal()?,
-                                    ),
+                                    response_200_json(state.list_allowed_identities(&query).await.err_internal()?),
                                 );
                             },
                             Method::POST => {
                                 let Some(identity) = r.subpath.strip_prefix("/") else {
-                                    return Ok(response_400("Missing identity in path"));
+                                    return Ok(response_bad_request("Missing identity in path"));
                                 };
                                 let identity = Identity::from_str(&identity).err_external()?;
                                 let body =
@@ -827,14 +2687,14 @@ pub async fn build_api_endpoints(
                             },
                             Method::DELETE => {
                                 let Some(identity) = r.subpath.strip_prefix("/") else {
-                                    return Ok(response_400("Missing identity in path"));
+                                    return Ok(response_bad_request("Missing identity in path"));
                                 };
                                 let identity = Identity::from_str(&identity).err_external()?;
                                 state.disallow_identity(&identity).await.err_internal()?;
                                 state.publisher.clear_identity(&identity).await.err_internal()?;
                                 return Ok(response_200());
                             },
-                            _ => return Ok(response_404()),
+                            _ => return Ok(response_not_found()),
                         }
                     }.await {
                         Ok(d) => {
@@ -842,11 +2702,11 @@ pub async fn build_api_endpoints(
                         },
                         Err(e) => match e {
                             VisErr::Internal(e) => {
-                                state.log.log_err(loga::WARN, e.context("Error getting published identities"));
-                                return response_503();
+                                log_warn_err(&state.log, e.context("Error getting published identities"));
+                                return response_internal();
                             },
                             VisErr::External(e) => {
-                                return response_400(e);
+                                return response_bad_request(e);
                             },
                         },
                     }
@@ -857,41 +2717,161 @@ pub async fn build_api_endpoints(
             let state = state.clone();
             let admin_token = admin_token.clone();
             Box::new(
-                htwrap::handler!((state: Arc < State >, admin_token: AuthTokenHash)(r -> htserve:: responses:: Body) {
+                htwrap::handler!((state: Arc < State >, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
                     match async {
                         ta_res!(Response < htserve:: responses:: Body >);
-                        if !check_auth_token_hash(&admin_token, &htserve::auth::get_auth_token(&r.head.headers)?) {
-                            return Ok(response_401());
+                        if !admin_token.check(&r.head.headers)? {
+                            return Ok(response_unauthorized());
                         }
                         let Some(identity) = r.subpath.strip_prefix("/") else {
-                            return Ok(response_400("Missing identity in path"));
+                            return Ok(response_bad_request("Missing identity in path"));
                         };
-                        let identity = Identity::from_str(&identity)?;
-
-                        #[derive(Debug, Deserialize)]
-                        struct Params {
-                            after: Option<String>,
-                        }
-
-                        let query = match serde_urlencoded::from_str::<Params>(&r.query) {
+                        let identity = Identity::from_str(identity)?;
+                        let query = match serde_urlencoded::from_str::<ListFilter>(r.query) {
                             Ok(q) => q,
                             Err(e) => {
-                                return Ok(response_400(format!("Invalid query parameters: {}", e)));
+                                return Ok(response_bad_request(format!("Invalid query parameters: {}", e)));
                             },
                         };
 
                         // Respond
-                        return Ok(response_200_json(state.publisher.list_value_keys(&identity, query.after).await?));
+                        return Ok(response_200_json(state.publisher.list_value_keys(&identity, &query).await?));
                     }.await {
                         Ok(d) => {
                             return d;
                         },
                         Err(e) => {
-                            state
-                                .publisher
-                                .log
-                                .log_err(loga::WARN, e.context("Error getting published keys for identity"));
-                            return response_503();
+                            log_warn_err(
+                                &state.publisher.log,
+                                e.context("Error getting published keys for identity"),
+                            );
+                            return response_internal();
+                        },
+                    }
+                }),
+            )
+        }).unwrap();
+        routes.insert("/maintenance", {
+            let state = state.clone();
+            let admin_token = admin_token.clone();
+            Box::new(
+                htwrap::handler!((state: Arc < State >, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
+                    match async {
+                        ta_vis_res!(Response < htserve:: responses:: Body >);
+                        if !admin_token.check(&r.head.headers).err_external()? {
+                            return Ok(response_unauthorized());
+                        }
+                        match r.head.method {
+                            Method::GET => {
+                                return Ok(
+                                    response_200_json(
+                                        state.publisher.maintenance().map(|r| AdminMaintenance { retry_after: r }),
+                                    ),
+                                );
+                            },
+                            Method::POST => {
+                                let body =
+                                    serde_json::from_slice::<AdminMaintenance>(
+                                        &r.body.collect().await.err_external()?.to_bytes(),
+                                    )
+                                        .context("Bad request body")
+                                        .err_external()?;
+                                state.publisher.set_maintenance(Some(body.retry_after));
+                                return Ok(response_200());
+                            },
+                            Method::DELETE => {
+                                state.publisher.set_maintenance(None);
+                                return Ok(response_200());
+                            },
+                            _ => return Ok(response_not_found()),
+                        }
+                    }.await {
+                        Ok(d) => {
+                            return d;
+                        },
+                        Err(e) => match e {
+                            VisErr::Internal(e) => {
+                                log_warn_err(&state.log, e.context("Error changing maintenance mode"));
+                                return response_internal();
+                            },
+                            VisErr::External(e) => {
+                                return response_bad_request(e);
+                            },
+                        },
+                    }
+                }),
+            )
+        }).unwrap();
+        routes.insert("/reannounce", {
+            let state = state.clone();
+            let admin_token = admin_token.clone();
+            Box::new(
+                htwrap::handler!((state: Arc < State >, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
+                    match async {
+                        ta_vis_res!(Response < htserve:: responses:: Body >);
+                        if !admin_token.check(&r.head.headers).err_external()? {
+                            return Ok(response_unauthorized());
+                        }
+                        if r.head.method != Method::POST {
+                            return Ok(response_not_found());
+                        }
+                        let Some(identity) = r.subpath.strip_prefix("/") else {
+                            return Ok(response_bad_request("Missing identity in path"));
+                        };
+                        let identity = Identity::from_str(identity).err_external()?;
+                        let Some(resp) = state.publisher.reannounce(&identity).await.err_internal()? else {
+                            return Ok(response_not_found());
+                        };
+                        return Ok(response_200_json(resp));
+                    }.await {
+                        Ok(d) => {
+                            return d;
+                        },
+                        Err(e) => match e {
+                            VisErr::Internal(e) => {
+                                log_warn_err(&state.log, e.context("Error reannouncing identity"));
+                                return response_internal();
+                            },
+                            VisErr::External(e) => {
+                                return response_bad_request(e);
+                            },
+                        },
+                    }
+                }),
+            )
+        }).unwrap();
+        routes.insert("/watchdog", {
+            let state = state.clone();
+            let admin_token = admin_token.clone();
+            Box::new(
+                htwrap::handler!((state: Arc < State >, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
+                    match async {
+                        ta_vis_res!(Response < htserve:: responses:: Body >);
+                        if !admin_token.check(&r.head.headers).err_external()? {
+                            return Ok(response_unauthorized());
+                        }
+                        match r.head.method {
+                            Method::GET => {
+                                return Ok(response_200_json(state.publisher.watchdog_status()));
+                            },
+                            Method::DELETE => {
+                                state.publisher.clear_watchdog_alerts();
+                                return Ok(response_200());
+                            },
+                            _ => return Ok(response_not_found()),
+                        }
+                    }.await {
+                        Ok(d) => {
+                            return d;
+                        },
+                        Err(e) => match e {
+                            VisErr::Internal(e) => {
+                                log_warn_err(&state.log, e.context("Error serving watchdog status"));
+                                return response_internal();
+                            },
+                            VisErr::External(e) => {
+                                return response_bad_request(e);
+                            },
                         },
                     }
                 }),
@@ -901,29 +2881,32 @@ pub async fn build_api_endpoints(
             let state = state.clone();
             let admin_token = admin_token.clone();
             Box::new(
-                htwrap::handler!((state: Arc < State >, admin_token: AuthTokenHash)(r -> htserve:: responses:: Body) {
+                htwrap::handler!((state: Arc < State >, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
                     match async {
                         ta_res!(Response < htserve:: responses:: Body >);
-                        if !check_auth_token_hash(&admin_token, &htserve::auth::get_auth_token(&r.head.headers)?) {
-                            return Ok(response_401());
+                        if !admin_token.check(&r.head.headers)? {
+                            return Ok(response_unauthorized());
                         }
 
                         #[derive(Debug, Deserialize)]
                         struct Params {
                             after: Option<String>,
+                            prefix: Option<String>,
+                            limit: Option<usize>,
+                            since: Option<DateTime<Utc>>,
+                            until: Option<DateTime<Utc>>,
                         }
 
-                        let query = match serde_urlencoded::from_str::<Params>(&r.query) {
+                        let query = match serde_urlencoded::from_str::<Params>(r.query) {
                             Ok(q) => q,
                             Err(e) => {
-                                return Ok(response_400(format!("Invalid query parameters: {}", e)));
+                                return Ok(response_bad_request(format!("Invalid query parameters: {}", e)));
                             },
                         };
-                        let after = match query.after {
-                            Some(i) => {
-                                Some(Identity::from_str(&i)?)
-                            },
-                            None => None,
+                        let filter = ListFilter {
+                            after: query.after,
+                            prefix: query.prefix,
+                            limit: query.limit,
                         };
 
                         // Respond
@@ -931,7 +2914,7 @@ pub async fn build_api_endpoints(
                             response_200_json(
                                 state
                                     .publisher
-                                    .list_announcements(after.as_ref())
+                                    .list_announcements_between(&filter, query.since, query.until)
                                     .await?
                                     .into_iter()
                                     .map(|e| e.0)
@@ -943,8 +2926,161 @@ pub async fn build_api_endpoints(
                             return d;
                         },
                         Err(e) => {
-                            state.log.log_err(loga::WARN, e.context("Error getting published identities"));
-                            return response_503();
+                            log_warn_err(&state.log, e.context("Error getting published identities"));
+                            return response_internal();
+                        },
+                    }
+                }),
+            )
+        }).unwrap();
+        routes.insert("/usage", {
+            let state = state.clone();
+            let admin_token = admin_token.clone();
+            Box::new(
+                htwrap::handler!((state: Arc < State >, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
+                    match async {
+                        ta_vis_res!(Response < htserve:: responses:: Body >);
+                        if !admin_token.check(&r.head.headers).err_external()? {
+                            return Ok(response_unauthorized());
+                        }
+
+                        #[derive(Debug, Deserialize)]
+                        #[serde(rename_all = "snake_case")]
+                        enum Format {
+                            Json,
+                            Csv,
+                        }
+
+                        #[derive(Debug, Deserialize)]
+                        struct Params {
+                            since: Option<DateTime<Utc>>,
+                            until: Option<DateTime<Utc>>,
+                            format: Option<Format>,
+                        }
+
+                        let query =
+                            serde_urlencoded::from_str::<Params>(r.query)
+                                .context("Invalid query parameters")
+                                .err_external()?;
+                        let until = query.until.unwrap_or_else(Utc::now);
+                        let since = query.since.unwrap_or(until - Duration::try_days(30).unwrap());
+                        if since >= until {
+                            return Ok(response_bad_request("`since` must be before `until`"));
+                        }
+                        let report = state.publisher.usage(since, until).await.err_internal()?;
+                        match query.format.unwrap_or(Format::Json) {
+                            Format::Json => {
+                                return Ok(response_200_json(report));
+                            },
+                            Format::Csv => {
+                                return Ok(
+                                    Response::builder()
+                                        .status(200)
+                                        .header(CONTENT_TYPE, "text/csv")
+                                        .body(body_full(report.to_csv().into_bytes()))
+                                        .unwrap(),
+                                );
+                            },
+                        }
+                    }.await {
+                        Ok(d) => {
+                            return d;
+                        },
+                        Err(e) => match e {
+                            VisErr::Internal(e) => {
+                                log_warn_err(&state.log, e.context("Error generating usage report"));
+                                return response_internal();
+                            },
+                            VisErr::External(e) => {
+                                return response_bad_request(e);
+                            },
+                        },
+                    }
+                }),
+            )
+        }).unwrap();
+        routes.insert("/reverse", {
+            let state = state.clone();
+            let admin_token = admin_token.clone();
+            Box::new(
+                htwrap::handler!((state: Arc < State >, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
+                    match async {
+                        ta_vis_res!(Response < htserve:: responses:: Body >);
+                        if !admin_token.check(&r.head.headers).err_external()? {
+                            return Ok(response_unauthorized());
+                        }
+
+                        #[derive(Debug, Deserialize)]
+                        struct Params {
+                            ip: Option<IpAddr>,
+                            cert_hash: Option<Blob>,
+                        }
+
+                        let query =
+                            serde_urlencoded::from_str::<Params>(r.query)
+                                .context("Invalid query parameters")
+                                .err_external()?;
+                        let query = match (query.ip, query.cert_hash) {
+                            (Some(ip), None) => ReverseQuery::Ip(ip),
+                            (None, Some(hash)) => ReverseQuery::CertHash(hash),
+                            _ => {
+                                return Err(loga::err("Exactly one of `ip` or `cert_hash` is required")).err_external();
+                            },
+                        };
+                        return Ok(response_200_json(state.publisher.reverse_lookup(&query).await.err_internal()?));
+                    }.await {
+                        Ok(d) => {
+                            return d;
+                        },
+                        Err(e) => match e {
+                            VisErr::Internal(e) => {
+                                log_warn_err(&state.log, e.context("Error doing reverse lookup"));
+                                return response_internal();
+                            },
+                            VisErr::External(e) => {
+                                return response_bad_request(e);
+                            },
+                        },
+                    }
+                }),
+            )
+        }).unwrap();
+        routes.insert("/audit", {
+            let state = state.clone();
+            let admin_token = admin_token.clone();
+            Box::new(
+                htwrap::handler!((state: Arc < State >, admin_token: AdminTokens)(r -> htserve:: responses:: Body) {
+                    match async {
+                        ta_vis_res!(Response < htserve:: responses:: Body >);
+                        if !admin_token.check(&r.head.headers).err_external()? {
+                            return Ok(response_unauthorized());
+                        }
+
+                        #[derive(Debug, Deserialize)]
+                        struct Params {
+                            since: Option<DateTime<Utc>>,
+                            until: Option<DateTime<Utc>>,
+                        }
+
+                        let query =
+                            serde_urlencoded::from_str::<Params>(r.query)
+                                .context("Invalid query parameters")
+                                .err_external()?;
+                        let until = query.until.unwrap_or_else(Utc::now);
+                        let since = query.since.unwrap_or(until - Duration::try_days(30).unwrap());
+                        return Ok(response_200_json(state.publisher.audit_log(since, until).await.err_internal()?));
+                    }.await {
+                        Ok(d) => {
+                            return d;
+                        },
+                        Err(e) => match e {
+                            VisErr::Internal(e) => {
+                                log_warn_err(&state.log, e.context("Error getting audit log"));
+                                return response_internal();
+                            },
+                            VisErr::External(e) => {
+                                return response_bad_request(e);
+                            },
                         },
                     }
                 }),
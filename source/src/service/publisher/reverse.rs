@@ -0,0 +1,82 @@
+//! Matching published records against an address or certificate, to find which
+//! hosted identities publish them.
+use {
+    crate::{
+        interface::stored::record::{
+            dns_record::{
+                DnsA,
+                DnsAaaa,
+                KEY_SUFFIX_DNS_A,
+                KEY_SUFFIX_DNS_AAAA,
+            },
+            record_utils::RecordKey,
+            tls_record::{
+                TlsCerts,
+                KEY_SUFFIX_TLS,
+            },
+        },
+        utils::{
+            blob::Blob,
+            tls_util::cert_pem_hash,
+        },
+    },
+    std::net::IpAddr,
+};
+
+pub enum ReverseQuery {
+    Ip(IpAddr),
+    /// The SHA-256 hash of a certificate's SPKI, as used for TLS verification
+    CertHash(Blob),
+}
+
+/// Whether the record publishes the queried address or certificate. Records that
+/// don't match their schema never match.
+pub fn reverse_matches(query: &ReverseQuery, key: &RecordKey, data: &serde_json::Value) -> bool {
+    let Some(suffix) = key.last() else {
+        return false;
+    };
+    match (query, suffix.as_str()) {
+        (ReverseQuery::Ip(IpAddr::V4(ip)), KEY_SUFFIX_DNS_A) => {
+            match serde_json::from_value::<DnsA>(data.clone()) {
+                Ok(DnsA::V1(v)) => return v.0.contains(ip),
+                Err(_) => return false,
+            }
+        },
+        (ReverseQuery::Ip(IpAddr::V6(ip)), KEY_SUFFIX_DNS_AAAA) => {
+            match serde_json::from_value::<DnsAaaa>(data.clone()) {
+                Ok(DnsAaaa::V1(v)) => return v.0.contains(ip),
+                Err(_) => return false,
+            }
+        },
+        (ReverseQuery::CertHash(hash), KEY_SUFFIX_TLS) => {
+            match serde_json::from_value::<TlsCerts>(data.clone()) {
+                Ok(TlsCerts::V1(v)) => return v.0.iter().any(|c| cert_pem_hash(c).is_ok_and(|h| &h == hash)),
+                Err(_) => return false,
+            }
+        },
+        _ => return false,
+    }
+}
+
+#[cfg(test)]
+mod test_reverse {
+    use {
+        super::{
+            reverse_matches,
+            ReverseQuery,
+        },
+        crate::interface::stored::record::dns_record::KEY_SUFFIX_DNS_A,
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_ip() {
+        let key = vec!["www".to_string(), KEY_SUFFIX_DNS_A.to_string()];
+        let data = json!({
+            "v1": ["192.0.2.1", "192.0.2.2"]
+        });
+        assert!(reverse_matches(&ReverseQuery::Ip("192.0.2.2".parse().unwrap()), &key, &data));
+        assert!(!reverse_matches(&ReverseQuery::Ip("192.0.2.3".parse().unwrap()), &key, &data));
+        assert!(!reverse_matches(&ReverseQuery::Ip("2001:db8::1".parse().unwrap()), &key, &data));
+    }
+}
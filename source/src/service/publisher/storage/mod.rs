@@ -0,0 +1,188 @@
+//! Where the publisher keeps its data: announcements, published values, retention
+//! state, usage rollups, and the admin identity allowlist.
+//!
+//! By default this is a pair of sqlite databases in the data directory. With the
+//! experimental `postgres` feature the data can be kept in a shared Postgres
+//! database instead, so several publisher replicas (with the same announced
+//! addresses) can serve the same identities.
+use {
+    super::ModifyValuesResult,
+    crate::{
+        interface::{
+            stored::{
+                self,
+                announcement::Announcement,
+                identity::Identity,
+                record::{
+                    record_utils::{
+                        join_record_key,
+                        normalize_record_key,
+                        RecordKey,
+                    },
+                    RecordValue,
+                },
+            },
+            wire::api::admin::v1::{
+                AdminAuditEntry,
+                AdminAuditEvent,
+                AdminIdentity,
+            },
+        },
+        utils::{
+            db_util::ListFilter,
+            publish_util::PublishArgs,
+        },
+    },
+    async_trait::async_trait,
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    sha2::{
+        Digest,
+        Sha256,
+    },
+    std::collections::HashMap,
+};
+
+pub mod sqlite;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
+/// A published value and when it was published, if known.
+pub struct StoredValue {
+    pub value: RecordValue,
+    pub published: Option<DateTime<Utc>>,
+}
+
+/// Saved usage for an identity, for one rollup period.
+pub struct UsageRow {
+    pub start: DateTime<Utc>,
+    pub identity: Identity,
+    pub resolves: u64,
+    pub bytes: u64,
+}
+
+/// Methods that change more than one thing are atomic. Keys are passed and returned
+/// in their stored form (see `db_key`).
+#[async_trait]
+pub trait PublisherStorage: Send + Sync {
+    /// Bytes used by the stored data, for enforcing `max_db_size`.
+    async fn size(&self) -> Result<u64, loga::Error>;
+
+    async fn get_certs(&self) -> Result<Option<stored::publisher::Certs>, loga::Error>;
+
+    /// Store the certs unless some were already stored (ex: by another replica).
+    /// Returns the stored certs.
+    async fn ensure_certs(
+        &self,
+        certs: stored::publisher::Certs,
+    ) -> Result<stored::publisher::Certs, loga::Error>;
+
+    async fn get_announcement(&self, identity: &Identity) -> Result<Option<Announcement>, loga::Error>;
+
+    /// Store the announcement and record activity for the identity.
+    async fn set_announcement(
+        &self,
+        identity: &Identity,
+        announcement: &Announcement,
+        now: DateTime<Utc>,
+    ) -> Result<(), loga::Error>;
+
+    /// Clear the identity if its local announcement is older than `remote_announced`.
+    /// Returns the local announcement time if it was cleared.
+    async fn clear_superseded(
+        &self,
+        identity: &Identity,
+        remote_announced: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, loga::Error>;
+
+    /// Delete the announcement, values, and retention state for an identity. Audit
+    /// entries are kept.
+    async fn clear_identity(&self, identity: &Identity) -> Result<(), loga::Error>;
+
+    async fn list_announcements(&self, filter: &ListFilter) -> Result<Vec<(Identity, Announcement)>, loga::Error>;
+
+    /// When the identity was marked dormant, if it is.
+    async fn dormant_since(&self, identity: &Identity) -> Result<Option<DateTime<Utc>>, loga::Error>;
+
+    /// When the identity last announced or published, if tracked.
+    async fn last_active(&self, identity: &Identity) -> Result<Option<DateTime<Utc>>, loga::Error>;
+
+    /// Mark the identity dormant and record it in the audit log.
+    async fn mark_dormant(&self, identity: &Identity, now: DateTime<Utc>) -> Result<(), loga::Error>;
+
+    async fn audit_insert(
+        &self,
+        time: DateTime<Utc>,
+        identity: &Identity,
+        event: AdminAuditEvent,
+    ) -> Result<(), loga::Error>;
+
+    /// Audit entries in a time range, oldest first.
+    async fn audit_log(&self, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<Vec<AdminAuditEntry>, loga::Error>;
+
+    /// Apply `args` (with already canonicalized `set` values), unless `if_version`
+    /// doesn't match. Records activity for the identity.
+    async fn modify_values(
+        &self,
+        identity: &Identity,
+        args: PublishArgs,
+        published: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<ModifyValuesResult, loga::Error>;
+
+    /// All published values for an identity, in key order, with the record set
+    /// version.
+    async fn record_set(&self, identity: &Identity) -> Result<(String, Vec<(String, RecordValue)>), loga::Error>;
+
+    /// Values for the keys that have them.
+    async fn get_values(
+        &self,
+        identity: &Identity,
+        keys: Vec<String>,
+    ) -> Result<HashMap<String, StoredValue>, loga::Error>;
+
+    /// The TTL (minutes) set for responses for missing keys.
+    async fn missing_ttl(&self, identity: &Identity) -> Result<Option<i64>, loga::Error>;
+
+    async fn list_value_keys(&self, identity: &Identity, filter: &ListFilter) -> Result<Vec<String>, loga::Error>;
+
+    /// Save usage for a rollup period and delete usage that ended before `expire`.
+    async fn save_usage(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        usage: Vec<(Identity, u64, u64)>,
+        expire: DateTime<Utc>,
+    ) -> Result<(), loga::Error>;
+
+    /// Saved usage for periods overlapping `since` to `until`.
+    async fn usage_between(&self, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<Vec<UsageRow>, loga::Error>;
+
+    async fn allow_identity(&self, identity: &Identity, group: String) -> Result<(), loga::Error>;
+
+    async fn disallow_identity(&self, identity: &Identity) -> Result<(), loga::Error>;
+
+    async fn list_allowed_identities(&self, filter: &ListFilter) -> Result<Vec<AdminIdentity>, loga::Error>;
+
+    async fn is_identity_allowed(&self, identity: &Identity) -> Result<bool, loga::Error>;
+}
+
+/// The stored form of a record key.
+pub fn db_key(key: &RecordKey) -> String {
+    return join_record_key(&normalize_record_key(key));
+}
+
+/// Hash published key-values (stored keys, in key order) for the record set
+/// version.
+pub fn values_version<'a>(values: impl IntoIterator<Item = (&'a String, &'a RecordValue)>) -> String {
+    let mut hash = Sha256::new();
+    for (k, v) in values {
+        hash.update(k.as_bytes());
+        hash.update([0u8]);
+        hash.update(serde_json::to_vec(v).unwrap());
+        hash.update([0u8]);
+    }
+    return zbase32::encode_full_bytes(&hash.finalize());
+}
@@ -0,0 +1,686 @@
+use {
+    super::{
+        db_key,
+        values_version,
+        PublisherStorage,
+        StoredValue,
+        UsageRow,
+    },
+    crate::{
+        interface::{
+            stored::{
+                self,
+                announcement::Announcement,
+                identity::Identity,
+                record::RecordValue,
+            },
+            wire::api::admin::v1::{
+                AdminAuditEntry,
+                AdminAuditEvent,
+                AdminIdentity,
+            },
+        },
+        service::publisher::ModifyValuesResult,
+        utils::{
+            db_util::ListFilter,
+            publish_util::PublishArgs,
+        },
+    },
+    async_trait::async_trait,
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    deadpool_postgres::{
+        GenericClient,
+        Manager,
+        ManagerConfig,
+        Pool,
+        RecyclingMethod,
+    },
+    good_ormning_runtime::sqlite::GoodOrmningCustomString,
+    loga::{
+        ea,
+        Log,
+        ResultContext,
+    },
+    std::{
+        collections::HashMap,
+        str::FromStr,
+    },
+};
+
+/// Schema migrations, applied in order. Only append to this.
+///
+/// Text keys use the `C` collation so paging by key matches sqlite's ordering.
+const MIGRATIONS: &[&str] = &[
+    r#"
+    create table certs (singleton integer primary key, certs text not null);
+    create table announce (identity text collate "C" primary key, value text not null);
+    create table publish_idents (identity text primary key, missing_ttl bigint);
+    create table publish_values (
+        identity text not null,
+        key text collate "C" not null,
+        value text not null,
+        published timestamptz,
+        primary key (identity, key)
+    );
+    create table identity_activity (identity text primary key, last_active timestamptz not null);
+    create table dormant (identity text primary key, since timestamptz not null);
+    create table audit (time timestamptz not null, identity text not null, event text not null);
+    create index audit_time on audit (time);
+    create table usage (
+        start timestamptz not null,
+        "end" timestamptz not null,
+        identity text not null,
+        resolves bigint not null,
+        bytes bigint not null
+    );
+    create index usage_end on usage ("end");
+    create table allowed_identities (identity text collate "C" primary key, "group" text not null);
+    "#
+];
+
+/// Arbitrary key for the lock held while migrating, so replicas starting at the
+/// same time don't migrate concurrently.
+const MIGRATE_LOCK: i64 = 0x7370_6167_6870_7562;
+
+fn enc<T: GoodOrmningCustomString<T>>(v: &T) -> String {
+    return T::to_sql(v).into_owned();
+}
+
+fn dec<T: GoodOrmningCustomString<T>>(v: String) -> Result<T, loga::Error> {
+    return T::from_sql(v).map_err(|e| loga::err_with("Error decoding value from database", ea!(err = e)));
+}
+
+fn dec_ident(v: String) -> Result<Identity, loga::Error> {
+    return Identity::from_str(&v).context("Error decoding identity from database");
+}
+
+/// Publisher data in a Postgres database, which can be shared by multiple
+/// publishers.
+pub struct PostgresStorage {
+    pool: Pool,
+}
+
+impl PostgresStorage {
+    pub async fn new(log: &Log, url: &str, max_connections: usize) -> Result<PostgresStorage, loga::Error> {
+        let pg_config = tokio_postgres::Config::from_str(url).stack_context(log, "Invalid Postgres url")?;
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in rustls_native_certs::load_native_certs().stack_context(log, "Error loading system root certs")? {
+            roots.add(cert).stack_context(log, "Error adding system root cert")?;
+        }
+        let tls =
+            tokio_postgres_rustls::MakeRustlsConnect::new(
+                rustls::ClientConfig::builder().with_root_certificates(roots).with_no_client_auth(),
+            );
+        let manager = Manager::from_config(pg_config, tls, ManagerConfig { recycling_method: RecyclingMethod::Fast });
+        let pool =
+            Pool::builder(manager)
+                .max_size(max_connections)
+                .build()
+                .stack_context(log, "Error constructing Postgres pool")?;
+        let mut conn = pool.get().await.stack_context(log, "Error connecting to Postgres")?;
+        let tx = conn.transaction().await?;
+        tx.execute("select pg_advisory_xact_lock($1)", &[&MIGRATE_LOCK]).await?;
+        tx.batch_execute("create table if not exists publisher_schema (version bigint not null)").await?;
+        let version = match tx.query_opt("select version from publisher_schema", &[]).await? {
+            Some(r) => r.get::<_, i64>(0),
+            None => {
+                tx.execute("insert into publisher_schema (version) values (0)", &[]).await?;
+                0
+            },
+        };
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            tx
+                .batch_execute(migration)
+                .await
+                .stack_context_with(log, "Error migrating Postgres database", ea!(version = i + 1))?;
+        }
+        tx.execute("update publisher_schema set version = $1", &[&(MIGRATIONS.len() as i64)]).await?;
+        tx.commit().await.stack_context(log, "Error committing Postgres migrations")?;
+        drop(conn);
+        return Ok(PostgresStorage { pool: pool });
+    }
+}
+
+async fn get_announcement(db: &impl GenericClient, identity: &Identity) -> Result<Option<Announcement>, loga::Error> {
+    let Some(r) =
+        db.query_opt("select value from announce where identity = $1", &[&identity.to_string()]).await? else {
+            return Ok(None);
+        };
+    return Ok(Some(dec(r.get(0))?));
+}
+
+/// All published key-values for an identity, in key order.
+async fn all_values(db: &impl GenericClient, identity: &Identity) -> Result<Vec<(String, RecordValue)>, loga::Error> {
+    let mut out = vec![];
+    for r in db
+        .query("select key, value from publish_values where identity = $1 order by key", &[&identity.to_string()])
+        .await? {
+        out.push((r.get(0), dec(r.get(1))?));
+    }
+    return Ok(out);
+}
+
+async fn version(db: &impl GenericClient, identity: &Identity) -> Result<String, loga::Error> {
+    return Ok(values_version(all_values(db, identity).await?.iter().map(|(k, v)| (k, v))));
+}
+
+/// Record that the identity announced or published, reactivating it if dormant.
+async fn touch_identity(db: &impl GenericClient, identity: &Identity, now: DateTime<Utc>) -> Result<(), loga::Error> {
+    let identity = identity.to_string();
+    db
+        .execute(
+            "insert into identity_activity (identity, last_active) values ($1, $2) \
+            on conflict (identity) do update set last_active = excluded.last_active",
+            &[&identity, &now],
+        )
+        .await?;
+    if db.execute("delete from dormant where identity = $1", &[&identity]).await? > 0 {
+        db
+            .execute(
+                "insert into audit (time, identity, event) values ($1, $2, $3)",
+                &[&now, &identity, &enc(&AdminAuditEvent::Reactivated)],
+            )
+            .await?;
+    }
+    return Ok(());
+}
+
+async fn clear_identity(db: &impl GenericClient, identity: &Identity) -> Result<(), loga::Error> {
+    let identity = identity.to_string();
+    for table in ["announce", "publish_values", "identity_activity", "dormant"] {
+        db.execute(&format!("delete from {} where identity = $1", table), &[&identity]).await?;
+    }
+    return Ok(());
+}
+
+#[async_trait]
+impl PublisherStorage for PostgresStorage {
+    async fn size(&self) -> Result<u64, loga::Error> {
+        let db = self.pool.get().await?;
+        let size: i64 = db.query_one("select pg_database_size(current_database())", &[]).await?.get(0);
+        return Ok(size as u64);
+    }
+
+    async fn get_certs(&self) -> Result<Option<stored::publisher::Certs>, loga::Error> {
+        let db = self.pool.get().await?;
+        let Some(r) = db.query_opt("select certs from certs where singleton = 0", &[]).await? else {
+            return Ok(None);
+        };
+        return Ok(Some(dec(r.get(0))?));
+    }
+
+    async fn ensure_certs(
+        &self,
+        certs: stored::publisher::Certs,
+    ) -> Result<stored::publisher::Certs, loga::Error> {
+        let db = self.pool.get().await?;
+        db
+            .execute(
+                "insert into certs (singleton, certs) values (0, $1) on conflict (singleton) do nothing",
+                &[&enc(&certs)],
+            )
+            .await?;
+        return dec(db.query_one("select certs from certs where singleton = 0", &[]).await?.get(0));
+    }
+
+    async fn get_announcement(&self, identity: &Identity) -> Result<Option<Announcement>, loga::Error> {
+        return get_announcement(&self.pool.get().await?, identity).await;
+    }
+
+    async fn set_announcement(
+        &self,
+        identity: &Identity,
+        announcement: &Announcement,
+        now: DateTime<Utc>,
+    ) -> Result<(), loga::Error> {
+        let mut db = self.pool.get().await?;
+        let tx = db.transaction().await?;
+        tx
+            .execute(
+                "insert into announce (identity, value) values ($1, $2) \
+                on conflict (identity) do update set value = excluded.value",
+                &[&identity.to_string(), &enc(announcement)],
+            )
+            .await?;
+        touch_identity(&tx, identity, now).await?;
+        tx.commit().await?;
+        return Ok(());
+    }
+
+    async fn clear_superseded(
+        &self,
+        identity: &Identity,
+        remote_announced: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, loga::Error> {
+        let mut db = self.pool.get().await?;
+        let tx = db.transaction().await?;
+        tx.execute("select 1 from announce where identity = $1 for update", &[&identity.to_string()]).await?;
+        let Some(local_announcement) = get_announcement(&tx, identity).await? else {
+            return Ok(None);
+        };
+        let local_announced =
+            local_announcement
+                .parse()
+                .context_with("Stored announcement is malformed", ea!(identity = identity))?
+                .announced;
+        if remote_announced <= local_announced {
+            return Ok(None);
+        }
+        clear_identity(&tx, identity).await?;
+        tx.commit().await?;
+        return Ok(Some(local_announced));
+    }
+
+    async fn clear_identity(&self, identity: &Identity) -> Result<(), loga::Error> {
+        let mut db = self.pool.get().await?;
+        let tx = db.transaction().await?;
+        clear_identity(&tx, identity).await?;
+        tx.commit().await?;
+        return Ok(());
+    }
+
+    async fn list_announcements(&self, filter: &ListFilter) -> Result<Vec<(Identity, Announcement)>, loga::Error> {
+        let (after, prefix_start, prefix_end, limit) = filter.params();
+        let db = self.pool.get().await?;
+        let mut out = vec![];
+        for r in db
+            .query(
+                "select identity, value from announce \
+                where identity > $1 and identity >= $2 and identity < $3 order by identity limit $4",
+                &[&after, &prefix_start, &prefix_end, &limit],
+            )
+            .await? {
+            out.push((dec_ident(r.get(0))?, dec(r.get(1))?));
+        }
+        return Ok(out);
+    }
+
+    async fn dormant_since(&self, identity: &Identity) -> Result<Option<DateTime<Utc>>, loga::Error> {
+        let db = self.pool.get().await?;
+        return Ok(
+            db
+                .query_opt("select since from dormant where identity = $1", &[&identity.to_string()])
+                .await?
+                .map(|r| r.get(0)),
+        );
+    }
+
+    async fn last_active(&self, identity: &Identity) -> Result<Option<DateTime<Utc>>, loga::Error> {
+        let db = self.pool.get().await?;
+        return Ok(
+            db
+                .query_opt("select last_active from identity_activity where identity = $1", &[&identity.to_string()])
+                .await?
+                .map(|r| r.get(0)),
+        );
+    }
+
+    async fn mark_dormant(&self, identity: &Identity, now: DateTime<Utc>) -> Result<(), loga::Error> {
+        let mut db = self.pool.get().await?;
+        let tx = db.transaction().await?;
+        let identity = identity.to_string();
+        if tx
+            .execute(
+                "insert into dormant (identity, since) values ($1, $2) on conflict (identity) do nothing",
+                &[&identity, &now],
+            )
+            .await? > 0 {
+            tx
+                .execute(
+                    "insert into audit (time, identity, event) values ($1, $2, $3)",
+                    &[&now, &identity, &enc(&AdminAuditEvent::Dormant)],
+                )
+                .await?;
+        }
+        tx.commit().await?;
+        return Ok(());
+    }
+
+    async fn audit_insert(
+        &self,
+        time: DateTime<Utc>,
+        identity: &Identity,
+        event: AdminAuditEvent,
+    ) -> Result<(), loga::Error> {
+        let db = self.pool.get().await?;
+        db
+            .execute(
+                "insert into audit (time, identity, event) values ($1, $2, $3)",
+                &[&time, &identity.to_string(), &enc(&event)],
+            )
+            .await?;
+        return Ok(());
+    }
+
+    async fn audit_log(&self, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<Vec<AdminAuditEntry>, loga::Error> {
+        let db = self.pool.get().await?;
+        let mut out = vec![];
+        for r in db
+            .query(
+                "select time, identity, event from audit where time >= $1 and time < $2 order by time",
+                &[&since, &until],
+            )
+            .await? {
+            out.push(AdminAuditEntry {
+                time: r.get(0),
+                identity: dec_ident(r.get(1))?,
+                event: dec(r.get(2))?,
+            });
+        }
+        return Ok(out);
+    }
+
+    async fn modify_values(
+        &self,
+        identity: &Identity,
+        args: PublishArgs,
+        published: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<ModifyValuesResult, loga::Error> {
+        let mut db = self.pool.get().await?;
+        let tx = db.transaction().await?;
+        let identity_str = identity.to_string();
+
+        // Serialize changes to the identity's values across publishers. The row is only
+        // for locking until a missing ttl is set, like sqlite which has no row.
+        tx
+            .execute(
+                "insert into publish_idents (identity, missing_ttl) values ($1, null) \
+                on conflict (identity) do nothing",
+                &[&identity_str],
+            )
+            .await?;
+        tx.execute("select 1 from publish_idents where identity = $1 for update", &[&identity_str]).await?;
+        if let Some(if_version) = &args.if_version {
+            let current = version(&tx, identity).await?;
+            if &current != if_version {
+                return Ok(ModifyValuesResult::VersionMismatch(current));
+            }
+        }
+        if let Some(missing_ttl) = args.missing_ttl {
+            tx
+                .execute(
+                    "update publish_idents set missing_ttl = $2 where identity = $1",
+                    &[&identity_str, &(missing_ttl as i64)],
+                )
+                .await?;
+        }
+        if args.clear_all {
+            tx.execute("delete from publish_values where identity = $1", &[&identity_str]).await?;
+        }
+        for k in args.clear {
+            tx
+                .execute("delete from publish_values where identity = $1 and key = $2", &[&identity_str, &db_key(&k)])
+                .await?;
+        }
+        for (k, v) in args.set {
+            tx
+                .execute(
+                    "insert into publish_values (identity, key, value, published) values ($1, $2, $3, $4) \
+                    on conflict (identity, key) do update set value = excluded.value, published = excluded.published",
+                    &[&identity_str, &db_key(&k), &enc(&v), &published],
+                )
+                .await?;
+        }
+        touch_identity(&tx, identity, now).await?;
+        let version = version(&tx, identity).await?;
+        tx.commit().await?;
+        return Ok(ModifyValuesResult::Applied(version));
+    }
+
+    async fn record_set(&self, identity: &Identity) -> Result<(String, Vec<(String, RecordValue)>), loga::Error> {
+        let values = all_values(&self.pool.get().await?, identity).await?;
+        return Ok((values_version(values.iter().map(|(k, v)| (k, v))), values));
+    }
+
+    async fn get_values(
+        &self,
+        identity: &Identity,
+        keys: Vec<String>,
+    ) -> Result<HashMap<String, StoredValue>, loga::Error> {
+        let db = self.pool.get().await?;
+        let mut out = HashMap::new();
+        for r in db
+            .query(
+                "select key, value, published from publish_values where identity = $1 and key = any($2)",
+                &[&identity.to_string(), &keys],
+            )
+            .await? {
+            out.insert(r.get(0), StoredValue {
+                value: dec(r.get(1))?,
+                published: r.get(2),
+            });
+        }
+        return Ok(out);
+    }
+
+    async fn missing_ttl(&self, identity: &Identity) -> Result<Option<i64>, loga::Error> {
+        let db = self.pool.get().await?;
+        return Ok(
+            db
+                .query_opt("select missing_ttl from publish_idents where identity = $1", &[&identity.to_string()])
+                .await?
+                .and_then(|r| r.get::<_, Option<i64>>(0)),
+        );
+    }
+
+    async fn list_value_keys(&self, identity: &Identity, filter: &ListFilter) -> Result<Vec<String>, loga::Error> {
+        let (after, prefix_start, prefix_end, limit) = filter.params();
+        let db = self.pool.get().await?;
+        return Ok(
+            db
+                .query(
+                    "select key from publish_values \
+                    where identity = $1 and key > $2 and key >= $3 and key < $4 order by key limit $5",
+                    &[&identity.to_string(), &after, &prefix_start, &prefix_end, &limit],
+                )
+                .await?
+                .into_iter()
+                .map(|r| r.get(0))
+                .collect(),
+        );
+    }
+
+    async fn save_usage(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        usage: Vec<(Identity, u64, u64)>,
+        expire: DateTime<Utc>,
+    ) -> Result<(), loga::Error> {
+        let mut db = self.pool.get().await?;
+        let tx = db.transaction().await?;
+        for (identity, resolves, bytes) in usage {
+            tx
+                .execute(
+                    "insert into usage (start, \"end\", identity, resolves, bytes) values ($1, $2, $3, $4, $5)",
+                    &[&start, &end, &identity.to_string(), &(resolves as i64), &(bytes as i64)],
+                )
+                .await?;
+        }
+        tx.execute("delete from usage where \"end\" < $1", &[&expire]).await?;
+        tx.commit().await?;
+        return Ok(());
+    }
+
+    async fn usage_between(&self, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<Vec<UsageRow>, loga::Error> {
+        let db = self.pool.get().await?;
+        let mut out = vec![];
+        for r in db
+            .query(
+                "select start, identity, resolves, bytes from usage where \"end\" > $1 and start < $2",
+                &[&since, &until],
+            )
+            .await? {
+            out.push(UsageRow {
+                start: r.get(0),
+                identity: dec_ident(r.get(1))?,
+                resolves: r.get::<_, i64>(2) as u64,
+                bytes: r.get::<_, i64>(3) as u64,
+            });
+        }
+        return Ok(out);
+    }
+
+    async fn allow_identity(&self, identity: &Identity, group: String) -> Result<(), loga::Error> {
+        let db = self.pool.get().await?;
+        db
+            .execute(
+                "insert into allowed_identities (identity, \"group\") values ($1, $2) \
+                on conflict (identity) do update set \"group\" = excluded.\"group\"",
+                &[&identity.to_string(), &group],
+            )
+            .await?;
+        return Ok(());
+    }
+
+    async fn disallow_identity(&self, identity: &Identity) -> Result<(), loga::Error> {
+        let db = self.pool.get().await?;
+        db.execute("delete from allowed_identities where identity = $1", &[&identity.to_string()]).await?;
+        return Ok(());
+    }
+
+    async fn list_allowed_identities(&self, filter: &ListFilter) -> Result<Vec<AdminIdentity>, loga::Error> {
+        let (after, prefix_start, prefix_end, limit) = filter.params();
+        let db = self.pool.get().await?;
+        let mut out = vec![];
+        for r in db
+            .query(
+                "select identity, \"group\" from allowed_identities \
+                where identity > $1 and identity >= $2 and identity < $3 order by identity limit $4",
+                &[&after, &prefix_start, &prefix_end, &limit],
+            )
+            .await? {
+            out.push(AdminIdentity {
+                identity: dec_ident(r.get(0))?,
+                group: r.get(1),
+            });
+        }
+        return Ok(out);
+    }
+
+    async fn is_identity_allowed(&self, identity: &Identity) -> Result<bool, loga::Error> {
+        let db = self.pool.get().await?;
+        return Ok(
+            db
+                .query_opt("select 1 from allowed_identities where identity = $1", &[&identity.to_string()])
+                .await?
+                .is_some(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_postgres {
+    use {
+        super::{
+            PostgresStorage,
+            MIGRATIONS,
+        },
+        crate::{
+            interface::{
+                config::identity::LocalIdentitySecret,
+                stored::{
+                    self,
+                    record::RecordValue,
+                },
+            },
+            service::publisher::{
+                storage::{
+                    db_key,
+                    PublisherStorage,
+                },
+                ModifyValuesResult,
+            },
+            utils::{
+                publish_util::PublishArgs,
+                signed::IdentSignatureMethods,
+            },
+        },
+        chrono::Utc,
+        loga::Log,
+        std::collections::{
+            HashMap,
+            HashSet,
+        },
+    };
+
+    /// Postgres url for the tests (ex: `host=/run/postgresql dbname=spagh_test`).
+    /// Rows for random identities are left behind, so use a database just for
+    /// testing.
+    const ENV_TEST_URL: &str = "SPAGH_TEST_POSTGRES_URL";
+
+    async fn storage() -> PostgresStorage {
+        let url = std::env::var(ENV_TEST_URL).expect("Set SPAGH_TEST_POSTGRES_URL to run the Postgres tests");
+        return PostgresStorage::new(&Log::new(), &url, 2).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "Needs a Postgres database, see ENV_TEST_URL"]
+    async fn test_migrations() {
+        // Migrating an already migrated database does nothing
+        storage().await;
+        let storage = storage().await;
+        let conn = storage.pool.get().await.unwrap();
+        let version = conn.query_one("select version from publisher_schema", &[]).await.unwrap().get::<_, i64>(0);
+        assert_eq!(version, MIGRATIONS.len() as i64);
+    }
+
+    #[tokio::test]
+    #[ignore = "Needs a Postgres database, see ENV_TEST_URL"]
+    async fn test_round_trip() {
+        let storage = storage().await;
+        let (identity, mut secret) = LocalIdentitySecret::new();
+        let now = Utc::now();
+
+        // Announcements
+        let (_, announcement) =
+            stored::announcement::v1::Announcement::sign(&mut secret, stored::announcement::v1::AnnouncementContent {
+                publishers: vec![],
+                announced: now,
+            }).unwrap();
+        let announcement = stored::announcement::Announcement::V1(announcement);
+        storage.set_announcement(&identity, &announcement, now).await.unwrap();
+        assert_eq!(storage.get_announcement(&identity).await.unwrap(), Some(announcement));
+        assert_eq!(storage.last_active(&identity).await.unwrap().map(|t| t.timestamp()), Some(now.timestamp()));
+
+        // Values
+        let key = vec!["www".to_string(), "a".to_string()];
+        let value = RecordValue::latest(stored::record::latest::RecordValue {
+            ttl: 60,
+            data: Some(serde_json::json!(["1.2.3.4"])),
+        });
+        let result = storage.modify_values(&identity, PublishArgs {
+            missing_ttl: Some(5),
+            set: HashMap::from([(key.clone(), value.clone())]),
+            ..Default::default()
+        }, now, now).await.unwrap();
+        let ModifyValuesResult::Applied(version) = result else {
+            panic!("Values weren't applied");
+        };
+        let (got_version, got) = storage.record_set(&identity).await.unwrap();
+        assert_eq!(got_version, version);
+        assert_eq!(got.len(), 1);
+        assert_eq!(got[0].0, db_key(&key));
+        assert_eq!(serde_json::to_value(&got[0].1).unwrap(), serde_json::to_value(&value).unwrap());
+        let got = storage.get_values(&identity, vec![db_key(&key)]).await.unwrap();
+        assert_eq!(got.get(&db_key(&key)).unwrap().published.map(|t| t.timestamp()), Some(now.timestamp()));
+        assert_eq!(storage.missing_ttl(&identity).await.unwrap(), Some(5));
+
+        // Version precondition
+        let result = storage.modify_values(&identity, PublishArgs {
+            clear: HashSet::from([key.clone()]),
+            if_version: Some("stale".to_string()),
+            ..Default::default()
+        }, now, now).await.unwrap();
+        assert!(matches!(result, ModifyValuesResult::VersionMismatch(v) if v == version));
+
+        // Clearing
+        storage.clear_identity(&identity).await.unwrap();
+        assert_eq!(storage.get_announcement(&identity).await.unwrap(), None);
+        assert!(storage.record_set(&identity).await.unwrap().1.is_empty());
+    }
+}
@@ -0,0 +1,418 @@
+use {
+    super::{
+        db_key,
+        values_version,
+        PublisherStorage,
+        StoredValue,
+        UsageRow,
+    },
+    crate::{
+        interface::{
+            stored::{
+                self,
+                announcement::Announcement,
+                identity::Identity,
+                record::RecordValue,
+            },
+            wire::api::admin::v1::{
+                AdminAuditEntry,
+                AdminAuditEvent,
+                AdminIdentity,
+            },
+        },
+        service::publisher::{
+            admin_db,
+            db,
+            ModifyValuesResult,
+        },
+        utils::{
+            db_util::{
+                db_disk_usage,
+                setup_db,
+                DbTx,
+                ListFilter,
+            },
+            publish_util::PublishArgs,
+        },
+    },
+    async_trait::async_trait,
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    deadpool_sqlite::Pool,
+    loga::{
+        ea,
+        Log,
+        ResultContext,
+    },
+    std::{
+        collections::HashMap,
+        path::{
+            Path,
+            PathBuf,
+        },
+    },
+};
+
+/// The latest `publisher.sqlite3` schema version, from `buildlib/publisher`. Bump
+/// this when adding a version there.
+pub const DB_SCHEMA_VERSION: usize = 3;
+
+/// The latest `publisher_admin.sqlite3` schema version, from
+/// `buildlib/publisher_admin`.
+pub const ADMIN_DB_SCHEMA_VERSION: usize = 0;
+
+/// Publisher data in `publisher.sqlite3` and the allowlist in
+/// `publisher_admin.sqlite3`, in the data directory.
+pub struct SqliteStorage {
+    db_pool: Pool,
+    db_path: PathBuf,
+    admin_db_pool: Pool,
+}
+
+impl SqliteStorage {
+    pub async fn new(log: &Log, persistent_dir: &Path) -> Result<SqliteStorage, loga::Error> {
+        let db_path = persistent_dir.join("publisher.sqlite3");
+        let db_pool =
+            setup_db(&db_path, DB_SCHEMA_VERSION, db::migrate)
+                .await
+                .stack_context(log, "Error initializing database")?;
+        let admin_db_pool =
+            setup_db(&persistent_dir.join("publisher_admin.sqlite3"), ADMIN_DB_SCHEMA_VERSION, admin_db::migrate)
+                .await
+                .stack_context(log, "Error initializing admin database")?;
+        return Ok(SqliteStorage {
+            db_pool: db_pool,
+            db_path: db_path,
+            admin_db_pool: admin_db_pool,
+        });
+    }
+}
+
+/// A page of keys published for an identity, in key order.
+fn values_keys_page(
+    db: &rusqlite::Connection,
+    identity: &Identity,
+    after: Option<String>,
+) -> Result<Vec<String>, loga::Error> {
+    let (after, prefix_start, prefix_end, limit) = ListFilter {
+        after: after,
+        ..Default::default()
+    }.params();
+    return Ok(db::values_keys_list(db, identity, &after, &prefix_start, &prefix_end, limit)?);
+}
+
+/// All published key-values for an identity, in key order.
+fn all_values(db: &rusqlite::Connection, identity: &Identity) -> Result<Vec<(String, RecordValue)>, loga::Error> {
+    let mut out = vec![];
+    let mut keys = values_keys_page(db, identity, None)?;
+    while let Some(last) = keys.last().cloned() {
+        for k in keys {
+            let Some(v) = db::values_get(db, identity, &k)? else {
+                continue;
+            };
+            out.push((k, v));
+        }
+        keys = values_keys_page(db, identity, Some(last))?;
+    }
+    return Ok(out);
+}
+
+fn version(db: &rusqlite::Connection, identity: &Identity) -> Result<String, loga::Error> {
+    return Ok(values_version(all_values(db, identity)?.iter().map(|(k, v)| (k, v))));
+}
+
+/// Record that the identity announced or published, reactivating it if dormant.
+fn touch_identity(db: &rusqlite::Connection, identity: &Identity, now: DateTime<Utc>) -> Result<(), loga::Error> {
+    db::activity_set(db, identity, now)?;
+    if db::dormant_get(db, identity)?.is_some() {
+        db::dormant_delete(db, identity)?;
+        db::audit_insert(db, now, identity, &AdminAuditEvent::Reactivated)?;
+    }
+    return Ok(());
+}
+
+fn clear_identity(db: &rusqlite::Connection, identity: &Identity) -> Result<(), loga::Error> {
+    db::announcements_delete(db, identity)?;
+    db::values_delete_all(db, identity)?;
+    db::values_published_delete_all(db, identity)?;
+    db::activity_delete(db, identity)?;
+    db::dormant_delete(db, identity)?;
+    return Ok(());
+}
+
+#[async_trait]
+impl PublisherStorage for SqliteStorage {
+    async fn size(&self) -> Result<u64, loga::Error> {
+        return Ok(db_disk_usage(&self.db_path));
+    }
+
+    async fn get_certs(&self) -> Result<Option<stored::publisher::Certs>, loga::Error> {
+        return self.db_pool.tx(|db| Ok(db::get_certs(db)?)).await;
+    }
+
+    async fn ensure_certs(
+        &self,
+        certs: stored::publisher::Certs,
+    ) -> Result<stored::publisher::Certs, loga::Error> {
+        return self.db_pool.tx(move |db| {
+            if let Some(existing) = db::get_certs(db)? {
+                return Ok(existing);
+            }
+            db::ensure_certs(db, &certs)?;
+            return Ok(certs);
+        }).await;
+    }
+
+    async fn get_announcement(&self, identity: &Identity) -> Result<Option<Announcement>, loga::Error> {
+        let identity = *identity;
+        return self.db_pool.tx(move |db| Ok(db::announcements_get(db, &identity)?)).await;
+    }
+
+    async fn set_announcement(
+        &self,
+        identity: &Identity,
+        announcement: &Announcement,
+        now: DateTime<Utc>,
+    ) -> Result<(), loga::Error> {
+        let identity = *identity;
+        let announcement = announcement.clone();
+        return self.db_pool.tx(move |db| {
+            db::announcements_set(db, &identity, &announcement)?;
+            touch_identity(db, &identity, now)?;
+            return Ok(());
+        }).await;
+    }
+
+    async fn clear_superseded(
+        &self,
+        identity: &Identity,
+        remote_announced: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, loga::Error> {
+        let identity = *identity;
+        return self.db_pool.tx(move |db| {
+            let Some(local_announcement) = db::announcements_get(db, &identity)? else {
+                return Ok(None);
+            };
+            let local_announced =
+                local_announcement
+                    .parse()
+                    .context_with("Stored announcement is malformed", ea!(identity = identity))?
+                    .announced;
+            if remote_announced <= local_announced {
+                return Ok(None);
+            }
+            clear_identity(db, &identity)?;
+            return Ok(Some(local_announced));
+        }).await;
+    }
+
+    async fn clear_identity(&self, identity: &Identity) -> Result<(), loga::Error> {
+        let identity = *identity;
+        return self.db_pool.tx(move |db| clear_identity(db, &identity)).await;
+    }
+
+    async fn list_announcements(&self, filter: &ListFilter) -> Result<Vec<(Identity, Announcement)>, loga::Error> {
+        let (after, prefix_start, prefix_end, limit) = filter.params();
+        return Ok(
+            self
+                .db_pool
+                .tx(move |db| Ok(db::announcements_list(db, &after, &prefix_start, &prefix_end, limit)?))
+                .await?
+                .into_iter()
+                .map(|p| (p.identity, p.value))
+                .collect(),
+        );
+    }
+
+    async fn dormant_since(&self, identity: &Identity) -> Result<Option<DateTime<Utc>>, loga::Error> {
+        let identity = *identity;
+        return self.db_pool.tx(move |db| Ok(db::dormant_get(db, &identity)?)).await;
+    }
+
+    async fn last_active(&self, identity: &Identity) -> Result<Option<DateTime<Utc>>, loga::Error> {
+        let identity = *identity;
+        return self.db_pool.tx(move |db| Ok(db::activity_get(db, &identity)?)).await;
+    }
+
+    async fn mark_dormant(&self, identity: &Identity, now: DateTime<Utc>) -> Result<(), loga::Error> {
+        let identity = *identity;
+        return self.db_pool.tx(move |db| {
+            db::dormant_set(db, &identity, now)?;
+            db::audit_insert(db, now, &identity, &AdminAuditEvent::Dormant)?;
+            return Ok(());
+        }).await;
+    }
+
+    async fn audit_insert(
+        &self,
+        time: DateTime<Utc>,
+        identity: &Identity,
+        event: AdminAuditEvent,
+    ) -> Result<(), loga::Error> {
+        let identity = *identity;
+        return self.db_pool.tx(move |db| Ok(db::audit_insert(db, time, &identity, &event)?)).await;
+    }
+
+    async fn audit_log(&self, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<Vec<AdminAuditEntry>, loga::Error> {
+        return Ok(
+            self
+                .db_pool
+                .tx(move |db| Ok(db::audit_get_between(db, since, until)?))
+                .await?
+                .into_iter()
+                .map(|r| AdminAuditEntry {
+                    time: r.time,
+                    identity: r.identity,
+                    event: r.event,
+                })
+                .collect(),
+        );
+    }
+
+    async fn modify_values(
+        &self,
+        identity: &Identity,
+        args: PublishArgs,
+        published: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Result<ModifyValuesResult, loga::Error> {
+        let identity = *identity;
+        return self.db_pool.tx(move |db| {
+            if let Some(if_version) = &args.if_version {
+                let current = version(db, &identity)?;
+                if &current != if_version {
+                    return Ok(ModifyValuesResult::VersionMismatch(current));
+                }
+            }
+            if let Some(missing_ttl) = args.missing_ttl {
+                db::ident_set(db, &identity, missing_ttl as i64)?;
+            }
+            if args.clear_all {
+                db::values_delete_all(db, &identity)?;
+                db::values_published_delete_all(db, &identity)?;
+            }
+            for k in args.clear {
+                let k = db_key(&k);
+                db::values_delete(db, &identity, &k)?;
+                db::values_published_delete(db, &identity, &k)?;
+            }
+            for (k, v) in args.set {
+                let k = db_key(&k);
+                db::values_set(db, &identity, &k, &v)?;
+                db::values_published_set(db, &identity, &k, published)?;
+            }
+            touch_identity(db, &identity, now)?;
+            return Ok(ModifyValuesResult::Applied(version(db, &identity)?));
+        }).await;
+    }
+
+    async fn record_set(&self, identity: &Identity) -> Result<(String, Vec<(String, RecordValue)>), loga::Error> {
+        let identity = *identity;
+        return self.db_pool.tx(move |db| {
+            let values = all_values(db, &identity)?;
+            return Ok((values_version(values.iter().map(|(k, v)| (k, v))), values));
+        }).await;
+    }
+
+    async fn get_values(
+        &self,
+        identity: &Identity,
+        keys: Vec<String>,
+    ) -> Result<HashMap<String, StoredValue>, loga::Error> {
+        let identity = *identity;
+        return self.db_pool.tx(move |db| {
+            let mut out = HashMap::new();
+            for k in keys {
+                let Some(v) = db::values_get(db, &identity, &k)? else {
+                    continue;
+                };
+                let published = db::values_published_get(db, &identity, &k)?;
+                out.insert(k, StoredValue {
+                    value: v,
+                    published: published,
+                });
+            }
+            return Ok(out);
+        }).await;
+    }
+
+    async fn missing_ttl(&self, identity: &Identity) -> Result<Option<i64>, loga::Error> {
+        let identity = *identity;
+        return self.db_pool.tx(move |db| Ok(db::ident_get(db, &identity)?)).await;
+    }
+
+    async fn list_value_keys(&self, identity: &Identity, filter: &ListFilter) -> Result<Vec<String>, loga::Error> {
+        let identity = *identity;
+        let (after, prefix_start, prefix_end, limit) = filter.params();
+        return self
+            .db_pool
+            .tx(move |db| Ok(db::values_keys_list(db, &identity, &after, &prefix_start, &prefix_end, limit)?))
+            .await;
+    }
+
+    async fn save_usage(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        usage: Vec<(Identity, u64, u64)>,
+        expire: DateTime<Utc>,
+    ) -> Result<(), loga::Error> {
+        return self.db_pool.tx(move |db| {
+            for (identity, resolves, bytes) in usage {
+                db::usage_insert(db, start, end, &identity, resolves as i64, bytes as i64)?;
+            }
+            db::usage_expire(db, expire)?;
+            return Ok(());
+        }).await;
+    }
+
+    async fn usage_between(&self, since: DateTime<Utc>, until: DateTime<Utc>) -> Result<Vec<UsageRow>, loga::Error> {
+        return Ok(
+            self
+                .db_pool
+                .tx(move |db| Ok(db::usage_get_between(db, since, until)?))
+                .await?
+                .into_iter()
+                .map(|r| UsageRow {
+                    start: r.start,
+                    identity: r.identity,
+                    resolves: r.resolves as u64,
+                    bytes: r.bytes as u64,
+                })
+                .collect(),
+        );
+    }
+
+    async fn allow_identity(&self, identity: &Identity, group: String) -> Result<(), loga::Error> {
+        let identity = *identity;
+        return self.admin_db_pool.tx(move |db| Ok(admin_db::allow_identity(db, &identity, &group)?)).await;
+    }
+
+    async fn disallow_identity(&self, identity: &Identity) -> Result<(), loga::Error> {
+        let identity = *identity;
+        return self.admin_db_pool.tx(move |db| Ok(admin_db::disallow_identity(db, &identity)?)).await;
+    }
+
+    async fn list_allowed_identities(&self, filter: &ListFilter) -> Result<Vec<AdminIdentity>, loga::Error> {
+        let (after, prefix_start, prefix_end, limit) = filter.params();
+        return Ok(
+            self
+                .admin_db_pool
+                .tx(move |db| Ok(admin_db::list_allowed_identities(db, &after, &prefix_start, &prefix_end, limit)?))
+                .await?
+                .into_iter()
+                .map(|r| AdminIdentity {
+                    identity: r.identity,
+                    group: r.group,
+                })
+                .collect(),
+        );
+    }
+
+    async fn is_identity_allowed(&self, identity: &Identity) -> Result<bool, loga::Error> {
+        let identity = *identity;
+        return self.admin_db_pool.tx(move |db| Ok(admin_db::is_identity_allowed(db, &identity)?.is_some())).await;
+    }
+}
@@ -0,0 +1,261 @@
+//! Checks that the network's announcements for hosted identities still list this
+//! publisher. An announcement listing only other publishers means the identity
+//! moved (or its key was used by someone else), and a missing one means
+//! announcing isn't working.
+use {
+    crate::{
+        interface::{
+            config::node::publisher_config::PublisherWatchdogConfig,
+            stored::{
+                announcement::Announcement,
+                identity::Identity,
+            },
+            wire::api::admin::v1::{
+                PublisherWatchdogAlert,
+                PublisherWatchdogProblem,
+                PublisherWatchdogStatus,
+            },
+        },
+        ta_res,
+        utils::blob::Blob,
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    htwrap::htreq,
+    http::Uri,
+    loga::{
+        Log,
+        ResultContext,
+    },
+    std::{
+        collections::HashMap,
+        net::SocketAddr,
+        str::FromStr,
+        sync::Mutex,
+    },
+    tokio::time::timeout,
+};
+
+/// Compare the announcement found in the network with this publisher (matched by
+/// any of the advertised addresses or the cert hash).
+pub fn check_announcement(
+    found: Option<&Announcement>,
+    advertise_addrs: &[SocketAddr],
+    cert_pub_hash: &Blob,
+) -> Option<PublisherWatchdogProblem> {
+    let Some(found) = found else {
+        return Some(PublisherWatchdogProblem::Missing);
+    };
+    let Ok(content) = found.parse() else {
+        return Some(PublisherWatchdogProblem::Malformed);
+    };
+    if content.publishers.iter().any(|p| advertise_addrs.contains(&p.addr.0) || &p.cert_hash == cert_pub_hash) {
+        return None;
+    }
+    return Some(PublisherWatchdogProblem::Unexpected {
+        publishers: content.publishers.iter().map(|p| p.addr.to_string()).collect(),
+        announced: content.announced,
+    });
+}
+
+struct WatchdogState {
+    last_check: Option<DateTime<Utc>>,
+    alerts: HashMap<Identity, PublisherWatchdogAlert>,
+}
+
+fn set_alert(
+    state: &mut WatchdogState,
+    now: DateTime<Utc>,
+    identity: &Identity,
+    problem: PublisherWatchdogProblem,
+) -> Option<PublisherWatchdogAlert> {
+    let since = match state.alerts.get(identity) {
+        Some(prev) if prev.problem == problem => return None,
+        Some(prev) => prev.since,
+        None => now,
+    };
+    let alert = PublisherWatchdogAlert {
+        identity: *identity,
+        since: since,
+        problem: problem,
+    };
+    state.alerts.insert(*identity, alert.clone());
+    return Some(alert);
+}
+
+pub struct Watchdog {
+    pub interval: std::time::Duration,
+    webhook: Option<Uri>,
+    state: Mutex<WatchdogState>,
+}
+
+impl Watchdog {
+    pub fn new(config: &PublisherWatchdogConfig) -> Result<Watchdog, loga::Error> {
+        return Ok(Watchdog {
+            interval: std::time::Duration::from_secs(config.interval_minutes.unwrap_or(60).max(1) * 60),
+            webhook: match &config.webhook {
+                Some(u) => Some(Uri::from_str(u.get()).context("Invalid watchdog webhook url")?),
+                None => None,
+            },
+            state: Mutex::new(WatchdogState {
+                last_check: None,
+                alerts: HashMap::new(),
+            }),
+        });
+    }
+
+    pub fn status(&self) -> PublisherWatchdogStatus {
+        let state = self.state.lock().unwrap();
+        let mut alerts = state.alerts.values().cloned().collect::<Vec<_>>();
+        alerts.sort_by_key(|a| a.since);
+        return PublisherWatchdogStatus {
+            enabled: true,
+            last_check: state.last_check,
+            alerts: alerts,
+        };
+    }
+
+    /// Remove all alerts.
+    pub fn clear(&self) {
+        self.state.lock().unwrap().alerts.clear();
+    }
+
+    /// Record the results of checking the `checked` identities. Returns alerts that
+    /// are new or whose problem changed since the last check.
+    pub fn update(
+        &self,
+        now: DateTime<Utc>,
+        checked: &[Identity],
+        mut problems: HashMap<Identity, PublisherWatchdogProblem>,
+    ) -> Vec<PublisherWatchdogAlert> {
+        let mut state = self.state.lock().unwrap();
+        state.last_check = Some(now);
+        let mut out = vec![];
+        for identity in checked {
+            let Some(problem) = problems.remove(identity) else {
+                state.alerts.remove(identity);
+                continue;
+            };
+            out.extend(set_alert(&mut state, now, identity, problem));
+        }
+        return out;
+    }
+
+    /// Record a problem found outside of a check (ex: the local announcement was
+    /// superseded while announcing). Returns the alert if it's new or changed.
+    pub fn alert(
+        &self,
+        now: DateTime<Utc>,
+        identity: &Identity,
+        problem: PublisherWatchdogProblem,
+    ) -> Option<PublisherWatchdogAlert> {
+        return set_alert(&mut self.state.lock().unwrap(), now, identity, problem);
+    }
+
+    /// Send an alert to the webhook, if configured.
+    pub async fn notify(&self, log: &Log, alert: &PublisherWatchdogAlert) -> Result<(), loga::Error> {
+        let Some(url) = &self.webhook else {
+            return Ok(());
+        };
+        timeout(std::time::Duration::from_secs(10), async {
+            ta_res!(());
+            htreq::post(
+                log,
+                &mut htreq::connect(url).await.context("Error connecting to watchdog webhook")?,
+                url,
+                &HashMap::new(),
+                serde_json::to_vec(alert).unwrap(),
+                64 * 1024,
+            ).await?;
+            return Ok(());
+        }).await.map_err(|_| loga::err("Timed out sending watchdog webhook"))??;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test_watchdog {
+    use {
+        super::{
+            check_announcement,
+            Watchdog,
+        },
+        crate::{
+            interface::{
+                config::{
+                    identity::LocalIdentitySecret,
+                    node::publisher_config::PublisherWatchdogConfig,
+                },
+                stored::announcement::{
+                    v1::BincodeSignature,
+                    Announcement,
+                },
+                wire::api::admin::v1::PublisherWatchdogProblem,
+            },
+            utils::{
+                blob::ToBlob,
+                identity_secret::IdentitySigner,
+            },
+        },
+        chrono::Utc,
+        std::collections::HashMap,
+    };
+
+    #[test]
+    fn test_missing() {
+        assert_eq!(
+            check_announcement(None, &["192.0.2.1:48391".parse().unwrap()], &vec![1u8].into()),
+            Some(PublisherWatchdogProblem::Missing)
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        let (_, mut secret) = LocalIdentitySecret::new();
+        let message = vec![0xffu8; 7].blob();
+        let (_, signature) = IdentitySigner::sign(&mut secret, &message).unwrap();
+        let found = Announcement::V1(BincodeSignature {
+            message: message,
+            signature: signature,
+            _p: Default::default(),
+        });
+        assert_eq!(
+            check_announcement(Some(&found), &["192.0.2.1:48391".parse().unwrap()], &vec![1u8].into()),
+            Some(PublisherWatchdogProblem::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_update() {
+        let watchdog = Watchdog::new(&PublisherWatchdogConfig {
+            interval_minutes: None,
+            webhook: None,
+        }).unwrap();
+        let (a, _) = LocalIdentitySecret::new();
+        let (b, _) = LocalIdentitySecret::new();
+        let checked = vec![a, b];
+        let t1 = Utc::now();
+        let alerts =
+            watchdog.update(t1, &checked, [(a, PublisherWatchdogProblem::Missing)].into_iter().collect());
+        assert_eq!(alerts.len(), 1);
+
+        // Unchanged problems aren't re-sent
+        let problems = [(a, PublisherWatchdogProblem::Missing)].into_iter().collect();
+        assert!(watchdog.update(Utc::now(), &checked, problems).is_empty());
+
+        // Changed problems are, keeping the first seen time
+        let problem = PublisherWatchdogProblem::Unexpected {
+            publishers: vec!["192.0.2.2:48391".to_string()],
+            announced: t1,
+        };
+        let alerts = watchdog.update(Utc::now(), &checked, [(a, problem)].into_iter().collect());
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].since, t1);
+
+        // Resolved
+        watchdog.update(Utc::now(), &checked, HashMap::new());
+        assert!(watchdog.status().alerts.is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+//! Per-identity accounting for the record value cache, to limit how many values
+//! one identity can hold and report which identities use the most space. Kept in
+//! sync with the cache by the cache's eviction listener, so counts can briefly lag
+//! behind evictions.
+use {
+    super::CacheIdentityUsage,
+    crate::interface::stored::{
+        identity::Identity,
+        record::record_utils::RecordKey,
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    std::{
+        cmp::Reverse,
+        collections::HashMap,
+        sync::{
+            atomic::{
+                AtomicU64,
+                Ordering,
+            },
+            Mutex,
+        },
+    },
+};
+
+/// Expiration and weight of each cached value, by key.
+type IdentityValues = HashMap<RecordKey, (DateTime<Utc>, u32)>;
+
+pub struct CacheUsage {
+    max_per_identity: Option<usize>,
+    identities: Mutex<HashMap<Identity, IdentityValues>>,
+    cap_evictions: AtomicU64,
+}
+
+impl CacheUsage {
+    pub fn new(max_per_identity: Option<usize>) -> CacheUsage {
+        return CacheUsage {
+            max_per_identity: max_per_identity.map(|m| m.max(1)),
+            identities: Mutex::new(HashMap::new()),
+            cap_evictions: AtomicU64::new(0),
+        };
+    }
+
+    /// Record a value being added to the cache. Returns other keys of the identity
+    /// that should be evicted to stay within the per-identity limit, the ones
+    /// expiring soonest first.
+    pub fn added(&self, identity: &Identity, key: &RecordKey, expires: DateTime<Utc>, weight: u32) -> Vec<RecordKey> {
+        let mut identities = self.identities.lock().unwrap();
+        let values = identities.entry(*identity).or_default();
+        values.insert(key.clone(), (expires, weight));
+        let Some(max) = self.max_per_identity else {
+            return vec![];
+        };
+        if values.len() <= max {
+            return vec![];
+        }
+        let mut candidates =
+            values.iter().filter(|(k, _)| *k != key).map(|(k, v)| (v.0, k.clone())).collect::<Vec<_>>();
+        candidates.sort();
+        let evict = candidates.into_iter().take(values.len() - max).map(|(_, k)| k).collect::<Vec<_>>();
+        for k in &evict {
+            values.remove(k);
+        }
+        self.cap_evictions.fetch_add(evict.len() as u64, Ordering::Relaxed);
+        return evict;
+    }
+
+    /// Record a value leaving the cache. `expires` is the removed value's, so
+    /// notifications arriving after the key was stored again are ignored.
+    pub fn removed(&self, identity: &Identity, key: &RecordKey, expires: DateTime<Utc>) {
+        let mut identities = self.identities.lock().unwrap();
+        let Some(values) = identities.get_mut(identity) else {
+            return;
+        };
+        if values.get(key).is_some_and(|v| v.0 == expires) {
+            values.remove(key);
+        }
+        if values.is_empty() {
+            identities.remove(identity);
+        }
+    }
+
+    /// Forget everything, when the whole cache is cleared.
+    pub fn clear(&self) {
+        self.identities.lock().unwrap().clear();
+    }
+
+    pub fn identity_count(&self) -> u64 {
+        return self.identities.lock().unwrap().len() as u64;
+    }
+
+    /// Values evicted to stay within the per-identity limit.
+    pub fn cap_evictions(&self) -> u64 {
+        return self.cap_evictions.load(Ordering::Relaxed);
+    }
+
+    /// The `count` identities using the most space, largest first.
+    pub fn top(&self, count: usize) -> Vec<CacheIdentityUsage> {
+        let mut out = self.identities.lock().unwrap().iter().map(|(identity, values)| CacheIdentityUsage {
+            identity: *identity,
+            entries: values.len() as u64,
+            bytes: values.values().map(|v| v.1 as u64).sum(),
+        }).collect::<Vec<_>>();
+        out.sort_by_key(|e| Reverse(e.bytes));
+        out.truncate(count);
+        return out;
+    }
+}
+
+#[cfg(test)]
+mod test_cache_usage {
+    use {
+        super::CacheUsage,
+        crate::interface::config::identity::LocalIdentitySecret,
+        chrono::{
+            Duration,
+            Utc,
+        },
+    };
+
+    #[test]
+    fn test_cap() {
+        let usage = CacheUsage::new(Some(2));
+        let (identity, _) = LocalIdentitySecret::new();
+        let (other, _) = LocalIdentitySecret::new();
+        let now = Utc::now();
+        let key = |k: &str| vec![k.to_string()];
+        assert!(usage.added(&identity, &key("a"), now + Duration::try_minutes(2).unwrap(), 10).is_empty());
+        assert!(usage.added(&identity, &key("b"), now + Duration::try_minutes(1).unwrap(), 10).is_empty());
+        assert!(usage.added(&other, &key("a"), now, 100).is_empty());
+
+        // The value expiring soonest is evicted
+        assert_eq!(usage.added(&identity, &key("c"), now + Duration::try_minutes(3).unwrap(), 10), vec![key("b")]);
+        assert_eq!(usage.cap_evictions(), 1);
+
+        // Replacing a value doesn't evict
+        assert!(usage.added(&identity, &key("c"), now + Duration::try_minutes(4).unwrap(), 10).is_empty());
+
+        // Stale removal notifications are ignored
+        usage.removed(&identity, &key("c"), now + Duration::try_minutes(3).unwrap());
+        let top = usage.top(10);
+        assert_eq!(top[0].identity, other);
+        assert_eq!(top[1].entries, 2);
+        assert_eq!(top[1].bytes, 20);
+    }
+}
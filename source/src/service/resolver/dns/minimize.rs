@@ -0,0 +1,204 @@
+//! QNAME minimization (RFC 7816) for queries forwarded to authoritative upstreams
+//! (`DnsBridgeConfig::upstream_minimize_names`), remembering ancestors that were
+//! already looked up so steady state queries don't need extra lookups.
+use {
+    hickory_proto::rr::LowerName,
+    hickory_resolver::Name,
+    std::{
+        collections::HashMap,
+        future::Future,
+        sync::Mutex,
+        time::{
+            Duration,
+            Instant,
+        },
+    },
+};
+
+/// Most ancestors looked up for one query, from the top (RFC 9156's
+/// MAX_MINIMISE_COUNT). Deeper ancestors are looked up by later queries once the
+/// ones above them are remembered.
+pub const MAX_QUERIES: usize = 10;
+
+/// Ancestors remembered at once. Past this, expired entries are dropped and new
+/// ones aren't remembered until there's space.
+const MAX_KNOWN: usize = 65536;
+
+/// Longest time to remember an ancestor, regardless of its TTL.
+const MAX_KNOWN_TTL: u32 = 86400;
+
+/// The result of looking up an ancestor.
+pub enum Probe<E> {
+    /// The ancestor exists (or the upstream can't say anything about it, ex: it's
+    /// above the upstream's zones); skip it for `ttl` seconds.
+    Pass {
+        ttl: u32,
+    },
+    /// The ancestor doesn't exist, so neither does anything under it.
+    Missing(E),
+    /// No usable answer; stop minimizing and send the full query.
+    Failed,
+}
+
+pub enum Minimizer {
+    Off,
+    On {
+        // Ancestors known to exist, with when that expires
+        known: Mutex<HashMap<LowerName, Instant>>,
+    },
+}
+
+impl Minimizer {
+    pub fn new(enabled: bool) -> Minimizer {
+        if !enabled {
+            return Minimizer::Off;
+        }
+        return Minimizer::On { known: Mutex::new(HashMap::new()) };
+    }
+
+    pub fn enabled(&self) -> bool {
+        return matches!(self, Minimizer::On { .. });
+    }
+
+    /// The ancestors of `name` to look up, from the top, starting below the deepest
+    /// one known to exist.
+    fn ancestors(known: &HashMap<LowerName, Instant>, name: &Name, now: Instant) -> Vec<Name> {
+        let labels = name.num_labels() as usize;
+        let mut start = 1;
+        for i in (1 .. labels).rev() {
+            if known.get(&LowerName::from(name.trim_to(i))).is_some_and(|expires| *expires > now) {
+                start = i + 1;
+                break;
+            }
+        }
+        return (start .. labels).take(MAX_QUERIES).map(|i| name.trim_to(i)).collect();
+    }
+
+    fn remember(known: &mut HashMap<LowerName, Instant>, name: &Name, ttl: u32, now: Instant) {
+        if ttl == 0 {
+            return;
+        }
+        if known.len() >= MAX_KNOWN {
+            known.retain(|_, expires| *expires > now);
+            if known.len() >= MAX_KNOWN {
+                return;
+            }
+        }
+        known.insert(LowerName::from(name), now + Duration::from_secs(ttl.min(MAX_KNOWN_TTL) as u64));
+    }
+
+    /// Look up the ancestors of `name` with `probe`, from the top, stopping at the
+    /// first that doesn't exist. Returns the `Missing` result for that ancestor, or
+    /// None if the full query should be sent. Does nothing if off.
+    pub async fn run<
+        E,
+        F: FnMut(Name) -> R,
+        R: Future<Output = Probe<E>>,
+    >(&self, name: &Name, now: Instant, mut probe: F) -> Option<E> {
+        let Minimizer::On { known } = self else {
+            return None;
+        };
+        let ancestors = Minimizer::ancestors(&known.lock().unwrap(), name, now);
+        for ancestor in ancestors {
+            match probe(ancestor.clone()).await {
+                Probe::Pass { ttl } => Minimizer::remember(&mut known.lock().unwrap(), &ancestor, ttl, now),
+                Probe::Missing(e) => return Some(e),
+                Probe::Failed => return None,
+            }
+        }
+        return None;
+    }
+}
+
+#[cfg(test)]
+mod test_minimize {
+    use {
+        super::{
+            Minimizer,
+            Probe,
+            MAX_QUERIES,
+        },
+        hickory_resolver::Name,
+        std::{
+            future::ready,
+            str::FromStr,
+            time::{
+                Duration,
+                Instant,
+            },
+        },
+    };
+
+    /// Minimize `name`, answering `Missing` for `missing` and `Pass` otherwise.
+    /// Returns whether it was found missing and the ancestors looked up.
+    async fn run(minimizer: &Minimizer, name: &str, missing: &str, now: Instant) -> (bool, Vec<String>) {
+        let mut probed = vec![];
+        let found = minimizer.run(&Name::from_str(name).unwrap(), now, |ancestor| {
+            let ancestor = ancestor.to_ascii();
+            probed.push(ancestor.clone());
+            if ancestor == missing {
+                return ready(Probe::Missing(()));
+            }
+            return ready(Probe::Pass { ttl: 60 });
+        }).await;
+        return (found.is_some(), probed);
+    }
+
+    #[tokio::test]
+    async fn test_labels() {
+        let minimizer = Minimizer::new(true);
+        assert_eq!(
+            run(&minimizer, "www.dev.example.org.", "", Instant::now()).await,
+            (false, vec!["org.".to_string(), "example.org.".to_string(), "dev.example.org.".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_queries() {
+        let minimizer = Minimizer::new(true);
+        let name = format!("{}example.org.", "a.".repeat(15));
+        let (found, probed) = run(&minimizer, &name, "", Instant::now()).await;
+        assert!(!found);
+        assert_eq!(probed.len(), MAX_QUERIES);
+        assert_eq!(probed[0], "org.");
+
+        // Later queries continue below the remembered ancestors
+        let (_, probed) = run(&minimizer, &name, "", Instant::now()).await;
+        assert_eq!(probed.len(), 16 - MAX_QUERIES);
+    }
+
+    #[tokio::test]
+    async fn test_missing() {
+        let minimizer = Minimizer::new(true);
+        assert_eq!(
+            run(&minimizer, "www.dev.example.org.", "example.org.", Instant::now()).await,
+            (true, vec!["org.".to_string(), "example.org.".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remembered() {
+        let minimizer = Minimizer::new(true);
+        let now = Instant::now();
+        run(&minimizer, "www.example.org.", "", now).await;
+        assert_eq!(run(&minimizer, "www.example.org.", "", now).await, (false, vec![]));
+        assert_eq!(run(&minimizer, "mail.EXAMPLE.org.", "", now).await, (false, vec![]));
+        assert_eq!(
+            run(&minimizer, "www.example.com.", "", now).await,
+            (false, vec!["com.".to_string(), "example.com.".to_string()])
+        );
+
+        // Forgotten after the TTL
+        assert_eq!(
+            run(&minimizer, "www.example.org.", "", now + Duration::from_secs(61)).await,
+            (false, vec!["org.".to_string(), "example.org.".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_off() {
+        let minimizer = Minimizer::new(false);
+        assert!(!minimizer.enabled());
+        assert_eq!(run(&minimizer, "www.example.org.", "example.org.", Instant::now()).await, (false, vec![]));
+    }
+}
@@ -1,18 +1,32 @@
 use {
-    super::Resolver,
+    super::{
+        health_component,
+        static_names::StaticNameMatch,
+        Resolver,
+        UpstreamProbe,
+    },
     crate::{
+        cap_fn,
         interface::{
             config::{
-                node::resolver_config::DnsBridgeConfig,
+                node::resolver_config::{
+                    DnsBridgeConfig,
+                    DnsClientSubnet,
+                    DnsLocalView,
+                    DnsLocalViewName,
+                    DnsUpstreamStrategy,
+                },
             },
             stored::{
                 self,
                 identity::Identity,
                 record::{
                     delegate_record::build_delegate_key,
+                    handoff_record::build_handoff_key,
                     dns_record::{
                         build_dns_key,
                         RecordType,
+                        MAX_DNS_VALUES,
                     },
                     record_utils::{
                         join_dns_name,
@@ -21,10 +35,34 @@ use {
                     },
                 },
             },
+            wire::{
+                api::resolve::v1::{
+                    DnsJsonAnswer,
+                    DnsJsonQuestion,
+                    DnsJsonResponse,
+                    HealthComponent,
+                    HealthStatus,
+                },
+                resolve::DNS_SUFFIX,
+            },
+        },
+        service::resolver::dns::{
+            minimize::{
+                Minimizer,
+                Probe,
+            },
+            rate_limit::{
+                RateLimitAction,
+                RateLimiter,
+            },
+            ttl::TtlPolicy,
         },
         ta_res,
         ta_vis_res,
         utils::{
+            recent_errors::log_warn_err,
+            reference_chain::ReferenceChain,
+            task_status::TrackedTasks,
             ResultVisErr,
             VisErr,
         },
@@ -35,23 +73,37 @@ use {
         Utc,
     },
     flowcontrol::shed,
-    futures::StreamExt,
+    futures::{
+        stream::FuturesUnordered,
+        StreamExt,
+    },
     hickory_proto::{
         op::{
+            Edns,
             Header,
             Message,
             MessageParts,
+            OpCode,
             Query,
             ResponseCode,
         },
         rr::{
             rdata::{
+                opt::{
+                    ClientSubnet,
+                    EdnsCode,
+                    EdnsOption,
+                },
                 A,
                 AAAA,
                 CNAME,
                 MX,
+                NS,
+                NULL,
+                SOA,
                 TXT,
             },
+            DNSClass,
             LowerName,
             RData,
             Record,
@@ -60,6 +112,7 @@ use {
             DnsHandle,
             DnsRequest,
             DnsRequestOptions,
+            DnsResponse,
         },
     },
     hickory_resolver::{
@@ -68,6 +121,10 @@ use {
             NameServerConfigGroup,
             ResolverOpts,
         },
+        error::{
+            ResolveError,
+            ResolveErrorKind,
+        },
         name_server::{
             GenericConnector,
             NameServerPool,
@@ -78,7 +135,11 @@ use {
     },
     hickory_server::{
         authority::MessageResponseBuilder,
-        server::ResponseInfo,
+        server::{
+            Protocol,
+            ResponseHandler,
+            ResponseInfo,
+        },
     },
     loga::{
         ea,
@@ -87,9 +148,16 @@ use {
         Log,
         ResultContext,
     },
+    ipnet::IpNet,
     rand::{
         seq::SliceRandom,
         thread_rng,
+        Rng,
+    },
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
     },
     std::{
         collections::HashMap,
@@ -100,7 +168,17 @@ use {
             SocketAddr,
         },
         str::FromStr,
-        sync::Arc,
+        sync::{
+            atomic::{
+                AtomicU64,
+                AtomicUsize,
+                Ordering,
+            },
+            Arc,
+            Mutex,
+            Weak,
+        },
+        time::Instant,
     },
     taskmanager::TaskManager,
     tokio::{
@@ -112,21 +190,941 @@ use {
     },
 };
 
+pub mod minimize;
+pub mod rate_limit;
+pub mod ttl;
+
+// TTL for synthesized zone records, also used as the negative caching TTL since
+// records may be published at any time
+const ZONE_TTL: u32 = 60;
+
+// Record types fetched together for queries of any of these types (and types without
+// spaghettinuum records), so concurrent queries for a name share a resolver lookup
+const COMMON_KEYS_DNS: [RecordType; 3] = [RecordType::A, RecordType::Aaaa, RecordType::Txt];
+
+// Consecutive failures before an upstream is skipped until it passes a health
+// check
+const UPSTREAM_FAILURE_THRESHOLD: usize = 3;
+
+#[derive(Default)]
+struct DnsCounters {
+    udp_queries: AtomicU64,
+    rate_limit_dropped: AtomicU64,
+    rate_limit_slipped: AtomicU64,
+}
+
+/// Query counts for the DNS bridge since startup.
+#[derive(Serialize, Deserialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct DnsStats {
+    pub udp_queries: u64,
+    /// UDP queries not answered because the client's subnet was over the response
+    /// rate limit
+    pub rate_limit_dropped: u64,
+    /// UDP queries over the response rate limit answered with an empty truncated
+    /// response
+    pub rate_limit_slipped: u64,
+}
+
+/// Shared counters for the DNS bridge. Clones refer to the same counters.
+#[derive(Clone, Default)]
+pub struct DnsMetrics(Arc<DnsCounters>);
+
+impl DnsMetrics {
+    pub fn stats(&self) -> DnsStats {
+        let c = &self.0;
+        return DnsStats {
+            udp_queries: c.udp_queries.load(Ordering::Relaxed),
+            rate_limit_dropped: c.rate_limit_dropped.load(Ordering::Relaxed),
+            rate_limit_slipped: c.rate_limit_slipped.load(Ordering::Relaxed),
+        };
+    }
+}
+
+struct Upstream {
+    log: Log,
+    pool: NameServerPool<TokioConnectionProvider>,
+    failures: AtomicUsize,
+    // Reject responses that don't echo the query name exactly, for 0x20 encoding
+    check_case: bool,
+    // Whether the upstream offers recursion, from the last response, None until it
+    // answers
+    recursion: Mutex<Option<bool>>,
+}
+
+impl Upstream {
+    fn healthy(&self) -> bool {
+        return self.failures.load(Ordering::Relaxed) < UPSTREAM_FAILURE_THRESHOLD;
+    }
+
+    /// Whether the upstream is a recursive resolver rather than an authoritative
+    /// server. If unknown, asks for the root NS records, which authoritative servers
+    /// refuse. Assumes recursive if it doesn't answer.
+    async fn offers_recursion(&self) -> bool {
+        if let Some(recursion) = *self.recursion.lock().unwrap() {
+            return recursion;
+        }
+        let mut req = Message::new();
+        req.set_recursion_desired(true);
+        req.add_query(Query::query(Name::root(), hickory_proto::rr::RecordType::NS));
+        if let Some(Err(e)) = self.send(DnsRequest::new(req, DnsRequestOptions::default())).await {
+            if let ResolveErrorKind::NoRecordsFound { response_code: ResponseCode::Refused, .. } = e.kind() {
+                *self.recursion.lock().unwrap() = Some(false);
+            }
+        }
+        return self.recursion.lock().unwrap().unwrap_or(true);
+    }
+
+    /// Send a request, tracking whether the upstream is working.
+    async fn send(&self, req: DnsRequest) -> Option<Result<DnsResponse, ResolveError>> {
+        let sent_names = req.queries().iter().map(|q| q.name().to_ascii()).collect::<Vec<_>>();
+        let mut resp = self.pool.send(req).next().await;
+        if let Some(Ok(r)) = &resp {
+            *self.recursion.lock().unwrap() = Some(r.recursion_available());
+        }
+        if self.check_case {
+            let resp_names = match &resp {
+                Some(Ok(r)) => Some(r.queries().iter().map(|q| q.name().to_ascii()).collect::<Vec<_>>()),
+                Some(Err(e)) => match e.kind() {
+                    ResolveErrorKind::NoRecordsFound { query, .. } => Some(vec![query.name().to_ascii()]),
+                    _ => None,
+                },
+                None => None,
+            };
+            if resp_names.is_some_and(|n| n != sent_names) {
+                self.log.log(loga::DEBUG, "Upstream response query name case doesn't match, rejecting");
+                resp = Some(Err(ResolveError::from("Upstream response query name case doesn't match request")));
+            }
+        }
+        if upstream_answered(&resp) {
+            if !self.healthy() {
+                self.log.log(loga::INFO, "Upstream DNS server recovered");
+            }
+            self.failures.store(0, Ordering::Relaxed);
+        } else if self.failures.fetch_add(1, Ordering::Relaxed) + 1 == UPSTREAM_FAILURE_THRESHOLD {
+            self.log.log(loga::WARN, "Upstream DNS server failing, skipping until it recovers");
+        }
+        return resp;
+    }
+}
+
+/// Client subnet handling for forwarded queries, parsed from `DnsClientSubnet`.
+enum ClientSubnetMode {
+    Forward,
+    Strip,
+    FromClient {
+        ipv4_prefix: u8,
+        ipv6_prefix: u8,
+    },
+    Fixed(IpNet),
+}
+
+impl ClientSubnetMode {
+    fn from_config(config: Option<DnsClientSubnet>) -> Result<Self, loga::Error> {
+        match config.unwrap_or(DnsClientSubnet::Forward) {
+            DnsClientSubnet::Forward => return Ok(ClientSubnetMode::Forward),
+            DnsClientSubnet::Strip => return Ok(ClientSubnetMode::Strip),
+            DnsClientSubnet::FromClient { ipv4_prefix, ipv6_prefix } => {
+                if ipv4_prefix > 32 || ipv6_prefix > 128 {
+                    return Err(
+                        loga::err_with(
+                            "Client subnet prefix length out of range",
+                            ea!(ipv4_prefix = ipv4_prefix, ipv6_prefix = ipv6_prefix),
+                        ),
+                    );
+                }
+                return Ok(ClientSubnetMode::FromClient {
+                    ipv4_prefix: ipv4_prefix,
+                    ipv6_prefix: ipv6_prefix,
+                });
+            },
+            DnsClientSubnet::Fixed(subnet) => {
+                return Ok(
+                    ClientSubnetMode::Fixed(
+                        IpNet::from_str(
+                            &subnet,
+                        ).context_with("Invalid fixed client subnet", ea!(subnet = subnet))?.trunc(),
+                    ),
+                );
+            },
+        }
+    }
+
+    /// Update the EDNS options of a query to forward, for a query from `client`.
+    fn apply(&self, edns: &mut Option<Edns>, client: IpAddr) {
+        let subnet = match self {
+            ClientSubnetMode::Forward => {
+                return;
+            },
+            ClientSubnetMode::Strip => {
+                if let Some(edns) = edns {
+                    edns.options_mut().remove(EdnsCode::Subnet);
+                }
+                return;
+            },
+            ClientSubnetMode::FromClient { ipv4_prefix, ipv6_prefix } => {
+                let client = match client {
+                    IpAddr::V6(c) => c.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(client),
+                    c => c,
+                };
+                let prefix = match client {
+                    IpAddr::V4(_) => *ipv4_prefix,
+                    IpAddr::V6(_) => *ipv6_prefix,
+                };
+                IpNet::new(client, prefix).unwrap().trunc()
+            },
+            ClientSubnetMode::Fixed(subnet) => *subnet,
+        };
+        let edns = edns.get_or_insert_with(Edns::new);
+        edns.options_mut().remove(EdnsCode::Subnet);
+        edns
+            .options_mut()
+            .insert(EdnsOption::Subnet(ClientSubnet::new(subnet.network(), subnet.prefix_len(), 0)));
+    }
+}
+
+/// Split DNS answers, parsed from `DnsLocalView`.
+struct LocalView {
+    clients: Vec<IpNet>,
+    names: Vec<LowerName>,
+    identities: Vec<Identity>,
+    ipv4: Vec<Ipv4Addr>,
+    ipv6: Vec<Ipv6Addr>,
+}
+
+impl LocalView {
+    fn from_config(config: DnsLocalView) -> Result<Self, loga::Error> {
+        let mut clients = vec![];
+        for subnet in config.clients {
+            clients.push(
+                IpNet::from_str(
+                    &subnet,
+                ).context_with("Invalid local view client subnet", ea!(subnet = subnet))?.trunc(),
+            );
+        }
+        let mut names = vec![];
+        let mut identities = vec![];
+        for name in config.names {
+            match name {
+                DnsLocalViewName::Name(name) => {
+                    let mut parsed =
+                        Name::from_utf8(
+                            &name,
+                        ).context_with("Local view name isn't a valid DNS name", ea!(name = name))?;
+                    parsed.set_fqdn(true);
+                    names.push(LowerName::from(parsed));
+                },
+                DnsLocalViewName::Identity(ident) => {
+                    identities.push(
+                        Identity::from_str(&ident).context_with("Invalid local view identity", ea!(identity = ident))?,
+                    );
+                },
+            }
+        }
+        return Ok(LocalView {
+            clients: clients,
+            names: names,
+            identities: identities,
+            ipv4: config.ipv4,
+            ipv6: config.ipv6,
+        });
+    }
+
+    fn matches(&self, client: IpAddr, name: &LowerName, ident: &Identity) -> bool {
+        let client = match client {
+            IpAddr::V6(c) => c.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(client),
+            c => c,
+        };
+        if !self.clients.iter().any(|c| c.contains(&client)) {
+            return false;
+        }
+        return self.identities.contains(ident) || self.names.contains(name);
+    }
+}
+
+/// `A` or `AAAA` records for the query, or `None` for other query types. Used for
+/// addresses configured locally (split DNS and static names).
+fn address_records(
+    name: &LowerName,
+    query_type: hickory_proto::rr::RecordType,
+    ipv4: &[Ipv4Addr],
+    ipv6: &[Ipv6Addr],
+) -> Option<Vec<Record>> {
+    match query_type {
+        hickory_proto::rr::RecordType::A => return Some(
+            ipv4.iter().map(|n| Record::from_rdata(name.into(), ZONE_TTL, RData::A(A(*n)))).collect(),
+        ),
+        hickory_proto::rr::RecordType::AAAA => return Some(
+            ipv6.iter().map(|n| Record::from_rdata(name.into(), ZONE_TTL, RData::AAAA(AAAA(*n)))).collect(),
+        ),
+        _ => return None,
+    }
+}
+
+/// Randomly change the case of the letters in a name (0x20 encoding).
+fn randomize_case(name: &Name) -> Name {
+    let mut rng = thread_rng();
+    let text = name.to_ascii().chars().map(|c| if rng.gen() {
+        c.to_ascii_uppercase()
+    } else {
+        c.to_ascii_lowercase()
+    }).collect::<String>();
+    return Name::from_ascii(&text).unwrap_or_else(|_| name.clone());
+}
+
+/// Whether the upstream produced an answer (including a negative answer), as
+/// opposed to failing.
+fn upstream_answered(resp: &Option<Result<DnsResponse, ResolveError>>) -> bool {
+    match resp {
+        Some(Ok(_)) => return true,
+        Some(Err(e)) => return matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }),
+        None => return false,
+    }
+}
+
+enum DoResolveRes {
+    Cname(Record),
+    /// The name is at or under `cut`, which is handed off to conventional DNS servers
+    Handoff {
+        cut: Name,
+        ttl: u32,
+        handoff: stored::record::handoff_record::latest::Handoff,
+    },
+    /// Found values, and the shortest time (seconds) any missing value will stay
+    /// missing, for negative caching
+    Other(HashMap<RecordKey, (u32, serde_json::Value)>, Option<u32>),
+}
+
+async fn do_resolve(
+    resolver: &Resolver,
+    original_name: &LowerName,
+    ident: &Identity,
+    path: RecordKey,
+    explicit_request_keys: Vec<RecordKey>,
+) -> Result<DoResolveRes, VisErr> {
+    let mut path = path;
+
+    // Always automatically request handoffs (-> NS referral) and delegation (-> CNAME)
+    let mut handoff_keys = vec![];
+    let mut delegate_keys = vec![];
+    for i in 1 ..= path.len() {
+        handoff_keys.push(build_handoff_key(path[..i].to_vec()));
+        delegate_keys.push(build_delegate_key(path[..i].to_vec()));
+    }
+    let mut request_keys = handoff_keys.clone();
+    request_keys.extend(delegate_keys.clone());
+    request_keys.extend(explicit_request_keys.clone());
+
+    // Make request, following identity succession
+    let (_, res) = resolver.get_following_succession(ident, request_keys).await.err_internal()?;
+
+    // Filter out empty results, remembering when they expire
+    let mut negative_ttl = None;
+    let mut res = res.into_iter().filter_map(|(k, v)| {
+        let ttl =
+            v.expires.signed_duration_since(Utc::now()).num_seconds().max(0).try_into().unwrap_or(i32::MAX as u32);
+        return match v.data {
+            Some(d) => Some((k, (ttl, d))),
+            None => {
+                negative_ttl = Some(negative_ttl.map_or(ttl, |n: u32| n.min(ttl)));
+                None
+            },
+        };
+    }).collect::<HashMap<_, _>>();
+
+    // Handoffs are zone cuts, so preempt everything under them, including delegation
+    for (i, handoff_key) in handoff_keys.into_iter().enumerate() {
+        let Some((ttl, data)) = res.remove(&handoff_key) else {
+            continue;
+        };
+        match serde_json::from_value::<stored::record::handoff_record::Handoff>(data.clone())
+            .context_with("Failed to parse received handoff record json", ea!(json = data))
+            .err_external()? {
+            stored::record::handoff_record::Handoff::V1(handoff) => {
+                // Drop the labels for the path below the handed off prefix
+                let below = path.len() - (i + 1);
+                let original_name = Name::from(original_name);
+                return Ok(DoResolveRes::Handoff {
+                    cut: original_name.trim_to(original_name.num_labels() as usize - below),
+                    ttl: ttl,
+                    handoff: handoff,
+                });
+            },
+        }
+    }
+
+    // Names starting with an underscore label (ex: `_acme-challenge`, `_dmarc`) hold
+    // verification records, often for a name delegated elsewhere. For these the
+    // longest published match wins: records at the exact name take precedence over
+    // delegation, and the most specific delegation is used.
+    if path.last().is_some_and(|p| p.starts_with('_')) {
+        if explicit_request_keys.iter().any(|k| res.contains_key(k)) {
+            return Ok(DoResolveRes::Other(res, negative_ttl));
+        }
+        delegate_keys.reverse();
+    }
+
+    // Delegation (->CNAME) is automatic preempts all other requests
+    for delegate_key in delegate_keys {
+        let Some((expires, data)) = res.remove(&delegate_key) else {
+            continue;
+        };
+        match serde_json::from_value::<stored::record::delegate_record::Delegate>(data.clone())
+            .context_with("Failed to parse received delegate record json", ea!(json = data))
+            .err_external()? {
+            stored::record::delegate_record::Delegate::V1(n) => {
+                ReferenceChain::new(original_name)
+                    .fanout(n.0.len())
+                    .map_err(|e| resolver.reference_limit_exceeded(e))
+                    .err_external()?;
+                let Some((choose_root, mut choose_path)) = n.0.as_slice().choose(&mut thread_rng()).cloned() else {
+                    continue;
+                };
+                choose_path.extend(path.split_off(delegate_key.len()));
+                return Ok(
+                    DoResolveRes::Cname(
+                        Record::from_rdata(
+                            original_name.into(),
+                            expires,
+                            RData::CNAME(
+                                CNAME(
+                                    Name::from_ascii(&join_dns_name(choose_root, choose_path).err_external()?).unwrap(),
+                                ),
+                            ),
+                        ),
+                    ),
+                );
+            },
+        }
+    }
+
+    // Otherwise return the normal results
+    return Ok(DoResolveRes::Other(res, negative_ttl));
+}
+
+/// Convert an identity's DNS record value to DNS records.
+fn dns_records(
+    log: &Log,
+    name: &LowerName,
+    record_type: RecordType,
+    ttl: u32,
+    data: serde_json::Value,
+) -> Result<Vec<Record>, VisErr> {
+    let mut out = vec![];
+    match record_type {
+        RecordType::A => {
+            match serde_json::from_value::<stored::record::dns_record::DnsA>(data.clone())
+                .context_with("Failed to parse received record json", ea!(json = data))
+                .err_external()? {
+                stored::record::dns_record::DnsA::V1(n) => {
+                    for n in n.0.into_iter().take(MAX_DNS_VALUES) {
+                        out.push(Record::from_rdata(name.into(), ttl, RData::A(A(n))));
+                    }
+                },
+            }
+        },
+        RecordType::Aaaa => {
+            match serde_json::from_value::<stored::record::dns_record::DnsAaaa>(data.clone())
+                .context_with("Failed to parse received record json", ea!(json = data))
+                .err_external()? {
+                stored::record::dns_record::DnsAaaa::V1(n) => {
+                    for n in n.0.into_iter().take(MAX_DNS_VALUES) {
+                        out.push(Record::from_rdata(name.into(), ttl, RData::AAAA(AAAA(n))));
+                    }
+                },
+            }
+        },
+        RecordType::Txt => {
+            match serde_json::from_value::<stored::record::dns_record::DnsTxt>(data.clone())
+                .context_with("Failed to parse received record json", ea!(json = data))
+                .err_external()? {
+                stored::record::dns_record::DnsTxt::V1(n) => {
+                    for n in n.0.into_iter().take(MAX_DNS_VALUES) {
+                        out.push(Record::from_rdata(name.into(), ttl, RData::TXT(TXT::new(vec![n]))));
+                    }
+                },
+            }
+        },
+        RecordType::Mx => {
+            match serde_json::from_value::<stored::record::dns_record::DnsMx>(data.clone())
+                .context_with("Failed to parse received record json", ea!(json = data))
+                .err_external()? {
+                stored::record::dns_record::DnsMx::V1(n) => {
+                    for (i, n) in n.0.into_iter().take(MAX_DNS_VALUES).enumerate() {
+                        let n = match Name::from_utf8(&n) {
+                            Err(e) => {
+                                log.log_err(
+                                    loga::DEBUG,
+                                    e.context_with("Mx name in record invalid for DNS", ea!(name = n)),
+                                );
+                                continue;
+                            },
+                            Ok(n) => n,
+                        };
+                        out.push(Record::from_rdata(name.into(), ttl, RData::MX(MX::new(i as u16, n))));
+                    }
+                },
+            }
+        },
+    }
+    return Ok(out);
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    return (0 .. s.len()).step_by(2).map(|i| u8::from_str_radix(s.get(i .. i + 2)?, 16).ok()).collect();
+}
+
+/// `DS` records for a handoff. Hickory only has a `DS` type with DNSSEC enabled so
+/// these are built from the wire format.
+fn handoff_ds_records(
+    cut: &Name,
+    ttl: u32,
+    handoff: &stored::record::handoff_record::latest::Handoff,
+) -> Result<Vec<Record>, VisErr> {
+    let mut out = vec![];
+    for ds in handoff.ds.iter().take(MAX_DNS_VALUES) {
+        let digest =
+            decode_hex(&ds.digest)
+                .context_with("Handoff DS digest isn't valid hex", ea!(digest = ds.digest))
+                .err_external()?;
+        let mut rdata = vec![];
+        rdata.extend(ds.key_tag.to_be_bytes());
+        rdata.push(ds.algorithm);
+        rdata.push(ds.digest_type);
+        rdata.extend(digest);
+        out.push(Record::from_rdata(cut.clone(), ttl, RData::Unknown {
+            code: hickory_proto::rr::RecordType::DS,
+            rdata: NULL::with(rdata),
+        }));
+    }
+    return Ok(out);
+}
+
+/// Records synthesized for a query.
+pub struct Synthesized {
+    pub answers: Vec<Record>,
+    /// For names handed off to conventional DNS servers, the referral: `NS` records
+    /// and any `DS` records for the cut.
+    pub authority: Vec<Record>,
+    /// Glue addresses for name servers in `authority`.
+    pub additionals: Vec<Record>,
+    /// The shortest time (seconds) any missing value will stay missing, for negative
+    /// caching.
+    pub negative_ttl: Option<u32>,
+}
+
+/// Records answering a query for a name under an identity, synthesized from the
+/// identity's DNS records. Handoffs take precedence, answered with a referral (or
+/// the `DS` records, for `DS` queries at the cut), then delegation, answered as a
+/// CNAME.
+///
+/// Queries for A, AAAA, TXT, and types without spaghettinuum records all request
+/// the same keys (`COMMON_KEYS_DNS`) so clients querying several types for a name at
+/// once (ex: A, AAAA, and HTTPS in parallel) share a single resolver lookup. ANY
+/// queries are answered with all the DNS records for the name.
+pub async fn synthesize_records(
+    log: &Log,
+    resolver: &Resolver,
+    name: &LowerName,
+    ident: &Identity,
+    path: RecordKey,
+    query_type: hickory_proto::rr::RecordType,
+) -> Result<Synthesized, VisErr> {
+    // Types to fetch, and which of those to answer with
+    let (fetch_types, answer_types) = match query_type {
+        hickory_proto::rr::RecordType::CNAME => (vec![], vec![]),
+        hickory_proto::rr::RecordType::A => (COMMON_KEYS_DNS.to_vec(), vec![RecordType::A]),
+        hickory_proto::rr::RecordType::AAAA => (COMMON_KEYS_DNS.to_vec(), vec![RecordType::Aaaa]),
+        hickory_proto::rr::RecordType::TXT => (COMMON_KEYS_DNS.to_vec(), vec![RecordType::Txt]),
+        hickory_proto::rr::RecordType::MX => (vec![RecordType::Mx], vec![RecordType::Mx]),
+        hickory_proto::rr::RecordType::ANY => {
+            let mut types = COMMON_KEYS_DNS.to_vec();
+            types.push(RecordType::Mx);
+            (types.clone(), types)
+        },
+        _ => {
+            // No records of this type, but the name may still be delegated
+            (COMMON_KEYS_DNS.to_vec(), vec![])
+        },
+    };
+    let request_keys = fetch_types.iter().map(|t| build_dns_key(path.clone(), *t)).collect::<Vec<_>>();
+    let mut out = Synthesized {
+        answers: vec![],
+        authority: vec![],
+        additionals: vec![],
+        negative_ttl: None,
+    };
+    match do_resolve(resolver, name, ident, path.clone(), request_keys).await? {
+        DoResolveRes::Cname(r) => {
+            out.answers.push(r);
+        },
+        DoResolveRes::Handoff { cut, ttl, handoff } => {
+            let ds = handoff_ds_records(&cut, ttl, &handoff)?;
+            if query_type == hickory_proto::rr::RecordType::DS && Name::from(name) == cut {
+                // The parent side of the cut answers for DS
+                out.answers = ds;
+                return Ok(out);
+            }
+            for ns in handoff.name_servers.iter().take(MAX_DNS_VALUES) {
+                let ns_name =
+                    Name::from_utf8(ns)
+                        .context_with("Handoff name server isn't a valid DNS name", ea!(name = ns))
+                        .err_external()?;
+                for ip in handoff.glue.get(ns).into_iter().flatten().take(MAX_DNS_VALUES) {
+                    out.additionals.push(Record::from_rdata(ns_name.clone(), ttl, match ip {
+                        IpAddr::V4(ip) => RData::A(A(*ip)),
+                        IpAddr::V6(ip) => RData::AAAA(AAAA(*ip)),
+                    }));
+                }
+                out.authority.push(Record::from_rdata(cut.clone(), ttl, RData::NS(NS(ns_name))));
+            }
+            out.authority.extend(ds);
+        },
+        DoResolveRes::Other(mut res, missing_ttl) => {
+            out.negative_ttl = missing_ttl;
+            for t in answer_types {
+                if let Some((expires, data)) = res.remove(&build_dns_key(path.clone(), t)) {
+                    out.answers.extend(dns_records(log, name, t, expires, data)?);
+                }
+            }
+        },
+    }
+    return Ok(out);
+}
+
+fn dns_json_records(records: &[Record]) -> Vec<DnsJsonAnswer> {
+    let mut out = vec![];
+    for record in records {
+        let data = match record.data() {
+            Some(RData::TXT(t)) => t
+                .txt_data()
+                .iter()
+                .map(|d| format!("\"{}\"", String::from_utf8_lossy(d).replace('\\', "\\\\").replace('"', "\\\"")))
+                .collect::<Vec<_>>()
+                .join(" "),
+            Some(d) => d.to_string(),
+            None => continue,
+        };
+        out.push(DnsJsonAnswer {
+            name: record.name().to_string(),
+            type_: u16::from(record.record_type()),
+            ttl: record.ttl(),
+            data: data,
+        });
+    }
+    return out;
+}
+
+/// Answer a query in the `application/dns-json` format, for `.s` names and static
+/// names only.
+/// `type_` is a record type name or number, defaulting to `A`.
+pub async fn resolve_dns_json(
+    log: &Log,
+    resolver: &Resolver,
+    name: &str,
+    type_: Option<&str>,
+) -> Result<DnsJsonResponse, VisErr> {
+    let mut name = Name::from_utf8(name).context("Invalid name").err_external()?;
+    name.set_fqdn(true);
+    let query_type = match type_ {
+        Some(t) => match t.parse::<u16>() {
+            Ok(t) => hickory_proto::rr::RecordType::from(t),
+            Err(_) => hickory_proto::rr::RecordType::from_str(&t.to_ascii_uppercase())
+                .context_with("Unknown record type", ea!(record_type = t))
+                .err_external()?,
+        },
+        None => hickory_proto::rr::RecordType::A,
+    };
+    let mut out = DnsJsonResponse {
+        status: u16::from(ResponseCode::NoError),
+        tc: false,
+        rd: true,
+        ra: true,
+        ad: false,
+        cd: false,
+        question: vec![DnsJsonQuestion {
+            name: name.to_string(),
+            type_: u16::from(query_type),
+        }],
+        answer: vec![],
+        authority: vec![],
+    };
+    let name = LowerName::from(name);
+    let (root, path) = match resolver.static_name(&name).err_external()? {
+        Some(StaticNameMatch::Identity { ident, path }) => (stored::record::record_utils::RecordRoot::S(ident), path),
+        Some(StaticNameMatch::Addresses { ipv4, ipv6 }) => {
+            out.answer = dns_json_records(&address_records(&name, query_type, &ipv4, &ipv6).unwrap_or_default());
+            return Ok(out);
+        },
+        None => match split_dns_name(&name) {
+            Ok(r) => r,
+            Err(e) => {
+                if LowerName::from(Name::from_ascii(format!("{}.", DNS_SUFFIX)).unwrap()).zone_of(&name) {
+                    // Names in the zone without a valid identity don't exist
+                    log.log_err(loga::DEBUG, e.context("Invalid name in spagh zone"));
+                    out.status = u16::from(ResponseCode::NXDomain);
+                } else {
+                    out.status = u16::from(ResponseCode::Refused);
+                }
+                return Ok(out);
+            },
+        },
+    };
+    let stored::record::record_utils::RecordRoot::S(ident) = root else {
+        out.status = u16::from(ResponseCode::Refused);
+        return Ok(out);
+    };
+    let synthesized = synthesize_records(log, resolver, &name, &ident, path, query_type).await?;
+    out.answer = dns_json_records(&synthesized.answers);
+    out.authority = dns_json_records(&synthesized.authority);
+    return Ok(out);
+}
+
+/// Start the DNS bridge servers. Returns a description of each listening socket.
+/// `metrics` is updated with counts of queries received.
 pub async fn start_dns_bridge(
     log: &Log,
     tm: &TaskManager,
     resolver: &Resolver,
     certs: Arc<dyn rustls_21::server::ResolvesServerCert>,
     global_ips: &[IpAddr],
+    metrics: &DnsMetrics,
     dns_config: DnsBridgeConfig,
-) -> Result<(), loga::Error> {
+) -> Result<Vec<String>, loga::Error> {
     struct HandlerInner {
         log: Log,
         resolver: Resolver,
-        upstream: NameServerPool<TokioConnectionProvider>,
+        metrics: DnsMetrics,
+        rate_limiter: Option<Arc<RateLimiter>>,
+        upstreams: Vec<Upstream>,
+        upstream_strategy: DnsUpstreamStrategy,
+        upstream_client_subnet: ClientSubnetMode,
+        upstream_randomize_case: bool,
+        minimizer: Minimizer,
+        authoritative_only: bool,
+        local_views: Vec<LocalView>,
+        hosted_zones: Vec<(LowerName, Identity)>,
+        flatten_cnames: bool,
+        ttl_policy: TtlPolicy,
         synthetic_self_record: Option<LowerName>,
         global_ipv4: Vec<Ipv4Addr>,
         global_ipv6: Vec<Ipv6Addr>,
+        zone: LowerName,
+        zone_soa: Record,
+        zone_ns: Vec<Record>,
+    }
+
+    impl HandlerInner {
+        /// Send a request upstream, per the upstream strategy. Returns the last failure if
+        /// no upstream answered.
+        async fn send_upstream(&self, req: DnsRequest) -> Option<Result<DnsResponse, ResolveError>> {
+            let mut candidates = self.upstreams.iter().filter(|u| u.healthy()).collect::<Vec<_>>();
+            if candidates.is_empty() {
+                // Everything is failing, try them all anyway
+                candidates = self.upstreams.iter().collect();
+            }
+            let mut last = None;
+            match self.upstream_strategy {
+                DnsUpstreamStrategy::Failover => {
+                    for upstream in candidates {
+                        let resp = upstream.send(req.clone()).await;
+                        if upstream_answered(&resp) {
+                            return resp;
+                        }
+                        last = resp;
+                    }
+                },
+                DnsUpstreamStrategy::Race => {
+                    let mut pending =
+                        candidates
+                            .into_iter()
+                            .map(|upstream| upstream.send(req.clone()))
+                            .collect::<FuturesUnordered<_>>();
+                    while let Some(resp) = pending.next().await {
+                        if upstream_answered(&resp) {
+                            return resp;
+                        }
+                        last = resp;
+                    }
+                },
+            }
+            return last;
+        }
+
+        /// Whether queries can be minimized, only for authoritative upstreams since
+        /// recursive resolvers see the full name anyway.
+        async fn upstreams_authoritative(&self) -> bool {
+            for upstream in &self.upstreams {
+                if upstream.offers_recursion().await {
+                    return false;
+                }
+            }
+            return true;
+        }
+
+        /// Look up an ancestor of a query name for QNAME minimization.
+        async fn probe_ancestor(&self, ancestor: Name, query_class: DNSClass) -> Probe<ResolveError> {
+            let ancestor = if self.upstream_randomize_case {
+                randomize_case(&ancestor)
+            } else {
+                ancestor
+            };
+            let mut req = Message::new();
+            req.add_query({
+                let mut q = Query::query(ancestor, hickory_proto::rr::RecordType::NS);
+                q.set_query_class(query_class);
+                q
+            });
+            match self.send_upstream(DnsRequest::new(req, DnsRequestOptions::default())).await {
+                Some(Ok(resp)) => {
+                    return Probe::Pass { ttl: resp.answers().iter().map(|r| r.ttl()).min().unwrap_or(0) };
+                },
+                Some(Err(e)) => match e.kind() {
+                    ResolveErrorKind::NoRecordsFound { response_code: ResponseCode::NXDomain, .. } => {
+                        return Probe::Missing(e);
+                    },
+                    ResolveErrorKind::NoRecordsFound { response_code: ResponseCode::NoError, negative_ttl, .. } => {
+                        return Probe::Pass { ttl: negative_ttl.unwrap_or(0) };
+                    },
+                    // Outside the upstream's zones
+                    ResolveErrorKind::NoRecordsFound { response_code: ResponseCode::Refused, .. } => {
+                        return Probe::Pass { ttl: ZONE_TTL };
+                    },
+                    _ => return Probe::Failed,
+                },
+                None => return Probe::Failed,
+            }
+        }
+
+        /// Replace a CNAME to a name outside the `s.` zone with the target's records of
+        /// the query type, looked up upstream and renamed to the query name. The TTL is
+        /// the lowest of the CNAME and the target records. Returns None if the target
+        /// has no records or the lookup failed.
+        async fn flatten_cname(
+            &self,
+            name: &LowerName,
+            query_type: hickory_proto::rr::RecordType,
+            cname: &Record,
+        ) -> Option<Vec<Record>> {
+            let Some(RData::CNAME(target)) = cname.data() else {
+                return None;
+            };
+            if self.zone.zone_of(&LowerName::from(&target.0)) {
+                return None;
+            }
+            let mut req = Message::new();
+            req.set_recursion_desired(true);
+            req.add_query(Query::query(target.0.clone(), query_type));
+            let Some(Ok(resp)) = self.send_upstream(DnsRequest::new(req, DnsRequestOptions::default())).await else {
+                return None;
+            };
+            let mut out = vec![];
+            for r in resp.answers() {
+                // Skip intermediate CNAMEs in the chain
+                if r.record_type() != query_type {
+                    continue;
+                }
+                let mut r = r.clone();
+                r.set_name(name.into());
+                r.set_ttl(r.ttl().min(cname.ttl()));
+                out.push(r);
+            }
+            if out.is_empty() {
+                return None;
+            }
+            return Some(out);
+        }
+
+        /// Query the root NS records on failing upstreams to see if they've recovered.
+        async fn check_upstreams(&self) {
+            for upstream in &self.upstreams {
+                if upstream.healthy() {
+                    continue;
+                }
+                let mut req = Message::new();
+                req.set_recursion_desired(true);
+                req.add_query(Query::query(Name::root(), hickory_proto::rr::RecordType::NS));
+                upstream.send(DnsRequest::new(req, DnsRequestOptions::default())).await;
+            }
+        }
+
+        /// Send a response with no records, for requests this server won't handle.
+        async fn send_error<
+            R: ResponseHandler,
+        >(
+            &self,
+            request: &hickory_server::server::Request,
+            response_handle: &mut R,
+            response_code: ResponseCode,
+        ) -> Result<ResponseInfo, VisErr> {
+            return response_handle
+                .send_response(
+                    MessageResponseBuilder::from_message_request(
+                        request,
+                    ).error_msg(request.header(), response_code),
+                )
+                .await
+                .context("Error sending response")
+                .err_internal();
+        }
+
+        /// Send an authoritative response for a name in the `s.` zone or a hosted zone.
+        /// Negative responses for names in the `s.` zone include the zone SOA for
+        /// negative caching, with the TTL lowered to `negative_ttl` (seconds) if
+        /// provided so negative answers don't outlive the resolver's.
+        async fn send_authoritative<
+            R: ResponseHandler,
+        >(
+            &self,
+            request: &hickory_server::server::Request,
+            response_handle: &mut R,
+            response_code: ResponseCode,
+            answers: &[Record],
+            negative_ttl: Option<u32>,
+        ) -> Result<ResponseInfo, VisErr> {
+            let mut header = Header::response_from_request(request.header());
+            header.set_authoritative(true);
+            header.set_response_code(response_code);
+            let soa = if answers.is_empty() && self.zone.zone_of(request.query().name()) {
+                let mut soa = self.zone_soa.clone();
+                if let Some(negative_ttl) = negative_ttl {
+                    soa.set_ttl(negative_ttl.min(ZONE_TTL));
+                }
+                Some(soa)
+            } else {
+                None
+            };
+            return response_handle
+                .send_response(
+                    MessageResponseBuilder::from_message_request(
+                        request,
+                    ).build(header, answers.iter(), &[], soa.as_ref(), &[]),
+                )
+                .await
+                .context("Error sending response")
+                .err_internal();
+        }
+    }
+
+    #[async_trait]
+    impl UpstreamProbe for HandlerInner {
+        async fn probe_upstreams(&self) -> HealthComponent {
+            for upstream in &self.upstreams {
+                let mut req = Message::new();
+                req.set_recursion_desired(true);
+                req.add_query(Query::query(Name::root(), hickory_proto::rr::RecordType::NS));
+                if upstream_answered(&upstream.send(DnsRequest::new(req, DnsRequestOptions::default())).await) {
+                    return health_component(HealthStatus::Ok, None);
+                }
+            }
+            return health_component(
+                HealthStatus::Failed,
+                Some(format!("None of the {} upstream DNS servers answered", self.upstreams.len())),
+            );
+        }
     }
 
     struct Handler(Arc<HandlerInner>);
@@ -143,6 +1141,33 @@ pub async fn start_dns_bridge(
             let self1 = self.0.clone();
             match async {
                 ta_vis_res!(ResponseInfo);
+                if matches!(request.protocol(), Protocol::Udp) {
+                    let counters = &self1.metrics.0;
+                    counters.udp_queries.fetch_add(1, Ordering::Relaxed);
+                    if let Some(rate_limiter) = &self1.rate_limiter {
+                        match rate_limiter.check(request.src().ip(), Instant::now()) {
+                            RateLimitAction::Allow => { },
+                            RateLimitAction::Slip => {
+                                counters.rate_limit_slipped.fetch_add(1, Ordering::Relaxed);
+                                let mut header = Header::response_from_request(request.header());
+                                header.set_truncated(true);
+                                return response_handle
+                                    .send_response(
+                                        MessageResponseBuilder::from_message_request(
+                                            request,
+                                        ).build_no_records(header),
+                                    )
+                                    .await
+                                    .context("Error sending truncated response")
+                                    .err_internal();
+                            },
+                            RateLimitAction::Drop => {
+                                counters.rate_limit_dropped.fetch_add(1, Ordering::Relaxed);
+                                return Ok(ResponseInfo::from(*request.header()));
+                            },
+                        }
+                    }
+                }
                 let name = request.query().name();
 
                 // First check + handle the syntetic name
@@ -190,295 +1215,214 @@ pub async fn start_dns_bridge(
                     );
                 }
 
+                // Names answered locally
+                let static_name = self1.resolver.static_name(name).err_external()?;
+
+                // Only handle queries in the zone if authoritative-only
+                if self1.authoritative_only {
+                    if static_name.is_none() && !self1.zone.zone_of(name) &&
+                        !self1.hosted_zones.iter().any(|(zone, _)| zone.zone_of(name)) {
+                        return self1.send_error(request, &mut response_handle, ResponseCode::Refused).await;
+                    }
+                    if request.header().op_code() != OpCode::Query ||
+                        matches!(
+                            request.query().query_type(),
+                            hickory_proto::rr::RecordType::AXFR | hickory_proto::rr::RecordType::IXFR
+                        ) {
+                        // Updates, notifications, and zone transfers aren't supported
+                        return self1.send_error(request, &mut response_handle, ResponseCode::NotAuth).await;
+                    }
+                }
+
+                if let Some(StaticNameMatch::Addresses { ipv4, ipv6 }) = &static_name {
+                    let answers =
+                        address_records(name, request.query().query_type(), ipv4, ipv6).unwrap_or_default();
+                    return self1
+                        .send_authoritative(request, &mut response_handle, ResponseCode::NoError, &answers, None)
+                        .await;
+                }
+
+                // Zone apex
+                if *name == self1.zone {
+                    let mut answers = vec![];
+                    match request.query().query_type() {
+                        hickory_proto::rr::RecordType::SOA => {
+                            answers.push(self1.zone_soa.clone());
+                        },
+                        hickory_proto::rr::RecordType::NS => {
+                            answers.extend(self1.zone_ns.iter().cloned());
+                        },
+                        _ => { },
+                    }
+                    return self1
+                        .send_authoritative(request, &mut response_handle, ResponseCode::NoError, &answers, None)
+                        .await;
+                }
+
                 // Spagh + upstream DNS
-                let (root, path) = split_dns_name(name).err_external()?;
+                let (root, path) = match static_name {
+                    Some(StaticNameMatch::Identity { ident, path }) => {
+                        (stored::record::record_utils::RecordRoot::S(ident), path)
+                    },
+                    _ => {
+                        let (root, path) = match split_dns_name(name) {
+                            Ok(r) => r,
+                            Err(e) => {
+                                if self1.zone.zone_of(name) {
+                                    // Names in the zone without a valid identity don't exist
+                                    self1.log.log_err(loga::DEBUG, e.context("Invalid name in spagh zone"));
+                                    return self1
+                                        .send_authoritative(
+                                            request,
+                                            &mut response_handle,
+                                            ResponseCode::NXDomain,
+                                            &[],
+                                            None,
+                                        )
+                                        .await;
+                                }
+                                return Err(e).err_external();
+                            },
+                        };
+
+                        // Hosted zones are answered from the hosting identity's records
+                        match self1.hosted_zones.iter().find(|(zone, _)| zone.zone_of(name)) {
+                            Some((zone, ident)) => {
+                                let mut path = path;
+                                let path = path.split_off(zone.num_labels() as usize - 1);
+                                (stored::record::record_utils::RecordRoot::S(*ident), path)
+                            },
+                            None => (root, path),
+                        }
+                    },
+                };
                 match root {
                     stored::record::record_utils::RecordRoot::S(ident) => {
                         self.0.log.log_with(loga::DEBUG, "Received spagh request", ea!(request = request.dbg_str()));
 
-                        enum DoResolveRes {
-                            Cname(Record),
-                            Other(HashMap<RecordKey, (u32, serde_json::Value)>),
-                        }
-
-                        async fn do_resolve(
-                            self1: &Arc<HandlerInner>,
-                            original_name: &LowerName,
-                            ident: &Identity,
-                            path: RecordKey,
-                            explicit_request_keys: Vec<RecordKey>,
-                        ) -> Result<DoResolveRes, VisErr> {
-                            let mut path = path;
-
-                            // Always automatically request delegation (-> CNAME)
-                            let mut delegate_keys = vec![];
-                            for i in 1 ..= path.len() {
-                                delegate_keys.push(build_delegate_key(path[..i].to_vec()));
-                            }
-                            let mut request_keys = delegate_keys.clone();
-                            request_keys.extend(explicit_request_keys);
-
-                            // Make request, filter out empty results
-                            let mut res = self1.resolver.get(&ident, request_keys).await.err_internal()?.into_iter().filter_map(|(k, v)| {
-                                return match v.data {
-                                    Some(d) => Some(
-                                        (
-                                            k,
-                                            (
-                                                v
-                                                    .expires
-                                                    .signed_duration_since(Utc::now())
-                                                    .num_seconds()
-                                                    .try_into()
-                                                    .unwrap_or(i32::MAX as u32),
-                                                d,
-                                            ),
-                                        ),
-                                    ),
-                                    None => None,
-                                };
-                            }).collect::<HashMap<_, _>>();
-
-                            // Delegation (->CNAME) is automatic preempts all other requests
-                            for delegate_key in delegate_keys {
-                                let Some((expires, data)) = res.remove(&delegate_key) else {
-                                    continue;
-                                };
-                                match serde_json::from_value::<stored::record::delegate_record::Delegate>(
-                                    data.clone(),
-                                )
-                                    .context_with("Failed to parse received delegate record json", ea!(json = data))
-                                    .err_external()? {
-                                    stored::record::delegate_record::Delegate::V1(n) => {
-                                        let Some((choose_root, mut choose_path)) =
-                                            n.0.as_slice().choose(&mut thread_rng()).cloned() else {
-                                                continue;
-                                            };
-                                        choose_path.extend(path.split_off(delegate_key.len()));
-                                        return Ok(
-                                            DoResolveRes::Cname(
-                                                Record::from_rdata(
-                                                    original_name.into(),
-                                                    expires,
-                                                    RData::CNAME(
-                                                        CNAME(
-                                                            Name::from_ascii(
-                                                                &join_dns_name(
-                                                                    choose_root,
-                                                                    choose_path,
-                                                                ).err_external()?,
-                                                            ).unwrap(),
-                                                        ),
-                                                    ),
-                                                ),
-                                            ),
-                                        );
-                                    },
-                                }
+                        // Split DNS, for local clients
+                        if let Some(view) =
+                            self1
+                                .local_views
+                                .iter()
+                                .find(|v| v.matches(request.src().ip(), request.query().name(), &ident)) {
+                            if let Some(answers) =
+                                address_records(
+                                    request.query().name(),
+                                    request.query().query_type(),
+                                    &view.ipv4,
+                                    &view.ipv6,
+                                ) {
+                                return self1
+                                    .send_authoritative(
+                                        request,
+                                        &mut response_handle,
+                                        ResponseCode::NoError,
+                                        &answers,
+                                        None,
+                                    )
+                                    .await;
                             }
-
-                            // Otherwise return the normal results
-                            return Ok(DoResolveRes::Other(res));
                         }
 
-                        let mut answers = vec![];
-                        match request.query().query_type() {
-                            hickory_proto::rr::RecordType::CNAME => {
-                                match do_resolve(&self1, request.query().name(), &ident, path, vec![]).await? {
-                                    DoResolveRes::Cname(r) => {
-                                        answers.push(r);
-                                    },
-                                    // No values
-                                    DoResolveRes::Other(_) => (),
-                                }
-                            },
-                            hickory_proto::rr::RecordType::A => {
-                                let primary_request_key = build_dns_key(path.clone(), RecordType::A);
-                                let mut request_keys = vec![primary_request_key.clone()];
-                                for t in [RecordType::Aaaa, RecordType::Txt] {
-                                    request_keys.push(build_dns_key(path.clone(), t));
-                                }
-                                match do_resolve(&self1, request.query().name(), &ident, path, request_keys).await? {
-                                    DoResolveRes::Cname(r) => {
-                                        answers.push(r);
-                                    },
-                                    DoResolveRes::Other(mut res) => {
-                                        if let Some((expires, data)) = res.remove(&primary_request_key) {
-                                            match serde_json::from_value::<stored::record::dns_record::DnsA>(
-                                                data.clone(),
-                                            )
-                                                .context_with("Failed to parse received record json", ea!(json = data))
-                                                .err_external()? {
-                                                stored::record::dns_record::DnsA::V1(n) => {
-                                                    for n in n.0 {
-                                                        answers.push(
-                                                            Record::from_rdata(
-                                                                request.query().name().into(),
-                                                                expires,
-                                                                RData::A(A(n)),
-                                                            ),
-                                                        );
-                                                    }
-                                                },
-                                            }
-                                        }
-                                    },
-                                }
-                            },
-                            hickory_proto::rr::RecordType::AAAA => {
-                                let primary_request_key = build_dns_key(path.clone(), RecordType::Aaaa);
-                                let mut request_keys = vec![primary_request_key.clone()];
-                                for t in [RecordType::A, RecordType::Txt] {
-                                    request_keys.push(build_dns_key(path.clone(), t));
-                                }
-                                match do_resolve(&self1, request.query().name(), &ident, path, request_keys).await? {
-                                    DoResolveRes::Cname(r) => {
-                                        answers.push(r);
-                                    },
-                                    DoResolveRes::Other(mut res) => {
-                                        if let Some((expires, data)) = res.remove(&primary_request_key) {
-                                            match serde_json::from_value::<stored::record::dns_record::DnsAaaa>(
-                                                data.clone(),
-                                            )
-                                                .context_with("Failed to parse received record json", ea!(json = data))
-                                                .err_external()? {
-                                                stored::record::dns_record::DnsAaaa::V1(n) => {
-                                                    for n in n.0 {
-                                                        answers.push(
-                                                            Record::from_rdata(
-                                                                request.query().name().into(),
-                                                                expires,
-                                                                RData::AAAA(AAAA(n)),
-                                                            ),
-                                                        );
-                                                    }
-                                                },
-                                            }
-                                        }
-                                    },
-                                }
-                            },
-                            hickory_proto::rr::RecordType::TXT => {
-                                let primary_request_key = build_dns_key(path.clone(), RecordType::Txt);
-                                let mut request_keys = vec![primary_request_key.clone()];
-                                for t in [RecordType::A, RecordType::Aaaa] {
-                                    request_keys.push(build_dns_key(path.clone(), t));
-                                }
-                                match do_resolve(&self1, request.query().name(), &ident, path, request_keys).await? {
-                                    DoResolveRes::Cname(r) => {
-                                        answers.push(r);
-                                    },
-                                    DoResolveRes::Other(mut res) => {
-                                        if let Some((expires, data)) = res.remove(&primary_request_key) {
-                                            match serde_json::from_value::<stored::record::dns_record::DnsTxt>(
-                                                data.clone(),
-                                            )
-                                                .context_with("Failed to parse received record json", ea!(json = data))
-                                                .err_external()? {
-                                                stored::record::dns_record::DnsTxt::V1(n) => {
-                                                    for n in n.0 {
-                                                        answers.push(
-                                                            Record::from_rdata(
-                                                                request.query().name().into(),
-                                                                expires,
-                                                                RData::TXT(TXT::new(vec![n])),
-                                                            ),
-                                                        );
-                                                    }
-                                                },
-                                            }
-                                        }
-                                    },
-                                }
-                            },
-                            hickory_proto::rr::RecordType::MX => {
-                                let primary_request_key = build_dns_key(path.clone(), RecordType::Mx);
-                                let request_keys = vec![primary_request_key.clone()];
-                                match do_resolve(&self1, request.query().name(), &ident, path, request_keys).await? {
-                                    DoResolveRes::Cname(r) => {
-                                        answers.push(r);
-                                    },
-                                    DoResolveRes::Other(mut res) => {
-                                        if let Some((expires, data)) = res.remove(&primary_request_key) {
-                                            match serde_json::from_value::<stored::record::dns_record::DnsMx>(
-                                                data.clone(),
-                                            )
-                                                .context_with("Failed to parse received record json", ea!(json = data))
-                                                .err_external()? {
-                                                stored::record::dns_record::DnsMx::V1(n) => {
-                                                    for (i, n) in n.0.into_iter().enumerate() {
-                                                        let n = match Name::from_utf8(&n) {
-                                                            Err(e) => {
-                                                                self1
-                                                                    .log
-                                                                    .log_err(
-                                                                        loga::DEBUG,
-                                                                        e.context_with(
-                                                                            "Mx name in record invalid for DNS",
-                                                                            ea!(name = n),
-                                                                        ),
-                                                                    );
-                                                                continue;
-                                                            },
-                                                            Ok(n) => n,
-                                                        };
-                                                        answers.push(
-                                                            Record::from_rdata(
-                                                                request.query().name().into(),
-                                                                expires,
-                                                                RData::MX(MX::new(i as u16, n)),
-                                                            ),
-                                                        );
-                                                    }
-                                                },
-                                            }
-                                        }
-                                    },
-                                }
-                            },
-                            _ => {
-                                // Unsupported key pairs
-                                return Ok(
-                                    response_handle
-                                        .send_response(
-                                            MessageResponseBuilder::from_message_request(
-                                                request,
-                                            ).build_no_records(Header::response_from_request(request.header())),
-                                        )
-                                        .await
-                                        .context("Error sending response")
-                                        .err_internal()?,
-                                );
-                            },
-                        };
-                        return Ok(
-                            response_handle
+                        let query_type = request.query().query_type();
+                        let mut synthesized =
+                            synthesize_records(
+                                &self1.log,
+                                &self1.resolver,
+                                request.query().name(),
+                                &ident,
+                                path,
+                                query_type,
+                            ).await?;
+                        self1.ttl_policy.apply(&mut synthesized);
+                        if !synthesized.authority.is_empty() {
+                            // Referral to the servers a subtree is handed off to, not authoritative
+                            return response_handle
                                 .send_response(
                                     MessageResponseBuilder::from_message_request(
                                         request,
                                     ).build(
                                         Header::response_from_request(request.header()),
-                                        answers.iter().map(|r| r),
-                                        &[],
                                         &[],
+                                        &synthesized.authority,
                                         &[],
+                                        &synthesized.additionals,
                                     ),
                                 )
                                 .await
-                                .context("Error sending response")
-                                .err_internal()?,
-                        );
+                                .context("Error sending referral")
+                                .err_internal();
+                        }
+                        let mut answers = synthesized.answers;
+                        let negative_ttl = synthesized.negative_ttl;
+                        if self1.flatten_cnames &&
+                            matches!(
+                                query_type,
+                                hickory_proto::rr::RecordType::A | hickory_proto::rr::RecordType::AAAA
+                            ) {
+                            if let [cname] = answers.as_slice() {
+                                if let Some(flat) =
+                                    self1.flatten_cname(request.query().name(), query_type, cname).await {
+                                    answers = flat;
+                                }
+                            }
+                        }
+                        return self1
+                            .send_authoritative(
+                                request,
+                                &mut response_handle,
+                                ResponseCode::NoError,
+                                &answers,
+                                negative_ttl,
+                            )
+                            .await;
                     },
                     stored::record::record_utils::RecordRoot::Dns(_name) => {
                         self
                             .0
                             .log
                             .log_with(loga::DEBUG, "Received non-spagh request", ea!(request = request.dbg_str()));
-                        let resp = self1.upstream.send(DnsRequest::new(Message::from(MessageParts {
+                        let query_name = Name::from(request.query().name());
+                        let upstream_name = if self1.upstream_randomize_case {
+                            randomize_case(&query_name)
+                        } else {
+                            query_name.clone()
+                        };
+                        let mut edns = request.edns().cloned();
+                        self1.upstream_client_subnet.apply(&mut edns, request.src().ip());
+                        let minimized = if self1.minimizer.enabled() && self1.upstreams_authoritative().await {
+                            let query_class = request.query().query_class();
+                            self1.minimizer.run(&query_name, Instant::now(), |ancestor| {
+                                self1.probe_ancestor(ancestor, query_class)
+                            }).await
+                        } else {
+                            None
+                        };
+                        if let Some(nxdomain) = minimized {
+                            // An ancestor doesn't exist, so neither does the query name
+                            let soa = match nxdomain.kind() {
+                                ResolveErrorKind::NoRecordsFound { soa, .. } => soa
+                                    .as_ref()
+                                    .map(|r| r.clone().into_record_of_rdata()),
+                                _ => None,
+                            };
+                            let mut header = Header::response_from_request(request.header());
+                            header.set_response_code(ResponseCode::NXDomain);
+                            return response_handle
+                                .send_response(
+                                    MessageResponseBuilder::from_message_request(
+                                        request,
+                                    ).build(header, &[], &[], soa.as_ref(), &[]),
+                                )
+                                .await
+                                .context("Error returning minimized NXDOMAIN")
+                                .err_internal();
+                        }
+                        let resp = self1.send_upstream(DnsRequest::new(Message::from(MessageParts {
                             header: *request.header(),
                             queries: vec![{
-                                let mut q =
-                                    Query::query(Name::from(request.query().name()), request.query().query_type());
+                                let mut q = Query::query(upstream_name.clone(), request.query().query_type());
                                 q.set_query_class(request.query().query_class());
                                 q
                             }],
@@ -486,34 +1430,43 @@ pub async fn start_dns_bridge(
                             name_servers: vec![],
                             additionals: vec![],
                             sig0: vec![],
-                            edns: request.edns().cloned(),
-                        }), DnsRequestOptions::default())).next().await;
+                            edns: edns,
+                        }), DnsRequestOptions::default())).await;
+
+                        // Return records for the query name with the case the client used
+                        let restore_case = |records: &[Record]| -> Vec<Record> {
+                            return records.iter().map(|r| {
+                                let mut r = r.clone();
+                                if self1.upstream_randomize_case && r.name() == &upstream_name {
+                                    r.set_name(query_name.clone());
+                                }
+                                return r;
+                            }).collect();
+                        };
                         match resp {
                             Some(resp) => {
                                 let resp = match resp {
                                     Ok(r) => r,
                                     Err(e) => match e.kind() {
                                         hickory_resolver::error::ResolveErrorKind::NoRecordsFound { soa, .. } => {
-                                            return Ok(
-                                                response_handle
-                                                    .send_response(
-                                                        MessageResponseBuilder::from_message_request(
-                                                            request,
-                                                        ).build(
-                                                            Header::response_from_request(request.header()),
-                                                            &[],
-                                                            &[],
-                                                            soa
-                                                                .as_ref()
-                                                                .map(|r| r.clone().into_record_of_rdata())
-                                                                .as_ref(),
-                                                            &[],
-                                                        ),
-                                                    )
-                                                    .await
-                                                    .context("Error returning empty results")
-                                                    .err_internal()?,
-                                            );
+                                            return response_handle
+                                                .send_response(
+                                                    MessageResponseBuilder::from_message_request(
+                                                        request,
+                                                    ).build(
+                                                        Header::response_from_request(request.header()),
+                                                        &[],
+                                                        &[],
+                                                        soa
+                                                            .as_ref()
+                                                            .map(|r| r.clone().into_record_of_rdata())
+                                                            .as_ref(),
+                                                        &[],
+                                                    ),
+                                                )
+                                                .await
+                                                .context("Error returning empty results")
+                                                .err_internal();
                                         },
                                         _ => {
                                             return Err(e)
@@ -529,10 +1482,10 @@ pub async fn start_dns_bridge(
                                                 request,
                                             ).build(
                                                 Header::response_from_request(request.header()),
-                                                resp.answers(),
-                                                resp.name_servers(),
+                                                &restore_case(resp.answers()),
+                                                &restore_case(resp.name_servers()),
                                                 resp.soa().map(|r| r.to_owned().into_record_of_rdata()).as_ref(),
-                                                resp.additionals(),
+                                                &restore_case(resp.additionals()),
                                             ),
                                         )
                                         .await
@@ -541,23 +1494,21 @@ pub async fn start_dns_bridge(
                                 );
                             },
                             None => {
-                                return Ok(
-                                    response_handle
-                                        .send_response(
-                                            MessageResponseBuilder::from_message_request(
-                                                request,
-                                            ).build(
-                                                Header::response_from_request(request.header()),
-                                                &[],
-                                                &[],
-                                                &[],
-                                                &[],
-                                            ),
-                                        )
-                                        .await
-                                        .context("Error sending empty response")
-                                        .err_internal()?,
-                                );
+                                return response_handle
+                                    .send_response(
+                                        MessageResponseBuilder::from_message_request(
+                                            request,
+                                        ).build(
+                                            Header::response_from_request(request.header()),
+                                            &[],
+                                            &[],
+                                            &[],
+                                            &[],
+                                        ),
+                                    )
+                                    .await
+                                    .context("Error sending empty response")
+                                    .err_internal();
                             },
                         };
                     },
@@ -577,13 +1528,13 @@ pub async fn start_dns_bridge(
                                 .await {
                                 Ok(r) => return r,
                                 Err(e) => {
-                                    self1.log.log_err(loga::WARN, e.context("Failed to send error response"));
+                                    log_warn_err(&self1.log, e.context("Failed to send error response"));
                                     return ResponseInfo::from(*request.header());
                                 },
                             };
                         },
                         VisErr::Internal(e) => {
-                            self1.log.log_err(loga::WARN, e.context("Request failed due to internal issue"));
+                            log_warn_err(&self1.log, e.context("Request failed due to internal issue"));
                             match response_handle
                                 .send_response(
                                     MessageResponseBuilder::from_message_request(
@@ -593,7 +1544,7 @@ pub async fn start_dns_bridge(
                                 .await {
                                 Ok(r) => return r,
                                 Err(e) => {
-                                    self1.log.log_err(loga::WARN, e.context("Failed to send error response"));
+                                    log_warn_err(&self1.log, e.context("Failed to send error response"));
                                     return ResponseInfo::from(*request.header());
                                 },
                             };
@@ -607,52 +1558,67 @@ pub async fn start_dns_bridge(
         }
     }
 
-    let upstream = {
-        let mut upstream_servers = NameServerConfigGroup::new();
-        let mut upstream_opts;
-        if let Some(dns_config_upstream) = &dns_config.upstream {
-            for n in dns_config_upstream {
-                let mut upstream;
-                match &n.adn {
-                    Some(adn) => {
-                        upstream =
-                            NameServerConfig::new(
-                                SocketAddr::new(n.ip, n.port.unwrap_or(853)),
-                                hickory_resolver::config::Protocol::Tls,
-                            );
-                        upstream.tls_dns_name = Some(adn.clone());
-                    },
-                    None => {
-                        upstream =
-                            NameServerConfig::new(
-                                SocketAddr::new(n.ip, n.port.unwrap_or(53)),
-                                hickory_resolver::config::Protocol::Udp,
-                            );
-                    },
-                }
-                upstream_servers.push(upstream);
-            }
-            upstream_opts = ResolverOpts::default();
-            upstream_opts.shuffle_dns_servers = true;
-        } else {
-            let (config, options) =
-                hickory_resolver
-                ::system_conf
-                ::read_system_conf().stack_context(
-                    log,
-                    "Error reading system dns resolver config for DNS bridge upstream",
-                )?;
-            for n in config.name_servers() {
-                upstream_servers.push(n.clone());
+    let mut upstreams = vec![];
+    if dns_config.authoritative_only {
+        // Nothing is forwarded
+    } else if let Some(dns_config_upstream) = &dns_config.upstream {
+        for n in dns_config_upstream {
+            let mut upstream_servers = NameServerConfigGroup::new();
+            let mut upstream;
+            match &n.adn {
+                Some(adn) => {
+                    upstream =
+                        NameServerConfig::new(
+                            SocketAddr::new(n.ip, n.port.unwrap_or(853)),
+                            hickory_resolver::config::Protocol::Tls,
+                        );
+                    upstream.tls_dns_name = Some(adn.clone());
+                },
+                None => {
+                    upstream =
+                        NameServerConfig::new(
+                            SocketAddr::new(n.ip, n.port.unwrap_or(53)),
+                            hickory_resolver::config::Protocol::Udp,
+                        );
+                },
             }
-            upstream_opts = options;
+            upstream_servers.push(upstream);
+            upstreams.push(Upstream {
+                log: log.fork(ea!(upstream = n)),
+                pool: NameServerPool::from_config(
+                    upstream_servers,
+                    ResolverOpts::default(),
+                    GenericConnector::new(TokioRuntimeProvider::new()),
+                ),
+                failures: AtomicUsize::new(0),
+                check_case: dns_config.upstream_randomize_case,
+                recursion: Mutex::new(None),
+            });
         }
-        NameServerPool::from_config(
-            upstream_servers,
-            upstream_opts,
-            GenericConnector::new(TokioRuntimeProvider::new()),
-        )
-    };
+    } else {
+        let (config, options) =
+            hickory_resolver
+            ::system_conf
+            ::read_system_conf().stack_context(
+                log,
+                "Error reading system dns resolver config for DNS bridge upstream",
+            )?;
+        let mut upstream_servers = NameServerConfigGroup::new();
+        for n in config.name_servers() {
+            upstream_servers.push(n.clone());
+        }
+        upstreams.push(Upstream {
+            log: log.fork(ea!(upstream = "system")),
+            pool: NameServerPool::from_config(
+                upstream_servers,
+                options,
+                GenericConnector::new(TokioRuntimeProvider::new()),
+            ),
+            failures: AtomicUsize::new(0),
+            check_case: dns_config.upstream_randomize_case,
+            recursion: Mutex::new(None),
+        });
+    }
     let mut global_ipv4 = vec![];
     let mut global_ipv6 = vec![];
     for ip in global_ips {
@@ -665,10 +1631,88 @@ pub async fn start_dns_bridge(
             },
         }
     }
-    let mut server = hickory_server::ServerFuture::new(Handler(Arc::new(HandlerInner {
+    let mut local_views = vec![];
+    for view in dns_config.local_views.clone() {
+        local_views.push(LocalView::from_config(view)?);
+    }
+    let zone = Name::from_ascii(format!("{}.", DNS_SUFFIX)).unwrap();
+    let mut hosted_zones = vec![];
+    for hosted in &dns_config.hosted_zones {
+        let mut name =
+            Name::from_utf8(
+                &hosted.zone,
+            ).context_with("Hosted zone name isn't a valid DNS name", ea!(zone = hosted.zone))?;
+        name.set_fqdn(true);
+        if name.is_root() || zone.zone_of(&name) {
+            return Err(loga::err_with("Hosted zones must be outside the root and `s.` zones", ea!(zone = hosted.zone)));
+        }
+        let ident =
+            Identity::from_str(
+                &hosted.identity,
+            ).context_with("Invalid hosted zone identity", ea!(identity = hosted.identity))?;
+        hosted_zones.push((LowerName::from(name), ident));
+    }
+    let mut zone_nameservers = vec![];
+    for name in dns_config
+        .zone_nameservers
+        .clone()
+        .or_else(|| dns_config.synthetic_self_record.clone().map(|n| vec![n]))
+        .unwrap_or_else(|| vec!["localhost.".to_string()]) {
+        zone_nameservers.push(
+            Name::from_utf8(&name).context_with("Zone nameserver name isn't a valid DNS name", ea!(name = name))?,
+        );
+    }
+    let zone_contact = dns_config.zone_contact.clone().unwrap_or_else(|| format!("hostmaster.{}.", DNS_SUFFIX));
+    let zone_contact =
+        Name::from_utf8(
+            &zone_contact,
+        ).context_with("Zone contact isn't a valid DNS name", ea!(name = zone_contact))?;
+    let zone_soa =
+        Record::from_rdata(
+            zone.clone(),
+            ZONE_TTL,
+            RData::SOA(
+                SOA::new(
+                    zone_nameservers.first().cloned().unwrap_or_else(|| Name::from_ascii("localhost.").unwrap()),
+                    zone_contact,
+                    dns_config.zone_serial.unwrap_or(1),
+                    3600,
+                    600,
+                    86400,
+                    ZONE_TTL,
+                ),
+            ),
+        );
+    let zone_ns =
+        zone_nameservers
+            .into_iter()
+            .map(|n| Record::from_rdata(zone.clone(), ZONE_TTL, RData::NS(NS(n))))
+            .collect::<Vec<_>>();
+    let rate_limiter = dns_config.response_rate_limit.as_ref().map(|c| Arc::new(RateLimiter::new(c)));
+    if let Some(rate_limiter) = &rate_limiter {
+        tm.tracked_periodic(
+            "DNS bridge - rate limit cleanup",
+            Duration::try_seconds(60).unwrap().to_std().unwrap(),
+            cap_fn!(()(rate_limiter) {
+                rate_limiter.prune(Instant::now());
+            }),
+        );
+    }
+    let inner = Arc::new(HandlerInner {
         log: log.clone(),
         resolver: resolver.clone(),
-        upstream: upstream,
+        metrics: metrics.clone(),
+        rate_limiter: rate_limiter,
+        upstreams: upstreams,
+        upstream_strategy: dns_config.upstream_strategy.unwrap_or(DnsUpstreamStrategy::Failover),
+        upstream_client_subnet: ClientSubnetMode::from_config(dns_config.upstream_client_subnet.clone())?,
+        upstream_randomize_case: dns_config.upstream_randomize_case,
+        minimizer: Minimizer::new(dns_config.upstream_minimize_names),
+        authoritative_only: dns_config.authoritative_only,
+        local_views: local_views,
+        hosted_zones: hosted_zones,
+        flatten_cnames: dns_config.flatten_cnames,
+        ttl_policy: TtlPolicy::new(&dns_config.ttl.clone().unwrap_or_default()),
         synthetic_self_record: if let Some(name) = dns_config.synthetic_self_record {
             Some(
                 LowerName::from_str(
@@ -680,7 +1724,21 @@ pub async fn start_dns_bridge(
         },
         global_ipv4: global_ipv4,
         global_ipv6: global_ipv6,
-    })));
+        zone: LowerName::from(zone),
+        zone_soa: zone_soa,
+        zone_ns: zone_ns,
+    });
+    if !inner.upstreams.is_empty() && !inner.authoritative_only {
+        resolver.set_upstream_probe(Arc::downgrade(&inner) as Weak<dyn UpstreamProbe>);
+    }
+    tm.tracked_periodic(
+        "DNS bridge - upstream health check",
+        Duration::try_seconds(30).unwrap().to_std().unwrap(),
+        cap_fn!(()(inner) {
+            inner.check_upstreams().await;
+        }),
+    );
+    let mut server = hickory_server::ServerFuture::new(Handler(inner));
     let udp_bind_addrs = if let Some(bind_addrs) = dns_config.udp_bind_addrs {
         let mut out = vec![];
         for bind_addr in bind_addrs {
@@ -705,14 +1763,14 @@ pub async fn start_dns_bridge(
             SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 853)
         ]
     };
-    let mut registered = false;
+    let mut registered = vec![];
     for bind_addr in udp_bind_addrs {
         server.register_socket(
             UdpSocket::bind(&bind_addr)
                 .await
                 .stack_context_with(&log, "Opening UDP listener failed", ea!(socket = bind_addr))?,
         );
-        registered = true;
+        registered.push(format!("dns udp {}", bind_addr));
     }
     for bind_addr in tcp_bind_addrs {
         server
@@ -729,12 +1787,12 @@ pub async fn start_dns_bridge(
                 ),
             )
             .context_with("Error starting DoT server", ea!(socket = bind_addr))?;
-        registered = true;
+        registered.push(format!("dns tls {}", bind_addr));
     }
-    if !registered {
+    if registered.is_empty() {
         return Err(loga::err("No UDP or TCP bind addresses defined for DNS resolver"));
     }
-    tm.critical_task("DNS bridge - server", {
+    tm.tracked_critical_task("DNS bridge - server", {
         let log = log.clone();
         let tm = tm.clone();
         async move {
@@ -750,5 +1808,5 @@ pub async fn start_dns_bridge(
             }
         }
     });
-    return Ok(());
+    return Ok(registered);
 }
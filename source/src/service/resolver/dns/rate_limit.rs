@@ -0,0 +1,164 @@
+//! Response rate limiting for UDP queries (`DnsBridgeConfig::response_rate_limit`),
+//! with a token bucket per client subnet.
+use {
+    crate::interface::config::node::resolver_config::DnsResponseRateLimit,
+    ipnet::IpNet,
+    std::{
+        collections::HashMap,
+        net::IpAddr,
+        sync::Mutex,
+        time::Instant,
+    },
+};
+
+/// Subnets tracked at once. Past this, untracked subnets share one bucket so
+/// spoofing many sources can't grow memory or escape the limit.
+const MAX_SUBNETS: usize = 65536;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RateLimitAction {
+    Allow,
+    /// Send an empty truncated response
+    Slip,
+    Drop,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    // Limited queries since the last allowed one, for slipping
+    limited: u32,
+}
+
+impl Bucket {
+    fn new(tokens: f64, now: Instant) -> Bucket {
+        return Bucket {
+            tokens: tokens,
+            updated: now,
+            limited: 0,
+        };
+    }
+}
+
+struct Buckets {
+    subnets: HashMap<IpNet, Bucket>,
+    overflow: Bucket,
+}
+
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    slip: u32,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &DnsResponseRateLimit) -> RateLimiter {
+        let rate = config.responses_per_second.max(1) as f64;
+        let burst = config.burst.unwrap_or(config.responses_per_second).max(1) as f64;
+        return RateLimiter {
+            rate: rate,
+            burst: burst,
+            slip: config.slip.unwrap_or(2),
+            ipv4_prefix: config.ipv4_prefix.unwrap_or(24).min(32),
+            ipv6_prefix: config.ipv6_prefix.unwrap_or(56).min(128),
+            buckets: Mutex::new(Buckets {
+                subnets: HashMap::new(),
+                overflow: Bucket::new(burst, Instant::now()),
+            }),
+        };
+    }
+
+    fn subnet(&self, ip: IpAddr) -> IpNet {
+        let prefix = match ip {
+            IpAddr::V4(_) => self.ipv4_prefix,
+            IpAddr::V6(_) => self.ipv6_prefix,
+        };
+        return IpNet::new(ip.to_canonical(), prefix).unwrap().trunc();
+    }
+
+    /// Take a token for a query from the client, deciding how to respond.
+    pub fn check(&self, client: IpAddr, now: Instant) -> RateLimitAction {
+        let subnet = self.subnet(client);
+        let mut buckets = self.buckets.lock().unwrap();
+        let buckets = &mut *buckets;
+        let bucket = if buckets.subnets.contains_key(&subnet) || buckets.subnets.len() < MAX_SUBNETS {
+            buckets.subnets.entry(subnet).or_insert_with(|| Bucket::new(self.burst, now))
+        } else {
+            &mut buckets.overflow
+        };
+        bucket.tokens =
+            (bucket.tokens + now.saturating_duration_since(bucket.updated).as_secs_f64() * self.rate).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1. {
+            bucket.tokens -= 1.;
+            bucket.limited = 0;
+            return RateLimitAction::Allow;
+        }
+        bucket.limited += 1;
+        if self.slip > 0 && bucket.limited % self.slip == 0 {
+            return RateLimitAction::Slip;
+        }
+        return RateLimitAction::Drop;
+    }
+
+    /// Forget subnets whose buckets have refilled.
+    pub fn prune(&self, now: Instant) {
+        let full_after = self.burst / self.rate;
+        self
+            .buckets
+            .lock()
+            .unwrap()
+            .subnets
+            .retain(|_, b| now.saturating_duration_since(b.updated).as_secs_f64() < full_after);
+    }
+
+    pub fn subnet_count(&self) -> usize {
+        return self.buckets.lock().unwrap().subnets.len();
+    }
+}
+
+#[cfg(test)]
+mod test_rate_limit {
+    use {
+        super::{
+            RateLimitAction,
+            RateLimiter,
+        },
+        crate::interface::config::node::resolver_config::DnsResponseRateLimit,
+        std::time::{
+            Duration,
+            Instant,
+        },
+    };
+
+    #[test]
+    fn test_check() {
+        let limiter = RateLimiter::new(&DnsResponseRateLimit {
+            responses_per_second: 2,
+            burst: None,
+            slip: None,
+            ipv4_prefix: None,
+            ipv6_prefix: None,
+        });
+        let now = Instant::now();
+        let a = "192.0.2.1".parse().unwrap();
+        assert_eq!(limiter.check(a, now), RateLimitAction::Allow);
+        assert_eq!(limiter.check(a, now), RateLimitAction::Allow);
+        assert_eq!(limiter.check(a, now), RateLimitAction::Drop);
+        assert_eq!(limiter.check(a, now), RateLimitAction::Slip);
+
+        // Same subnet shares the bucket, others don't
+        assert_eq!(limiter.check("192.0.2.200".parse().unwrap(), now), RateLimitAction::Drop);
+        assert_eq!(limiter.check("198.51.100.1".parse().unwrap(), now), RateLimitAction::Allow);
+
+        // Refills over time
+        let later = now + Duration::from_millis(500);
+        assert_eq!(limiter.check(a, later), RateLimitAction::Allow);
+        assert_eq!(limiter.check(a, later), RateLimitAction::Drop);
+        limiter.prune(later + Duration::from_secs(1));
+        assert_eq!(limiter.subnet_count(), 0);
+    }
+}
@@ -0,0 +1,76 @@
+//! Limits and jitter for the TTLs of records synthesized from spaghettinuum records
+//! (`DnsBridgeConfig::ttl`). TTLs come from when values expire, which can be 0 or
+//! far in the future.
+use {
+    super::Synthesized,
+    crate::interface::config::node::resolver_config::DnsTtlConfig,
+    rand::{
+        thread_rng,
+        Rng,
+    },
+};
+
+pub struct TtlPolicy {
+    min: u32,
+    max: u32,
+    jitter: f64,
+}
+
+impl TtlPolicy {
+    pub fn new(config: &DnsTtlConfig) -> TtlPolicy {
+        let min = config.min.unwrap_or(30);
+        return TtlPolicy {
+            min: min,
+            max: config.max.unwrap_or(86400).max(min),
+            jitter: config.jitter_percent.unwrap_or(0).min(100) as f64 / 100.,
+        };
+    }
+
+    /// Clamp the TTL, then reduce it by `shave` (0-1) of the jitter, staying at or
+    /// above the minimum.
+    fn ttl(&self, ttl: u32, shave: f64) -> u32 {
+        let ttl = ttl.clamp(self.min, self.max);
+        let reduce = ((ttl - self.min) as f64 * self.jitter * shave) as u32;
+        return ttl - reduce;
+    }
+
+    /// Apply to the records and negative caching TTL of one response. The same jitter
+    /// is used for the whole response so record sets keep a single TTL.
+    pub fn apply(&self, synthesized: &mut Synthesized) {
+        let shave = thread_rng().gen::<f64>();
+        for record in synthesized
+            .answers
+            .iter_mut()
+            .chain(synthesized.authority.iter_mut())
+            .chain(synthesized.additionals.iter_mut()) {
+            record.set_ttl(self.ttl(record.ttl(), shave));
+        }
+        if let Some(negative_ttl) = &mut synthesized.negative_ttl {
+            *negative_ttl = self.ttl(*negative_ttl, shave);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_ttl {
+    use {
+        super::TtlPolicy,
+        crate::interface::config::node::resolver_config::DnsTtlConfig,
+    };
+
+    #[test]
+    fn test_ttl() {
+        let policy = TtlPolicy::new(&DnsTtlConfig {
+            min: Some(10),
+            max: Some(110),
+            jitter_percent: Some(50),
+        });
+        assert_eq!(policy.ttl(0, 0.), 10);
+        assert_eq!(policy.ttl(1_000_000, 0.), 110);
+        assert_eq!(policy.ttl(60, 0.), 60);
+
+        // Jitter takes up to the percent of the TTL above the minimum
+        assert_eq!(policy.ttl(110, 1.), 60);
+        assert_eq!(policy.ttl(0, 1.), 10);
+    }
+}
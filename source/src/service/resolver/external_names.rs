@@ -0,0 +1,236 @@
+//! Looking up external DNS names referenced by record values
+//! (`ResolverConfig::external_names`), to include their addresses in lookup API
+//! responses.
+use {
+    crate::interface::{
+        config::node::resolver_config::ExternalNamesConfig,
+        stored::record::{
+            delegate_record::{
+                Delegate,
+                KEY_SUFFIX_DELEGATE,
+            },
+            dns_record::{
+                DnsMx,
+                KEY_SUFFIX_DNS_MX,
+            },
+            handoff_record::{
+                Handoff,
+                KEY_SUFFIX_HANDOFF,
+            },
+            record_utils::{
+                RecordKey,
+                RecordRoot,
+            },
+        },
+        wire::{
+            self,
+            resolve::DNS_SUFFIX,
+        },
+    },
+    chrono::{
+        Duration,
+        Utc,
+    },
+    futures::future::join_all,
+    hickory_resolver::{
+        config::{
+            NameServerConfig,
+            Protocol,
+            ResolverConfig,
+            ResolverOpts,
+        },
+        TokioAsyncResolver,
+    },
+    loga::ResultContext,
+    std::{
+        collections::{
+            BTreeMap,
+            HashMap,
+        },
+        time::Instant,
+    },
+};
+
+/// Normalize a name to lowercase and fully qualified. Returns `None` for names in
+/// the `s.` zone, which aren't external.
+fn normalize_name(name: &str) -> Option<String> {
+    let mut name = name.trim_end_matches('.').to_ascii_lowercase();
+    if name.is_empty() || name == DNS_SUFFIX || name.ends_with(&format!(".{}", DNS_SUFFIX)) {
+        return None;
+    }
+    name.push('.');
+    return Some(name);
+}
+
+/// External DNS names referenced by the value at the key, for record types that
+/// have them. Invalid values have none.
+pub fn value_external_names(key: &RecordKey, data: &serde_json::Value) -> Vec<String> {
+    let mut names = vec![];
+    match key.last().map(|k| k.as_str()) {
+        Some(KEY_SUFFIX_DNS_MX) => {
+            if let Ok(DnsMx::V1(v)) = serde_json::from_value::<DnsMx>(data.clone()) {
+                names.extend(v.0);
+            }
+        },
+        Some(KEY_SUFFIX_DELEGATE) => {
+            if let Ok(Delegate::V1(v)) = serde_json::from_value::<Delegate>(data.clone()) {
+                for (root, _) in v.0 {
+                    if let RecordRoot::Dns(name) = root {
+                        names.push(name);
+                    }
+                }
+            }
+        },
+        Some(KEY_SUFFIX_HANDOFF) => {
+            if let Ok(Handoff::V1(v)) = serde_json::from_value::<Handoff>(data.clone()) {
+                // Servers with glue already have addresses
+                names.extend(v.name_servers.into_iter().filter(|n| !v.glue.contains_key(n)));
+            }
+        },
+        _ => { },
+    }
+    let mut out = vec![];
+    for name in names {
+        let Some(name) = normalize_name(&name) else {
+            continue;
+        };
+        if !out.contains(&name) {
+            out.push(name);
+        }
+    }
+    return out;
+}
+
+pub struct ExternalNames {
+    dns: TokioAsyncResolver,
+    max_names: usize,
+}
+
+impl ExternalNames {
+    pub fn new(config: &ExternalNamesConfig) -> Result<ExternalNames, loga::Error> {
+        let (dns_config, mut dns_opts) = match &config.upstream {
+            Some(upstream) => {
+                let mut dns_config = ResolverConfig::new();
+                for addr in upstream {
+                    dns_config.add_name_server(NameServerConfig::new(addr.resolve()?, Protocol::Udp));
+                }
+                (dns_config, ResolverOpts::default())
+            },
+            None => hickory_resolver::system_conf::read_system_conf().context(
+                "Error reading system dns resolver config for external name lookups",
+            )?,
+        };
+
+        // The resolver's cache follows record TTLs
+        dns_opts.cache_size = 4096;
+        return Ok(ExternalNames {
+            dns: TokioAsyncResolver::tokio(dns_config, dns_opts),
+            max_names: config.max_names.unwrap_or(16),
+        });
+    }
+
+    async fn lookup(&self, name: &str) -> wire::resolve::v1::ResolveExternalName {
+        let now = Utc::now();
+        match self.dns.lookup_ip(name).await {
+            Ok(ips) => {
+                let expires =
+                    now +
+                        Duration::from_std(
+                            ips.valid_until().saturating_duration_since(Instant::now()),
+                        ).unwrap_or(Duration::zero());
+                return wire::resolve::v1::ResolveExternalName {
+                    addrs: ips.iter().collect(),
+                    expires: expires,
+                    error: None,
+                };
+            },
+            Err(e) => return wire::resolve::v1::ResolveExternalName {
+                addrs: vec![],
+                expires: now,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+
+    /// Look up the external names in the values and add their addresses to the values.
+    pub async fn add_external(&self, kvs: &mut wire::resolve::v1::ResolveKeyValues) {
+        let mut value_names = vec![];
+        let mut lookup_names = vec![];
+        for (k, v) in kvs.iter() {
+            let Some(data) = &v.data else {
+                continue;
+            };
+            let names = value_external_names(k, data);
+            if names.is_empty() {
+                continue;
+            }
+            for name in &names {
+                if lookup_names.len() < self.max_names && !lookup_names.contains(name) {
+                    lookup_names.push(name.clone());
+                }
+            }
+            value_names.push((k.clone(), names));
+        }
+        if lookup_names.is_empty() {
+            return;
+        }
+        let results =
+            lookup_names
+                .iter()
+                .cloned()
+                .zip(join_all(lookup_names.iter().map(|n| self.lookup(n))).await)
+                .collect::<HashMap<_, _>>();
+        for (k, names) in value_names {
+            let external =
+                names
+                    .into_iter()
+                    .filter_map(|n| results.get(&n).map(|r| (n, r.clone())))
+                    .collect::<BTreeMap<_, _>>();
+            kvs.get_mut(&k).unwrap().external = Some(external);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_external_names {
+    use {
+        super::value_external_names,
+        crate::interface::stored::record::{
+            dns_record::KEY_SUFFIX_DNS_MX,
+            handoff_record::KEY_SUFFIX_HANDOFF,
+        },
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_value_names() {
+        assert_eq!(
+            value_external_names(
+                &vec![KEY_SUFFIX_DNS_MX.to_string()],
+                &json!({
+                    "v1": ["Mail.Example.org", "mail.example.org.", "abc.s"]
+                }),
+            ),
+            vec!["mail.example.org.".to_string()]
+        );
+        assert_eq!(
+            value_external_names(
+                &vec!["sub".to_string(), KEY_SUFFIX_HANDOFF.to_string()],
+                &json!({
+                    "v1": {
+                        "name_servers": ["ns1.example.org", "ns1.sub.example.net"],
+                        "glue": {
+                            "ns1.sub.example.net": ["192.0.2.1"]
+                        }
+                    }
+                }),
+            ),
+            vec!["ns1.example.org.".to_string()]
+        );
+
+        // Other records have no names
+        assert!(value_external_names(&vec!["dns/a".to_string()], &json!({
+            "v1": ["192.0.2.1"]
+        })).is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+//! Canned records for the resolver, used instead of the DHT and publishers. This
+//! is for testing the DNS bridge (delegation, record synthesis) deterministically
+//! and for trying out bridge configs without a network.
+use {
+    crate::{
+        interface::{
+            stored::{
+                self,
+                identity::Identity,
+                record::record_utils::{
+                    normalize_record_key,
+                    split_record_key,
+                    RecordKey,
+                },
+            },
+            wire,
+        },
+        utils::fs_util,
+    },
+    chrono::{
+        Duration,
+        Utc,
+    },
+    loga::{
+        ea,
+        ResultContext,
+    },
+    std::{
+        collections::HashMap,
+        path::Path,
+        str::FromStr,
+    },
+};
+
+/// Records by identity. In JSON this is
+/// `{IDENTITY: {KEY: {"ttl": MINUTES, "data": DATA}, ...}, ...}`, where each
+/// identity's records are in the same format as `spagh publish set` data.
+#[derive(Clone, Default)]
+pub struct Fixtures(HashMap<Identity, HashMap<RecordKey, stored::record::latest::RecordValue>>);
+
+impl Fixtures {
+    pub fn from_json(data: &[u8]) -> Result<Fixtures, loga::Error> {
+        let raw =
+            serde_json::from_slice::<HashMap<String, HashMap<String, stored::record::latest::RecordValue>>>(
+                data,
+            ).context("Fixtures don't match schema")?;
+        let mut out = Fixtures::default();
+        for (identity, values) in raw {
+            let identity =
+                Identity::from_str(
+                    &identity,
+                ).context_with("Invalid identity in fixtures", ea!(identity = identity))?;
+            for (k, v) in values {
+                out.insert(identity, split_record_key(&k), v);
+            }
+        }
+        return Ok(out);
+    }
+
+    pub async fn load(path: &Path) -> Result<Fixtures, loga::Error> {
+        return Fixtures::from_json(
+            &fs_util::read(path).await?,
+        ).context_with("Error loading resolver fixtures", ea!(path = path.to_string_lossy()));
+    }
+
+    pub fn insert(&mut self, identity: Identity, key: RecordKey, value: stored::record::latest::RecordValue) {
+        self.0.entry(identity).or_default().insert(normalize_record_key(&key), value);
+    }
+
+    /// Look up values like a publisher would. Missing keys have no data and expire
+    /// immediately. Unknown identities have no values, like identities with no
+    /// announcement.
+    pub fn get(
+        &self,
+        identity: &Identity,
+        keys: &[RecordKey],
+    ) -> Result<wire::resolve::v1::ResolveKeyValues, loga::Error> {
+        let mut out = HashMap::new();
+        let Some(values) = self.0.get(identity) else {
+            return Ok(out);
+        };
+        let now = Utc::now();
+        for k in keys {
+            let (ttl, data) = match values.get(&normalize_record_key(k)) {
+                Some(v) => (v.ttl, v.data.clone()),
+                None => (0, None),
+            };
+            out.insert(k.clone(), wire::resolve::v1::ResolveValue {
+                expires: now + Duration::try_minutes(ttl as i64).context("TTL out of range")?,
+                data: data,
+                published: None,
+                provenance: Some(wire::resolve::v1::ResolveProvenance {
+                    source: wire::resolve::v1::ResolveSource::Fetch,
+                    publisher: None,
+                    announced: None,
+                    ttl_remaining: ttl as i64 * 60,
+                    conflicts: 0,
+                }),
+                external: None,
+            });
+        }
+        return Ok(out);
+    }
+}
+
+#[cfg(test)]
+mod test_fixtures {
+    use {
+        super::Fixtures,
+        crate::interface::config::identity::LocalIdentitySecret,
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_get() {
+        let (identity, _) = LocalIdentitySecret::new();
+        let (other, _) = LocalIdentitySecret::new();
+        let fixtures = Fixtures::from_json(serde_json::to_string(&json!({
+            identity.to_string(): {
+                "www.dns/a": {
+                    "ttl": 60,
+                    "data": "x"
+                }
+            }
+        })).unwrap().as_bytes()).unwrap();
+        let found = vec!["www".to_string(), "dns/a".to_string()];
+        let missing = vec!["dns/a".to_string()];
+        let res = fixtures.get(&identity, &[found.clone(), missing.clone()]).unwrap();
+        assert_eq!(res.get(&found).unwrap().data, Some(json!("x")));
+        assert_eq!(res.get(&missing).unwrap().data, None);
+        assert!(fixtures.get(&other, &[found]).unwrap().is_empty());
+    }
+}
@@ -3,39 +3,113 @@ use {
         interface::{
             stored::{
                 self,
+                announcement::latest::AnnouncementPublisher,
                 identity::Identity,
-                record::record_utils::{
-                    join_record_key,
-                    split_query_record_keys,
-                    split_record_key,
-                    RecordKey,
+                record::{
+                    record_utils::{
+                        join_record_key,
+                        split_query_record_keys,
+                        split_record_key,
+                        RecordKey,
+                    },
+                    succession_record::{
+                        build_succession_key,
+                        Succession,
+                        SUCCESSION_MAX_HOPS,
+                    },
                 },
             },
             wire::{
                 self,
+                api::resolve::v1::{
+                    HealthComponent,
+                    HealthStatus,
+                    ResolverHealth,
+                    SignedResolveResp,
+                    SignedResolveRespContent,
+                },
             },
         },
+        resolving::{
+            connect_staggered,
+            interleave_families,
+        },
         service::{
             node::Node,
             publisher::Publisher,
+            resolver::{
+                cache_usage::CacheUsage,
+                external_names::ExternalNames,
+                static_names::{
+                    StaticNameMatch,
+                    StaticNames,
+                },
+            },
         },
         ta_res,
         ta_vis_res,
         utils::{
-            blob::Blob,
+            api_error::{
+                response_bad_request,
+                response_internal,
+            },
+            blob::{
+                Blob,
+                ToBlob,
+            },
             db_util::setup_db,
+            identity_secret::IdentitySigner,
+            jsonrpc,
+            recent_errors::log_warn_err,
+            reference_chain::ReferenceChain,
+            request_id::{
+                current_request_trace,
+                propagate_trace_headers,
+                request_log,
+                with_request_trace,
+            },
             signed::IdentSignatureMethods,
+            task_status::TrackedTasks,
             tls_util::cert_der_hash,
+            unstable_ip::{
+                UnstableIpv4,
+                UnstableIpv6,
+            },
             ResultVisErr,
             VisErr,
         },
     },
+    async_trait::async_trait,
     chrono::{
         DateTime,
         Duration,
         Utc,
     },
+    deadpool_sqlite::Pool,
     flowcontrol::shed,
+    futures::{
+        stream::FuturesUnordered,
+        StreamExt,
+    },
+    hickory_proto::rr::LowerName,
+    http::{
+        header::{
+            CONTENT_TYPE,
+            ETAG,
+            IF_NONE_MATCH,
+            RETRY_AFTER,
+        },
+        HeaderMap,
+        HeaderName,
+        HeaderValue,
+        Method,
+        Request,
+        StatusCode,
+    },
+    http_body_util::{
+        BodyExt,
+        Full,
+    },
     htwrap::{
         htreq::{
             self,
@@ -45,12 +119,11 @@ use {
             self,
             responses::{
                 response_200_json,
-                response_400,
-                response_503,
             },
         },
     },
     hyper::{
+        body::Bytes,
         Uri,
     },
     hyper_rustls::HttpsConnectorBuilder,
@@ -61,30 +134,74 @@ use {
         Log,
         ResultContext,
     },
-    moka::future::Cache,
-    rand::{
-        seq::SliceRandom,
-        thread_rng,
+    manual_future::{
+        ManualFuture,
+        ManualFutureCompleter,
+    },
+    moka::{
+        future::Cache,
+        notification::RemovalCause,
     },
     rustls::ClientConfig,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    sha2::{
+        Digest,
+        Sha256,
+    },
     std::{
-        collections::HashMap,
-        net::IpAddr,
+        collections::{
+            hash_map::Entry,
+            HashMap,
+        },
+        net::{
+            IpAddr,
+            SocketAddr,
+        },
         path::Path,
+        pin::Pin,
         str::FromStr,
-        sync::Arc,
+        sync::{
+            atomic::{
+                AtomicU64,
+                Ordering,
+            },
+            Arc,
+            Mutex,
+            Weak,
+        },
+        task::{
+            Context,
+            Poll,
+        },
     },
     taskmanager::TaskManager,
     tokio::{
         select,
         spawn,
-        time::sleep,
+        time::{
+            sleep_until,
+            timeout,
+            Instant,
+        },
     },
     tower_service::Service,
 };
 
+pub mod cache_usage;
 pub mod db;
+
+/// The latest `resolver.sqlite3` schema version, from `buildlib/resolver`.
+pub const DB_SCHEMA_VERSION: usize = 0;
 pub mod dns;
+pub mod external_names;
+#[cfg(feature = "fixtures")]
+pub mod fixtures;
+pub mod static_names;
+pub mod warm_cache;
 
 #[derive(Debug)]
 pub struct SingleKeyVerifier {
@@ -152,12 +269,206 @@ impl rustls::client::danger::ServerCertVerifier for SingleKeyVerifier {
     }
 }
 
+#[derive(Default)]
+struct CacheCounters {
+    value_hits: AtomicU64,
+    value_misses: AtomicU64,
+    announcement_hits: AtomicU64,
+    announcement_misses: AtomicU64,
+    coalesced_lookups: AtomicU64,
+    stale_lookups: AtomicU64,
+    merge_conflicts: AtomicU64,
+    reference_limit_errors: AtomicU64,
+    negative_hits: AtomicU64,
+    missing_identity_hits: AtomicU64,
+    dht_lookups: AtomicU64,
+    dht_lookups_queued: AtomicU64,
+    dht_lookups_waiting: AtomicU64,
+    dht_lookup_queue_ms: AtomicU64,
+    dht_lookup_queue_ms_max: AtomicU64,
+    publisher_not_modified: AtomicU64,
+    local_lookups: AtomicU64,
+    suspect_reports: AtomicU64,
+    suspect_changed: AtomicU64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CacheStats {
+    pub value_entries: u64,
+    /// Approximate size of the cached values (bytes)
+    pub value_bytes: u64,
+    /// The limit for `value_bytes`
+    pub value_max_bytes: u64,
+    /// Identities with cached values
+    pub value_identities: u64,
+    /// Values evicted because their identity had the maximum number of cached values
+    /// (`max_cache_entries_per_identity`)
+    pub identity_cap_evictions: u64,
+    /// The identities using the most cache space
+    pub top_identities: Vec<CacheIdentityUsage>,
+    pub value_hits: u64,
+    pub value_misses: u64,
+    pub announcement_entries: u64,
+    pub announcement_hits: u64,
+    pub announcement_misses: u64,
+    /// Lookups that waited on an identical in-progress lookup instead of making
+    /// their own requests
+    pub coalesced_lookups: u64,
+    /// Lookups answered with expired cached values because all of the identity's
+    /// publishers were in maintenance mode
+    pub stale_lookups: u64,
+    /// Lookups answered by the publisher in the same node (see
+    /// `local_publisher_fast_path`)
+    pub local_lookups: u64,
+    /// Fetched values where the identity's publishers returned different data for the
+    /// key
+    pub merge_conflicts: u64,
+    /// Lookups that failed because references between records (successions,
+    /// delegations) formed a cycle or exceeded a limit (see `reference_chain`)
+    pub reference_limit_errors: u64,
+    /// Value cache hits for keys the publisher had no value for (included in
+    /// `value_hits`)
+    pub negative_hits: u64,
+    /// Identities remembered as having no announcement
+    pub missing_identity_entries: u64,
+    /// Lookups for identities remembered as having no announcement, answered without
+    /// querying the DHT
+    pub missing_identity_hits: u64,
+    /// DHT lookups for announcements
+    pub dht_lookups: u64,
+    /// DHT lookups in progress
+    pub dht_lookups_active: u64,
+    /// DHT lookups that had to wait because the maximum were already in progress
+    pub dht_lookups_queued: u64,
+    /// DHT lookups waiting now
+    pub dht_lookups_waiting: u64,
+    /// Total time queued DHT lookups waited, in milliseconds
+    pub dht_lookup_queue_ms: u64,
+    /// Longest time a DHT lookup waited, in milliseconds
+    pub dht_lookup_queue_ms_max: u64,
+    /// Publisher requests answered "not modified", reusing the previous response
+    /// because the values hadn't changed
+    pub publisher_not_modified: u64,
+    /// Client reports of values that seemed wrong that were accepted and looked up
+    /// again
+    pub suspect_reports: u64,
+    /// Accepted reports where looking up again got different values
+    pub suspect_changed: u64,
+}
+
+/// Decrements a count of waiting lookups when the wait ends, even if the lookup is
+/// cancelled.
+struct WaitingGuard<'a>(&'a AtomicU64);
+
+impl<'a> Drop for WaitingGuard<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Value cache use by one identity.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CacheIdentityUsage {
+    pub identity: Identity,
+    pub entries: u64,
+    /// Approximate size of the identity's cached values and keys
+    pub bytes: u64,
+}
+
+/// A value in the resolver cache.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct CacheEntry {
+    pub identity: Identity,
+    /// Dotted record key
+    pub key: String,
+    pub expires: DateTime<Utc>,
+    /// False if the publisher had no value for the key (the missing value is cached)
+    pub found: bool,
+}
+
+/// Identity and sorted requested keys.
+type InflightKey = (Identity, Vec<RecordKey>);
+
+type InflightResult = Result<wire::resolve::v1::ResolveKeyValues, String>;
+
+/// Where a cached value was fetched from. Not persisted.
+#[derive(Clone)]
+struct CacheOrigin {
+    publisher: SocketAddr,
+    announced: DateTime<Utc>,
+    published: Option<DateTime<Utc>>,
+    conflicts: usize,
+}
+
+/// Expiration, json-serialized value (`None` if the publisher had no value), and
+/// origin.
+type CacheValue = (DateTime<Utc>, Option<String>, Option<CacheOrigin>);
+
+/// How long a health check result is reused, so frequent health requests don't
+/// each start a DHT lookup.
+const HEALTH_REUSE: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long each health check component can take before it's considered failed.
+const HEALTH_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Checks that upstream DNS servers answer, for resolver health checks. Provided
+/// by the DNS bridge.
+#[async_trait]
+pub trait UpstreamProbe: Send + Sync {
+    async fn probe_upstreams(&self) -> HealthComponent;
+}
+
+fn health_component(status: HealthStatus, message: Option<String>) -> HealthComponent {
+    return HealthComponent {
+        status: status,
+        message: message,
+    };
+}
+
 struct Resolver_ {
-    node: Node,
+    // `None` if using fixtures
+    node: Option<Node>,
+    // `None` if using fixtures
+    db_pool: Option<Pool>,
     log: Log,
-    cache: Cache<(Identity, RecordKey), (DateTime<Utc>, Option<String>)>,
+    cache: Cache<(Identity, RecordKey), CacheValue>,
+    max_cache: u64,
+    cache_usage: Arc<CacheUsage>,
+    announcement_cache: Cache<Identity, stored::announcement::Announcement>,
+    // Identities with no announcement, with when to look again
+    missing_identity_cache: Cache<Identity, DateTime<Utc>>,
+    missing_identity_ttl: Duration,
+    max_missing_ttl: Option<Duration>,
+    cache_counters: CacheCounters,
+    // Limits concurrent DHT lookups; further lookups wait in order
+    dht_lookup_permits: tokio::sync::Semaphore,
+    max_dht_lookups: usize,
+    // Lookups in progress, by identity and sorted keys. Concurrent identical lookups
+    // wait for the first instead of repeating the DHT and publisher requests.
+    inflight: Mutex<HashMap<InflightKey, Vec<ManualFutureCompleter<InflightResult>>>>,
+    // Publishers in maintenance mode, with when to try them again
+    publisher_backoff: Cache<SocketAddr, DateTime<Utc>>,
+    // The last response from each publisher, for conditional requests
+    publisher_etags: Cache<PublisherEtagKey, PublisherEtag>,
     publisher: Option<Arc<Publisher>>,
+    // Answer identities the publisher hosts from it directly
+    local_fast_path: bool,
     global_addrs: Vec<IpAddr>,
+    allow_private_publishers: bool,
+    // Signs requests to publishers, if enabled
+    request_signer: Option<Arc<Mutex<dyn IdentitySigner>>>,
+    upstream_probe: Mutex<Option<Weak<dyn UpstreamProbe>>>,
+    static_names: Mutex<Arc<StaticNames>>,
+    last_health: tokio::sync::Mutex<Option<(Instant, ResolverHealth)>>,
+    // Cap on the expiry of values reported as suspect that didn't change
+    suspect_ttl: Option<Duration>,
+    // Identities with recently accepted suspect reports, with when
+    suspect_reports: Mutex<HashMap<Identity, DateTime<Utc>>>,
+    #[cfg(feature = "fixtures")]
+    fixtures: Option<fixtures::Fixtures>,
 }
 
 /// This is the core of the resolver; it does lookups using a local node. If you
@@ -166,39 +477,315 @@ struct Resolver_ {
 #[derive(Clone)]
 pub struct Resolver(Arc<Resolver_>);
 
+/// The result of looking up an identity's announcement.
+enum AnnouncementLookup {
+    Found(stored::announcement::Announcement),
+    /// There's no announcement, and lookups can assume there won't be one until this
+    /// time. `cached` if this was remembered from a previous lookup.
+    Missing {
+        until: DateTime<Utc>,
+        cached: bool,
+    },
+}
+
+/// A publisher's response to a value request.
+enum PublisherResp {
+    Values(wire::resolve::v1::ResolveKeyValues),
+    /// The publisher is in maintenance mode, try again after this time
+    Maintenance(DateTime<Utc>),
+}
+
+/// Read the wait from a maintenance response's `Retry-After` header. Only the
+/// seconds form is supported.
+fn parse_retry_after(headers: &HeaderMap) -> Duration {
+    let secs =
+        headers
+            .get(RETRY_AFTER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| u32::from_str(v.trim()).ok())
+            .unwrap_or(wire::resolve::DEFAULT_RETRY_AFTER_SECS)
+            .min(wire::resolve::MAX_RETRY_AFTER_SECS);
+    return Duration::try_seconds(secs as i64).unwrap();
+}
+
+/// Collects a response body, failing once it's larger than `max`.
+struct LimitedBody {
+    data: Vec<u8>,
+    max: usize,
+}
+
+impl tokio::io::AsyncWrite for LimitedBody {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, std::io::Error>> {
+        let self1 = self.get_mut();
+        if self1.data.len() + buf.len() > self1.max {
+            return Poll::Ready(Err(std::io::Error::other("Response body too large")));
+        }
+        self1.data.extend_from_slice(buf);
+        return Poll::Ready(Ok(buf.len()));
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        return Poll::Ready(Ok(()));
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), std::io::Error>> {
+        return Poll::Ready(Ok(()));
+    }
+}
+
+fn build_provenance(
+    source: wire::resolve::v1::ResolveSource,
+    origin: Option<&CacheOrigin>,
+    expires: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> wire::resolve::v1::ResolveProvenance {
+    return wire::resolve::v1::ResolveProvenance {
+        source: source,
+        publisher: origin.map(|o| o.publisher),
+        announced: origin.map(|o| o.announced),
+        ttl_remaining: (expires - now).num_seconds().max(0),
+        conflicts: origin.map(|o| o.conflicts).unwrap_or(0),
+    };
+}
+
+/// Whether an address is publicly routable. Anyone can announce any address for
+/// their publishers, so connecting to other addresses would let a malicious
+/// announcement steer the resolver into internal networks.
+fn publisher_addr_global(ip: IpAddr) -> bool {
+    match ip.to_canonical() {
+        IpAddr::V4(ip) => return ip.unstable_is_global(),
+        IpAddr::V6(ip) => return ip.unstable_is_global(),
+    }
+}
+
+/// Reports of suspect values for an identity are ignored for this long after one
+/// is accepted, so clients can't keep the resolver looking the identity up.
+fn suspect_report_cooldown() -> Duration {
+    return Duration::try_minutes(1).unwrap();
+}
+
+/// The most identities with recently accepted suspect reports; further reports
+/// are ignored until some cool down.
+const MAX_SUSPECT_REPORTS: usize = 4096;
+
+/// The most keys in one suspect report.
+const MAX_SUSPECT_KEYS: usize = 64;
+
+/// How long to wait for more publishers after the first responds before merging
+/// the responses. Slower publishers are left out.
+const MERGE_WAIT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// The value picked for a key from multiple publishers' responses.
+struct MergedValue {
+    value: wire::resolve::v1::ResolveValue,
+    publisher: SocketAddr,
+    /// Responses with different data
+    conflicts: usize,
+}
+
+/// Pick a value for each key from the publishers' responses: the most recently
+/// published, or if tied the one with the lowest data hash. This doesn't depend on
+/// response order, so all resolvers pick the same value given the same responses.
+fn merge_responses(
+    responses: Vec<(wire::resolve::v1::ResolveKeyValues, SocketAddr)>,
+) -> HashMap<RecordKey, MergedValue> {
+    let mut candidates: HashMap<RecordKey, Vec<(Blob, wire::resolve::v1::ResolveValue, SocketAddr)>> =
+        HashMap::new();
+    for (values, publisher) in responses {
+        for (k, v) in values {
+            let hash = <Sha256 as Digest>::digest(serde_json::to_vec(&v.data).unwrap()).blob();
+            candidates.entry(k).or_default().push((hash, v, publisher));
+        }
+    }
+    let mut out = HashMap::new();
+    for (k, mut candidates) in candidates {
+        candidates.sort_by(|a, b| b.1.published.cmp(&a.1.published).then_with(|| a.0.cmp(&b.0)));
+        let mut candidates = candidates.into_iter();
+        let (hash, value, publisher) = candidates.next().unwrap();
+        out.insert(k, MergedValue {
+            value: value,
+            publisher: publisher,
+            conflicts: candidates.filter(|c| c.0 != hash).count(),
+        });
+    }
+    return out;
+}
+
+/// Group announced publisher addresses by cert, since a publisher with multiple
+/// addresses (ex: IPv4 and IPv6) is listed once per address. Groups keep
+/// announcement order; within a group, addresses in a family this node has a
+/// global address in come first.
+fn group_publishers(
+    publishers: Vec<AnnouncementPublisher>,
+    global_addrs: &[IpAddr],
+) -> Vec<Vec<AnnouncementPublisher>> {
+    let mut groups: Vec<Vec<AnnouncementPublisher>> = vec![];
+    for publisher in publishers {
+        match groups.iter_mut().find(|g| g[0].cert_hash == publisher.cert_hash) {
+            Some(g) => g.push(publisher),
+            None => groups.push(vec![publisher]),
+        }
+    }
+    for group in &mut groups {
+        group.sort_by_key(|p| !global_addrs.iter().any(|i| i.is_ipv4() == p.addr.0.ip().is_ipv4()));
+    }
+    return groups;
+}
+
+/// Approximate size of a cached value and its key, for cache limits.
+fn cache_weight(key: &(Identity, RecordKey), pair: &CacheValue) -> u32 {
+    let key_len = key.1.iter().map(|s| s.len()).sum::<usize>();
+    let value_len = pair.1.as_ref().map(|v| v.len()).unwrap_or(0);
+    return (key_len + value_len).max(1).try_into().unwrap_or(u32::MAX);
+}
+
+/// Build the record value cache, limited by size and keeping `usage` in sync.
+fn build_value_cache(max_cache: u64, usage: &Arc<CacheUsage>) -> Cache<(Identity, RecordKey), CacheValue> {
+    return Cache::builder().weigher(|key, pair: &CacheValue| -> u32 {
+        cache_weight(key, pair)
+    }).eviction_listener_with_queued_delivery_mode({
+        let usage = usage.clone();
+        move |key: Arc<(Identity, RecordKey)>, pair: CacheValue, cause| {
+            // The replacement was already recorded when it was stored
+            if cause != RemovalCause::Replaced {
+                usage.removed(&key.0, &key.1, pair.0);
+            }
+        }
+    }).max_capacity(max_cache).build();
+}
+
+/// Store a value in the record value cache, evicting other values of the identity
+/// if it's over the per-identity limit.
+async fn cache_store(
+    cache: &Cache<(Identity, RecordKey), CacheValue>,
+    usage: &CacheUsage,
+    identity: Identity,
+    key: RecordKey,
+    value: CacheValue,
+) {
+    let key = (identity, key);
+    for evict in usage.added(&key.0, &key.1, value.0, cache_weight(&key, &value)) {
+        cache.invalidate(&(key.0, evict)).await;
+    }
+    cache.insert(key, value).await;
+}
+
+/// Publisher address, identity, and sorted requested keys.
+type PublisherEtagKey = (SocketAddr, Identity, Vec<RecordKey>);
+
+#[derive(Clone)]
+struct PublisherEtag {
+    etag: HeaderValue,
+    // When the response was received; expirations in the body are relative to this
+    received: DateTime<Utc>,
+    body: Arc<Vec<u8>>,
+}
+
+/// Build the cache of the last response from each publisher, limited to about
+/// 16MiB. Entries unused for an hour are dropped.
+fn build_publisher_etag_cache() -> Cache<PublisherEtagKey, PublisherEtag> {
+    return Cache::builder()
+        .weigher(|_, v: &PublisherEtag| -> u32 {
+            v.body.len().max(1).try_into().unwrap_or(u32::MAX)
+        })
+        .max_capacity(16 * 1024 * 1024)
+        .time_to_idle(std::time::Duration::from_secs(60 * 60))
+        .build();
+}
+
 impl Resolver {
     /// Start a new resolver core in the task manager.
     ///
-    /// * `max_cache`: The maximum data to store in the cache (bytes, roughly). Defaults to
-    ///   about 64MiB.
+    /// * `max_cache`: The maximum data to store in the record value cache (bytes,
+    ///   roughly). Defaults to about 64MiB.
+    ///
+    /// * `max_cache_per_identity`: The maximum number of values to cache for a single
+    ///   identity. When exceeded, the identity's values expiring soonest are evicted.
+    ///   Defaults to no limit.
+    ///
+    /// * `max_announcement_cache`: The maximum number of announcements (identity to
+    ///   publishers) to store in the announcement cache. Defaults to 4096.
+    ///
+    /// * `announcement_cache_ttl`: How long announcements stay cached. Defaults to 1
+    ///   hour.
+    ///
+    /// * `missing_identity_ttl`: How long to remember that an identity has no
+    ///   announcement. Zero disables this. Defaults to 1 minute.
+    ///
+    /// * `max_missing_ttl`: The longest a publisher's answer that a key has no value is
+    ///   cached, regardless of the identity's missing TTL. Defaults to no limit.
+    ///
+    /// * `max_dht_lookups`: The maximum number of DHT lookups for announcements to run
+    ///   at once. Others wait in order. Defaults to 64.
+    ///
+    /// * `max_persist`: The maximum data to persist from the record value cache at
+    ///   shutdown (bytes, roughly). Expired values are never persisted, and the values
+    ///   expiring soonest are dropped first. Defaults to `max_cache`.
     ///
     /// * `cache_path`: If a cache path is provided the cache will be persisted there when
     ///   shutting down, and initialized from that data when starting up.
+    ///
+    /// * `allow_private_publishers`: Connect to publishers announced at non-global
+    ///   addresses (loopback, private, link-local, etc). Otherwise they're skipped.
+    ///
+    /// * `request_signer`: Sign requests to publishers with this identity, for
+    ///   publishers that only answer authorized resolvers.
+    ///
+    /// * `suspect_ttl`: When a client reports values as suspect and looking them up
+    ///   again gets the same values, cache them for at most this long. Defaults to no
+    ///   limit.
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         log: &Log,
         tm: &TaskManager,
         node: Node,
         max_cache: Option<u64>,
+        max_cache_per_identity: Option<usize>,
+        max_persist: Option<u64>,
+        max_announcement_cache: Option<u64>,
+        announcement_cache_ttl: Option<Duration>,
+        missing_identity_ttl: Option<Duration>,
+        max_missing_ttl: Option<Duration>,
+        max_dht_lookups: Option<usize>,
         cache_dir: &Path,
         publisher: Option<Arc<Publisher>>,
+        local_fast_path: bool,
         global_addrs: Vec<IpAddr>,
+        allow_private_publishers: bool,
+        request_signer: Option<Arc<Mutex<dyn IdentitySigner>>>,
+        suspect_ttl: Option<Duration>,
     ) -> Result<Resolver, loga::Error> {
         let db_pool =
-            setup_db(&cache_dir.join("resolver.sqlite3"), db::migrate)
+            setup_db(&cache_dir.join("resolver.sqlite3"), DB_SCHEMA_VERSION, db::migrate)
                 .await
                 .stack_context(log, "Error initializing database")?;
-        let cache = Cache::builder().weigher(|_key, pair: &(DateTime<Utc>, Option<String>)| -> u32 {
-            match &pair.1 {
-                Some(v) => v.len().try_into().unwrap_or(u32::MAX),
-                None => 1,
-            }
-        }).max_capacity(max_cache.unwrap_or(64 * 1024 * 1024)).build();
+        let max_cache = max_cache.unwrap_or(64 * 1024 * 1024);
+        let max_dht_lookups = max_dht_lookups.unwrap_or(64).max(1);
+        let max_persist = max_persist.unwrap_or(max_cache);
+        let cache_usage = Arc::new(CacheUsage::new(max_cache_per_identity));
+        let cache = build_value_cache(max_cache, &cache_usage);
+        let announcement_cache =
+            Cache::builder()
+                .max_capacity(max_announcement_cache.unwrap_or(4096))
+                .time_to_live(
+                    announcement_cache_ttl
+                        .unwrap_or_else(|| Duration::try_hours(1).unwrap())
+                        .to_std()
+                        .context("Announcement cache TTL out of range")?,
+                )
+                .build();
+        let missing_identity_ttl = missing_identity_ttl.unwrap_or_else(|| Duration::try_minutes(1).unwrap());
+        let missing_identity_cache =
+            Cache::builder()
+                .max_capacity(max_announcement_cache.unwrap_or(4096))
+                .time_to_live(missing_identity_ttl.to_std().context("Missing identity TTL out of range")?)
+                .build();
 
         // Seed with stored cache data
         {
             let log = &log.fork(ea!(subsys = "restore_cache"));
             let db_pool = db_pool.clone();
-            match async {
+            if let Err(e) = async {
                 let mut edge = Some(i64::MAX);
                 while let Some(e) = edge.take() {
                     for row in db_pool
@@ -208,29 +795,58 @@ impl Resolver {
                         .interact(move |db| db::cache_list(db, e))
                         .await?? {
                         edge = Some(row.rowid);
-                        cache
-                            .insert((row.identity.clone(), split_record_key(&row.key)), (row.expires, row.value))
-                            .await;
+                        cache_store(
+                            &cache,
+                            &cache_usage,
+                            row.identity,
+                            split_record_key(&row.key),
+                            (row.expires, row.value, None),
+                        ).await;
                     }
                 }
                 return Ok(()) as Result<(), loga::Error>;
             }.await {
-                Err(e) => {
-                    log.log_err(loga::WARN, e.context("Error seeding cache with persisted data"));
-                },
-                _ => { },
+                log_warn_err(log, e.context("Error seeding cache with persisted data"));
             }
         }
         let core = Resolver(Arc::new(Resolver_ {
-            node: node,
+            node: Some(node),
+            db_pool: Some(db_pool.clone()),
             log: log.clone(),
             cache: cache.clone(),
+            max_cache: max_cache,
+            cache_usage: cache_usage,
+            announcement_cache: announcement_cache,
+            missing_identity_cache: missing_identity_cache,
+            missing_identity_ttl: missing_identity_ttl,
+            max_missing_ttl: max_missing_ttl,
+            cache_counters: CacheCounters::default(),
+            dht_lookup_permits: tokio::sync::Semaphore::new(max_dht_lookups),
+            max_dht_lookups: max_dht_lookups,
+            inflight: Mutex::new(HashMap::new()),
+            publisher_backoff: Cache::builder()
+                .max_capacity(4096)
+                .time_to_live(
+                    Duration::try_seconds(wire::resolve::MAX_RETRY_AFTER_SECS as i64).unwrap().to_std().unwrap(),
+                )
+                .build(),
+            publisher_etags: build_publisher_etag_cache(),
             publisher: publisher,
+            local_fast_path: local_fast_path,
             global_addrs: global_addrs,
+            allow_private_publishers: allow_private_publishers,
+            request_signer: request_signer,
+            upstream_probe: Mutex::new(None),
+            static_names: Mutex::new(Arc::new(StaticNames::default())),
+            last_health: tokio::sync::Mutex::new(None),
+            suspect_ttl: suspect_ttl,
+            suspect_reports: Mutex::new(HashMap::new()),
+            #[cfg(feature = "fixtures")]
+            fixtures: None,
         }));
 
         // Bg core cleanup
-        tm.task("Resolver - cache persister", {
+        tm.tracked_task("Resolver - cache persister", {
             let tm1 = tm.clone();
             let db_pool = db_pool.clone();
             let log = log.fork(ea!(subsys = "persist_cache"));
@@ -244,7 +860,18 @@ impl Resolver {
                         let cache = cache.clone();
                         move |db| {
                             db::cache_clear(db)?;
-                            for (k, v) in cache.iter() {
+
+                            // Keep the longest lived values within the size limit
+                            let now = Utc::now();
+                            let mut entries =
+                                cache.iter().filter(|(_, v)| v.0 > now).collect::<Vec<_>>();
+                            entries.sort_by_key(|(_, v)| std::cmp::Reverse(v.0));
+                            let mut size = 0u64;
+                            for (k, v) in entries {
+                                size += cache_weight(&k, &v) as u64;
+                                if size > max_persist {
+                                    break;
+                                }
                                 db::cache_push(
                                     db,
                                     &k.0,
@@ -253,6 +880,9 @@ impl Resolver {
                                     v.1.as_ref().map(|v| v.as_str()),
                                 )?;
                             }
+
+                            // Release space freed by the clear
+                            db.execute_batch("vacuum")?;
                             return Ok(()) as Result<_, loga::Error>;
                         }
                     }).await??;
@@ -260,7 +890,7 @@ impl Resolver {
                 }.await {
                     Ok(_) => { },
                     Err(e) => {
-                        log.log_err(loga::WARN, e.context("Failed to persist cache at shutdown"));
+                        log_warn_err(log, e.context("Failed to persist cache at shutdown"));
                     },
                 }
             }
@@ -268,11 +898,273 @@ impl Resolver {
         Ok(core)
     }
 
+    /// Create a resolver that answers from canned records instead of the DHT and
+    /// publishers. Nothing is persisted.
+    #[cfg(feature = "fixtures")]
+    pub fn new_fixtures(log: &Log, fixtures: fixtures::Fixtures) -> Resolver {
+        let cache_usage = Arc::new(CacheUsage::new(None));
+        return Resolver(Arc::new(Resolver_ {
+            node: None,
+            db_pool: None,
+            log: log.clone(),
+            cache: build_value_cache(64 * 1024 * 1024, &cache_usage),
+            max_cache: 64 * 1024 * 1024,
+            cache_usage: cache_usage,
+            announcement_cache: Cache::builder().max_capacity(4096).build(),
+            missing_identity_cache: Cache::builder().max_capacity(4096).build(),
+            missing_identity_ttl: Duration::zero(),
+            max_missing_ttl: None,
+            cache_counters: CacheCounters::default(),
+            dht_lookup_permits: tokio::sync::Semaphore::new(1),
+            max_dht_lookups: 1,
+            inflight: Mutex::new(HashMap::new()),
+            publisher_backoff: Cache::builder().max_capacity(4096).build(),
+            publisher_etags: build_publisher_etag_cache(),
+            publisher: None,
+            local_fast_path: false,
+            global_addrs: vec![],
+            allow_private_publishers: false,
+            request_signer: None,
+            upstream_probe: Mutex::new(None),
+            static_names: Mutex::new(Arc::new(StaticNames::default())),
+            last_health: tokio::sync::Mutex::new(None),
+            suspect_ttl: None,
+            suspect_reports: Mutex::new(HashMap::new()),
+            fixtures: Some(fixtures),
+        }));
+    }
+
+    /// Register the DNS bridge's upstream check, for health checks.
+    pub fn set_upstream_probe(&self, probe: Weak<dyn UpstreamProbe>) {
+        *self.0.upstream_probe.lock().unwrap() = Some(probe);
+    }
+
+    /// Replace the names answered locally (see `ResolverConfig::static_names`).
+    pub fn set_static_names(&self, names: StaticNames) {
+        *self.0.static_names.lock().unwrap() = Arc::new(names);
+    }
+
+    /// The locally answered entry for a DNS name, if any.
+    pub fn static_name(&self, name: &LowerName) -> Result<Option<StaticNameMatch>, loga::Error> {
+        let names = self.0.static_names.lock().unwrap().clone();
+        return names.get(name);
+    }
+
+    /// Check the components the resolver depends on: that a DHT lookup gets
+    /// responses, that an upstream DNS server answers (if the DNS bridge forwards
+    /// queries), and that the cache database is writable. Results are reused for a
+    /// few seconds.
+    pub async fn health(&self) -> ResolverHealth {
+        let mut last_health = self.0.last_health.lock().await;
+        if let Some((at, health)) = &*last_health {
+            if at.elapsed() < HEALTH_REUSE {
+                return health.clone();
+            }
+        }
+        let dht = match &self.0.node {
+            Some(node) => match timeout(HEALTH_CHECK_TIMEOUT, node.probe_lookup()).await {
+                Ok(0) => health_component(HealthStatus::Failed, Some("No nodes responded to a lookup".to_string())),
+                Ok(n) => health_component(HealthStatus::Ok, Some(format!("{} nodes responded to a lookup", n))),
+                Err(_) => health_component(HealthStatus::Failed, Some("Lookup timed out".to_string())),
+            },
+            None => health_component(HealthStatus::Disabled, None),
+        };
+        let upstream_probe = self.0.upstream_probe.lock().unwrap().as_ref().and_then(|p| p.upgrade());
+        let upstream_dns = match upstream_probe {
+            Some(probe) => match timeout(HEALTH_CHECK_TIMEOUT, probe.probe_upstreams()).await {
+                Ok(c) => c,
+                Err(_) => health_component(
+                    HealthStatus::Failed,
+                    Some("Timed out waiting for upstream DNS servers".to_string()),
+                ),
+            },
+            None => health_component(HealthStatus::Disabled, None),
+        };
+        let cache_db = match &self.0.db_pool {
+            Some(db_pool) => match timeout(HEALTH_CHECK_TIMEOUT, async {
+                ta_res!(());
+                db_pool.get().await.context("Error getting db connection")?.interact(|db| {
+                    // Fails if the database is read-only
+                    db.execute_batch("begin immediate; rollback")?;
+                    return Ok(()) as Result<_, loga::Error>;
+                }).await??;
+                return Ok(());
+            }).await {
+                Ok(Ok(())) => health_component(HealthStatus::Ok, None),
+                Ok(Err(e)) => health_component(HealthStatus::Failed, Some(e.to_string())),
+                Err(_) => health_component(HealthStatus::Failed, Some("Database check timed out".to_string())),
+            },
+            None => health_component(HealthStatus::Disabled, None),
+        };
+        let health = ResolverHealth {
+            healthy: [&dht, &upstream_dns, &cache_db].iter().all(|c| c.status != HealthStatus::Failed),
+            dht: dht,
+            upstream_dns: upstream_dns,
+            cache_db: cache_db,
+        };
+        *last_health = Some((Instant::now(), health.clone()));
+        return health;
+    }
+
+    /// Sizes and hit/miss counts for the resolver caches.
+    pub fn cache_stats(&self) -> CacheStats {
+        let counters = &self.0.cache_counters;
+        return CacheStats {
+            value_entries: self.0.cache.entry_count(),
+            value_bytes: self.0.cache.weighted_size(),
+            value_max_bytes: self.0.max_cache,
+            value_identities: self.0.cache_usage.identity_count(),
+            identity_cap_evictions: self.0.cache_usage.cap_evictions(),
+            top_identities: self.0.cache_usage.top(10),
+            value_hits: counters.value_hits.load(Ordering::Relaxed),
+            value_misses: counters.value_misses.load(Ordering::Relaxed),
+            announcement_entries: self.0.announcement_cache.entry_count(),
+            announcement_hits: counters.announcement_hits.load(Ordering::Relaxed),
+            announcement_misses: counters.announcement_misses.load(Ordering::Relaxed),
+            coalesced_lookups: counters.coalesced_lookups.load(Ordering::Relaxed),
+            stale_lookups: counters.stale_lookups.load(Ordering::Relaxed),
+            local_lookups: counters.local_lookups.load(Ordering::Relaxed),
+            merge_conflicts: counters.merge_conflicts.load(Ordering::Relaxed),
+            reference_limit_errors: counters.reference_limit_errors.load(Ordering::Relaxed),
+            negative_hits: counters.negative_hits.load(Ordering::Relaxed),
+            missing_identity_entries: self.0.missing_identity_cache.entry_count(),
+            missing_identity_hits: counters.missing_identity_hits.load(Ordering::Relaxed),
+            dht_lookups: counters.dht_lookups.load(Ordering::Relaxed),
+            dht_lookups_active: (self.0.max_dht_lookups - self.0.dht_lookup_permits.available_permits()) as u64,
+            dht_lookups_queued: counters.dht_lookups_queued.load(Ordering::Relaxed),
+            dht_lookups_waiting: counters.dht_lookups_waiting.load(Ordering::Relaxed),
+            dht_lookup_queue_ms: counters.dht_lookup_queue_ms.load(Ordering::Relaxed),
+            dht_lookup_queue_ms_max: counters.dht_lookup_queue_ms_max.load(Ordering::Relaxed),
+            publisher_not_modified: counters.publisher_not_modified.load(Ordering::Relaxed),
+            suspect_reports: counters.suspect_reports.load(Ordering::Relaxed),
+            suspect_changed: counters.suspect_changed.load(Ordering::Relaxed),
+        };
+    }
+
+    /// DHT lookups waiting for a free lookup slot now.
+    pub fn waiting_dht_lookups(&self) -> usize {
+        return self.0.cache_counters.dht_lookups_waiting.load(Ordering::Relaxed) as usize;
+    }
+
+    /// Wait for a free DHT lookup slot, recording how long the wait took.
+    async fn dht_lookup_permit(&self) -> tokio::sync::SemaphorePermit<'_> {
+        let counters = &self.0.cache_counters;
+        counters.dht_lookups.fetch_add(1, Ordering::Relaxed);
+        if let Ok(permit) = self.0.dht_lookup_permits.try_acquire() {
+            return permit;
+        }
+        counters.dht_lookups_queued.fetch_add(1, Ordering::Relaxed);
+        counters.dht_lookups_waiting.fetch_add(1, Ordering::Relaxed);
+        let _waiting = WaitingGuard(&counters.dht_lookups_waiting);
+        let start = Instant::now();
+        let permit = self.0.dht_lookup_permits.acquire().await.unwrap();
+        let waited = start.elapsed().as_millis() as u64;
+        counters.dht_lookup_queue_ms.fetch_add(waited, Ordering::Relaxed);
+        counters.dht_lookup_queue_ms_max.fetch_max(waited, Ordering::Relaxed);
+        return permit;
+    }
+
+    /// List cached values, optionally only for one identity.
+    pub fn cache_entries(&self, identity: Option<&Identity>) -> Vec<CacheEntry> {
+        let mut out = vec![];
+        for (k, v) in self.0.cache.iter() {
+            if let Some(identity) = identity {
+                if &k.0 != identity {
+                    continue;
+                }
+            }
+            out.push(CacheEntry {
+                identity: k.0,
+                key: join_record_key(&k.1),
+                expires: v.0,
+                found: v.1.is_some(),
+            });
+        }
+        out.sort_by_cached_key(|e| (e.identity.to_string(), e.key.clone()));
+        return out;
+    }
+
+    /// Drop cached values and announcements for an identity, or for all identities
+    /// if `identity` is `None`. The next lookup goes to the DHT and publishers.
+    pub async fn purge_cache(&self, identity: Option<&Identity>) {
+        match identity {
+            Some(identity) => {
+                let keys =
+                    self.0.cache.iter().filter(|(k, _)| &k.0 == identity).map(|(k, _)| k).collect::<Vec<_>>();
+                for k in keys {
+                    self.0.cache.invalidate(&*k).await;
+                }
+                self.0.announcement_cache.invalidate(identity).await;
+                self.0.missing_identity_cache.invalidate(identity).await;
+                let keys =
+                    self
+                        .0
+                        .publisher_etags
+                        .iter()
+                        .filter(|(k, _)| &k.1 == identity)
+                        .map(|(k, _)| k)
+                        .collect::<Vec<_>>();
+                for k in keys {
+                    self.0.publisher_etags.invalidate(&*k).await;
+                }
+            },
+            None => {
+                self.0.cache.invalidate_all();
+                self.0.cache_usage.clear();
+                self.0.announcement_cache.invalidate_all();
+                self.0.missing_identity_cache.invalidate_all();
+                self.0.publisher_etags.invalidate_all();
+            },
+        }
+    }
+
+    /// Look up the announcement for an identity, using the announcement caches.
+    async fn get_announcement(&self, ident: &Identity) -> AnnouncementLookup {
+        if let Some(found) = self.0.announcement_cache.get(ident) {
+            self.0.cache_counters.announcement_hits.fetch_add(1, Ordering::Relaxed);
+            return AnnouncementLookup::Found(found);
+        }
+        if let Some(until) = self.0.missing_identity_cache.get(ident) {
+            self.0.cache_counters.missing_identity_hits.fetch_add(1, Ordering::Relaxed);
+            return AnnouncementLookup::Missing {
+                until: until,
+                cached: true,
+            };
+        }
+        self.0.cache_counters.announcement_misses.fetch_add(1, Ordering::Relaxed);
+        let found = match &self.0.node {
+            Some(node) => {
+                let _permit = self.dht_lookup_permit().await;
+                node.get(*ident).await
+            },
+            None => None,
+        };
+        let Some(found) = found else {
+            // Missing announcements are only remembered briefly, so newly announced
+            // identities become resolvable soon
+            let until = Utc::now() + self.0.missing_identity_ttl;
+            if self.0.missing_identity_ttl > Duration::zero() {
+                self.0.missing_identity_cache.insert(*ident, until).await;
+            }
+            return AnnouncementLookup::Missing {
+                until: until,
+                cached: false,
+            };
+        };
+        self.0.announcement_cache.insert(*ident, found.clone()).await;
+        return AnnouncementLookup::Found(found);
+    }
+
     pub async fn get(
         &self,
         ident: &Identity,
         request_keys: Vec<RecordKey>,
     ) -> Result<wire::resolve::v1::ResolveKeyValues, loga::Error> {
+        // Identities hosted by the publisher in this node don't need the cache or DHT
+        if let Some(kvs) = self.get_local(ident, &request_keys).await? {
+            return Ok(kvs);
+        }
+
         // First check cache. Only respond with cache answers if all keys are in cache
         // (will be making a request anyway, might as well get fresh data).
         let now = Utc::now();
@@ -280,9 +1172,10 @@ impl Resolver {
             'missing _;
             let mut kvs = HashMap::new();
             for k in &request_keys {
-                if let Some(found) = self.0.cache.get(&(ident.clone(), k.clone())) {
-                    let (expiry, v) = found;
+                if let Some(found) = self.0.cache.get(&(*ident, k.clone())) {
+                    let (expiry, v, origin) = found;
                     if expiry < now {
+                        self.0.cache_counters.value_misses.fetch_add(1, Ordering::Relaxed);
                         break 'missing;
                     }
                     let v = match v {
@@ -304,13 +1197,23 @@ impl Resolver {
                                 },
                             }
                         },
-                        None => None,
+                        None => {
+                            self.0.cache_counters.negative_hits.fetch_add(1, Ordering::Relaxed);
+                            None
+                        },
                     };
+                    self.0.cache_counters.value_hits.fetch_add(1, Ordering::Relaxed);
                     kvs.insert(k.clone(), wire::resolve::v1::ResolveValue {
                         expires: expiry,
                         data: v,
+                        published: origin.as_ref().and_then(|o| o.published),
+                        provenance: Some(
+                            build_provenance(wire::resolve::v1::ResolveSource::Cache, origin.as_ref(), expiry, now),
+                        ),
+                        external: None,
                     });
                 } else {
+                    self.0.cache_counters.value_misses.fetch_add(1, Ordering::Relaxed);
                     self.0.log.log_with(loga::DEBUG, "Cache miss", ea!(ident = ident, key = k.dbg_str()));
                     break 'missing;
                 }
@@ -318,177 +1221,1035 @@ impl Resolver {
             return Ok(kvs);
         };
 
-        // Not in cache, find publisher via nodes
-        let resp = match self.0.node.get(ident.clone()).await {
-            Some(v) => v,
-            None => {
+        // Not in cache, join an identical in-progress lookup or start a new one
+        let mut inflight_keys = request_keys;
+        inflight_keys.sort();
+        inflight_keys.dedup();
+        let inflight_key = (*ident, inflight_keys);
+        let (f, c) = ManualFuture::new();
+        let start = match self.0.inflight.lock().unwrap().entry(inflight_key.clone()) {
+            Entry::Occupied(mut e) => {
+                self.0.cache_counters.coalesced_lookups.fetch_add(1, Ordering::Relaxed);
+                request_log(&self.0.log).log_with(loga::DEBUG, "Joining in-progress lookup", ea!(ident = ident));
+                e.get_mut().push(c);
+                false
+            },
+            Entry::Vacant(e) => {
+                e.insert(vec![c]);
+                true
+            },
+        };
+        if start {
+            // Run detached so waiters are completed even if the first caller goes away. The
+            // lookup is traced as part of the first caller's request.
+            spawn({
+                let s = self.clone();
+                let trace = current_request_trace();
+                async move {
+                    let res =
+                        with_request_trace(trace, s.fetch(&inflight_key.0, inflight_key.1.clone()))
+                            .await
+                            .map_err(|e| e.to_string());
+                    let waiters = s.0.inflight.lock().unwrap().remove(&inflight_key).unwrap_or_default();
+                    for c in waiters {
+                        c.complete(res.clone()).await;
+                    }
+                }
+            });
+        }
+        return f.await.map_err(|e| loga::err_with("Error resolving values", ea!(err = e)));
+    }
+
+    /// Like `get`, but always looks up the values (unless they're from the local
+    /// publisher) instead of answering from the cache, updating the cache. For
+    /// refreshing values before they expire.
+    pub async fn refresh(
+        &self,
+        ident: &Identity,
+        request_keys: Vec<RecordKey>,
+    ) -> Result<wire::resolve::v1::ResolveKeyValues, loga::Error> {
+        if let Some(kvs) = self.get_local(ident, &request_keys).await? {
+            return Ok(kvs);
+        }
+        return self.fetch(ident, request_keys).await;
+    }
+
+    /// Handle a client's report that values seem wrong (ex: none of the resolved
+    /// addresses accepted a connection): look the identity's announcement and the
+    /// values up again in the background, replacing the cached values. Values that
+    /// didn't change are cached for at most `suspect_ttl`. Reports for an identity
+    /// within a minute of the last accepted one are ignored. Returns whether the
+    /// report was accepted.
+    pub fn report_suspect(
+        &self,
+        ident: &Identity,
+        keys: Vec<RecordKey>,
+        reason: wire::api::resolve::v1::SuspectReason,
+    ) -> bool {
+        let now = Utc::now();
+        {
+            let mut reports = self.0.suspect_reports.lock().unwrap();
+            reports.retain(|_, at| now - *at < suspect_report_cooldown());
+            if reports.contains_key(ident) || reports.len() >= MAX_SUSPECT_REPORTS {
+                return false;
+            }
+            reports.insert(*ident, now);
+        }
+        self.0.cache_counters.suspect_reports.fetch_add(1, Ordering::Relaxed);
+        request_log(
+            &self.0.log,
+        ).log_with(
+            loga::DEBUG,
+            "Looking up values reported as suspect again",
+            ea!(ident = ident, reason = reason.dbg_str(), keys = keys.dbg_str()),
+        );
+        spawn({
+            let s = self.clone();
+            let ident = *ident;
+            async move {
+                let before =
+                    keys.iter().map(|k| s.0.cache.get(&(ident, k.clone())).map(|v| v.1)).collect::<Vec<_>>();
+                s.0.announcement_cache.invalidate(&ident).await;
+                s.0.missing_identity_cache.invalidate(&ident).await;
+                if let Err(e) = s.refresh(&ident, keys.clone()).await {
+                    s
+                        .0
+                        .log
+                        .log_err(
+                            loga::DEBUG,
+                            e.context_with("Error looking up suspect values again", ea!(ident = ident)),
+                        );
+                    return;
+                }
+                let mut changed = false;
+                for (k, before) in keys.into_iter().zip(before) {
+                    let Some((expires, value, origin)) = s.0.cache.get(&(ident, k.clone())) else {
+                        continue;
+                    };
+                    let Some(before) = before else {
+                        continue;
+                    };
+                    if before != value {
+                        changed = true;
+                        continue;
+                    }
+                    let Some(ttl) = s.0.suspect_ttl else {
+                        continue;
+                    };
+                    let until = Utc::now() + ttl;
+                    if expires > until {
+                        cache_store(&s.0.cache, &s.0.cache_usage, ident, k, (until, value, origin)).await;
+                    }
+                }
+                if changed {
+                    s.0.cache_counters.suspect_changed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+        return true;
+    }
+
+    /// Values from the publisher in this node if the fast path is enabled and the
+    /// publisher hosts the identity.
+    async fn get_local(
+        &self,
+        ident: &Identity,
+        request_keys: &[RecordKey],
+    ) -> Result<Option<wire::resolve::v1::ResolveKeyValues>, loga::Error> {
+        if !self.0.local_fast_path {
+            return Ok(None);
+        }
+        let Some(publisher) = &self.0.publisher else {
+            return Ok(None);
+        };
+        let Some(mut kvs) =
+            publisher
+                .get_local_values(ident, request_keys.to_vec())
+                .await
+                .context("Error reading values from local publisher")? else {
+                return Ok(None);
+            };
+        self.0.cache_counters.local_lookups.fetch_add(1, Ordering::Relaxed);
+        let now = Utc::now();
+        for v in kvs.values_mut() {
+            v.provenance = Some(build_provenance(wire::resolve::v1::ResolveSource::Local, None, v.expires, now));
+        }
+        return Ok(Some(kvs));
+    }
+
+    /// Count a lookup that failed due to a reference limit, passing the error through.
+    pub fn reference_limit_exceeded(&self, e: loga::Error) -> loga::Error {
+        self.0.cache_counters.reference_limit_errors.fetch_add(1, Ordering::Relaxed);
+        return e;
+    }
+
+    /// Like `get`, but if the identity has published a valid succession record follow
+    /// it and look up the keys on the successor instead, up to `SUCCESSION_MAX_HOPS`
+    /// times. Returns the identity the values came from. Fails if the successions
+    /// form a cycle. Succession records are cached like any other value.
+    pub async fn get_following_succession(
+        &self,
+        ident: &Identity,
+        request_keys: Vec<RecordKey>,
+    ) -> Result<(Identity, wire::resolve::v1::ResolveKeyValues), loga::Error> {
+        let key_succession = build_succession_key();
+        let keep_succession = request_keys.contains(&key_succession);
+        let mut ident = *ident;
+        let mut chain = ReferenceChain::new(ident);
+        loop {
+            let mut keys = request_keys.clone();
+            if !keep_succession {
+                keys.push(key_succession.clone());
+            }
+            let mut res = self.get(&ident, keys).await?;
+            let succession = if keep_succession {
+                res.get(&key_succession).cloned()
+            } else {
+                res.remove(&key_succession)
+            };
+            let Some(succession) = succession.and_then(|v| v.data) else {
+                return Ok((ident, res));
+            };
+            let successor = match serde_json::from_value::<Succession>(succession) {
+                Ok(s) => s.verify(&ident),
+                Err(e) => Err(e.context("Succession record doesn't match schema")),
+            };
+            let successor = match successor {
+                Ok(s) => s,
+                Err(e) => {
+                    self
+                        .0
+                        .log
+                        .log_err(loga::DEBUG, e.context_with("Ignoring invalid succession record", ea!(ident = ident)));
+                    return Ok((ident, res));
+                },
+            };
+            if chain.depth() >= SUCCESSION_MAX_HOPS {
                 self
                     .0
                     .log
-                    .log_with(loga::DEBUG, "No announcement found, returning empty result", ea!(ident = ident));
-                return Ok(HashMap::new());
+                    .log_with(
+                        loga::DEBUG,
+                        "Succession chain is too long, not following further",
+                        ea!(ident = ident, successor = successor),
+                    );
+                return Ok((ident, res));
+            }
+            chain.follow(successor).map_err(|e| self.reference_limit_exceeded(e))?;
+            ident = successor;
+        }
+    }
+
+    /// Cached values for all of `keys` even if expired, with the expiration replaced by
+    /// `until`. `None` if any key isn't cached.
+    fn get_stale(
+        &self,
+        ident: &Identity,
+        keys: &[RecordKey],
+        until: DateTime<Utc>,
+    ) -> Option<wire::resolve::v1::ResolveKeyValues> {
+        let now = Utc::now();
+        let mut kvs = HashMap::new();
+        for k in keys {
+            let (_, v, origin) = self.0.cache.get(&(*ident, k.clone()))?;
+            let v = match v {
+                Some(v) => Some(serde_json::from_str::<serde_json::Value>(&v).ok()?),
+                None => None,
+            };
+            kvs.insert(k.clone(), wire::resolve::v1::ResolveValue {
+                expires: until,
+                data: v,
+                published: origin.as_ref().and_then(|o| o.published),
+                provenance: Some(
+                    build_provenance(wire::resolve::v1::ResolveSource::StaleCache, origin.as_ref(), until, now),
+                ),
+                external: None,
+            });
+        }
+        return Some(kvs);
+    }
+
+    /// Look up values via the announced publishers, bypassing the value cache (but not
+    /// the announcement cache), and update the cache with the results. Publishers in
+    /// maintenance mode are skipped until their retry time, and if all are in
+    /// maintenance expired cached values are returned instead (but not re-cached).
+    async fn fetch(
+        &self,
+        ident: &Identity,
+        request_keys: Vec<RecordKey>,
+    ) -> Result<wire::resolve::v1::ResolveKeyValues, loga::Error> {
+        #[cfg(feature = "fixtures")]
+        if let Some(fixtures) = &self.0.fixtures {
+            return fixtures.get(ident, &request_keys);
+        }
+
+        // Find publisher via nodes
+        let resp = match self.get_announcement(ident).await {
+            AnnouncementLookup::Found(v) => v,
+            AnnouncementLookup::Missing { until, cached } => {
+                self
+                    .0
+                    .log
+                    .log_with(loga::DEBUG, "No announcement found, returning missing values", ea!(ident = ident));
+                let now = Utc::now();
+                let source = if cached {
+                    wire::resolve::v1::ResolveSource::Cache
+                } else {
+                    wire::resolve::v1::ResolveSource::Fetch
+                };
+                return Ok(request_keys.into_iter().map(|k| (k, wire::resolve::v1::ResolveValue {
+                    expires: until,
+                    data: None,
+                    published: None,
+                    provenance: Some(build_provenance(source, None, until, now)),
+                    external: None,
+                })).collect());
             },
         };
-        let mut publishers;
+        let publishers;
+        let announced;
         match resp {
             stored::announcement::Announcement::V1(a) => {
-                let a = a.parse_unwrap();
+                let a =
+                    a
+                        .verify(ident)
+                        .map_err(
+                            |_| loga::err_with(
+                                "Announcement has a bad signature or malformed content",
+                                ea!(ident = ident),
+                            ),
+                        )?;
                 publishers = a.publishers;
+                announced = a.announced;
             },
         };
-        publishers.shuffle(&mut thread_rng());
-        let mut values = None;
-        let mut errs = vec![];
+        let mut maintenance_until: Option<DateTime<Utc>> = None;
         let resp_max_size = request_keys.len() * 128 * 1024;
-        for publisher in publishers {
-            let log = self.0.log.fork(ea!(publisher = publisher.addr));
-            let log = &log;
-            match async {
-                ta_res!(wire::resolve::v1::ResolveKeyValues);
-
-                // Request values from publisher
-                shed!{
-                    // Check if publisher is us, short circuit network
-                    if !self.0.global_addrs.iter().any(|i| *i == publisher.addr.0.ip()) {
+
+        // Query all publishers at once and merge the responses, so replicated publishers
+        // that disagree still give consistent answers. Each publisher's addresses are
+        // raced (see `fetch_publisher`).
+        let mut pending = FuturesUnordered::new();
+        for group in group_publishers(publishers, &self.0.global_addrs) {
+            let mut backoff_until = None;
+            for publisher in &group {
+                if let Some(until) = self.0.publisher_backoff.get(&publisher.addr.0) {
+                    if until > Utc::now() {
+                        backoff_until = Some(until);
                         break;
                     }
-                    let Some(publisher) = &self.0.publisher else {
-                        break;
-                    };
-                    return Ok(publisher.get_values(&ident, request_keys.clone()).await?);
                 }
-
-                // Request values via publisher over internet
-                let url = Uri::from_str(&format!("https://{}", publisher.addr)).unwrap();
-                let connect = async {
-                    return Ok(
-                        HttpsConnectorBuilder::new()
-                            .with_tls_config(
-                                ClientConfig::builder()
-                                    .dangerous()
-                                    .with_custom_certificate_verifier(SingleKeyVerifier::new(publisher.cert_hash))
-                                    .with_no_client_auth(),
-                            )
-                            .https_only()
-                            .enable_http1()
-                            .build()
-                            .call(url.clone())
-                            .await
-                            .map_err(|e| loga::err_with("Connection failed", ea!(err = e.to_string(), url = url)))?,
-                    );
-                };
-                let mut conn =
-                    Conn::new(
-                        hyper::client::conn::http1::handshake(select!{
-                            _ = sleep(
-                                Duration::try_seconds(10).unwrap().to_std().unwrap()
-                            ) => Err(loga::err("Timeout connecting")),
-                            res = connect => res,
-                        }.context_with("Error connecting to publisher", ea!(url = url))?)
-                            .await
-                            .context("Error completing http handshake")?,
+            }
+            if let Some(until) = backoff_until {
+                self
+                    .0
+                    .log
+                    .log_with(
+                        loga::DEBUG,
+                        "Publisher is in maintenance, skipping",
+                        ea!(publisher = group[0].addr, until = until),
                     );
-                let resp =
-                    htreq::post_json::<wire::resolve::v1::ResolveResp>(
-                        log,
-                        &mut conn,
-                        &url,
-                        &HashMap::new(),
-                        &wire::resolve::ResolveRequest::V1(wire::resolve::v1::ResolveRequest {
-                            ident: ident.clone(),
-                            keys: request_keys.clone(),
-                        }),
-                        resp_max_size,
-                    )
-                        .await
-                        .context("Error getting response from publisher")?
-                        .into_iter()
-                        .collect::<wire::resolve::v1::ResolveKeyValues>();
-                return Ok(resp);
-            }.await {
-                Ok(v) => {
-                    values = Some(v);
-                    break;
+                maintenance_until = Some(maintenance_until.map_or(until, |m| m.min(until)));
+                continue;
+            }
+            pending.push({
+                let request_keys = &request_keys;
+                async move {
+                    let addrs = group.iter().map(|p| p.addr.0).collect::<Vec<_>>();
+                    match self.fetch_publisher(ident, request_keys, &group, resp_max_size).await {
+                        Ok((addr, r)) => {
+                            let log = request_log(&self.0.log).fork(ea!(publisher = addr));
+                            return (log, addrs, addr, Ok(r));
+                        },
+                        Err(e) => {
+                            let log = request_log(&self.0.log).fork(ea!(publisher = addrs.dbg_str()));
+                            return (log, addrs, group[0].addr.0, Err(e));
+                        },
+                    }
+                }
+            });
+        }
+        let mut responses = vec![];
+        let mut errs = vec![];
+        let mut merge_deadline = None;
+        loop {
+            let next = match merge_deadline {
+                Some(deadline) => select!{
+                    n = pending.next() => n,
+                    _ = sleep_until(deadline) => break,
+                },
+                None => pending.next().await,
+            };
+            let Some((log, group_addrs, publisher_addr, res)) = next else {
+                break;
+            };
+            match res {
+                Ok(PublisherResp::Values(v)) => {
+                    responses.push((v, publisher_addr));
+                    if merge_deadline.is_none() {
+                        merge_deadline = Some(Instant::now() + MERGE_WAIT);
+                    }
+                },
+                Ok(PublisherResp::Maintenance(until)) => {
+                    log.log_with(loga::DEBUG, "Publisher is in maintenance, backing off", ea!(until = until));
+                    for addr in group_addrs {
+                        self.0.publisher_backoff.insert(addr, until).await;
+                    }
+                    maintenance_until = Some(maintenance_until.map_or(until, |m| m.min(until)));
                 },
                 Err(e) => {
-                    errs.push(e.stack_context(log, "Error retrieving response from publisher"));
+                    errs.push(e.stack_context(&log, "Error retrieving response from publisher"));
                 },
             }
         }
-        let Some(values) = values else {
+        drop(pending);
+        if responses.is_empty() {
+            if let Some(until) = maintenance_until {
+                // Planned downtime; keep answering with what we had
+                if let Some(stale) = self.get_stale(ident, &request_keys, until) {
+                    self.0.cache_counters.stale_lookups.fetch_add(1, Ordering::Relaxed);
+                    self
+                        .0
+                        .log
+                        .log_with(
+                            loga::DEBUG,
+                            "Publishers in maintenance, responding with stale cached values",
+                            ea!(ident = ident),
+                        );
+                    return Ok(stale);
+                }
+                errs.push(loga::err_with("Publishers are in maintenance", ea!(until = until)));
+            }
             if errs.is_empty() {
                 return Err(loga::err("Publisher announcement listed no publishers"));
             }
             return Err(loga::agg_err("Value lookup failed on all announced publishers", errs));
-        };
+        }
+        let now = Utc::now();
+        let mut values = HashMap::new();
+        let mut cache_values = vec![];
+        for (k, merged) in merge_responses(responses) {
+            if merged.conflicts > 0 {
+                self.0.cache_counters.merge_conflicts.fetch_add(1, Ordering::Relaxed);
+                self
+                    .0
+                    .log
+                    .log_with(
+                        loga::DEBUG,
+                        "Publishers returned different values, using the newest",
+                        ea!(ident = ident, key = k.dbg_str(), conflicts = merged.conflicts),
+                    );
+            }
+            let origin = CacheOrigin {
+                publisher: merged.publisher,
+                announced: announced,
+                published: merged.value.published,
+                conflicts: merged.conflicts,
+            };
+            let mut v = merged.value;
+            if v.data.is_none() {
+                if let Some(max_missing_ttl) = self.0.max_missing_ttl {
+                    v.expires = v.expires.min(now + max_missing_ttl);
+                }
+            }
+            v.provenance =
+                Some(build_provenance(wire::resolve::v1::ResolveSource::Fetch, Some(&origin), v.expires, now));
+            cache_values.push(
+                (k.clone(), (v.expires, v.data.as_ref().map(|v| serde_json::to_string(v).unwrap()), Some(origin))),
+            );
+            values.insert(k, v);
+        }
 
         // Store found values
         spawn({
-            let resp_kvs = values.clone();
             let cache = self.0.cache.clone();
+            let cache_usage = self.0.cache_usage.clone();
             let identity = ident.clone();
             let log = self.0.log.clone();
             let ident = ident.clone();
             async move {
                 let log = &log;
-                for (k, v) in resp_kvs {
+                for (k, v) in cache_values {
                     log.log_with(loga::DEBUG, "Cache store", ea!(ident = ident, key = k.dbg_str()));
-                    cache
-                        .insert(
-                            (identity.clone(), k.to_owned()),
-                            (v.expires, v.data.as_ref().map(|v| serde_json::to_string(v).unwrap())),
-                        )
-                        .await;
+                    cache_store(&cache, &cache_usage, identity, k, v).await;
                 }
             }
         });
         return Ok(values);
     }
+
+    /// Request values from a publisher, which may have multiple addresses (ex: IPv4
+    /// and IPv6). Connections to the addresses are raced Happy Eyeballs style (RFC
+    /// 8305), so a broken path in one address family doesn't hold up the lookup. If
+    /// the request fails after connecting, the remaining addresses are tried the same
+    /// way. Returns the address that responded.
+    async fn fetch_publisher(
+        &self,
+        ident: &Identity,
+        request_keys: &[RecordKey],
+        group: &[AnnouncementPublisher],
+        resp_max_size: usize,
+    ) -> Result<(SocketAddr, PublisherResp), loga::Error> {
+        // Check if publisher is us, short circuit network
+        shed!{
+            let Some(local) = group.iter().find(|p| self.0.global_addrs.iter().any(|i| *i == p.addr.0.ip())) else {
+                break;
+            };
+            let Some(publisher) = &self.0.publisher else {
+                break;
+            };
+            return Ok(
+                (local.addr.0, PublisherResp::Values(publisher.get_values(ident, request_keys.to_vec()).await?)),
+            );
+        }
+        let mut errs = vec![];
+        let mut remaining = vec![];
+        for publisher in group {
+            if !self.0.allow_private_publishers && !publisher_addr_global(publisher.addr.0.ip()) {
+                errs.push(
+                    loga::err_with(
+                        "Publisher address isn't globally routable, not connecting",
+                        ea!(publisher = publisher.addr),
+                    ),
+                );
+                continue;
+            }
+            remaining.push(publisher.addr.0);
+        }
+
+        // Addresses in the family this node can reach best are already first
+        let mut remaining = interleave_families(remaining, |a| a.is_ipv6());
+        while !remaining.is_empty() {
+            let log = request_log(&self.0.log).fork(ea!(publisher = remaining.dbg_str()));
+            let (addr, conn) =
+                match connect_staggered(
+                    &log,
+                    remaining.clone(),
+                    |addr| connect_publisher(addr, group[0].cert_hash.clone()),
+                ).await {
+                    Ok(c) => c,
+                    Err(e) => {
+                        errs.push(e.context("Error connecting to publisher"));
+                        break;
+                    },
+                };
+            remaining.retain(|a| *a != addr);
+            let log = request_log(&self.0.log).fork(ea!(publisher = addr));
+            match self.request_publisher(&log, conn, ident, request_keys, addr, resp_max_size).await {
+                Ok(r) => return Ok((addr, r)),
+                Err(e) => {
+                    log.log(loga::DEBUG, "Request to publisher failed, trying other addresses");
+                    errs.push(e.context_with("Error requesting values from publisher", ea!(publisher = addr)));
+                },
+            }
+        }
+        if errs.len() == 1 {
+            return Err(errs.pop().unwrap());
+        }
+        return Err(loga::agg_err("Requests to all of the publisher's addresses failed", errs));
+    }
+
+    /// Request values over a connection to one of a publisher's addresses.
+    async fn request_publisher(
+        &self,
+        log: &Log,
+        mut conn: Conn,
+        ident: &Identity,
+        request_keys: &[RecordKey],
+        addr: SocketAddr,
+        resp_max_size: usize,
+    ) -> Result<PublisherResp, loga::Error> {
+        let url = Uri::from_str(&format!("https://{}", addr)).unwrap();
+        let req_body = wire::resolve::v1::ResolveRequest {
+            ident: *ident,
+            keys: request_keys.to_vec(),
+        };
+        let req_body = match &self.0.request_signer {
+            Some(signer) => {
+                let (resolver, content) =
+                    wire::api::publish::v1::JsonSignature::sign(
+                        &mut *signer.lock().unwrap(),
+                        wire::resolve::v1::SignedResolveRequestContent {
+                            requested: Utc::now(),
+                            request: req_body,
+                        },
+                    ).context("Error signing request to publisher")?;
+                wire::resolve::ResolveRequest::V1Signed(wire::resolve::v1::SignedResolveRequest {
+                    resolver: resolver,
+                    content: content,
+                })
+            },
+            None => wire::resolve::ResolveRequest::V1(req_body),
+        };
+        let etag_key = (addr, *ident, {
+            let mut keys = request_keys.to_vec();
+            keys.sort();
+            keys
+        });
+        let prev = self.0.publisher_etags.get(&etag_key);
+        let mut req =
+            Request::builder()
+                .method(Method::POST)
+                .uri(url.clone())
+                .header(CONTENT_TYPE, "application/json");
+        if let Some(prev) = &prev {
+            req = req.header(IF_NONE_MATCH, prev.etag.clone());
+        }
+        for (k, v) in propagate_trace_headers() {
+            req = req.header(k, v);
+        }
+        let req = req.body(Full::new(Bytes::from(serde_json::to_vec(&req_body).unwrap()))).unwrap();
+        let (status, headers, continue_send) =
+            htreq::send(log, &mut conn, Duration::try_seconds(30).unwrap().to_std().unwrap(), req)
+                .await
+                .context("Error getting response from publisher")?;
+        if status == StatusCode::SERVICE_UNAVAILABLE && headers.contains_key(RETRY_AFTER) {
+            return Ok(PublisherResp::Maintenance(Utc::now() + parse_retry_after(&headers)));
+        }
+        if status == StatusCode::NOT_MODIFIED {
+            let Some(prev) = prev else {
+                return Err(loga::err("Publisher responded not modified to an unconditional request"));
+            };
+            self.0.cache_counters.publisher_not_modified.fetch_add(1, Ordering::Relaxed);
+
+            // Same values as last time, with expirations moved forward by the time since
+            let mut resp =
+                serde_json::from_slice::<wire::resolve::v1::ResolveResp>(&prev.body)
+                    .context("Previous publisher response doesn't match schema")?
+                    .into_iter()
+                    .collect::<wire::resolve::v1::ResolveKeyValues>();
+            let elapsed = Utc::now() - prev.received;
+            for v in resp.values_mut() {
+                v.expires += elapsed;
+            }
+            return Ok(PublisherResp::Values(resp));
+        }
+        let mut body = LimitedBody {
+            data: vec![],
+            max: resp_max_size,
+        };
+        htreq::receive_stream(continue_send, &mut body)
+            .await
+            .context("Error reading response from publisher")?;
+        if !status.is_success() {
+            return Err(
+                loga::err_with(
+                    "Publisher responded with error",
+                    ea!(status = status, body = String::from_utf8_lossy(&body.data)),
+                ),
+            );
+        }
+        let resp =
+            serde_json::from_slice::<wire::resolve::v1::ResolveResp>(&body.data)
+                .context("Publisher response doesn't match schema")?
+                .into_iter()
+                .collect::<wire::resolve::v1::ResolveKeyValues>();
+        if let Some(etag) = headers.get(ETAG) {
+            self.0.publisher_etags.insert(etag_key, PublisherEtag {
+                etag: etag.clone(),
+                received: Utc::now(),
+                body: Arc::new(body.data),
+            }).await;
+        }
+        return Ok(PublisherResp::Values(resp));
+    }
+}
+
+/// Open a connection to a publisher address, accepting only the publisher's
+/// announced cert.
+async fn connect_publisher(addr: SocketAddr, cert_hash: Blob) -> Result<Conn, loga::Error> {
+    let url = Uri::from_str(&format!("https://{}", addr)).unwrap();
+    let stream =
+        HttpsConnectorBuilder::new()
+            .with_tls_config(
+                ClientConfig::builder()
+                    .dangerous()
+                    .with_custom_certificate_verifier(SingleKeyVerifier::new(cert_hash))
+                    .with_no_client_auth(),
+            )
+            .https_only()
+            .enable_http1()
+            .build()
+            .call(url.clone())
+            .await
+            .map_err(|e| loga::err_with("Connection failed", ea!(err = e.to_string(), url = url)))?;
+    return Ok(
+        Conn::new(hyper::client::conn::http1::handshake(stream).await.context("Error completing http handshake")?),
+    );
 }
 
 pub const API_ROUTE_RESOLVE: &str = "resolve";
 
+/// Request header; if present, lookup responses include value provenance.
+pub const HEADER_PROVENANCE: HeaderName = HeaderName::from_static("x-spagh-provenance");
+
+/// Request header; if present, lookup responses are wrapped in a
+/// `SignedResolveResp` signed by the resolver. Requests fail if the resolver
+/// isn't configured to sign responses.
+pub const HEADER_SIGN: HeaderName = HeaderName::from_static("x-spagh-sign");
+
+fn strip_provenance(kvs: &mut wire::resolve::v1::ResolveKeyValues) {
+    for v in kvs.values_mut() {
+        v.provenance = None;
+    }
+}
+
 /// Launch a publisher into the task manager and return the API endpoints for
-/// attaching to the user-facing HTTP servers.
-pub fn build_api_endpoints(log: Log, resolver: &Resolver) -> htserve::handler::PathRouter<htserve::responses::Body> {
+/// attaching to the user-facing HTTP servers. If `jsonrpc` is set, lookups are
+/// also available as the JSON-RPC method `resolve` at `/jsonrpc`. DNS lookups of
+/// `.s` names in the `application/dns-json` format are at `/dns-query`. The
+/// resolver health check is at `/health`.
+///
+/// If `response_signer` is set, lookups requested with `HEADER_SIGN` are signed
+/// with that identity.
+///
+/// If `external_names` is set, lookup responses include the addresses of external
+/// DNS names referenced in the values.
+pub fn build_api_endpoints(
+    log: Log,
+    resolver: &Resolver,
+    jsonrpc: bool,
+    response_signer: Option<Arc<Mutex<dyn IdentitySigner>>>,
+    external_names: Option<Arc<ExternalNames>>,
+) -> htserve::handler::PathRouter<htserve::responses::Body> {
     struct Inner {
         resolver: Resolver,
         log: Log,
+        response_signer: Option<Arc<Mutex<dyn IdentitySigner>>>,
+        external_names: Option<Arc<ExternalNames>>,
     }
 
     let state = Arc::new(Inner {
         resolver: resolver.clone(),
         log: log,
+        response_signer: response_signer,
+        external_names: external_names,
     });
     let mut r = htserve::handler::PathRouter::default();
     r.insert("/v1", Box::new(htwrap::handler!((state: Arc < Inner >)(args -> htserve:: responses:: Body) {
+        let sign = args.head.headers.contains_key(&HEADER_SIGN);
         match async {
-            ta_vis_res!(wire::api::resolve::v1::ResolveResp);
+            ta_vis_res!((Identity, Vec < RecordKey >, wire::api::resolve::v1::ResolveResp));
+            if sign && state.response_signer.is_none() {
+                return Err(loga::err("This resolver isn't configured to sign responses")).err_external();
+            }
             let ident_src =
                 args.subpath.strip_prefix("/").context("Missing identity final path element").err_external()?;
-            let keys = split_query_record_keys(&args.query);
-            let kvs =
-                state
-                    .resolver
-                    .get(
-                        &Identity::from_str(&ident_src)
-                            .context_with("Failed to parse identity", ea!(identity = ident_src))
-                            .err_external()?,
-                        keys,
-                    )
-                    .await
-                    .err_internal()?;
-            return Ok(kvs.into_iter().collect::<Vec<_>>());
+            let identity =
+                Identity::from_str(ident_src)
+                    .context_with("Failed to parse identity", ea!(identity = ident_src))
+                    .err_external()?;
+            let keys = split_query_record_keys(args.query);
+            let mut kvs = state.resolver.get(&identity, keys.clone()).await.err_internal()?;
+            if !args.head.headers.contains_key(&HEADER_PROVENANCE) {
+                strip_provenance(&mut kvs);
+            }
+            if let Some(external_names) = &state.external_names {
+                external_names.add_external(&mut kvs).await;
+            }
+            return Ok((identity, keys, kvs.into_iter().collect::<Vec<_>>()));
+        }.await {
+            Ok((identity, keys, values)) => {
+                let Some(signer) = state.response_signer.as_ref().filter(|_| sign) else {
+                    return response_200_json(values);
+                };
+                match wire::api::publish::v1::JsonSignature::sign(
+                    &mut *signer.lock().unwrap(),
+                    SignedResolveRespContent {
+                        signed: Utc::now(),
+                        identity: identity,
+                        keys: keys,
+                        values: values,
+                    },
+                ) {
+                    Ok((resolver, content)) => return response_200_json(SignedResolveResp {
+                        resolver: resolver,
+                        content: content,
+                    }),
+                    Err(e) => {
+                        log_warn_err(&state.log, e.context("Error signing response"));
+                        return response_internal();
+                    },
+                }
+            },
+            Err(VisErr::External(e)) => {
+                return response_bad_request(e);
+            },
+            Err(VisErr::Internal(e)) => {
+                log_warn_err(&state.log, e.context("Error responding to query"));
+                return response_internal();
+            },
+        }
+    }))).unwrap();
+    r.insert("/dns-query", Box::new(htwrap::handler!((state: Arc < Inner >)(args -> htserve:: responses:: Body) {
+        match async {
+            ta_vis_res!(wire::api::resolve::v1::DnsJsonResponse);
+
+            #[derive(Deserialize)]
+            struct Params {
+                name: String,
+                #[serde(rename = "type")]
+                type_: Option<String>,
+            }
+
+            let query =
+                serde_urlencoded::from_str::<Params>(args.query).context("Invalid query parameters").err_external()?;
+            return dns::resolve_dns_json(&state.log, &state.resolver, &query.name, query.type_.as_deref()).await;
+        }.await {
+            Ok(r) => {
+                let mut resp = response_200_json(r);
+                resp.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/dns-json"));
+                return resp;
+            },
+            Err(VisErr::External(e)) => {
+                return response_bad_request(e);
+            },
+            Err(VisErr::Internal(e)) => {
+                log_warn_err(&state.log, e.context("Error responding to DNS JSON query"));
+                return response_internal();
+            },
+        }
+    }))).unwrap();
+    r.insert("/suspect", Box::new(htwrap::handler!((state: Arc < Inner >)(args -> htserve:: responses:: Body) {
+        match async {
+            ta_vis_res!(wire::api::resolve::v1::SuspectReportResponse);
+            if args.head.method != Method::POST {
+                return Err(loga::err("Suspect reports must be POST requests")).err_external();
+            }
+            let body = args.body.collect().await.context("Error reading body").err_external()?.to_bytes();
+            let report =
+                serde_json::from_slice::<wire::api::resolve::v1::SuspectReport>(&body)
+                    .context("Bad request body")
+                    .err_external()?;
+            if report.keys.len() > MAX_SUSPECT_KEYS {
+                return Err(
+                    loga::err_with("Too many keys in suspect report", ea!(max = MAX_SUSPECT_KEYS)),
+                ).err_external();
+            }
+            return Ok(wire::api::resolve::v1::SuspectReportResponse {
+                revalidating: state.resolver.report_suspect(&report.identity, report.keys, report.reason),
+            });
         }.await {
-            Ok(r) => response_200_json(r),
+            Ok(r) => {
+                return response_200_json(r);
+            },
             Err(VisErr::External(e)) => {
-                return response_400(e);
+                return response_bad_request(e);
             },
             Err(VisErr::Internal(e)) => {
-                state.log.log_err(loga::WARN, e.context("Error responding to query"));
-                return response_503();
+                log_warn_err(&state.log, e.context("Error handling suspect report"));
+                return response_internal();
             },
         }
     }))).unwrap();
+    r.insert("/health", Box::new(htwrap::handler!((state: Arc < Inner >)(_args -> htserve:: responses:: Body) {
+        let health = state.resolver.health().await;
+        let healthy = health.healthy;
+        let mut resp = response_200_json(health);
+        if !healthy {
+            *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        }
+        return resp;
+    }))).unwrap();
+    if jsonrpc {
+        r.insert("/jsonrpc", Box::new(htwrap::handler!((state: Arc < Inner >)(args -> htserve:: responses:: Body) {
+            let body = match args.body.collect().await {
+                Ok(b) => b.to_bytes(),
+                Err(e) => {
+                    return response_bad_request(format!("Error reading body: {}", e));
+                },
+            };
+            return jsonrpc::handle(&state.log, &body, |method, params| async move {
+                match method.as_str() {
+                    "resolve" => {
+                        #[derive(Deserialize)]
+                        struct Params {
+                            identity: Identity,
+                            keys: Vec<RecordKey>,
+                            #[serde(default)]
+                            provenance: bool,
+                        }
+
+                        let req = jsonrpc::params::<Params>(params)?;
+                        let mut kvs = state.resolver.get(&req.identity, req.keys).await?;
+                        if !req.provenance {
+                            strip_provenance(&mut kvs);
+                        }
+                        if let Some(external_names) = &state.external_names {
+                            external_names.add_external(&mut kvs).await;
+                        }
+                        return jsonrpc::result(kvs.into_iter().collect::<wire::api::resolve::v1::ResolveResp>());
+                    },
+                    _ => return Err(jsonrpc::RpcError::MethodNotFound),
+                }
+            }).await;
+        }))).unwrap();
+    }
     return r;
 }
+
+#[cfg(test)]
+mod test_retry_after {
+    use {
+        super::parse_retry_after,
+        crate::interface::wire,
+        chrono::Duration,
+        http::{
+            header::RETRY_AFTER,
+            HeaderMap,
+            HeaderValue,
+        },
+    };
+
+    #[test]
+    fn test_seconds() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+        assert_eq!(parse_retry_after(&headers), Duration::try_seconds(120).unwrap());
+    }
+
+    #[test]
+    fn test_capped() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("999999"));
+        assert_eq!(
+            parse_retry_after(&headers),
+            Duration::try_seconds(wire::resolve::MAX_RETRY_AFTER_SECS as i64).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_unparseable() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(
+            parse_retry_after(&headers),
+            Duration::try_seconds(wire::resolve::DEFAULT_RETRY_AFTER_SECS as i64).unwrap()
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_publisher_addr_global {
+    use {
+        super::publisher_addr_global,
+        std::{
+            net::IpAddr,
+            str::FromStr,
+        },
+    };
+
+    #[test]
+    fn test_rejected() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "192.168.0.1",
+            "169.254.169.254",
+            "::1",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!publisher_addr_global(IpAddr::from_str(ip).unwrap()), "{}", ip);
+        }
+    }
+
+    #[test]
+    fn test_allowed() {
+        for ip in ["1.1.1.1", "2606:4700:4700::1111", "::ffff:1.1.1.1"] {
+            assert!(publisher_addr_global(IpAddr::from_str(ip).unwrap()), "{}", ip);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_group_publishers {
+    use {
+        super::group_publishers,
+        crate::interface::stored::{
+            announcement::latest::AnnouncementPublisher,
+            shared::SerialAddr,
+        },
+    };
+
+    #[test]
+    fn test_group() {
+        let p = |addr: &str, cert: u8| AnnouncementPublisher {
+            addr: SerialAddr(addr.parse().unwrap()),
+            cert_hash: vec![cert].into(),
+        };
+        let groups =
+            group_publishers(
+                vec![p("192.0.2.1:48391", 1), p("192.0.2.2:48391", 2), p("[2001:db8::1]:48391", 1)],
+                &["2001:db8::10".parse().unwrap()],
+            );
+        assert_eq!(groups.len(), 2);
+
+        // Same family as this node first
+        assert_eq!(
+            groups[0].iter().map(|p| p.addr.0).collect::<Vec<_>>(),
+            vec!["[2001:db8::1]:48391".parse().unwrap(), "192.0.2.1:48391".parse().unwrap()]
+        );
+        assert_eq!(groups[1].len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod test_merge {
+    use {
+        super::merge_responses,
+        crate::interface::{
+            stored::record::record_utils::RecordKey,
+            wire::resolve::v1::ResolveValue,
+        },
+        chrono::{
+            TimeZone,
+            Utc,
+        },
+        std::{
+            collections::HashMap,
+            net::SocketAddr,
+        },
+    };
+
+    fn resp(
+        publisher: &str,
+        data: &str,
+        published: Option<i64>,
+    ) -> (HashMap<RecordKey, ResolveValue>, SocketAddr) {
+        let mut values = HashMap::new();
+        values.insert(vec!["k".to_string()], ResolveValue {
+            expires: Utc.timestamp_opt(100, 0).unwrap(),
+            data: Some(serde_json::Value::String(data.to_string())),
+            published: published.map(|p| Utc.timestamp_opt(p, 0).unwrap()),
+            provenance: None,
+            external: None,
+        });
+        return (values, publisher.parse().unwrap());
+    }
+
+    #[test]
+    fn test_newest() {
+        let merged = merge_responses(vec![resp("1.1.1.1:443", "old", Some(1)), resp("2.2.2.2:443", "new", Some(2))]);
+        let merged = merged.get(&vec!["k".to_string()]).unwrap();
+        assert_eq!(merged.value.data, Some(serde_json::Value::String("new".to_string())));
+        assert_eq!(merged.conflicts, 1);
+    }
+
+    #[test]
+    fn test_order_independent() {
+        let a = resp("1.1.1.1:443", "a", None);
+        let b = resp("2.2.2.2:443", "b", None);
+        let ab = merge_responses(vec![a.clone(), b.clone()]);
+        let ba = merge_responses(vec![b, a]);
+        let k = vec!["k".to_string()];
+        assert_eq!(ab.get(&k).unwrap().value.data, ba.get(&k).unwrap().value.data);
+    }
+
+    #[test]
+    fn test_agree() {
+        let merged = merge_responses(vec![resp("1.1.1.1:443", "a", Some(1)), resp("2.2.2.2:443", "a", Some(1))]);
+        assert_eq!(merged.get(&vec!["k".to_string()]).unwrap().conflicts, 0);
+    }
+}
@@ -0,0 +1,254 @@
+//! Names answered locally from a file (`ResolverConfig::static_names`) before
+//! looking anything up, for friendly names in labs and internal deployments. The
+//! file is polled and reloaded when its modification time changes.
+use {
+    super::Resolver,
+    crate::{
+        cap_fn,
+        interface::{
+            config::node::resolver_config::StaticName,
+            stored::{
+                identity::Identity,
+                record::record_utils::{
+                    domain_part_raw_to_string,
+                    RecordKey,
+                },
+            },
+        },
+        ta_res,
+        utils::{
+            fs_util,
+            recent_errors::log_warn_err,
+            task_status::TrackedTasks,
+        },
+    },
+    hickory_proto::rr::LowerName,
+    hickory_resolver::Name,
+    loga::{
+        ea,
+        Log,
+        ResultContext,
+    },
+    std::{
+        collections::HashMap,
+        net::{
+            Ipv4Addr,
+            Ipv6Addr,
+        },
+        path::{
+            Path,
+            PathBuf,
+        },
+        str::FromStr,
+        sync::{
+            Arc,
+            Mutex,
+        },
+        time::SystemTime,
+    },
+    taskmanager::TaskManager,
+};
+
+/// How a query name matched the static names.
+#[derive(Debug, PartialEq, Eq)]
+pub enum StaticNameMatch {
+    /// Answer from the identity's records at this path.
+    Identity {
+        ident: Identity,
+        path: RecordKey,
+    },
+    /// Answer `A` and `AAAA` queries with these addresses.
+    Addresses {
+        ipv4: Vec<Ipv4Addr>,
+        ipv6: Vec<Ipv6Addr>,
+    },
+}
+
+enum StaticTarget {
+    Identity(Identity),
+    Addresses {
+        ipv4: Vec<Ipv4Addr>,
+        ipv6: Vec<Ipv6Addr>,
+    },
+}
+
+#[derive(Default)]
+pub struct StaticNames(HashMap<LowerName, StaticTarget>);
+
+impl StaticNames {
+    pub fn from_json(data: &[u8]) -> Result<StaticNames, loga::Error> {
+        let raw =
+            serde_json::from_slice::<HashMap<String, StaticName>>(data).context("Static names don't match schema")?;
+        let mut out = StaticNames::default();
+        for (name, target) in raw {
+            let mut dns_name =
+                Name::from_utf8(&name).context_with("Static name isn't a valid DNS name", ea!(name = name))?;
+            dns_name.set_fqdn(true);
+            if dns_name.is_root() {
+                return Err(loga::err("Static names can't include the root"));
+            }
+            out.0.insert(LowerName::from(dns_name), match target {
+                StaticName::Identity(ident) => StaticTarget::Identity(
+                    Identity::from_str(
+                        &ident,
+                    ).context_with("Invalid identity for static name", ea!(name = name, identity = ident))?,
+                ),
+                StaticName::Addresses { ipv4, ipv6 } => StaticTarget::Addresses {
+                    ipv4: ipv4,
+                    ipv6: ipv6,
+                },
+            });
+        }
+        return Ok(out);
+    }
+
+    pub async fn load(path: &Path) -> Result<StaticNames, loga::Error> {
+        return StaticNames::from_json(
+            &fs_util::read(path).await?,
+        ).context_with("Error loading static names", ea!(path = path.to_string_lossy()));
+    }
+
+    pub fn count(&self) -> usize {
+        return self.0.len();
+    }
+
+    /// Find the most specific entry for the name. Addresses only match the name
+    /// itself, identity aliases also match names under them.
+    pub fn get(&self, name: &LowerName) -> Result<Option<StaticNameMatch>, loga::Error> {
+        let name = Name::from(name);
+        let mut at = name.clone();
+        loop {
+            match self.0.get(&LowerName::from(&at)) {
+                Some(StaticTarget::Identity(ident)) => {
+                    let mut path = vec![];
+                    for part in name.iter().take((name.num_labels() - at.num_labels()) as usize) {
+                        let part =
+                            String::from_utf8(
+                                part.to_vec(),
+                            ).context_with(
+                                "DNS name part isn't valid utf-8",
+                                ea!(part = String::from_utf8_lossy(part)),
+                            )?;
+                        path.push(
+                            domain_part_raw_to_string(
+                                &part,
+                            ).context_with("DNS name part isn't valid", ea!(full = name))?,
+                        );
+                    }
+                    path.reverse();
+                    return Ok(Some(StaticNameMatch::Identity {
+                        ident: *ident,
+                        path: path,
+                    }));
+                },
+                Some(StaticTarget::Addresses { ipv4, ipv6 }) if at == name => {
+                    return Ok(Some(StaticNameMatch::Addresses {
+                        ipv4: ipv4.clone(),
+                        ipv6: ipv6.clone(),
+                    }));
+                },
+                _ => { },
+            }
+            if at.is_root() {
+                return Ok(None);
+            }
+            at = at.base_name();
+        }
+    }
+}
+
+async fn modified(path: &Path) -> Result<SystemTime, loga::Error> {
+    return tokio::fs::metadata(path)
+        .await
+        .and_then(|m| m.modified())
+        .context_with("Error reading static names file modification time", ea!(path = path.to_string_lossy()));
+}
+
+/// Load the static names into the resolver, then check the file periodically and
+/// reload it if it changed. Failing to load at startup is an error; later failures
+/// are logged and the previous names are kept.
+pub async fn watch_static_names(
+    log: &Log,
+    tm: &TaskManager,
+    resolver: &Resolver,
+    path: PathBuf,
+) -> Result<(), loga::Error> {
+    let last_modified = Arc::new(Mutex::new(modified(&path).await?));
+    resolver.set_static_names(StaticNames::load(&path).await?);
+    let log = log.fork(ea!(subsys = "static_names"));
+    let resolver = resolver.clone();
+    tm.tracked_periodic(
+        "Resolver - static names reload",
+        std::time::Duration::from_secs(10),
+        cap_fn!(()(log, resolver, path, last_modified) {
+            match async {
+                ta_res!(());
+                let m = modified(&path).await?;
+                {
+                    let mut last_modified = last_modified.lock().unwrap();
+                    if *last_modified == m {
+                        return Ok(());
+                    }
+
+                    // Only warn once per change if the new file is invalid
+                    *last_modified = m;
+                }
+                let names = StaticNames::load(&path).await?;
+                log.log_with(loga::INFO, "Reloaded static names", ea!(count = names.count()));
+                resolver.set_static_names(names);
+                return Ok(());
+            }.await {
+                Ok(_) => { },
+                Err(e) => {
+                    log_warn_err(&log, e.context("Error reloading static names, keeping previous names"));
+                },
+            }
+        }),
+    );
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test_static_names {
+    use {
+        super::{
+            StaticNameMatch,
+            StaticNames,
+        },
+        crate::interface::config::identity::LocalIdentitySecret,
+        hickory_proto::rr::LowerName,
+        serde_json::json,
+        std::str::FromStr,
+    };
+
+    #[test]
+    fn test_get() {
+        let (identity, _) = LocalIdentitySecret::new();
+        let names = StaticNames::from_json(serde_json::to_string(&json!({
+            "lab.internal": {
+                "identity": identity.to_string()
+            },
+            "nas.lab.internal": {
+                "addresses": {
+                    "ipv4": ["192.0.2.1"]
+                }
+            }
+        })).unwrap().as_bytes()).unwrap();
+        let get = |n: &str| names.get(&LowerName::from_str(n).unwrap()).unwrap();
+        assert_eq!(get("WWW.a.Lab.Internal."), Some(StaticNameMatch::Identity {
+            ident: identity,
+            path: vec!["a".to_string(), "www".to_string()],
+        }));
+        assert_eq!(get("nas.lab.internal."), Some(StaticNameMatch::Addresses {
+            ipv4: vec!["192.0.2.1".parse().unwrap()],
+            ipv6: vec![],
+        }));
+
+        // Addresses only apply to the exact name
+        assert_eq!(get("x.nas.lab.internal."), Some(StaticNameMatch::Identity {
+            ident: identity,
+            path: vec!["nas".to_string(), "x".to_string()],
+        }));
+        assert_eq!(get("other.internal."), None);
+    }
+}
@@ -0,0 +1,196 @@
+//! Keeping the values of a configured set of identities in the resolver cache
+//! (`ResolverConfig::warm_cache`), looking them up at startup and again shortly
+//! before they expire.
+use {
+    super::Resolver,
+    crate::{
+        interface::{
+            config::node::resolver_config::WarmCacheConfig,
+            stored::{
+                identity::Identity,
+                record::{
+                    dns_record::{
+                        build_dns_key,
+                        RecordType,
+                    },
+                    record_utils::{
+                        split_record_key,
+                        RecordKey,
+                    },
+                },
+            },
+        },
+        utils::{
+            fs_util,
+            task_status::TrackedTasks,
+        },
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    futures::future::join_all,
+    loga::{
+        ea,
+        Log,
+        ResultContext,
+    },
+    std::{
+        str::FromStr,
+        time::Duration,
+    },
+    taskmanager::TaskManager,
+    tokio::{
+        select,
+        time::sleep,
+    },
+};
+
+// How long before the earliest value expires to refresh
+const REFRESH_MARGIN: Duration = Duration::from_secs(30);
+const MIN_REFRESH: Duration = Duration::from_secs(30);
+
+// Also refresh at least this often, in case values were evicted from the cache
+const MAX_REFRESH: Duration = Duration::from_secs(60 * 60);
+const ERROR_RETRY: Duration = Duration::from_secs(60);
+
+/// Parse an identity file: one identity per line, ignoring blank lines and lines
+/// starting with `#`.
+fn parse_identities(text: &str) -> Result<Vec<Identity>, loga::Error> {
+    let mut out = vec![];
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        out.push(Identity::from_str(line).context_with("Invalid identity", ea!(line = line))?);
+    }
+    return Ok(out);
+}
+
+/// How long to wait before refreshing values with these expiration times.
+fn refresh_delay(now: DateTime<Utc>, expires: impl Iterator<Item = DateTime<Utc>>) -> Duration {
+    let Some(earliest) = expires.min() else {
+        return MAX_REFRESH;
+    };
+    return (earliest - now)
+        .to_std()
+        .unwrap_or_default()
+        .saturating_sub(REFRESH_MARGIN)
+        .clamp(MIN_REFRESH, MAX_REFRESH);
+}
+
+async fn keep_warm(log: Log, tm: TaskManager, resolver: Resolver, ident: Identity, keys: Vec<RecordKey>) {
+    let mut first = true;
+    loop {
+        // The first lookup can use values persisted from before the restart
+        let res = if first {
+            resolver.get(&ident, keys.clone()).await
+        } else {
+            resolver.refresh(&ident, keys.clone()).await
+        };
+        first = false;
+        let delay = match res {
+            Ok(kvs) => refresh_delay(Utc::now(), kvs.values().map(|v| v.expires)),
+            Err(e) => {
+                log.log_err(loga::DEBUG, e.context_with("Error looking up identity to keep warm", ea!(ident = ident)));
+                ERROR_RETRY
+            },
+        };
+        select!{
+            _ = tm.until_terminate() => return,
+            _ = sleep(delay) => { },
+        }
+    }
+}
+
+pub async fn start_warm_cache(
+    log: &Log,
+    tm: &TaskManager,
+    resolver: &Resolver,
+    config: WarmCacheConfig,
+) -> Result<(), loga::Error> {
+    let mut identities = vec![];
+    for ident in &config.identities {
+        identities.push(
+            Identity::from_str(ident).context_with("Invalid identity to keep warm", ea!(identity = ident))?,
+        );
+    }
+    if let Some(path) = &config.file {
+        let text =
+            String::from_utf8(
+                fs_util::read(path).await?,
+            ).context_with("Warm cache identity file isn't valid utf-8", ea!(path = path.to_string_lossy()))?;
+        identities.extend(
+            parse_identities(
+                &text,
+            ).context_with("Error reading warm cache identity file", ea!(path = path.to_string_lossy()))?,
+        );
+    }
+    let mut unique = vec![];
+    for ident in identities {
+        if !unique.contains(&ident) {
+            unique.push(ident);
+        }
+    }
+    let keys = match &config.keys {
+        Some(keys) => keys.iter().map(|k| split_record_key(k)).collect::<Vec<_>>(),
+        None => [RecordType::A, RecordType::Aaaa, RecordType::Txt]
+            .into_iter()
+            .map(|t| build_dns_key(vec![], t))
+            .collect(),
+    };
+    let log = log.fork(ea!(subsys = "warm_cache"));
+    log.log_with(loga::INFO, "Keeping identities warm in the cache", ea!(count = unique.len()));
+    tm.tracked_task("Resolver - warm cache", {
+        let tm = tm.clone();
+        let resolver = resolver.clone();
+        async move {
+            join_all(
+                unique
+                    .into_iter()
+                    .map(|ident| keep_warm(log.clone(), tm.clone(), resolver.clone(), ident, keys.clone())),
+            ).await;
+        }
+    });
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test_warm_cache {
+    use {
+        super::{
+            parse_identities,
+            refresh_delay,
+            MAX_REFRESH,
+            MIN_REFRESH,
+        },
+        crate::interface::config::identity::LocalIdentitySecret,
+        chrono::{
+            Duration,
+            Utc,
+        },
+    };
+
+    #[test]
+    fn test_parse_identities() {
+        let (ident, _) = LocalIdentitySecret::new();
+        assert_eq!(parse_identities(&format!("# popular\n\n  {}  \n", ident)).unwrap(), vec![ident]);
+        assert!(parse_identities("notanidentity").is_err());
+    }
+
+    #[test]
+    fn test_refresh_delay() {
+        let now = Utc::now();
+        assert_eq!(
+            refresh_delay(
+                now,
+                [now + Duration::try_minutes(10).unwrap(), now + Duration::try_minutes(5).unwrap()].into_iter(),
+            ),
+            std::time::Duration::from_secs(270)
+        );
+        assert_eq!(refresh_delay(now, [now - Duration::try_minutes(1).unwrap()].into_iter()), MIN_REFRESH);
+        assert_eq!(refresh_delay(now, [now + Duration::try_days(1).unwrap()].into_iter()), MAX_REFRESH);
+        assert_eq!(refresh_delay(now, [].into_iter()), MAX_REFRESH);
+    }
+}
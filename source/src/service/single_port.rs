@@ -0,0 +1,289 @@
+//! Serving the API, DNS-over-HTTPS, and content on one HTTPS listener
+//! (`Config::single_port`), for container platforms that only expose one port.
+//! Requests are routed by host name (TLS SNI, or the `Host` header) and path.
+use {
+    super::{
+        content::ContentSite,
+        publisher::API_ROUTE_PUBLISH,
+        resolver::API_ROUTE_RESOLVE,
+    },
+    crate::{
+        cap_fn,
+        interface::wire::api::openapi::API_ROUTE_OPENAPI,
+        utils::{
+            api_error::response_not_found,
+            graceful::{
+                drain_conn,
+                serve_draining,
+                ShutdownSignal,
+            },
+            tls_util::{
+                cert_der_identity,
+                ClientCertIdentity,
+                IdentityClientCertVerifier,
+            },
+        },
+    },
+    http::{
+        uri::Authority,
+        Request,
+        Response,
+    },
+    http_body_util::{
+        combinators::UnsyncBoxBody,
+        BodyExt,
+    },
+    htwrap::htserve::{
+        self,
+        handler::{
+            Handler,
+            HandlerArgs,
+        },
+    },
+    hyper::body::{
+        Bytes,
+        Incoming,
+    },
+    hyper_util::rt::{
+        TokioExecutor,
+        TokioIo,
+    },
+    loga::{
+        ea,
+        ErrContext,
+        Log,
+        ResultContext,
+    },
+    rustls::{
+        server::ResolvesServerCert,
+        ServerConfig,
+    },
+    std::{
+        convert::Infallible,
+        net::SocketAddr,
+        str::FromStr,
+        sync::Arc,
+        time::Duration,
+    },
+    taskmanager::TaskManager,
+    tokio::net::{
+        TcpListener,
+        TcpStream,
+    },
+    tokio_rustls::TlsAcceptor,
+    tokio_stream::wrappers::TcpListenerStream,
+};
+
+/// The conventional DNS-over-HTTPS path, answered by the resolver's `dns-query`
+/// endpoint.
+pub const DOH_PATH: &str = "/dns-query";
+
+type SinglePortBody = UnsyncBoxBody<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+/// What's served on the single port.
+pub struct SinglePortRoutes {
+    /// The API router, if the API or DNS-over-HTTPS is enabled
+    pub api: Option<Arc<dyn Handler<htserve::responses::Body>>>,
+    /// Serve all API paths, not just DNS-over-HTTPS
+    pub full_api: bool,
+    pub dns_over_https: bool,
+    /// Lowercase host names the API is served for, or empty for any
+    pub api_hosts: Vec<String>,
+    /// Content, in order of preference when several serve the same host
+    pub content: Vec<ContentSite>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum ApiRoute {
+    Api,
+    DnsOverHttps,
+}
+
+/// The request's host name, lowercase and without a port: the TLS SNI, or the
+/// `Host` header (or HTTP/2 authority) if the client didn't send SNI.
+fn request_host(sni: Option<&str>, head: &http::request::Parts) -> Option<String> {
+    if let Some(sni) = sni {
+        return Some(sni.to_ascii_lowercase());
+    }
+    let authority = match head.headers.get(http::header::HOST).and_then(|h| h.to_str().ok()) {
+        Some(h) => Authority::from_str(h).ok()?,
+        None => head.uri.authority()?.clone(),
+    };
+    return Some(authority.host().to_ascii_lowercase());
+}
+
+/// Whether a request goes to the API rather than content.
+fn api_route(routes: &SinglePortRoutes, host: Option<&str>, path: &str) -> Option<ApiRoute> {
+    if !routes.api_hosts.is_empty() && !host.is_some_and(|h| routes.api_hosts.iter().any(|a| a == h)) {
+        return None;
+    }
+    if routes.dns_over_https && path == DOH_PATH {
+        return Some(ApiRoute::DnsOverHttps);
+    }
+    if !routes.full_api {
+        return None;
+    }
+    let first = path.trim_start_matches('/').split('/').next().unwrap_or("");
+    if [API_ROUTE_RESOLVE, API_ROUTE_PUBLISH, API_ROUTE_OPENAPI, "admin", "health"].contains(&first) {
+        return Some(ApiRoute::Api);
+    }
+    return None;
+}
+
+async fn handle_request(
+    routes: &SinglePortRoutes,
+    sni: Option<&str>,
+    client_identity: Option<&ClientCertIdentity>,
+    peer_addr: SocketAddr,
+    req: Request<Incoming>,
+) -> Response<SinglePortBody> {
+    let (mut head, body) = req.into_parts();
+    if let Some(identity) = client_identity {
+        head.extensions.insert(identity.clone());
+    }
+    let host = request_host(sni, &head);
+    let path = head.uri.path().to_string();
+    let api_path = match (api_route(routes, host.as_deref(), &path), &routes.api) {
+        (Some(ApiRoute::Api), Some(_)) => Some(path.clone()),
+        (Some(ApiRoute::DnsOverHttps), Some(_)) => Some(format!("/{}{}", API_ROUTE_RESOLVE, DOH_PATH)),
+        _ => None,
+    };
+    if let (Some(api_path), Some(api)) = (api_path, &routes.api) {
+        let query = head.uri.query().unwrap_or("").to_string();
+        return api.handle(HandlerArgs {
+            peer_addr: peer_addr,
+            query: &query,
+            head: &head,
+            subpath: &api_path,
+            body: body,
+        }).await.map(|b| b.map_err(Into::into).boxed_unsync());
+    }
+    for site in &routes.content {
+        if site.serves_host(host.as_deref()) {
+            return site
+                .handle(sni, peer_addr, Request::from_parts(head, body))
+                .await
+                .map(|b| b.map_err(Into::into).boxed_unsync());
+        }
+    }
+    return response_not_found().map(|b| b.map_err(Into::into).boxed_unsync());
+}
+
+async fn handle_conn(
+    tls_acceptor: TlsAcceptor,
+    routes: Arc<SinglePortRoutes>,
+    stream: TcpStream,
+    shutdown: ShutdownSignal,
+) -> Result<(), loga::Error> {
+    let peer_addr = stream.peer_addr().context("Error getting peer address of connection")?;
+    let stream = tls_acceptor.accept(stream).await.context("Error during TLS handshake")?;
+    let sni = stream.get_ref().1.server_name().map(|s| s.to_string());
+    let client_identity =
+        stream
+            .get_ref()
+            .1
+            .peer_certificates()
+            .and_then(|c| c.first())
+            .and_then(|c| cert_der_identity(c))
+            .map(ClientCertIdentity);
+    drain_conn(
+        hyper_util::server::conn::auto::Builder::new(TokioExecutor::new()).serve_connection(
+            TokioIo::new(stream),
+            hyper::service::service_fn(move |req: Request<Incoming>| {
+                let routes = routes.clone();
+                let sni = sni.clone();
+                let client_identity = client_identity.clone();
+                async move {
+                    return Ok(
+                        handle_request(&routes, sni.as_deref(), client_identity.as_ref(), peer_addr, req).await,
+                    ) as Result<_, Infallible>;
+                }
+            }),
+        ),
+        |c| c.graceful_shutdown(),
+        shutdown,
+    )
+        .await
+        .map_err(|e| loga::err_with("Error serving HTTP connection", ea!(err = e)))?;
+    return Ok(());
+}
+
+pub async fn start_serving_single_port(
+    log: &Log,
+    tm: &TaskManager,
+    shutdown_grace: Duration,
+    resolves_cert: Arc<dyn ResolvesServerCert>,
+    client_cert_auth: bool,
+    bind_addr: SocketAddr,
+    routes: SinglePortRoutes,
+) -> Result<(), loga::Error> {
+    let log = log.fork(ea!(sys = "single_port", bind_addr = bind_addr));
+    let tls_acceptor = TlsAcceptor::from(Arc::new({
+        let mut server_config = if client_cert_auth {
+            ServerConfig::builder()
+                .with_client_cert_verifier(IdentityClientCertVerifier::new())
+                .with_cert_resolver(resolves_cert)
+        } else {
+            ServerConfig::builder().with_no_client_auth().with_cert_resolver(resolves_cert)
+        };
+        server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec(), b"http/1.0".to_vec()];
+        server_config
+    }));
+    let routes = Arc::new(routes);
+    serve_draining(
+        &log,
+        tm,
+        format!("Serve - single port ({})", bind_addr),
+        shutdown_grace,
+        TcpListenerStream::new(TcpListener::bind(bind_addr).await.stack_context(&log, "Error binding to address")?),
+        cap_fn!((stream, shutdown)(log, tls_acceptor, routes) {
+            let stream = match stream {
+                Ok(s) => s,
+                Err(e) => {
+                    log.log_err(loga::DEBUG, e.context("Error opening peer stream"));
+                    return;
+                },
+            };
+            if let Err(e) = handle_conn(tls_acceptor, routes, stream, shutdown).await {
+                log.log_err(loga::DEBUG, e.context("Error handling connection"));
+            }
+        }),
+    );
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test_single_port {
+    use super::{
+        api_route,
+        ApiRoute,
+        SinglePortRoutes,
+    };
+
+    fn routes(full_api: bool, api_hosts: &[&str]) -> SinglePortRoutes {
+        return SinglePortRoutes {
+            api: None,
+            full_api: full_api,
+            dns_over_https: true,
+            api_hosts: api_hosts.iter().map(|h| h.to_string()).collect(),
+            content: vec![],
+        };
+    }
+
+    #[test]
+    fn test_api_route() {
+        let r = routes(true, &[]);
+        assert_eq!(api_route(&r, None, "/resolve/v1/x"), Some(ApiRoute::Api));
+        assert_eq!(api_route(&r, Some("a.example"), "/admin/health"), Some(ApiRoute::Api));
+        assert_eq!(api_route(&r, None, "/dns-query"), Some(ApiRoute::DnsOverHttps));
+        assert_eq!(api_route(&r, None, "/"), None);
+        assert_eq!(api_route(&r, None, "/resolved.html"), None);
+        let r = routes(false, &[]);
+        assert_eq!(api_route(&r, None, "/resolve/v1/x"), None);
+        assert_eq!(api_route(&r, None, "/dns-query"), Some(ApiRoute::DnsOverHttps));
+        let r = routes(true, &["api.example"]);
+        assert_eq!(api_route(&r, Some("api.example"), "/resolve/v1/x"), Some(ApiRoute::Api));
+        assert_eq!(api_route(&r, Some("www.example"), "/resolve/v1/x"), None);
+        assert_eq!(api_route(&r, None, "/dns-query"), None);
+    }
+}
@@ -0,0 +1,83 @@
+//! Admin endpoint tokens, and keeping admin endpoints off public listeners.
+use {
+    super::api_error::response_not_found,
+    crate::service::publisher::API_ROUTE_PUBLISH,
+    async_trait::async_trait,
+    htwrap::htserve::{
+        self,
+        auth::{
+            check_auth_token_hash,
+            get_auth_token,
+            hash_auth_token,
+            AuthTokenHash,
+        },
+        handler::{
+            Handler,
+            HandlerArgs,
+        },
+    },
+    http::{
+        HeaderMap,
+        Response,
+    },
+    std::sync::Arc,
+};
+
+/// The tokens accepted for admin endpoints.
+#[derive(Clone)]
+pub struct AdminTokens(Arc<Vec<AuthTokenHash>>);
+
+impl AdminTokens {
+    pub fn new(raw: &[String]) -> AdminTokens {
+        return AdminTokens(Arc::new(raw.iter().map(|t| hash_auth_token(t)).collect()));
+    }
+
+    /// Check the bearer token in the request headers against all the tokens. Errors if
+    /// there's no token.
+    pub fn check(&self, headers: &HeaderMap) -> Result<bool, loga::Error> {
+        let token = get_auth_token(headers)?;
+        return Ok(self.0.iter().any(|h| check_auth_token_hash(h, &token)));
+    }
+}
+
+/// Whether the path is for an admin endpoint (`/admin/...` or
+/// `/publish/admin/...`).
+pub fn is_admin_path(path: &str) -> bool {
+    let mut segs = path.split('/').filter(|s| !s.is_empty());
+    match (segs.next(), segs.next()) {
+        (Some("admin"), _) => return true,
+        (Some(first), Some("admin")) if first == API_ROUTE_PUBLISH => return true,
+        _ => return false,
+    }
+}
+
+/// Wraps the API handler for listeners that shouldn't serve admin endpoints (when
+/// admin endpoints have their own listeners). Admin paths get `404`.
+pub struct PublicOnlyHandler {
+    pub inner: Arc<dyn Handler<htserve::responses::Body>>,
+}
+
+#[async_trait]
+impl Handler<htserve::responses::Body> for PublicOnlyHandler {
+    async fn handle(&self, args: HandlerArgs<'_>) -> Response<htserve::responses::Body> {
+        if is_admin_path(args.head.uri.path()) {
+            return response_not_found();
+        }
+        return self.inner.handle(args).await;
+    }
+}
+
+#[cfg(test)]
+mod test_admin_auth {
+    use super::is_admin_path;
+
+    #[test]
+    fn test_admin_paths() {
+        assert!(is_admin_path("/admin/health"));
+        assert!(is_admin_path("//admin"));
+        assert!(is_admin_path("/publish/admin/allowed_identities"));
+        assert!(!is_admin_path("/publish/v1/publish"));
+        assert!(!is_admin_path("/resolve/v1/x"));
+        assert!(!is_admin_path("/health"));
+    }
+}
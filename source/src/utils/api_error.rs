@@ -0,0 +1,113 @@
+//! Structured error responses for the API, and finding them again in client-side
+//! errors.
+use {
+    crate::interface::wire::api::error::latest::{
+        ApiError,
+        ErrorCode,
+    },
+    htwrap::htserve::{
+        self,
+        responses::response_200_json,
+    },
+    http::{
+        header::RETRY_AFTER,
+        HeaderValue,
+        Response,
+        StatusCode,
+    },
+};
+
+pub fn error_status(code: ErrorCode) -> StatusCode {
+    match code {
+        ErrorCode::BadRequest | ErrorCode::BadSignature | ErrorCode::Expired => return StatusCode::BAD_REQUEST,
+        ErrorCode::Unauthorized => return StatusCode::UNAUTHORIZED,
+        ErrorCode::NotFound => return StatusCode::NOT_FOUND,
+        ErrorCode::RateLimited => return StatusCode::TOO_MANY_REQUESTS,
+        ErrorCode::StorageFull => return StatusCode::INSUFFICIENT_STORAGE,
+        ErrorCode::Rejected => return StatusCode::FORBIDDEN,
+        ErrorCode::Unavailable | ErrorCode::Internal => return StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+pub fn response_error(code: ErrorCode, message: impl ToString) -> Response<htserve::responses::Body> {
+    let mut resp = response_200_json(ApiError {
+        error: code,
+        message: message.to_string(),
+    });
+    *resp.status_mut() = error_status(code);
+    return resp;
+}
+
+pub fn response_bad_request(message: impl ToString) -> Response<htserve::responses::Body> {
+    return response_error(ErrorCode::BadRequest, message);
+}
+
+pub fn response_bad_signature() -> Response<htserve::responses::Body> {
+    return response_error(ErrorCode::BadSignature, "Couldn't verify payload");
+}
+
+pub fn response_expired() -> Response<htserve::responses::Body> {
+    return response_error(ErrorCode::Expired, "Request time too far from current time");
+}
+
+pub fn response_unauthorized() -> Response<htserve::responses::Body> {
+    return response_error(ErrorCode::Unauthorized, "Unauthorized");
+}
+
+pub fn response_not_found() -> Response<htserve::responses::Body> {
+    return response_error(ErrorCode::NotFound, "Not found");
+}
+
+/// Details are logged, not sent to the client.
+pub fn response_internal() -> Response<htserve::responses::Body> {
+    return response_error(ErrorCode::Internal, "Internal error");
+}
+
+pub fn response_unavailable(retry_after: u32) -> Response<htserve::responses::Body> {
+    let mut resp = response_error(ErrorCode::Unavailable, "Down for maintenance");
+    resp.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    return resp;
+}
+
+/// For requests refused because the node is overloaded.
+pub fn response_overloaded(retry_after: u32) -> Response<htserve::responses::Body> {
+    let mut resp = response_error(ErrorCode::RateLimited, "Overloaded, try again later");
+    resp.headers_mut().insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    return resp;
+}
+
+/// Find the code of an API error response in a client-side error. Request errors
+/// include the response body, possibly escaped, so this looks for the serialized
+/// `error` field.
+pub fn find_error_code(e: &loga::Error) -> Option<ErrorCode> {
+    let text = e.to_string().replace('\\', "");
+    for code in ErrorCode::ALL {
+        if text.contains(&format!("\"error\":\"{}\"", code.as_str())) {
+            return Some(code);
+        }
+    }
+    return None;
+}
+
+#[cfg(test)]
+mod test_api_error {
+    use {
+        super::find_error_code,
+        crate::interface::wire::api::error::latest::ErrorCode,
+    };
+
+    #[test]
+    fn test_find_escaped() {
+        let e =
+            loga::err_with(
+                "Error response from server",
+                loga::ea!(body = "{\\\"error\\\":\\\"bad_signature\\\",\\\"message\\\":\\\"x\\\"}"),
+            );
+        assert_eq!(find_error_code(&e), Some(ErrorCode::BadSignature));
+    }
+
+    #[test]
+    fn test_find_none() {
+        assert_eq!(find_error_code(&loga::err("Connection refused")), None);
+    }
+}
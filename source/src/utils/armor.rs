@@ -0,0 +1,108 @@
+//! Armored text encoding for files users handle directly (identity secrets,
+//! etc). This is PEM framing with a `SPAGHETTINUUM` label prefix and required
+//! `Version` and `Type` headers so the contents can be identified (and grepped
+//! for) without parsing the payload, and so payload formats can change between
+//! versions.
+use {
+    loga::{
+        ea,
+        ResultContext,
+    },
+    pem::{
+        EncodeConfig,
+        LineEnding,
+        Pem,
+    },
+};
+
+pub const LABEL_PREFIX: &str = "SPAGHETTINUUM ";
+pub const HEADER_VERSION: &str = "Version";
+pub const HEADER_TYPE: &str = "Type";
+
+pub struct Dearmored {
+    pub version: String,
+    pub type_: String,
+    pub pem: Pem,
+}
+
+/// Heuristic for distinguishing armored data from older (json) formats.
+pub fn is_armored(data: &[u8]) -> bool {
+    return data.trim_ascii_start().starts_with(b"-----BEGIN ");
+}
+
+/// Encode `data` with the label `SPAGHETTINUUM {kind}`. Extra headers are
+/// informational only and follow `Version` and `Type`.
+pub fn armor(
+    kind: &str,
+    version: &str,
+    type_: &str,
+    extra_headers: &[(&str, &str)],
+    data: &[u8],
+) -> Result<String, loga::Error> {
+    let mut pem = Pem::new(format!("{}{}", LABEL_PREFIX, kind), data);
+    let headers = pem.headers_mut();
+    for (k, v) in [(HEADER_VERSION, version), (HEADER_TYPE, type_)].iter().chain(extra_headers.iter()) {
+        headers.add(k, v).context_with("Invalid armor header", ea!(header = k, value = v))?;
+    }
+    return Ok(pem::encode_config(&pem, EncodeConfig { line_ending: LineEnding::LF }));
+}
+
+/// Decode armored data, confirming the label is `SPAGHETTINUUM {kind}` and
+/// extracting the required headers.
+pub fn dearmor(kind: &str, data: &[u8]) -> Result<Dearmored, loga::Error> {
+    let pem = pem::parse(data).context("Error parsing armored data")?;
+    let want_label = format!("{}{}", LABEL_PREFIX, kind);
+    if pem.tag() != want_label {
+        return Err(loga::err_with("Armored data has wrong label", ea!(want = want_label, got = pem.tag())));
+    }
+    let version =
+        pem
+            .headers()
+            .get(HEADER_VERSION)
+            .context_with("Armored data is missing header", ea!(header = HEADER_VERSION))?
+            .to_string();
+    let type_ =
+        pem
+            .headers()
+            .get(HEADER_TYPE)
+            .context_with("Armored data is missing header", ea!(header = HEADER_TYPE))?
+            .to_string();
+    return Ok(Dearmored {
+        version: version,
+        type_: type_,
+        pem: pem,
+    });
+}
+
+#[cfg(test)]
+mod test_armor {
+    use super::{
+        armor,
+        dearmor,
+        is_armored,
+    };
+
+    #[test]
+    fn test_roundtrip() {
+        let text = armor("THING", "1", "widget", &[("Id", "abc")], b"hello world").unwrap();
+        assert!(text.starts_with("-----BEGIN SPAGHETTINUUM THING-----\nVersion: 1\nType: widget\nId: abc\n\n"));
+        assert!(is_armored(text.as_bytes()));
+        let got = dearmor("THING", text.as_bytes()).unwrap();
+        assert_eq!(got.version, "1");
+        assert_eq!(got.type_, "widget");
+        assert_eq!(got.pem.headers().get("Id"), Some("abc"));
+        assert_eq!(got.pem.contents(), b"hello world");
+    }
+
+    #[test]
+    fn test_wrong_label() {
+        let text = armor("THING", "1", "widget", &[], b"hello world").unwrap();
+        assert!(dearmor("OTHER", text.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_not_armored() {
+        assert!(!is_armored(b"{\"v1\": {}}"));
+        assert!(is_armored(b"\n  -----BEGIN SPAGHETTINUUM THING-----\n"));
+    }
+}
@@ -2,6 +2,7 @@ use std::ops::{
     Deref,
     DerefMut,
 };
+use schemars::JsonSchema;
 use serde::{
     Serialize,
     Deserialize,
@@ -70,6 +71,23 @@ impl From<Vec<u8>> for Blob {
     }
 }
 
+impl JsonSchema for Blob {
+    fn schema_name() -> String {
+        return "Blob".to_string();
+    }
+
+    fn json_schema(_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        return schemars::schema::SchemaObject {
+            instance_type: Some(schemars::schema::InstanceType::String.into()),
+            metadata: Some(Box::new(schemars::schema::Metadata {
+                description: Some("Binary data (zbase32 string)".to_string()),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }.into();
+    }
+}
+
 impl Serialize for Blob {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
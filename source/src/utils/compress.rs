@@ -0,0 +1,218 @@
+//! Negotiated compression of HTTP responses.
+use {
+    crate::interface::config::shared::CompressionConfig,
+    async_trait::async_trait,
+    flate2::write::GzEncoder,
+    htwrap::htserve::handler::{
+        Handler,
+        HandlerArgs,
+    },
+    http::{
+        header::{
+            ACCEPT_ENCODING,
+            CONTENT_ENCODING,
+            CONTENT_LENGTH,
+            CONTENT_RANGE,
+            CONTENT_TYPE,
+            VARY,
+        },
+        HeaderValue,
+        Response,
+        StatusCode,
+    },
+    http_body_util::BodyExt,
+    hyper::body::{
+        Body,
+        Bytes,
+    },
+    std::{
+        io::Write,
+        sync::Arc,
+    },
+};
+
+/// Smaller responses aren't compressed by default; the savings don't make up for
+/// the overhead.
+pub const DEFAULT_MIN_SIZE: usize = 1024;
+
+/// Larger responses are sent as is rather than buffered in memory to compress.
+const MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => return "br",
+            Encoding::Gzip => return "gzip",
+        }
+    }
+}
+
+/// Pick an encoding from an `Accept-Encoding` header, preferring brotli. Encodings
+/// with `q=0` are refused.
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut brotli = false;
+    let mut gzip = false;
+    for part in accept_encoding.split(',') {
+        let mut params = part.split(';');
+        let coding = params.next().unwrap().trim().to_ascii_lowercase();
+        let refused = params.any(|p| {
+            let p = p.trim().to_ascii_lowercase();
+            return p.strip_prefix("q=").and_then(|q| q.trim().parse::<f32>().ok()).map(|q| q <= 0.).unwrap_or(false);
+        });
+        if refused {
+            continue;
+        }
+        match coding.as_str() {
+            "br" => brotli = true,
+            "gzip" | "x-gzip" => gzip = true,
+            _ => { },
+        }
+    }
+    if brotli {
+        return Some(Encoding::Brotli);
+    }
+    if gzip {
+        return Some(Encoding::Gzip);
+    }
+    return None;
+}
+
+/// Whether compressing content of this type is likely to help (text and structured
+/// text, not already-compressed media).
+fn compressible(content_type: &str) -> bool {
+    let t = content_type.split(';').next().unwrap().trim().to_ascii_lowercase();
+    return t.starts_with("text/") || t.ends_with("+json") || t.ends_with("+xml") ||
+        ["application/json", "application/javascript", "application/xml", "application/wasm"].contains(&t.as_str());
+}
+
+pub fn compress(encoding: Encoding, data: &[u8]) -> Vec<u8> {
+    match encoding {
+        Encoding::Brotli => {
+            // Quality 5 is much faster than the default (11) and still smaller than gzip
+            let mut w = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+            w.write_all(data).unwrap();
+            return w.into_inner();
+        },
+        Encoding::Gzip => {
+            let mut w = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            w.write_all(data).unwrap();
+            return w.finish().unwrap();
+        },
+    }
+}
+
+/// Compress a response if the client accepts a supported encoding, the content
+/// type is compressible, and the body has a known size of at least `min_size`.
+/// Streamed bodies (unknown size) and very large bodies are passed through. `full` makes a body of the
+/// response's type from bytes.
+pub async fn compress_response<
+    B: Body<Data = Bytes> + Send + 'static,
+>(
+    accept_encoding: Option<HeaderValue>,
+    min_size: usize,
+    resp: Response<B>,
+    full: fn(Bytes) -> B,
+) -> Response<B> {
+    let Some(encoding) = accept_encoding.as_ref().and_then(|v| v.to_str().ok()).and_then(negotiate) else {
+        return resp;
+    };
+    let headers = resp.headers();
+    if headers.contains_key(CONTENT_ENCODING) || headers.contains_key(CONTENT_RANGE) {
+        return resp;
+    }
+    if !headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(compressible).unwrap_or(false) {
+        return resp;
+    }
+    match resp.body().size_hint().exact() {
+        Some(size) if size >= min_size as u64 && size <= MAX_SIZE => { },
+        _ => return resp,
+    }
+    let (mut head, body) = resp.into_parts();
+    let data = match body.collect().await {
+        Ok(b) => b.to_bytes(),
+        Err(_) => {
+            head.status = StatusCode::INTERNAL_SERVER_ERROR;
+            head.headers.remove(CONTENT_TYPE);
+            head.headers.remove(CONTENT_LENGTH);
+            return Response::from_parts(head, full(Bytes::new()));
+        },
+    };
+    let compressed = {
+        let data = data.clone();
+        tokio::task::spawn_blocking(move || compress(encoding, &data)).await.unwrap()
+    };
+    head.headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    if compressed.len() >= data.len() {
+        return Response::from_parts(head, full(data));
+    }
+    head.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+    head.headers.remove(CONTENT_LENGTH);
+    return Response::from_parts(head, full(Bytes::from(compressed)));
+}
+
+/// Wraps a handler, compressing its responses (see `compress_response`).
+pub struct CompressHandler<B> {
+    pub inner: Arc<dyn Handler<B>>,
+    pub min_size: usize,
+    pub full: fn(Bytes) -> B,
+}
+
+impl<B: Body<Data = Bytes> + Send + 'static> CompressHandler<B> {
+    /// Wrap `inner` per the config, or return it as is if compression is disabled.
+    pub fn wrap(
+        config: &Option<CompressionConfig>,
+        inner: Arc<dyn Handler<B>>,
+        full: fn(Bytes) -> B,
+    ) -> Arc<dyn Handler<B>> {
+        let min_size = match config {
+            Some(c) if c.disable => return inner,
+            Some(c) => c.min_size.unwrap_or(DEFAULT_MIN_SIZE),
+            None => DEFAULT_MIN_SIZE,
+        };
+        return Arc::new(CompressHandler {
+            inner: inner,
+            min_size: min_size,
+            full: full,
+        });
+    }
+}
+
+#[async_trait]
+impl<B: Body<Data = Bytes> + Send + 'static> Handler<B> for CompressHandler<B> {
+    async fn handle(&self, args: HandlerArgs<'_>) -> Response<B> {
+        let accept_encoding = args.head.headers.get(ACCEPT_ENCODING).cloned();
+        let resp = self.inner.handle(args).await;
+        return compress_response(accept_encoding, self.min_size, resp, self.full).await;
+    }
+}
+
+#[cfg(test)]
+mod test_compress {
+    use super::{
+        compressible,
+        negotiate,
+        Encoding,
+    };
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip;q=0.5, br;q=0"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("deflate, identity"), None);
+        assert_eq!(negotiate("GZIP; Q=0"), None);
+    }
+
+    #[test]
+    fn test_compressible() {
+        assert!(compressible("application/json"));
+        assert!(compressible("text/html; charset=utf-8"));
+        assert!(compressible("image/svg+xml"));
+        assert!(!compressible("image/png"));
+    }
+}
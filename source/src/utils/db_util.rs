@@ -12,13 +12,74 @@ use {
         Log,
         ResultContext,
     },
-    rusqlite::Transaction,
-    std::path::Path,
+    rusqlite::{
+        OptionalExtension,
+        Transaction,
+    },
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    std::path::{
+        Path,
+        PathBuf,
+    },
     tokio::fs::create_dir_all,
 };
 
+/// Check the schema version recorded in the database (see `mark_schema_version`)
+/// isn't newer than `schema_version`, the latest this build knows. Opening a
+/// database from a newer version after a downgrade could misread or drop its data,
+/// so this fails instead. Databases without a recorded version are accepted.
+pub fn check_schema_version(conn: &rusqlite::Connection, schema_version: usize) -> Result<(), loga::Error> {
+    conn
+        .execute(
+            "create table if not exists spagh_schema \
+                (unique_ integer primary key, version integer not null, crate_version text not null)",
+            [],
+        )
+        .context("Error creating schema version table")?;
+    let found =
+        conn
+            .query_row(
+                "select version, crate_version from spagh_schema where unique_ = 0",
+                [],
+                |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)),
+            )
+            .optional()
+            .context("Error reading schema version")?;
+    if let Some((version, crate_version)) = found {
+        if version > schema_version as i64 {
+            return Err(
+                loga::err_with(
+                    "Database was written by a newer version; upgrade again or restore a backup made before upgrading",
+                    ea!(db_schema = version, supported_schema = schema_version, written_by = crate_version),
+                ),
+            );
+        }
+    }
+    return Ok(());
+}
+
+/// Record the schema version and the version of this crate in the database, after
+/// migrating.
+pub fn mark_schema_version(conn: &rusqlite::Connection, schema_version: usize) -> Result<(), loga::Error> {
+    conn
+        .execute(
+            "insert into spagh_schema (unique_, version, crate_version) values (0, ?1, ?2) \
+                on conflict (unique_) do update set version = ?1, crate_version = ?2",
+            rusqlite::params![schema_version as i64, env!("CARGO_PKG_VERSION")],
+        )
+        .context("Error recording schema version")?;
+    return Ok(());
+}
+
+/// Open (creating if necessary) and migrate a database. `schema_version` is the
+/// latest version in the database's `buildlib` definition.
 pub async fn setup_db(
     p: &Path,
+    schema_version: usize,
     migrate: fn(&mut rusqlite::Connection) -> Result<(), GoodError>,
 ) -> Result<Pool, loga::Error> {
     let log = &Log::new().fork(ea!(path = p.to_string_lossy()));
@@ -28,12 +89,96 @@ pub async fn setup_db(
     let pool = Config::new(p).create_pool(Runtime::Tokio1).stack_context(log, "Error constructing db pool")?;
     let conn = pool.get().await.stack_context(log, "Error getting db connection from pool")?;
     conn.interact(move |conn| {
+        check_schema_version(conn, schema_version)?;
         migrate(conn)?;
+        mark_schema_version(conn, schema_version)?;
         return Ok(()) as Result<(), loga::Error>;
     }).await.stack_context(log, "Error performing db interaction")?.stack_context(log, "Error migrating database")?;
     return Ok(pool);
 }
 
+/// Write a consistent snapshot of the sqlite database at `p` to `dest` using
+/// sqlite's online backup, which is safe while other connections are writing
+/// (unlike copying the file). Blocking.
+pub fn db_backup(p: &Path, dest: &Path) -> Result<(), loga::Error> {
+    let log = &Log::new().fork(ea!(path = p.to_string_lossy(), dest = dest.to_string_lossy()));
+    let conn =
+        rusqlite::Connection::open_with_flags(
+            p,
+            rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        ).stack_context(log, "Error opening database to back up")?;
+    conn
+        .backup(rusqlite::DatabaseName::Main, dest, None)
+        .stack_context(log, "Error backing up database")?;
+    return Ok(());
+}
+
+/// Bytes used on disk by a sqlite database, including its write-ahead log and
+/// shared memory files. Missing files count as 0.
+pub fn db_disk_usage(p: &Path) -> u64 {
+    let mut total = 0;
+    for suffix in ["", "-wal", "-shm"] {
+        let mut path = p.as_os_str().to_owned();
+        path.push(suffix);
+        if let Ok(meta) = std::fs::metadata(&path) {
+            total += meta.len();
+        }
+    }
+    return total;
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct DbUsage {
+    /// Short name of the database.
+    pub name: String,
+    pub path: PathBuf,
+    /// Bytes used on disk, including the write-ahead log.
+    pub bytes: u64,
+    /// The configured size limit, if any.
+    pub limit: Option<u64>,
+}
+
+/// Entries per page of a paged listing, if not specified.
+pub const PAGE_DEFAULT: usize = 50;
+
+/// Maximum entries per page of a paged listing.
+pub const PAGE_MAX: usize = 1000;
+
+/// Paging and filtering for listings ordered by a string key (like an identity or
+/// record key). Pass `after` as the last entry of the previous page to get the next
+/// page; an empty page means there are no more entries.
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct ListFilter {
+    /// Only return entries after this one
+    #[serde(default)]
+    pub after: Option<String>,
+    /// Only return entries starting with this
+    #[serde(default)]
+    pub prefix: Option<String>,
+    /// Maximum entries to return, defaults to `PAGE_DEFAULT` and capped at `PAGE_MAX`
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl ListFilter {
+    pub fn limit(&self) -> usize {
+        return self.limit.unwrap_or(PAGE_DEFAULT).clamp(1, PAGE_MAX);
+    }
+
+    /// Params for paged queries: `after`, `prefix_start`, `prefix_end`, and `limit`.
+    pub fn params(&self) -> (String, String, String, i64) {
+        let prefix = self.prefix.clone().unwrap_or_default();
+        return (
+            self.after.clone().unwrap_or_default(),
+            prefix.clone(),
+            format!("{}{}", prefix, char::MAX),
+            self.limit() as i64,
+        );
+    }
+}
+
 #[async_trait]
 pub trait DbTx {
     async fn tx<
@@ -68,3 +213,54 @@ impl DbTx for Pool {
         }).await??);
     }
 }
+
+#[cfg(test)]
+mod test_schema_version {
+    use {
+        super::{
+            check_schema_version,
+            mark_schema_version,
+        },
+        crate::{
+            self_tls,
+            service::{
+                node,
+                publisher,
+                resolver,
+            },
+        },
+        good_ormning_runtime::GoodError,
+    };
+
+    #[test]
+    fn test_downgrade() {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+
+        // No version recorded yet
+        check_schema_version(&conn, 1).unwrap();
+        mark_schema_version(&conn, 2).unwrap();
+        check_schema_version(&conn, 2).unwrap();
+        check_schema_version(&conn, 3).unwrap();
+        assert!(check_schema_version(&conn, 1).is_err());
+    }
+
+    /// The `DB_SCHEMA_VERSION` constants are maintained by hand, so catch ones that
+    /// weren't bumped with a new `buildlib` version.
+    #[test]
+    fn test_latest() {
+        let dbs: [(&str, usize, fn(&mut rusqlite::Connection) -> Result<(), GoodError>); 5] = [
+            ("node", node::DB_SCHEMA_VERSION, node::db::migrate),
+            ("publisher", publisher::storage::sqlite::DB_SCHEMA_VERSION, publisher::db::migrate),
+            ("publisher_admin", publisher::storage::sqlite::ADMIN_DB_SCHEMA_VERSION, publisher::admin_db::migrate),
+            ("resolver", resolver::DB_SCHEMA_VERSION, resolver::db::migrate),
+            ("self_tls", self_tls::DB_SCHEMA_VERSION, self_tls::db::migrate),
+        ];
+        for (name, schema_version, migrate) in dbs {
+            let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+            migrate(&mut conn).unwrap();
+            let latest =
+                conn.query_row("select version from __good_version where rid = 0", [], |r| r.get::<_, i64>(0)).unwrap();
+            assert_eq!(schema_version as i64, latest, "{}", name);
+        }
+    }
+}
@@ -1,10 +1,15 @@
 use {
+    crate::{
+        interface::config::ENV_CONTROL_SOCKET,
+        ta_res,
+    },
     loga::{
         ea,
         ErrContext,
         ResultContext,
     },
     serde::de::DeserializeOwned,
+    tokio::io::AsyncWriteExt,
     std::{
         env,
         path::{
@@ -21,6 +26,36 @@ pub async fn write(path: impl AsRef<Path>, data: &[u8]) -> Result<(), loga::Erro
     return Ok(());
 }
 
+/// Write a file readable only by the owner, replacing any existing file
+/// atomically: the data is written and synced to a sibling temporary file which is
+/// then moved over `path`, so a crash or full disk never leaves `path` partially
+/// written.
+pub async fn write_private_atomic(path: impl AsRef<Path>, data: &[u8]) -> Result<(), loga::Error> {
+    let path = path.as_ref();
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    let temp_path = path.with_file_name(temp_name);
+    let res = async {
+        ta_res!(());
+        let mut f =
+            tokio::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&temp_path)
+                .await
+                .context("Error creating temporary file")?;
+        f.write_all(data).await.context("Error writing temporary file")?;
+        f.sync_all().await.context("Error syncing temporary file")?;
+        tokio::fs::rename(&temp_path, path).await.context("Error moving temporary file into place")?;
+        return Ok(());
+    }.await;
+    if res.is_err() {
+        _ = tokio::fs::remove_file(&temp_path).await;
+    }
+    return res.context_with("Error writing file", ea!(path = path.to_string_lossy()));
+}
+
 pub async fn read(path: impl AsRef<Path>) -> Result<Vec<u8>, loga::Error> {
     return Ok(
         tokio::fs::read(path.as_ref())
@@ -98,3 +133,22 @@ pub fn cache_dir() -> PathBuf {
         return PathBuf::from("/var/cache").join(APP_DIRNAME);
     }
 }
+
+pub fn runtime_dir() -> PathBuf {
+    if let Some(d) = env::var_os("APP_RUNTIME_DIRECTORY") {
+        return PathBuf::from(d);
+    } else if let Some(d) = env::var_os("RUNTIME_DIRECTORY") {
+        return PathBuf::from(d).join(APP_DIRNAME);
+    } else {
+        return PathBuf::from("/run").join(APP_DIRNAME);
+    }
+}
+
+/// Path of the `spagh-node` control socket, shared by `spagh-node` and `spagh`.
+pub fn control_socket_path() -> PathBuf {
+    if let Some(p) = env::var_os(ENV_CONTROL_SOCKET) {
+        return PathBuf::from(p);
+    } else {
+        return runtime_dir().join("control.sock");
+    }
+}
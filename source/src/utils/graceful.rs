@@ -0,0 +1,207 @@
+//! Connection draining for HTTP listeners. When the task manager terminates,
+//! listeners stop accepting, open connections are asked to finish their in-flight
+//! requests and close, and connections still open after the grace period are
+//! aborted.
+use {
+    super::{
+        task_status::TrackedTasks,
+        tls_util::{
+            cert_der_identity,
+            ClientCertIdentity,
+        },
+    },
+    futures::{
+        Future,
+        Stream,
+        StreamExt,
+    },
+    htwrap::htserve::{
+        self,
+        handler::{
+            Handler,
+            HandlerArgs,
+        },
+    },
+    http::Request,
+    hyper::body::Incoming,
+    hyper_util::rt::{
+        TokioExecutor,
+        TokioIo,
+    },
+    loga::{
+        ea,
+        Log,
+        ResultContext,
+    },
+    std::{
+        convert::Infallible,
+        pin::Pin,
+        sync::Arc,
+        task::{
+            Context,
+            Poll,
+        },
+        time::Duration,
+    },
+    taskmanager::TaskManager,
+    tokio::{
+        net::TcpStream,
+        select,
+        sync::watch,
+        task::JoinSet,
+        time::timeout,
+    },
+    tokio_rustls::TlsAcceptor,
+};
+
+/// How long connections get to finish in-flight requests at shutdown, if not
+/// configured.
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+/// Resolves when the connection should finish in-flight requests and close.
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    pub async fn wait(&mut self) {
+        _ = self.0.wait_for(|s| *s).await;
+    }
+
+    fn into_wait(mut self) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        return Box::pin(async move {
+            self.wait().await;
+        });
+    }
+}
+
+/// Handle connections from `listener` until the task manager terminates, then
+/// drain them. Each connection is handled in its own task, and should finish up
+/// and close when the `ShutdownSignal` resolves (see `drain_conn`).
+pub fn serve_draining<T: Send + 'static, N: Future<Output = ()> + Send + 'static>(
+    log: &Log,
+    tm: &TaskManager,
+    name: String,
+    grace: Duration,
+    listener: impl Stream<Item = T> + Send + 'static,
+    handle: impl Fn(T, ShutdownSignal) -> N + Send + 'static,
+) {
+    tm.tracked_task(name, {
+        let log = log.clone();
+        let tm = tm.clone();
+        async move {
+            let mut listener = Box::pin(listener);
+            let (shutdown_tx, shutdown_rx) = watch::channel(false);
+            let mut conns = JoinSet::new();
+            loop {
+                select!{
+                    _ = tm.until_terminate() => break,
+                    conn = listener.next() => {
+                        let Some(conn) = conn else {
+                            break;
+                        };
+                        conns.spawn(handle(conn, ShutdownSignal(shutdown_rx.clone())));
+                    },
+                    // Clean up finished connections
+                    Some(_) = conns.join_next(), if !conns.is_empty() => { },
+                }
+            }
+            drop(listener);
+            _ = shutdown_tx.send(true);
+            if conns.is_empty() {
+                return;
+            }
+            if timeout(grace, async {
+                while conns.join_next().await.is_some() { }
+            }).await.is_err() {
+                log.log_with(
+                    loga::WARN,
+                    "Connections still open after shutdown grace period, aborting",
+                    ea!(count = conns.len()),
+                );
+                conns.abort_all();
+            }
+        }
+    });
+}
+
+/// Run a connection future (ex: a hyper connection) until it finishes. If shutdown
+/// is signaled first, call `graceful` (ex: hyper's `graceful_shutdown`) and keep
+/// running it until it finishes its in-flight requests.
+pub fn drain_conn<F: Future, G: FnOnce(Pin<&mut F>)>(conn: F, graceful: G, shutdown: ShutdownSignal) -> DrainConn<F, G> {
+    return DrainConn {
+        conn: Box::pin(conn),
+        graceful: Some(graceful),
+        shutdown: Some(shutdown.into_wait()),
+    };
+}
+
+/// Future returned by `drain_conn`. This is a plain struct rather than an `async
+/// fn` so that proving a connection task is `Send` doesn't require re-proving
+/// hyper's `Future` bounds on the connection, which rustc can't do for the
+/// higher-ranked lifetimes in the request handlers.
+pub struct DrainConn<F, G> {
+    conn: Pin<Box<F>>,
+    graceful: Option<G>,
+    shutdown: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl<F, G> Unpin for DrainConn<F, G> { }
+
+impl<F: Future, G: FnOnce(Pin<&mut F>)> Future for DrainConn<F, G> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(shutdown) = &mut self.shutdown {
+            if shutdown.as_mut().poll(cx).is_ready() {
+                self.shutdown = None;
+                let graceful = self.graceful.take().unwrap();
+                graceful(self.conn.as_mut());
+            }
+        }
+        return self.conn.as_mut().poll(cx);
+    }
+}
+
+/// Like `htserve::handler::root_handle_https`, but closing gracefully on shutdown.
+/// If the client authenticated with a cert bound to an identity, requests get a
+/// `ClientCertIdentity` extension.
+pub async fn handle_https_conn(
+    tls_acceptor: TlsAcceptor,
+    handler: Arc<dyn Handler<htserve::responses::Body>>,
+    stream: TcpStream,
+    shutdown: ShutdownSignal,
+) -> Result<(), loga::Error> {
+    let peer_addr = stream.peer_addr().context("Error getting peer address of connection")?;
+    let stream = tls_acceptor.accept(stream).await.context("Error during TLS handshake")?;
+    let client_identity =
+        stream.get_ref().1.peer_certificates().and_then(|c| c.first()).and_then(|c| cert_der_identity(c));
+    drain_conn(
+        hyper_util::server::conn::auto::Builder::new(TokioExecutor::new()).serve_connection(
+            TokioIo::new(stream),
+            hyper::service::service_fn(move |req: Request<Incoming>| {
+                let handler = handler.clone();
+                let client_identity = client_identity;
+                async move {
+                    let (mut head, body) = req.into_parts();
+                    if let Some(identity) = client_identity {
+                        head.extensions.insert(ClientCertIdentity(identity));
+                    }
+                    let path = head.uri.path().to_string();
+                    let query = head.uri.query().unwrap_or("").to_string();
+                    return Ok(handler.handle(HandlerArgs {
+                        peer_addr: peer_addr,
+                        query: &query,
+                        head: &head,
+                        subpath: &path,
+                        body: body,
+                    }).await) as Result<_, Infallible>;
+                }
+            }),
+        ),
+        |c| c.graceful_shutdown(),
+        shutdown,
+    )
+        .await
+        .map_err(|e| loga::err_with("Error serving HTTP connection", ea!(err = e)))?;
+    return Ok(());
+}
@@ -1,5 +1,6 @@
 use {
     std::{
+        path::Path,
         sync::{
             Arc,
             Mutex,
@@ -13,8 +14,12 @@ use {
     crate::{
         interface::{
             config::{
-                identity::LocalIdentitySecret,
+                identity::{
+                    LocalIdentityFile,
+                    LocalIdentitySecret,
+                },
                 shared::IdentitySecretArg,
+                ENV_IDENTITY_PASSPHRASE,
             },
             stored::identity::{
                 Identity,
@@ -30,6 +35,9 @@ use {
 pub trait IdentitySigner: Send {
     fn identity(&mut self) -> Result<Identity, loga::Error>;
     fn sign(&mut self, data: &[u8]) -> Result<(Identity, Blob), loga::Error>;
+    /// Sign `data` directly with the identity key, without the hashing done for
+    /// identity signatures. Used for SSH user authentication.
+    fn sign_raw(&mut self, data: &[u8]) -> Result<Blob, loga::Error>;
 }
 
 impl IdentitySigner for LocalIdentitySecret {
@@ -40,6 +48,10 @@ impl IdentitySigner for LocalIdentitySecret {
     fn identity(&mut self) -> Result<Identity, loga::Error> {
         return Ok(LocalIdentitySecret::identity(self));
     }
+
+    fn sign_raw(&mut self, data: &[u8]) -> Result<Blob, loga::Error> {
+        return Ok(LocalIdentitySecret::sign_raw(self, data));
+    }
 }
 
 #[cfg(feature = "card")]
@@ -144,18 +156,93 @@ mod card {
                 ),
             );
         }
+
+        fn sign_raw(&mut self, data: &[u8]) -> Result<Blob, loga::Error> {
+            let mut transaction = self.card.transaction().context("Failed to start card transaction")?;
+            transaction
+                .verify_user_for_signing(self.pin.as_bytes())
+                .context_with("Error unlocking card with pin", ea!(card = self.pcsc_id))?;
+            let mut user = transaction.signing_card().unwrap();
+            let signer_interact = || eprintln!("Card {} requests interaction to sign", self.pcsc_id);
+            let mut signer = user.signer(&signer_interact).context("Failed to get signer from card")?;
+
+            // For EdDSA the card signs the "digest" as the message, so passing the data
+            // directly gets a plain ed25519 signature
+            return Ok(
+                extract_pgp_ed25519_sig(
+                    &signer
+                        .sign(HashAlgorithm::SHA512, data)
+                        .map_err(|e| loga::err_with("Card signature failed", ea!(err = e)))?,
+                )
+                    .to_bytes()
+                    .blob(),
+            );
+        }
+    }
+}
+
+/// The identity passphrase from the environment, if set.
+fn env_identity_passphrase() -> Result<Option<String>, loga::Error> {
+    match std::env::var(ENV_IDENTITY_PASSPHRASE) {
+        Ok(p) => return Ok(Some(p)),
+        Err(e) => match e {
+            std::env::VarError::NotPresent => return Ok(None),
+            std::env::VarError::NotUnicode(_) => {
+                return Err(loga::err_with("Error parsing env var as unicode", ea!(env = ENV_IDENTITY_PASSPHRASE)));
+            },
+        },
+    }
+}
+
+/// Get the passphrase for an encrypted local identity, from the environment or
+/// by prompting.
+pub fn get_identity_passphrase() -> Result<String, loga::Error> {
+    if let Some(p) = env_identity_passphrase()? {
+        return Ok(p);
+    }
+    return rpassword::prompt_password(
+        "Enter passphrase for identity file: ",
+    ).context("Error securely reading passphrase");
+}
+
+/// Get the passphrase for encrypting a new local identity, from the environment or
+/// by prompting twice (to catch typos that would make the identity unrecoverable).
+pub fn get_new_identity_passphrase() -> Result<String, loga::Error> {
+    if let Some(p) = env_identity_passphrase()? {
+        return Ok(p);
+    }
+    let passphrase =
+        rpassword::prompt_password("Enter new passphrase for identity file: ").context(
+            "Error securely reading passphrase",
+        )?;
+    let confirm =
+        rpassword::prompt_password("Confirm passphrase: ").context("Error securely reading passphrase")?;
+    if passphrase != confirm {
+        return Err(loga::err("Passphrases don't match"));
+    }
+    return Ok(passphrase);
+}
+
+/// Read a local identity file, decrypting it if it's encrypted.
+pub async fn load_local_identity_secret(path: &Path) -> Result<LocalIdentitySecret, loga::Error> {
+    let log = &Log::new().fork(ea!(path = path.to_string_lossy()));
+    let ident_data =
+        LocalIdentityFile::from_bytes(
+            &read(path).await.stack_context(log, "Error reading identity file")?,
+        ).stack_context(log, "Error parsing identity file")?;
+    match ident_data {
+        LocalIdentityFile::Plain(s) => return Ok(s),
+        LocalIdentityFile::Encrypted(s) => {
+            let passphrase = get_identity_passphrase().stack_context(log, "Error getting passphrase")?;
+            return s.decrypt(&passphrase).stack_context(log, "Error decrypting identity file");
+        },
     }
 }
 
 pub async fn get_identity_signer(ident: IdentitySecretArg) -> Result<Arc<Mutex<dyn IdentitySigner>>, loga::Error> {
     match ident {
         IdentitySecretArg::Local(ident_config) => {
-            let log = &Log::new().fork(ea!(path = ident_config.to_string_lossy()));
-            let ident_data =
-                serde_json::from_slice::<LocalIdentitySecret>(
-                    &read(&ident_config).await.stack_context(log, "Error reading identity file")?,
-                ).stack_context(log, "Error parsing json in identity file")?;
-            return Ok(Arc::new(Mutex::new(ident_data)));
+            return Ok(Arc::new(Mutex::new(load_local_identity_secret(&ident_config).await?)));
         },
         #[cfg(feature = "card")]
         IdentitySecretArg::Card { pcsc_id, pin } => {
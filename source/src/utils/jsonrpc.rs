@@ -0,0 +1,183 @@
+//! JSON-RPC 2.0 over HTTP, as an alternative to the REST endpoints. Only single
+//! (non-batch) requests are supported.
+use {
+    super::recent_errors::log_warn_err,
+    htwrap::htserve::{
+        self,
+        responses::{
+            response_200,
+            response_200_json,
+        },
+    },
+    http::Response,
+    loga::{
+        ea,
+        Log,
+    },
+    serde::{
+        de::DeserializeOwned,
+        Deserialize,
+        Serialize,
+    },
+    serde_json::Value,
+    std::future::Future,
+};
+
+pub const ERR_PARSE: i64 = -32700;
+pub const ERR_INVALID_REQUEST: i64 = -32600;
+pub const ERR_METHOD_NOT_FOUND: i64 = -32601;
+pub const ERR_INVALID_PARAMS: i64 = -32602;
+pub const ERR_INTERNAL: i64 = -32603;
+
+/// The identity isn't allowed to publish here (REST `401`)
+pub const ERR_UNAUTHORIZED: i64 = -32001;
+
+/// `if_version` didn't match; `data` is the current version (REST `409`)
+pub const ERR_VERSION_MISMATCH: i64 = -32002;
+
+/// The publisher database is over its size limit (REST `507`)
+pub const ERR_STORAGE_FULL: i64 = -32003;
+
+/// The requested data isn't available (REST `404`)
+pub const ERR_NOT_FOUND: i64 = -32004;
+
+/// The publisher's publish policy rejected the request (REST `403`)
+pub const ERR_REJECTED: i64 = -32005;
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize)]
+struct ResponseError {
+    code: i64,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Value>,
+}
+
+#[derive(Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ResponseError>,
+}
+
+pub enum RpcError {
+    MethodNotFound,
+    InvalidParams(String),
+    App {
+        code: i64,
+        message: String,
+        data: Option<Value>,
+    },
+    Internal(loga::Error),
+}
+
+impl From<loga::Error> for RpcError {
+    fn from(value: loga::Error) -> Self {
+        return RpcError::Internal(value);
+    }
+}
+
+pub fn app_err(code: i64, message: impl ToString) -> RpcError {
+    return RpcError::App {
+        code: code,
+        message: message.to_string(),
+        data: None,
+    };
+}
+
+pub fn invalid_params(message: impl ToString) -> RpcError {
+    return RpcError::InvalidParams(message.to_string());
+}
+
+pub fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    return serde_json::from_value(params).map_err(invalid_params);
+}
+
+pub fn result<T: Serialize>(value: T) -> Result<Value, RpcError> {
+    return Ok(serde_json::to_value(value).unwrap());
+}
+
+fn error_response(id: Value, code: i64, message: String, data: Option<Value>) -> Response<htserve::responses::Body> {
+    return response_200_json(RpcResponse {
+        jsonrpc: "2.0",
+        id: id,
+        result: None,
+        error: Some(ResponseError {
+            code: code,
+            message: message,
+            data: data,
+        }),
+    });
+}
+
+/// Parse a request body and call `dispatch` with the method and params. Errors are
+/// returned as JSON-RPC errors with status 200. Notifications (requests without an
+/// `id`) get an empty response.
+pub async fn handle<
+    F: FnOnce(String, Value) -> Fut,
+    Fut: Future<Output = Result<Value, RpcError>>,
+>(log: &Log, body: &[u8], dispatch: F) -> Response<htserve::responses::Body> {
+    let req = match serde_json::from_slice::<Value>(body) {
+        Ok(r) => r,
+        Err(e) => {
+            return error_response(Value::Null, ERR_PARSE, e.to_string(), None);
+        },
+    };
+    let req = match serde_json::from_value::<Request>(req) {
+        Ok(r) => r,
+        Err(e) => {
+            return error_response(Value::Null, ERR_INVALID_REQUEST, e.to_string(), None);
+        },
+    };
+    if req.jsonrpc != "2.0" {
+        return error_response(
+            req.id.unwrap_or_default(),
+            ERR_INVALID_REQUEST,
+            "Only JSON-RPC 2.0 is supported".to_string(),
+            None,
+        );
+    }
+    let method = req.method.clone();
+    let res = dispatch(req.method, req.params).await;
+    let Some(id) = req.id else {
+        if let Err(RpcError::Internal(e)) = res {
+            log_warn_err(log, e.context_with("Error handling JSON-RPC notification", ea!(method = method)));
+        }
+        return response_200();
+    };
+    match res {
+        Ok(v) => {
+            return response_200_json(RpcResponse {
+                jsonrpc: "2.0",
+                id: id,
+                result: Some(v),
+                error: None,
+            });
+        },
+        Err(RpcError::MethodNotFound) => {
+            return error_response(id, ERR_METHOD_NOT_FOUND, format!("Unknown method [{}]", method), None);
+        },
+        Err(RpcError::InvalidParams(e)) => {
+            return error_response(id, ERR_INVALID_PARAMS, e, None);
+        },
+        Err(RpcError::App { code, message, data }) => {
+            return error_response(id, code, message, data);
+        },
+        Err(RpcError::Internal(e)) => {
+            log_warn_err(log, e.context_with("Error handling JSON-RPC request", ea!(method = method)));
+            return error_response(id, ERR_INTERNAL, "Internal error".to_string(), None);
+        },
+    }
+}
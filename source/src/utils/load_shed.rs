@@ -0,0 +1,154 @@
+//! Refusing API requests with `429` when too many are being handled at once
+//! (`ApiConfig::load_shedding`), instead of letting them queue without bound.
+use {
+    super::{
+        admin_auth::is_admin_path,
+        api_error::response_overloaded,
+    },
+    async_trait::async_trait,
+    htwrap::htserve::{
+        self,
+        handler::{
+            Handler,
+            HandlerArgs,
+        },
+    },
+    http::Response,
+    schemars::JsonSchema,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    std::sync::{
+        atomic::{
+            AtomicBool,
+            AtomicU64,
+            AtomicUsize,
+            Ordering,
+        },
+        Arc,
+    },
+};
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct LoadShedStats {
+    /// Requests being handled now
+    pub in_flight: usize,
+    /// The limit for `in_flight`
+    pub max_in_flight: usize,
+    /// Requests refused with `429` since startup
+    pub shed: u64,
+    /// Whether new requests are being refused now
+    pub overloaded: bool,
+}
+
+struct LoadShedInner {
+    max_in_flight: usize,
+    retry_after: u32,
+    in_flight: AtomicUsize,
+    shed: AtomicU64,
+    // Whether the queue was too deep when the last request arrived
+    queue_full: AtomicBool,
+}
+
+/// Limits and counters for one API, shared between its handler and health
+/// reporting.
+#[derive(Clone)]
+pub struct LoadShed(Arc<LoadShedInner>);
+
+impl LoadShed {
+    pub fn new(max_in_flight: usize, retry_after: u32) -> LoadShed {
+        return LoadShed(Arc::new(LoadShedInner {
+            max_in_flight: max_in_flight,
+            retry_after: retry_after,
+            in_flight: AtomicUsize::new(0),
+            shed: AtomicU64::new(0),
+            queue_full: AtomicBool::new(false),
+        }));
+    }
+
+    /// Whether new requests would be refused now.
+    pub fn overloaded(&self) -> bool {
+        return self.0.in_flight.load(Ordering::Relaxed) >= self.0.max_in_flight ||
+            self.0.queue_full.load(Ordering::Relaxed);
+    }
+
+    pub fn retry_after(&self) -> u32 {
+        return self.0.retry_after;
+    }
+
+    pub fn stats(&self) -> LoadShedStats {
+        return LoadShedStats {
+            in_flight: self.0.in_flight.load(Ordering::Relaxed),
+            max_in_flight: self.0.max_in_flight,
+            shed: self.0.shed.load(Ordering::Relaxed),
+            overloaded: self.overloaded(),
+        };
+    }
+}
+
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl<'a> Drop for InFlightGuard<'a> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Whether a request should be refused, given the requests already being handled
+/// and (optionally) the depth of a queue the request would wait in.
+fn should_shed(in_flight: usize, max_in_flight: usize, queue: Option<(usize, usize)>) -> bool {
+    if in_flight >= max_in_flight {
+        return true;
+    }
+    if let Some((depth, max_depth)) = queue {
+        if depth >= max_depth {
+            return true;
+        }
+    }
+    return false;
+}
+
+/// Wraps an API's handler, refusing requests with `429` and `Retry-After` when the
+/// API is handling too many requests, or when `queue` (the current depth of a
+/// queue requests wait in, and the limit) is too deep. Admin endpoints are never
+/// refused.
+pub struct LoadShedHandler {
+    pub inner: Arc<dyn Handler<htserve::responses::Body>>,
+    pub shed: LoadShed,
+    pub queue: Option<(Box<dyn Fn() -> usize + Send + Sync>, usize)>,
+}
+
+#[async_trait]
+impl Handler<htserve::responses::Body> for LoadShedHandler {
+    async fn handle(&self, args: HandlerArgs<'_>) -> Response<htserve::responses::Body> {
+        if is_admin_path(args.head.uri.path()) {
+            return self.inner.handle(args).await;
+        }
+        let state = &self.shed.0;
+        let queue = self.queue.as_ref().map(|(depth, max)| (depth(), *max));
+        let queue_full = queue.is_some_and(|(depth, max)| depth >= max);
+        state.queue_full.store(queue_full, Ordering::Relaxed);
+        let in_flight = state.in_flight.fetch_add(1, Ordering::Relaxed);
+        let _guard = InFlightGuard(&state.in_flight);
+        if should_shed(in_flight, state.max_in_flight, queue) {
+            state.shed.fetch_add(1, Ordering::Relaxed);
+            return response_overloaded(state.retry_after);
+        }
+        return self.inner.handle(args).await;
+    }
+}
+
+#[cfg(test)]
+mod test_load_shed {
+    use super::should_shed;
+
+    #[test]
+    fn test_should_shed() {
+        assert!(!should_shed(0, 1, None));
+        assert!(should_shed(1, 1, None));
+        assert!(!should_shed(3, 10, Some((4, 5))));
+        assert!(should_shed(3, 10, Some((5, 5))));
+    }
+}
@@ -1,15 +1,49 @@
 use {
     super::fs_util::{
-        write,
+        write_private_atomic,
+    },
+    crate::interface::config::identity::{
+        EncryptedLocalIdentitySecret,
+        LocalIdentityFile,
+        LocalIdentitySecret,
     },
-    crate::interface::config::identity::LocalIdentitySecret,
     loga::ResultContext,
-    std::path::Path,
+    std::{
+        env,
+        path::{
+            Path,
+            PathBuf,
+        },
+    },
 };
 
+/// The standard directory for the current user's local identity files,
+/// `spaghettinuum/identities` in `$XDG_CONFIG_HOME` (or `~/.config`). `None` if
+/// neither can be determined.
+pub fn user_identity_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(d) => PathBuf::from(d),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    return Some(base.join("spaghettinuum").join("identities"));
+}
+
 pub async fn write_identity_secret(path: &Path, identity: &LocalIdentitySecret) -> Result<(), loga::Error> {
-    write(path, serde_json::to_string_pretty(identity).unwrap().as_bytes())
+    write_private_atomic(path, LocalIdentityFile::Plain(identity.clone()).to_armored(&identity.identity()).as_bytes())
         .await
         .context("Failed to write identity secret to file")?;
     return Ok(());
 }
+
+pub async fn write_encrypted_identity_secret(
+    path: &Path,
+    identity: &LocalIdentitySecret,
+    passphrase: &str,
+) -> Result<(), loga::Error> {
+    let encrypted =
+        EncryptedLocalIdentitySecret::encrypt(identity, passphrase).context("Failed to encrypt identity secret")?;
+    write_private_atomic(path, LocalIdentityFile::Encrypted(encrypted).to_armored(&identity.identity()).as_bytes())
+        .await
+        .context("Failed to write encrypted identity secret to file")?;
+    return Ok(());
+}
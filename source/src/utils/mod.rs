@@ -22,6 +22,7 @@ pub mod versioned;
 pub mod misc_tests;
 pub mod system_addr;
 pub mod unstable_ip;
+pub mod armor;
 pub mod local_identity;
 pub mod identity_secret;
 pub mod tls_util;
@@ -32,6 +33,23 @@ pub mod blob;
 pub mod signed;
 pub mod fs_util;
 pub mod ssh_util;
+pub mod ssh_agent;
+pub mod unix_http;
+pub mod graceful;
+pub mod recent_errors;
+pub mod request_id;
+pub mod compress;
+pub mod jsonrpc;
+pub mod udp_batch;
+pub mod api_error;
+pub mod admin_auth;
+pub mod zone_import;
+pub mod privacy;
+pub mod task_status;
+pub mod reference_chain;
+pub mod vanity_identity;
+pub mod value_encryption;
+pub mod load_shed;
 
 #[derive(Clone)]
 pub struct AsyncBus<T: Clone + Unpin>(Arc<Mutex<Vec<ManualFutureCompleter<T>>>>);
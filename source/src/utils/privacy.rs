@@ -0,0 +1,311 @@
+//! Privacy mode, for operators who want to minimize the identifying data the node
+//! logs and keeps, plus an inventory of what data each subsystem retains.
+//!
+//! Privacy mode is process-wide and set once at startup. When enabled:
+//!
+//! * Identities and addresses in INFO and WARN logs are truncated or masked (use
+//!   `redact_id` and `redact_addr` when logging them at those levels). DEBUG logs,
+//!   which are only produced when explicitly enabled, aren't redacted.
+//!
+//! * Per-peer node traffic accounting is disabled - all traffic is counted under
+//!   `other`.
+//!
+//! * Content access logs mask client addresses.
+use std::{
+    fmt::Display,
+    net::{
+        IpAddr,
+        SocketAddr,
+    },
+    sync::atomic::{
+        AtomicBool,
+        Ordering,
+    },
+};
+
+static PRIVACY_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Number of characters of identifiers kept in privacy mode.
+const ID_PREFIX_LEN: usize = 6;
+
+pub fn set_privacy_mode(enabled: bool) {
+    PRIVACY_MODE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn privacy_mode() -> bool {
+    return PRIVACY_MODE.load(Ordering::Relaxed);
+}
+
+fn redact_id_(enabled: bool, id: &str) -> String {
+    if !enabled || id.chars().count() <= ID_PREFIX_LEN {
+        return id.to_string();
+    }
+    return format!("{}…", id.chars().take(ID_PREFIX_LEN).collect::<String>());
+}
+
+/// An identity or node identity for logging: truncated in privacy mode.
+pub fn redact_id(id: impl Display) -> String {
+    return redact_id_(privacy_mode(), &id.to_string());
+}
+
+fn redact_ip_(enabled: bool, ip: &IpAddr, display: String) -> String {
+    if !enabled {
+        return display;
+    }
+    match ip {
+        IpAddr::V4(_) => return "ipv4".to_string(),
+        IpAddr::V6(_) => return "ipv6".to_string(),
+    }
+}
+
+/// An address for logging: only the address family in privacy mode.
+pub fn redact_addr(addr: &SocketAddr) -> String {
+    return redact_ip_(privacy_mode(), &addr.ip(), addr.to_string());
+}
+
+/// An IP for logging: only the address family in privacy mode.
+pub fn redact_ip(ip: &IpAddr) -> String {
+    return redact_ip_(privacy_mode(), ip, ip.to_string());
+}
+
+/// Which subsystem keeps data.
+#[derive(Clone, Copy, Debug)]
+pub enum Subsystem {
+    Node,
+    Resolver,
+    Publisher,
+    Content,
+    Daemon,
+    /// The `spagh` command line tool, on the client's machine
+    Cli,
+}
+
+/// Where retained data is kept.
+#[derive(Clone, Copy, Debug)]
+pub enum Storage {
+    /// Lost at restart
+    Memory,
+    /// In the subsystem's database
+    Disk,
+}
+
+/// How privacy mode changes what's kept.
+#[derive(Clone, Copy, Debug)]
+pub enum PrivacyModeEffect {
+    /// Needed for the subsystem to work
+    Unchanged,
+    /// Not collected
+    Disabled,
+    /// Kept without identifying details
+    Aggregated,
+}
+
+/// A kind of data a subsystem keeps.
+#[derive(Clone, Copy, Debug)]
+pub struct Retention {
+    pub subsystem: Subsystem,
+    pub data: &'static str,
+    pub storage: Storage,
+    /// How long it's kept
+    pub kept: &'static str,
+    pub privacy_mode: PrivacyModeEffect,
+}
+
+/// What each subsystem retains, beyond logs. The table in the privacy mode section
+/// of `reference_spagh_node.md` is generated from this (see `retention_markdown`)
+/// and a test checks they match.
+pub const RETENTION: &[Retention] = &[
+    Retention {
+        subsystem: Subsystem::Node,
+        data: "Routing table: neighbor node identities and addresses",
+        storage: Storage::Disk,
+        kept: "Until the neighbor is replaced",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Node,
+        data: "Announcements stored for other nodes (identity, publisher addresses)",
+        storage: Storage::Memory,
+        kept: "Until evicted or replaced",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Node,
+        data: "Traffic counts per peer node identity or address",
+        storage: Storage::Disk,
+        kept: "7 days",
+        privacy_mode: PrivacyModeEffect::Aggregated,
+    },
+    Retention {
+        subsystem: Subsystem::Node,
+        data: "Protocol versions and store capability of neighbors",
+        storage: Storage::Memory,
+        kept: "Until the neighbor leaves the routing table",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Node,
+        data: "Average response times of neighbors",
+        storage: Storage::Memory,
+        kept: "Until the neighbor leaves the routing table",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Node,
+        data: "Fastest responding neighbors (with `proximity_index`)",
+        storage: Storage::Memory,
+        kept: "Until the neighbor leaves the routing table or faster neighbors replace it",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Node,
+        data: "Identities of neighbors pruned for being unresponsive",
+        storage: Storage::Memory,
+        kept: "`pruned_peer_memory` (default 60 minutes)",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Resolver,
+        data: "Resolved identities, keys, and values",
+        storage: Storage::Disk,
+        kept: "Until the values expire (persisted at shutdown)",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Resolver,
+        data: "Announcements (identity to publisher addresses) and missing identities",
+        storage: Storage::Memory,
+        kept: "Announcement cache TTL",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Resolver,
+        data: "Identities with recently reported suspect values",
+        storage: Storage::Memory,
+        kept: "1 minute",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Publisher,
+        data: "Published records and announcements",
+        storage: Storage::Disk,
+        kept: "Until unpublished (or purged, with a retention policy)",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Publisher,
+        data: "Last activity time per published identity, and retention events",
+        storage: Storage::Disk,
+        kept: "Until the identity is cleared (events are kept)",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Publisher,
+        data: "Read counts per published key",
+        storage: Storage::Memory,
+        kept: "Until restart",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Publisher,
+        data: "Usage (requests and bytes) per published identity",
+        storage: Storage::Disk,
+        kept: "400 days",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Content,
+        data: "Access log lines with client addresses",
+        storage: Storage::Memory,
+        kept: "Not kept, only logged",
+        privacy_mode: PrivacyModeEffect::Aggregated,
+    },
+    Retention {
+        subsystem: Subsystem::Daemon,
+        data: "Recent warning messages",
+        storage: Storage::Memory,
+        kept: "Last 32",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Daemon,
+        data: "Database backups (with `backup`)",
+        storage: Storage::Disk,
+        kept: "The newest `backup.keep`",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Cli,
+        data: "Resolver responses (`spagh get`, `http`, `ssh` cache)",
+        storage: Storage::Disk,
+        kept: "Until the values expire",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+    Retention {
+        subsystem: Subsystem::Cli,
+        data: "Signed publish requests (with `SPAGH_PUBLISH_SPOOL`)",
+        storage: Storage::Disk,
+        kept: "Until sent, or dropped after 5 rejections",
+        privacy_mode: PrivacyModeEffect::Unchanged,
+    },
+];
+
+/// `RETENTION` as a markdown table.
+pub fn retention_markdown() -> String {
+    let mut out = String::new();
+    out.push_str("| Subsystem | Data | Where | How long | In privacy mode |\n");
+    out.push_str("| --- | --- | --- | --- | --- |\n");
+    for r in RETENTION {
+        let privacy_mode = match r.privacy_mode {
+            PrivacyModeEffect::Unchanged => "Unchanged",
+            PrivacyModeEffect::Disabled => "Not collected",
+            PrivacyModeEffect::Aggregated => "Without identifying details",
+        };
+        out.push_str(
+            &format!("| {:?} | {} | {:?} | {} | {} |\n", r.subsystem, r.data, r.storage, r.kept, privacy_mode),
+        );
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod test_privacy {
+    use {
+        super::{
+            redact_id_,
+            redact_ip_,
+            retention_markdown,
+        },
+        std::{
+            net::SocketAddr,
+            path::PathBuf,
+        },
+    };
+
+    #[test]
+    fn test_retention_documented() {
+        let reference =
+            std::fs::read_to_string(
+                PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../readme/reference_spagh_node.md"),
+            ).unwrap();
+        assert!(
+            reference.contains(&retention_markdown()),
+            "Retention table in reference_spagh_node.md is out of date, replace it with:\n{}",
+            retention_markdown()
+        );
+    }
+
+    #[test]
+    fn test_redact_id() {
+        assert_eq!(redact_id_(false, "yryyyyyyyyei1n3e"), "yryyyyyyyyei1n3e");
+        assert_eq!(redact_id_(true, "yryyyyyyyyei1n3e"), "yryyyy…");
+        assert_eq!(redact_id_(true, "abc"), "abc");
+    }
+
+    #[test]
+    fn test_redact_addr() {
+        let addr = SocketAddr::from(([10, 0, 0, 1], 1000));
+        assert_eq!(redact_ip_(false, &addr.ip(), addr.to_string()), "10.0.0.1:1000");
+        assert_eq!(redact_ip_(true, &addr.ip(), addr.to_string()), "ipv4");
+    }
+}
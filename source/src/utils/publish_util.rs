@@ -4,6 +4,10 @@ use {
         fs_util,
         identity_secret::IdentitySigner,
         signed::IdentSignatureMethods,
+        unstable_ip::{
+            UnstableIpv4,
+            UnstableIpv6,
+        },
     },
     crate::{
         interface::{
@@ -12,27 +16,64 @@ use {
                 announcement::latest::AnnouncementPublisher,
                 identity::Identity,
                 record::{
+                    addr_pref_record::{
+                        build_addr_pref_key,
+                        latest::IpFamily,
+                    },
+                    delegate_record::KEY_SUFFIX_DELEGATE,
                     dns_record::{
                         build_dns_key,
                         RecordType,
+                        KEY_SUFFIX_DNS_A,
+                        KEY_SUFFIX_DNS_AAAA,
+                        KEY_SUFFIX_DNS_MX,
+                        KEY_SUFFIX_DNS_TXT,
+                        MAX_DNS_RDATA_BYTES,
+                        MAX_DNS_VALUES,
+                    },
+                    encrypted_record::is_encrypted,
+                    handoff_record::KEY_SUFFIX_HANDOFF,
+                    key_alias_record::{
+                        build_key_alias_key,
+                        KeyAlias,
+                        KEY_ALIAS_MAX_HOPS,
+                        KEY_SUFFIX_KEY_ALIAS,
+                    },
+                    key_registry::{
+                        classify_key_suffix,
+                        KeySuffixKind,
+                    },
+                    record_utils::{
+                        join_record_key,
+                        normalize_record_key,
+                        split_dns_name,
+                        RecordKey,
+                        RecordRoot,
                     },
-                    record_utils::RecordKey,
                     RecordValue,
                 },
                 shared::SerialAddr,
             },
             wire::{
                 self,
-                api::publish::v1::InfoResponse,
+                api::publish::v1::{
+                    InfoResponse,
+                    RecordProblem,
+                },
             },
         },
+        publishing::spool::PublishSpool,
         resolving::{
             connect_publisher_node,
             UrlPair,
         },
         service::publisher::API_ROUTE_PUBLISH,
     },
-    chrono::Utc,
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    hickory_resolver::Name,
     htwrap::htreq,
     loga::{
         ea,
@@ -45,7 +86,9 @@ use {
             HashMap,
             HashSet,
         },
+        net::IpAddr,
         path::PathBuf,
+        str::FromStr,
         sync::{
             Arc,
             Mutex,
@@ -58,10 +101,14 @@ pub fn generate_publish_announce(
     publishers_info: Vec<InfoResponse>,
 ) -> Result<(Identity, stored::announcement::Announcement), String> {
     let announce_message = bincode::serialize(&stored::announcement::latest::AnnouncementContent {
-        publishers: publishers_info.into_iter().map(|info| AnnouncementPublisher {
-            addr: SerialAddr(info.advertise_addr),
-            cert_hash: info.cert_pub_hash,
-        }).collect(),
+        // Each address is listed separately with the same cert; resolvers treat entries
+        // with the same cert as one publisher
+        publishers: publishers_info.into_iter().flat_map(|info| info.addrs().into_iter().map(move |addr| {
+            AnnouncementPublisher {
+                addr: SerialAddr(addr),
+                cert_hash: info.cert_pub_hash.clone(),
+            }
+        })).collect(),
         announced: Utc::now(),
     }).unwrap().blob();
     let (identity, request_message_sig) =
@@ -73,12 +120,15 @@ pub fn generate_publish_announce(
     })));
 }
 
+/// Announce the publishers as authoritative for the identity. Returns each
+/// publisher's URL and how the announcement was stored in the DHT (`None` if the
+/// publisher doesn't report this).
 pub async fn announce(
     log: &Log,
     resolvers: &[UrlPair],
     publishers: &[UrlPair],
     identity_signer: &Arc<Mutex<dyn IdentitySigner>>,
-) -> Result<(), loga::Error> {
+) -> Result<Vec<(String, Option<wire::api::publish::latest::AnnounceResponse>)>, loga::Error> {
     let mut publishers_info = vec![];
     for s in publishers {
         let url = s.join("publish/v1/info");
@@ -116,20 +166,37 @@ pub async fn announce(
         identity: identity,
         announcement: announcement,
     };
+    let mut out = vec![];
     for s in publishers {
         let url = s.join("publish/v1/announce");
-        htreq::post(
-            log,
-            &mut connect_publisher_node(log, resolvers, &url).await.context("Error connecting to publisher")?,
-            &url.url,
-            &HashMap::new(),
-            serde_json::to_vec(&request).unwrap(),
-            100,
-        )
-            .await
-            .context("Error making announce request")?;
+        let body =
+            htreq::post(
+                log,
+                &mut connect_publisher_node(log, resolvers, &url).await.context("Error connecting to publisher")?,
+                &url.url,
+                &HashMap::new(),
+                serde_json::to_vec(&request).unwrap(),
+                1024,
+            )
+                .await
+                .context("Error making announce request")?;
+        let resp = if body.is_empty() {
+            // Older publishers don't return a body
+            None
+        } else {
+            Some(
+                serde_json::from_slice::<wire::api::publish::latest::AnnounceResponse>(
+                    &body,
+                ).stack_context_with(
+                    log,
+                    "Error parsing announce response from publisher as json",
+                    ea!(body = String::from_utf8_lossy(&body)),
+                )?,
+            )
+        };
+        out.push((url.url.to_string(), resp));
     }
-    return Ok(());
+    return Ok(out);
 }
 
 #[derive(Default, Clone)]
@@ -143,15 +210,354 @@ pub struct PublishArgs {
     pub clear: HashSet<RecordKey>,
     /// Start publishing values for keys
     pub set: HashMap<RecordKey, RecordValue>,
+    /// Only apply changes if the current record set version matches this
+    pub if_version: Option<String>,
+    /// When the values were published, defaults to now. `publish` always sends the
+    /// current time.
+    pub published: Option<DateTime<Utc>>,
+    /// Don't check `set` for common mistakes before publishing
+    pub no_lint: bool,
 }
 
-pub async fn publish(
+fn lint_ip_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            return ip.unstable_is_private() || ip.unstable_is_loopback() || ip.unstable_is_link_local() ||
+                ip.unstable_is_unspecified() || ip.unstable_is_shared();
+        },
+        IpAddr::V6(ip) => {
+            return ip.unstable_is_loopback() || ip.unstable_is_unspecified() || ip.unstable_is_unique_local() ||
+                ip.unstable_is_unicast_link_local();
+        },
+    }
+}
+
+/// Check records to be published for common mistakes that would otherwise only
+/// show up as broken resolution later. Returns a warning message for each problem
+/// found. This only looks at `set` - records already published aren't considered.
+pub fn lint_records(identity: &Identity, set: &HashMap<RecordKey, RecordValue>) -> Vec<String> {
+    let mut out = vec![];
+    for (key, value) in set {
+        let key_str = join_record_key(key);
+        let RecordValue::V1(value) = value;
+        let Some(data) = &value.data else {
+            continue;
+        };
+        if value.ttl <= 0 {
+            out.push(format!("[{}] TTL is {}, resolvers won't cache this value", key_str, value.ttl));
+        }
+        let Some((suffix, head)) = key.split_last() else {
+            continue;
+        };
+        match classify_key_suffix(suffix) {
+            KeySuffixKind::ShadowsWellKnown(well_known) => {
+                out.push(
+                    format!(
+                        "[{}] Record type {} only differs from the predefined {} by case, it won't be treated as one",
+                        key_str,
+                        suffix,
+                        well_known
+                    ),
+                );
+            },
+            KeySuffixKind::ReservedNamespace(namespace) => {
+                out.push(
+                    format!(
+                        "[{}] Record type {} is in the reserved {}/ namespace, use VENDOR/NAME for custom record types",
+                        key_str,
+                        suffix,
+                        namespace
+                    ),
+                );
+            },
+            KeySuffixKind::WellKnown | KeySuffixKind::Vendor { .. } | KeySuffixKind::Bare => { },
+        }
+        if is_encrypted(data) {
+            match suffix.as_str() {
+                KEY_SUFFIX_DNS_A |
+                KEY_SUFFIX_DNS_AAAA |
+                KEY_SUFFIX_DNS_TXT |
+                KEY_SUFFIX_DNS_MX |
+                KEY_SUFFIX_DELEGATE |
+                KEY_SUFFIX_HANDOFF |
+                KEY_SUFFIX_KEY_ALIAS => {
+                    out.push(
+                        format!(
+                            "[{}] Value is encrypted, but publishers and DNS bridges need to read this record",
+                            key_str
+                        ),
+                    );
+                },
+                _ => { },
+            }
+            continue;
+        }
+        match suffix.as_str() {
+            KEY_SUFFIX_DNS_A | KEY_SUFFIX_DNS_AAAA => {
+                let ips = match suffix.as_str() {
+                    KEY_SUFFIX_DNS_A => match serde_json::from_value::<stored::record::dns_record::DnsA>(
+                        data.clone(),
+                    ) {
+                        Ok(stored::record::dns_record::DnsA::V1(v)) => v.0.into_iter().map(IpAddr::V4).collect(),
+                        Err(_) => vec![],
+                    },
+                    _ => match serde_json::from_value::<stored::record::dns_record::DnsAaaa>(data.clone()) {
+                        Ok(stored::record::dns_record::DnsAaaa::V1(v)) => v.0.into_iter().map(IpAddr::V6).collect(),
+                        Err(_) => vec![],
+                    },
+                };
+                for ip in ips {
+                    if lint_ip_private(ip) {
+                        out.push(format!("[{}] Address {} isn't globally reachable", key_str, ip));
+                    }
+                }
+            },
+            KEY_SUFFIX_DNS_TXT => {
+                let Ok(stored::record::dns_record::DnsTxt::V1(txt)) =
+                    serde_json::from_value::<stored::record::dns_record::DnsTxt>(data.clone()) else {
+                        continue;
+                    };
+                for t in txt.0 {
+                    if t.len() > 255 {
+                        out.push(
+                            format!(
+                                "[{}] TXT string is {} bytes, DNS limits TXT strings to 255 bytes",
+                                key_str,
+                                t.len()
+                            ),
+                        );
+                    }
+                }
+            },
+            KEY_SUFFIX_DNS_MX => {
+                let Ok(stored::record::dns_record::DnsMx::V1(mx)) =
+                    serde_json::from_value::<stored::record::dns_record::DnsMx>(data.clone()) else {
+                        continue;
+                    };
+                for target in mx.0 {
+                    let Ok(name) = Name::from_str(&target) else {
+                        out.push(format!("[{}] MX target {} isn't a valid DNS name", key_str, target));
+                        continue;
+                    };
+                    let Ok((RecordRoot::S(target_ident), target_path)) = split_dns_name(name) else {
+                        continue;
+                    };
+                    if &target_ident != identity {
+                        continue;
+                    }
+                    if !set.contains_key(&build_dns_key(target_path.clone(), RecordType::A)) &&
+                        !set.contains_key(&build_dns_key(target_path, RecordType::Aaaa)) {
+                        out.push(format!("[{}] MX target {} has no address records in this publish", key_str, target));
+                    }
+                }
+            },
+            KEY_SUFFIX_DELEGATE => {
+                let Ok(stored::record::delegate_record::Delegate::V1(delegate)) =
+                    serde_json::from_value::<stored::record::delegate_record::Delegate>(data.clone()) else {
+                        continue;
+                    };
+                for (root, path) in delegate.0 {
+                    let RecordRoot::S(target_ident) = root else {
+                        continue;
+                    };
+                    if &target_ident == identity && path.starts_with(head) {
+                        out.push(
+                            format!(
+                                "[{}] Delegates to {}, which is under the delegated key - resolution will loop",
+                                key_str,
+                                join_record_key(&path)
+                            ),
+                        );
+                    }
+                }
+            },
+            KEY_SUFFIX_HANDOFF => {
+                let Ok(stored::record::handoff_record::Handoff::V1(handoff)) =
+                    serde_json::from_value::<stored::record::handoff_record::Handoff>(data.clone()) else {
+                        continue;
+                    };
+                if handoff.name_servers.is_empty() {
+                    out.push(format!("[{}] Handoff has no name servers", key_str));
+                }
+                for ns in &handoff.name_servers {
+                    let Ok(name) = Name::from_str(ns) else {
+                        out.push(format!("[{}] Name server {} isn't a valid DNS name", key_str, ns));
+                        continue;
+                    };
+                    let Ok((RecordRoot::S(ns_ident), ns_path)) = split_dns_name(name) else {
+                        continue;
+                    };
+                    if &ns_ident == identity && ns_path.starts_with(head) &&
+                        handoff.glue.get(ns).map(|g| g.is_empty()).unwrap_or(true) {
+                        out.push(
+                            format!(
+                                "[{}] Name server {} is inside the handed off subtree but has no glue addresses",
+                                key_str,
+                                ns
+                            ),
+                        );
+                    }
+                }
+            },
+            KEY_SUFFIX_KEY_ALIAS => {
+                let Ok(KeyAlias::V1(alias)) = serde_json::from_value::<KeyAlias>(data.clone()) else {
+                    continue;
+                };
+                for (other_key, other_value) in set {
+                    let RecordValue::V1(other_value) = other_value;
+                    if other_value.data.is_some() && other_key.len() == head.len() + 1 &&
+                        other_key.starts_with(head) &&
+                        other_key.last().unwrap() != KEY_SUFFIX_KEY_ALIAS {
+                        out.push(
+                            format!(
+                                "[{}] Record at {} is hidden by the key alias",
+                                key_str,
+                                join_record_key(other_key)
+                            ),
+                        );
+                    }
+                }
+                let mut seen = vec![head.to_vec()];
+                let mut target = normalize_record_key(&alias.target);
+                loop {
+                    if seen.contains(&target) {
+                        out.push(format!("[{}] Key aliases loop, the keys will resolve as missing", key_str));
+                        break;
+                    }
+                    if seen.len() > KEY_ALIAS_MAX_HOPS {
+                        out.push(
+                            format!(
+                                "[{}] Key alias chain is longer than {} hops, the keys will resolve as missing",
+                                key_str,
+                                KEY_ALIAS_MAX_HOPS
+                            ),
+                        );
+                        break;
+                    }
+                    let Some(RecordValue::V1(next)) = set.get(&build_key_alias_key(target.clone())) else {
+                        break;
+                    };
+                    let Some(Ok(KeyAlias::V1(next))) =
+                        next.data.clone().map(serde_json::from_value::<KeyAlias>) else {
+                            break;
+                        };
+                    seen.push(target);
+                    target = normalize_record_key(&next.target);
+                }
+            },
+            _ => { },
+        }
+    }
+    out.sort();
+    return out;
+}
+
+// Returns the canonical data, the number of values, and their size in DNS wire
+// format, or `None` if the data doesn't parse as the record type.
+fn canonicalize_dns_record(suffix: &str, data: &serde_json::Value) -> Option<(serde_json::Value, usize, usize)> {
+    match suffix {
+        KEY_SUFFIX_DNS_A => {
+            let stored::record::dns_record::DnsA::V1(mut v) =
+                serde_json::from_value::<stored::record::dns_record::DnsA>(data.clone()).ok()?;
+            v.0.sort();
+            v.0.dedup();
+            let count = v.0.len();
+            return Some(
+                (serde_json::to_value(stored::record::dns_record::DnsA::V1(v)).unwrap(), count, count * 4),
+            );
+        },
+        KEY_SUFFIX_DNS_AAAA => {
+            let stored::record::dns_record::DnsAaaa::V1(mut v) =
+                serde_json::from_value::<stored::record::dns_record::DnsAaaa>(data.clone()).ok()?;
+            v.0.sort();
+            v.0.dedup();
+            let count = v.0.len();
+            return Some(
+                (serde_json::to_value(stored::record::dns_record::DnsAaaa::V1(v)).unwrap(), count, count * 16),
+            );
+        },
+        KEY_SUFFIX_DNS_TXT => {
+            let stored::record::dns_record::DnsTxt::V1(mut v) =
+                serde_json::from_value::<stored::record::dns_record::DnsTxt>(data.clone()).ok()?;
+            v.0.sort();
+            v.0.dedup();
+            let count = v.0.len();
+
+            // Long strings are split into 255 byte chunks, each with a length byte
+            let bytes = v.0.iter().map(|t| t.len() + t.len().div_ceil(255).max(1)).sum();
+            return Some((serde_json::to_value(stored::record::dns_record::DnsTxt::V1(v)).unwrap(), count, bytes));
+        },
+        KEY_SUFFIX_DNS_MX => {
+            let stored::record::dns_record::DnsMx::V1(mut v) =
+                serde_json::from_value::<stored::record::dns_record::DnsMx>(data.clone()).ok()?;
+
+            // Order is priority, so only drop later duplicates
+            let mut seen = HashSet::new();
+            v.0 = v.0.into_iter().map(|n| n.to_ascii_lowercase()).filter(|n| seen.insert(n.clone())).collect();
+            let count = v.0.len();
+
+            // Preference, plus name labels with length bytes
+            let bytes = v.0.iter().map(|n| 2 + n.trim_end_matches('.').len() + 2).sum();
+            return Some((serde_json::to_value(stored::record::dns_record::DnsMx::V1(v)).unwrap(), count, bytes));
+        },
+        _ => return None,
+    }
+}
+
+/// Canonicalize DNS records to be published: remove duplicate values, sort values
+/// (except MX, where order is priority), and lowercase MX names. Returns a problem
+/// for each record with more than `MAX_DNS_VALUES` values or more than
+/// `MAX_DNS_RDATA_BYTES` of data. Other records and values that don't parse are
+/// left as is.
+pub fn canonicalize_records(
+    set: HashMap<RecordKey, RecordValue>,
+) -> Result<HashMap<RecordKey, RecordValue>, Vec<RecordProblem>> {
+    let mut out = HashMap::new();
+    let mut problems = vec![];
+    for (key, value) in set {
+        let RecordValue::V1(mut value) = value;
+        if let (Some(suffix), Some(data)) = (key.last(), &value.data) {
+            if let Some((data, count, bytes)) = canonicalize_dns_record(suffix, data) {
+                if count > MAX_DNS_VALUES {
+                    problems.push(RecordProblem::TooManyValues {
+                        key: key.clone(),
+                        values: count,
+                        max: MAX_DNS_VALUES,
+                    });
+                } else if bytes > MAX_DNS_RDATA_BYTES {
+                    problems.push(RecordProblem::TooLarge {
+                        key: key.clone(),
+                        bytes: bytes,
+                        max: MAX_DNS_RDATA_BYTES,
+                    });
+                }
+                value.data = Some(data);
+            }
+        }
+        out.insert(key, RecordValue::V1(value));
+    }
+    if !problems.is_empty() {
+        problems.sort_by_key(|p| match p {
+            RecordProblem::TooManyValues { key, .. } => key.clone(),
+            RecordProblem::TooLarge { key, .. } => key.clone(),
+        });
+        return Err(problems);
+    }
+    return Ok(out);
+}
+
+/// Lint and sign changes without sending them, ex: to send later in a batch.
+pub fn sign_publish_request(
     log: &Log,
-    resolvers: &[UrlPair],
-    publishers: &[UrlPair],
     identity_signer: &Arc<Mutex<dyn IdentitySigner>>,
     args: PublishArgs,
-) -> Result<(), loga::Error> {
+) -> Result<wire::api::publish::latest::PublishRequest, loga::Error> {
+    if !args.no_lint {
+        let identity = identity_signer.lock().unwrap().identity()?;
+        for warning in lint_records(&identity, &args.set) {
+            log.log_with(loga::WARN, "Possible mistake in published records", ea!(warning = warning));
+        }
+    }
     let (identity, signed_request_content) =
         wire::api::publish::v1::JsonSignature::sign(
             &mut *identity_signer.lock().unwrap(),
@@ -160,33 +566,88 @@ pub async fn publish(
                 clear_all: args.clear_all,
                 clear: args.clear,
                 set: args.set.into_iter().collect(),
+                if_version: args.if_version,
+                published: Some(Utc::now()),
             },
         ).stack_context(&log, "Failed to sign publish request content")?;
-    let request = wire::api::publish::latest::PublishRequest {
+    return Ok(wire::api::publish::latest::PublishRequest {
         identity: identity,
         content: signed_request_content,
-    };
+    });
+}
+
+pub async fn publish(
+    log: &Log,
+    resolvers: &[UrlPair],
+    publishers: &[UrlPair],
+    identity_signer: &Arc<Mutex<dyn IdentitySigner>>,
+    args: PublishArgs,
+) -> Result<(), loga::Error> {
+    let request = sign_publish_request(log, identity_signer, args)?;
+    let spool = PublishSpool::from_env();
     for s in publishers {
-        let url = s.join(format!("{}/v1/publish", API_ROUTE_PUBLISH));
+        let Some(spool) = &spool else {
+            if let Err(SendPublishError::Connect(e) | SendPublishError::Request(e)) =
+                send_publish(log, resolvers, s, &request).await {
+                return Err(e);
+            }
+            continue;
+        };
+
+        // Send anything already queued for the identity first, to keep the order
+        let flushed = spool.flush(log, resolvers, Some((s, &request.identity))).await?;
+        let mut error = None;
+        if flushed.remaining == 0 {
+            match send_publish(log, resolvers, s, &request).await {
+                Ok(_) => continue,
+                Err(SendPublishError::Connect(e)) => {
+                    error = Some(e.to_string());
+                },
+                Err(SendPublishError::Request(e)) => return Err(e),
+            }
+        }
+        spool.push(s, &request, error).await?;
         log.log_with(
-            loga::DEBUG,
-            "Sending publish request",
-            ea!(url = url, body = serde_json::to_string_pretty(&request).unwrap()),
+            loga::WARN,
+            "Couldn't send publish request yet, queued it in spool to send later",
+            ea!(publisher = s, spool = spool.dir.to_string_lossy(), pending = flushed.remaining + 1),
         );
-        htreq::post(
-            log,
-            &mut connect_publisher_node(&log, resolvers, &url).await.context("Error connecting to publisher")?,
-            &url.url,
-            &HashMap::new(),
-            serde_json::to_vec(&request).unwrap(),
-            100,
-        )
-            .await
-            .context("Error making publish request")?;
     }
     return Ok(());
 }
 
+pub enum SendPublishError {
+    /// The publisher couldn't be reached, so the request may succeed later
+    Connect(loga::Error),
+    /// The publisher was reached but the request failed
+    Request(loga::Error),
+}
+
+/// Send a signed publish request to one publisher.
+pub async fn send_publish(
+    log: &Log,
+    resolvers: &[UrlPair],
+    publisher: &UrlPair,
+    request: &wire::api::publish::latest::PublishRequest,
+) -> Result<(), SendPublishError> {
+    let url = publisher.join(format!("{}/v1/publish", API_ROUTE_PUBLISH));
+    log.log_with(
+        loga::DEBUG,
+        "Sending publish request",
+        ea!(url = url, body = serde_json::to_string_pretty(&request).unwrap()),
+    );
+    let mut conn =
+        connect_publisher_node(log, resolvers, &url)
+            .await
+            .context("Error connecting to publisher")
+            .map_err(SendPublishError::Connect)?;
+    htreq::post(log, &mut conn, &url.url, &HashMap::new(), serde_json::to_vec(&request).unwrap(), 1024)
+        .await
+        .context("Error making publish request")
+        .map_err(SendPublishError::Request)?;
+    return Ok(());
+}
+
 /// Add an ip address record to a set to publish
 pub fn add_ip_record(
     publish_data: &mut HashMap<RecordKey, stored::record::RecordValue>,
@@ -219,6 +680,28 @@ pub fn add_ip_record(
     }));
 }
 
+/// Add an address family preference record to a set to publish
+pub fn add_addr_pref_record(
+    publish_data: &mut HashMap<RecordKey, stored::record::RecordValue>,
+    head: Vec<String>,
+    ttl: i32,
+    prefer: IpFamily,
+) {
+    publish_data.insert(
+        build_addr_pref_key(head),
+        stored::record::RecordValue::latest(stored::record::latest::RecordValue {
+            ttl: ttl,
+            data: Some(
+                serde_json::to_value(
+                    stored::record::addr_pref_record::AddrPref::latest(
+                        stored::record::addr_pref_record::latest::AddrPref { prefer: prefer },
+                    ),
+                ).unwrap(),
+            ),
+        }),
+    );
+}
+
 /// Scan system for ssh host keys and add them to a record set to publish.
 ///
 /// * `paths` - if empty, search the system for default host key paths
@@ -263,3 +746,157 @@ pub async fn add_ssh_host_key_records(
     }));
     return Ok(());
 }
+
+#[cfg(test)]
+mod test_lint_records {
+    use {
+        super::lint_records,
+        crate::interface::{
+            config::identity::LocalIdentitySecret,
+            stored::record::{
+                self,
+                RecordValue,
+            },
+        },
+        std::collections::HashMap,
+    };
+
+    fn value(ttl: i32, data: serde_json::Value) -> RecordValue {
+        return RecordValue::latest(record::latest::RecordValue {
+            ttl: ttl,
+            data: Some(data),
+        });
+    }
+
+    #[test]
+    fn test_clean() {
+        let (ident, _) = LocalIdentitySecret::new();
+        let mut set = HashMap::new();
+        set.insert(vec!["dns/a".to_string()], value(60, serde_json::json!({
+            "v1": ["203.0.114.1"]
+        })));
+        set.insert(vec!["mail".to_string(), "dns/aaaa".to_string()], value(60, serde_json::json!({
+            "v1": ["2001:4860::1"]
+        })));
+        set.insert(vec!["dns/mx".to_string()], value(60, serde_json::json!({
+            "v1": [format!("mail.{}.s", ident)]
+        })));
+        assert_eq!(lint_records(&ident, &set), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_mistakes() {
+        let (ident, _) = LocalIdentitySecret::new();
+        let mut set = HashMap::new();
+        set.insert(vec!["dns/a".to_string()], value(0, serde_json::json!({
+            "v1": ["192.168.0.1"]
+        })));
+        set.insert(vec!["dns/txt".to_string()], value(60, serde_json::json!({
+            "v1": ["x".repeat(300)]
+        })));
+        set.insert(vec!["dns/mx".to_string()], value(60, serde_json::json!({
+            "v1": [format!("mail.{}.s", ident)]
+        })));
+        set.insert(vec!["www".to_string(), "delegate".to_string()], value(60, serde_json::json!({
+            "v1": [[{
+                "s": ident.to_string()
+            }, ["www", "x"]]]
+        })));
+        set.insert(vec!["lab".to_string(), "handoff".to_string()], value(60, serde_json::json!({
+            "v1": {
+                "name_servers": [format!("ns1.lab.{}.s", ident)]
+            }
+        })));
+        set.insert(vec!["a".to_string(), "key_alias".to_string()], value(60, serde_json::json!({
+            "v1": {
+                "target": ["b"]
+            }
+        })));
+        set.insert(vec!["b".to_string(), "key_alias".to_string()], value(60, serde_json::json!({
+            "v1": {
+                "target": ["a"]
+            }
+        })));
+        set.insert(vec!["internal".to_string(), "dns/a".to_string()], value(60, serde_json::json!({
+            "encrypted": {
+                "v1": {
+                    "recipients": [],
+                    "nonce": "",
+                    "ciphertext": ""
+                }
+            }
+        })));
+        assert_eq!(lint_records(&ident, &set).len(), 9);
+    }
+
+    #[test]
+    fn test_shadowed_keys() {
+        let (ident, _) = LocalIdentitySecret::new();
+        let mut set = HashMap::new();
+        set.insert(vec!["DNS/A".to_string()], value(60, serde_json::json!({
+            "v1": ["203.0.114.1"]
+        })));
+        set.insert(vec!["dns/srv".to_string()], value(60, serde_json::json!([])));
+        set.insert(vec!["acme/cache".to_string()], value(60, serde_json::json!({ })));
+        assert_eq!(lint_records(&ident, &set).len(), 2);
+    }
+}
+
+#[cfg(test)]
+mod test_canonicalize_records {
+    use {
+        super::canonicalize_records,
+        crate::interface::{
+            stored::record::{
+                self,
+                RecordValue,
+            },
+            wire::api::publish::v1::RecordProblem,
+        },
+        std::collections::HashMap,
+    };
+
+    fn value(data: serde_json::Value) -> RecordValue {
+        return RecordValue::latest(record::latest::RecordValue {
+            ttl: 60,
+            data: Some(data),
+        });
+    }
+
+    fn data(set: &HashMap<Vec<String>, RecordValue>, key: &str) -> serde_json::Value {
+        match set.get(&vec![key.to_string()]).unwrap() {
+            RecordValue::V1(v) => return v.data.clone().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_canonical() {
+        let mut set = HashMap::new();
+        set.insert(vec!["dns/a".to_string()], value(serde_json::json!({
+            "v1": ["203.0.114.2", "203.0.114.1", "203.0.114.2"]
+        })));
+        set.insert(vec!["dns/mx".to_string()], value(serde_json::json!({
+            "v1": ["B.example.", "a.example.", "b.example."]
+        })));
+        let set = canonicalize_records(set).unwrap();
+        assert_eq!(data(&set, "dns/a"), serde_json::json!({
+            "v1": ["203.0.114.1", "203.0.114.2"]
+        }));
+        assert_eq!(data(&set, "dns/mx"), serde_json::json!({
+            "v1": ["b.example.", "a.example."]
+        }));
+    }
+
+    #[test]
+    fn test_too_many() {
+        let mut set = HashMap::new();
+        set.insert(vec!["dns/txt".to_string()], value(serde_json::json!({
+            "v1": (0 .. 100).map(|i| i.to_string()).collect::<Vec<_>>()
+        })));
+        assert_eq!(canonicalize_records(set).err(), Some(vec![RecordProblem::TooManyValues {
+            key: vec!["dns/txt".to_string()],
+            values: 100,
+            max: 64,
+        }]));
+    }
+}
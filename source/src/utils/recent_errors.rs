@@ -0,0 +1,37 @@
+//! In-memory record of recent warnings, for daemon status reporting.
+use {
+    super::request_id::request_log,
+    crate::interface::wire,
+    chrono::Utc,
+    loga::Log,
+    std::{
+        collections::VecDeque,
+        sync::Mutex,
+    },
+};
+
+const MAX_RECENT_ERRORS: usize = 32;
+
+static RECENT_ERRORS: Mutex<VecDeque<wire::api::admin::latest::RecentError>> = Mutex::new(VecDeque::new());
+
+/// Log an error at warning level, and remember it so it can be retrieved with
+/// `recent_errors`. If called while handling an API request the request ID is
+/// included.
+pub fn log_warn_err(log: &Log, e: loga::Error) {
+    {
+        let mut recent = RECENT_ERRORS.lock().unwrap();
+        if recent.len() >= MAX_RECENT_ERRORS {
+            recent.pop_front();
+        }
+        recent.push_back(wire::api::admin::latest::RecentError {
+            time: Utc::now(),
+            message: e.to_string(),
+        });
+    }
+    request_log(log).log_err(loga::WARN, e);
+}
+
+/// Warnings logged with `log_warn_err`, oldest first.
+pub fn recent_errors() -> Vec<wire::api::admin::latest::RecentError> {
+    return RECENT_ERRORS.lock().unwrap().iter().cloned().collect();
+}
@@ -0,0 +1,116 @@
+//! Limits for following references between records (identity successions and
+//! delegations) while resolving a name, so long, cyclic, or wide record graphs
+//! (accidental or malicious) can't make a resolver do unbounded work.
+use {
+    loga::ea,
+    std::fmt::Display,
+};
+
+/// The maximum number of references (successions and delegations combined)
+/// followed to resolve one name.
+pub const REFERENCE_MAX_DEPTH: usize = 8;
+
+/// The maximum number of targets in a delegation. Delegations with more are
+/// rejected rather than choosing among them.
+pub const REFERENCE_MAX_FANOUT: usize = 16;
+
+/// The maximum number of records looked up along the whole chain (ex: the delegate
+/// records for each prefix of the path at each hop).
+pub const REFERENCE_MAX_VISITED: usize = 64;
+
+/// The references followed so far to resolve a name. Errors name the whole chain.
+pub struct ReferenceChain {
+    chain: Vec<String>,
+    visited: usize,
+}
+
+impl ReferenceChain {
+    pub fn new(start: impl Display) -> Self {
+        return ReferenceChain {
+            chain: vec![start.to_string()],
+            visited: 0,
+        };
+    }
+
+    fn err(&self, message: &'static str, limit: usize) -> loga::Error {
+        return loga::err_with(message, ea!(limit = limit, chain = self.chain.join(" -> ")));
+    }
+
+    /// Count `count` more records looked up.
+    pub fn visit(&mut self, count: usize) -> Result<(), loga::Error> {
+        self.visited += count;
+        if self.visited > REFERENCE_MAX_VISITED {
+            return Err(self.err("Too many records looked up following references", REFERENCE_MAX_VISITED));
+        }
+        return Ok(());
+    }
+
+    /// Check the number of targets of a reference before choosing one.
+    pub fn fanout(&self, count: usize) -> Result<(), loga::Error> {
+        if count > REFERENCE_MAX_FANOUT {
+            return Err(self.err("Reference has too many targets", REFERENCE_MAX_FANOUT));
+        }
+        return Ok(());
+    }
+
+    /// Follow a reference to `target`. Fails if the chain is too long or returns to
+    /// somewhere it's already been.
+    pub fn follow(&mut self, target: impl Display) -> Result<(), loga::Error> {
+        let target = target.to_string();
+        let cycle = self.chain.contains(&target);
+        self.chain.push(target);
+        if cycle {
+            return Err(self.err("References form a cycle", REFERENCE_MAX_DEPTH));
+        }
+        if self.chain.len() - 1 > REFERENCE_MAX_DEPTH {
+            return Err(self.err("Reference chain is too long", REFERENCE_MAX_DEPTH));
+        }
+        return Ok(());
+    }
+
+    /// The number of references followed.
+    pub fn depth(&self) -> usize {
+        return self.chain.len() - 1;
+    }
+
+    /// The names visited, starting with the original name.
+    pub fn names(&self) -> &[String] {
+        return &self.chain;
+    }
+}
+
+#[cfg(test)]
+mod test_reference_chain {
+    use super::{
+        ReferenceChain,
+        REFERENCE_MAX_DEPTH,
+        REFERENCE_MAX_FANOUT,
+        REFERENCE_MAX_VISITED,
+    };
+
+    #[test]
+    fn test_depth() {
+        let mut chain = ReferenceChain::new("a");
+        for i in 0 .. REFERENCE_MAX_DEPTH {
+            chain.follow(i).unwrap();
+        }
+        assert_eq!(chain.depth(), REFERENCE_MAX_DEPTH);
+        assert!(chain.follow("z").is_err());
+    }
+
+    #[test]
+    fn test_cycle() {
+        let mut chain = ReferenceChain::new("a");
+        chain.follow("b").unwrap();
+        assert!(chain.follow("a").is_err());
+    }
+
+    #[test]
+    fn test_fanout_visited() {
+        let mut chain = ReferenceChain::new("a");
+        assert!(chain.fanout(REFERENCE_MAX_FANOUT).is_ok());
+        assert!(chain.fanout(REFERENCE_MAX_FANOUT + 1).is_err());
+        chain.visit(REFERENCE_MAX_VISITED).unwrap();
+        assert!(chain.visit(1).is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! Per-request IDs for tracing API requests through logs, and propagating them
+//! (with W3C `traceparent` trace context) to requests made while handling them.
+use {
+    async_trait::async_trait,
+    chrono::Utc,
+    htwrap::htserve::{
+        self,
+        handler::{
+            Handler,
+            HandlerArgs,
+        },
+    },
+    http::{
+        HeaderMap,
+        HeaderName,
+        HeaderValue,
+        Response,
+    },
+    loga::{
+        ea,
+        Log,
+    },
+    std::{
+        future::Future,
+        sync::Arc,
+    },
+};
+
+pub const HEADER_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+pub const HEADER_TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+const MAX_CLIENT_REQUEST_ID_LEN: usize = 64;
+
+/// Tracing context of a request being handled.
+#[derive(Clone, Debug)]
+pub struct RequestTrace {
+    pub id: String,
+    /// The W3C trace context `traceparent` the client sent, if any
+    pub traceparent: Option<String>,
+}
+
+tokio::task_local!{
+    static REQUEST_TRACE: RequestTrace;
+}
+
+/// The ID of the request being handled by the current task, if any.
+pub fn current_request_id() -> Option<String> {
+    return REQUEST_TRACE.try_with(|t| t.id.clone()).ok();
+}
+
+/// The tracing context of the request being handled by the current task, if any.
+pub fn current_request_trace() -> Option<RequestTrace> {
+    return REQUEST_TRACE.try_with(|t| t.clone()).ok();
+}
+
+/// Run a future with a request's tracing context, for work spawned while handling
+/// the request.
+pub async fn with_request_trace<F: Future>(trace: Option<RequestTrace>, f: F) -> F::Output {
+    match trace {
+        Some(trace) => return REQUEST_TRACE.scope(trace, f).await,
+        None => return f.await,
+    }
+}
+
+/// Add the current request ID and `traceparent` (if any) to a log.
+pub fn request_log(log: &Log) -> Log {
+    let Some(trace) = current_request_trace() else {
+        return log.clone();
+    };
+    let log = log.fork(ea!(request_id = trace.id));
+    match trace.traceparent {
+        Some(traceparent) => return log.fork(ea!(traceparent = traceparent)),
+        None => return log,
+    }
+}
+
+fn valid_client_request_id(id: &str) -> bool {
+    return !id.is_empty() && id.len() <= MAX_CLIENT_REQUEST_ID_LEN &&
+        id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+}
+
+fn valid_hex_field(field: &str, len: usize) -> bool {
+    return field.len() == len && field.chars().all(|c| c.is_ascii_digit() || ('a' ..= 'f').contains(&c));
+}
+
+/// Whether the value is a version `00` W3C trace context `traceparent`.
+fn valid_traceparent(traceparent: &str) -> bool {
+    let parts = traceparent.split('-').collect::<Vec<_>>();
+    let [version, trace_id, parent_id, flags] = parts.as_slice() else {
+        return false;
+    };
+    return *version == "00" && valid_hex_field(trace_id, 32) && trace_id.chars().any(|c| c != '0') &&
+        valid_hex_field(parent_id, 16) &&
+        parent_id.chars().any(|c| c != '0') &&
+        valid_hex_field(flags, 2);
+}
+
+/// A `traceparent` for a request made while handling a request with this one: the
+/// same trace, with a new parent ID.
+fn child_traceparent(traceparent: &str) -> String {
+    let parts = traceparent.split('-').collect::<Vec<_>>();
+    return format!("00-{}-{:016x}-{}", parts[1], rand::random::<u64>().max(1), parts[3]);
+}
+
+/// The request ID and `traceparent` sent with a request, if valid.
+pub fn request_trace_headers(headers: &HeaderMap) -> (Option<String>, Option<String>) {
+    let header = |name: &HeaderName| headers.get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+    return (
+        header(&HEADER_REQUEST_ID).filter(|v| valid_client_request_id(v)),
+        header(&HEADER_TRACEPARENT).filter(|v| valid_traceparent(v)),
+    );
+}
+
+/// Headers propagating the current request's tracing context (if any) to a request
+/// made while handling it: the request ID, and if the client sent one a
+/// `traceparent` continuing its trace.
+pub fn propagate_trace_headers() -> Vec<(HeaderName, HeaderValue)> {
+    let mut out = vec![];
+    let Some(trace) = current_request_trace() else {
+        return out;
+    };
+    out.push((HEADER_REQUEST_ID, HeaderValue::from_str(&trace.id).unwrap()));
+    if let Some(traceparent) = &trace.traceparent {
+        out.push((HEADER_TRACEPARENT, HeaderValue::from_str(&child_traceparent(traceparent)).unwrap()));
+    }
+    return out;
+}
+
+/// Wraps a handler, assigning each request an ID. The ID is available to the inner
+/// handler via `current_request_id` (and is added to warnings logged with
+/// `log_warn_err`), and is returned in the `X-Request-Id` response header.
+///
+/// If `trust_client` is set, an `X-Request-Id` sent by the client is used instead
+/// of generating a new ID (if it's reasonably short and only contains letters,
+/// digits, `-`, `_`, and `.`), and a valid `traceparent` sent by the client is kept
+/// for `propagate_trace_headers`.
+pub struct RequestIdHandler {
+    pub log: Log,
+    pub inner: Arc<dyn Handler<htserve::responses::Body>>,
+    pub trust_client: bool,
+}
+
+#[async_trait]
+impl Handler<htserve::responses::Body> for RequestIdHandler {
+    async fn handle(&self, args: HandlerArgs<'_>) -> Response<htserve::responses::Body> {
+        let (client_id, traceparent) = match self.trust_client {
+            true => request_trace_headers(&args.head.headers),
+            false => (None, None),
+        };
+        let id = client_id.unwrap_or_else(|| zbase32::encode_full_bytes(&rand::random::<[u8; 10]>()));
+        let method = args.head.method.clone();
+        let path = args.head.uri.path().to_string();
+        let start = Utc::now();
+        let mut resp = REQUEST_TRACE.scope(RequestTrace {
+            id: id.clone(),
+            traceparent: traceparent.clone(),
+        }, self.inner.handle(args)).await;
+        self
+            .log
+            .log_with(
+                loga::DEBUG,
+                "Request",
+                ea!(
+                    request_id = id,
+                    traceparent = traceparent.clone().unwrap_or_default(),
+                    method = method,
+                    path = path,
+                    status = resp.status().as_u16(),
+                    duration_ms = (Utc::now() - start).num_milliseconds()
+                ),
+            );
+        resp.headers_mut().insert(HEADER_REQUEST_ID, HeaderValue::from_str(&id).unwrap());
+        return resp;
+    }
+}
+
+#[cfg(test)]
+mod test_request_id {
+    use super::{
+        child_traceparent,
+        valid_client_request_id,
+        valid_traceparent,
+    };
+
+    #[test]
+    fn test_valid() {
+        assert!(valid_client_request_id("a1b2-c3_d4.e5"));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(!valid_client_request_id(""));
+        assert!(!valid_client_request_id("a b"));
+        assert!(!valid_client_request_id("a\nb"));
+        assert!(!valid_client_request_id(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn test_traceparent() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        assert!(valid_traceparent(traceparent));
+        assert!(!valid_traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01"));
+        assert!(!valid_traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01"));
+        assert!(!valid_traceparent("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01"));
+        assert!(!valid_traceparent("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-x"));
+
+        // Children continue the same trace
+        let child = child_traceparent(traceparent);
+        assert!(valid_traceparent(&child));
+        assert!(child.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(child.ends_with("-01"));
+        assert_ne!(child, traceparent);
+    }
+}
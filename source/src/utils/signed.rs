@@ -1,3 +1,4 @@
+use loga::ResultContext;
 use serde::{
     de::DeserializeOwned,
     Serialize,
@@ -25,7 +26,9 @@ where
     Self: Sized {
     fn sign(signer: &mut dyn IdentitySigner, body: B) -> Result<(I, Self), loga::Error>;
     fn verify(&self, identity: &Identity) -> Result<B, ()>;
-    fn parse_unwrap(&self) -> B;
+    /// Parse the message without checking the signature, for messages that were
+    /// verified earlier. Fails if the message is malformed.
+    fn parse(&self) -> Result<B, loga::Error>;
 }
 
 impl<
@@ -46,8 +49,8 @@ impl<
         return Ok(bincode::deserialize(&self.message).map_err(|_| ())?);
     }
 
-    fn parse_unwrap(&self) -> B {
-        return bincode::deserialize(&self.message).map_err(|_| ()).unwrap();
+    fn parse(&self) -> Result<B, loga::Error> {
+        return bincode::deserialize(&self.message).context("Signed message is malformed");
     }
 }
 
@@ -69,8 +72,8 @@ impl<
         return Ok(bincode::deserialize(&self.message).map_err(|_| ())?);
     }
 
-    fn parse_unwrap(&self) -> B {
-        return bincode::deserialize(&self.message).map_err(|_| ()).unwrap();
+    fn parse(&self) -> Result<B, loga::Error> {
+        return bincode::deserialize(&self.message).context("Signed message is malformed");
     }
 }
 
@@ -92,8 +95,8 @@ impl<
         return Ok(serde_json::from_str(&self.message).map_err(|_| ())?);
     }
 
-    fn parse_unwrap(&self) -> B {
-        return serde_json::from_str(&self.message).map_err(|_| ()).unwrap();
+    fn parse(&self) -> Result<B, loga::Error> {
+        return serde_json::from_str(&self.message).context("Signed message is malformed");
     }
 }
 
@@ -0,0 +1,293 @@
+//! An ssh-agent protocol server exposing an identity as an ed25519 SSH user key,
+//! so OpenSSH clients can authenticate with the same identity used for names.
+//!
+//! The agent only signs SSH user authentication requests for its own key. The
+//! identity also signs announcements and publisher requests, and a forwarded agent
+//! that signed arbitrary data could be used to forge those.
+use {
+    super::identity_secret::IdentitySigner,
+    crate::interface::stored::identity::{
+        self,
+        Identity,
+    },
+    loga::{
+        ea,
+        ErrContext,
+        Log,
+        ResultContext,
+    },
+    russh_keys::PublicKeyBase64,
+    std::{
+        path::Path,
+        sync::{
+            Arc,
+            Mutex,
+        },
+    },
+    tokio::{
+        io::{
+            AsyncRead,
+            AsyncReadExt,
+            AsyncWrite,
+            AsyncWriteExt,
+        },
+        net::UnixListener,
+        spawn,
+        task::spawn_blocking,
+    },
+};
+
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+const SSH_MSG_USERAUTH_REQUEST: u8 = 50;
+const KEY_TYPE_ED25519: &[u8] = b"ssh-ed25519";
+
+// Larger than any request a client would send; guards against bad lengths
+const MAX_MESSAGE_LEN: usize = 256 * 1024;
+
+fn put_string(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend(data);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let (b, rest) = self.0.split_first()?;
+        self.0 = rest;
+        return Some(*b);
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        if self.0.len() < 4 {
+            return None;
+        }
+        let (b, rest) = self.0.split_at(4);
+        self.0 = rest;
+        return Some(u32::from_be_bytes(b.try_into().unwrap()));
+    }
+
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        if self.0.len() < len {
+            return None;
+        }
+        let (b, rest) = self.0.split_at(len);
+        self.0 = rest;
+        return Some(b);
+    }
+}
+
+/// The SSH wire encoding of the identity's public key.
+pub fn ssh_key_blob(identity: &Identity) -> Vec<u8> {
+    match identity {
+        Identity::V1(identity::v1::Identity::Ed25519(i)) => {
+            let mut out = vec![];
+            put_string(&mut out, KEY_TYPE_ED25519);
+            put_string(&mut out, i.0.as_bytes());
+            return out;
+        },
+    }
+}
+
+pub fn ssh_public_key(identity: &Identity) -> russh_keys::key::PublicKey {
+    match identity {
+        Identity::V1(identity::v1::Identity::Ed25519(i)) => {
+            return russh_keys::key::PublicKey::Ed25519(i.0);
+        },
+    }
+}
+
+/// The identity's public key as an `authorized_keys` line.
+pub fn ssh_authorized_key(identity: &Identity) -> String {
+    return format!("ssh-ed25519 {} {}", ssh_public_key(identity).public_key_base64(), identity);
+}
+
+/// Check that `data` is a publickey user authentication request for `key_blob`
+/// (RFC 4252 section 7) - the only thing the agent will sign.
+fn is_userauth_request(data: &[u8], key_blob: &[u8]) -> bool {
+    let mut r = Reader(data);
+    let Some(_session_id) = r.string() else {
+        return false;
+    };
+    if r.byte() != Some(SSH_MSG_USERAUTH_REQUEST) {
+        return false;
+    }
+    let Some(_user) = r.string() else {
+        return false;
+    };
+    let Some(_service) = r.string() else {
+        return false;
+    };
+    if r.string() != Some(&b"publickey"[..]) {
+        return false;
+    }
+    if r.byte() != Some(1) {
+        return false;
+    }
+    if r.string() != Some(KEY_TYPE_ED25519) {
+        return false;
+    }
+    if r.string() != Some(key_blob) {
+        return false;
+    }
+    return r.0.is_empty();
+}
+
+async fn handle_request(
+    log: &Log,
+    signer: &Arc<Mutex<dyn IdentitySigner>>,
+    identity: &Identity,
+    key_blob: &[u8],
+    req: &[u8],
+) -> Vec<u8> {
+    let mut r = Reader(req);
+    match r.byte() {
+        Some(SSH_AGENTC_REQUEST_IDENTITIES) => {
+            let mut out = vec![SSH_AGENT_IDENTITIES_ANSWER];
+            out.extend(1u32.to_be_bytes());
+            put_string(&mut out, key_blob);
+            put_string(&mut out, identity.to_string().as_bytes());
+            return out;
+        },
+        Some(SSH_AGENTC_SIGN_REQUEST) => {
+            let (Some(req_key), Some(data)) = (r.string(), r.string()) else {
+                return vec![SSH_AGENT_FAILURE];
+            };
+            if req_key != key_blob {
+                return vec![SSH_AGENT_FAILURE];
+            }
+            if !is_userauth_request(data, key_blob) {
+                log.log(loga::WARN, "Refusing to sign data that isn't an SSH user authentication request");
+                return vec![SSH_AGENT_FAILURE];
+            }
+            let signer = signer.clone();
+            let data = data.to_vec();
+            let sig = match spawn_blocking(move || signer.lock().unwrap().sign_raw(&data)).await {
+                Ok(Ok(s)) => s,
+                Ok(Err(e)) => {
+                    log.log_err(loga::WARN, e.context("Error signing SSH authentication request"));
+                    return vec![SSH_AGENT_FAILURE];
+                },
+                Err(e) => {
+                    log.log_err(loga::WARN, e.context("Signing task failed"));
+                    return vec![SSH_AGENT_FAILURE];
+                },
+            };
+            let mut sig_blob = vec![];
+            put_string(&mut sig_blob, KEY_TYPE_ED25519);
+            put_string(&mut sig_blob, &sig);
+            let mut out = vec![SSH_AGENT_SIGN_RESPONSE];
+            put_string(&mut out, &sig_blob);
+            return out;
+        },
+        _ => {
+            return vec![SSH_AGENT_FAILURE];
+        },
+    }
+}
+
+/// Get the signer's identity without blocking the runtime (cards may be slow).
+pub async fn agent_identity(signer: &Arc<Mutex<dyn IdentitySigner>>) -> Result<Identity, loga::Error> {
+    let signer = signer.clone();
+    return spawn_blocking(move || signer.lock().unwrap().identity())
+        .await
+        .context("Identity task failed")?
+        .context("Error getting identity");
+}
+
+/// Answer agent requests on a single connection until it closes. `identity` is the
+/// signer's identity.
+pub async fn serve_agent_conn(
+    log: &Log,
+    signer: &Arc<Mutex<dyn IdentitySigner>>,
+    identity: &Identity,
+    mut conn: impl AsyncRead + AsyncWrite + Unpin,
+) -> Result<(), loga::Error> {
+    let key_blob = ssh_key_blob(identity);
+    loop {
+        let mut len = [0u8; 4];
+        match conn.read_exact(&mut len).await {
+            Ok(_) => { },
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.context("Error reading request length")),
+        }
+        let len = u32::from_be_bytes(len) as usize;
+        if len > MAX_MESSAGE_LEN {
+            return Err(loga::err_with("Agent request too large", ea!(len = len)));
+        }
+        let mut req = vec![0u8; len];
+        conn.read_exact(&mut req).await.context("Error reading request")?;
+        let resp = handle_request(log, signer, identity, &key_blob, &req).await;
+        let mut out = vec![];
+        put_string(&mut out, &resp);
+        conn.write_all(&out).await.context("Error writing response")?;
+    }
+}
+
+/// Listen for agent connections on a unix socket at `path`, until an error.
+pub async fn serve_agent(
+    log: &Log,
+    signer: Arc<Mutex<dyn IdentitySigner>>,
+    identity: Identity,
+    path: &Path,
+) -> Result<(), loga::Error> {
+    let listener =
+        UnixListener::bind(path).context_with("Error binding agent socket", ea!(path = path.to_string_lossy()))?;
+    loop {
+        let (conn, _) = listener.accept().await.context("Error accepting agent connection")?;
+        let log = log.clone();
+        let signer = signer.clone();
+        spawn(async move {
+            if let Err(e) = serve_agent_conn(&log, &signer, &identity, conn).await {
+                log.log_err(loga::DEBUG, e.context("Error serving agent connection"));
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_ssh_agent {
+    use {
+        super::{
+            is_userauth_request,
+            put_string,
+            ssh_key_blob,
+            KEY_TYPE_ED25519,
+            SSH_MSG_USERAUTH_REQUEST,
+        },
+        crate::interface::config::identity::LocalIdentitySecret,
+    };
+
+    fn userauth(key_blob: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        put_string(&mut out, &[7u8; 32]);
+        out.push(SSH_MSG_USERAUTH_REQUEST);
+        put_string(&mut out, b"root");
+        put_string(&mut out, b"ssh-connection");
+        put_string(&mut out, b"publickey");
+        out.push(1);
+        put_string(&mut out, KEY_TYPE_ED25519);
+        put_string(&mut out, key_blob);
+        return out;
+    }
+
+    #[test]
+    fn test_userauth_request() {
+        let (identity, _) = LocalIdentitySecret::new();
+        let (other_identity, _) = LocalIdentitySecret::new();
+        let key_blob = ssh_key_blob(&identity);
+        assert!(is_userauth_request(&userauth(&key_blob), &key_blob));
+        assert!(!is_userauth_request(&userauth(&ssh_key_blob(&other_identity)), &key_blob));
+        let mut trailing = userauth(&key_blob);
+        trailing.push(0);
+        assert!(!is_userauth_request(&trailing, &key_blob));
+
+        // E.g. the hash of an announcement
+        assert!(!is_userauth_request(&[3u8; 64], &key_blob));
+    }
+}
@@ -1,9 +1,24 @@
 use {
+    super::{
+        identity_secret::IdentitySigner,
+        ssh_agent::{
+            agent_identity,
+            serve_agent_conn,
+            ssh_public_key,
+        },
+    },
     crate::{
-        interface::stored::record,
+        interface::{
+            stored::record,
+            wire::api::resolve::v1::SuspectReason,
+        },
         resolving::{
-            resolve,
-            default_resolver_url_pairs,
+            cache::ResolveCache,
+            connect_any_ip,
+            report_suspect,
+            resolve_via,
+            ResolveRes,
+            ResolveVia,
         },
     },
     flowcontrol::{
@@ -39,28 +54,38 @@ use {
         },
         ffi::OsString,
         io::ErrorKind,
-        net::{
-            IpAddr,
-            SocketAddr,
-        },
+        net::SocketAddr,
         path::{
             Path,
             PathBuf,
         },
-        sync::Arc,
+        sync::{
+            atomic::{
+                AtomicBool,
+                Ordering,
+            },
+            Arc,
+            Mutex,
+        },
         time::SystemTime,
     },
-    tokio::fs::{
-        create_dir,
-        read_dir,
-        remove_dir_all,
-        File,
+    tokio::{
+        fs::{
+            create_dir,
+            read_dir,
+            remove_dir_all,
+            File,
+        },
+        io::duplex,
+        spawn,
     },
 };
 
 #[doc(hidden)]
 pub struct Handler {
     host_keys: Vec<russh_keys::key::PublicKey>,
+    // Set if the server's key wasn't one of the published keys
+    key_mismatch: Arc<AtomicBool>,
 }
 
 #[async_trait::async_trait]
@@ -76,6 +101,7 @@ impl russh::client::Handler for Handler {
                 return Ok(true);
             }
         }
+        self.key_mismatch.store(true, Ordering::Relaxed);
         return Ok(false);
     }
 }
@@ -88,19 +114,25 @@ pub trait SshConnectHandler {
     async fn run(self, conn: SshConn) -> Result<(), loga::Error>;
 }
 
+/// Connect and authenticate, then run `inner`. If `identity` is set, authenticate
+/// only with the identity (as an ed25519 SSH key). Otherwise use `key` if set, or
+/// else try the SSH agent and default key files. The host is resolved via `via`,
+/// using `cache` if provided.
+#[allow(clippy::too_many_arguments)]
 pub async fn ssh_connect(
     log: &Log,
+    via: ResolveVia<'_>,
+    cache: Option<&ResolveCache>,
     user: Option<String>,
     host: String,
     port: Option<u16>,
     key: Option<PathBuf>,
+    identity: Option<Arc<Mutex<dyn IdentitySigner>>>,
     inner: impl SshConnectHandler,
 ) -> Result<(), loga::Error> {
     let hostkey_key = vec![record::ssh_record::KEY_SUFFIX_SSH_HOSTKEYS.to_string()];
-    let (ips, mut additional_records) =
-        resolve(log, &default_resolver_url_pairs(log)?, &host, &[hostkey_key.clone()])
-            .await
-            .context("Error resolving host")?;
+    let ResolveRes { ips, prefer, additional: mut additional_records, chain } =
+        resolve_via(log, via, cache, &host, std::slice::from_ref(&hostkey_key)).await.context("Error resolving host")?;
     let mut host_keys = vec![];
     shed!{
         let Some(r) = additional_records.remove(&hostkey_key) else {
@@ -174,23 +206,62 @@ pub async fn ssh_connect(
             _ => return Err(e.context("Error parsing ssh config")),
         },
     };
-    let mut conn =
-        russh::client::connect(
-            Arc::new(russh::client::Config::default()),
-            Iterator::chain(
-                ips.ipv6s.into_iter().map(|x| IpAddr::V6(x)),
-                ips.ipv4s.into_iter().map(|x| IpAddr::V4(x)),
-            )
-                .map(|i| SocketAddr::new(i, port.unwrap_or(config_port)))
-                .collect_vec()
-                .as_slice(),
-            Handler { host_keys: host_keys },
-        )
-            .await
-            .context("Error connecting to remote host")?;
+    let ssh_config = Arc::new(russh::client::Config::default());
+    let key_mismatch = Arc::new(AtomicBool::new(false));
+    let mut conn = match connect_any_ip(
+        log,
+        &ips,
+        prefer,
+        |ip| russh::client::connect(
+            ssh_config.clone(),
+            SocketAddr::new(ip, port.unwrap_or(config_port)),
+            Handler {
+                host_keys: host_keys.clone(),
+                key_mismatch: key_mismatch.clone(),
+            },
+        ),
+    ).await {
+        Ok(c) => c,
+        Err(e) => {
+            // The addresses or host keys may be stale, have resolvers look them up again
+            report_suspect(
+                log,
+                via,
+                &chain,
+                std::slice::from_ref(&hostkey_key),
+                if key_mismatch.load(Ordering::Relaxed) {
+                    SuspectReason::KeyMismatch
+                } else {
+                    SuspectReason::ConnectFailed
+                },
+            ).await;
+            return Err(e.context("Error connecting to remote host"));
+        },
+    };
     shed!{
         'authenticated _;
         let user = user.or(config_user).unwrap_or("root".to_string());
+        if let Some(signer) = identity {
+            // Use the agent shim in process, so card and local identities work the same
+            let identity = agent_identity(&signer).await?;
+            log.log_with(loga::DEBUG, "Attempting auth via identity", ea!(identity = identity));
+            let (client_end, agent_end) = duplex(4096);
+            spawn({
+                let log = log.clone();
+                async move {
+                    if let Err(e) = serve_agent_conn(&log, &signer, &identity, agent_end).await {
+                        log.log_err(loga::DEBUG, e.context("Error in identity agent"));
+                    }
+                }
+            });
+            let agent = russh_keys::agent::client::AgentClient::connect(client_end);
+            let (_, res) = conn.authenticate_future(&user, ssh_public_key(&identity), agent).await;
+            if res.context("Error attempting auth method via identity")? {
+                break 'authenticated;
+            } else {
+                return Err(loga::err("Identity was rejected"));
+            }
+        }
         if let Some(key) = key {
             let key =
                 russh_keys::load_secret_key(
@@ -1,7 +1,10 @@
 use {
-    super::unstable_ip::{
-        UnstableIpv4,
-        UnstableIpv6,
+    super::{
+        privacy::redact_ip,
+        unstable_ip::{
+            UnstableIpv4,
+            UnstableIpv6,
+        },
     },
     crate::interface::config::shared::{
         GlobalAddrConfig,
@@ -160,7 +163,7 @@ pub async fn remote_resolve_global_ip(
 pub async fn resolve_global_ip(log: &Log, config: GlobalAddrConfig) -> Result<IpAddr, loga::Error> {
     return Ok(match config {
         GlobalAddrConfig::Fixed(s) => {
-            log.log_with(loga::INFO, "Identified fixed public ip address from config", ea!(addr = s));
+            log.log_with(loga::INFO, "Identified fixed public ip address from config", ea!(addr = redact_ip(&s)));
             s
         },
         GlobalAddrConfig::FromInterface { name, ip_version } => {
@@ -171,7 +174,7 @@ pub async fn resolve_global_ip(log: &Log, config: GlobalAddrConfig) -> Result<Ip
                 log.log_with(loga::INFO, "Waiting for public ip address on interface", ea!());
                 sleep(Duration::from_secs(10)).await;
             };
-            log.log_with(loga::INFO, "Identified public ip address via interface", ea!(addr = res));
+            log.log_with(loga::INFO, "Identified public ip address via interface", ea!(addr = redact_ip(&res)));
             res
         },
         GlobalAddrConfig::Lookup(lookup) => {
@@ -187,7 +190,7 @@ pub async fn resolve_global_ip(log: &Log, config: GlobalAddrConfig) -> Result<Ip
                 }
                 sleep(Duration::from_secs(10)).await;
             };
-            log.log_with(loga::INFO, "Identified public ip address via external lookup", ea!(addr = res));
+            log.log_with(loga::INFO, "Identified public ip address via external lookup", ea!(addr = redact_ip(&res)));
             res
         },
     });
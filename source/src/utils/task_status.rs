@@ -0,0 +1,153 @@
+//! Registry of background tasks started in the task manager, for admin status
+//! reporting. Start tasks with the `TrackedTasks` methods instead of the plain task
+//! manager methods to have them listed.
+use {
+    crate::interface::wire::api::admin::latest::{
+        TaskKind,
+        TaskStatus,
+    },
+    chrono::Utc,
+    futures::Stream,
+    std::{
+        collections::BTreeMap,
+        future::Future,
+        pin::Pin,
+        sync::{
+            Arc,
+            Mutex,
+        },
+    },
+    taskmanager::TaskManager,
+};
+
+static TASKS: Mutex<BTreeMap<String, TaskStatus>> = Mutex::new(BTreeMap::new());
+
+fn register(name: &str, kind: TaskKind) {
+    TASKS.lock().unwrap().insert(name.to_string(), TaskStatus {
+        name: name.to_string(),
+        kind: kind,
+        started: Utc::now(),
+        running: true,
+        runs: 0,
+        last_run: None,
+        last_run_ms: None,
+        exited: None,
+        error: None,
+    });
+}
+
+fn update(name: &str, f: impl FnOnce(&mut TaskStatus)) {
+    if let Some(t) = TASKS.lock().unwrap().get_mut(name) {
+        f(t);
+    }
+}
+
+/// Wrap a repeated action to record when each run started and how long it took.
+fn track_run<
+    F: Future<Output = ()> + Send + 'static,
+>(name: Arc<String>, run: F) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    return Box::pin(async move {
+        let start = Utc::now();
+        run.await;
+        let end = Utc::now();
+        update(&name, |t| {
+            t.runs += 1;
+            t.last_run = Some(start);
+            t.last_run_ms = Some((end - start).num_milliseconds().max(0) as u64);
+        });
+    });
+}
+
+/// All tasks, ordered by name.
+pub fn tasks() -> Vec<TaskStatus> {
+    return TASKS.lock().unwrap().values().cloned().collect();
+}
+
+/// Critical tasks that exited with an error.
+pub fn failed_tasks() -> Vec<TaskStatus> {
+    return TASKS
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|t| t.kind == TaskKind::Critical && t.error.is_some())
+        .cloned()
+        .collect();
+}
+
+/// Task manager methods that record tasks for `tasks`.
+pub trait TrackedTasks {
+    fn tracked_task(&self, name: impl ToString, f: impl Future<Output = ()> + Send + 'static);
+    fn tracked_critical_task(
+        &self,
+        name: impl ToString,
+        f: impl Future<Output = Result<(), loga::Error>> + Send + 'static,
+    );
+    fn tracked_periodic<
+        F: Future<Output = ()> + Send + 'static,
+    >(&self, name: impl ToString, period: std::time::Duration, f: impl Fn() -> F + Send + 'static);
+    fn tracked_stream<
+        T: Send + 'static,
+        F: Future<Output = ()> + Send + 'static,
+    >(
+        &self,
+        name: impl ToString,
+        stream: impl Stream<Item = T> + Send + Unpin + 'static,
+        f: impl Fn(T) -> F + Send + 'static,
+    );
+}
+
+impl TrackedTasks for TaskManager {
+    fn tracked_task(&self, name: impl ToString, f: impl Future<Output = ()> + Send + 'static) {
+        let name = name.to_string();
+        register(&name, TaskKind::Task);
+        self.task(name.clone(), async move {
+            f.await;
+            update(&name, |t| {
+                t.running = false;
+                t.exited = Some(Utc::now());
+            });
+        });
+    }
+
+    fn tracked_critical_task(
+        &self,
+        name: impl ToString,
+        f: impl Future<Output = Result<(), loga::Error>> + Send + 'static,
+    ) {
+        let name = name.to_string();
+        register(&name, TaskKind::Critical);
+        self.critical_task(name.clone(), async move {
+            let res = f.await;
+            update(&name, |t| {
+                t.running = false;
+                t.exited = Some(Utc::now());
+                if let Err(e) = &res {
+                    t.error = Some(e.to_string());
+                }
+            });
+            return res;
+        });
+    }
+
+    fn tracked_periodic<
+        F: Future<Output = ()> + Send + 'static,
+    >(&self, name: impl ToString, period: std::time::Duration, f: impl Fn() -> F + Send + 'static) {
+        let name = Arc::new(name.to_string());
+        register(&name, TaskKind::Periodic);
+        self.periodic(name.as_ref().clone(), period, move || track_run(name.clone(), f()));
+    }
+
+    fn tracked_stream<
+        T: Send + 'static,
+        F: Future<Output = ()> + Send + 'static,
+    >(
+        &self,
+        name: impl ToString,
+        stream: impl Stream<Item = T> + Send + Unpin + 'static,
+        f: impl Fn(T) -> F + Send + 'static,
+    ) {
+        let name = Arc::new(name.to_string());
+        register(&name, TaskKind::Stream);
+        self.stream(name.as_ref().clone(), stream, move |v| track_run(name.clone(), f(v)));
+    }
+}
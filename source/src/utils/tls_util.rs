@@ -26,7 +26,10 @@ use {
     flowcontrol::shed,
     futures::Future,
     loga::ResultContext,
-    p256::ecdsa::DerSignature,
+    p256::{
+        ecdsa::DerSignature,
+        pkcs8::DecodePrivateKey,
+    },
     pem::Pem,
     rand::RngCore,
     rustls::client::WebPkiServerVerifier,
@@ -34,11 +37,17 @@ use {
         Digest,
         Sha256,
     },
-    signature::SignerMut,
+    signature::{
+        SignerMut,
+        Verifier,
+    },
     std::{
         collections::HashSet,
         str::FromStr,
-        sync::Arc,
+        sync::{
+            Arc,
+            Mutex,
+        },
     },
     x509_cert::{
         builder::Builder,
@@ -138,6 +147,30 @@ pub fn cert_der_hash(cert_der: &[u8]) -> Result<Blob, loga::Error> {
     );
 }
 
+/// Sign a message with a cert's private key (P-256, PKCS#8 DER). The signature
+/// (DER) can be checked by anyone with the public cert using `cert_der_verify`.
+pub fn cert_der_sign(priv_der: &[u8], message: &[u8]) -> Result<Blob, loga::Error> {
+    let mut key = p256::ecdsa::SigningKey::from_pkcs8_der(priv_der).context("Error parsing private key DER")?;
+    return Ok(
+        SignerMut::<DerSignature>::try_sign(&mut key, message)
+            .context("Error signing message with cert key")?
+            .to_bytes()
+            .blob(),
+    );
+}
+
+/// Check a signature made with `cert_der_sign` using the public cert.
+pub fn cert_der_verify(cert_der: &[u8], message: &[u8], signature: &[u8]) -> Result<(), loga::Error> {
+    let cert = Certificate::from_der(cert_der).context("Error parsing cert DER")?;
+    let key =
+        p256::ecdsa::VerifyingKey::from_sec1_bytes(
+            cert.tbs_certificate.subject_public_key_info.subject_public_key.raw_bytes(),
+        ).context("Cert public key isn't a P-256 key")?;
+    let signature = DerSignature::from_bytes(signature).context("Invalid signature DER")?;
+    key.verify(message, &signature).context("Signature doesn't match message")?;
+    return Ok(());
+}
+
 pub fn cert_pem_hash(cert_pem: &str) -> Result<Blob, loga::Error> {
     return Ok(
         <Sha256 as Digest>::digest(
@@ -212,6 +245,29 @@ impl rustls::client::danger::ServerCertVerifier for UnverifyingVerifier {
     }
 }
 
+/// How `SpaghTlsClientVerifier` accepted a server cert.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TlsVerifyPath {
+    /// The cert matches one published in the host's TLS record
+    PublishedCert,
+    /// The cert has the spaghettinuum extension with its key signed by the host's
+    /// identity
+    IdentitySignature,
+    /// The cert chains to a CA trusted by the system (ex: the `certipasta` `.s` CA),
+    /// with the issuer of the cert
+    CaChain(String),
+}
+
+impl std::fmt::Display for TlsVerifyPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsVerifyPath::PublishedCert => return write!(f, "cert published in the host's TLS record"),
+            TlsVerifyPath::IdentitySignature => return write!(f, "cert signed by the host's identity"),
+            TlsVerifyPath::CaChain(issuer) => return write!(f, "CA chain, issued by {}", issuer),
+        }
+    }
+}
+
 /// A http client verifier that allows certs signed by an identity or provided
 /// out-of-band.
 #[derive(Debug)]
@@ -219,12 +275,12 @@ pub struct SpaghTlsClientVerifier {
     /// Any cert whose DER (SHA256) hash matches one of these is considered verified.
     pub hashes: HashSet<Blob>,
     pub inner: Option<Arc<dyn rustls::client::danger::ServerCertVerifier>>,
+    /// How the most recently accepted cert was verified.
+    pub verified_by: Mutex<Option<TlsVerifyPath>>,
 }
 
 impl SpaghTlsClientVerifier {
-    pub fn with_root_cas(
-        hashes: HashSet<Blob>,
-    ) -> Result<Arc<dyn rustls::client::danger::ServerCertVerifier>, loga::Error> {
+    pub fn with_root_cas(hashes: HashSet<Blob>) -> Result<Arc<SpaghTlsClientVerifier>, loga::Error> {
         let mut roots = rustls::RootCertStore::empty();
         for cert in rustls_native_certs::load_native_certs().expect("could not load platform certs") {
             roots.add(cert).ignore();
@@ -233,6 +289,7 @@ impl SpaghTlsClientVerifier {
         return Ok(Arc::new(Self {
             hashes: hashes,
             inner: Some(inner),
+            verified_by: Mutex::new(None),
         }));
     }
 }
@@ -256,54 +313,24 @@ impl rustls::client::danger::ServerCertVerifier for SpaghTlsClientVerifier {
                     end_entity.as_ref(),
                 ).map_err(|_| rustls::Error::InvalidCertificate(rustls::CertificateError::BadEncoding))?,
             ) {
+            *self.verified_by.lock().unwrap() = Some(TlsVerifyPath::PublishedCert);
             return Ok(rustls::client::danger::ServerCertVerified::assertion());
         }
 
         // Validate based on signature extension - the extension contains the SPKI signed
         // by the identity itself. Zero conf. This is not revokable.
         shed!{
-            // Get signature
             let Ok(cert) = x509_cert::Certificate::from_der(&end_entity) else {
                 break;
             };
-            let Some(ext) =
-                cert.tbs_certificate.extensions.iter().flatten().find(|x| x.extn_id == X509_EXT_SPAGH_OID) else {
-                    break;
-                };
-            let Ok(ext) = stored::cert::X509ExtSpagh::from_bytes(&ext.extn_value.as_bytes()) else {
-                break;
-            };
-            let signature = match ext {
-                stored::cert::X509ExtSpagh::V1(ext) => {
-                    ext.signature
-                },
-            };
-
-            // Get id
             let rustls::pki_types::ServerName::DnsName(server_name) = server_name else {
                 break;
             };
-            let Some((prefix, suffix)) = server_name.as_ref().trim_matches('.').rsplit_once('.') else {
+            let Some(id) = fqdn_identity(server_name.as_ref()) else {
                 break;
             };
-            if suffix != DNS_SUFFIX {
-                break;
-            }
-            let raw_id_id;
-            match prefix.rsplit_once('.') {
-                Some((_, r)) => {
-                    raw_id_id = r;
-                },
-                None => {
-                    raw_id_id = prefix;
-                },
-            }
-            let Ok(id) = Identity::from_str(raw_id_id) else {
-                break;
-            };
-
-            // Check sig
-            if id.verify(&cert.tbs_certificate.subject_public_key_info.to_der().unwrap(), &signature).is_ok() {
+            if cert_signed_by_identity(&cert, &id) {
+                *self.verified_by.lock().unwrap() = Some(TlsVerifyPath::IdentitySignature);
                 return Ok(rustls::client::danger::ServerCertVerified::assertion());
             }
         }
@@ -311,7 +338,13 @@ impl rustls::client::danger::ServerCertVerifier for SpaghTlsClientVerifier {
         // Verify via chain/local CA certs - centralized and requires client configuration
         // for extra certs.
         if let Some(inner) = &self.inner {
-            return inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now);
+            let verified = inner.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+            let issuer = match x509_cert::Certificate::from_der(end_entity) {
+                Ok(cert) => cert.tbs_certificate.issuer.to_string(),
+                Err(_) => "(unparsable cert)".to_string(),
+            };
+            *self.verified_by.lock().unwrap() = Some(TlsVerifyPath::CaChain(issuer));
+            return Ok(verified);
         }
 
         // Default reject
@@ -341,6 +374,138 @@ impl rustls::client::danger::ServerCertVerifier for SpaghTlsClientVerifier {
     }
 }
 
+/// Get the identity a `.s` name belongs to (ex: `<identity>.s` or
+/// `www.<identity>.s`).
+fn fqdn_identity(fqdn: &str) -> Option<Identity> {
+    let (prefix, suffix) = fqdn.trim_matches('.').rsplit_once('.')?;
+    if suffix != DNS_SUFFIX {
+        return None;
+    }
+    let raw_id_id = match prefix.rsplit_once('.') {
+        Some((_, r)) => r,
+        None => prefix,
+    };
+    return Identity::from_str(raw_id_id).ok();
+}
+
+/// Check that the cert has the spaghettinuum extension with its SPKI signed by
+/// `identity`.
+fn cert_signed_by_identity(cert: &Certificate, identity: &Identity) -> bool {
+    let Some(ext) = cert.tbs_certificate.extensions.iter().flatten().find(|x| x.extn_id == X509_EXT_SPAGH_OID) else {
+        return false;
+    };
+    let Ok(ext) = stored::cert::X509ExtSpagh::from_bytes(ext.extn_value.as_bytes()) else {
+        return false;
+    };
+    let signature = match ext {
+        stored::cert::X509ExtSpagh::V1(ext) => {
+            ext.signature
+        },
+    };
+    let Ok(spki_der) = cert.tbs_certificate.subject_public_key_info.to_der() else {
+        return false;
+    };
+    return identity.verify(&spki_der, &signature).is_ok();
+}
+
+/// Get the identity a cert is bound to: a `.s` name in the cert's subject alt
+/// names whose identity signed the cert's key in the spaghettinuum extension. This
+/// is true of `self_tls` certs, and of certs made for a machine by signing its key
+/// with the identity (ex: `machine.<identity>.s`).
+pub fn cert_der_identity(cert_der: &[u8]) -> Option<Identity> {
+    let cert = Certificate::from_der(cert_der).ok()?;
+    let (_, names) = cert.tbs_certificate.get::<x509_cert::ext::pkix::SubjectAltName>().ok()??;
+    for name in names.0 {
+        let x509_cert::ext::pkix::name::GeneralName::DnsName(name) = name else {
+            continue;
+        };
+        let Some(identity) = fqdn_identity(name.as_str()) else {
+            continue;
+        };
+        if cert_signed_by_identity(&cert, &identity) {
+            return Some(identity);
+        }
+    }
+    return None;
+}
+
+/// The identity of a TLS client cert accepted by `IdentityClientCertVerifier`. The
+/// API listener adds this to the extensions of requests on the connection.
+#[derive(Clone, Debug)]
+pub struct ClientCertIdentity(pub Identity);
+
+/// A server verifier for (optional) client certs, accepting certs bound to an
+/// identity (see `cert_der_identity`) and in their validity period. Clients
+/// without a cert are accepted too.
+#[derive(Debug)]
+pub struct IdentityClientCertVerifier {
+    pub algorithms: rustls::crypto::WebPkiSupportedAlgorithms,
+}
+
+impl IdentityClientCertVerifier {
+    pub fn new() -> Arc<IdentityClientCertVerifier> {
+        return Arc::new(Self {
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        });
+    }
+}
+
+impl rustls::server::danger::ClientCertVerifier for IdentityClientCertVerifier {
+    fn client_auth_mandatory(&self) -> bool {
+        return false;
+    }
+
+    fn root_hint_subjects(&self) -> &[rustls::DistinguishedName] {
+        return &[];
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::server::danger::ClientCertVerified, rustls::Error> {
+        let cert =
+            Certificate::from_der(
+                end_entity,
+            ).map_err(|_| rustls::Error::InvalidCertificate(rustls::CertificateError::BadEncoding))?;
+        let validity = &cert.tbs_certificate.validity;
+        let now = std::time::UNIX_EPOCH + std::time::Duration::from_secs(now.as_secs());
+        if now < validity.not_before.to_system_time() {
+            return Err(rustls::Error::InvalidCertificate(rustls::CertificateError::NotValidYet));
+        }
+        if now > validity.not_after.to_system_time() {
+            return Err(rustls::Error::InvalidCertificate(rustls::CertificateError::Expired));
+        }
+        if cert_der_identity(end_entity).is_none() {
+            return Err(rustls::Error::InvalidCertificate(rustls::CertificateError::ApplicationVerificationFailure));
+        }
+        return Ok(rustls::server::danger::ClientCertVerified::assertion());
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        return rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms);
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        return rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms);
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        return self.algorithms.supported_schemes();
+    }
+}
+
 /// Produce a random serial number for a certificate
 pub fn rand_serial() -> x509_cert::serial_number::SerialNumber {
     let mut data = [0u8; 20];
@@ -465,3 +630,58 @@ pub async fn create_leaf_cert_der_local(
         ).await?.blob(),
     );
 }
+
+#[cfg(test)]
+mod test_cert_der_identity {
+    use {
+        super::{
+            cert_der_identity,
+            create_leaf_cert_der_local,
+        },
+        crate::{
+            interface::{
+                config::identity::LocalIdentitySecret,
+                stored::cert::v1::X509ExtSpagh,
+            },
+            utils::blob::Blob,
+        },
+        chrono::{
+            Duration,
+            Utc,
+        },
+        der::Encode,
+    };
+
+    async fn cert(fqdn: &str, signer: Option<&LocalIdentitySecret>) -> Blob {
+        let key = p256::ecdsa::SigningKey::random(&mut rand::thread_rng());
+        let spki_der =
+            x509_cert::spki::SubjectPublicKeyInfoOwned::from_key(*key.verifying_key()).unwrap().to_der().unwrap();
+        return create_leaf_cert_der_local(
+            key,
+            fqdn,
+            Utc::now() - Duration::try_days(1).unwrap(),
+            Utc::now() + Duration::try_days(1).unwrap(),
+            signer.map(|s| X509ExtSpagh { signature: s.sign(&spki_der) }),
+            fqdn,
+        ).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bound() {
+        let (identity, secret) = LocalIdentitySecret::new();
+        assert_eq!(cert_der_identity(&cert(&format!("{}.s", identity), Some(&secret)).await), Some(identity));
+        assert_eq!(
+            cert_der_identity(&cert(&format!("machine.{}.s", identity), Some(&secret)).await),
+            Some(identity)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unbound() {
+        let (identity, _) = LocalIdentitySecret::new();
+        let (_, other_secret) = LocalIdentitySecret::new();
+        assert_eq!(cert_der_identity(&cert(&format!("{}.s", identity), None).await), None);
+        assert_eq!(cert_der_identity(&cert(&format!("{}.s", identity), Some(&other_secret)).await), None);
+        assert_eq!(cert_der_identity(&cert("example.com", Some(&other_secret)).await), None);
+    }
+}
@@ -0,0 +1,133 @@
+//! Sending many UDP datagrams at once. On Linux this uses `sendmmsg` to send a
+//! batch with one syscall, elsewhere datagrams are sent one at a time.
+use {
+    std::{
+        io,
+        net::SocketAddr,
+    },
+    tokio::net::UdpSocket,
+};
+
+/// Send all `packets` to `addr`, stopping at the first error.
+pub async fn send_to_many(socket: &UdpSocket, addr: &SocketAddr, packets: &[impl AsRef<[u8]>]) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut sent = 0;
+        while sent < packets.len() {
+            sent +=
+                socket
+                    .async_io(tokio::io::Interest::WRITABLE, || linux::sendmmsg(socket, addr, &packets[sent..]))
+                    .await?;
+        }
+        return Ok(());
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        for p in packets {
+            socket.send_to(p.as_ref(), addr).await?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use {
+        std::{
+            io,
+            mem,
+            net::SocketAddr,
+            os::fd::AsRawFd,
+        },
+        tokio::net::UdpSocket,
+    };
+
+    // Max datagrams per `sendmmsg` call (`UIO_MAXIOV`)
+    const MAX_BATCH: usize = 1024;
+
+    fn sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        // Safety: all-zero is a valid `sockaddr_storage`, and it's large and aligned
+        // enough for both address types.
+        let mut storage: libc::sockaddr_storage = unsafe {
+            mem::zeroed()
+        };
+        let len = match addr {
+            SocketAddr::V4(a) => {
+                let out = unsafe {
+                    &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in)
+                };
+                out.sin_family = libc::AF_INET as libc::sa_family_t;
+                out.sin_port = a.port().to_be();
+                out.sin_addr = libc::in_addr { s_addr: u32::from_ne_bytes(a.ip().octets()) };
+                mem::size_of::<libc::sockaddr_in>()
+            },
+            SocketAddr::V6(a) => {
+                let out = unsafe {
+                    &mut *(&mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr_in6)
+                };
+                out.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                out.sin6_port = a.port().to_be();
+                out.sin6_flowinfo = a.flowinfo();
+                out.sin6_addr = libc::in6_addr { s6_addr: a.ip().octets() };
+                out.sin6_scope_id = a.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            },
+        };
+        return (storage, len as libc::socklen_t);
+    }
+
+    /// Send as many of `packets` as the kernel accepts in one call, returning the
+    /// number sent.
+    pub fn sendmmsg(socket: &UdpSocket, addr: &SocketAddr, packets: &[impl AsRef<[u8]>]) -> io::Result<usize> {
+        let (mut name, name_len) = sockaddr(addr);
+        let mut iovecs = packets.iter().take(MAX_BATCH).map(|p| libc::iovec {
+            iov_base: p.as_ref().as_ptr() as *mut libc::c_void,
+            iov_len: p.as_ref().len(),
+        }).collect::<Vec<_>>();
+        let mut msgs = iovecs.iter_mut().map(|iov| {
+            // Safety: all-zero is a valid empty `mmsghdr`
+            let mut msg: libc::mmsghdr = unsafe {
+                mem::zeroed()
+            };
+            msg.msg_hdr.msg_name = &mut name as *mut libc::sockaddr_storage as *mut libc::c_void;
+            msg.msg_hdr.msg_namelen = name_len;
+            msg.msg_hdr.msg_iov = iov;
+            msg.msg_hdr.msg_iovlen = 1;
+            return msg;
+        }).collect::<Vec<_>>();
+
+        // Safety: the headers point to `name` and `iovecs`, which point to `packets`, all
+        // of which outlive the call. The kernel only reads them.
+        let res = unsafe {
+            libc::sendmmsg(socket.as_raw_fd(), msgs.as_mut_ptr(), msgs.len() as libc::c_uint, 0)
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        return Ok(res as usize);
+    }
+}
+
+#[cfg(test)]
+mod test_udp_batch {
+    use {
+        super::send_to_many,
+        std::collections::HashSet,
+        tokio::net::UdpSocket,
+    };
+
+    #[tokio::test]
+    async fn test_send_to_many() {
+        let send = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let recv = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let packets = (0u8 .. 50).map(|i| vec![i; i as usize + 1]).collect::<Vec<_>>();
+        send_to_many(&send, &recv.local_addr().unwrap(), &packets).await.unwrap();
+        let mut got = HashSet::new();
+        let mut buf = [0u8; 1024];
+        for _ in 0 .. packets.len() {
+            let (len, _) = recv.recv_from(&mut buf).await.unwrap();
+            got.insert(buf[..len].to_vec());
+        }
+        assert_eq!(got, packets.into_iter().collect::<HashSet<_>>());
+    }
+}
@@ -0,0 +1,266 @@
+use {
+    super::graceful::{
+        drain_conn,
+        serve_draining,
+        ShutdownSignal,
+    },
+    crate::{
+        cap_fn,
+        ta_res,
+    },
+    htwrap::htserve::{
+        self,
+        handler::{
+            Handler,
+            HandlerArgs,
+        },
+    },
+    http::{
+        header::AUTHORIZATION,
+        HeaderValue,
+        Request,
+    },
+    http_body_util::{
+        BodyExt,
+        Empty,
+        Limited,
+    },
+    hyper::body::{
+        Bytes,
+        Incoming,
+    },
+    hyper_util::rt::{
+        TokioExecutor,
+        TokioIo,
+    },
+    loga::{
+        ea,
+        ErrContext,
+        Log,
+        ResultContext,
+    },
+    std::{
+        convert::Infallible,
+        net::{
+            Ipv4Addr,
+            SocketAddr,
+            SocketAddrV4,
+        },
+        os::unix::fs::{
+            DirBuilderExt,
+            PermissionsExt,
+        },
+        path::Path,
+        sync::Arc,
+        time::Duration,
+    },
+    taskmanager::TaskManager,
+    tokio::net::{
+        UnixListener,
+        UnixStream,
+    },
+    tokio_stream::wrappers::UnixListenerStream,
+};
+
+/// Serve plain HTTP on a unix domain socket, gated by filesystem permissions.
+///
+/// Since anyone who can open the socket is trusted, any `Authorization` header in
+/// requests is replaced with `auth_token` (if specified) so handlers requiring the
+/// admin token accept the request.
+pub async fn serve_unix(
+    log: &Log,
+    tm: &TaskManager,
+    path: &Path,
+    mode: u32,
+    shutdown_grace: Duration,
+    handler: Arc<dyn Handler<htserve::responses::Body>>,
+    auth_token: Option<String>,
+) -> Result<(), loga::Error> {
+    let log = log.fork(ea!(path = path.to_string_lossy()));
+
+    // Bind in a private directory and move the socket into place once its permissions
+    // are set, so it's never reachable with looser permissions
+    let mut private_name = std::ffi::OsString::from(".");
+    private_name.push(path.file_name().context("Unix socket path has no file name")?);
+    private_name.push(format!(".{}", std::process::id()));
+    let private_dir = path.with_file_name(private_name);
+    match std::fs::remove_dir_all(&private_dir) {
+        Ok(_) => { },
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => { },
+        Err(e) => {
+            return Err(
+                e.context_with("Error removing old unix socket directory", ea!(dir = private_dir.to_string_lossy())),
+            );
+        },
+    }
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)
+        .stack_context(&log, "Error creating private directory for unix socket")?;
+    let listener = async {
+        ta_res!(UnixListener);
+        let temp_path = private_dir.join("s");
+        let listener = UnixListener::bind(&temp_path).context("Error binding to unix socket")?;
+        std::fs::set_permissions(
+            &temp_path,
+            std::fs::Permissions::from_mode(mode),
+        ).context("Error setting unix socket permissions")?;
+        std::fs::rename(&temp_path, path).context("Error moving unix socket into place")?;
+        return Ok(listener);
+    }.await;
+    _ = std::fs::remove_dir_all(&private_dir);
+    let listener = listener.stack_context(&log, "Error creating unix socket")?;
+    let auth_header = match auth_token {
+        Some(t) => Some(
+            HeaderValue::from_str(
+                &format!("Bearer {}", t),
+            ).stack_context(&log, "Admin token isn't valid as an HTTP header value")?,
+        ),
+        None => None,
+    };
+    serve_draining(
+        &log,
+        tm,
+        format!("API - Server (unix {})", path.to_string_lossy()),
+        shutdown_grace,
+        UnixListenerStream::new(listener),
+        cap_fn!((stream, shutdown)(log, handler, auth_header) {
+            match async {
+                ta_res!(());
+                handle_unix_conn(handler, auth_header, stream?, shutdown).await?;
+                return Ok(());
+            }.await {
+                Ok(_) => (),
+                Err(e) => {
+                    log.log_err(loga::DEBUG, e.context("Error serving request"));
+                },
+            }
+        }),
+    );
+    return Ok(());
+}
+
+async fn handle_unix_conn(
+    handler: Arc<dyn Handler<htserve::responses::Body>>,
+    auth_header: Option<HeaderValue>,
+    stream: UnixStream,
+    shutdown: ShutdownSignal,
+) -> Result<(), loga::Error> {
+    drain_conn(
+        hyper_util::server::conn::auto::Builder::new(TokioExecutor::new()).serve_connection(
+            TokioIo::new(stream),
+            hyper::service::service_fn(move |req: Request<Incoming>| {
+                let handler = handler.clone();
+                let auth_header = auth_header.clone();
+                async move {
+                    let (mut head, body) = req.into_parts();
+                    head.headers.remove(AUTHORIZATION);
+                    if let Some(auth_header) = auth_header {
+                        head.headers.insert(AUTHORIZATION, auth_header);
+                    }
+                    let path = head.uri.path().to_string();
+                    let query = head.uri.query().unwrap_or("").to_string();
+                    return Ok(handler.handle(HandlerArgs {
+                        // Unix socket peers have no IP address
+                        peer_addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)),
+                        query: &query,
+                        head: &head,
+                        subpath: &path,
+                        body: body,
+                    }).await) as Result<_, Infallible>;
+                }
+            }),
+        ),
+        |c| c.graceful_shutdown(),
+        shutdown,
+    )
+        .await
+        .map_err(|e| loga::err_with("Error serving HTTP connection", ea!(err = e)))?;
+    return Ok(());
+}
+
+/// Make a plain HTTP GET request to a server listening on a unix domain socket,
+/// returning the response body.
+pub async fn unix_get(path: &Path, uri_path: &str) -> Result<Vec<u8>, loga::Error> {
+    let log = Log::new().fork(ea!(path = path.to_string_lossy(), uri = uri_path));
+    let stream = UnixStream::connect(path).await.stack_context(&log, "Error connecting to unix socket")?;
+    let (mut sender, conn) =
+        hyper::client::conn::http1::handshake(
+            TokioIo::new(stream),
+        ).await.stack_context(&log, "Error starting HTTP connection")?;
+    tokio::spawn(conn);
+    let req =
+        Request::builder()
+            .uri(uri_path)
+            .header(http::header::HOST, "localhost")
+            .body(Empty::<Bytes>::new())
+            .stack_context(&log, "Error building request")?;
+    let resp = sender.send_request(req).await.stack_context(&log, "Error sending request")?;
+    let status = resp.status();
+    let body =
+        Limited::new(resp.into_body(), 1024 * 1024)
+            .collect()
+            .await
+            .map_err(|e| log.err_with("Error reading response body", ea!(err = e)))?
+            .to_bytes()
+            .to_vec();
+    if !status.is_success() {
+        return Err(
+            log.err_with(
+                "Received error response",
+                ea!(status = status, body = String::from_utf8_lossy(&body)),
+            ),
+        );
+    }
+    return Ok(body);
+}
+
+#[cfg(test)]
+mod test_unix_http {
+    use {
+        super::serve_unix,
+        async_trait::async_trait,
+        htwrap::htserve::{
+            self,
+            handler::{
+                Handler,
+                HandlerArgs,
+            },
+            responses::response_200,
+        },
+        http::Response,
+        loga::Log,
+        std::{
+            env,
+            os::unix::fs::PermissionsExt,
+            sync::Arc,
+            time::Duration,
+        },
+        taskmanager::TaskManager,
+    };
+
+    struct Ok200;
+
+    #[async_trait]
+    impl Handler<htserve::responses::Body> for Ok200 {
+        async fn handle(&self, _args: HandlerArgs<'_>) -> Response<htserve::responses::Body> {
+            return response_200();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_permissions() {
+        let dir = env::temp_dir().join(format!("spagh-test-unix-http-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("api.sock");
+        let tm = TaskManager::new();
+        serve_unix(&Log::new(), &tm, &path, 0o600, Duration::ZERO, Arc::new(Ok200), None).await.unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // Only the socket is left
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        tokio::net::UnixStream::connect(&path).await.unwrap();
+        tm.terminate();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+//! Encrypting record data to recipient identities (see `EncryptedData`), so
+//! private values can be published and only the recipients can read them.
+use {
+    crate::{
+        interface::{
+            config::identity::LocalIdentitySecret,
+            stored::{
+                self,
+                identity::Identity,
+                record::encrypted_record::{
+                    self,
+                    Encrypted,
+                    EncryptedData,
+                },
+            },
+        },
+        utils::blob::{
+            Blob,
+            ToBlob,
+        },
+    },
+    chacha20poly1305::{
+        aead::{
+            Aead,
+            KeyInit,
+        },
+        XChaCha20Poly1305,
+        XNonce,
+    },
+    ed25519_dalek::{
+        SigningKey,
+        VerifyingKey,
+    },
+    loga::{
+        ea,
+        ResultContext,
+    },
+    rand::{
+        rngs::OsRng,
+        RngCore,
+    },
+    sha2::{
+        Digest,
+        Sha256,
+    },
+};
+
+// Binds wrapping keys to this scheme
+const WRAP_KEY_CONTEXT: &[u8] = b"spaghettinuum encrypted record v1";
+
+fn identity_key(identity: &Identity) -> VerifyingKey {
+    match identity {
+        Identity::V1(stored::identity::v1::Identity::Ed25519(i)) => return i.0,
+    }
+}
+
+fn random_nonce() -> Blob {
+    let mut nonce = Blob::new(24);
+    OsRng.fill_bytes(&mut nonce);
+    return nonce;
+}
+
+/// The key wrapping the data key for one recipient, from the X25519 shared secret
+/// and both public keys.
+fn wrap_key(shared: &[u8; 32], ephemeral: &VerifyingKey, recipient: &VerifyingKey) -> chacha20poly1305::Key {
+    let mut hash = Sha256::new();
+    hash.update(WRAP_KEY_CONTEXT);
+    hash.update(shared);
+    hash.update(ephemeral.as_bytes());
+    hash.update(recipient.as_bytes());
+    return chacha20poly1305::Key::clone_from_slice(&hash.finalize());
+}
+
+/// Encrypt record data so any of the recipients can decrypt it. The result is
+/// published as the record's data.
+pub fn encrypt_data(recipients: &[Identity], data: &serde_json::Value) -> Result<serde_json::Value, loga::Error> {
+    if recipients.is_empty() {
+        return Err(loga::err("Encrypting record data requires at least one recipient"));
+    }
+    let mut data_key = chacha20poly1305::Key::default();
+    OsRng.fill_bytes(&mut data_key);
+    let nonce = random_nonce();
+    let ciphertext =
+        XChaCha20Poly1305::new(&data_key)
+            .encrypt(XNonce::from_slice(&nonce), serde_json::to_vec(data).unwrap().as_ref())
+            .map_err(|_| loga::err("Error encrypting record data"))?
+            .blob();
+    let mut out_recipients = vec![];
+    for recipient in recipients {
+        let recipient_key = identity_key(recipient);
+        let ephemeral = SigningKey::generate(&mut OsRng {});
+        let ephemeral_key = ephemeral.verifying_key();
+        let shared = recipient_key.to_montgomery().mul_clamped(ephemeral.to_scalar_bytes()).to_bytes();
+        if shared == [0u8; 32] {
+            return Err(loga::err_with("Recipient identity key can't be used for encryption", ea!(identity = recipient)));
+        }
+        let wrap_nonce = random_nonce();
+        let wrapped_key =
+            XChaCha20Poly1305::new(&wrap_key(&shared, &ephemeral_key, &recipient_key))
+                .encrypt(XNonce::from_slice(&wrap_nonce), data_key.as_slice())
+                .map_err(|_| loga::err("Error wrapping record data key"))?
+                .blob();
+        out_recipients.push(encrypted_record::latest::Recipient {
+            ephemeral: ephemeral_key.as_bytes().as_slice().blob(),
+            nonce: wrap_nonce,
+            wrapped_key: wrapped_key,
+        });
+    }
+    return Ok(serde_json::to_value(EncryptedData::Encrypted(Encrypted::latest(encrypted_record::latest::Encrypted {
+        recipients: out_recipients,
+        nonce: nonce,
+        ciphertext: ciphertext,
+    }))).unwrap());
+}
+
+/// Decrypt encrypted record data with a recipient's identity secret. Returns
+/// `None` if the data isn't encrypted to the identity.
+pub fn decrypt_data(
+    secret: &LocalIdentitySecret,
+    data: &serde_json::Value,
+) -> Result<Option<serde_json::Value>, loga::Error> {
+    let EncryptedData::Encrypted(encrypted) =
+        serde_json::from_value::<EncryptedData>(
+            data.clone(),
+        ).context("Encrypted record data doesn't match schema")?;
+    let Encrypted::V1(encrypted) = encrypted;
+    if encrypted.nonce.len() != 24 {
+        return Err(loga::err("Encrypted record data nonce has wrong length"));
+    }
+    let own_key = identity_key(&secret.identity());
+    for recipient in &encrypted.recipients {
+        if recipient.nonce.len() != 24 {
+            continue;
+        }
+        let Ok(ephemeral_key) = <[u8; 32]>::try_from(recipient.ephemeral.as_ref()) else {
+            continue;
+        };
+        let Ok(ephemeral_key) = VerifyingKey::from_bytes(&ephemeral_key) else {
+            continue;
+        };
+        let shared = secret.agree(&ephemeral_key);
+        if shared == [0u8; 32] {
+            continue;
+        }
+
+        // Stanzas for other recipients fail authentication
+        let Ok(data_key) =
+            XChaCha20Poly1305::new(
+                &wrap_key(&shared, &ephemeral_key, &own_key),
+            ).decrypt(XNonce::from_slice(&recipient.nonce), recipient.wrapped_key.as_ref()) else {
+                continue;
+            };
+        if data_key.len() != 32 {
+            return Err(loga::err("Wrapped record data key has wrong length"));
+        }
+        let plaintext =
+            XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&data_key))
+                .decrypt(XNonce::from_slice(&encrypted.nonce), encrypted.ciphertext.as_ref())
+                .map_err(|_| loga::err("Error decrypting record data"))?;
+        return Ok(Some(serde_json::from_slice(&plaintext).context("Decrypted record data isn't valid JSON")?));
+    }
+    return Ok(None);
+}
+
+#[cfg(test)]
+mod test_value_encryption {
+    use {
+        super::{
+            decrypt_data,
+            encrypt_data,
+        },
+        crate::interface::{
+            config::identity::LocalIdentitySecret,
+            stored::record::encrypted_record::is_encrypted,
+        },
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_roundtrip() {
+        let (ident1, secret1) = LocalIdentitySecret::new();
+        let (ident2, secret2) = LocalIdentitySecret::new();
+        let (_, secret3) = LocalIdentitySecret::new();
+        let data = json!({
+            "v1": {
+                "url": "https://internal.example.org"
+            }
+        });
+        let encrypted = encrypt_data(&[ident1, ident2], &data).unwrap();
+        assert!(is_encrypted(&encrypted));
+        assert!(!is_encrypted(&data));
+        assert_eq!(decrypt_data(&secret1, &encrypted).unwrap(), Some(data.clone()));
+        assert_eq!(decrypt_data(&secret2, &encrypted).unwrap(), Some(data));
+        assert_eq!(decrypt_data(&secret3, &encrypted).unwrap(), None);
+        assert!(encrypt_data(&[], &json!("x")).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Searching for local identities whose id starts with chosen characters.
+use {
+    crate::interface::{
+        config::identity::LocalIdentitySecret,
+        stored::identity::Identity,
+    },
+    loga::ea,
+    std::sync::{
+        atomic::{
+            AtomicBool,
+            AtomicU64,
+            Ordering,
+        },
+        Mutex,
+    },
+};
+
+const ZBASE32_ALPHABET: &str = "ybndrfg8ejkmcpqxot1uwisza345h769";
+
+/// The number of leading characters shared by all identity ids (version and key
+/// type). The vanity prefix is matched after these.
+pub fn vanity_fixed_len() -> usize {
+    let (identity, _) = LocalIdentitySecret::new();
+    return (identity.to_bytes().len() - ed25519_dalek::PUBLIC_KEY_LENGTH) * 8 / 5;
+}
+
+/// Check that every character of the prefix can appear in an id, returning it
+/// lowercased.
+pub fn validate_vanity_prefix(prefix: &str) -> Result<String, loga::Error> {
+    let prefix = prefix.to_lowercase();
+    if prefix.is_empty() {
+        return Err(loga::err("Prefix is empty"));
+    }
+    if let Some(c) = prefix.chars().find(|c| !ZBASE32_ALPHABET.contains(*c)) {
+        return Err(
+            loga::err_with(
+                "Prefix has a character that can't appear in ids",
+                ea!(char = c, allowed = ZBASE32_ALPHABET),
+            ),
+        );
+    }
+    return Ok(prefix);
+}
+
+/// Average attempts needed to find an id with a prefix of this length.
+pub fn vanity_expected_attempts(prefix_len: usize) -> f64 {
+    return 32f64.powi(prefix_len as i32);
+}
+
+pub fn vanity_matches(identity: &Identity, fixed_len: usize, prefix: &str) -> bool {
+    return identity.to_string().get(fixed_len..).is_some_and(|s| s.starts_with(prefix));
+}
+
+/// Generate identities on `workers` threads until one matches `prefix` (validated)
+/// or `max_attempts` are made in total. Blocks; `attempts` is updated as the search
+/// runs for progress reporting.
+pub fn search_vanity(
+    prefix: &str,
+    workers: usize,
+    max_attempts: u64,
+    attempts: &AtomicU64,
+) -> Option<(Identity, LocalIdentitySecret)> {
+    let fixed_len = vanity_fixed_len();
+    let done = AtomicBool::new(false);
+    let found = Mutex::new(None);
+    std::thread::scope(|s| {
+        for _ in 0 .. workers.max(1) {
+            s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    if attempts.fetch_add(1, Ordering::Relaxed) >= max_attempts {
+                        done.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                    let (identity, secret) = LocalIdentitySecret::new();
+                    if vanity_matches(&identity, fixed_len, prefix) {
+                        done.store(true, Ordering::Relaxed);
+                        found.lock().unwrap().get_or_insert((identity, secret));
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    return found.into_inner().unwrap();
+}
+
+#[cfg(test)]
+mod test_vanity_identity {
+    use {
+        super::{
+            search_vanity,
+            validate_vanity_prefix,
+            vanity_fixed_len,
+        },
+        std::sync::atomic::AtomicU64,
+    };
+
+    #[test]
+    fn test_search() {
+        assert!(validate_vanity_prefix("abl").is_err());
+        let prefix = validate_vanity_prefix("Y").unwrap();
+        let attempts = AtomicU64::new(0);
+        let (identity, secret) = search_vanity(&prefix, 2, 100_000, &attempts).unwrap();
+        assert_eq!(secret.identity(), identity);
+        assert!(identity.to_string()[vanity_fixed_len()..].starts_with("y"));
+
+        // Gives up at the limit
+        let attempts = AtomicU64::new(0);
+        assert!(search_vanity("yyyyyyyyyyyy", 2, 10, &attempts).is_none());
+    }
+}
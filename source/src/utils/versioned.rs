@@ -22,6 +22,8 @@ macro_rules! versioned{
             $($var($t),) *
         }
         impl $et {
+            /// All versions that can be deserialized, oldest first.
+            pub const VERSIONS: &'static [$crate:: utils:: versioned:: VerInt] = &[$($ver), *];
             pub fn from_bytes(data: &[u8]) -> Result < $et,
             loga:: Error > {
                 let version = $crate:: utils:: versioned:: VerInt:: from_le_bytes(
@@ -0,0 +1,613 @@
+//! Converting standard DNS zone files into records, for migrating names from
+//! conventional DNS.
+//!
+//! Names in the zone are published under the identity, at the same path relative
+//! to the zone origin. Targets (CNAME, MX, SRV) inside the zone are rewritten to
+//! point to the identity.
+use {
+    crate::interface::stored::{
+        self,
+        identity::Identity,
+        record::{
+            delegate_record::build_delegate_key,
+            dns_record::{
+                build_dns_key,
+                RecordType,
+            },
+            record_utils::{
+                join_dns_name,
+                split_dns_name,
+                split_dns_path,
+                RecordKey,
+                RecordRoot,
+            },
+            service_record::{
+                build_services_key,
+                latest::{
+                    Service,
+                    ServiceProtocol,
+                },
+            },
+        },
+    },
+    loga::{
+        ea,
+        ResultContext,
+    },
+    serde::Serialize,
+    std::{
+        collections::{
+            BTreeMap,
+            HashMap,
+        },
+        net::{
+            Ipv4Addr,
+            Ipv6Addr,
+        },
+        str::FromStr,
+    },
+};
+
+/// A zone file record that couldn't be converted.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub struct UnsupportedRecord {
+    pub line: usize,
+    pub name: String,
+    pub record_type: String,
+    pub reason: String,
+}
+
+pub struct ZoneImport {
+    pub records: HashMap<RecordKey, stored::record::RecordValue>,
+    pub unsupported: Vec<UnsupportedRecord>,
+}
+
+/// A record or directive, possibly spanning multiple lines with parentheses.
+struct Entry {
+    line: usize,
+    // The owner is omitted (the previous record's is used)
+    blank_owner: bool,
+    tokens: Vec<String>,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Entry>, loga::Error> {
+    let mut entries = vec![];
+    let mut line = 1;
+    let mut entry = Entry {
+        line: line,
+        blank_owner: false,
+        tokens: vec![],
+    };
+    let mut at_line_start = true;
+    let mut depth = 0usize;
+    let mut chars = text.chars().peekable();
+    loop {
+        let c = chars.next();
+        if at_line_start && depth == 0 {
+            entry.line = line;
+            entry.blank_owner = matches!(c, Some(' ') | Some('\t'));
+        }
+        at_line_start = false;
+        match c {
+            None | Some('\n') => {
+                if depth == 0 && !entry.tokens.is_empty() {
+                    entries.push(std::mem::replace(&mut entry, Entry {
+                        line: line,
+                        blank_owner: false,
+                        tokens: vec![],
+                    }));
+                }
+                if c.is_none() {
+                    if depth > 0 {
+                        return Err(loga::err_with("Unclosed parenthesis", ea!(line = entry.line)));
+                    }
+                    return Ok(entries);
+                }
+                line += 1;
+                at_line_start = true;
+            },
+            Some(' ') | Some('\t') | Some('\r') => { },
+            Some(';') => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            },
+            Some('(') => {
+                depth += 1;
+            },
+            Some(')') => {
+                depth = depth.checked_sub(1).context_with("Unmatched closing parenthesis", ea!(line = line))?;
+            },
+            Some(c) => {
+                let quoted = c == '"';
+                let mut bytes = vec![];
+                let mut next = if quoted {
+                    chars.next()
+                } else {
+                    Some(c)
+                };
+                loop {
+                    match next {
+                        None => {
+                            if quoted {
+                                return Err(loga::err_with("Unclosed quoted string", ea!(line = line)));
+                            }
+                            break;
+                        },
+                        Some('"') if quoted => {
+                            break;
+                        },
+                        Some('\\') => {
+                            let digits = chars.clone().take(3).collect::<String>();
+                            if digits.len() == 3 && digits.chars().all(|c| c.is_ascii_digit()) {
+                                bytes.push(
+                                    u8::from_str(
+                                        &digits,
+                                    ).context_with("Invalid escape in zone file", ea!(line = line))?,
+                                );
+                                chars.nth(2);
+                            } else if let Some(c) = chars.next() {
+                                let mut buf = [0u8; 4];
+                                bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+                            }
+                        },
+                        Some('\n') if quoted => {
+                            return Err(loga::err_with("Unclosed quoted string", ea!(line = line)));
+                        },
+                        Some(c) => {
+                            let mut buf = [0u8; 4];
+                            bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+                        },
+                    }
+                    if !quoted &&
+                        chars.peek().is_some_and(|c| matches!(c, ' ' | '\t' | '\r' | '\n' | ';' | '(' | ')')) {
+                        break;
+                    }
+                    next = chars.next();
+                }
+                entry.tokens.push(String::from_utf8_lossy(&bytes).to_string());
+            },
+        }
+    }
+}
+
+/// Parse a TTL in seconds, with optional unit suffixes (ex: `3600`, `1h30m`).
+fn parse_ttl(text: &str) -> Option<u32> {
+    if text.is_empty() || !text.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let mut total = 0u32;
+    let mut num = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            num.push(c);
+            continue;
+        }
+        let mult = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            'w' => 7 * 24 * 60 * 60,
+            _ => return None,
+        };
+        total = total.checked_add(u32::from_str(&num).ok()?.checked_mul(mult)?)?;
+        num.clear();
+    }
+    if !num.is_empty() {
+        total = total.checked_add(u32::from_str(&num).ok()?)?;
+    }
+    return Some(total);
+}
+
+/// Make a name absolute, lowercase, without the trailing dot.
+fn absolute_name(name: &str, origin: &Option<String>, line: usize) -> Result<String, loga::Error> {
+    if let Some(name) = name.strip_suffix(".") {
+        return Ok(name.to_ascii_lowercase());
+    }
+    let Some(origin) = origin else {
+        return Err(loga::err_with("Relative name but no origin specified", ea!(name = name, line = line)));
+    };
+    if name == "@" {
+        return Ok(origin.clone());
+    }
+    return Ok(format!("{}.{}", name.to_ascii_lowercase(), origin));
+}
+
+struct ZoneRecord {
+    line: usize,
+    // For relative names in the data
+    origin: Option<String>,
+    name: String,
+    ttl: u32,
+    record_type: String,
+    rdata: Vec<String>,
+}
+
+/// Returns the zone origin (`origin` or else the first `$ORIGIN`) and the records.
+fn parse_zone(text: &str, origin: Option<&str>) -> Result<(String, Vec<ZoneRecord>), loga::Error> {
+    let mut origin = origin.map(|o| o.trim_end_matches('.').to_ascii_lowercase());
+    let mut zone_origin = origin.clone();
+    let mut default_ttl = None;
+    let mut last_ttl = None;
+    let mut last_owner: Option<String> = None;
+    let mut out = vec![];
+    for entry in tokenize(text)? {
+        let line = entry.line;
+        let mut tokens = entry.tokens.into_iter().peekable();
+        let owner;
+        if entry.blank_owner {
+            owner = last_owner.clone().context_with("Record has no owner name", ea!(line = line))?;
+        } else {
+            let first = tokens.next().unwrap();
+            match first.to_ascii_uppercase().as_str() {
+                "$ORIGIN" => {
+                    let o = tokens.next().context_with("$ORIGIN has no name", ea!(line = line))?;
+                    origin = Some(absolute_name(&o, &origin, line)?);
+                    if zone_origin.is_none() {
+                        zone_origin = origin.clone();
+                    }
+                    continue;
+                },
+                "$TTL" => {
+                    let t = tokens.next().context_with("$TTL has no value", ea!(line = line))?;
+                    default_ttl =
+                        Some(parse_ttl(&t).context_with("Invalid $TTL", ea!(ttl = t, line = line))?);
+                    continue;
+                },
+                s if s.starts_with("$") => {
+                    return Err(loga::err_with("Unsupported zone file directive", ea!(directive = s, line = line)));
+                },
+                _ => { },
+            }
+            owner = absolute_name(&first, &origin, line)?;
+            last_owner = Some(owner.clone());
+        }
+
+        // TTL and class can be in either order
+        let mut ttl = None;
+        for _ in 0 .. 2 {
+            let Some(t) = tokens.peek() else {
+                break;
+            };
+            if let Some(t) = parse_ttl(t) {
+                ttl = Some(t);
+                tokens.next();
+            } else if t.eq_ignore_ascii_case("IN") {
+                tokens.next();
+            } else if ["CH", "HS", "CS"].iter().any(|c| t.eq_ignore_ascii_case(c)) {
+                return Err(loga::err_with("Only the IN class is supported", ea!(class = t, line = line)));
+            } else {
+                break;
+            }
+        }
+        let record_type = tokens.next().context_with("Record has no type", ea!(line = line))?.to_ascii_uppercase();
+        let ttl = ttl.or(default_ttl).or(last_ttl).context_with("Record has no TTL", ea!(line = line))?;
+        last_ttl = Some(ttl);
+        out.push(ZoneRecord {
+            line: line,
+            origin: origin.clone(),
+            name: owner,
+            ttl: ttl,
+            record_type: record_type,
+            rdata: tokens.collect(),
+        });
+    }
+    let zone_origin = zone_origin.context("Zone file has no $ORIGIN and no origin was specified")?;
+    return Ok((zone_origin, out));
+}
+
+/// Convert a zone file to records for `identity`. `origin` is used if the zone
+/// file doesn't specify `$ORIGIN` before relative names.
+pub fn import_zone(text: &str, origin: Option<&str>, identity: &Identity) -> Result<ZoneImport, loga::Error> {
+    let (origin, records) = parse_zone(text, origin)?;
+    let path_of = |name: &str| -> Result<Option<RecordKey>, loga::Error> {
+        if name == origin {
+            return Ok(Some(vec![]));
+        }
+        let Some(sub) = name.strip_suffix(&format!(".{}", origin)) else {
+            return Ok(None);
+        };
+        return Ok(Some(split_dns_path(sub)?));
+    };
+    let target_of = |name: &str| -> Result<(RecordRoot, RecordKey), loga::Error> {
+        if let Some(path) = path_of(name)? {
+            return Ok((RecordRoot::S(*identity), path));
+        }
+        return split_dns_name(
+            hickory_resolver::Name::from_utf8(name).context_with("Invalid DNS name", ea!(name = name))?,
+        );
+    };
+
+    // Accumulate values for each key, with the lowest TTL
+    let mut a: BTreeMap<RecordKey, (u32, Vec<Ipv4Addr>)> = BTreeMap::new();
+    let mut aaaa: BTreeMap<RecordKey, (u32, Vec<Ipv6Addr>)> = BTreeMap::new();
+    let mut txt: BTreeMap<RecordKey, (u32, Vec<String>)> = BTreeMap::new();
+    let mut mx: BTreeMap<RecordKey, (u32, Vec<(u16, String)>)> = BTreeMap::new();
+    let mut delegate: BTreeMap<RecordKey, (u32, Vec<(RecordRoot, RecordKey)>)> = BTreeMap::new();
+    let mut services: BTreeMap<RecordKey, (u32, Vec<Service>)> = BTreeMap::new();
+
+    fn add<T>(m: &mut BTreeMap<RecordKey, (u32, Vec<T>)>, key: RecordKey, ttl: u32, value: T) {
+        let e = m.entry(key).or_insert_with(|| (ttl, vec![]));
+        e.0 = e.0.min(ttl);
+        e.1.push(value);
+    }
+
+    let mut unsupported = vec![];
+    for r in records {
+        let unsupported_reason = match convert_record(&r, &path_of, &target_of) {
+            Ok(Some(Converted::A(path, v))) => {
+                add(&mut a, path, r.ttl, v);
+                None
+            },
+            Ok(Some(Converted::Aaaa(path, v))) => {
+                add(&mut aaaa, path, r.ttl, v);
+                None
+            },
+            Ok(Some(Converted::Txt(path, v))) => {
+                add(&mut txt, path, r.ttl, v);
+                None
+            },
+            Ok(Some(Converted::Mx(path, v))) => {
+                add(&mut mx, path, r.ttl, v);
+                None
+            },
+            Ok(Some(Converted::Delegate(path, v))) => {
+                add(&mut delegate, path, r.ttl, v);
+                None
+            },
+            Ok(Some(Converted::Service(path, v))) => {
+                add(&mut services, path, r.ttl, v);
+                None
+            },
+            Ok(None) => Some("Unsupported record type".to_string()),
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(reason) = unsupported_reason {
+            unsupported.push(UnsupportedRecord {
+                line: r.line,
+                name: r.name,
+                record_type: r.record_type,
+                reason: reason,
+            });
+        }
+    }
+
+    // Build records
+    fn rec_val(ttl_secs: u32, data: impl Serialize) -> stored::record::RecordValue {
+        return stored::record::RecordValue::latest(stored::record::latest::RecordValue {
+            ttl: (ttl_secs.div_ceil(60)).max(1) as i32,
+            data: Some(serde_json::to_value(&data).unwrap()),
+        });
+    }
+
+    let mut out = HashMap::new();
+    for (path, (ttl, v)) in a {
+        out.insert(
+            build_dns_key(path, RecordType::A),
+            rec_val(ttl, stored::record::dns_record::DnsA::V1(stored::record::dns_record::latest::DnsA(v))),
+        );
+    }
+    for (path, (ttl, v)) in aaaa {
+        out.insert(
+            build_dns_key(path, RecordType::Aaaa),
+            rec_val(ttl, stored::record::dns_record::DnsAaaa::V1(stored::record::dns_record::latest::DnsAaaa(v))),
+        );
+    }
+    for (path, (ttl, v)) in txt {
+        out.insert(
+            build_dns_key(path, RecordType::Txt),
+            rec_val(ttl, stored::record::dns_record::DnsTxt::V1(stored::record::dns_record::latest::DnsTxt(v))),
+        );
+    }
+    for (path, (ttl, mut v)) in mx {
+        // Preference becomes list order
+        v.sort_by_key(|(pref, _)| *pref);
+        out.insert(
+            build_dns_key(path, RecordType::Mx),
+            rec_val(
+                ttl,
+                stored::record::dns_record::DnsMx::V1(
+                    stored::record::dns_record::latest::DnsMx(v.into_iter().map(|(_, n)| n).collect()),
+                ),
+            ),
+        );
+    }
+    for (path, (ttl, v)) in delegate {
+        out.insert(
+            build_delegate_key(path),
+            rec_val(
+                ttl,
+                stored::record::delegate_record::Delegate::latest(
+                    stored::record::delegate_record::latest::Delegate(v),
+                ),
+            ),
+        );
+    }
+    for (path, (ttl, v)) in services {
+        out.insert(
+            build_services_key(path),
+            rec_val(
+                ttl,
+                stored::record::service_record::Services::latest(stored::record::service_record::latest::Services(v)),
+            ),
+        );
+    }
+    return Ok(ZoneImport {
+        records: out,
+        unsupported: unsupported,
+    });
+}
+
+enum Converted {
+    A(RecordKey, Ipv4Addr),
+    Aaaa(RecordKey, Ipv6Addr),
+    Txt(RecordKey, String),
+    Mx(RecordKey, (u16, String)),
+    Delegate(RecordKey, (RecordRoot, RecordKey)),
+    Service(RecordKey, Service),
+}
+
+fn rdata(r: &ZoneRecord, i: usize) -> Result<&str, loga::Error> {
+    return Ok(r.rdata.get(i).context("Record is missing data")?);
+}
+
+fn rdata_name(r: &ZoneRecord, i: usize) -> Result<String, loga::Error> {
+    return absolute_name(rdata(r, i)?, &r.origin, r.line);
+}
+
+/// Convert a single record. Returns `None` if the type isn't supported.
+fn convert_record(
+    r: &ZoneRecord,
+    path_of: &impl Fn(&str) -> Result<Option<RecordKey>, loga::Error>,
+    target_of: &impl Fn(&str) -> Result<(RecordRoot, RecordKey), loga::Error>,
+) -> Result<Option<Converted>, loga::Error> {
+    if r.name.starts_with("*") {
+        return Err(loga::err("Wildcard names aren't supported"));
+    }
+    match r.record_type.as_str() {
+        "A" | "AAAA" | "TXT" | "MX" | "CNAME" => {
+            let path = path_of(&r.name)?.context("Name is outside the zone origin")?;
+            match r.record_type.as_str() {
+                "A" => {
+                    return Ok(
+                        Some(Converted::A(path, Ipv4Addr::from_str(rdata(r, 0)?).context("Invalid IPv4 address")?)),
+                    );
+                },
+                "AAAA" => {
+                    return Ok(
+                        Some(Converted::Aaaa(path, Ipv6Addr::from_str(rdata(r, 0)?).context("Invalid IPv6 address")?)),
+                    );
+                },
+                "TXT" => {
+                    // Spaghettinuum TXT values are single strings
+                    return Ok(
+                        Some(Converted::Txt(path, r.rdata.concat())),
+                    );
+                },
+                "MX" => {
+                    let pref = u16::from_str(rdata(r, 0)?).context("Invalid MX preference")?;
+                    let (root, target_path) = target_of(&rdata_name(r, 1)?)?;
+                    return Ok(Some(Converted::Mx(path, (pref, join_dns_name(root, target_path)?))));
+                },
+                "CNAME" => {
+                    return Ok(Some(Converted::Delegate(path, target_of(&rdata_name(r, 0)?)?)));
+                },
+                _ => unreachable!(),
+            }
+        },
+        "SRV" => {
+            // `_service._proto.domain`; the service is published at the target host's path
+            let mut labels = r.name.splitn(3, ".");
+            let (Some(service), Some(proto)) = (labels.next(), labels.next()) else {
+                return Err(loga::err("SRV name isn't in the form `_service._proto.name`"));
+            };
+            let (Some(service), Some(proto)) = (service.strip_prefix("_"), proto.strip_prefix("_")) else {
+                return Err(loga::err("SRV name isn't in the form `_service._proto.name`"));
+            };
+            let protocol = match proto {
+                "tcp" => ServiceProtocol::Tcp,
+                "udp" => ServiceProtocol::Udp,
+                "sctp" => ServiceProtocol::Sctp,
+                _ => return Err(loga::err_with("Unsupported SRV protocol", ea!(protocol = proto))),
+            };
+            let priority = u16::from_str(rdata(r, 0)?).context("Invalid SRV priority")?;
+            let weight = u16::from_str(rdata(r, 1)?).context("Invalid SRV weight")?;
+            let port = u16::from_str(rdata(r, 2)?).context("Invalid SRV port")?;
+            if rdata(r, 3)? == "." {
+                return Err(loga::err("SRV record says the service isn't available"));
+            }
+            let target =
+                path_of(
+                    &rdata_name(r, 3)?,
+                )?.context("SRV target is outside the zone origin, only hosts in the zone can have services")?;
+            return Ok(Some(Converted::Service(target, Service {
+                name: service.to_string(),
+                protocol: protocol,
+                port: port,
+                priority: priority,
+                weight: weight,
+                description: None,
+            })));
+        },
+        _ => {
+            return Ok(None);
+        },
+    }
+}
+
+#[cfg(test)]
+mod test_zone_import {
+    use {
+        super::import_zone,
+        crate::interface::{
+            config::identity::LocalIdentitySecret,
+            stored::{
+                self,
+                record::{
+                    delegate_record::build_delegate_key,
+                    dns_record::{
+                        build_dns_key,
+                        RecordType,
+                    },
+                    record_utils::{
+                        join_dns_name,
+                        RecordRoot,
+                    },
+                    service_record::build_services_key,
+                },
+            },
+        },
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_import() {
+        let (identity, _) = LocalIdentitySecret::new();
+        let zone = r#"
+$ORIGIN example.org.
+$TTL 1h
+@       IN SOA ns1 hostmaster ( 1 3600 600 86400 60 )
+        IN A 203.0.113.1
+        IN MX 20 mail2
+        IN MX 10 mail.example.org.
+www 300 IN AAAA 2001:db8::1
+        IN TXT "hello " "world" ; comment
+blog    IN CNAME other.example.com.
+_http._tcp IN SRV 0 5 8080 www.example.org.
+"#;
+        let res = import_zone(zone, None, &identity).unwrap();
+        assert_eq!(res.unsupported.len(), 1);
+        assert_eq!(res.unsupported[0].record_type, "SOA");
+        let get = |k| {
+            let stored::record::RecordValue::V1(v) = res.records.get(&k).unwrap().clone();
+            return v;
+        };
+        let a = get(build_dns_key(vec![], RecordType::A));
+        assert_eq!(a.ttl, 60);
+        assert_eq!(a.data, Some(json!({
+            "v1": ["203.0.113.1"]
+        })));
+        let mail = |n: &str| join_dns_name(RecordRoot::S(identity), vec![n.to_string()]).unwrap();
+        assert_eq!(get(build_dns_key(vec![], RecordType::Mx)).data, Some(json!({
+            "v1": [mail("mail"), mail("mail2")]
+        })));
+        assert_eq!(get(build_dns_key(vec!["www".to_string()], RecordType::Aaaa)).ttl, 5);
+        let txt = get(build_dns_key(vec!["www".to_string()], RecordType::Txt));
+        assert_eq!(txt.ttl, 60);
+        assert_eq!(txt.data, Some(json!({
+            "v1": ["hello world"]
+        })));
+        assert!(res.records.contains_key(&build_delegate_key(vec!["blog".to_string()])));
+        assert_eq!(get(build_services_key(vec!["www".to_string()])).data, Some(json!({
+            "v1": [{
+                "name": "http",
+                "protocol": "tcp",
+                "port": 8080,
+                "priority": 0,
+                "weight": 5
+            }]
+        })));
+    }
+}